@@ -1,9 +1,16 @@
 use crate::config::Settings;
+use crate::fs::dataset::{Dataset, DatasetQuota, DatasetUsage, tree_children};
+use crate::fs::destroy::DestroyProgress;
+use crate::fs::diff::ChangeKind;
+use crate::fs::restore::OnConflict;
+use crate::fs::send::ReceiveOptions;
 use crate::rpc::client::RpcClient;
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow, bail};
 use comfy_table::{Table, presets::UTF8_FULL};
-use std::io::Write;
+use std::collections::BTreeSet;
+use std::io::{IsTerminal, Write};
 use std::path::Path;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
 async fn connect_rpc_client(config_path: &Path) -> Result<RpcClient> {
     let settings = Settings::from_file(config_path)
@@ -45,9 +52,9 @@ fn format_size(bytes: u64) -> String {
 }
 
 /// Create a new dataset
-pub async fn create_dataset(config_path: &Path, name: &str) -> Result<()> {
+pub async fn create_dataset(config_path: &Path, name: &str, encrypt: bool) -> Result<()> {
     let client = connect_rpc_client(config_path).await?;
-    let dataset = client.create_dataset(name).await?;
+    let dataset = client.create_dataset(name, encrypt).await?;
 
     println!("✓ Dataset created successfully!");
     println!("  Name: {}", dataset.name);
@@ -55,25 +62,40 @@ pub async fn create_dataset(config_path: &Path, name: &str) -> Result<()> {
     println!("  UUID: {}", dataset.uuid);
     println!("  Created at: {}", format_timestamp(dataset.created_at));
     println!("  Root inode: {}", dataset.root_inode);
+    println!("  Encrypted: {}", dataset.wrapped_key.is_some());
 
     Ok(())
 }
 
-/// List all datasets
-pub async fn list_datasets(config_path: &Path) -> Result<()> {
+/// List all datasets. With `tree`, each dataset is listed below the one it
+/// is nested in and each snapshot below its source, indented.
+pub async fn list_datasets(config_path: &Path, tree: bool) -> Result<()> {
     let client = connect_rpc_client(config_path).await?;
-    let datasets = client.list_datasets().await?;
+    let mut datasets = client.list_datasets().await?;
 
     if datasets.is_empty() {
         println!("No datasets found.");
         return Ok(());
     }
 
+    let mut depths = vec![0; datasets.len()];
+    if tree {
+        (datasets, depths) = tree_order(datasets).into_iter().unzip();
+    }
+
     let mut table = Table::new();
     table.load_preset(UTF8_FULL);
-    table.set_header(vec!["ID", "Name", "UUID", "Created At", "Type", "Readonly"]);
-
-    for subvol in datasets {
+    table.set_header(vec![
+        "ID",
+        "Name",
+        "UUID",
+        "Created At",
+        "Type",
+        "Readonly",
+        "Encrypted",
+    ]);
+
+    for (subvol, depth) in datasets.into_iter().zip(depths) {
         let sv_type = if subvol.is_snapshot {
             "Snapshot"
         } else {
@@ -82,11 +104,12 @@ pub async fn list_datasets(config_path: &Path) -> Result<()> {
 
         table.add_row(vec![
             subvol.id.to_string(),
-            subvol.name,
+            format!("{}{}", "  ".repeat(depth), subvol.name),
             subvol.uuid.to_string(),
             format_timestamp(subvol.created_at),
             sv_type.to_string(),
             subvol.is_readonly.to_string(),
+            subvol.wrapped_key.is_some().to_string(),
         ]);
     }
 
@@ -94,19 +117,84 @@ pub async fn list_datasets(config_path: &Path) -> Result<()> {
     Ok(())
 }
 
-/// Delete a dataset
-pub async fn delete_dataset(config_path: &Path, name: &str) -> Result<()> {
+/// Datasets depth-first from the root dataset, each with its depth in the
+/// tree. Datasets whose parent is not listed are kept at the top.
+fn tree_order(datasets: Vec<Dataset>) -> Vec<(Dataset, usize)> {
+    let mut children = tree_children(datasets);
+    let mut ordered = Vec::new();
+    let top = children.remove(&None).unwrap_or_default();
+    let mut stack: Vec<_> = top.into_iter().rev().map(|d| (d, 0)).collect();
+    while let Some((dataset, depth)) = stack.pop() {
+        let below = children.remove(&Some(dataset.id)).unwrap_or_default();
+        stack.extend(below.into_iter().rev().map(|d| (d, depth + 1)));
+        ordered.push((dataset, depth));
+    }
+    ordered
+}
+
+/// Delete a dataset, and with `recursive` everything below it
+pub async fn delete_dataset(config_path: &Path, name: &str, recursive: bool) -> Result<()> {
     let client = connect_rpc_client(config_path).await?;
-    client.delete_dataset(name).await?;
+    let (progress, descendants) = client.delete_dataset(name, recursive).await?;
 
     println!("✓ Dataset '{}' deleted successfully!", name);
+    print_destroyed(&progress, &descendants);
+    Ok(())
+}
+
+fn print_destroyed(progress: &DestroyProgress, descendants: &[DestroyProgress]) {
+    for descendant in descendants {
+        println!("  Also deleted '{}'", descendant.name);
+    }
+    let (bytes, inodes) = std::iter::once(progress)
+        .chain(descendants)
+        .fold((0, 0), |(bytes, inodes), p| {
+            (bytes + p.total_bytes, inodes + p.total_inodes)
+        });
+    println!(
+        "  Reclaiming {} in {} inodes in the background",
+        format_size(bytes),
+        inodes
+    );
+}
+
+/// List detached trees whose storage is still being reclaimed
+pub async fn list_dataset_destroys(config_path: &Path) -> Result<()> {
+    let client = connect_rpc_client(config_path).await?;
+    let destroys = client.list_dataset_destroys().await?;
+
+    if destroys.is_empty() {
+        println!("No datasets being reclaimed.");
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL);
+    table.set_header(vec!["ID", "Name", "Root Inode", "Destroyed At", "Inodes", "Bytes"]);
+
+    for progress in destroys {
+        table.add_row(vec![
+            progress.dataset_id.to_string(),
+            progress.name,
+            progress.root_inode.to_string(),
+            format_timestamp(progress.destroyed_at),
+            format!("{} / {}", progress.reclaimed_inodes, progress.total_inodes),
+            format!(
+                "{} / {}",
+                format_size(progress.reclaimed_bytes),
+                format_size(progress.total_bytes)
+            ),
+        ]);
+    }
+
+    println!("{table}");
     Ok(())
 }
 
 /// Get dataset info
 pub async fn get_dataset_info(config_path: &Path, name: &str) -> Result<()> {
     let client = connect_rpc_client(config_path).await?;
-    let dataset = client
+    let (dataset, usage) = client
         .get_dataset_info(name)
         .await?
         .context("Dataset not found")?;
@@ -124,6 +212,7 @@ pub async fn get_dataset_info(config_path: &Path, name: &str) -> Result<()> {
         }
     );
     println!("  Readonly: {}", dataset.is_readonly);
+    println!("  Encrypted: {}", dataset.wrapped_key.is_some());
     println!("  Created at: {}", format_timestamp(dataset.created_at));
     println!("  Root inode: {}", dataset.root_inode);
     println!("  Generation: {}", dataset.generation);
@@ -136,36 +225,153 @@ pub async fn get_dataset_info(config_path: &Path, name: &str) -> Result<()> {
         println!("  Parent UUID: {}", parent_uuid);
     }
 
+    if dataset.is_snapshot {
+        println!("  Holds: {}", format_holds(&dataset.holds));
+    }
+
+    print_usage(&dataset.quota, &usage);
+
+    Ok(())
+}
+
+/// Set the quota of a dataset
+pub async fn set_dataset_quota(config_path: &Path, name: &str, quota: DatasetQuota) -> Result<()> {
+    let client = connect_rpc_client(config_path).await?;
+    let (dataset, usage) = client.set_dataset_quota(name, quota).await?;
+
+    println!("✓ Quota of dataset '{}' updated", dataset.name);
+    print_usage(&dataset.quota, &usage);
     Ok(())
 }
 
-/// Create a snapshot (implemented as a COW clone)
+/// Show the properties of a dataset
+pub async fn get_dataset_properties(
+    config_path: &Path,
+    name: &str,
+    property: Option<&str>,
+) -> Result<()> {
+    let client = connect_rpc_client(config_path).await?;
+    let properties = client.get_dataset_properties(name, property).await?;
+
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL);
+    table.set_header(vec!["Property", "Value", "Source"]);
+
+    for value in properties {
+        let source = match value.inherited_from {
+            Some(from) => format!("inherited from {}", from),
+            None => value.source.as_str().to_string(),
+        };
+        table.add_row(vec![value.property, value.value, source]);
+    }
+
+    println!("{table}");
+    Ok(())
+}
+
+/// Set a property of a dataset from a `property=value` assignment
+pub async fn set_dataset_property(config_path: &Path, name: &str, assignment: &str) -> Result<()> {
+    let (property, value) = assignment
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Expected property=value, got '{}'", assignment))?;
+    let value = (value != "inherit").then_some(value);
+
+    let client = connect_rpc_client(config_path).await?;
+    let dataset = client.set_dataset_property(name, property, value).await?;
+
+    match value {
+        Some(value) => println!(
+            "✓ Property '{}' of dataset '{}' set to {}",
+            property, dataset.name, value
+        ),
+        None => println!(
+            "✓ Property '{}' of dataset '{}' is now inherited",
+            property, dataset.name
+        ),
+    }
+    Ok(())
+}
+
+pub async fn rename_dataset(config_path: &Path, name: &str, new_name: &str) -> Result<()> {
+    let client = connect_rpc_client(config_path).await?;
+    let dataset = client.rename_dataset(name, new_name).await?;
+
+    let kind = if dataset.is_snapshot {
+        "Snapshot"
+    } else {
+        "Dataset"
+    };
+    println!("✓ {} '{}' renamed to '{}'", kind, name, dataset.name);
+    println!("  UUID: {}", dataset.uuid);
+    Ok(())
+}
+
+fn format_limit(hard: Option<u64>, soft: Option<u64>, format: fn(u64) -> String) -> String {
+    match (hard, soft) {
+        (None, None) => "none".to_string(),
+        (Some(hard), None) => format(hard),
+        (None, Some(soft)) => format!("{} (soft)", format(soft)),
+        (Some(hard), Some(soft)) => format!("{} (soft {})", format(hard), format(soft)),
+    }
+}
+
+fn print_usage(quota: &DatasetQuota, usage: &DatasetUsage) {
+    println!(
+        "  Used: {} in {} inodes",
+        format_size(usage.bytes),
+        usage.inodes
+    );
+    println!(
+        "  Stored: {} ({} compressed, {} before compression)",
+        format_size(usage.space.stored),
+        format_size(usage.space.compressed),
+        format_size(usage.space.logical)
+    );
+    println!(
+        "  Byte quota: {}",
+        format_limit(quota.bytes_hard, quota.bytes_soft, format_size)
+    );
+    println!(
+        "  Inode quota: {}",
+        format_limit(quota.inodes_hard, quota.inodes_soft, |n| n.to_string())
+    );
+}
+
+/// Create a snapshot of a dataset
 pub async fn create_snapshot(
     config_path: &Path,
-    source_path: &str,
+    source: &str,
     snapshot_name: &str,
-    _readonly: bool, // Ignored - all clones are independent
+    readonly: bool,
+    recursive: bool,
 ) -> Result<()> {
-    // Snapshot is just a clone to /snapshots/<name>
-    let dest_path = format!("/snapshots/{}", snapshot_name);
-    
-    println!("📸 Creating snapshot (COW clone)...");
-    println!("   Source: {}", source_path);
-    println!("   Destination: {}", dest_path);
-    println!();
-    
-    // Call clone
-    clone_path(config_path, source_path, &dest_path).await?;
-    
-    println!();
-    println!("✅ Snapshot created!");
-    println!("   Location: {}", dest_path);
-    println!("   Type: Independent COW clone");
-    println!();
-    println!("💡 Tip: Snapshots are just directories");
-    println!("   View: ls {}", dest_path);
-    println!("   Restore: cp -r {} {}", dest_path, source_path);
-    
+    let client = connect_rpc_client(config_path).await?;
+    let (snapshot, descendants) = if recursive {
+        let mut snapshots = client
+            .create_recursive_snapshot(source, snapshot_name, readonly)
+            .await?
+            .into_iter();
+        let snapshot = snapshots.next().context("No snapshot was taken")?;
+        (snapshot, snapshots.collect())
+    } else {
+        let snapshot = client
+            .create_snapshot_with_options(source, snapshot_name, readonly)
+            .await?;
+        (snapshot, Vec::new())
+    };
+
+    println!(
+        "✓ Snapshot '{}' of dataset '{}' created!",
+        snapshot.name, source
+    );
+    println!("  ID: {}", snapshot.id);
+    println!("  UUID: {}", snapshot.uuid);
+    println!("  Created at: {}", format_timestamp(snapshot.created_at));
+    println!("  Read-only: {}", snapshot.is_readonly);
+    for descendant in descendants {
+        println!("  Child snapshot: {}", descendant.name);
+    }
+
     Ok(())
 }
 
@@ -186,7 +392,7 @@ pub async fn list_snapshots(config_path: &Path) -> Result<()> {
 
     let mut table = Table::new();
     table.load_preset(UTF8_FULL);
-    table.set_header(vec!["ID", "Name", "Source", "UUID", "Created At"]);
+    table.set_header(vec!["ID", "Name", "Source", "UUID", "Created At", "Holds"]);
 
     for snapshot in snapshots {
         let source = snapshot
@@ -205,6 +411,7 @@ pub async fn list_snapshots(config_path: &Path) -> Result<()> {
             source,
             snapshot.uuid.to_string(),
             format_timestamp(snapshot.created_at),
+            format_holds(&snapshot.holds),
         ]);
     }
 
@@ -212,12 +419,184 @@ pub async fn list_snapshots(config_path: &Path) -> Result<()> {
     Ok(())
 }
 
-/// Delete a snapshot
-pub async fn delete_snapshot(config_path: &Path, name: &str) -> Result<()> {
+/// Delete a snapshot, and with `recursive` the snapshots taken with it
+pub async fn delete_snapshot(config_path: &Path, name: &str, recursive: bool) -> Result<()> {
     let client = connect_rpc_client(config_path).await?;
-    client.delete_snapshot(name).await?;
+    let (progress, descendants) = client.delete_snapshot(name, recursive).await?;
 
     println!("✓ Snapshot '{}' deleted successfully!", name);
+    print_destroyed(&progress, &descendants);
+    Ok(())
+}
+
+/// Put a named hold on a snapshot
+pub async fn hold_snapshot(config_path: &Path, name: &str, tag: &str) -> Result<()> {
+    let client = connect_rpc_client(config_path).await?;
+    let snapshot = client.hold_snapshot(name, tag).await?;
+
+    println!("✓ Hold '{}' put on snapshot '{}'", tag, snapshot.name);
+    println!("  Holds: {}", format_holds(&snapshot.holds));
+    Ok(())
+}
+
+/// Release a hold on a snapshot
+pub async fn release_snapshot(config_path: &Path, name: &str, tag: &str) -> Result<()> {
+    let client = connect_rpc_client(config_path).await?;
+    let snapshot = client.release_snapshot(name, tag).await?;
+
+    println!("✓ Hold '{}' released on snapshot '{}'", tag, snapshot.name);
+    println!("  Holds: {}", format_holds(&snapshot.holds));
+    Ok(())
+}
+
+fn format_holds(holds: &BTreeSet<String>) -> String {
+    if holds.is_empty() {
+        return "-".to_string();
+    }
+    holds.iter().map(String::as_str).collect::<Vec<_>>().join(", ")
+}
+
+/// Roll a dataset back to one of its snapshots
+pub async fn rollback_dataset(
+    config_path: &Path,
+    name: &str,
+    snapshot_name: &str,
+    destroy_newer: bool,
+) -> Result<()> {
+    let client = connect_rpc_client(config_path).await?;
+    let (dataset, destroyed, replaced) = client
+        .rollback_dataset(name, snapshot_name, destroy_newer)
+        .await?;
+
+    println!(
+        "✓ Dataset '{}' rolled back to snapshot '{}'",
+        dataset.name, snapshot_name
+    );
+    println!("  Root inode: {}", dataset.root_inode);
+    for snapshot in &destroyed {
+        println!("  Destroyed newer snapshot '{}'", snapshot.name);
+    }
+    println!(
+        "  Reclaiming {} in {} inodes of the previous contents in the background",
+        format_size(replaced.total_bytes),
+        replaced.total_inodes
+    );
+    Ok(())
+}
+
+/// Print the changes since a snapshot, one per line: `+` added, `-`
+/// removed, `M` modified, `R` renamed
+pub async fn diff_snapshots(config_path: &Path, snapshot: &str, to: Option<&str>) -> Result<()> {
+    let client = connect_rpc_client(config_path).await?;
+    let changes = client.diff_snapshots(snapshot, to).await?;
+
+    if changes.is_empty() {
+        println!("No changes.");
+        return Ok(());
+    }
+
+    for change in changes {
+        let suffix = if change.is_directory { "/" } else { "" };
+        let mut what = Vec::new();
+        if change.content_changed {
+            what.push("content");
+        }
+        if change.metadata_changed {
+            what.push("metadata");
+        }
+        let what = if what.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", what.join(", "))
+        };
+        match change.kind {
+            ChangeKind::Added => println!("+ {}{}", change.path, suffix),
+            ChangeKind::Removed => println!("- {}{}", change.path, suffix),
+            ChangeKind::Modified => println!("M {}{}{}", change.path, suffix, what),
+            ChangeKind::Renamed => println!(
+                "R {}{} -> {}{}{}",
+                change.old_path.unwrap_or_default(),
+                suffix,
+                change.path,
+                suffix,
+                what
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Write the stream of a snapshot to `output`, or to stdout
+pub async fn send_snapshot(
+    config_path: &Path,
+    snapshot: &str,
+    from: Option<&str>,
+    output: Option<&Path>,
+) -> Result<()> {
+    if output.is_none() && std::io::stdout().is_terminal() {
+        bail!("Refusing to write the stream to a terminal; use --output or redirect stdout");
+    }
+
+    let client = connect_rpc_client(config_path).await?;
+    let mut stream = client.send_snapshot(snapshot, from).await?;
+
+    let mut out: Box<dyn AsyncWrite + Unpin> = match output {
+        Some(path) => Box::new(
+            tokio::fs::File::create(path)
+                .await
+                .with_context(|| format!("Failed to create {}", path.display()))?,
+        ),
+        None => Box::new(tokio::io::stdout()),
+    };
+    let mut bytes = 0u64;
+    while let Some(chunk) = stream
+        .message()
+        .await
+        .map_err(|s| anyhow!("Failed to send snapshot: {}", s.message()))?
+    {
+        out.write_all(&chunk.data)
+            .await
+            .context("Failed to write the stream")?;
+        bytes += chunk.data.len() as u64;
+    }
+    out.shutdown().await.context("Failed to write the stream")?;
+
+    // Stdout may be the stream itself
+    eprintln!("✓ Sent snapshot '{}' ({})", snapshot, format_size(bytes));
+    Ok(())
+}
+
+/// Apply the stream read from `input`, or from stdin, to a dataset
+pub async fn receive_snapshot(
+    config_path: &Path,
+    dataset: &str,
+    input: Option<&Path>,
+    encrypt: bool,
+    force: bool,
+) -> Result<()> {
+    let client = connect_rpc_client(config_path).await?;
+    let options = ReceiveOptions { encrypt, force };
+    let outcome = match input {
+        Some(path) => {
+            let file = tokio::fs::File::open(path)
+                .await
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+            client.receive_snapshot(dataset, options, file).await?
+        }
+        None => {
+            client
+                .receive_snapshot(dataset, options, tokio::io::stdin())
+                .await?
+        }
+    };
+
+    println!(
+        "✓ Received snapshot '{}' into dataset '{}'",
+        outcome.snapshot.name, outcome.dataset.name
+    );
+    println!("  Entries created: {}", outcome.entries);
+    println!("  Data written: {}", format_size(outcome.data_bytes));
+    println!("  Encrypted: {}", outcome.dataset.wrapped_key.is_some());
     Ok(())
 }
 
@@ -239,30 +618,54 @@ pub async fn get_default_dataset(config_path: &Path) -> Result<()> {
     Ok(())
 }
 
-// Removed: is_internal_zerofs_path() - no longer needed
-
-/// Restore is deprecated - just use clone or copy the directory
+/// Restore a file or directory from a snapshot (COW, instant)
 pub async fn restore_from_snapshot(
-    _config_path: &Path,
+    config_path: &Path,
     snapshot_name: &str,
     source_path: &str,
     destination_path: &str,
+    on_conflict: OnConflict,
+    dry_run: bool,
 ) -> Result<()> {
-    println!("❌ 'restore' command is deprecated!");
-    println!();
-    println!("Snapshots are just directories now. To restore:");
-    println!();
-    println!("Option 1: Copy the directory (filesystem operations)");
-    println!("  cp -r /snapshots/{}/{} {}", snapshot_name, source_path, destination_path);
-    println!();
-    println!("Option 2: Use clone command (COW, instant)");
-    println!("  zerofs dataset clone --source /snapshots/{}/{} --destination {}", 
-        snapshot_name, source_path, destination_path);
-    println!();
-    println!("💡 Tip: Snapshots are independent copies, not special entities.");
-    println!("   Just treat them as regular directories!");
-    
-    anyhow::bail!("Use 'clone' or 'cp' instead of 'restore'")
+    let client = connect_rpc_client(config_path).await?;
+    let outcome = client
+        .instant_restore_file(
+            snapshot_name,
+            source_path,
+            destination_path,
+            on_conflict,
+            dry_run,
+        )
+        .await?;
+
+    let kind = if outcome.is_directory {
+        "Directory"
+    } else {
+        "File"
+    };
+    if outcome.dry_run {
+        println!(
+            "Would restore '{}' of snapshot '{}' to {}",
+            source_path, snapshot_name, outcome.destination_path
+        );
+        if outcome.replaced {
+            println!("  {} would be replaced", destination_path);
+        }
+        return Ok(());
+    }
+
+    println!(
+        "✓ {} restored from snapshot '{}' to {}",
+        kind, snapshot_name, outcome.destination_path
+    );
+    println!("  Inode: {}", outcome.inode_id);
+    if !outcome.is_directory {
+        println!("  Size: {}", format_size(outcome.size));
+    }
+    if outcome.replaced {
+        println!("  Replaced the previous {}", destination_path);
+    }
+    Ok(())
 }
 
 /// Clone a path (COW, instant copy)
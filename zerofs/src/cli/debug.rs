@@ -1,7 +1,7 @@
 use crate::config::Settings;
 use crate::encryption::SlateDbHandle;
 use crate::fs::CacheConfig;
-use crate::fs::key_codec::{KeyPrefix, SYSTEM_WRAPPED_ENCRYPTION_KEY};
+use crate::fs::key_codec::{KeyCodec, KeyPrefix, SYSTEM_KEY_SLOTS, SYSTEM_WRAPPED_ENCRYPTION_KEY};
 use crate::key_management;
 use crate::parse_object_store::parse_url_opts;
 use anyhow::{Context, Result};
@@ -35,7 +35,9 @@ pub async fn list_keys(config_path: PathBuf) -> Result<()> {
         ..cache_config
     };
 
-    let password = settings.storage.encryption_password.clone();
+    let password = key_management::KeyProvider::from_config(&settings.storage)?
+        .secret()
+        .await?;
 
     crate::cli::password::validate_password(&password)
         .map_err(|e| anyhow::anyhow!("Password validation failed: {}", e))?;
@@ -50,13 +52,13 @@ pub async fn list_keys(config_path: PathBuf) -> Result<()> {
     )
     .await?;
 
-    let encryption_key = key_management::load_or_init_encryption_key(&slatedb, &password).await?;
+    let data_keys = key_management::load_or_init_encryption_key(&slatedb, &password).await?;
 
     let encrypted_db = match slatedb {
         // We don't write but we want the "current" view of the DB
         SlateDbHandle::ReadWrite(db) => {
-            let encryptor = Arc::new(crate::encryption::EncryptionManager::new(
-                &encryption_key,
+            let encryptor = Arc::new(crate::encryption::EncryptionManager::with_keys(
+                &data_keys,
                 crate::config::CompressionConfig::default(),
             ));
             crate::encryption::EncryptedDb::new(db, encryptor)
@@ -83,6 +85,11 @@ pub async fn list_keys(config_path: PathBuf) -> Result<()> {
                     count += 1;
                     continue;
                 }
+                if key.as_ref() == SYSTEM_KEY_SLOTS {
+                    println!("[SYSTEM] key=system:key_slots");
+                    count += 1;
+                    continue;
+                }
 
                 let prefix = match key.first().and_then(|&b| KeyPrefix::try_from(b).ok()) {
                     Some(p) => p,
@@ -136,6 +143,64 @@ pub async fn list_keys(config_path: PathBuf) -> Result<()> {
                     KeyPrefix::System => {
                         println!("subtype=0x{:02x}", key.get(1).unwrap_or(&0));
                     }
+                    KeyPrefix::ChunkLayer if key.len() == 9 => {
+                        let map_id = u64::from_be_bytes(key[1..9].try_into().unwrap());
+                        println!("map_id={}", map_id);
+                    }
+                    KeyPrefix::LazyDir if key.len() == 9 => {
+                        let dir_id = u64::from_be_bytes(key[1..9].try_into().unwrap());
+                        println!("dir_id={}", dir_id);
+                    }
+                    KeyPrefix::Xattr if key.len() == 9 => {
+                        let inode_id = u64::from_be_bytes(key[1..9].try_into().unwrap());
+                        println!("inode_id={}", inode_id);
+                    }
+                    KeyPrefix::DatasetStats if key.len() == 17 => {
+                        let dataset_id = u64::from_be_bytes(key[1..9].try_into().unwrap());
+                        let shard_id = u64::from_be_bytes(key[9..17].try_into().unwrap());
+                        println!("dataset_id={}, shard_id={}", dataset_id, shard_id);
+                    }
+                    KeyPrefix::OwnerStats => match KeyCodec::parse_owner_stats_key(&key) {
+                        Some((owner, shard_id)) => {
+                            println!("owner={:?}, shard_id={}", owner, shard_id);
+                        }
+                        None => println!("raw={:?}", key),
+                    },
+                    KeyPrefix::DatasetDestroy => match KeyCodec::parse_dataset_destroy_key(&key) {
+                        Some(root_inode) => println!("root_inode={}", root_inode),
+                        None => println!("raw={:?}", key),
+                    },
+                    KeyPrefix::LinkDir => match KeyCodec::parse_link_dir_key(&key) {
+                        Some((inode_id, dir_id, name)) => println!(
+                            "inode_id={}, dir_id={}, name=\"{}\"",
+                            inode_id,
+                            dir_id,
+                            String::from_utf8_lossy(name)
+                        ),
+                        None => println!("raw={:?}", key),
+                    },
+                    KeyPrefix::CaseFold if key.len() > 9 => {
+                        let dir_id = u64::from_be_bytes(key[1..9].try_into().unwrap());
+                        let name = KeyCodec::parse_case_fold_key(&key).unwrap_or_default();
+                        println!(
+                            "dir_id={}, name=\"{}\"",
+                            dir_id,
+                            String::from_utf8_lossy(name)
+                        );
+                    }
+                    KeyPrefix::SpaceStats => match KeyCodec::parse_space_stats_key(&key) {
+                        Some(dataset_id) => println!("dataset_id={}", dataset_id),
+                        None => println!("raw={:?}", key),
+                    },
+                    KeyPrefix::ChunkCas
+                    | KeyPrefix::ChunkRefCount
+                    | KeyPrefix::ChunkOrphan
+                    | KeyPrefix::ChunkFootprint
+                        if key.len() == 33 =>
+                    {
+                        let hash: String = key[1..].iter().map(|b| format!("{:02x}", b)).collect();
+                        println!("hash={}", hash);
+                    }
                     _ => {
                         println!("raw={:?}", key);
                     }
@@ -0,0 +1,111 @@
+use crate::cli::server::{DatabaseMode, build_slatedb};
+use crate::config::Settings;
+use crate::encryption::{KeyId, SlateDbHandle};
+use crate::fs::CacheConfig;
+use crate::key_management::{self, Argon2Params, KeyProvider};
+use crate::parse_object_store::parse_url_opts;
+use crate::rpc::client::RpcClient;
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::sync::Arc;
+
+async fn open_db(settings: &Settings, mode: DatabaseMode) -> Result<SlateDbHandle> {
+    let env_vars = settings.cloud_provider_env_vars();
+    let (object_store, path_from_url) =
+        parse_url_opts(&settings.storage.url.parse()?, env_vars.into_iter())?;
+    let object_store: Arc<dyn object_store::ObjectStore> = Arc::from(object_store);
+    let actual_db_path = path_from_url.to_string();
+
+    let bucket =
+        crate::bucket_identity::BucketIdentity::get_or_create(&object_store, &actual_db_path)
+            .await?;
+    let cache_config = CacheConfig {
+        root_folder: settings.cache.dir.join(bucket.cache_directory_name()),
+        max_cache_size_gb: settings.cache.disk_size_gb,
+        memory_cache_size_gb: settings.cache.memory_size_gb,
+    };
+
+    let (slatedb, _, _) = build_slatedb(
+        object_store,
+        &cache_config,
+        actual_db_path,
+        mode,
+        settings.lsm,
+        false,
+    )
+    .await?;
+
+    Ok(slatedb)
+}
+
+/// Connect to the running server, which changes the keys itself so that no
+/// second writer opens the database, with the password in the config.
+async fn connect_rpc_client(config_path: &Path) -> Result<(RpcClient, String)> {
+    let settings = Settings::from_file(config_path)
+        .with_context(|| format!("Failed to load config from {}", config_path.display()))?;
+
+    let password = KeyProvider::from_config(&settings.storage)?
+        .secret()
+        .await?;
+    crate::cli::password::validate_password(&password)
+        .map_err(|e| anyhow::anyhow!("Password validation failed: {}", e))?;
+
+    let rpc_config = settings
+        .servers
+        .rpc
+        .as_ref()
+        .context("RPC server not configured in config file")?;
+    let client = RpcClient::connect_from_config(rpc_config)
+        .await
+        .context("Failed to connect to RPC server. Is the server running?")?;
+
+    Ok((client, password))
+}
+
+/// Switch new writes to a freshly generated data encryption key. Existing
+/// values are re-encrypted by the server in the background.
+pub async fn rotate_key(config_path: &Path) -> Result<KeyId> {
+    let (client, password) = connect_rpc_client(config_path).await?;
+    client.rotate_data_key(&password).await
+}
+
+pub async fn add_key_slot(
+    config_path: &Path,
+    label: &str,
+    new_password: &str,
+    params: Argon2Params,
+) -> Result<()> {
+    crate::cli::password::validate_password(new_password)
+        .map_err(|e| anyhow::anyhow!("Password validation failed: {}", e))?;
+
+    let (client, password) = connect_rpc_client(config_path).await?;
+    client
+        .add_key_slot(&password, label, new_password, params)
+        .await
+}
+
+pub async fn list_key_slots(config_path: &Path) -> Result<()> {
+    let settings = Settings::from_file(config_path)
+        .with_context(|| format!("Failed to load config from {}", config_path.display()))?;
+
+    let slatedb = open_db(&settings, DatabaseMode::ReadOnly).await?;
+    let slots = key_management::list_key_slots(&slatedb).await?;
+
+    println!(
+        "{:<24} {:>12} {:>10} {:>11}",
+        "LABEL", "MEMORY_KIB", "ITERATIONS", "PARALLELISM"
+    );
+    for (label, params) in slots {
+        println!(
+            "{:<24} {:>12} {:>10} {:>11}",
+            label, params.memory_kib, params.iterations, params.parallelism
+        );
+    }
+
+    Ok(())
+}
+
+pub async fn revoke_key_slot(config_path: &Path, label: &str) -> Result<()> {
+    let (client, password) = connect_rpc_client(config_path).await?;
+    client.revoke_key_slot(&password, label).await
+}
@@ -6,8 +6,10 @@ pub mod compactor;
 pub mod dataset;
 pub mod debug;
 pub mod fatrace;
+pub mod keys;
 pub mod nbd;
 pub mod password;
+pub mod quota;
 pub mod server;
 
 #[derive(Parser)]
@@ -50,6 +52,20 @@ pub enum Commands {
         #[arg(short, long)]
         config: PathBuf,
     },
+    /// Rotate the data encryption key
+    ///
+    /// Asks the running server to generate a new key, which it encrypts new
+    /// values with from its next start. It then re-encrypts existing values
+    /// in the background and retires the old key once it is done.
+    RotateKey {
+        #[arg(short, long)]
+        config: PathBuf,
+    },
+    /// Manage the key slots: passwords that can each unlock the filesystem
+    KeySlot {
+        #[command(subcommand)]
+        subcommand: KeySlotCommands,
+    },
     /// Debug commands for inspecting the database
     Debug {
         #[command(subcommand)]
@@ -70,6 +86,11 @@ pub enum Commands {
         #[command(subcommand)]
         subcommand: DatasetCommands,
     },
+    /// Per-uid and per-gid usage and quotas
+    Quota {
+        #[command(subcommand)]
+        subcommand: QuotaCommands,
+    },
     /// Clone a file or directory using COW (instant copy, no data duplication)
     Clone {
         #[arg(short, long)]
@@ -96,6 +117,44 @@ pub enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+pub enum KeySlotCommands {
+    /// Add a key slot
+    ///
+    /// Reads the new slot's password from stdin. The password in the config
+    /// file must open an existing slot. Applied by the running server.
+    Add {
+        #[arg(short, long)]
+        config: PathBuf,
+        /// Label identifying the slot
+        label: String,
+        /// Argon2 memory cost in KiB
+        #[arg(long, default_value_t = 65536)]
+        memory_kib: u32,
+        /// Argon2 iterations
+        #[arg(long, default_value_t = 3)]
+        iterations: u32,
+        /// Argon2 parallelism
+        #[arg(long, default_value_t = 4)]
+        parallelism: u32,
+    },
+    /// List key slots
+    List {
+        #[arg(short, long)]
+        config: PathBuf,
+    },
+    /// Revoke a key slot
+    ///
+    /// The password in the config file must open one of the slots. The last
+    /// slot cannot be revoked. Applied by the running server.
+    Revoke {
+        #[arg(short, long)]
+        config: PathBuf,
+        /// Label of the slot to revoke
+        label: String,
+    },
+}
+
 #[derive(Subcommand)]
 pub enum DebugCommands {
     /// List all keys in the database
@@ -135,6 +194,32 @@ pub enum CheckpointCommands {
     },
 }
 
+#[derive(Subcommand)]
+pub enum QuotaCommands {
+    /// Show the usage and limits of every uid and gid
+    Report {
+        #[arg(short, long)]
+        config: PathBuf,
+    },
+    /// Set the limits of a uid or gid. Limits that are not given are removed.
+    Set {
+        #[arg(short, long)]
+        config: PathBuf,
+        /// uid to limit
+        #[arg(long, required_unless_present = "group", conflicts_with = "group")]
+        user: Option<u32>,
+        /// gid to limit
+        #[arg(long)]
+        group: Option<u32>,
+        /// Hard limit on bytes used
+        #[arg(long)]
+        bytes: Option<u64>,
+        /// Hard limit on inodes used
+        #[arg(long)]
+        inodes: Option<u64>,
+    },
+}
+
 #[derive(Subcommand)]
 pub enum NbdCommands {
     /// Create a new NBD device
@@ -287,13 +372,22 @@ pub enum DatasetCommands {
     Create {
         #[arg(short, long)]
         config: PathBuf,
-        /// Dataset name (must be unique)
+        /// Dataset name (must be unique). A name like team/app nests the
+        /// dataset in dataset team, at the directory app of its root.
         name: String,
+        /// Encrypt the dataset's data with a key of its own, destroyed when
+        /// the dataset is deleted
+        #[arg(long)]
+        encrypt: bool,
     },
     /// List all datasets
     List {
         #[arg(short, long)]
         config: PathBuf,
+        /// Show datasets below the dataset they are nested in, and snapshots
+        /// below their source
+        #[arg(long)]
+        tree: bool,
     },
     /// Delete a dataset
     Delete {
@@ -301,6 +395,15 @@ pub enum DatasetCommands {
         config: PathBuf,
         /// Dataset name to delete
         name: String,
+        /// Also delete the child datasets and the snapshots of all of them
+        #[arg(short, long)]
+        recursive: bool,
+    },
+    /// List deleted datasets and snapshots, and trees replaced by a
+    /// rollback, whose storage is still being reclaimed
+    Destroys {
+        #[arg(short, long)]
+        config: PathBuf,
     },
     /// Get dataset information
     Info {
@@ -309,6 +412,52 @@ pub enum DatasetCommands {
         /// Dataset name to query
         name: String,
     },
+    /// Set the quota of a dataset. Limits that are not given are removed.
+    Quota {
+        #[arg(short, long)]
+        config: PathBuf,
+        /// Dataset name
+        name: String,
+        /// Hard limit on bytes used
+        #[arg(long)]
+        bytes: Option<u64>,
+        /// Soft limit on bytes used, logged when crossed
+        #[arg(long)]
+        bytes_soft: Option<u64>,
+        /// Hard limit on inodes used
+        #[arg(long)]
+        inodes: Option<u64>,
+        /// Soft limit on inodes used, logged when crossed
+        #[arg(long)]
+        inodes_soft: Option<u64>,
+    },
+    /// Show the properties of a dataset and where their values come from
+    Get {
+        #[arg(short, long)]
+        config: PathBuf,
+        /// Dataset name
+        name: String,
+        /// Only show this property (e.g., compression)
+        property: Option<String>,
+    },
+    /// Set a property of a dataset (property=value, or property=inherit to unset it)
+    Set {
+        #[arg(short, long)]
+        config: PathBuf,
+        /// Dataset name
+        name: String,
+        /// Property assignment (e.g., compression=zstd-3)
+        assignment: String,
+    },
+    /// Rename a dataset or snapshot. A dataset moves with its name (e.g., to other/app).
+    Rename {
+        #[arg(short, long)]
+        config: PathBuf,
+        /// Current dataset or snapshot name
+        name: String,
+        /// New name
+        new_name: String,
+    },
     /// Create a snapshot of a dataset
     Snapshot {
         #[arg(short, long)]
@@ -320,6 +469,10 @@ pub enum DatasetCommands {
         /// Create read-only snapshot (default: read-write, like btrfs)
         #[arg(long)]
         readonly: bool,
+        /// Also snapshot every child dataset at the same point in time, as
+        /// <name>/<path below the source>
+        #[arg(short, long)]
+        recursive: bool,
     },
     /// List all snapshots
     ListSnapshots {
@@ -332,6 +485,83 @@ pub enum DatasetCommands {
         config: PathBuf,
         /// Snapshot name to delete
         name: String,
+        /// Also delete the snapshots taken with it by a recursive snapshot
+        #[arg(short, long)]
+        recursive: bool,
+    },
+    /// Put a named hold on a snapshot; it cannot be deleted until every hold
+    /// is released
+    Hold {
+        #[arg(short, long)]
+        config: PathBuf,
+        /// Snapshot name
+        snapshot: String,
+        /// Name of the hold
+        tag: String,
+    },
+    /// Release a hold on a snapshot
+    Release {
+        #[arg(short, long)]
+        config: PathBuf,
+        /// Snapshot name
+        snapshot: String,
+        /// Name of the hold
+        tag: String,
+    },
+    /// Roll a dataset back to one of its snapshots. The dataset's current
+    /// contents are discarded and open file handles into it become stale.
+    Rollback {
+        #[arg(short, long)]
+        config: PathBuf,
+        /// Dataset name
+        dataset: String,
+        /// Snapshot of the dataset to roll back to
+        snapshot: String,
+        /// Destroy the snapshots taken after `snapshot` instead of refusing
+        #[arg(long)]
+        destroy_newer: bool,
+    },
+    /// Show what changed since a snapshot
+    Diff {
+        #[arg(short, long)]
+        config: PathBuf,
+        /// Snapshot to compare from
+        snapshot: String,
+        /// Later snapshot or dataset to compare with (defaults to the live
+        /// dataset the snapshot was taken of)
+        to: Option<String>,
+    },
+    /// Write a snapshot as a stream that `receive` applies in another
+    /// filesystem
+    Send {
+        #[arg(short, long)]
+        config: PathBuf,
+        /// Snapshot to send
+        snapshot: String,
+        /// Earlier snapshot of the same dataset the receiving side has; only
+        /// the changes since are sent
+        #[arg(long)]
+        from: Option<String>,
+        /// File to write the stream to (defaults to stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Apply a stream written by `send` to a dataset, and snapshot it
+    Receive {
+        #[arg(short, long)]
+        config: PathBuf,
+        /// Dataset to receive into; created by a full stream
+        dataset: String,
+        /// File to read the stream from (defaults to stdin)
+        #[arg(short, long)]
+        input: Option<PathBuf>,
+        /// Give a dataset created from a full stream a key of its own
+        #[arg(long)]
+        encrypt: bool,
+        /// Roll the dataset back to the base snapshot of an incremental
+        /// stream, discarding changes and snapshots made since
+        #[arg(long)]
+        force: bool,
     },
     /// Set the default dataset
     SetDefault {
@@ -345,19 +575,29 @@ pub enum DatasetCommands {
         #[arg(short, long)]
         config: PathBuf,
     },
-    /// Restore a file from a snapshot
+    /// Restore a file or directory from a snapshot (COW, instant)
     Restore {
         #[arg(short, long)]
         config: PathBuf,
         /// Snapshot name to restore from
         #[arg(long)]
         snapshot: String,
-        /// Path to file/directory within snapshot (e.g., /mnt/my-volume/file.txt)
-        #[arg(long)]
+        /// Path to file/directory within the snapshot (e.g., /file.txt); the
+        /// whole snapshot when omitted
+        #[arg(long, default_value = "")]
         source: String,
-        /// Destination path to restore to (e.g., /tmp/restored-file.txt)
+        /// Destination path within the filesystem (e.g., /my-volume/file.txt)
         #[arg(long)]
         destination: String,
+        /// Replace the destination if it exists
+        #[arg(long, conflicts_with = "rename")]
+        overwrite: bool,
+        /// Restore as <name>.restored if the destination exists
+        #[arg(long)]
+        rename: bool,
+        /// Only report what would be restored
+        #[arg(long)]
+        dry_run: bool,
     },
 }
 
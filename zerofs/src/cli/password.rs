@@ -37,7 +37,11 @@ pub async fn change_password(
     settings: &Settings,
     new_password: String,
 ) -> Result<(), PasswordError> {
-    let current_password = &settings.storage.encryption_password;
+    let current_password = match key_management::KeyProvider::from_config(&settings.storage) {
+        Ok(provider) => provider.secret().await,
+        Err(e) => Err(e),
+    }
+    .map_err(|e| PasswordError::Other(e.to_string()))?;
 
     if current_password == "CHANGEME" {
         return Err(PasswordError::CurrentPasswordIsDefault);
@@ -76,7 +80,7 @@ pub async fn change_password(
     .await
     .map_err(|e| PasswordError::Other(e.to_string()))?;
 
-    key_management::change_encryption_password(&slatedb, current_password, &new_password)
+    key_management::change_encryption_password(&slatedb, &current_password, &new_password)
         .await
         .map_err(|e| PasswordError::EncryptionError(e.to_string()))?;
 
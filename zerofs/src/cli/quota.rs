@@ -0,0 +1,101 @@
+use crate::config::Settings;
+use crate::fs::quota::{Owner, OwnerQuota, OwnerReport};
+use crate::rpc::client::RpcClient;
+use anyhow::{Context, Result};
+use comfy_table::{Table, presets::UTF8_FULL};
+use std::path::Path;
+
+async fn connect_rpc_client(config_path: &Path) -> Result<RpcClient> {
+    let settings = Settings::from_file(config_path)
+        .with_context(|| format!("Failed to load config from {}", config_path.display()))?;
+
+    let rpc_config = settings
+        .servers
+        .rpc
+        .as_ref()
+        .context("RPC server not configured in config file")?;
+
+    RpcClient::connect_from_config(rpc_config)
+        .await
+        .context("Failed to connect to RPC server. Is the server running?")
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit_idx = 0;
+
+    while size >= 1024.0 && unit_idx < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit_idx += 1;
+    }
+
+    if unit_idx == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.2} {}", size, UNITS[unit_idx])
+    }
+}
+
+fn owner_row(report: OwnerReport) -> Vec<String> {
+    let (kind, id) = match report.owner {
+        Owner::User(uid) => ("user", uid),
+        Owner::Group(gid) => ("group", gid),
+    };
+    vec![
+        kind.to_string(),
+        id.to_string(),
+        format_size(report.bytes),
+        report.quota.bytes.map_or("-".to_string(), format_size),
+        report.inodes.to_string(),
+        report
+            .quota
+            .inodes
+            .map_or("-".to_string(), |n| n.to_string()),
+    ]
+}
+
+fn owner_table(reports: impl IntoIterator<Item = OwnerReport>) -> Table {
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL);
+    table.set_header(vec![
+        "Type",
+        "ID",
+        "Used",
+        "Byte Limit",
+        "Inodes",
+        "Inode Limit",
+    ]);
+    for report in reports {
+        table.add_row(owner_row(report));
+    }
+    table
+}
+
+/// Show the usage and limits of every uid and gid
+pub async fn quota_report(config_path: &Path) -> Result<()> {
+    let client = connect_rpc_client(config_path).await?;
+    let reports = client.quota_report().await?;
+
+    if reports.is_empty() {
+        println!("No usage recorded.");
+        return Ok(());
+    }
+
+    println!("{}", owner_table(reports));
+    Ok(())
+}
+
+/// Set the limits of a uid or gid
+pub async fn set_quota(config_path: &Path, owner: Owner, quota: OwnerQuota) -> Result<()> {
+    let client = connect_rpc_client(config_path).await?;
+    let report = client.set_owner_quota(owner, quota).await?;
+
+    if quota.is_empty() {
+        println!("✓ Removed the quota of {}", owner);
+    } else {
+        println!("✓ Quota of {} updated", owner);
+    }
+    println!("{}", owner_table([report]));
+    Ok(())
+}
@@ -4,9 +4,12 @@ use crate::checkpoint_manager::CheckpointManager;
 use crate::config::{NbdConfig, NfsConfig, NinePConfig, RpcConfig, Settings};
 use crate::encryption::SlateDbHandle;
 use crate::fs::permissions::Credentials;
+use crate::fs::reencrypt::ReencryptionState;
 use crate::fs::tracing::AccessTracer;
 use crate::fs::types::SetAttributes;
-use crate::fs::{CacheConfig, GarbageCollector, ZeroFS};
+use crate::fs::{
+    CacheConfig, DatasetReclaimer, GarbageCollector, Reencryptor, SnapshotScheduler, ZeroFS,
+};
 use crate::key_management;
 use crate::nbd::NBDServer;
 use crate::parse_object_store::parse_url_opts;
@@ -30,6 +33,7 @@ use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 
 const CHECKPOINT_REFRESH_INTERVAL_SECS: u64 = 10;
+const SNAPSHOT_SCHEDULE_CHECK_INTERVAL_SECS: u64 = 60;
 
 #[derive(Debug, Clone, Copy)]
 pub enum DatabaseMode {
@@ -212,6 +216,7 @@ async fn start_rpc_servers(
     checkpoint_manager: Arc<CheckpointManager>,
     tracer: AccessTracer,
     fs: Arc<crate::fs::ZeroFS>,
+    snapshot_scheduler: Arc<SnapshotScheduler>,
     shutdown: CancellationToken,
 ) -> Vec<JoinHandle<Result<(), std::io::Error>>> {
     let config = match config {
@@ -223,6 +228,7 @@ async fn start_rpc_servers(
         checkpoint_manager,
         tracer,
         fs.clone(),
+        snapshot_scheduler,
     );
     let mut handles = Vec::new();
 
@@ -268,7 +274,7 @@ fn start_stats_reporting(fs: Arc<ZeroFS>, shutdown: CancellationToken) -> JoinHa
                     break;
                 }
                 _ = interval.tick() => {
-                    fs.stats.output_report_debug();
+                    fs.stats.output_report_debug(fs.db.space_stats().totals());
                 }
             }
         }
@@ -302,6 +308,33 @@ fn start_periodic_flush(
     })
 }
 
+fn start_snapshot_scheduler(
+    scheduler: Arc<SnapshotScheduler>,
+    shutdown: CancellationToken,
+) -> JoinHandle<()> {
+    spawn_named("snapshot-scheduler", async move {
+        info!(
+            "Starting snapshot scheduler (checks every {} seconds)",
+            SNAPSHOT_SCHEDULE_CHECK_INTERVAL_SECS
+        );
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+            SNAPSHOT_SCHEDULE_CHECK_INTERVAL_SECS,
+        ));
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => {
+                    info!("Snapshot scheduler shutting down");
+                    break;
+                }
+                _ = interval.tick() => {
+                    let (now, _) = crate::fs::get_current_time();
+                    scheduler.run(now).await;
+                }
+            }
+        }
+    })
+}
+
 pub struct CheckpointRefreshParams {
     pub db_path: String,
     pub object_store: Arc<dyn object_store::ObjectStore>,
@@ -625,7 +658,9 @@ async fn initialize_filesystem(
             .await?;
     }
 
-    let password = settings.storage.encryption_password.clone();
+    let password = key_management::KeyProvider::from_config(&settings.storage)?
+        .secret()
+        .await?;
 
     super::password::validate_password(&password)
         .map_err(|e| anyhow::anyhow!("Password validation failed: {}", e))?;
@@ -642,7 +677,8 @@ async fn initialize_filesystem(
     )
     .await?;
 
-    let encryption_key = key_management::load_or_init_encryption_key(&slatedb, &password).await?;
+    let data_keys = key_management::load_or_init_encryption_key(&slatedb, &password).await?;
+    let has_retired_keys = data_keys.keys.len() > 1;
 
     // Configure writeback cache if enabled
     let writeback_config = settings.cache.writeback.as_ref().and_then(|wb| {
@@ -711,13 +747,26 @@ async fn initialize_filesystem(
     let db_handle = slatedb.clone();
     let mut fs = ZeroFS::new_with_slatedb_and_writeback(
         slatedb,
-        encryption_key,
+        data_keys,
         settings.max_bytes(),
         settings.compression(),
         writeback_config,
         metadata_cache_config,
     )
     .await?;
+    // New files are created with the configured chunk size; existing files
+    // keep the one they were written with.
+    fs.chunk_size = settings.chunk_size();
+
+    // Key generations replaced by `zerofs rotate-key` are dropped once every
+    // value has been re-encrypted with the current one.
+    if has_retired_keys && !db_mode.is_read_only() {
+        let state = ReencryptionState::load(&fs.db).await?;
+        if state.complete && state.key_id == fs.db.current_key_id() {
+            info!("Retiring encryption keys replaced by key rotation");
+            key_management::retire_old_keys(&db_handle, &password).await?;
+        }
+    }
 
     Ok(InitResult {
         fs: Arc::new(fs),
@@ -831,11 +880,17 @@ pub async fn run_server(
         init_result.object_store,
     ));
 
+    let snapshot_scheduler = Arc::new(SnapshotScheduler::new(
+        Arc::clone(&fs),
+        settings.snapshot_schedules.clone(),
+    ));
+
     let rpc_handles = start_rpc_servers(
         settings.servers.rpc.as_ref(),
         checkpoint_manager,
         fs.tracer.clone(),
         fs.clone(),
+        Arc::clone(&snapshot_scheduler),
         shutdown.clone(),
     )
     .await;
@@ -858,11 +913,35 @@ pub async fn run_server(
             fs.tombstone_store.clone(),
             fs.chunk_store.clone(),
             Arc::clone(&fs.stats),
+            Arc::clone(&fs.write_coordinator),
         ));
         Some(gc.start(shutdown.clone(), init_result.maintenance_runtime.clone()))
     } else {
         None
     };
+    let reencrypt_handle = if !db_mode.is_read_only() {
+        let reencryptor = Arc::new(Reencryptor::new(
+            Arc::clone(&fs.db),
+            fs.dataset_store.clone(),
+        ));
+        Some(reencryptor.start(shutdown.clone(), init_result.maintenance_runtime.clone()))
+    } else {
+        None
+    };
+    let reclaim_handle = if !db_mode.is_read_only() {
+        let reclaimer = Arc::new(DatasetReclaimer::new(Arc::clone(&fs)));
+        Some(reclaimer.start(shutdown.clone(), init_result.maintenance_runtime.clone()))
+    } else {
+        None
+    };
+    let schedule_handle = if !db_mode.is_read_only() && !snapshot_scheduler.is_empty() {
+        Some(start_snapshot_scheduler(
+            Arc::clone(&snapshot_scheduler),
+            shutdown.clone(),
+        ))
+    } else {
+        None
+    };
     let stats_handle = start_stats_reporting(Arc::clone(&fs), shutdown.clone());
     let flush_handle = if !db_mode.is_read_only() {
         let flush_interval_secs = settings
@@ -917,6 +996,15 @@ pub async fn run_server(
     if let Some(gc_handle) = gc_handle {
         let _ = gc_handle.await;
     }
+    if let Some(reencrypt_handle) = reencrypt_handle {
+        let _ = reencrypt_handle.await;
+    }
+    if let Some(reclaim_handle) = reclaim_handle {
+        let _ = reclaim_handle.await;
+    }
+    if let Some(schedule_handle) = schedule_handle {
+        let _ = schedule_handle.await;
+    }
     let _ = stats_handle.await;
     if let Some(flush_handle) = flush_handle {
         let _ = flush_handle.await;
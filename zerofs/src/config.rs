@@ -1,6 +1,6 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::fmt;
 use std::fs;
 use std::net::{IpAddr, SocketAddr};
@@ -30,6 +30,15 @@ impl Serialize for CompressionConfig {
     }
 }
 
+impl fmt::Display for CompressionConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompressionConfig::Lz4 => write!(f, "lz4"),
+            CompressionConfig::Zstd(level) => write!(f, "zstd-{}", level),
+        }
+    }
+}
+
 impl<'de> Deserialize<'de> for CompressionConfig {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -91,6 +100,9 @@ pub struct Settings {
     pub filesystem: Option<FilesystemConfig>,
     #[serde(skip_serializing_if = "Option::is_none", default)]
     pub lsm: Option<LsmConfig>,
+    /// Scheduled snapshots, by dataset name
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub snapshot_schedules: BTreeMap<String, SnapshotScheduleConfig>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub aws: Option<AwsConfig>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -203,8 +215,29 @@ fn default_dirty_time_threshold_secs() -> u64 {
 pub struct StorageConfig {
     #[serde(deserialize_with = "deserialize_expandable_string")]
     pub url: String,
-    #[serde(deserialize_with = "deserialize_expandable_string")]
-    pub encryption_password: String,
+    /// Exactly one of the `encryption_*` secret sources must be set
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_optional_expandable_string",
+        default
+    )]
+    pub encryption_password: Option<String>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_optional_expandable_path",
+        default
+    )]
+    pub encryption_password_file: Option<PathBuf>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_optional_expandable_path",
+        default
+    )]
+    pub encryption_key_file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub encryption_key_command: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub encryption_key_command_timeout_secs: Option<u64>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -232,7 +265,7 @@ impl FilesystemConfig {
     pub fn chunk_size(&self) -> usize {
         const DEFAULT_CHUNK_SIZE_KB: usize = 32; // Default to 32KB to match existing data
         const MIN_CHUNK_SIZE_KB: usize = 32;
-        const MAX_CHUNK_SIZE_KB: usize = 1024;
+        const MAX_CHUNK_SIZE_KB: usize = crate::fs::MAX_CHUNK_SIZE / 1024;
 
         let size_kb = self.chunk_size_kb.unwrap_or(DEFAULT_CHUNK_SIZE_KB);
 
@@ -313,6 +346,22 @@ impl LsmConfig {
     }
 }
 
+/// Snapshots taken of a dataset by the server: the number of most recent
+/// hours, days, weeks and months that keep one. A snapshot is taken once
+/// per the shortest of them that is set.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct SnapshotScheduleConfig {
+    #[serde(default)]
+    pub hourly: u32,
+    #[serde(default)]
+    pub daily: u32,
+    #[serde(default)]
+    pub weekly: u32,
+    #[serde(default)]
+    pub monthly: u32,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct ServerConfig {
@@ -467,6 +516,23 @@ where
     }
 }
 
+fn deserialize_optional_expandable_string<'de, D>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let opt = Option::<String>::deserialize(deserializer)?;
+    opt.map(|s| match shellexpand::env(&s) {
+        Ok(expanded) => Ok(expanded.into_owned()),
+        Err(e) => Err(serde::de::Error::custom(format!(
+            "Failed to expand environment variable: {}",
+            e
+        ))),
+    })
+    .transpose()
+}
+
 fn deserialize_expandable_path<'de, D>(deserializer: D) -> Result<PathBuf, D::Error>
 where
     D: Deserializer<'de>,
@@ -524,6 +590,13 @@ impl Settings {
             .unwrap_or(u64::MAX)
     }
 
+    pub fn chunk_size(&self) -> usize {
+        self.filesystem
+            .as_ref()
+            .map(|fs| fs.chunk_size())
+            .unwrap_or(crate::fs::CHUNK_SIZE)
+    }
+
     pub fn compression(&self) -> CompressionConfig {
         self.filesystem
             .as_ref()
@@ -588,7 +661,11 @@ impl Settings {
             },
             storage: StorageConfig {
                 url: "s3://your-bucket/zerofs-data".to_string(),
-                encryption_password: "${ZEROFS_PASSWORD}".to_string(),
+                encryption_password: Some("${ZEROFS_PASSWORD}".to_string()),
+                encryption_password_file: None,
+                encryption_key_file: None,
+                encryption_key_command: None,
+                encryption_key_command_timeout_secs: None,
             },
             servers: ServerConfig {
                 nfs: Some(NfsConfig {
@@ -619,6 +696,7 @@ impl Settings {
             },
             filesystem: None,
             lsm: None,
+            snapshot_schedules: BTreeMap::new(),
             aws: Some(AwsConfig(aws_config)),
             azure: None,
             gcp: None,
@@ -665,6 +743,16 @@ impl Settings {
         toml_string.push_str("# max_concurrent_compactions = 8   # Max concurrent compaction operations (default: 8, min: 1)\n");
         toml_string.push_str("# flush_interval_secs = 30         # Interval between periodic flushes in seconds (default: 30, min: 5)\n");
 
+        toml_string.push_str("\n# Optional scheduled snapshots, one table per dataset\n");
+        toml_string.push_str("# Snapshots are named <dataset>@auto-<UTC time> and taken once per the\n");
+        toml_string.push_str("# shortest period set; each count is how many of the most recent periods\n");
+        toml_string.push_str("# keep a snapshot. Older scheduled snapshots are destroyed.\n");
+        toml_string.push_str("\n# [snapshot_schedules.my-dataset]\n");
+        toml_string.push_str("# hourly = 24\n");
+        toml_string.push_str("# daily = 7\n");
+        toml_string.push_str("# weekly = 4\n");
+        toml_string.push_str("# monthly = 0\n");
+
         toml_string.push_str("\n# Optional Writeback Cache (NVMe-optimized write buffer)\n");
         toml_string.push_str("# Enables a fast write-back cache layer that coalesces writes and reduces\n");
         toml_string.push_str("# LSM tree write amplification. Best used with NVMe storage for maximum performance.\n");
@@ -737,6 +825,20 @@ impl Settings {
              # All referenced environment variables must be set, or the config will fail to load.\n\
              #\n\
              # ============================================================================\n\
+             # ENCRYPTION SECRET\n\
+             # ============================================================================\n\
+             # Set exactly one of these in [storage] to unlock the encryption key:\n\
+             #   encryption_password = \"${{ZEROFS_PASSWORD}}\"\n\
+             #   encryption_password_file = \"/run/secrets/zerofs-password\"\n\
+             #   encryption_key_file = \"/run/secrets/zerofs.key\"  # 32 random bytes\n\
+             #   encryption_key_command = [\"vault\", \"kv\", \"get\", \"-field=password\", \"secret/zerofs\"]\n\
+             #\n\
+             # A key file is not used as the key itself: its bytes, hex-encoded, are the\n\
+             # password of a key slot and go through Argon2id like any other password.\n\
+             # A key command is killed after encryption_key_command_timeout_secs\n\
+             # (default 30).\n\
+             #\n\
+             # ============================================================================\n\
              # SERVER CONFIGURATION\n\
              # ============================================================================\n\
              # - To disable a server, remove or comment out its entire section\n\
@@ -771,7 +873,9 @@ impl Settings {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::key_management::KeyProvider;
     use std::env;
+    use std::time::Duration;
     use tempfile::NamedTempFile;
 
     #[test]
@@ -798,7 +902,10 @@ encryption_password = "${ZEROFS_TEST_PASSWORD}"
 
         let settings = Settings::from_file(temp_file.path().to_str().unwrap()).unwrap();
         assert_eq!(settings.storage.url, "s3://my-bucket/data");
-        assert_eq!(settings.storage.encryption_password, "secret123");
+        assert_eq!(
+            settings.storage.encryption_password.as_deref(),
+            Some("secret123")
+        );
     }
 
     #[test]
@@ -982,4 +1089,71 @@ allow_http = "true"
         let settings = result.unwrap();
         assert_eq!(settings.aws.unwrap().0.get("allow_http").unwrap(), "true");
     }
+
+    #[test]
+    fn test_encryption_secret_sources() {
+        unsafe {
+            env::set_var("ZEROFS_TEST_SECRETS", "/run/secrets");
+        }
+
+        let config_content = r#"
+[cache]
+dir = "/tmp/cache"
+disk_size_gb = 1.0
+
+[storage]
+url = "s3://bucket/data"
+encryption_key_file = "${ZEROFS_TEST_SECRETS}/zerofs.key"
+
+[servers]
+"#;
+
+        let temp_file = NamedTempFile::new().unwrap();
+        std::fs::write(temp_file.path(), config_content).unwrap();
+        let settings = Settings::from_file(temp_file.path().to_str().unwrap()).unwrap();
+        assert_eq!(settings.storage.encryption_password, None);
+        assert_eq!(
+            KeyProvider::from_config(&settings.storage).unwrap(),
+            KeyProvider::KeyFile(PathBuf::from("/run/secrets/zerofs.key"))
+        );
+
+        let config_content = r#"
+[cache]
+dir = "/tmp/cache"
+disk_size_gb = 1.0
+
+[storage]
+url = "s3://bucket/data"
+encryption_password = "test"
+encryption_key_command = ["secret-tool", "lookup", "service", "zerofs"]
+
+[servers]
+"#;
+
+        std::fs::write(temp_file.path(), config_content).unwrap();
+        let mut settings = Settings::from_file(temp_file.path().to_str().unwrap()).unwrap();
+        assert!(KeyProvider::from_config(&settings.storage).is_err());
+
+        settings.storage.encryption_password = None;
+        assert_eq!(
+            KeyProvider::from_config(&settings.storage).unwrap(),
+            KeyProvider::Command {
+                argv: vec![
+                    "secret-tool".to_string(),
+                    "lookup".to_string(),
+                    "service".to_string(),
+                    "zerofs".to_string(),
+                ],
+                timeout: Duration::from_secs(30),
+            }
+        );
+        settings.storage.encryption_key_command_timeout_secs = Some(5);
+        assert!(matches!(
+            KeyProvider::from_config(&settings.storage).unwrap(),
+            KeyProvider::Command { timeout, .. } if timeout == Duration::from_secs(5)
+        ));
+
+        settings.storage.encryption_key_command = None;
+        assert!(KeyProvider::from_config(&settings.storage).is_err());
+    }
 }
@@ -1,31 +1,140 @@
 use crate::config::CompressionConfig;
-use crate::fs::CHUNK_SIZE;
+use crate::fs::MAX_CHUNK_SIZE;
+use crate::fs::dataset::DatasetId;
 use crate::fs::errors::FsError;
-use crate::fs::key_codec::KeyPrefix;
+use crate::fs::inode::InodeId;
+use crate::fs::key_codec::{ChunkHash, KeyCodec, KeyPrefix};
+use crate::fs::stats::{SpaceStats, SpaceUsage};
 use crate::task::spawn_blocking_named;
+use crate::writeback_cache::CachedWrite;
 use anyhow::Result;
 use arc_swap::ArcSwap;
 use bytes::Bytes;
 use chacha20poly1305::{
     Key, XChaCha20Poly1305, XNonce,
-    aead::{Aead, KeyInit},
+    aead::{Aead, KeyInit, Payload},
 };
 use hkdf::Hkdf;
 use rand::{RngCore, thread_rng};
-use sha2::Sha256;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use slatedb::{
     DbReader, WriteBatch,
     config::{DurabilityLevel, ReadOptions, ScanOptions, WriteOptions},
 };
+use std::collections::{BTreeMap, HashMap};
 use std::ops::RangeBounds;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
 use tokio_stream::Stream;
 
 type KeyCache = foyer_memory::Cache<Bytes, Bytes>;
 
 const NONCE_SIZE: usize = 24;
 
+/// Format versions of values bound to their storage key. The header starts
+/// with the version byte and is authenticated, together with the key, as
+/// associated data. Values written before it have no header:
+/// `[nonce][ciphertext]`.
+///
+/// - V1: `[1][nonce][ciphertext]`, encrypted with key generation 0
+/// - V2: `[2][key id][nonce][ciphertext]`
+/// - V3: `[3][dataset id][nonce][ciphertext]`, encrypted with the dataset's
+///   own key
+const FORMAT_BOUND_V1: u8 = 1;
+const FORMAT_KEY_ID_V2: u8 = 2;
+const FORMAT_DATASET_V3: u8 = 3;
+const KEY_ID_SIZE: usize = std::mem::size_of::<KeyId>();
+
+/// Format of dataset keys wrapped with the filesystem key:
+/// `[2][key id][nonce][ciphertext]`, wrapped with a key derived from that
+/// key generation. The header and the dataset id are authenticated. Keys
+/// wrapped before it are `[nonce][ciphertext]`, wrapped with a key derived
+/// from the content hash key.
+const WRAPPED_KEY_V2: u8 = 2;
+const LEGACY_WRAPPED_KEY_SIZE: usize = NONCE_SIZE + 32 + 16;
+const DATASET_ID_SIZE: usize = std::mem::size_of::<DatasetId>();
+
+/// Generation of a data encryption key, recorded in every value header
+pub type KeyId = u32;
+
+/// The data encryption keys of a filesystem: the generation new values are
+/// encrypted with, and the older generations values may still be stored
+/// with until a rotation has re-encrypted them.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct DataKeys {
+    pub current: KeyId,
+    pub keys: BTreeMap<KeyId, [u8; 32]>,
+    /// Chunks are stored under their content hash, so this key stays the
+    /// same across rotations.
+    pub content_hash_key: [u8; 32],
+}
+
+impl DataKeys {
+    /// Keys of a filesystem whose data key has never been rotated
+    pub fn new(dek: [u8; 32]) -> Self {
+        Self {
+            current: 0,
+            keys: BTreeMap::from([(0, dek)]),
+            content_hash_key: derive_content_hash_key(&dek),
+        }
+    }
+
+    /// Add a new random key generation and make it current
+    pub fn rotate(&mut self) -> KeyId {
+        let mut dek = [0u8; 32];
+        thread_rng().fill_bytes(&mut dek);
+
+        let id = self.keys.keys().next_back().map_or(0, |id| id + 1);
+        self.keys.insert(id, dek);
+        self.current = id;
+        id
+    }
+
+    /// Drop every generation but the current one
+    pub fn retire_old(&mut self) {
+        let current = self.current;
+        self.keys.retain(|&id, _| id == current);
+    }
+}
+
+/// Chunk hashes appear in storage keys, so they are keyed to avoid revealing
+/// which well-known blocks a filesystem contains.
+fn derive_content_hash_key(dek: &[u8; 32]) -> [u8; 32] {
+    let mut content_hash_key = [0u8; 32];
+    Hkdf::<Sha256>::new(None, dek)
+        .expand(b"zerofs-v1-content-hash", &mut content_hash_key)
+        .expect("valid length");
+    content_hash_key
+}
+
+/// Wraps the keys of datasets encrypted with a key of their own. Derived
+/// from each key generation, so rotating the filesystem key also replaces
+/// the one protecting dataset keys once they are rewrapped.
+fn derive_dataset_key_wrap(secret: &[u8; 32]) -> XChaCha20Poly1305 {
+    let mut wrap_key = [0u8; 32];
+    Hkdf::<Sha256>::new(None, secret)
+        .expand(b"zerofs-v1-dataset-key-wrap", &mut wrap_key)
+        .expect("valid length");
+    XChaCha20Poly1305::new(Key::from_slice(&wrap_key))
+}
+
+fn derive_cipher(dek: &[u8; 32]) -> XChaCha20Poly1305 {
+    let mut encryption_key = [0u8; 32];
+    Hkdf::<Sha256>::new(None, dek)
+        .expand(b"zerofs-v1-encryption", &mut encryption_key)
+        .expect("valid length");
+    XChaCha20Poly1305::new(Key::from_slice(&encryption_key))
+}
+
+/// Key of a dataset that encrypts its chunks with its own key. Chunks are
+/// deduplicated within the dataset only: their hashes are keyed with it too.
+struct DatasetCipher {
+    cipher: XChaCha20Poly1305,
+    content_hash_key: [u8; 32],
+}
+
 const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
 
 /// Fatal handler for SlateDB write errors.
@@ -38,70 +147,247 @@ pub fn exit_on_write_error(err: impl std::fmt::Display) -> ! {
 
 #[derive(Clone)]
 pub struct EncryptionManager {
-    cipher: XChaCha20Poly1305,
+    ciphers: BTreeMap<KeyId, XChaCha20Poly1305>,
+    current: KeyId,
+    content_hash_key: [u8; 32],
     compression: CompressionConfig,
+    /// Whether values written without associated data are still readable.
+    /// Cleared once every stored value has been re-encrypted.
+    accept_unbound: Arc<AtomicBool>,
+    /// Wrap the keys of datasets encrypted with a key of their own, by key
+    /// generation
+    dataset_key_wraps: BTreeMap<KeyId, XChaCha20Poly1305>,
+    /// Unwraps dataset keys wrapped before key generations were recorded
+    legacy_dataset_key_wrap: XChaCha20Poly1305,
+    /// Unwrapped dataset keys, by dataset
+    dataset_ciphers: Arc<RwLock<HashMap<DatasetId, Arc<DatasetCipher>>>>,
+    /// Compression of chunk bodies written in datasets that do not use
+    /// `compression`
+    dataset_compression: Arc<RwLock<HashMap<DatasetId, CompressionConfig>>>,
 }
 
 impl EncryptionManager {
     pub fn new(master_key: &[u8; 32], compression: CompressionConfig) -> Self {
-        let hk = Hkdf::<Sha256>::new(None, master_key);
+        Self::with_keys(&DataKeys::new(*master_key), compression)
+    }
 
-        let mut encryption_key = [0u8; 32];
+    pub fn with_keys(keys: &DataKeys, compression: CompressionConfig) -> Self {
+        let ciphers = keys
+            .keys
+            .iter()
+            .map(|(&id, dek)| (id, derive_cipher(dek)))
+            .collect();
 
-        hk.expand(b"zerofs-v1-encryption", &mut encryption_key)
-            .expect("valid length");
+        let dataset_key_wraps = keys
+            .keys
+            .iter()
+            .map(|(&id, dek)| (id, derive_dataset_key_wrap(dek)))
+            .collect();
 
         Self {
-            cipher: XChaCha20Poly1305::new(Key::from_slice(&encryption_key)),
+            ciphers,
+            current: keys.current,
+            content_hash_key: keys.content_hash_key,
             compression,
+            accept_unbound: Arc::new(AtomicBool::new(true)),
+            dataset_key_wraps,
+            legacy_dataset_key_wrap: derive_dataset_key_wrap(&keys.content_hash_key),
+            dataset_ciphers: Arc::new(RwLock::new(HashMap::new())),
+            dataset_compression: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
-    pub fn encrypt(&self, key: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
-        let mut nonce_bytes = [0u8; NONCE_SIZE];
-        thread_rng().fill_bytes(&mut nonce_bytes);
-        let nonce = XNonce::from_slice(&nonce_bytes);
+    /// Key generation new values are encrypted with
+    pub fn current_key_id(&self) -> KeyId {
+        self.current
+    }
 
-        // Check if this is a chunk key to decide on compression
-        let data =
-            if key.first().and_then(|&b| KeyPrefix::try_from(b).ok()) == Some(KeyPrefix::Chunk) {
-                match self.compression {
-                    CompressionConfig::Lz4 => lz4_flex::compress_prepend_size(plaintext),
-                    CompressionConfig::Zstd(level) => zstd::bulk::compress(plaintext, level)
-                        .map_err(|e| anyhow::anyhow!("Zstd compression failed: {}", e))?,
-                }
-            } else {
-                plaintext.to_vec()
-            };
+    /// Stop reading values that are not bound to their storage key
+    pub fn reject_unbound(&self) {
+        self.accept_unbound.store(false, Ordering::Relaxed);
+    }
 
-        let ciphertext = self
-            .cipher
-            .encrypt(nonce, data.as_ref())
-            .map_err(|e| anyhow::anyhow!("Encryption failed: {}", e))?;
+    /// Generate a key for `dataset` and make it available. Returns it wrapped
+    /// with the filesystem key, to be recorded on the dataset.
+    pub fn generate_dataset_key(&self, dataset: DatasetId) -> Result<Vec<u8>> {
+        let mut dek = [0u8; 32];
+        thread_rng().fill_bytes(&mut dek);
 
-        // Format: [nonce][ciphertext]
-        let mut result = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
-        result.extend_from_slice(&nonce_bytes);
-        result.extend_from_slice(&ciphertext);
-        Ok(result)
+        let wrapped = self.wrap_dataset_key(dataset, &dek)?;
+        self.install_dataset_key(dataset, &dek);
+        Ok(wrapped)
     }
 
-    pub fn decrypt(&self, key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
-        if data.len() < NONCE_SIZE {
-            return Err(anyhow::anyhow!("Invalid ciphertext: too short"));
+    /// Unwrap the key recorded on `dataset` and make it available
+    pub fn unlock_dataset_key(&self, dataset: DatasetId, wrapped: &[u8]) -> Result<()> {
+        let dek = self.unwrap_dataset_key(dataset, wrapped)?;
+        self.install_dataset_key(dataset, &dek);
+        Ok(())
+    }
+
+    /// The key recorded on `dataset` wrapped with the current key
+    /// generation, if it is wrapped with another one
+    pub fn rewrap_dataset_key(
+        &self,
+        dataset: DatasetId,
+        wrapped: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        if wrapped.len() != LEGACY_WRAPPED_KEY_SIZE && parse_key_id(wrapped) == Some(self.current) {
+            return Ok(None);
         }
+        let dek = self.unwrap_dataset_key(dataset, wrapped)?;
+        self.wrap_dataset_key(dataset, &dek).map(Some)
+    }
 
-        let (nonce_bytes, ciphertext) = data.split_at(NONCE_SIZE);
-        let nonce = XNonce::from_slice(nonce_bytes);
+    fn wrap_dataset_key(&self, dataset: DatasetId, dek: &[u8; 32]) -> Result<Vec<u8>> {
+        let mut header = Vec::with_capacity(1 + KEY_ID_SIZE);
+        header.push(WRAPPED_KEY_V2);
+        header.extend_from_slice(&self.current.to_be_bytes());
+
+        let cipher = self
+            .dataset_key_wraps
+            .get(&self.current)
+            .ok_or_else(|| anyhow::anyhow!("Missing current encryption key"))?;
+        seal_with(cipher, &header, &dataset.to_be_bytes(), dek)
+            .map_err(|e| anyhow::anyhow!("Failed to wrap key of dataset {}: {}", dataset, e))
+    }
 
-        let decrypted = self
-            .cipher
-            .decrypt(nonce, ciphertext)
-            .map_err(|e| anyhow::anyhow!("Decryption failed: {}", e))?;
+    fn unwrap_dataset_key(&self, dataset: DatasetId, wrapped: &[u8]) -> Result<[u8; 32]> {
+        let aad = dataset.to_be_bytes();
+        let dek = if wrapped.len() == LEGACY_WRAPPED_KEY_SIZE {
+            open_with(&self.legacy_dataset_key_wrap, 0, &aad, wrapped)
+        } else if wrapped.first() == Some(&WRAPPED_KEY_V2) {
+            let key_id = parse_key_id(wrapped)
+                .ok_or_else(|| anyhow::anyhow!("Invalid wrapped key for dataset {}", dataset))?;
+            let cipher = self.dataset_key_wraps.get(&key_id).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Key generation {} wrapping the key of dataset {} is not available",
+                    key_id,
+                    dataset
+                )
+            })?;
+            open_with(cipher, 1 + KEY_ID_SIZE, &aad, wrapped)
+        } else {
+            return Err(anyhow::anyhow!(
+                "Invalid wrapped key for dataset {}",
+                dataset
+            ));
+        };
+
+        dek.and_then(|dek| dek.try_into().ok())
+            .ok_or_else(|| anyhow::anyhow!("Failed to unwrap key of dataset {}", dataset))
+    }
+
+    /// Drop the key of `dataset`. Values encrypted with it can no longer be
+    /// read or written.
+    pub fn forget_dataset_key(&self, dataset: DatasetId) {
+        self.dataset_ciphers
+            .write()
+            .expect("dataset ciphers lock poisoned")
+            .remove(&dataset);
+    }
+
+    fn install_dataset_key(&self, dataset: DatasetId, dek: &[u8; 32]) {
+        let cipher = DatasetCipher {
+            cipher: derive_cipher(dek),
+            content_hash_key: derive_content_hash_key(dek),
+        };
+        self.dataset_ciphers
+            .write()
+            .expect("dataset ciphers lock poisoned")
+            .insert(dataset, Arc::new(cipher));
+    }
+
+    /// Compression chunk bodies are written with unless their dataset sets
+    /// its own
+    pub fn default_compression(&self) -> CompressionConfig {
+        self.compression
+    }
+
+    /// Replace the compression of the datasets that set their own
+    pub fn set_dataset_compression(&self, overrides: HashMap<DatasetId, CompressionConfig>) {
+        *self
+            .dataset_compression
+            .write()
+            .expect("dataset compression lock poisoned") = overrides;
+    }
+
+    fn compression_of(&self, dataset: DatasetId) -> CompressionConfig {
+        self.dataset_compression
+            .read()
+            .expect("dataset compression lock poisoned")
+            .get(&dataset)
+            .copied()
+            .unwrap_or(self.compression)
+    }
+
+    fn dataset_cipher(&self, dataset: DatasetId) -> Result<Arc<DatasetCipher>> {
+        self.dataset_ciphers
+            .read()
+            .expect("dataset ciphers lock poisoned")
+            .get(&dataset)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Key of dataset {} is not available", dataset))
+    }
 
-        if key.first().and_then(|&b| KeyPrefix::try_from(b).ok()) == Some(KeyPrefix::Chunk) {
+    /// Hash identifying a chunk body in the content-addressed chunk store
+    pub fn content_hash(&self, data: &[u8]) -> ChunkHash {
+        keyed_hash(&self.content_hash_key, data)
+    }
+
+    /// Content hash of a chunk written with the key of `dataset`, or with the
+    /// filesystem key for `None`
+    pub fn content_hash_in(&self, dataset: Option<DatasetId>, data: &[u8]) -> Result<ChunkHash> {
+        match dataset {
+            Some(dataset) => Ok(keyed_hash(
+                &self.dataset_cipher(dataset)?.content_hash_key,
+                data,
+            )),
+            None => Ok(self.content_hash(data)),
+        }
+    }
+
+    pub fn encrypt(&self, key: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        self.encrypt_in(None, key, plaintext)
+    }
+
+    /// Encrypt with the key of `dataset`, or with the filesystem key for `None`
+    pub fn encrypt_in(
+        &self,
+        dataset: Option<DatasetId>,
+        key: &[u8],
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>> {
+        let data = self.compress(self.compression, key, plaintext)?;
+        match dataset {
+            Some(dataset) => self.seal_in_dataset(dataset, key, &data),
+            None => self.seal(key, &data),
+        }
+    }
+
+    /// Like `encrypt_in`, compressing with the compression of dataset
+    /// `owner`, and also returning the size of the data once compressed
+    pub fn encrypt_measured(
+        &self,
+        dataset: Option<DatasetId>,
+        owner: DatasetId,
+        key: &[u8],
+        plaintext: &[u8],
+    ) -> Result<(Vec<u8>, usize)> {
+        let data = self.compress(self.compression_of(owner), key, plaintext)?;
+        let encrypted = match dataset {
+            Some(dataset) => self.seal_in_dataset(dataset, key, &data),
+            None => self.seal(key, &data),
+        }?;
+        Ok((encrypted, data.len()))
+    }
+
+    pub fn decrypt(&self, key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+        let decrypted = self.open(key, data)?;
+
+        if is_chunk_key(key) {
             if decrypted.len() >= 4 && decrypted[..4] == ZSTD_MAGIC {
-                zstd::bulk::decompress(&decrypted, CHUNK_SIZE)
+                zstd::bulk::decompress(&decrypted, MAX_CHUNK_SIZE)
                     .map_err(|e| anyhow::anyhow!("Zstd decompression failed: {}", e))
             } else {
                 lz4_flex::decompress_size_prepended(&decrypted)
@@ -111,6 +397,237 @@ impl EncryptionManager {
             Ok(decrypted)
         }
     }
+
+    /// Whether a stored value is bound to `key` in the current format and
+    /// encrypted with the current key generation. Values encrypted with a
+    /// dataset key are left as they are: dataset keys are not rotated, only
+    /// rewrapped with the current key generation
+    /// (`DatasetStore::rewrap_keys`).
+    pub fn is_current(&self, key: &[u8], data: &[u8]) -> bool {
+        match data.first() {
+            Some(&FORMAT_DATASET_V3) => true,
+            Some(&FORMAT_KEY_ID_V2) => {
+                parse_key_id(data) == Some(self.current) && self.open_bound(key, data).is_some()
+            }
+            _ => false,
+        }
+    }
+
+    /// Re-encrypt a stored value in the current format and key generation,
+    /// keeping its (possibly compressed) contents as they are.
+    pub fn reseal(&self, key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+        let decrypted = match self.open_bound(key, data) {
+            Some(decrypted) => decrypted,
+            None => self.open_unbound(data)?,
+        };
+        self.seal(key, &decrypted)
+    }
+
+    fn compress(
+        &self,
+        compression: CompressionConfig,
+        key: &[u8],
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>> {
+        // Check if this is a chunk key to decide on compression
+        if is_chunk_key(key) {
+            match compression {
+                CompressionConfig::Lz4 => Ok(lz4_flex::compress_prepend_size(plaintext)),
+                CompressionConfig::Zstd(level) => zstd::bulk::compress(plaintext, level)
+                    .map_err(|e| anyhow::anyhow!("Zstd compression failed: {}", e)),
+            }
+        } else {
+            Ok(plaintext.to_vec())
+        }
+    }
+
+    fn seal(&self, key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+        let mut header = Vec::with_capacity(1 + KEY_ID_SIZE);
+        header.push(FORMAT_KEY_ID_V2);
+        header.extend_from_slice(&self.current.to_be_bytes());
+
+        let cipher = self
+            .ciphers
+            .get(&self.current)
+            .ok_or_else(|| anyhow::anyhow!("Missing current encryption key"))?;
+        seal_with(cipher, &header, key, data)
+    }
+
+    fn seal_in_dataset(&self, dataset: DatasetId, key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+        let mut header = Vec::with_capacity(1 + DATASET_ID_SIZE);
+        header.push(FORMAT_DATASET_V3);
+        header.extend_from_slice(&dataset.to_be_bytes());
+
+        seal_with(&self.dataset_cipher(dataset)?.cipher, &header, key, data)
+    }
+
+    fn open(&self, key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+        if data.first() == Some(&FORMAT_DATASET_V3) {
+            let dataset = parse_dataset_id(data)
+                .ok_or_else(|| anyhow::anyhow!("Invalid ciphertext: too short"))?;
+            let cipher = self.dataset_cipher(dataset)?;
+            return open_with(&cipher.cipher, 1 + DATASET_ID_SIZE, key, data)
+                .ok_or_else(|| anyhow::anyhow!("Decryption failed for dataset {}", dataset));
+        }
+        if let Some(decrypted) = self.open_bound(key, data) {
+            return Ok(decrypted);
+        }
+        if !self.accept_unbound.load(Ordering::Relaxed) {
+            return Err(anyhow::anyhow!(
+                "Decryption failed: value is not bound to its key"
+            ));
+        }
+        self.open_unbound(data)
+    }
+
+    fn open_bound(&self, key: &[u8], data: &[u8]) -> Option<Vec<u8>> {
+        let (key_id, header_len) = match *data.first()? {
+            FORMAT_BOUND_V1 => (0, 1),
+            FORMAT_KEY_ID_V2 => (parse_key_id(data)?, 1 + KEY_ID_SIZE),
+            _ => return None,
+        };
+        open_with(self.ciphers.get(&key_id)?, header_len, key, data)
+    }
+
+    /// Values written before the key was bound: `[nonce][ciphertext]`,
+    /// encrypted with key generation 0
+    fn open_unbound(&self, data: &[u8]) -> Result<Vec<u8>> {
+        if data.len() < NONCE_SIZE {
+            return Err(anyhow::anyhow!("Invalid ciphertext: too short"));
+        }
+
+        let (nonce_bytes, ciphertext) = data.split_at(NONCE_SIZE);
+        let nonce = XNonce::from_slice(nonce_bytes);
+
+        self.ciphers
+            .get(&0)
+            .ok_or_else(|| anyhow::anyhow!("Decryption failed: key generation 0 was retired"))?
+            .decrypt(nonce, ciphertext)
+            .map_err(|e| anyhow::anyhow!("Decryption failed: {}", e))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn encrypt_unbound(&self, key: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        let data = self.compress(self.compression, key, plaintext)?;
+        let mut nonce_bytes = [0u8; NONCE_SIZE];
+        thread_rng().fill_bytes(&mut nonce_bytes);
+        let ciphertext = self.ciphers[&0]
+            .encrypt(XNonce::from_slice(&nonce_bytes), data.as_ref())
+            .map_err(|e| anyhow::anyhow!("Encryption failed: {}", e))?;
+
+        let mut result = nonce_bytes.to_vec();
+        result.extend_from_slice(&ciphertext);
+        Ok(result)
+    }
+}
+
+fn keyed_hash(hash_key: &[u8; 32], data: &[u8]) -> ChunkHash {
+    let mut hasher = Sha256::new();
+    hasher.update(hash_key);
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+/// Format: `[header][nonce][ciphertext]`
+fn seal_with(
+    cipher: &XChaCha20Poly1305,
+    header: &[u8],
+    key: &[u8],
+    data: &[u8],
+) -> Result<Vec<u8>> {
+    let mut nonce_bytes = [0u8; NONCE_SIZE];
+    thread_rng().fill_bytes(&mut nonce_bytes);
+
+    let aad = associated_data(header, key);
+    let ciphertext = cipher
+        .encrypt(
+            XNonce::from_slice(&nonce_bytes),
+            Payload {
+                msg: data,
+                aad: &aad,
+            },
+        )
+        .map_err(|e| anyhow::anyhow!("Encryption failed: {}", e))?;
+
+    let mut result = Vec::with_capacity(header.len() + NONCE_SIZE + ciphertext.len());
+    result.extend_from_slice(header);
+    result.extend_from_slice(&nonce_bytes);
+    result.extend_from_slice(&ciphertext);
+    Ok(result)
+}
+
+fn open_with(
+    cipher: &XChaCha20Poly1305,
+    header_len: usize,
+    key: &[u8],
+    data: &[u8],
+) -> Option<Vec<u8>> {
+    if data.len() < header_len + NONCE_SIZE {
+        return None;
+    }
+
+    let (header, rest) = data.split_at(header_len);
+    let (nonce_bytes, ciphertext) = rest.split_at(NONCE_SIZE);
+    let aad = associated_data(header, key);
+    cipher
+        .decrypt(
+            XNonce::from_slice(nonce_bytes),
+            Payload {
+                msg: ciphertext,
+                aad: &aad,
+            },
+        )
+        .ok()
+}
+
+/// The value header and storage key, authenticated with every value so it
+/// cannot be moved to another key.
+fn associated_data(header: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(header.len() + key.len());
+    aad.extend_from_slice(header);
+    aad.extend_from_slice(key);
+    aad
+}
+
+fn parse_key_id(data: &[u8]) -> Option<KeyId> {
+    let bytes = data.get(1..1 + KEY_ID_SIZE)?;
+    Some(KeyId::from_be_bytes(bytes.try_into().ok()?))
+}
+
+fn parse_dataset_id(data: &[u8]) -> Option<DatasetId> {
+    let bytes = data.get(1..1 + DATASET_ID_SIZE)?;
+    Some(DatasetId::from_be_bytes(bytes.try_into().ok()?))
+}
+
+/// Net change to one content-addressed chunk's references within a transaction.
+#[derive(Default)]
+struct ChunkRefChange {
+    delta: i64,
+    /// Chunk body, stored only if the chunk has no live references at commit time.
+    data: Option<Bytes>,
+    /// Dataset whose key encrypts the body, `None` for the filesystem key
+    dataset: Option<DatasetId>,
+    /// Dataset the space of the body is accounted to
+    owner: DatasetId,
+}
+
+/// Chunk reference change with its body already encrypted.
+pub struct PreparedChunkRef {
+    hash: ChunkHash,
+    delta: i64,
+    encrypted_data: Option<Vec<u8>>,
+    /// Dataset the body is accounted to and its sizes, if it is stored
+    footprint: Option<(DatasetId, SpaceUsage)>,
+}
+
+/// Net change to the references held on a shared chunk map (backing layer).
+#[derive(Default)]
+pub struct LayerRefChange {
+    delta: i64,
+    /// Size of the data in the layer, recorded when its first reference appears.
+    size: u64,
+    /// Chunk size of the layer's chunk map
+    chunk_size: u32,
 }
 
 pub struct EncryptedTransaction {
@@ -118,6 +635,10 @@ pub struct EncryptedTransaction {
     encryptor: Arc<EncryptionManager>,
     pending_operations: Vec<(Bytes, Bytes)>,
     deleted_keys: Vec<Bytes>,
+    chunk_refs: HashMap<ChunkHash, ChunkRefChange>,
+    collected_orphans: Vec<ChunkHash>,
+    layer_refs: HashMap<InodeId, LayerRefChange>,
+    cached_writes: Vec<CachedWrite>,
 }
 
 /// Result of preparing a transaction for commit, containing the write batch
@@ -126,6 +647,13 @@ pub struct PreparedTransaction {
     pub batch: WriteBatch,
     pub pending_operations: Vec<(Bytes, Bytes)>,
     pub deleted_keys: Vec<Bytes>,
+    /// Reference count changes, resolved against committed state when written
+    pub chunk_refs: Vec<PreparedChunkRef>,
+    pub collected_orphans: Vec<ChunkHash>,
+    pub layer_refs: Vec<(InodeId, LayerRefChange)>,
+    /// Space usage of the datasets whose chunk bodies were stored or
+    /// collected, applied once the batch is written
+    pub space_updates: Vec<(DatasetId, SpaceUsage)>,
 }
 
 impl EncryptedTransaction {
@@ -135,9 +663,59 @@ impl EncryptedTransaction {
             encryptor,
             pending_operations: Vec::new(),
             deleted_keys: Vec::new(),
+            chunk_refs: HashMap::new(),
+            collected_orphans: Vec::new(),
+            layer_refs: HashMap::new(),
+            cached_writes: Vec::new(),
+        }
+    }
+
+    /// Add a reference to a content-addressed chunk. `data` is the chunk body;
+    /// it is written only if no other reference to the chunk exists when the
+    /// transaction commits. Pass `None` when an existing reference is being
+    /// duplicated and the body is known to be stored. The body is encrypted
+    /// with the key of `dataset`, or with the filesystem key for `None`, and
+    /// its space is accounted to `owner`.
+    pub fn add_chunk_ref(
+        &mut self,
+        hash: ChunkHash,
+        data: Option<Bytes>,
+        dataset: Option<DatasetId>,
+        owner: DatasetId,
+    ) {
+        let change = self.chunk_refs.entry(hash).or_default();
+        change.delta += 1;
+        if data.is_some() {
+            change.data = data;
+            change.dataset = dataset;
+            change.owner = owner;
         }
     }
 
+    pub fn remove_chunk_ref(&mut self, hash: ChunkHash) {
+        self.chunk_refs.entry(hash).or_default().delta -= 1;
+    }
+
+    /// Delete an orphaned chunk body, unless it gained a new reference since it
+    /// was orphaned.
+    pub fn collect_orphan_chunk(&mut self, hash: ChunkHash) {
+        self.collected_orphans.push(hash);
+    }
+
+    /// Add a reference to a chunk map shared as a backing layer. `size` and
+    /// `chunk_size` describe the data it holds, used to reclaim it once the
+    /// last reference goes away.
+    pub fn add_layer_ref(&mut self, map_id: InodeId, size: u64, chunk_size: u32) {
+        let change = self.layer_refs.entry(map_id).or_default();
+        change.delta += 1;
+        change.size = change.size.max(size);
+        change.chunk_size = chunk_size;
+    }
+
+    pub fn remove_layer_ref(&mut self, map_id: InodeId) {
+        self.layer_refs.entry(map_id).or_default().delta -= 1;
+    }
+
     pub fn put_bytes(&mut self, key: &bytes::Bytes, value: Bytes) {
         self.pending_operations.push((key.clone(), value));
     }
@@ -147,11 +725,31 @@ impl EncryptedTransaction {
         self.inner.delete(key);
     }
 
+    /// Record a change to a chunk through the writeback cache. The filesystem
+    /// applies these when it commits the transaction, before the batch.
+    pub fn cache_write(&mut self, write: CachedWrite) {
+        self.cached_writes.push(write);
+    }
+
+    pub fn has_cached_writes(&self) -> bool {
+        !self.cached_writes.is_empty()
+    }
+
+    pub fn take_cached_writes(&mut self) -> Vec<CachedWrite> {
+        std::mem::take(&mut self.cached_writes)
+    }
+
     #[allow(clippy::type_complexity)]
     pub async fn into_inner(self) -> Result<PreparedTransaction> {
+        debug_assert!(
+            self.cached_writes.is_empty(),
+            "writeback cache changes must be applied before the batch"
+        );
         let mut inner = self.inner;
         let pending_operations = self.pending_operations;
         let deleted_keys = self.deleted_keys;
+        let collected_orphans = self.collected_orphans;
+        let layer_refs = self.layer_refs.into_iter().collect();
 
         let encrypted_pending = if !pending_operations.is_empty() {
             let ops = pending_operations.clone();
@@ -176,10 +774,61 @@ impl EncryptedTransaction {
             pending_operations
         };
 
+        let chunk_refs = if self.chunk_refs.values().any(|c| c.data.is_some()) {
+            let refs: Vec<_> = self.chunk_refs.into_iter().collect();
+            let encryptor = self.encryptor.clone();
+
+            spawn_blocking_named("encrypt-chunk-refs", move || {
+                refs.into_iter()
+                    .map(|(hash, change)| {
+                        let (encrypted_data, footprint) = match change.data {
+                            Some(data) => {
+                                let (encrypted, compressed) = encryptor.encrypt_measured(
+                                    change.dataset,
+                                    change.owner,
+                                    &KeyCodec::chunk_cas_key(&hash),
+                                    &data,
+                                )?;
+                                let usage = SpaceUsage {
+                                    logical: data.len() as u64,
+                                    compressed: compressed as u64,
+                                    stored: encrypted.len() as u64,
+                                };
+                                (Some(encrypted), Some((change.owner, usage)))
+                            }
+                            None => (None, None),
+                        };
+                        Ok::<PreparedChunkRef, anyhow::Error>(PreparedChunkRef {
+                            hash,
+                            delta: change.delta,
+                            encrypted_data,
+                            footprint,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Task join error: {}", e))??
+        } else {
+            self.chunk_refs
+                .into_iter()
+                .map(|(hash, change)| PreparedChunkRef {
+                    hash,
+                    delta: change.delta,
+                    encrypted_data: None,
+                    footprint: None,
+                })
+                .collect()
+        };
+
         Ok(PreparedTransaction {
             batch: inner,
             pending_operations: encrypted_pending,
             deleted_keys,
+            chunk_refs,
+            collected_orphans,
+            layer_refs,
+            space_updates: Vec::new(),
         })
     }
 }
@@ -215,10 +864,25 @@ pub struct EncryptedDb {
     encryptor: Arc<EncryptionManager>,
     /// Cache for decrypted non-chunk key-value pairs.
     key_cache: KeyCache,
+    /// Held shared by writes and exclusively by `reencrypt`, which must not
+    /// put back a value that was overwritten after it read it.
+    rewrite_lock: tokio::sync::RwLock<()>,
+    /// Space taken by the chunk bodies of each dataset
+    space_stats: Arc<SpaceStats>,
 }
 
+/// Chunk map entries and chunk bodies: compressed, and kept out of the key cache.
 fn is_chunk_key(key: &[u8]) -> bool {
-    key.first().and_then(|&b| KeyPrefix::try_from(b).ok()) == Some(KeyPrefix::Chunk)
+    matches!(
+        key.first().and_then(|&b| KeyPrefix::try_from(b).ok()),
+        Some(KeyPrefix::Chunk | KeyPrefix::ChunkCas)
+    )
+}
+
+/// Values stored as they are, outside the encrypted keyspace
+fn is_unencrypted_key(key: &[u8]) -> bool {
+    key == crate::fs::key_codec::SYSTEM_WRAPPED_ENCRYPTION_KEY
+        || key == crate::fs::key_codec::SYSTEM_KEY_SLOTS
 }
 
 fn build_key_cache() -> KeyCache {
@@ -233,6 +897,8 @@ impl EncryptedDb {
             inner: SlateDbHandle::ReadWrite(db),
             encryptor,
             key_cache: build_key_cache(),
+            rewrite_lock: tokio::sync::RwLock::new(()),
+            space_stats: Arc::new(SpaceStats::new()),
         }
     }
 
@@ -241,6 +907,8 @@ impl EncryptedDb {
             inner: SlateDbHandle::ReadOnly(db_reader),
             encryptor,
             key_cache: build_key_cache(),
+            rewrite_lock: tokio::sync::RwLock::new(()),
+            space_stats: Arc::new(SpaceStats::new()),
         }
     }
 
@@ -248,6 +916,64 @@ impl EncryptedDb {
         self.inner.is_read_only()
     }
 
+    pub fn space_stats(&self) -> &Arc<SpaceStats> {
+        &self.space_stats
+    }
+
+    pub fn content_hash_in(&self, dataset: Option<DatasetId>, data: &[u8]) -> Result<ChunkHash> {
+        self.encryptor.content_hash_in(dataset, data)
+    }
+
+    pub fn generate_dataset_key(&self, dataset: DatasetId) -> Result<Vec<u8>> {
+        self.encryptor.generate_dataset_key(dataset)
+    }
+
+    pub fn unlock_dataset_key(&self, dataset: DatasetId, wrapped: &[u8]) -> Result<()> {
+        self.encryptor.unlock_dataset_key(dataset, wrapped)
+    }
+
+    pub fn rewrap_dataset_key(
+        &self,
+        dataset: DatasetId,
+        wrapped: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        self.encryptor.rewrap_dataset_key(dataset, wrapped)
+    }
+
+    pub fn forget_dataset_key(&self, dataset: DatasetId) {
+        self.encryptor.forget_dataset_key(dataset)
+    }
+
+    pub fn default_compression(&self) -> CompressionConfig {
+        self.encryptor.default_compression()
+    }
+
+    pub fn set_dataset_compression(&self, overrides: HashMap<DatasetId, CompressionConfig>) {
+        self.encryptor.set_dataset_compression(overrides)
+    }
+
+    /// Stop reading values that are not bound to their storage key, once
+    /// none are left.
+    pub fn reject_unbound_values(&self) {
+        self.encryptor.reject_unbound();
+    }
+
+    /// Key generation new values are encrypted with
+    pub fn current_key_id(&self) -> KeyId {
+        self.encryptor.current_key_id()
+    }
+
+    /// The underlying database, for the unencrypted system entries such as
+    /// the key slots
+    pub fn handle(&self) -> &SlateDbHandle {
+        &self.inner
+    }
+
+    /// Whether a value returned by `scan_raw` has to be re-encrypted
+    pub fn needs_reencryption(&self, key: &[u8], stored: &[u8]) -> bool {
+        !is_unencrypted_key(key) && !self.encryptor.is_current(key, stored)
+    }
+
     pub fn swap_reader(&self, new_reader: Arc<DbReader>) -> Result<()> {
         match &self.inner {
             SlateDbHandle::ReadOnly(reader_swap) => {
@@ -331,8 +1057,7 @@ impl EncryptedDb {
 
         spawn_blocking_named("scan-decrypt", move || {
             while let Some((key, encrypted)) = rx_in.blocking_recv() {
-                let result = if key.as_ref() == crate::fs::key_codec::SYSTEM_WRAPPED_ENCRYPTION_KEY
-                {
+                let result = if is_unencrypted_key(&key) {
                     Ok((key, encrypted))
                 } else {
                     encryptor
@@ -359,6 +1084,99 @@ impl EncryptedDb {
         )))
     }
 
+    /// Scan stored values as they are, without decrypting them
+    pub async fn scan_raw<R: RangeBounds<Bytes> + Clone + Send + Sync + 'static>(
+        &self,
+        range: R,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<(Bytes, Bytes)>> + Send + '_>>> {
+        let scan_options = ScanOptions {
+            durability_filter: DurabilityLevel::Memory,
+            read_ahead_bytes: 1024 * 1024,
+            max_fetch_tasks: 8,
+            ..Default::default()
+        };
+        let iter = match &self.inner {
+            SlateDbHandle::ReadWrite(db) => db.scan_with_options(range, &scan_options).await?,
+            SlateDbHandle::ReadOnly(reader_swap) => {
+                let reader = reader_swap.load();
+                reader.scan_with_options(range, &scan_options).await?
+            }
+        };
+
+        Ok(Box::pin(futures::stream::unfold(
+            iter,
+            |mut iter| async move {
+                match iter.next().await {
+                    Ok(Some(kv)) => Some((Ok((kv.key, kv.value)), iter)),
+                    Ok(None) => None,
+                    Err(e) => Some((Err(e.into()), iter)),
+                }
+            },
+        )))
+    }
+
+    /// Re-encrypt the values stored under `keys` that are not bound to their
+    /// key in the current format and key generation. Returns the number of
+    /// values rewritten.
+    ///
+    /// Values are re-read and written back while other writes are held off,
+    /// so a value written concurrently is never replaced by an older one.
+    pub async fn reencrypt(&self, keys: Vec<Bytes>) -> Result<usize> {
+        let SlateDbHandle::ReadWrite(db) = &self.inner else {
+            return Err(FsError::ReadOnlyFilesystem.into());
+        };
+
+        let read_options = ReadOptions {
+            durability_filter: DurabilityLevel::Memory,
+            ..Default::default()
+        };
+
+        let _rewrite = self.rewrite_lock.write().await;
+
+        let mut stale = Vec::new();
+        for key in keys {
+            if let Some(stored) = db.get_with_options(&key, &read_options).await?
+                && self.needs_reencryption(&key, &stored)
+            {
+                stale.push((key, stored));
+            }
+        }
+        if stale.is_empty() {
+            return Ok(0);
+        }
+
+        let encryptor = self.encryptor.clone();
+        let resealed = spawn_blocking_named("reencrypt", move || {
+            stale
+                .into_iter()
+                .map(|(key, stored)| {
+                    let resealed = encryptor.reseal(&key, &stored)?;
+                    Ok::<(Bytes, Vec<u8>), anyhow::Error>((key, resealed))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .await
+        .map_err(|e| anyhow::anyhow!("Task join error: {}", e))??;
+
+        let mut batch = WriteBatch::new();
+        for (key, value) in &resealed {
+            batch.put(key, value);
+        }
+        if let Err(e) = db
+            .write_with_options(
+                batch,
+                &WriteOptions {
+                    await_durable: false,
+                },
+            )
+            .await
+        {
+            exit_on_write_error(e);
+        }
+
+        Ok(resealed.len())
+    }
+
     pub async fn write_with_options(
         &self,
         txn: EncryptedTransaction,
@@ -369,9 +1187,29 @@ impl EncryptedDb {
         }
 
         let prepared = txn.into_inner().await?;
+        self.write_prepared(prepared, options).await
+    }
+
+    /// Write a prepared transaction.
+    ///
+    /// Chunk reference counts are read and updated here, so callers that run
+    /// concurrently must order their writes (see `WriteCoordinator`) for the
+    /// counts to stay exact.
+    pub(crate) async fn write_prepared(
+        &self,
+        mut prepared: PreparedTransaction,
+        options: &WriteOptions,
+    ) -> Result<()> {
+        if self.is_read_only() {
+            return Err(FsError::ReadOnlyFilesystem.into());
+        }
+
+        self.resolve_chunk_refs(&mut prepared).await?;
+        self.resolve_layer_refs(&mut prepared).await?;
 
         match &self.inner {
             SlateDbHandle::ReadWrite(db) => {
+                let _rewrite = self.rewrite_lock.read().await;
                 if let Err(e) = db.write_with_options(prepared.batch, options).await {
                     exit_on_write_error(e);
                 }
@@ -389,35 +1227,172 @@ impl EncryptedDb {
             }
         }
 
+        for (dataset_id, usage) in prepared.space_updates {
+            self.space_stats.set(dataset_id, usage);
+        }
+
         Ok(())
     }
 
-    pub(crate) async fn write_raw_batch(
-        &self,
-        batch: WriteBatch,
-        pending_operations: Vec<(Bytes, Bytes)>,
-        deleted_keys: Vec<Bytes>,
-        options: &WriteOptions,
-    ) -> Result<()> {
-        if self.is_read_only() {
-            return Err(FsError::ReadOnlyFilesystem.into());
+    async fn chunk_refcount(&self, hash: &ChunkHash) -> Result<u64> {
+        match self.get_bytes(&KeyCodec::chunk_refcount_key(hash)).await? {
+            Some(data) => Ok(KeyCodec::decode_counter(&data)?),
+            None => Ok(0),
         }
-        match &self.inner {
-            SlateDbHandle::ReadWrite(db) => {
-                if let Err(e) = db.write_with_options(batch, options).await {
-                    exit_on_write_error(e);
+    }
+
+    /// Turn the reference changes of a transaction into writes against the
+    /// current reference counts.
+    ///
+    /// A chunk body is stored when its first reference appears and marked as
+    /// an orphan when its last reference goes away. Orphans are deleted by the
+    /// garbage collector, which rechecks the count in its own transaction.
+    /// The space of a body is accounted from when it is stored until it is
+    /// deleted.
+    async fn resolve_chunk_refs(&self, prepared: &mut PreparedTransaction) -> Result<()> {
+        let mut space: HashMap<DatasetId, SpaceUsage> = HashMap::new();
+
+        for chunk_ref in std::mem::take(&mut prepared.chunk_refs) {
+            if chunk_ref.delta == 0 {
+                continue;
+            }
+
+            let current = self.chunk_refcount(&chunk_ref.hash).await?;
+            let updated = current as i64 + chunk_ref.delta;
+            if updated < 0 {
+                tracing::error!(
+                    "Chunk reference count underflow (current={}, delta={})",
+                    current,
+                    chunk_ref.delta
+                );
+            }
+            let updated = updated.max(0) as u64;
+
+            if current == 0 && updated > 0 {
+                let orphan_key = KeyCodec::chunk_orphan_key(&chunk_ref.hash);
+                prepared.batch.delete(&orphan_key);
+                prepared.deleted_keys.push(orphan_key);
+
+                if let Some(encrypted) = &chunk_ref.encrypted_data {
+                    prepared
+                        .batch
+                        .put(KeyCodec::chunk_cas_key(&chunk_ref.hash), encrypted);
+
+                    // A body orphaned but not yet collected is still accounted
+                    let footprint_key = KeyCodec::chunk_footprint_key(&chunk_ref.hash);
+                    if let Some((dataset_id, usage)) = chunk_ref.footprint
+                        && self.get_bytes(&footprint_key).await?.is_none()
+                    {
+                        let value = KeyCodec::encode_chunk_footprint(dataset_id, &usage);
+                        let encrypted = self.encryptor.encrypt(&footprint_key, &value)?;
+                        prepared.batch.put(&footprint_key, &encrypted);
+                        prepared.pending_operations.push((footprint_key, value));
+                        space
+                            .entry(dataset_id)
+                            .or_insert_with(|| self.space_stats.get(dataset_id))
+                            .add(&usage);
+                    }
                 }
             }
-            SlateDbHandle::ReadOnly(_) => unreachable!("Already checked read-only above"),
+
+            let refcount_key = KeyCodec::chunk_refcount_key(&chunk_ref.hash);
+            if updated == 0 {
+                prepared.batch.delete(&refcount_key);
+                prepared.deleted_keys.push(refcount_key);
+
+                if current > 0 {
+                    let orphan_key = KeyCodec::chunk_orphan_key(&chunk_ref.hash);
+                    let encrypted = self.encryptor.encrypt(&orphan_key, &[])?;
+                    prepared.batch.put(&orphan_key, &encrypted);
+                    prepared.pending_operations.push((orphan_key, Bytes::new()));
+                }
+            } else if updated != current {
+                let value = KeyCodec::encode_counter(updated);
+                let encrypted = self.encryptor.encrypt(&refcount_key, &value)?;
+                prepared.batch.put(&refcount_key, &encrypted);
+                prepared.pending_operations.push((refcount_key, value));
+            }
         }
 
-        for key in deleted_keys {
-            self.key_cache.remove(&key);
+        for hash in std::mem::take(&mut prepared.collected_orphans) {
+            let orphan_key = KeyCodec::chunk_orphan_key(&hash);
+            prepared.batch.delete(&orphan_key);
+            prepared.deleted_keys.push(orphan_key);
+
+            if self.chunk_refcount(&hash).await? == 0 {
+                prepared.batch.delete(KeyCodec::chunk_cas_key(&hash));
+
+                let footprint_key = KeyCodec::chunk_footprint_key(&hash);
+                if let Some(data) = self.get_bytes(&footprint_key).await? {
+                    let (dataset_id, usage) = KeyCodec::decode_chunk_footprint(&data)?;
+                    space
+                        .entry(dataset_id)
+                        .or_insert_with(|| self.space_stats.get(dataset_id))
+                        .sub(&usage);
+                    prepared.batch.delete(&footprint_key);
+                    prepared.deleted_keys.push(footprint_key);
+                }
+            }
         }
 
-        for (key, value) in pending_operations {
-            if !is_chunk_key(&key) {
-                self.key_cache.insert(key, value);
+        for (dataset_id, usage) in space {
+            let key = KeyCodec::space_stats_key(dataset_id);
+            let value = KeyCodec::encode_space_usage(&usage);
+            let encrypted = self.encryptor.encrypt(&key, &value)?;
+            prepared.batch.put(&key, &encrypted);
+            prepared.pending_operations.push((key, value));
+            prepared.space_updates.push((dataset_id, usage));
+        }
+
+        Ok(())
+    }
+
+    /// Apply the backing layer reference changes of a transaction.
+    ///
+    /// A layer whose last reference goes away is handed to the garbage
+    /// collector as a tombstone covering its chunk map.
+    async fn resolve_layer_refs(&self, prepared: &mut PreparedTransaction) -> Result<()> {
+        for (map_id, change) in std::mem::take(&mut prepared.layer_refs) {
+            if change.delta == 0 {
+                continue;
+            }
+
+            let key = KeyCodec::chunk_layer_key(map_id);
+            let (current, size, chunk_size) = match self.get_bytes(&key).await? {
+                Some(data) => KeyCodec::decode_chunk_layer(&data)?,
+                None => (0, change.size, change.chunk_size),
+            };
+            let updated = current as i64 + change.delta;
+            if updated < 0 {
+                tracing::error!(
+                    "Chunk layer {} reference count underflow (current={}, delta={})",
+                    map_id,
+                    current,
+                    change.delta
+                );
+            }
+            let updated = updated.max(0) as u64;
+
+            if updated == 0 {
+                prepared.batch.delete(&key);
+                prepared.deleted_keys.push(key);
+
+                if current > 0 {
+                    let timestamp = std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map(|d| d.as_secs())
+                        .unwrap_or(0);
+                    let tombstone_key = KeyCodec::tombstone_key(timestamp, map_id);
+                    let value = KeyCodec::encode_tombstone(size, chunk_size);
+                    let encrypted = self.encryptor.encrypt(&tombstone_key, &value)?;
+                    prepared.batch.put(&tombstone_key, &encrypted);
+                    prepared.pending_operations.push((tombstone_key, value));
+                }
+            } else {
+                let value = KeyCodec::encode_chunk_layer(updated, size, chunk_size);
+                let encrypted = self.encryptor.encrypt(&key, &value)?;
+                prepared.batch.put(&key, &encrypted);
+                prepared.pending_operations.push((key, value));
             }
         }
 
@@ -458,6 +1433,7 @@ impl EncryptedDb {
 
         match &self.inner {
             SlateDbHandle::ReadWrite(db) => {
+                let _rewrite = self.rewrite_lock.read().await;
                 if let Err(e) = db
                     .put_with_options(key, &encrypted, put_options, write_options)
                     .await
@@ -476,6 +1452,19 @@ impl EncryptedDb {
         Ok(())
     }
 
+    /// Store a value the way it was written before values were bound to
+    /// their key
+    #[cfg(test)]
+    pub(crate) async fn put_unbound(&self, key: &Bytes, value: &[u8]) -> Result<()> {
+        let SlateDbHandle::ReadWrite(db) = &self.inner else {
+            return Err(FsError::ReadOnlyFilesystem.into());
+        };
+        let encrypted = self.encryptor.encrypt_unbound(key, value)?;
+        db.put(key, &encrypted).await?;
+        self.key_cache.remove(key);
+        Ok(())
+    }
+
     pub async fn flush(&self) -> Result<()> {
         if self.is_read_only() {
             return Err(FsError::ReadOnlyFilesystem.into());
@@ -601,6 +1590,176 @@ mod tests {
         assert_eq!(decrypted, plaintext);
     }
 
+    #[test]
+    fn test_content_hash_is_keyed() {
+        let manager = EncryptionManager::new(&[0u8; 32], CompressionConfig::Lz4);
+        let other = EncryptionManager::new(&[1u8; 32], CompressionConfig::Lz4);
+        let data = vec![9u8; 4096];
+
+        assert_eq!(manager.content_hash(&data), manager.content_hash(&data));
+        assert_ne!(
+            manager.content_hash(&data),
+            manager.content_hash(&[8u8; 4096])
+        );
+        assert_ne!(manager.content_hash(&data), other.content_hash(&data));
+    }
+
+    #[test]
+    fn test_cas_chunk_compress_decompress() {
+        let manager = EncryptionManager::new(&[0u8; 32], CompressionConfig::Zstd(3));
+        let plaintext = vec![5u8; 4096];
+        let key = KeyCodec::chunk_cas_key(&manager.content_hash(&plaintext));
+
+        let encrypted = manager.encrypt(&key, &plaintext).unwrap();
+        assert!(encrypted.len() < plaintext.len());
+        assert_eq!(manager.decrypt(&key, &encrypted).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn test_dataset_compression() {
+        let manager = EncryptionManager::new(&[0u8; 32], CompressionConfig::Lz4);
+        let plaintext = vec![6u8; 4096];
+        let key = KeyCodec::chunk_cas_key(&manager.content_hash(&plaintext));
+        manager.set_dataset_compression(HashMap::from([(3, CompressionConfig::Zstd(3))]));
+
+        let (encrypted, _) = manager.encrypt_measured(None, 3, &key, &plaintext).unwrap();
+        let compressed = manager.open(&key, &encrypted).unwrap();
+        assert!(compressed.starts_with(&ZSTD_MAGIC));
+        assert_eq!(manager.decrypt(&key, &encrypted).unwrap(), plaintext);
+
+        // Datasets that do not set their own use the filesystem compression
+        let (encrypted, _) = manager.encrypt_measured(None, 4, &key, &plaintext).unwrap();
+        let compressed = manager.open(&key, &encrypted).unwrap();
+        assert!(!compressed.starts_with(&ZSTD_MAGIC));
+        assert_eq!(manager.decrypt(&key, &encrypted).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn test_value_bound_to_key() {
+        let manager = EncryptionManager::new(&[0u8; 32], CompressionConfig::Lz4);
+        let key = non_chunk_key();
+        let other_key = KeyCodec::inode_key(2).to_vec();
+
+        let encrypted = manager.encrypt(&key, b"inode 1").unwrap();
+        assert!(manager.is_current(&key, &encrypted));
+        assert!(!manager.is_current(&other_key, &encrypted));
+        assert!(manager.decrypt(&other_key, &encrypted).is_err());
+
+        // The format version is authenticated too
+        let mut downgraded = encrypted.clone();
+        downgraded[0] = 0;
+        assert!(manager.decrypt(&key, &downgraded).is_err());
+    }
+
+    #[test]
+    fn test_unbound_values_readable_until_rejected() {
+        let manager = EncryptionManager::new(&[0u8; 32], CompressionConfig::Zstd(3));
+        let key = chunk_key();
+        let plaintext = vec![7u8; 4096];
+
+        let legacy = manager.encrypt_unbound(&key, &plaintext).unwrap();
+        assert!(!manager.is_current(&key, &legacy));
+        assert_eq!(manager.decrypt(&key, &legacy).unwrap(), plaintext);
+
+        let resealed = manager.reseal(&key, &legacy).unwrap();
+        assert!(manager.is_current(&key, &resealed));
+
+        manager.reject_unbound();
+        assert!(manager.decrypt(&key, &legacy).is_err());
+        assert_eq!(manager.decrypt(&key, &resealed).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let mut keys = DataKeys::new([3u8; 32]);
+        let old = EncryptionManager::with_keys(&keys, CompressionConfig::Lz4);
+        let key = non_chunk_key();
+        let encrypted = old.encrypt(&key, b"old generation").unwrap();
+
+        assert_eq!(keys.rotate(), 1);
+        let rotated = EncryptionManager::with_keys(&keys, CompressionConfig::Lz4);
+        assert_eq!(rotated.content_hash(b"data"), old.content_hash(b"data"));
+
+        // Both generations are readable until the old one is retired
+        assert!(!rotated.is_current(&key, &encrypted));
+        assert_eq!(
+            rotated.decrypt(&key, &encrypted).unwrap(),
+            b"old generation"
+        );
+        let resealed = rotated.reseal(&key, &encrypted).unwrap();
+        assert!(rotated.is_current(&key, &resealed));
+        assert!(old.decrypt(&key, &resealed).is_err());
+
+        keys.retire_old();
+        let retired = EncryptionManager::with_keys(&keys, CompressionConfig::Lz4);
+        assert!(retired.decrypt(&key, &encrypted).is_err());
+        assert_eq!(retired.decrypt(&key, &resealed).unwrap(), b"old generation");
+    }
+
+    #[test]
+    fn test_dataset_keys() {
+        let keys = DataKeys::new([4u8; 32]);
+        let manager = EncryptionManager::with_keys(&keys, CompressionConfig::Lz4);
+        let key = chunk_key();
+        let plaintext = vec![9u8; 4096];
+
+        let wrapped = manager.generate_dataset_key(7).unwrap();
+        let encrypted = manager.encrypt_in(Some(7), &key, &plaintext).unwrap();
+        assert!(manager.is_current(&key, &encrypted));
+        assert_eq!(manager.decrypt(&key, &encrypted).unwrap(), plaintext);
+        assert_ne!(
+            manager.content_hash_in(Some(7), &plaintext).unwrap(),
+            manager.content_hash(&plaintext)
+        );
+
+        // The wrapped key only unlocks the dataset it was generated for
+        let restarted = EncryptionManager::with_keys(&keys, CompressionConfig::Lz4);
+        assert!(restarted.decrypt(&key, &encrypted).is_err());
+        assert!(restarted.unlock_dataset_key(8, &wrapped).is_err());
+        restarted.unlock_dataset_key(7, &wrapped).unwrap();
+        assert_eq!(restarted.decrypt(&key, &encrypted).unwrap(), plaintext);
+
+        restarted.forget_dataset_key(7);
+        assert!(restarted.decrypt(&key, &encrypted).is_err());
+        assert!(restarted.encrypt_in(Some(7), &key, &plaintext).is_err());
+    }
+
+    #[test]
+    fn test_dataset_keys_follow_key_rotation() {
+        let mut keys = DataKeys::new([5u8; 32]);
+        let manager = EncryptionManager::with_keys(&keys, CompressionConfig::Lz4);
+        let key = chunk_key();
+        let plaintext = vec![3u8; 4096];
+
+        let wrapped = manager.generate_dataset_key(7).unwrap();
+        let encrypted = manager.encrypt_in(Some(7), &key, &plaintext).unwrap();
+        assert_eq!(manager.rewrap_dataset_key(7, &wrapped).unwrap(), None);
+
+        // Keys wrapped before key generations were recorded still unlock
+        let dek = [6u8; 32];
+        let legacy = seal_with(
+            &manager.legacy_dataset_key_wrap,
+            &[],
+            &8u64.to_be_bytes(),
+            &dek,
+        )
+        .unwrap();
+        assert_eq!(manager.unwrap_dataset_key(8, &legacy).unwrap(), dek);
+        assert!(manager.rewrap_dataset_key(8, &legacy).unwrap().is_some());
+
+        keys.rotate();
+        let rotated = EncryptionManager::with_keys(&keys, CompressionConfig::Lz4);
+        let rewrapped = rotated.rewrap_dataset_key(7, &wrapped).unwrap().unwrap();
+        assert_eq!(rotated.rewrap_dataset_key(7, &rewrapped).unwrap(), None);
+
+        // Only the rewrapped key unlocks once the old generation is retired
+        keys.retire_old();
+        let retired = EncryptionManager::with_keys(&keys, CompressionConfig::Lz4);
+        assert!(retired.unlock_dataset_key(7, &wrapped).is_err());
+        retired.unlock_dataset_key(7, &rewrapped).unwrap();
+        assert_eq!(retired.decrypt(&key, &encrypted).unwrap(), plaintext);
+    }
+
     #[test]
     fn test_zstd_magic_detection() {
         // Verify zstd compressed data starts with magic bytes
@@ -0,0 +1,401 @@
+//! POSIX.1e access control lists.
+//!
+//! Access and default ACLs are stored as the `system.posix_acl_access` and
+//! `system.posix_acl_default` extended attributes, in the format Linux uses
+//! for them, so clients can read and write them with getfacl and setfacl.
+//! An access ACL is only stored when it grants more than the mode bits can
+//! express; the owner, group class and other permissions are kept equal to
+//! the mode bits.
+
+use super::errors::FsError;
+use super::inode::{Inode, InodeAttrs};
+use super::permissions::Credentials;
+use super::store::xattr::Xattrs;
+
+pub const ACL_ACCESS_XATTR: &[u8] = b"system.posix_acl_access";
+pub const ACL_DEFAULT_XATTR: &[u8] = b"system.posix_acl_default";
+
+const ACL_XATTR_VERSION: u32 = 2;
+const ACL_UNDEFINED_ID: u32 = u32::MAX;
+
+pub const ACL_USER_OBJ: u16 = 0x01;
+pub const ACL_USER: u16 = 0x02;
+pub const ACL_GROUP_OBJ: u16 = 0x04;
+pub const ACL_GROUP: u16 = 0x08;
+pub const ACL_MASK: u16 = 0x10;
+pub const ACL_OTHER: u16 = 0x20;
+
+pub const ACL_READ: u16 = 0x04;
+pub const ACL_WRITE: u16 = 0x02;
+pub const ACL_EXECUTE: u16 = 0x01;
+
+/// Whether extended attribute `name` holds an ACL
+pub fn is_acl_xattr(name: &[u8]) -> bool {
+    name == ACL_ACCESS_XATTR || name == ACL_DEFAULT_XATTR
+}
+
+/// ACL stored as attribute `name` in `xattrs`
+pub fn stored_acl(xattrs: &Xattrs, name: &[u8]) -> Result<Option<Acl>, FsError> {
+    xattrs
+        .get(name)
+        .map(Vec::as_slice)
+        .map(Acl::decode)
+        .transpose()
+}
+
+/// Store `value` as ACL attribute `name` of `inode`. Setting the access ACL
+/// sets the mode bits from it; it is only kept if they cannot express it.
+pub fn set_acl(
+    inode: &mut Inode,
+    xattrs: &mut Xattrs,
+    creds: &Credentials,
+    name: &[u8],
+    value: &[u8],
+) -> Result<(), FsError> {
+    let acl = Acl::decode(value)?;
+
+    if name == ACL_DEFAULT_XATTR {
+        if !inode.is_directory() {
+            return Err(FsError::PermissionDenied);
+        }
+        if acl.is_empty() {
+            xattrs.remove(name);
+        } else {
+            xattrs.insert(name.to_vec(), acl.encode());
+        }
+        return Ok(());
+    }
+
+    if acl.is_empty() {
+        return Err(FsError::InvalidArgument);
+    }
+
+    let mut mode = (inode.mode() & !0o777) | acl.mode_bits();
+    // As with chmod, setgid is cleared for callers outside the owning group
+    if creds.uid != 0 && !creds.is_member_of_group(inode.gid()) {
+        mode &= !0o2000;
+    }
+    inode.set_mode(mode);
+
+    if acl.is_minimal() {
+        xattrs.remove(name);
+    } else {
+        xattrs.insert(name.to_vec(), acl.encode());
+    }
+    Ok(())
+}
+
+/// Entries sort by tag, then qualifier, which is the order they are checked in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AclEntry {
+    pub tag: u16,
+    /// uid or gid for `ACL_USER` and `ACL_GROUP` entries
+    pub id: u32,
+    pub perm: u16,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Acl {
+    entries: Vec<AclEntry>,
+}
+
+impl Acl {
+    /// Parse and validate an ACL in the Linux xattr format. A value without
+    /// entries is valid, and stands for removing a default ACL.
+    pub fn decode(data: &[u8]) -> Result<Self, FsError> {
+        if data.len() < 4 || data.len() % 8 != 4 {
+            return Err(FsError::InvalidArgument);
+        }
+        if u32::from_le_bytes(data[..4].try_into().unwrap()) != ACL_XATTR_VERSION {
+            return Err(FsError::NotSupported);
+        }
+
+        let mut entries: Vec<AclEntry> = data[4..]
+            .chunks_exact(8)
+            .map(|e| AclEntry {
+                tag: u16::from_le_bytes([e[0], e[1]]),
+                perm: u16::from_le_bytes([e[2], e[3]]),
+                id: u32::from_le_bytes([e[4], e[5], e[6], e[7]]),
+            })
+            .collect();
+
+        for entry in &mut entries {
+            if !matches!(entry.tag, ACL_USER | ACL_GROUP) {
+                entry.id = ACL_UNDEFINED_ID;
+            }
+        }
+        entries.sort();
+
+        let acl = Self { entries };
+        if !acl.is_empty() {
+            acl.validate()?;
+        }
+        Ok(acl)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(4 + self.entries.len() * 8);
+        data.extend_from_slice(&ACL_XATTR_VERSION.to_le_bytes());
+        for entry in &self.entries {
+            data.extend_from_slice(&entry.tag.to_le_bytes());
+            data.extend_from_slice(&entry.perm.to_le_bytes());
+            data.extend_from_slice(&entry.id.to_le_bytes());
+        }
+        data
+    }
+
+    fn validate(&self) -> Result<(), FsError> {
+        let count = |tag| self.entries.iter().filter(|e| e.tag == tag).count();
+
+        if count(ACL_USER_OBJ) != 1
+            || count(ACL_GROUP_OBJ) != 1
+            || count(ACL_OTHER) != 1
+            || count(ACL_MASK) > 1
+        {
+            return Err(FsError::InvalidArgument);
+        }
+        if count(ACL_USER) + count(ACL_GROUP) > 0 && count(ACL_MASK) == 0 {
+            return Err(FsError::InvalidArgument);
+        }
+
+        for entry in &self.entries {
+            let known_tag = matches!(
+                entry.tag,
+                ACL_USER_OBJ | ACL_USER | ACL_GROUP_OBJ | ACL_GROUP | ACL_MASK | ACL_OTHER
+            );
+            if !known_tag || entry.perm & !0o7 != 0 {
+                return Err(FsError::InvalidArgument);
+            }
+        }
+
+        if self
+            .entries
+            .windows(2)
+            .any(|pair| pair[0].tag == pair[1].tag && pair[0].id == pair[1].id)
+        {
+            return Err(FsError::InvalidArgument);
+        }
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether the ACL has no named user or group entries, and so says no
+    /// more than the mode bits
+    pub fn is_minimal(&self) -> bool {
+        !self
+            .entries
+            .iter()
+            .any(|e| matches!(e.tag, ACL_USER | ACL_GROUP))
+    }
+
+    /// Permission bits of the mode matching the ACL: the owner, the mask (or
+    /// the owning group when there is none) and others
+    pub fn mode_bits(&self) -> u32 {
+        let mut mode = 0;
+        for entry in &self.entries {
+            let perm = entry.perm as u32;
+            match entry.tag {
+                ACL_USER_OBJ => mode |= perm << 6,
+                ACL_GROUP_OBJ => mode |= perm << 3,
+                ACL_MASK => mode = (mode & !0o070) | (perm << 3),
+                ACL_OTHER => mode |= perm,
+                _ => {}
+            }
+        }
+        mode
+    }
+
+    /// Update the ACL for a chmod to `mode`
+    pub fn chmod(&mut self, mode: u32) {
+        let has_mask = self.entries.iter().any(|e| e.tag == ACL_MASK);
+        for entry in &mut self.entries {
+            let shift = match entry.tag {
+                ACL_USER_OBJ => 6,
+                ACL_GROUP_OBJ if !has_mask => 3,
+                ACL_MASK => 3,
+                ACL_OTHER => 0,
+                _ => continue,
+            };
+            entry.perm = ((mode >> shift) & 0o7) as u16;
+        }
+    }
+
+    /// Access ACL and mode of an inode created with `mode` in a directory
+    /// with this default ACL: the permissions of each are limited by the
+    /// other's.
+    pub fn inherit(&self, mode: u32) -> (Acl, u32) {
+        let mut acl = self.clone();
+        let mut mode = mode;
+        let has_mask = acl.entries.iter().any(|e| e.tag == ACL_MASK);
+        for entry in &mut acl.entries {
+            let shift = match entry.tag {
+                ACL_USER_OBJ => 6,
+                ACL_GROUP_OBJ if !has_mask => 3,
+                ACL_MASK => 3,
+                ACL_OTHER => 0,
+                _ => continue,
+            };
+            entry.perm &= ((mode >> shift) & 0o7) as u16;
+            mode &= !(0o7 << shift) | ((entry.perm as u32) << shift);
+        }
+        (acl, mode)
+    }
+
+    /// Whether the ACL of an inode owned by `uid` and `gid` grants all of
+    /// `want` to `creds`. Root is not treated specially here.
+    pub fn permits(&self, uid: u32, gid: u32, creds: &Credentials, want: u16) -> bool {
+        let mask = self
+            .entries
+            .iter()
+            .find(|e| e.tag == ACL_MASK)
+            .map_or(0o7, |e| e.perm);
+
+        let mut in_group = false;
+        for entry in &self.entries {
+            match entry.tag {
+                ACL_USER_OBJ if creds.uid == uid => return entry.perm & want == want,
+                ACL_USER if creds.uid == entry.id => return entry.perm & mask & want == want,
+                ACL_GROUP_OBJ | ACL_GROUP => {
+                    let group = if entry.tag == ACL_GROUP_OBJ {
+                        gid
+                    } else {
+                        entry.id
+                    };
+                    if creds.is_member_of_group(group) {
+                        if entry.perm & mask & want == want {
+                            return true;
+                        }
+                        in_group = true;
+                    }
+                }
+                ACL_OTHER => return !in_group && entry.perm & want == want,
+                _ => {}
+            }
+        }
+        false
+    }
+}
+
+/// Encode an ACL of `(tag, id, perm)` entries
+#[cfg(test)]
+pub fn acl_xattr(entries: &[(u16, u32, u16)]) -> Vec<u8> {
+    let mut data = ACL_XATTR_VERSION.to_le_bytes().to_vec();
+    for &(tag, id, perm) in entries {
+        data.extend_from_slice(&tag.to_le_bytes());
+        data.extend_from_slice(&perm.to_le_bytes());
+        data.extend_from_slice(&id.to_le_bytes());
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const U: u32 = ACL_UNDEFINED_ID;
+
+    fn creds(uid: u32, gid: u32) -> Credentials {
+        Credentials {
+            uid,
+            gid,
+            groups: [0; 16],
+            groups_count: 0,
+        }
+    }
+
+    fn team_acl() -> Acl {
+        Acl::decode(&acl_xattr(&[
+            (ACL_OTHER, U, 0),
+            (ACL_USER_OBJ, U, 7),
+            (ACL_USER, 1001, 6),
+            (ACL_GROUP_OBJ, U, 5),
+            (ACL_GROUP, 2000, 7),
+            (ACL_MASK, U, 6),
+        ]))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_acl_round_trip() {
+        let acl = team_acl();
+        assert_eq!(Acl::decode(&acl.encode()).unwrap(), acl);
+        assert!(!acl.is_minimal());
+        assert_eq!(acl.mode_bits(), 0o760);
+    }
+
+    #[test]
+    fn test_acl_validation() {
+        // No mask with named entries
+        assert_eq!(
+            Acl::decode(&acl_xattr(&[
+                (ACL_USER_OBJ, U, 7),
+                (ACL_USER, 1001, 6),
+                (ACL_GROUP_OBJ, U, 5),
+                (ACL_OTHER, U, 0),
+            ])),
+            Err(FsError::InvalidArgument)
+        );
+        // Duplicate named user
+        assert_eq!(
+            Acl::decode(&acl_xattr(&[
+                (ACL_USER_OBJ, U, 7),
+                (ACL_USER, 1001, 6),
+                (ACL_USER, 1001, 4),
+                (ACL_GROUP_OBJ, U, 5),
+                (ACL_MASK, U, 7),
+                (ACL_OTHER, U, 0),
+            ])),
+            Err(FsError::InvalidArgument)
+        );
+        // Missing other entry
+        assert_eq!(
+            Acl::decode(&acl_xattr(&[(ACL_USER_OBJ, U, 7), (ACL_GROUP_OBJ, U, 5)])),
+            Err(FsError::InvalidArgument)
+        );
+        assert_eq!(Acl::decode(&[2, 0, 0]), Err(FsError::InvalidArgument));
+        assert_eq!(Acl::decode(&[1, 0, 0, 0]), Err(FsError::NotSupported));
+        assert!(Acl::decode(&acl_xattr(&[])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_acl_permits() {
+        let acl = team_acl();
+        let (owner, group) = (1000, 100);
+
+        assert!(acl.permits(owner, group, &creds(1000, 1), ACL_READ | ACL_WRITE));
+        // Named user, limited by the mask
+        assert!(acl.permits(owner, group, &creds(1001, 1), ACL_WRITE));
+        assert!(!acl.permits(owner, group, &creds(1001, 1), ACL_EXECUTE));
+        // Owning group
+        assert!(acl.permits(owner, group, &creds(1002, 100), ACL_READ));
+        assert!(!acl.permits(owner, group, &creds(1002, 100), ACL_WRITE));
+        // Named group, through a supplementary group
+        let mut member = creds(1003, 1);
+        member.groups[0] = 2000;
+        member.groups_count = 1;
+        assert!(acl.permits(owner, group, &member, ACL_READ | ACL_WRITE));
+        assert!(!acl.permits(owner, group, &member, ACL_EXECUTE));
+        // Others
+        assert!(!acl.permits(owner, group, &creds(1004, 1), ACL_READ));
+    }
+
+    #[test]
+    fn test_acl_chmod_and_inherit() {
+        let mut acl = team_acl();
+        acl.chmod(0o750);
+        assert_eq!(acl.mode_bits(), 0o750);
+        // The mask changed, the owning group entry did not
+        assert!(!acl.permits(1000, 100, &creds(1001, 1), ACL_WRITE));
+        assert!(acl.permits(1000, 100, &creds(1002, 100), ACL_EXECUTE));
+
+        let (inherited, mode) = team_acl().inherit(0o100644);
+        assert_eq!(mode, 0o100640);
+        assert_eq!(inherited.mode_bits(), 0o640);
+        assert!(inherited.permits(1000, 100, &creds(1001, 1), ACL_READ));
+        assert!(!inherited.permits(1000, 100, &creds(1001, 1), ACL_WRITE));
+    }
+}
@@ -1,191 +1,714 @@
-use crate::encryption::EncryptedDb;
+use crate::encryption::EncryptedTransaction;
+use crate::fs::dataset::{DatasetId, ROOT_DATASET_ID};
+use crate::fs::dataset_ops;
+use crate::fs::destroy::DestroyProgress;
 use crate::fs::errors::FsError;
-use crate::fs::inode::{Inode, InodeId};
+use crate::fs::inode::{BackingLayer, FileInode, Inode, InodeAttrs, InodeId};
 use crate::fs::key_codec::KeyCodec;
-use crate::fs::store::directory::DirScanValue;
-use crate::fs::store::{ChunkStore, DirectoryStore, InodeStore};
-use bytes::Bytes;
+use crate::fs::quota;
+use crate::fs::stats::InodeAccount;
+use crate::fs::store::chunk::ChunkLayout;
+use crate::fs::{ROOT_INODE_ID, ZeroFS, get_current_time, validate_filename};
 use futures::{StreamExt, pin_mut};
-use std::sync::Arc;
-use tracing::{debug, info};
-
-/// Encode directory scan entry value: name + DirScanValue
-/// This matches the format expected by directory listing
-fn encode_dir_scan_value(name: &[u8], value: &DirScanValue) -> Bytes {
-    let value_bytes =
-        bincode::serialize(value).expect("DirScanValue serialization should not fail");
-    let mut buf = Vec::with_capacity(4 + name.len() + value_bytes.len());
-    buf.extend_from_slice(&(name.len() as u32).to_le_bytes());
-    buf.extend_from_slice(name);
-    buf.extend_from_slice(&value_bytes);
-    Bytes::from(buf)
+use tokio::sync::RwLockReadGuard;
+use tracing::{debug, warn};
+
+/// Backing layers a file reads through at most. Cloning a file with that
+/// many folds them into one.
+pub(crate) const MAX_BACKING_LAYERS: usize = 16;
+
+/// Share the data of file `source_id` with a new clone, in O(1).
+///
+/// The source's chunk map is frozen into a backing layer referenced by both
+/// files, and the source moves on to a fresh map; the clone starts with an
+/// empty map of its own. A chunk becomes private to one side only when that
+/// side writes to it. Only inode metadata is written, whatever the file size,
+/// except when the source already reads through `MAX_BACKING_LAYERS`
+/// layers: these are then folded into one, copying chunk entries, so that
+/// reads never go through more.
+///
+/// The updated source inode is saved to `txn`. The caller must hold the
+/// source's write lock until `txn` commits, and save the returned inode.
+pub async fn reflink_file(
+    fs: &ZeroFS,
+    txn: &mut EncryptedTransaction,
+    source_id: InodeId,
+) -> Result<FileInode, FsError> {
+    let mut source = match fs.inode_store.get(source_id).await? {
+        Inode::File(file) => file,
+        Inode::Directory(_) => return Err(FsError::IsDirectory),
+        _ => return Err(FsError::InvalidArgument),
+    };
+
+    let map_id = source.chunk_map_id(source_id);
+    let chunk_size = source.chunk_size as usize;
+    if source.size > 0
+        && fs
+            .chunk_store
+            .has_chunks(map_id, source.size, chunk_size)
+            .await?
+    {
+        if source.backing.len() < MAX_BACKING_LAYERS
+            || !flatten_backing(fs, txn, source_id, &mut source).await?
+        {
+            source.backing.insert(
+                0,
+                BackingLayer {
+                    map_id,
+                    visible_size: source.size,
+                },
+            );
+            txn.add_layer_ref(map_id, source.size, source.chunk_size);
+        }
+        source.chunk_map = Some(fs.inode_store.allocate());
+
+        let parent_name = source.parent.zip(source.name.clone());
+        let source_inode = Inode::File(source.clone());
+        fs.inode_store.save(txn, source_id, &source_inode)?;
+        if let Some((parent_id, name)) = parent_name {
+            fs.directory_store
+                .update_inode_in_entry(txn, parent_id, &name, source_id, &source_inode)
+                .await?;
+        }
+    }
+
+    let mut clone = source;
+    clone.chunk_map = None;
+    clone.backing.retain(|layer| layer.visible_size > 0);
+    for layer in &clone.backing {
+        txn.add_layer_ref(layer.map_id, layer.visible_size, clone.chunk_size);
+    }
+
+    Ok(clone)
 }
 
-/// Deep clone directory and all its contents recursively
-/// This creates new inodes for all files and subdirectories
-/// Data chunks are copied via copy_chunks_for_cow for true COW
-pub async fn clone_directory_deep(
-    db: Arc<EncryptedDb>,
-    inode_store: &InodeStore,
-    directory_store: &DirectoryStore,
-    chunk_store: &ChunkStore,
-    source_dir_id: InodeId,
-    dest_dir_id: InodeId,
-) -> Result<(), FsError> {
-    // Get all entries from source directory
-    let mut entries: Vec<(Vec<u8>, InodeId, u64)> = vec![];
-    let stream = directory_store.list_from(source_dir_id, 0).await?;
-    pin_mut!(stream);
+/// Fold the chunk map of file `source_id` and its backing layers into one
+/// new layer, which replaces them as the file's backing. The file's own map
+/// is emptied; the caller moves the file to a fresh one. Returns false,
+/// leaving the file as it was, if the chunks could not be copied yet.
+async fn flatten_backing(
+    fs: &ZeroFS,
+    txn: &mut EncryptedTransaction,
+    source_id: InodeId,
+    source: &mut FileInode,
+) -> Result<bool, FsError> {
+    let layout = ChunkLayout::for_file(source_id, source);
+    let flat_id = fs.inode_store.allocate();
+    if !fs
+        .chunk_store
+        .flatten(txn, &layout, source.size, flat_id)
+        .await?
+    {
+        return Ok(false);
+    }
 
-    while let Some(result) = stream.next().await {
-        let entry = match result {
-            Ok(e) => e,
-            Err(FsError::InvalidData) => {
-                debug!("Skipping corrupted entry in directory {}", source_dir_id);
-                continue;
+    fs.chunk_store
+        .delete_range(txn, layout.map_id, 0, source.chunk_count())
+        .await?;
+    for layer in &source.backing {
+        txn.remove_layer_ref(layer.map_id);
+    }
+    source.backing = vec![BackingLayer {
+        map_id: flat_id,
+        visible_size: source.size,
+    }];
+    txn.add_layer_ref(flat_id, source.size, source.chunk_size);
+    debug!("Flattened the backing layers of inode {}", source_id);
+    Ok(true)
+}
+
+/// Copy inode `source_id` as `name` in directory `parent` of dataset
+/// `dataset`, to be saved as `new_id`. Files share their data with the
+/// source and a non-empty directory becomes a lazy directory of
+/// `source_id`, so the cost does not depend on the size of the source.
+/// Extended attributes are copied.
+///
+/// The caller must hold the source's write lock until `txn` commits, then
+/// call `commit_clone`.
+pub(crate) async fn clone_inode(
+    fs: &ZeroFS,
+    txn: &mut EncryptedTransaction,
+    source_id: InodeId,
+    new_id: InodeId,
+    parent: InodeId,
+    name: &[u8],
+    dataset: DatasetId,
+) -> Result<Inode, FsError> {
+    let inode = match fs.inode_store.get(source_id).await? {
+        Inode::File(_) => {
+            let mut file = reflink_file(fs, txn, source_id).await?;
+            file.parent = Some(parent);
+            file.name = Some(name.to_vec());
+            file.nlink = 1;
+            file.dataset = dataset;
+            Inode::File(file)
+        }
+        Inode::Directory(mut dir) => {
+            dir.parent = parent;
+            dir.name = Some(name.to_vec());
+            if dir.entry_count > 0 {
+                fs.lazy_dirs.add(txn, new_id, source_id);
+            }
+            Inode::Directory(dir)
+        }
+        Inode::Symlink(mut link) => {
+            link.parent = Some(parent);
+            link.name = Some(name.to_vec());
+            link.nlink = 1;
+            Inode::Symlink(link)
+        }
+        mut inode => {
+            if let Inode::Fifo(special)
+            | Inode::Socket(special)
+            | Inode::CharDevice(special)
+            | Inode::BlockDevice(special) = &mut inode
+            {
+                special.parent = Some(parent);
+                special.name = Some(name.to_vec());
+                special.nlink = 1;
             }
+            inode
+        }
+    };
+
+    let xattrs = fs.xattr_store.get(source_id).await?;
+    if !xattrs.is_empty() {
+        fs.xattr_store.save(txn, new_id, &xattrs)?;
+    }
+
+    Ok(inode)
+}
+
+/// Apply the in-memory side of `clone_inode` once its transaction committed
+pub(crate) fn commit_clone(fs: &ZeroFS, source_id: InodeId, new_id: InodeId, new_inode: &Inode) {
+    if let Inode::Directory(dir) = new_inode
+        && dir.entry_count > 0
+    {
+        fs.lazy_dirs.commit_add(new_id, source_id);
+    }
+}
+
+/// Clone inode `source_id` as `name` in directory `dest_dir_id`, in one
+/// transaction. Files share their data with the source, and a directory
+/// shares its whole subtree: entries are copied lazily, on the first write
+/// to either side.
+pub async fn clone_entry(
+    fs: &ZeroFS,
+    source_id: InodeId,
+    dest_dir_id: InodeId,
+    name: &[u8],
+) -> Result<(InodeId, Inode), FsError> {
+    // No modification may be in flight in the tree being cloned
+    let _gate = fs.lazy_dirs.hold_exclusive().await;
+    let (new_id, new_inode, _) = clone_into(fs, source_id, dest_dir_id, name, false).await?;
+    Ok((new_id, new_inode))
+}
+
+/// Like `clone_entry`, but an existing entry `name` is replaced in the same
+/// transaction. The replaced inode loses its link; unless it has other
+/// links, it is detached with its subtree and left to the
+/// `DatasetReclaimer`, and file handles into it become stale. Dataset roots,
+/// and trees holding one, cannot be replaced.
+///
+/// Returns the replaced tree when one was detached.
+pub async fn replace_entry(
+    fs: &ZeroFS,
+    source_id: InodeId,
+    dest_dir_id: InodeId,
+    name: &[u8],
+) -> Result<(InodeId, Inode, Option<DestroyProgress>), FsError> {
+    let _gate = fs.lazy_dirs.hold_exclusive().await;
+    clone_into(fs, source_id, dest_dir_id, name, true).await
+}
+
+/// `clone_entry`, or `replace_entry` with `replace`, for a caller already
+/// holding `fs.lazy_dirs.hold_exclusive()`
+pub(crate) async fn clone_into(
+    fs: &ZeroFS,
+    source_id: InodeId,
+    dest_dir_id: InodeId,
+    name: &[u8],
+    replace: bool,
+) -> Result<(InodeId, Inode, Option<DestroyProgress>), FsError> {
+    validate_filename(name)?;
+    dataset_ops::dir_properties(fs, dest_dir_id)
+        .await?
+        .check_writable()?;
+    materialize_paths(fs, &[source_id, dest_dir_id]).await?;
+
+    let existing = if replace {
+        match fs
+            .directory_store
+            .get_entry_with_cookie(dest_dir_id, name)
+            .await
+        {
+            Ok(entry) => Some(entry),
+            Err(FsError::NotFound) => None,
             Err(e) => return Err(e),
-        };
-        entries.push((entry.name.clone(), entry.inode_id, entry.cookie));
+        }
+    } else {
+        None
+    };
+
+    // Hold the source so no write lands in the chunk map being frozen, and
+    // the destination directory while the entry is added.
+    let mut lock_ids = vec![source_id, dest_dir_id];
+    if let Some((old_id, _)) = existing {
+        lock_ids.push(old_id);
+    }
+    let _guard = fs.lock_manager.acquire_multiple_write(lock_ids).await;
+
+    let mut dest_dir = fs.inode_store.get(dest_dir_id).await?;
+    let Inode::Directory(dir) = &mut dest_dir else {
+        return Err(FsError::NotDirectory);
+    };
+    match existing {
+        Some((old_id, old_cookie)) => {
+            // Re-check inside lock to verify entry still points to same inode
+            let verified = fs
+                .directory_store
+                .get_entry_with_cookie(dest_dir_id, name)
+                .await?;
+            if verified != (old_id, old_cookie) {
+                return Err(FsError::NotFound);
+            }
+            if old_id == source_id || fs.is_ancestor_of(old_id, source_id).await? {
+                return Err(FsError::InvalidArgument);
+            }
+            if fs.dataset_store.get_by_root_inode(old_id).await.is_some()
+                || dataset_ops::has_dataset_below(fs, old_id).await?
+            {
+                return Err(FsError::InvalidArgument);
+            }
+        }
+        None => {
+            if fs.directory_store.exists(dest_dir_id, name).await? {
+                return Err(FsError::Exists);
+            }
+        }
+    }
+    if fs.is_ancestor_of(source_id, dest_dir_id).await? {
+        return Err(FsError::InvalidArgument);
+    }
+
+    // Entries of a cloned directory are accounted as they are copied
+    let dataset = dataset_ops::dataset_of(fs, dest_dir_id).await?;
+    let source = fs.inode_store.get(source_id).await?;
+    let source_size = match &source {
+        Inode::File(file) => file.allocated_bytes(),
+        _ => 0,
+    };
+    let account = InodeAccount::new(dataset, source.ownership());
+    quota::check_quota(fs, account, source_size, 1).await?;
+
+    let new_id = fs.inode_store.allocate();
+    let mut txn = fs.db.new_transaction()?;
+    let (now_sec, now_nsec) = get_current_time();
+
+    let mut new_inode =
+        clone_inode(fs, &mut txn, source_id, new_id, dest_dir_id, name, dataset).await?;
+
+    match &mut new_inode {
+        Inode::File(f) => {
+            f.ctime = now_sec;
+            f.ctime_nsec = now_nsec;
+            f.mtime = now_sec;
+            f.mtime_nsec = now_nsec;
+            f.atime = now_sec;
+            f.atime_nsec = now_nsec;
+        }
+        Inode::Directory(d) => {
+            d.ctime = now_sec;
+            d.ctime_nsec = now_nsec;
+            d.mtime = now_sec;
+            d.mtime_nsec = now_nsec;
+            d.atime = now_sec;
+            d.atime_nsec = now_nsec;
+        }
+        _ => {}
     }
 
-    info!(
-        "Deep cloning {} entries from directory {} to {}",
+    let mut replaced = None;
+    let cookie = match existing {
+        Some((old_id, old_cookie)) => {
+            let mut old_inode = fs.inode_store.get(old_id).await?;
+            if old_inode.parent().is_none() {
+                fs.directory_store
+                    .remove_link_dir(&mut txn, old_id, dest_dir_id, name);
+            }
+            dir.entry_count = dir.entry_count.saturating_sub(1);
+            if old_inode.is_directory() {
+                dir.nlink = dir.nlink.saturating_sub(1);
+            }
+
+            let other_links = match &mut old_inode {
+                Inode::File(file) => Some(&mut file.nlink),
+                Inode::Fifo(special)
+                | Inode::Socket(special)
+                | Inode::CharDevice(special)
+                | Inode::BlockDevice(special) => Some(&mut special.nlink),
+                _ => None,
+            }
+            .filter(|nlink| **nlink > 1);
+            if let Some(nlink) = other_links {
+                *nlink -= 1;
+                fs.inode_store.save(&mut txn, old_id, &old_inode)?;
+            } else {
+                let (root_account, totals) = match &old_inode {
+                    Inode::File(file) => (file.dataset, (file.allocated_bytes(), 1)),
+                    _ => (dataset, (0, 1)),
+                };
+                let dataset_name = fs
+                    .dataset_store
+                    .get_by_id(dataset)
+                    .await
+                    .map(|d| d.name)
+                    .unwrap_or_default();
+                let progress = DestroyProgress::new(
+                    dataset,
+                    dataset_name,
+                    old_id,
+                    dataset,
+                    root_account,
+                    totals,
+                );
+                txn.put_bytes(&progress.key(), progress.encode()?);
+                replaced = Some(progress);
+            }
+            old_cookie
+        }
+        None => {
+            fs.directory_store
+                .allocate_cookie(dest_dir_id, &mut txn)
+                .await?
+        }
+    };
+    fs.directory_store.add(
+        &mut txn,
+        dest_dir_id,
+        name,
+        new_id,
+        cookie,
+        Some(&new_inode),
+    );
+    fs.inode_store.save(&mut txn, new_id, &new_inode)?;
+
+    dir.entry_count += 1;
+    if new_inode.is_directory() {
+        dir.nlink = dir.nlink.saturating_add(1);
+    }
+    dir.mtime = now_sec;
+    dir.mtime_nsec = now_nsec;
+    dir.ctime = now_sec;
+    dir.ctime_nsec = now_nsec;
+    let parent_update_info = dir.name.clone().map(|n| (dir.parent, n));
+
+    fs.inode_store.save(&mut txn, dest_dir_id, &dest_dir)?;
+    if let Some((parent_id, dir_name)) = parent_update_info {
+        fs.directory_store
+            .update_inode_in_entry(&mut txn, parent_id, &dir_name, dest_dir_id, &dest_dir)
+            .await
+            .ok();
+    }
+
+    let mut stats_update = fs.global_stats.prepare_inode_create(new_id, account).await;
+    if let Inode::File(file) = &new_inode {
+        stats_update.add_bytes(file.allocated_bytes());
+    }
+    fs.global_stats
+        .add_to_transaction(&stats_update, &mut txn)?;
+
+    let mut seq_guard = fs.write_coordinator.allocate_sequence();
+    fs.commit_transaction(txn, &mut seq_guard).await?;
+    fs.global_stats.commit_update(&stats_update);
+    commit_clone(fs, source_id, new_id, &new_inode);
+    if let Some(progress) = &replaced {
+        fs.detached_trees.commit_add(progress.root_inode);
+    }
+
+    Ok((new_id, new_inode, replaced))
+}
+
+/// Clone directory `source_id` as the root of a snapshot, in one
+/// transaction. The copy is not linked anywhere in the namespace; it reads
+/// through the source until either side is modified, like any clone. Its
+/// contents count towards the root dataset.
+pub async fn snapshot_dir(fs: &ZeroFS, source_id: InodeId) -> Result<InodeId, FsError> {
+    let roots = snapshot_dirs(fs, &[source_id]).await?;
+    Ok(roots[0])
+}
+
+/// Clone directories `source_ids` as snapshot roots like `snapshot_dir`,
+/// all at the same point in time: no modification commits until every
+/// clone has.
+pub async fn snapshot_dirs(fs: &ZeroFS, source_ids: &[InodeId]) -> Result<Vec<InodeId>, FsError> {
+    let _gate = fs.lazy_dirs.hold_exclusive().await;
+
+    let mut roots = Vec::with_capacity(source_ids.len());
+    for &source_id in source_ids {
+        // Earlier clones can have made this source a lazy dependency
+        materialize_paths(fs, &[source_id]).await?;
+        roots.push(clone_snapshot_root(fs, source_id).await?);
+    }
+    Ok(roots)
+}
+
+/// Clone one snapshot root; the caller holds the clone gate
+async fn clone_snapshot_root(fs: &ZeroFS, source_id: InodeId) -> Result<InodeId, FsError> {
+    let _guard = fs.lock_manager.acquire_write(source_id).await;
+    let source = fs.inode_store.get(source_id).await?;
+    if !source.is_directory() {
+        return Err(FsError::NotDirectory);
+    }
+
+    let new_id = fs.inode_store.allocate();
+    let mut txn = fs.db.new_transaction()?;
+    let mut new_inode = clone_inode(
+        fs,
+        &mut txn,
+        source_id,
+        new_id,
+        ROOT_INODE_ID,
+        b"",
+        ROOT_DATASET_ID,
+    )
+    .await?;
+    if let Inode::Directory(dir) = &mut new_inode {
+        dir.name = None;
+    }
+    fs.inode_store.save(&mut txn, new_id, &new_inode)?;
+
+    let account = InodeAccount::new(ROOT_DATASET_ID, new_inode.ownership());
+    let stats_update = fs.global_stats.prepare_inode_create(new_id, account).await;
+    fs.global_stats
+        .add_to_transaction(&stats_update, &mut txn)?;
+
+    let mut seq_guard = fs.write_coordinator.allocate_sequence();
+    fs.commit_transaction(txn, &mut seq_guard).await?;
+    fs.global_stats.commit_update(&stats_update);
+    commit_clone(fs, source_id, new_id, &new_inode);
+
+    Ok(new_id)
+}
+
+/// Copy the entries of lazy directory `dir_id` from its origin. Files share
+/// their data with the origin's files and subdirectories become lazy
+/// directories themselves, so only one level of the tree is copied. Does
+/// nothing if `dir_id` is not lazy.
+///
+/// Each entry is committed on its own; entries already present are left as
+/// they are, so an interrupted run is completed by the next one.
+pub async fn materialize_dir(fs: &ZeroFS, dir_id: InodeId) -> Result<(), FsError> {
+    if fs.lazy_dirs.origin(dir_id).is_none() {
+        return Ok(());
+    }
+
+    let _guard = fs.lock_manager.acquire_write(dir_id).await;
+    let Some(origin) = fs.lazy_dirs.origin(dir_id) else {
+        return Ok(());
+    };
+
+    // The origin can be a clone that has not been used yet
+    Box::pin(materialize_dir(fs, origin)).await?;
+
+    let dataset = dataset_ops::dataset_of(fs, dir_id).await?;
+
+    let mut entries = Vec::new();
+    let stream = fs.directory_store.list(origin).await?;
+    pin_mut!(stream);
+    while let Some(entry) = stream.next().await {
+        entries.push(entry?);
+    }
+
+    debug!(
+        "Materializing {} entries of directory {} from {}",
         entries.len(),
-        source_dir_id,
-        dest_dir_id
+        dir_id,
+        origin
     );
 
-    let mut cloned_count = 0;
-    let mut skipped_count = 0;
-    
-    for (name, source_inode_id, _cookie) in entries {
-        let name_str = String::from_utf8_lossy(&name);
-        
-        // Skip . and .. entries
-        if name_str == "." || name_str == ".." {
-            skipped_count += 1;
+    for entry in entries {
+        if fs.directory_store.exists(dir_id, &entry.name).await? {
             continue;
         }
-        
-        debug!("Cloning entry '{}' (inode {})", name_str, source_inode_id);
-        
-        // Get the source inode
-        let source_inode = match inode_store.get(source_inode_id).await {
-            Ok(inode) => inode,
-            Err(e) => {
-                debug!("Failed to get inode {} for entry '{}': {}. Skipping.", source_inode_id, name_str, e);
-                continue;
-            }
-        };
-        
-        // Allocate new inode ID for the clone
-        let new_inode_id = inode_store.allocate();
-        
-        // Clone the inode
-        let cloned_inode = source_inode.clone();
-        let is_directory = matches!(cloned_inode, Inode::Directory(_));
-        let file_size = if let Inode::File(ref file) = cloned_inode {
-            file.size
-        } else {
-            0
-        };
-        
-        // Save the cloned inode
-        let inode_key = KeyCodec::inode_key(new_inode_id);
-        let inode_bytes = bincode::serialize(&cloned_inode).map_err(|_| FsError::IoError)?;
-        db.put_with_options(
-            &inode_key,
-            &inode_bytes,
-            &slatedb::config::PutOptions::default(),
-            &slatedb::config::WriteOptions {
-                await_durable: false, // Batch writes for performance, flush at end
-            },
+
+        let _source_guard = fs.lock_manager.acquire_write(entry.inode_id).await;
+        // The copy takes the ID the entry was looked up as
+        let new_id = fs
+            .lazy_dirs
+            .alias(dir_id, &entry.name, dataset, || fs.inode_store.allocate())
+            .unwrap_or_else(|| fs.inode_store.allocate());
+        let mut txn = fs.db.new_transaction()?;
+
+        let new_inode = match clone_inode(
+            fs,
+            &mut txn,
+            entry.inode_id,
+            new_id,
+            dir_id,
+            &entry.name,
+            dataset,
         )
         .await
-        .map_err(|_| FsError::IoError)?;
-        
-        // For files, copy chunk metadata (COW)
-        if !is_directory && file_size > 0 {
-            chunk_store.copy_chunks_for_cow(source_inode_id, new_inode_id, file_size).await?;
-        }
-        
-        // Get next cookie for directory entry
-        let cookie_key = KeyCodec::dir_cookie_counter_key(dest_dir_id);
-        let cookie: u64 = match db.get_bytes(&cookie_key).await {
-            Ok(Some(val)) => {
-                let bytes: [u8; 8] = val.as_ref().try_into().map_err(|_| FsError::IoError)?;
-                u64::from_be_bytes(bytes)
+        {
+            Ok(inode) => inode,
+            Err(FsError::NotFound) => {
+                warn!(
+                    "Skipping entry '{}' of directory {}: inode {} not found",
+                    String::from_utf8_lossy(&entry.name),
+                    origin,
+                    entry.inode_id
+                );
+                continue;
             }
-            _ => crate::fs::store::directory::COOKIE_FIRST_ENTRY,
-        };
-        
-        let new_cookie = cookie + 1;
-        db.put_with_options(
-            &cookie_key,
-            &new_cookie.to_be_bytes(),
-            &slatedb::config::PutOptions::default(),
-            &slatedb::config::WriteOptions {
-                await_durable: false, // Batch writes for performance, flush at end
-            },
-        )
-        .await
-        .map_err(|_| FsError::IoError)?;
-        
-        // Create directory entry in destination
-        let entry_key = KeyCodec::dir_entry_key(dest_dir_id, &name);
-        let entry_value = KeyCodec::encode_dir_entry(new_inode_id, cookie);
-        db.put_with_options(
-            &entry_key,
-            &entry_value,
-            &slatedb::config::PutOptions::default(),
-            &slatedb::config::WriteOptions {
-                await_durable: false, // Batch writes for performance, flush at end
-            },
-        )
-        .await
-        .map_err(|_| FsError::IoError)?;
-        
-        // Create dir_scan entry with proper encoding (name + DirScanValue)
-        let scan_key = KeyCodec::dir_scan_key(dest_dir_id, cookie);
-        let scan_value = DirScanValue::Reference {
-            inode_id: new_inode_id,
+            Err(e) => return Err(e),
         };
-        let scan_value_bytes = encode_dir_scan_value(&name, &scan_value);
-        db.put_with_options(
-            &scan_key,
-            &scan_value_bytes,
-            &slatedb::config::PutOptions::default(),
-            &slatedb::config::WriteOptions {
-                await_durable: false, // Batch writes for performance, flush at end
-            },
-        )
+
+        // Keep the origin's cookies so resumed listings stay stable
+        fs.directory_store.add(
+            &mut txn,
+            dir_id,
+            &entry.name,
+            new_id,
+            entry.cookie,
+            Some(&new_inode),
+        );
+        fs.inode_store.save(&mut txn, new_id, &new_inode)?;
+
+        let account = InodeAccount::new(dataset, new_inode.ownership());
+        let mut stats_update = fs.global_stats.prepare_inode_create(new_id, account).await;
+        if let Inode::File(file) = &new_inode {
+            stats_update.add_bytes(file.allocated_bytes());
+        }
+        fs.global_stats
+            .add_to_transaction(&stats_update, &mut txn)?;
+
+        let mut seq_guard = fs.write_coordinator.allocate_sequence();
+        fs.commit_transaction(txn, &mut seq_guard).await?;
+        fs.global_stats.commit_update(&stats_update);
+        fs.lazy_dirs.commit_copy(new_id);
+        commit_clone(fs, entry.inode_id, new_id, &new_inode);
+    }
+
+    let mut txn = fs.db.new_transaction()?;
+    let origin_counter = fs
+        .db
+        .get_bytes(&KeyCodec::dir_cookie_counter_key(origin))
         .await
         .map_err(|_| FsError::IoError)?;
-        
-        // If it's a directory, recursively clone its contents
-        if is_directory {
-            Box::pin(clone_directory_deep(
-                db.clone(),
-                inode_store,
-                directory_store,
-                chunk_store,
-                source_inode_id,
-                new_inode_id,
-            ))
-            .await?;
-        }
-        
-        cloned_count += 1;
-    }
-
-    info!(
-        "Completed deep cloning: {} entries cloned, {} skipped from directory {} to {}",
-        cloned_count,
-        skipped_count,
-        source_dir_id,
-        dest_dir_id
-    );
+    if let Some(counter) = origin_counter {
+        txn.put_bytes(&KeyCodec::dir_cookie_counter_key(dir_id), counter);
+    }
+    fs.lazy_dirs.remove(&mut txn, dir_id);
+
+    let mut seq_guard = fs.write_coordinator.allocate_sequence();
+    fs.commit_transaction(txn, &mut seq_guard).await?;
+    fs.lazy_dirs.commit_remove(dir_id);
+    fs.directory_store.invalidate_directory(dir_id);
 
     Ok(())
 }
+
+/// Materialize every lazy directory that still reads through one of `ids`,
+/// so they can be modified: `ids` themselves when they are lazy, and clones
+/// of them or of any of their ancestors. An alias is copied, with the lazy
+/// directories it was looked up through.
+///
+/// Ancestors are found through parent pointers. An inode with several hard
+/// links has none, and is walked up from each directory holding one of its
+/// links instead.
+pub async fn materialize_paths(fs: &ZeroFS, ids: &[InodeId]) -> Result<(), FsError> {
+    if fs.lazy_dirs.is_empty() {
+        return Ok(());
+    }
+
+    for &id in ids {
+        // Each pass copies one level, and may leave new lazy directories
+        // one level down the path
+        while let Some(dir_id) = next_lazy_dir(fs, id).await? {
+            materialize_dir(fs, dir_id).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The lazy directory closest to the root that reads through `id`
+async fn next_lazy_dir(fs: &ZeroFS, id: InodeId) -> Result<Option<InodeId>, FsError> {
+    let inode = match fs.inode_store.get(id).await {
+        Ok(inode) => Some(inode),
+        Err(FsError::NotFound) => None,
+        Err(e) => return Err(e),
+    };
+    let link_dirs = match inode.map(|inode| inode.parent()) {
+        Some(None) => {
+            let dirs = fs.directory_store.link_dirs(id).await?;
+            // Linked before the directories of links were recorded: any
+            // lazy directory can read through it
+            if dirs.is_empty() {
+                return Ok(fs.lazy_dirs.any());
+            }
+            dirs
+        }
+        _ => Vec::new(),
+    };
+
+    for start in link_dirs.into_iter().chain([id]) {
+        let mut path = vec![start];
+        let mut current = start;
+        while current != ROOT_INODE_ID {
+            let parent = match fs.inode_store.get(current).await {
+                Ok(inode) => inode.parent(),
+                Err(FsError::NotFound) => None,
+                Err(e) => return Err(e),
+            };
+            let Some(parent) = parent else {
+                break;
+            };
+            path.push(parent);
+            current = parent;
+        }
+
+        for &ancestor in path.iter().rev() {
+            if let Some(&dir_id) = fs.lazy_dirs.dependents(ancestor).first() {
+                return Ok(Some(dir_id));
+            }
+            // Lazy itself, or the directory an alias was looked up in
+            if fs.lazy_dirs.origin(ancestor).is_some() {
+                return Ok(Some(ancestor));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// ID to hand out for entry `name` of directory `dir_id` while the directory
+/// reads through its origin: see `LazyDirStore::alias`
+pub(crate) async fn entry_alias(
+    fs: &ZeroFS,
+    dir_id: InodeId,
+    dataset: DatasetId,
+    name: &[u8],
+) -> Result<InodeId, FsError> {
+    match fs
+        .lazy_dirs
+        .alias(dir_id, name, dataset, || fs.inode_store.allocate())
+    {
+        Some(id) => Ok(id),
+        // Materialized in the meantime
+        None => fs.directory_store.get(dir_id, name).await,
+    }
+}
+
+/// Call before modifying any of `ids`, and keep the returned guard until
+/// the modification has committed: see `materialize_paths`. The guard keeps
+/// directories from being cloned in the meantime.
+pub async fn prepare_write<'a>(
+    fs: &'a ZeroFS,
+    ids: &[InodeId],
+) -> Result<RwLockReadGuard<'a, ()>, FsError> {
+    let gate = fs.lazy_dirs.hold().await;
+    materialize_paths(fs, ids).await?;
+    Ok(gate)
+}
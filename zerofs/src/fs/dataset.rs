@@ -1,9 +1,41 @@
+use crate::fs::properties::DatasetProperties;
+use crate::fs::stats::SpaceUsage;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use uuid::Uuid;
 
 pub type DatasetId = u64;
 
+/// Dataset every inode outside other datasets belongs to
+pub const ROOT_DATASET_ID: DatasetId = 0;
+
+/// Limits on the bytes and inodes used by a dataset. Bytes are those files
+/// have allocated, so holes in sparse files do not count. Writes that would
+/// go over a hard limit fail with EDQUOT; going over a soft limit is only
+/// logged and reported.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DatasetQuota {
+    pub bytes_hard: Option<u64>,
+    pub bytes_soft: Option<u64>,
+    pub inodes_hard: Option<u64>,
+    pub inodes_soft: Option<u64>,
+}
+
+impl DatasetQuota {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Logical bytes and inodes used by a dataset, and the space taken by its
+/// chunk bodies
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DatasetUsage {
+    pub bytes: u64,
+    pub inodes: u64,
+    pub space: SpaceUsage,
+}
+
 /// Dataset metadata
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Dataset {
@@ -13,7 +45,8 @@ pub struct Dataset {
     pub name: String,
     /// UUID for this dataset
     pub uuid: Uuid,
-    /// Parent dataset ID (None for root dataset)
+    /// Dataset this one is nested in, or the source of a snapshot. None for
+    /// the root dataset and the datasets directly below it.
     pub parent_id: Option<DatasetId>,
     /// Parent UUID (for snapshots - references source dataset)
     pub parent_uuid: Option<Uuid>,
@@ -29,6 +62,16 @@ pub struct Dataset {
     pub generation: u64,
     /// Flags for future extensions
     pub flags: u64,
+    /// Key of the dataset, wrapped with the filesystem key. Chunks written in
+    /// the dataset are encrypted with it; destroying it makes them
+    /// unrecoverable. None if the dataset uses the filesystem key.
+    pub wrapped_key: Option<Vec<u8>>,
+    /// Space limits of the dataset. Snapshots have none.
+    pub quota: DatasetQuota,
+    /// Names of the holds on a snapshot. A held snapshot cannot be destroyed.
+    pub holds: BTreeSet<String>,
+    /// Properties set on the dataset itself
+    pub properties: DatasetProperties,
 }
 
 impl Dataset {
@@ -51,6 +94,20 @@ impl Dataset {
             is_snapshot: false,
             generation: 1,
             flags: 0,
+            wrapped_key: None,
+            quota: DatasetQuota::default(),
+            holds: BTreeSet::new(),
+            properties: DatasetProperties::default(),
+        }
+    }
+
+    /// Dataset this one is shown below in the dataset tree: its parent, or
+    /// the root dataset for top-level datasets
+    pub fn tree_parent(&self) -> Option<DatasetId> {
+        match self.parent_id {
+            Some(parent) => Some(parent),
+            None if self.id != ROOT_DATASET_ID => Some(ROOT_DATASET_ID),
+            None => None,
         }
     }
 
@@ -74,10 +131,217 @@ impl Dataset {
             is_snapshot: true,
             generation: source.generation,
             flags: 0,
+            wrapped_key: None,
+            quota: DatasetQuota::default(),
+            holds: BTreeSet::new(),
+            properties: DatasetProperties::default(),
+        }
+    }
+}
+
+/// `datasets` grouped by the dataset they are shown below in the dataset
+/// tree, in their original order. Those whose tree parent is not among
+/// `datasets` are grouped under None.
+pub fn tree_children(datasets: Vec<Dataset>) -> HashMap<Option<DatasetId>, Vec<Dataset>> {
+    let ids: HashSet<DatasetId> = datasets.iter().map(|d| d.id).collect();
+    let mut children: HashMap<_, Vec<_>> = HashMap::new();
+    for dataset in datasets {
+        let parent = dataset.tree_parent().filter(|p| ids.contains(p));
+        children.entry(parent).or_default().push(dataset);
+    }
+    children
+}
+
+/// Dataset as stored before datasets could have their own key
+#[derive(Deserialize)]
+struct DatasetV1 {
+    id: DatasetId,
+    name: String,
+    uuid: Uuid,
+    parent_id: Option<DatasetId>,
+    parent_uuid: Option<Uuid>,
+    root_inode: u64,
+    created_at: u64,
+    is_readonly: bool,
+    is_snapshot: bool,
+    generation: u64,
+    flags: u64,
+}
+
+impl From<DatasetV1> for Dataset {
+    fn from(v1: DatasetV1) -> Self {
+        Self {
+            id: v1.id,
+            name: v1.name,
+            uuid: v1.uuid,
+            parent_id: v1.parent_id,
+            parent_uuid: v1.parent_uuid,
+            root_inode: v1.root_inode,
+            created_at: v1.created_at,
+            is_readonly: v1.is_readonly,
+            is_snapshot: v1.is_snapshot,
+            generation: v1.generation,
+            flags: v1.flags,
+            wrapped_key: None,
+            quota: DatasetQuota::default(),
+            holds: BTreeSet::new(),
+            properties: DatasetProperties::default(),
+        }
+    }
+}
+
+/// Dataset as stored before datasets could have quotas
+#[derive(Deserialize)]
+struct DatasetV2 {
+    id: DatasetId,
+    name: String,
+    uuid: Uuid,
+    parent_id: Option<DatasetId>,
+    parent_uuid: Option<Uuid>,
+    root_inode: u64,
+    created_at: u64,
+    is_readonly: bool,
+    is_snapshot: bool,
+    generation: u64,
+    flags: u64,
+    wrapped_key: Option<Vec<u8>>,
+}
+
+impl From<DatasetV2> for Dataset {
+    fn from(v2: DatasetV2) -> Self {
+        Self {
+            id: v2.id,
+            name: v2.name,
+            uuid: v2.uuid,
+            parent_id: v2.parent_id,
+            parent_uuid: v2.parent_uuid,
+            root_inode: v2.root_inode,
+            created_at: v2.created_at,
+            is_readonly: v2.is_readonly,
+            is_snapshot: v2.is_snapshot,
+            generation: v2.generation,
+            flags: v2.flags,
+            wrapped_key: v2.wrapped_key,
+            quota: DatasetQuota::default(),
+            holds: BTreeSet::new(),
+            properties: DatasetProperties::default(),
+        }
+    }
+}
+
+/// Dataset as stored before snapshots could be held
+#[derive(Deserialize)]
+struct DatasetV3 {
+    id: DatasetId,
+    name: String,
+    uuid: Uuid,
+    parent_id: Option<DatasetId>,
+    parent_uuid: Option<Uuid>,
+    root_inode: u64,
+    created_at: u64,
+    is_readonly: bool,
+    is_snapshot: bool,
+    generation: u64,
+    flags: u64,
+    wrapped_key: Option<Vec<u8>>,
+    quota: DatasetQuota,
+}
+
+impl From<DatasetV3> for Dataset {
+    fn from(v3: DatasetV3) -> Self {
+        Self {
+            id: v3.id,
+            name: v3.name,
+            uuid: v3.uuid,
+            parent_id: v3.parent_id,
+            parent_uuid: v3.parent_uuid,
+            root_inode: v3.root_inode,
+            created_at: v3.created_at,
+            is_readonly: v3.is_readonly,
+            is_snapshot: v3.is_snapshot,
+            generation: v3.generation,
+            flags: v3.flags,
+            wrapped_key: v3.wrapped_key,
+            quota: v3.quota,
+            holds: BTreeSet::new(),
+            properties: DatasetProperties::default(),
+        }
+    }
+}
+
+/// Dataset as stored before datasets had properties
+#[derive(Deserialize)]
+struct DatasetV4 {
+    id: DatasetId,
+    name: String,
+    uuid: Uuid,
+    parent_id: Option<DatasetId>,
+    parent_uuid: Option<Uuid>,
+    root_inode: u64,
+    created_at: u64,
+    is_readonly: bool,
+    is_snapshot: bool,
+    generation: u64,
+    flags: u64,
+    wrapped_key: Option<Vec<u8>>,
+    quota: DatasetQuota,
+    holds: BTreeSet<String>,
+}
+
+impl From<DatasetV4> for Dataset {
+    fn from(v4: DatasetV4) -> Self {
+        Self {
+            id: v4.id,
+            name: v4.name,
+            uuid: v4.uuid,
+            parent_id: v4.parent_id,
+            parent_uuid: v4.parent_uuid,
+            root_inode: v4.root_inode,
+            created_at: v4.created_at,
+            is_readonly: v4.is_readonly,
+            is_snapshot: v4.is_snapshot,
+            generation: v4.generation,
+            flags: v4.flags,
+            wrapped_key: v4.wrapped_key,
+            quota: v4.quota,
+            holds: v4.holds,
+            properties: DatasetProperties::default(),
         }
     }
 }
 
+/// Registry holding datasets in an older layout
+#[derive(Deserialize)]
+struct LegacyRegistry<D> {
+    next_id: DatasetId,
+    datasets: HashMap<DatasetId, D>,
+    name_to_id: HashMap<String, DatasetId>,
+    default_dataset_id: DatasetId,
+}
+
+impl<D: Into<Dataset>> From<LegacyRegistry<D>> for DatasetRegistry {
+    fn from(legacy: LegacyRegistry<D>) -> Self {
+        Self {
+            next_id: legacy.next_id,
+            datasets: legacy
+                .datasets
+                .into_iter()
+                .map(|(id, dataset)| (id, dataset.into()))
+                .collect(),
+            name_to_id: legacy.name_to_id,
+            default_dataset_id: legacy.default_dataset_id,
+        }
+    }
+}
+
+/// Prefix of registries stored with a format version. Unversioned registries
+/// start with `next_id`, which never reaches `u64::MAX`.
+const REGISTRY_MAGIC: [u8; 8] = [0xFF; 8];
+const REGISTRY_FORMAT_V2: u8 = 2;
+const REGISTRY_FORMAT_V3: u8 = 3;
+const REGISTRY_FORMAT_V4: u8 = 4;
+const REGISTRY_FORMAT_V5: u8 = 5;
+
 /// Dataset tree entry - links inode to dataset
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DatasetInodeMapping {
@@ -115,6 +379,39 @@ impl DatasetRegistry {
         }
     }
 
+    pub fn encode(&self) -> Result<Vec<u8>, bincode::Error> {
+        let mut data = REGISTRY_MAGIC.to_vec();
+        data.push(REGISTRY_FORMAT_V5);
+        bincode::serialize_into(&mut data, self)?;
+        Ok(data)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, bincode::Error> {
+        let Some(rest) = data.strip_prefix(&REGISTRY_MAGIC) else {
+            let v1: LegacyRegistry<DatasetV1> = bincode::deserialize(data)?;
+            return Ok(v1.into());
+        };
+
+        match rest.split_first() {
+            Some((&REGISTRY_FORMAT_V2, registry)) => {
+                let v2: LegacyRegistry<DatasetV2> = bincode::deserialize(registry)?;
+                Ok(v2.into())
+            }
+            Some((&REGISTRY_FORMAT_V3, registry)) => {
+                let v3: LegacyRegistry<DatasetV3> = bincode::deserialize(registry)?;
+                Ok(v3.into())
+            }
+            Some((&REGISTRY_FORMAT_V4, registry)) => {
+                let v4: LegacyRegistry<DatasetV4> = bincode::deserialize(registry)?;
+                Ok(v4.into())
+            }
+            Some((&REGISTRY_FORMAT_V5, registry)) => bincode::deserialize(registry),
+            _ => Err(Box::new(bincode::ErrorKind::Custom(
+                "Unknown dataset registry format".to_string(),
+            ))),
+        }
+    }
+
     pub fn allocate_id(&mut self) -> DatasetId {
         let id = self.next_id;
         self.next_id += 1;
@@ -145,6 +442,135 @@ impl DatasetRegistry {
             .and_then(|id| self.datasets.get(id))
     }
 
+    pub fn get_by_root_inode(&self, root_inode: u64) -> Option<&Dataset> {
+        self.datasets
+            .values()
+            .find(|d| !d.is_snapshot && d.root_inode == root_inode)
+    }
+
+    /// Whether any dataset besides the root one holds files
+    pub fn has_child_datasets(&self) -> bool {
+        self.datasets
+            .values()
+            .any(|d| !d.is_snapshot && d.id != ROOT_DATASET_ID)
+    }
+
+    /// Whether any dataset has a key of its own
+    pub fn has_dataset_keys(&self) -> bool {
+        self.datasets.values().any(|d| d.wrapped_key.is_some())
+    }
+
+    /// Dataset whose key encrypts data written in dataset `id`: the dataset
+    /// itself if it has a key, otherwise the closest parent that has one.
+    /// Snapshots and child datasets inherit the key of their parent.
+    pub fn encryption_dataset(&self, id: DatasetId) -> Option<DatasetId> {
+        let mut dataset = self.datasets.get(&id);
+        while let Some(d) = dataset {
+            if d.wrapped_key.is_some() {
+                return Some(d.id);
+            }
+            dataset = d.parent_id.and_then(|parent| self.datasets.get(&parent));
+        }
+        None
+    }
+
+    /// Dataset that dataset `id` inherits the properties it does not set
+    /// from: its parent, or the root dataset for top-level datasets
+    pub fn property_parent(&self, id: DatasetId) -> Option<DatasetId> {
+        self.datasets.get(&id)?.tree_parent()
+    }
+
+    /// Datasets and snapshots below dataset `id`: its child datasets, their
+    /// children, the snapshots of any of them, and for a snapshot the ones
+    /// taken with it by a recursive snapshot. Deepest first, so that each
+    /// comes before the dataset it depends on.
+    pub fn descendants(&self, id: DatasetId) -> Vec<&Dataset> {
+        let Some(top) = self.datasets.get(&id) else {
+            return Vec::new();
+        };
+        let datasets = self.list_datasets();
+
+        let mut below = HashSet::from([id]);
+        let mut descendants = Vec::new();
+        if top.is_snapshot {
+            let prefix = format!("{}/", top.name);
+            for &dataset in &datasets {
+                if dataset.is_snapshot && dataset.name.starts_with(&prefix) {
+                    below.insert(dataset.id);
+                    descendants.push(dataset);
+                }
+            }
+        }
+
+        // Level by level: a moved dataset can be older than its parent
+        loop {
+            let level: Vec<&Dataset> = datasets
+                .iter()
+                .copied()
+                .filter(|d| !below.contains(&d.id))
+                .filter(|d| d.parent_id.is_some_and(|p| below.contains(&p)))
+                .collect();
+            if level.is_empty() {
+                break;
+            }
+            below.extend(level.iter().map(|d| d.id));
+            descendants.extend(level);
+        }
+        descendants.reverse();
+        descendants
+    }
+
+    /// Rename dataset or snapshot `id` to `name`, nested in `parent_id`.
+    /// Child datasets are renamed along, `team/app` with `team`, and so are
+    /// the snapshots taken with a snapshot, `nightly/app` with `nightly`;
+    /// snapshots of datasets keep their names. Ids and UUIDs are kept.
+    /// Returns the renamed ids, `id` first.
+    pub fn rename_dataset(
+        &mut self,
+        id: DatasetId,
+        name: String,
+        parent_id: Option<DatasetId>,
+    ) -> Result<Vec<DatasetId>, String> {
+        let dataset = self
+            .datasets
+            .get(&id)
+            .ok_or_else(|| format!("Dataset {} not found", id))?;
+        let prefix = format!("{}/", dataset.name);
+        let mut renames = vec![(id, name.clone())];
+        for descendant in self.descendants(id) {
+            if descendant.is_snapshot != dataset.is_snapshot {
+                continue;
+            }
+            if let Some(path) = descendant.name.strip_prefix(&prefix) {
+                renames.push((descendant.id, format!("{}/{}", name, path)));
+            }
+        }
+
+        for (_, new_name) in &renames {
+            if let Some(other) = self.name_to_id.get(new_name)
+                && !renames.iter().any(|(id, _)| id == other)
+            {
+                return Err(format!("Dataset '{}' already exists", new_name));
+            }
+        }
+
+        for (id, _) in &renames {
+            self.name_to_id.remove(&self.datasets[id].name);
+        }
+        for (renamed, new_name) in &renames {
+            if let Some(dataset) = self.datasets.get_mut(renamed) {
+                dataset.name = new_name.clone();
+                dataset.generation += 1;
+                if *renamed == id {
+                    dataset.parent_id = parent_id;
+                }
+            }
+            self.name_to_id.insert(new_name.clone(), *renamed);
+        }
+
+        Ok(renames.into_iter().map(|(id, _)| id).collect())
+    }
+
     pub fn remove_dataset(&mut self, id: DatasetId) -> Result<Dataset, String> {
         // Don't allow removing root dataset
         if id == 0 {
@@ -177,6 +603,7 @@ impl DatasetRegistry {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::CompressionConfig;
 
     #[test]
     fn test_dataset_registry() {
@@ -199,6 +626,62 @@ mod tests {
         assert!(registry.add_dataset(dup).is_err());
     }
 
+    #[test]
+    fn test_rename_dataset() {
+        let mut registry = DatasetRegistry::new_with_root(0, 1000);
+        let team = Dataset::new(registry.allocate_id(), "team".to_string(), 100, 2000, false);
+        let mut app = Dataset::new(
+            registry.allocate_id(),
+            "team/app".to_string(),
+            101,
+            2000,
+            false,
+        );
+        app.parent_id = Some(team.id);
+        let snapshot = Dataset::new_snapshot(
+            registry.allocate_id(),
+            "team/app@1".to_string(),
+            &app,
+            200,
+            3000,
+            true,
+        );
+        let other = Dataset::new(
+            registry.allocate_id(),
+            "group".to_string(),
+            102,
+            2000,
+            false,
+        );
+        for dataset in [team.clone(), app.clone(), snapshot.clone(), other.clone()] {
+            registry.add_dataset(dataset).unwrap();
+        }
+
+        assert_eq!(
+            registry.rename_dataset(team.id, "squad".to_string(), None),
+            Ok(vec![team.id, app.id])
+        );
+        assert!(registry.get_by_name("team").is_none());
+        assert!(registry.get_by_name("team/app").is_none());
+        let renamed = registry.get_by_name("squad/app").unwrap();
+        assert_eq!((renamed.id, renamed.uuid), (app.id, app.uuid));
+        assert_eq!(renamed.parent_id, Some(team.id));
+        // Snapshots named otherwise keep their name
+        assert_eq!(registry.get_by_name("team/app@1").unwrap().id, snapshot.id);
+
+        assert!(
+            registry
+                .rename_dataset(other.id, "squad/app".to_string(), Some(team.id))
+                .is_err()
+        );
+        assert_eq!(registry.get_by_name("group").unwrap().id, other.id);
+        registry
+            .rename_dataset(app.id, "group/app".to_string(), Some(other.id))
+            .unwrap();
+        assert_eq!(registry.property_parent(app.id), Some(other.id));
+        assert_eq!(registry.name_to_id.len(), registry.datasets.len());
+    }
+
     #[test]
     fn test_snapshot_creation() {
         let source = Dataset::new(1, "source".to_string(), 100, 1000, false);
@@ -210,4 +693,105 @@ mod tests {
         assert_eq!(snapshot.parent_uuid, Some(source.uuid));
         assert_eq!(snapshot.generation, source.generation);
     }
+
+    #[test]
+    fn test_registry_encoding() {
+        #[derive(Serialize)]
+        struct LegacyDataset {
+            id: DatasetId,
+            name: String,
+            uuid: Uuid,
+            parent_id: Option<DatasetId>,
+            parent_uuid: Option<Uuid>,
+            root_inode: u64,
+            created_at: u64,
+            is_readonly: bool,
+            is_snapshot: bool,
+            generation: u64,
+            flags: u64,
+        }
+
+        #[derive(Serialize)]
+        struct LegacyRegistry {
+            next_id: DatasetId,
+            datasets: HashMap<DatasetId, LegacyDataset>,
+            name_to_id: HashMap<String, DatasetId>,
+            default_dataset_id: DatasetId,
+        }
+
+        let legacy = LegacyRegistry {
+            next_id: 2,
+            datasets: HashMap::from([(
+                1,
+                LegacyDataset {
+                    id: 1,
+                    name: "data".to_string(),
+                    uuid: Uuid::new_v4(),
+                    parent_id: None,
+                    parent_uuid: None,
+                    root_inode: 100,
+                    created_at: 1000,
+                    is_readonly: false,
+                    is_snapshot: false,
+                    generation: 1,
+                    flags: 0,
+                },
+            )]),
+            name_to_id: HashMap::from([("data".to_string(), 1)]),
+            default_dataset_id: 1,
+        };
+
+        let mut registry = DatasetRegistry::decode(&bincode::serialize(&legacy).unwrap()).unwrap();
+        assert_eq!(registry.next_id, 2);
+        assert_eq!(registry.default_dataset_id, 1);
+        assert_eq!(registry.get_by_name("data").unwrap().root_inode, 100);
+        assert!(!registry.has_dataset_keys());
+
+        let id = registry.allocate_id();
+        let mut encrypted = Dataset::new(id, "tenant".to_string(), 200, 2000, false);
+        encrypted.wrapped_key = Some(vec![1, 2, 3]);
+        encrypted.quota = DatasetQuota {
+            bytes_hard: Some(1 << 30),
+            inodes_soft: Some(1000),
+            ..Default::default()
+        };
+        encrypted.properties.compression = Some(CompressionConfig::Zstd(3));
+        encrypted.properties.readonly = Some(true);
+        registry.add_dataset(encrypted.clone()).unwrap();
+        let mut snapshot = Dataset::new_snapshot(
+            registry.allocate_id(),
+            "tenant@1".to_string(),
+            &encrypted,
+            300,
+            3000,
+            true,
+        );
+        let snapshot_id = snapshot.id;
+        snapshot.holds.insert("backup".to_string());
+        registry.add_dataset(snapshot).unwrap();
+
+        let decoded = DatasetRegistry::decode(&registry.encode().unwrap()).unwrap();
+        assert!(decoded.has_dataset_keys());
+        assert_eq!(
+            decoded.get_by_id(id).unwrap().wrapped_key,
+            Some(vec![1, 2, 3])
+        );
+        assert_eq!(decoded.encryption_dataset(snapshot_id), Some(id));
+        assert_eq!(decoded.encryption_dataset(1), None);
+        assert_eq!(decoded.get_by_id(id).unwrap().quota, encrypted.quota);
+        assert!(decoded.get_by_id(snapshot_id).unwrap().quota.is_empty());
+        assert!(decoded.get_by_id(1).unwrap().quota.is_empty());
+        assert_eq!(decoded.get_by_root_inode(200).unwrap().id, id);
+        assert_eq!(
+            decoded.get_by_id(snapshot_id).unwrap().holds,
+            BTreeSet::from(["backup".to_string()])
+        );
+        assert_eq!(
+            decoded.get_by_id(id).unwrap().properties,
+            encrypted.properties
+        );
+        assert_eq!(decoded.property_parent(snapshot_id), Some(id));
+        assert_eq!(decoded.property_parent(id), Some(ROOT_DATASET_ID));
+        assert_eq!(decoded.property_parent(ROOT_DATASET_ID), None);
+    }
 }
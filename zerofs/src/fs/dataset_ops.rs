@@ -0,0 +1,891 @@
+use crate::encryption::EncryptedTransaction;
+use crate::fs::clone;
+use crate::fs::dataset::{Dataset, DatasetId, DatasetQuota, DatasetUsage, ROOT_DATASET_ID};
+use crate::fs::destroy::DestroyProgress;
+use crate::fs::diff;
+use crate::fs::errors::FsError;
+use crate::fs::inode::{Inode, InodeAttrs, InodeId};
+use crate::fs::key_codec::KeyCodec;
+use crate::fs::permissions::Credentials;
+use crate::fs::properties::{self, CaseSensitivity, EffectiveProperties, Property};
+use crate::fs::stats::{InodeAccount, UsageScope};
+use crate::fs::store::directory::fold_case;
+use crate::fs::types::{SetAttributes, SetGid, SetMode, SetUid};
+use crate::fs::{ROOT_INODE_ID, ZeroFS, get_current_time, validate_filename};
+use bytes::Bytes;
+use std::collections::HashSet;
+use tracing::info;
+
+const ROOT_CREDS: Credentials = Credentials {
+    uid: 0,
+    gid: 0,
+    groups: [0; 16],
+    groups_count: 1,
+};
+
+/// Create dataset `name`, rooted at a new directory of the same name in the
+/// filesystem root. A name like `team/app` nests the dataset in dataset
+/// `team`, which must exist: its root is the directory `app` in the root of
+/// `team`, and it inherits the key and properties of `team`. With
+/// `encrypt`, data written in the dataset is encrypted with a key of its
+/// own, so deleting the dataset makes it unrecoverable.
+pub async fn create_dataset(fs: &ZeroFS, name: &str, encrypt: bool) -> Result<Dataset, FsError> {
+    let (parent_name, dir_name) = split_dataset_name(name)?;
+    if fs.dataset_store.get_by_name(name).await.is_some() {
+        return Err(FsError::Exists);
+    }
+    let parent = match parent_name {
+        Some(parent_name) => Some(
+            fs.dataset_store
+                .get_by_name(parent_name)
+                .await
+                .filter(|d| !d.is_snapshot)
+                .ok_or(FsError::NotFound)?,
+        ),
+        None => None,
+    };
+
+    let attr = SetAttributes {
+        mode: SetMode::Set(0o755),
+        uid: SetUid::Set(0),
+        gid: SetGid::Set(0),
+        ..Default::default()
+    };
+    let mount_dir = parent.as_ref().map_or(ROOT_INODE_ID, |p| p.root_inode);
+    let (root_inode, _) = fs
+        .mkdir(&ROOT_CREDS, mount_dir, dir_name.as_bytes(), &attr)
+        .await?;
+
+    let (created_at, _) = get_current_time();
+    let dataset = fs
+        .dataset_store
+        .create_dataset(
+            name.to_string(),
+            parent.map(|p| p.id),
+            root_inode,
+            created_at,
+            false,
+            encrypt,
+        )
+        .await?;
+
+    info!(
+        "Created dataset '{}' (id={}, root={}, encrypted={})",
+        dataset.name,
+        dataset.id,
+        dataset.root_inode,
+        dataset.wrapped_key.is_some()
+    );
+    Ok(dataset)
+}
+
+/// Split dataset name `name` into the name of the dataset it is nested in,
+/// if any, and the name of its root directory
+fn split_dataset_name(name: &str) -> Result<(Option<&str>, &str), FsError> {
+    for component in name.split('/') {
+        if component.is_empty() || component == "." || component == ".." {
+            return Err(FsError::InvalidArgument);
+        }
+    }
+
+    let (parent_name, dir_name) = match name.rsplit_once('/') {
+        Some((parent_name, dir_name)) => (Some(parent_name), dir_name),
+        None => (None, name),
+    };
+    validate_filename(dir_name.as_bytes())?;
+    Ok((parent_name, dir_name))
+}
+
+/// Rename dataset or snapshot `id` to `name`, keeping its id and UUID. A
+/// dataset moves with its name: its root directory is renamed after the
+/// last component of `name` and moved into the root of the dataset the rest
+/// names, in the same transaction as the registry. Its child datasets come
+/// along and are renamed with it. A move that would change the key data
+/// written in the dataset is encrypted with is refused. Snapshots can only
+/// be renamed in place; those taken with them by a recursive snapshot are
+/// renamed along.
+pub async fn rename_dataset(fs: &ZeroFS, id: DatasetId, name: &str) -> Result<Dataset, FsError> {
+    let dataset = fs
+        .dataset_store
+        .get_by_id(id)
+        .await
+        .ok_or(FsError::NotFound)?;
+    if id == ROOT_DATASET_ID {
+        return Err(FsError::InvalidArgument);
+    }
+    if dataset.name == name {
+        return Ok(dataset);
+    }
+    let (parent_name, dir_name) = split_dataset_name(name)?;
+    if fs.dataset_store.get_by_name(name).await.is_some() {
+        return Err(FsError::Exists);
+    }
+
+    if dataset.is_snapshot {
+        if dataset.name.rsplit_once('/').map(|(p, _)| p) != parent_name {
+            return Err(FsError::InvalidArgument);
+        }
+        let mut txn = fs.db.new_transaction()?;
+        let update = fs
+            .dataset_store
+            .prepare_rename(&mut txn, id, name.to_string(), dataset.parent_id)
+            .await?;
+        let mut seq_guard = fs.write_coordinator.allocate_sequence();
+        fs.commit_transaction(txn, &mut seq_guard).await?;
+        let snapshot = update.commit();
+
+        info!(
+            "Renamed snapshot '{}' to '{}' (id={})",
+            dataset.name, snapshot.name, id
+        );
+        return Ok(snapshot);
+    }
+
+    let parent = match parent_name {
+        Some(parent_name) => Some(
+            fs.dataset_store
+                .get_by_name(parent_name)
+                .await
+                .filter(|d| !d.is_snapshot)
+                .ok_or(FsError::NotFound)?,
+        ),
+        None => None,
+    };
+    let parent_id = parent.as_ref().map(|p| p.id);
+
+    // Data already written stays encrypted with the key it inherits
+    if dataset.wrapped_key.is_none() {
+        let inherited = match parent_id {
+            Some(parent_id) => fs.dataset_store.encryption_dataset(parent_id).await,
+            None => None,
+        };
+        if fs.dataset_store.encryption_dataset(id).await != inherited {
+            return Err(FsError::InvalidArgument);
+        }
+    }
+
+    let root_inode = dataset.root_inode;
+    let Inode::Directory(root_dir) = fs.inode_store.get(root_inode).await? else {
+        return Err(FsError::NotDirectory);
+    };
+    let Some(old_dir_name) = root_dir.name else {
+        return Err(FsError::InvalidArgument);
+    };
+    let old_mount = root_dir.parent;
+    let new_mount = parent.as_ref().map_or(ROOT_INODE_ID, |p| p.root_inode);
+    if fs.is_ancestor_of(root_inode, new_mount).await? {
+        return Err(FsError::InvalidArgument);
+    }
+
+    let _cow = clone::prepare_write(fs, &[old_mount, new_mount, root_inode]).await?;
+    let _guards = fs
+        .lock_manager
+        .acquire_multiple_write(vec![old_mount, new_mount, root_inode])
+        .await;
+
+    let cookie = match fs
+        .directory_store
+        .get_entry_with_cookie(old_mount, &old_dir_name)
+        .await
+    {
+        Ok((entry_id, cookie)) if entry_id == root_inode => cookie,
+        Ok(_) => return Err(FsError::NotFound),
+        Err(e) => return Err(e),
+    };
+    match fs
+        .directory_store
+        .get_entry_with_cookie(new_mount, dir_name.as_bytes())
+        .await
+    {
+        Ok(_) => return Err(FsError::Exists),
+        Err(FsError::NotFound) => {}
+        Err(e) => return Err(e),
+    }
+
+    let mut txn = fs.db.new_transaction()?;
+    fs.directory_store
+        .unlink_entry(&mut txn, old_mount, &old_dir_name, cookie);
+
+    let (now_sec, now_nsec) = get_current_time();
+    let mut root = fs.inode_store.get(root_inode).await?;
+    if let Inode::Directory(dir) = &mut root {
+        dir.parent = new_mount;
+        dir.name = Some(dir_name.as_bytes().to_vec());
+        dir.ctime = now_sec;
+        dir.ctime_nsec = now_nsec;
+    }
+    let new_cookie = if old_mount == new_mount {
+        cookie
+    } else {
+        fs.directory_store
+            .allocate_cookie(new_mount, &mut txn)
+            .await?
+    };
+    fs.directory_store.add(
+        &mut txn,
+        new_mount,
+        dir_name.as_bytes(),
+        root_inode,
+        new_cookie,
+        Some(&root),
+    );
+    fs.inode_store.save(&mut txn, root_inode, &root)?;
+
+    let mut mounts = vec![(old_mount, -1)];
+    if new_mount != old_mount {
+        mounts.push((new_mount, 1));
+    }
+    for (mount_id, links) in mounts {
+        let mut mount_inode = fs.inode_store.get(mount_id).await?;
+        let Inode::Directory(mount) = &mut mount_inode else {
+            return Err(FsError::NotDirectory);
+        };
+        if old_mount != new_mount {
+            mount.entry_count = mount.entry_count.saturating_add_signed(links);
+            mount.nlink = mount.nlink.saturating_add_signed(links as i32);
+        }
+        mount.mtime = now_sec;
+        mount.mtime_nsec = now_nsec;
+        mount.ctime = now_sec;
+        mount.ctime_nsec = now_nsec;
+        let grandparent = mount.name.clone().map(|n| (mount.parent, n));
+        fs.inode_store.save(&mut txn, mount_id, &mount_inode)?;
+        if let Some((grandparent_id, mount_name)) = grandparent {
+            fs.directory_store
+                .update_inode_in_entry(
+                    &mut txn,
+                    grandparent_id,
+                    &mount_name,
+                    mount_id,
+                    &mount_inode,
+                )
+                .await
+                .ok();
+        }
+    }
+
+    // Last: the registry stays locked until the rename is applied
+    let update = fs
+        .dataset_store
+        .prepare_rename(&mut txn, id, name.to_string(), parent_id)
+        .await?;
+    let mut seq_guard = fs.write_coordinator.allocate_sequence();
+    fs.commit_transaction(txn, &mut seq_guard).await?;
+    let renamed = update.commit();
+
+    info!(
+        "Renamed dataset '{}' to '{}' (id={}, root={})",
+        dataset.name, renamed.name, id, root_inode
+    );
+    Ok(renamed)
+}
+
+/// Destroy dataset or snapshot `id`. Its root directory is detached from the
+/// namespace and the tree below it is left to the `DatasetReclaimer`, which
+/// removes it in the background; until then its usage counts towards the
+/// root dataset. A dataset with snapshots and a held snapshot are refused.
+pub async fn delete_dataset(fs: &ZeroFS, id: DatasetId) -> Result<DestroyProgress, FsError> {
+    let dataset = fs
+        .dataset_store
+        .get_by_id(id)
+        .await
+        .ok_or(FsError::NotFound)?;
+    let root_inode = dataset.root_inode;
+    let link = match fs.inode_store.get(root_inode).await {
+        Ok(Inode::Directory(dir)) => dir.name.map(|name| (dir.parent, name)),
+        Ok(_) => return Err(FsError::NotDirectory),
+        Err(FsError::NotFound) => None,
+        Err(e) => return Err(e),
+    };
+
+    let usage = dataset_usage(fs, id).await;
+    let progress = DestroyProgress::new(
+        id,
+        dataset.name.clone(),
+        root_inode,
+        ROOT_DATASET_ID,
+        ROOT_DATASET_ID,
+        (usage.bytes, usage.inodes),
+    );
+
+    // Writes check that their inode is still attached while they hold the
+    // clone gate: wait for those in flight, and keep new ones out until the
+    // tree is detached
+    let _gate = fs.lazy_dirs.hold_exclusive().await;
+    let mut lock_ids = vec![root_inode];
+    if let Some((parent_id, _)) = &link {
+        clone::materialize_paths(fs, &[*parent_id]).await?;
+        lock_ids.push(*parent_id);
+    }
+    let _guards = fs.lock_manager.acquire_multiple_write(lock_ids).await;
+
+    let mut txn = fs.db.new_transaction()?;
+    if let Some((parent_id, name)) = &link {
+        detach_dir(fs, &mut txn, *parent_id, name, root_inode).await?;
+    }
+    txn.put_bytes(&progress.key(), progress.encode()?);
+
+    // Last: the registry stays locked until the removal is applied
+    let update = fs.dataset_store.prepare_delete(&mut txn, id).await?;
+    fs.global_stats.forget_dataset(id, &mut txn);
+    let mut seq_guard = fs.write_coordinator.allocate_sequence();
+    fs.commit_transaction(txn, &mut seq_guard).await?;
+    let dataset = update.commit();
+    fs.detached_trees.commit_add(root_inode);
+
+    info!(
+        "Destroyed dataset '{}' (id={}): reclaiming {} inodes, {} bytes",
+        dataset.name, id, usage.inodes, usage.bytes
+    );
+    Ok(progress)
+}
+
+/// Destroy dataset or snapshot `id` with everything below it: its child
+/// datasets and their snapshots, deepest first, or for a snapshot the
+/// snapshots taken with it by a recursive snapshot. Nothing is destroyed if
+/// any of them is held. Returns the destroy of `id` and those of the
+/// datasets below it.
+pub async fn delete_dataset_recursive(
+    fs: &ZeroFS,
+    id: DatasetId,
+) -> Result<(DestroyProgress, Vec<DestroyProgress>), FsError> {
+    let descendants = fs.dataset_store.descendants(id).await;
+    if descendants.iter().any(|d| !d.holds.is_empty()) {
+        return Err(FsError::OperationNotPermitted);
+    }
+
+    let mut destroyed = Vec::with_capacity(descendants.len());
+    for descendant in descendants {
+        destroyed.push(delete_dataset(fs, descendant.id).await?);
+    }
+    Ok((delete_dataset(fs, id).await?, destroyed))
+}
+
+/// Snapshot dataset or snapshot `source_id` as `name`. The snapshot
+/// includes the files of the datasets nested in the source, but not
+/// snapshots of those datasets; see `create_recursive_snapshot`.
+pub async fn create_snapshot(
+    fs: &ZeroFS,
+    source_id: DatasetId,
+    name: &str,
+    readonly: bool,
+) -> Result<Dataset, FsError> {
+    let source = fs
+        .dataset_store
+        .get_by_id(source_id)
+        .await
+        .ok_or(FsError::NotFound)?;
+
+    let mut snapshots = take_snapshots(fs, vec![(source, name.to_string())], readonly).await?;
+    Ok(snapshots.remove(0))
+}
+
+/// Snapshot dataset `source_id` as `name` together with every dataset below
+/// it, all at the same point in time. The snapshot of a child dataset is
+/// named after its path below the source: snapshotting `team` as `nightly`
+/// takes `nightly/app` of `team/app`. Returns the snapshots, the source's
+/// first.
+pub async fn create_recursive_snapshot(
+    fs: &ZeroFS,
+    source_id: DatasetId,
+    name: &str,
+    readonly: bool,
+) -> Result<Vec<Dataset>, FsError> {
+    let source = fs
+        .dataset_store
+        .get_by_id(source_id)
+        .await
+        .ok_or(FsError::NotFound)?;
+
+    let prefix = format!("{}/", source.name);
+    let mut children: Vec<_> = fs
+        .dataset_store
+        .descendants(source_id)
+        .await
+        .into_iter()
+        .filter(|d| !d.is_snapshot)
+        .map(|d| {
+            let path = d.name.strip_prefix(&prefix).unwrap_or(&d.name);
+            let snapshot_name = format!("{}/{}", name, path);
+            (d, snapshot_name)
+        })
+        .collect();
+    children.reverse();
+
+    let mut sources = vec![(source, name.to_string())];
+    sources.extend(children);
+    take_snapshots(fs, sources, readonly).await
+}
+
+/// Snapshot each dataset of `sources` under the name given with it. The
+/// snapshot roots are clones of the sources' root directories that are not
+/// linked in the namespace, so a snapshot costs O(1) and its contents are
+/// only copied as either side changes.
+async fn take_snapshots(
+    fs: &ZeroFS,
+    sources: Vec<(Dataset, String)>,
+    readonly: bool,
+) -> Result<Vec<Dataset>, FsError> {
+    for (_, name) in &sources {
+        validate_filename(name.as_bytes())?;
+        if fs.dataset_store.get_by_name(name).await.is_some() {
+            return Err(FsError::Exists);
+        }
+    }
+
+    let source_roots: Vec<_> = sources.iter().map(|(s, _)| s.root_inode).collect();
+    let roots = clone::snapshot_dirs(fs, &source_roots).await?;
+
+    let (created_at, _) = get_current_time();
+    let snapshots = fs
+        .dataset_store
+        .create_snapshots(
+            sources
+                .iter()
+                .zip(roots)
+                .map(|((source, name), root)| (source.id, name.clone(), root))
+                .collect(),
+            created_at,
+            readonly,
+        )
+        .await?;
+
+    for ((source, _), snapshot) in sources.iter().zip(&snapshots) {
+        info!(
+            "Created snapshot '{}' of dataset '{}' (id={}, root={})",
+            snapshot.name, source.name, snapshot.id, snapshot.root_inode
+        );
+    }
+    Ok(snapshots)
+}
+
+/// Snapshots of dataset `id` taken after snapshot `snapshot_id`, oldest first
+pub async fn newer_snapshots(fs: &ZeroFS, id: DatasetId, snapshot_id: DatasetId) -> Vec<Dataset> {
+    let mut snapshots: Vec<Dataset> = fs
+        .dataset_store
+        .list_snapshots()
+        .await
+        .into_iter()
+        .filter(|s| s.parent_id == Some(id) && s.id > snapshot_id)
+        .collect();
+    snapshots.sort_by_key(|s| s.id);
+    snapshots
+}
+
+/// Roll dataset `id` back to its snapshot `snapshot_id`. In one transaction
+/// the dataset's root directory is replaced by a clone of the snapshot's
+/// root, so the snapshot is kept and the rollback costs O(1). The previous
+/// tree is detached and left to the `DatasetReclaimer`; file handles into it
+/// fail with `StaleHandle` from then on.
+///
+/// Datasets nested in the dataset would be detached with its tree: the
+/// rollback fails with `CrossDevice` while there are any. Snapshots taken
+/// after `snapshot_id` make the rollback fail with
+/// `NotEmpty`, unless `destroy_newer`: they are then destroyed once the
+/// root has been replaced. A hold on any of them fails it with
+/// `OperationNotPermitted` before anything is changed.
+/// Returns the updated dataset, the destroyed snapshots and the replaced
+/// tree.
+pub async fn rollback_dataset(
+    fs: &ZeroFS,
+    id: DatasetId,
+    snapshot_id: DatasetId,
+    destroy_newer: bool,
+) -> Result<(Dataset, Vec<DestroyProgress>, DestroyProgress), FsError> {
+    let dataset = fs
+        .dataset_store
+        .get_by_id(id)
+        .await
+        .ok_or(FsError::NotFound)?;
+    let snapshot = fs
+        .dataset_store
+        .get_by_id(snapshot_id)
+        .await
+        .ok_or(FsError::NotFound)?;
+    if dataset.is_snapshot || !snapshot.is_snapshot || snapshot.parent_id != Some(id) {
+        return Err(FsError::InvalidArgument);
+    }
+
+    let newer = newer_snapshots(fs, id, snapshot_id).await;
+    if !newer.is_empty() && !destroy_newer {
+        return Err(FsError::NotEmpty);
+    }
+    if newer.iter().any(|s| !s.holds.is_empty()) {
+        return Err(FsError::OperationNotPermitted);
+    }
+
+    // The root dataset is not linked in any directory
+    let old_root = dataset.root_inode;
+    let Inode::Directory(root_dir) = fs.inode_store.get(old_root).await? else {
+        return Err(FsError::NotDirectory);
+    };
+    let Some(name) = root_dir.name else {
+        return Err(FsError::InvalidArgument);
+    };
+    let parent_id = root_dir.parent;
+
+    // No modification may be in flight in the tree being replaced
+    let gate = fs.lazy_dirs.hold_exclusive().await;
+    clone::materialize_paths(fs, &[parent_id]).await?;
+    let guards = fs
+        .lock_manager
+        .acquire_multiple_write(vec![parent_id, old_root, snapshot.root_inode])
+        .await;
+
+    let cookie = match fs
+        .directory_store
+        .get_entry_with_cookie(parent_id, &name)
+        .await
+    {
+        Ok((entry_id, cookie)) if entry_id == old_root => cookie,
+        Ok(_) => return Err(FsError::NotFound),
+        Err(e) => return Err(e),
+    };
+    if has_dataset_below(fs, old_root).await? {
+        return Err(FsError::CrossDevice);
+    }
+
+    let usage = dataset_usage(fs, id).await;
+    let replaced = DestroyProgress::new(
+        id,
+        dataset.name.clone(),
+        old_root,
+        id,
+        ROOT_DATASET_ID,
+        (usage.bytes, usage.inodes),
+    );
+
+    let new_root = fs.inode_store.allocate();
+    let mut txn = fs.db.new_transaction()?;
+    let mut new_inode = clone::clone_inode(
+        fs,
+        &mut txn,
+        snapshot.root_inode,
+        new_root,
+        parent_id,
+        &name,
+        id,
+    )
+    .await?;
+    let (now_sec, now_nsec) = get_current_time();
+    if let Inode::Directory(dir) = &mut new_inode {
+        dir.ctime = now_sec;
+        dir.ctime_nsec = now_nsec;
+    }
+    fs.directory_store.add(
+        &mut txn,
+        parent_id,
+        &name,
+        new_root,
+        cookie,
+        Some(&new_inode),
+    );
+    fs.inode_store.save(&mut txn, new_root, &new_inode)?;
+
+    let mut parent_inode = fs.inode_store.get(parent_id).await?;
+    let Inode::Directory(parent) = &mut parent_inode else {
+        return Err(FsError::NotDirectory);
+    };
+    parent.mtime = now_sec;
+    parent.mtime_nsec = now_nsec;
+    parent.ctime = now_sec;
+    parent.ctime_nsec = now_nsec;
+    let grandparent = parent.name.clone().map(|n| (parent.parent, n));
+    fs.inode_store.save(&mut txn, parent_id, &parent_inode)?;
+    if let Some((grandparent_id, parent_name)) = grandparent {
+        fs.directory_store
+            .update_inode_in_entry(
+                &mut txn,
+                grandparent_id,
+                &parent_name,
+                parent_id,
+                &parent_inode,
+            )
+            .await
+            .ok();
+    }
+
+    // Like every dataset root, the new one counts towards the root dataset
+    let account = InodeAccount::new(ROOT_DATASET_ID, new_inode.ownership());
+    let stats_update = fs
+        .global_stats
+        .prepare_inode_create(new_root, account)
+        .await;
+    fs.global_stats
+        .add_to_transaction(&stats_update, &mut txn)?;
+    txn.put_bytes(&replaced.key(), replaced.encode()?);
+
+    // Last: the registry stays locked until the swap is applied
+    let swap = fs
+        .dataset_store
+        .prepare_root_swap(&mut txn, id, new_root)
+        .await?;
+    let mut seq_guard = fs.write_coordinator.allocate_sequence();
+    fs.commit_transaction(txn, &mut seq_guard).await?;
+    let dataset = swap.commit();
+    fs.global_stats.commit_update(&stats_update);
+    clone::commit_clone(fs, snapshot.root_inode, new_root, &new_inode);
+    fs.detached_trees.commit_add(old_root);
+    drop(guards);
+    drop(gate);
+
+    info!(
+        "Rolled back dataset '{}' (id={}) to snapshot '{}': root {} replaced by {}",
+        dataset.name, id, snapshot.name, old_root, new_root
+    );
+
+    // Only once the dataset no longer holds their state
+    let mut destroyed = Vec::with_capacity(newer.len());
+    for newer_snapshot in newer {
+        destroyed.push(delete_dataset(fs, newer_snapshot.id).await?);
+    }
+    Ok((dataset, destroyed, replaced))
+}
+
+/// Remove the entry `name` of directory `dir_id` in `parent_id`, leaving
+/// `dir_id` and its tree in place. Nothing is removed if the entry no longer
+/// leads to `dir_id`. The caller holds the write locks of both directories.
+async fn detach_dir(
+    fs: &ZeroFS,
+    txn: &mut EncryptedTransaction,
+    parent_id: InodeId,
+    name: &[u8],
+    dir_id: InodeId,
+) -> Result<(), FsError> {
+    let cookie = match fs
+        .directory_store
+        .get_entry_with_cookie(parent_id, name)
+        .await
+    {
+        Ok((entry_id, cookie)) if entry_id == dir_id => cookie,
+        Ok(_) | Err(FsError::NotFound) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    fs.directory_store
+        .unlink_entry(txn, parent_id, name, cookie);
+
+    let mut parent_inode = fs.inode_store.get(parent_id).await?;
+    let Inode::Directory(parent) = &mut parent_inode else {
+        return Err(FsError::NotDirectory);
+    };
+    let (now_sec, now_nsec) = get_current_time();
+    parent.entry_count = parent.entry_count.saturating_sub(1);
+    parent.nlink = parent.nlink.saturating_sub(1);
+    parent.mtime = now_sec;
+    parent.mtime_nsec = now_nsec;
+    parent.ctime = now_sec;
+    parent.ctime_nsec = now_nsec;
+
+    let grandparent = parent.name.clone().map(|n| (parent.parent, n));
+    fs.inode_store.save(txn, parent_id, &parent_inode)?;
+    if let Some((grandparent_id, parent_name)) = grandparent {
+        fs.directory_store
+            .update_inode_in_entry(txn, grandparent_id, &parent_name, parent_id, &parent_inode)
+            .await
+            .ok();
+    }
+
+    Ok(())
+}
+
+/// Set `property` on dataset `id`, or unset it for `None`. Making a dataset
+/// case-insensitive is refused with `FsError::Exists` while a directory in
+/// it, or in a child dataset inheriting the property, holds names differing
+/// only in case.
+pub async fn set_property(
+    fs: &ZeroFS,
+    id: DatasetId,
+    property: Property,
+    value: Option<&str>,
+) -> Result<Dataset, FsError> {
+    if property != Property::CaseSensitivity {
+        return fs.dataset_store.set_property(id, property, value).await;
+    }
+
+    let dataset = fs
+        .dataset_store
+        .get_by_id(id)
+        .await
+        .ok_or(FsError::NotFound)?;
+    let mut updated = dataset.clone();
+    properties::set_property(&mut updated, property, value)?;
+    let case_sensitivity = match (updated.properties.case_sensitivity, dataset.tree_parent()) {
+        (Some(case_sensitivity), _) => case_sensitivity,
+        (None, Some(parent)) => {
+            fs.dataset_store
+                .effective_properties(parent)
+                .await
+                .case_sensitivity
+        }
+        (None, None) => CaseSensitivity::default(),
+    };
+
+    // Keep names from being created while the dataset is checked
+    let _gate = fs.lazy_dirs.hold_exclusive().await;
+    let current = fs.dataset_store.effective_properties(id).await;
+    if current.case_sensitivity == CaseSensitivity::Sensitive
+        && case_sensitivity == CaseSensitivity::Insensitive
+    {
+        check_case_collisions(fs, &dataset).await?;
+    }
+    fs.dataset_store.set_property(id, property, value).await
+}
+
+/// Fail with `FsError::Exists` if a directory of `dataset` holds names
+/// differing only in case. Child datasets setting their own case
+/// sensitivity are left out. Entries written before names were indexed by
+/// their case-folded form are indexed along the way.
+async fn check_case_collisions(fs: &ZeroFS, dataset: &Dataset) -> Result<(), FsError> {
+    let mut txn = fs.db.new_transaction()?;
+    let mut pending = vec![dataset.root_inode];
+    while let Some(dir_id) = pending.pop() {
+        let entries_dir = fs.lazy_dirs.entries_dir(dir_id).await?;
+        let mut folded_names = HashSet::new();
+        for (name, (id, inode)) in diff::list(fs, entries_dir).await? {
+            let folded = fold_case(&name);
+            if !folded_names.insert(folded.clone()) {
+                return Err(FsError::Exists);
+            }
+            if folded != name {
+                txn.put_bytes(
+                    &KeyCodec::case_fold_key(entries_dir, &folded, &name),
+                    Bytes::new(),
+                );
+            }
+
+            if !matches!(inode, Inode::Directory(_)) {
+                continue;
+            }
+            if let Some(child) = fs.dataset_store.get_by_root_inode(id).await
+                && !child.is_snapshot
+                && child.properties.case_sensitivity.is_some()
+            {
+                continue;
+            }
+            pending.push(id);
+        }
+    }
+
+    let mut seq_guard = fs.write_coordinator.allocate_sequence();
+    fs.commit_transaction(txn, &mut seq_guard).await?;
+    Ok(())
+}
+
+/// Dataset directory `dir_id` belongs to: the innermost dataset rooted at
+/// the directory or one of its ancestors. Snapshots are not datasets of
+/// their own here, their contents count towards the enclosing dataset.
+pub async fn dataset_of(fs: &ZeroFS, dir_id: InodeId) -> Result<DatasetId, FsError> {
+    if !fs.dataset_store.has_child_datasets().await {
+        return Ok(ROOT_DATASET_ID);
+    }
+
+    let mut current = dir_id;
+    loop {
+        if let Some(dataset) = fs.dataset_store.get_by_root_inode(current).await
+            && !dataset.is_snapshot
+        {
+            return Ok(dataset.id);
+        }
+        if current == ROOT_INODE_ID {
+            return Ok(ROOT_DATASET_ID);
+        }
+        current = match fs.inode_store.get(current).await? {
+            Inode::Directory(dir) => dir.parent,
+            _ => return Err(FsError::NotDirectory),
+        };
+    }
+}
+
+/// Dataset inode `id` belongs to. Files carry theirs; other inodes belong
+/// to the dataset of their directory.
+pub async fn dataset_of_inode(fs: &ZeroFS, id: InodeId) -> Result<DatasetId, FsError> {
+    match fs.inode_store.get(id).await? {
+        Inode::File(file) => Ok(file.dataset),
+        Inode::Directory(_) => dataset_of(fs, id).await,
+        inode => match inode.parent() {
+            Some(parent) => dataset_of(fs, parent).await,
+            None => Ok(ROOT_DATASET_ID),
+        },
+    }
+}
+
+/// Whether the root directory of a dataset lies below directory `dir_id`.
+/// Such a tree cannot be replaced as a whole: the dataset would go with it.
+pub(crate) async fn has_dataset_below(fs: &ZeroFS, dir_id: InodeId) -> Result<bool, FsError> {
+    for dataset in fs.dataset_store.list_datasets().await {
+        if !dataset.is_snapshot
+            && dataset.root_inode != dir_id
+            && fs.is_ancestor_of(dir_id, dataset.root_inode).await?
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Properties in effect in the dataset of directory `dir_id`
+pub async fn dir_properties(fs: &ZeroFS, dir_id: InodeId) -> Result<EffectiveProperties, FsError> {
+    let dataset = dataset_of(fs, dir_id).await?;
+    Ok(fs.dataset_store.effective_properties(dataset).await)
+}
+
+/// Properties in effect in the dataset inode `id` belongs to
+pub async fn inode_properties(fs: &ZeroFS, id: InodeId) -> Result<EffectiveProperties, FsError> {
+    let dataset = dataset_of_inode(fs, id).await?;
+    Ok(fs.dataset_store.effective_properties(dataset).await)
+}
+
+/// Bytes and inodes used by dataset `id`. The root dataset holds whatever
+/// the other datasets do not.
+pub async fn dataset_usage(fs: &ZeroFS, id: DatasetId) -> DatasetUsage {
+    let space = fs.db.space_stats().get(id);
+    if id != ROOT_DATASET_ID {
+        let (bytes, inodes) = fs.global_stats.get_scope_totals(UsageScope::Dataset(id));
+        return DatasetUsage {
+            bytes,
+            inodes,
+            space,
+        };
+    }
+
+    let (mut bytes, mut inodes) = fs.global_stats.get_totals();
+    for dataset in fs.dataset_store.list_datasets().await {
+        if dataset.id != ROOT_DATASET_ID {
+            let (used_bytes, used_inodes) = fs
+                .global_stats
+                .get_scope_totals(UsageScope::Dataset(dataset.id));
+            bytes = bytes.saturating_sub(used_bytes);
+            inodes = inodes.saturating_sub(used_inodes);
+        }
+    }
+    DatasetUsage {
+        bytes,
+        inodes,
+        space,
+    }
+}
+
+/// Bytes and inodes used by the whole filesystem
+pub fn filesystem_usage(fs: &ZeroFS) -> DatasetUsage {
+    let (bytes, inodes) = fs.global_stats.get_totals();
+    DatasetUsage {
+        bytes,
+        inodes,
+        space: fs.db.space_stats().totals(),
+    }
+}
+
+/// Quota and usage of the dataset inode `id` belongs to, if the dataset has
+/// a hard limit. Reported as the size of the filesystem by statfs.
+pub async fn dataset_limits(
+    fs: &ZeroFS,
+    id: InodeId,
+) -> Result<Option<(DatasetQuota, DatasetUsage)>, FsError> {
+    let dataset = dataset_of_inode(fs, id).await?;
+    let quota = fs.dataset_store.get_quota(dataset).await;
+    if quota.bytes_hard.is_none() && quota.inodes_hard.is_none() {
+        return Ok(None);
+    }
+
+    Ok(Some((quota, dataset_usage(fs, dataset).await)))
+}
@@ -0,0 +1,446 @@
+use crate::encryption::EncryptedDb;
+use crate::fs::clone;
+use crate::fs::dataset::{DatasetId, ROOT_DATASET_ID};
+use crate::fs::errors::FsError;
+use crate::fs::inode::{Inode, InodeAttrs, InodeId};
+use crate::fs::key_codec::{KeyCodec, KeyPrefix};
+use crate::fs::stats::InodeAccount;
+use crate::fs::store::directory::DirEntryInfo;
+use crate::fs::{ROOT_INODE_ID, ZeroFS, get_current_time};
+use crate::task::{spawn_named, spawn_named_on};
+use bytes::Bytes;
+use futures::{StreamExt, pin_mut};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, RwLock};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+const MAX_ENTRIES_PER_BATCH: usize = 1_000;
+const RECLAIM_INTERVAL_SECS: u64 = 10;
+
+/// Reclamation of a tree detached from the namespace: the tree of a
+/// destroyed dataset or snapshot, or the tree a rollback replaced. Updated in
+/// the transaction of every reclaimed inode so an interrupted pass resumes
+/// where it stopped.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DestroyProgress {
+    pub dataset_id: DatasetId,
+    pub name: String,
+    /// Root of the tree, detached from the namespace
+    pub root_inode: InodeId,
+    /// Dataset the usage of the tree counts towards until it is reclaimed
+    pub account: DatasetId,
+    /// Dataset the root itself counts towards: the root dataset for the
+    /// root directory of a dataset
+    pub root_account: DatasetId,
+    /// When the tree was detached (seconds since UNIX epoch)
+    pub destroyed_at: u64,
+    /// Usage of the tree when it was detached, as far as known
+    pub total_bytes: u64,
+    pub total_inodes: u64,
+    pub reclaimed_bytes: u64,
+    pub reclaimed_inodes: u64,
+}
+
+impl DestroyProgress {
+    pub fn new(
+        dataset_id: DatasetId,
+        name: String,
+        root_inode: InodeId,
+        account: DatasetId,
+        root_account: DatasetId,
+        (total_bytes, total_inodes): (u64, u64),
+    ) -> Self {
+        let (destroyed_at, _) = get_current_time();
+        Self {
+            dataset_id,
+            name,
+            root_inode,
+            account,
+            root_account,
+            destroyed_at,
+            total_bytes,
+            total_inodes,
+            reclaimed_bytes: 0,
+            reclaimed_inodes: 0,
+        }
+    }
+
+    pub fn key(&self) -> Bytes {
+        KeyCodec::dataset_destroy_key(self.root_inode)
+    }
+
+    pub fn encode(&self) -> Result<Bytes, FsError> {
+        bincode::serialize(self)
+            .map(Bytes::from)
+            .map_err(|_| FsError::IoError)
+    }
+}
+
+/// Detached trees still being reclaimed
+pub async fn pending(fs: &ZeroFS) -> Result<Vec<DestroyProgress>, FsError> {
+    let (start, end) = KeyCodec::prefix_range(KeyPrefix::DatasetDestroy);
+    let iter = fs.db.scan(start..end).await.map_err(|_| FsError::IoError)?;
+    pin_mut!(iter);
+
+    let mut destroys = Vec::new();
+    while let Some(result) = iter.next().await {
+        let (_, value) = result.map_err(|_| FsError::IoError)?;
+        destroys.push(bincode::deserialize(&value).map_err(|_| FsError::InvalidData)?);
+    }
+    Ok(destroys)
+}
+
+/// Roots of the trees being reclaimed, kept in memory so file handles into
+/// them can be refused. Loaded at startup; changes are applied once the
+/// transaction writing the `DestroyProgress` has committed.
+#[derive(Clone)]
+pub struct DetachedTrees {
+    roots: Arc<RwLock<HashSet<InodeId>>>,
+}
+
+impl DetachedTrees {
+    pub async fn new(db: Arc<EncryptedDb>) -> Result<Self, FsError> {
+        let (start, end) = KeyCodec::prefix_range(KeyPrefix::DatasetDestroy);
+        let iter = db.scan(start..end).await.map_err(|_| FsError::IoError)?;
+        pin_mut!(iter);
+
+        let mut roots = HashSet::new();
+        while let Some(result) = iter.next().await {
+            let (key, _) = result.map_err(|_| FsError::IoError)?;
+            roots.insert(KeyCodec::parse_dataset_destroy_key(&key).ok_or(FsError::InvalidData)?);
+        }
+
+        Ok(Self {
+            roots: Arc::new(RwLock::new(roots)),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roots.read().unwrap().is_empty()
+    }
+
+    pub fn contains(&self, id: InodeId) -> bool {
+        self.roots.read().unwrap().contains(&id)
+    }
+
+    pub fn commit_add(&self, root: InodeId) {
+        self.roots.write().unwrap().insert(root);
+    }
+
+    pub fn commit_remove(&self, root: InodeId) {
+        self.roots.write().unwrap().remove(&root);
+    }
+}
+
+/// Fail with `StaleHandle` if inode `id` is in a detached tree, so NFS and
+/// 9P handles kept across a destroy or rollback do not write into a tree
+/// that is going away. Inodes with several hard links have no parent
+/// pointer and are let through.
+///
+/// The write paths of `ZeroFS` check again under the write lock of the inode
+/// they modify, with the clone gate held: destroys and rollbacks take it
+/// exclusively, so a tree cannot be detached between the check and the
+/// commit.
+pub async fn check_attached(fs: &ZeroFS, id: InodeId) -> Result<(), FsError> {
+    if fs.detached_trees.is_empty() {
+        return Ok(());
+    }
+
+    let mut current = id;
+    loop {
+        if fs.detached_trees.contains(current) {
+            return Err(FsError::StaleHandle);
+        }
+        if current == ROOT_INODE_ID {
+            return Ok(());
+        }
+        current = match fs.inode_store.get(current).await {
+            Ok(inode) => match inode.parent() {
+                Some(parent) => parent,
+                None => return Ok(()),
+            },
+            // The operation reports a missing inode itself, but a missing
+            // ancestor was reclaimed
+            Err(FsError::NotFound) if current == id => return Ok(()),
+            Err(FsError::NotFound) => return Err(FsError::StaleHandle),
+            Err(e) => return Err(e),
+        };
+    }
+}
+
+/// Background task removing detached trees, one inode per transaction. The
+/// data of removed files is released as on unlink: chunks go through
+/// tombstones to the `GarbageCollector`, and chunks or backing
+/// layers still referenced by clones outside the tree are kept.
+///
+/// Directories are removed depth first, each once its entries are gone, so
+/// the tree only ever shrinks and a restarted pass walks what is left.
+pub struct DatasetReclaimer {
+    fs: Arc<ZeroFS>,
+}
+
+impl DatasetReclaimer {
+    pub fn new(fs: Arc<ZeroFS>) -> Self {
+        Self { fs }
+    }
+
+    pub fn start(
+        self: Arc<Self>,
+        shutdown: CancellationToken,
+        runtime: Option<tokio::runtime::Handle>,
+    ) -> JoinHandle<()> {
+        let fut = async move {
+            loop {
+                tokio::select! {
+                    _ = shutdown.cancelled() => {
+                        info!("Dataset reclamation task shutting down");
+                        break;
+                    }
+                    result = self.run() => {
+                        if let Err(e) = result {
+                            tracing::error!("Dataset reclamation failed: {:?}", e);
+                        }
+                    }
+                }
+
+                tokio::select! {
+                    _ = shutdown.cancelled() => {
+                        info!("Dataset reclamation task shutting down");
+                        break;
+                    }
+                    _ = tokio::time::sleep(std::time::Duration::from_secs(RECLAIM_INTERVAL_SECS)) => {}
+                }
+            }
+        };
+
+        if let Some(rt) = runtime {
+            spawn_named_on("dataset-reclaim", fut, &rt)
+        } else {
+            spawn_named("dataset-reclaim", fut)
+        }
+    }
+
+    pub async fn run(&self) -> Result<(), FsError> {
+        for progress in pending(&self.fs).await? {
+            self.reclaim(progress).await?;
+        }
+        Ok(())
+    }
+
+    async fn reclaim(&self, mut progress: DestroyProgress) -> Result<(), FsError> {
+        let fs = &self.fs;
+        info!(
+            "Reclaiming tree {} of dataset '{}' (id={}): {} of {} inodes done",
+            progress.root_inode,
+            progress.name,
+            progress.dataset_id,
+            progress.reclaimed_inodes,
+            progress.total_inodes
+        );
+
+        if self.is_dataset_root(progress.root_inode).await {
+            warn!(
+                "Tree {} of dataset '{}' is the root of a dataset, leaving it in place",
+                progress.root_inode, progress.name
+            );
+            let mut txn = fs.db.new_transaction()?;
+            txn.delete_bytes(&progress.key());
+            let mut seq_guard = fs.write_coordinator.allocate_sequence();
+            fs.commit_transaction(txn, &mut seq_guard).await?;
+            fs.detached_trees.commit_remove(progress.root_inode);
+            return Ok(());
+        }
+
+        // Directories being emptied, with their entry in the directory above
+        let mut stack: Vec<(InodeId, Option<DirEntryInfo>)> = vec![(progress.root_inode, None)];
+        'dirs: while let Some((dir_id, _)) = stack.last() {
+            let dir_id = *dir_id;
+
+            // Clones reading through the directory get their own copy first.
+            // A lazy directory is not copied from its origin: those entries
+            // are not part of the tree.
+            let _gate = fs.lazy_dirs.hold().await;
+            for clone_id in fs.lazy_dirs.dependents(dir_id) {
+                clone::materialize_dir(fs, clone_id).await?;
+            }
+
+            let mut entries = Vec::new();
+            {
+                let stream = fs.directory_store.list(dir_id).await?;
+                let mut stream = stream.take(MAX_ENTRIES_PER_BATCH);
+                while let Some(entry) = stream.next().await {
+                    entries.push(entry?);
+                }
+            }
+
+            if entries.is_empty() {
+                let (_, entry) = stack.pop().unwrap();
+                let parent = stack.last().map(|(parent_id, _)| *parent_id);
+                let inode = match fs.inode_store.get(dir_id).await {
+                    Ok(inode) => Some(inode),
+                    Err(FsError::NotFound) => None,
+                    Err(e) => return Err(e),
+                };
+                let link = parent.zip(entry);
+                self.remove(&mut progress, link.as_ref(), dir_id, inode)
+                    .await?;
+                continue;
+            }
+
+            for entry in entries {
+                match fs.inode_store.get(entry.inode_id).await {
+                    // Only the link goes: the dataset is not part of the tree
+                    Ok(Inode::Directory(_)) if self.is_dataset_root(entry.inode_id).await => {
+                        warn!(
+                            "Leaving dataset root {} in place while reclaiming tree {}",
+                            entry.inode_id, progress.root_inode
+                        );
+                        let id = entry.inode_id;
+                        self.remove(&mut progress, Some(&(dir_id, entry)), id, None)
+                            .await?;
+                    }
+                    Ok(Inode::Directory(_)) => {
+                        stack.push((entry.inode_id, Some(entry)));
+                        continue 'dirs;
+                    }
+                    Ok(inode) => {
+                        let id = entry.inode_id;
+                        self.remove(&mut progress, Some(&(dir_id, entry)), id, Some(inode))
+                            .await?;
+                    }
+                    Err(FsError::NotFound) => {
+                        let id = entry.inode_id;
+                        self.remove(&mut progress, Some(&(dir_id, entry)), id, None)
+                            .await?;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            tokio::task::yield_now().await;
+        }
+
+        info!(
+            "Reclaimed tree {} of dataset '{}' (id={}): {} inodes, {} bytes",
+            progress.root_inode,
+            progress.name,
+            progress.dataset_id,
+            progress.reclaimed_inodes,
+            progress.reclaimed_bytes
+        );
+        Ok(())
+    }
+
+    /// Whether `id` is the root directory of a registered dataset or
+    /// snapshot, which reclamation must never remove
+    async fn is_dataset_root(&self, id: InodeId) -> bool {
+        self.fs.dataset_store.get_by_root_inode(id).await.is_some()
+    }
+
+    /// Remove the link to inode `id` from `link`'s directory, and the inode
+    /// itself with its last link. Without `link`, `id` is the root of the
+    /// tree, whose removal completes the reclamation. `inode` is `None` for
+    /// an entry whose inode is already gone.
+    async fn remove(
+        &self,
+        progress: &mut DestroyProgress,
+        link: Option<&(InodeId, DirEntryInfo)>,
+        id: InodeId,
+        inode: Option<Inode>,
+    ) -> Result<(), FsError> {
+        let fs = &self.fs;
+        let mut lock_ids = vec![id];
+        if let Some((dir_id, _)) = link {
+            lock_ids.push(*dir_id);
+        }
+        let _guards = fs.lock_manager.acquire_multiple_write(lock_ids).await;
+
+        let mut txn = fs.db.new_transaction()?;
+        if let Some((dir_id, entry)) = link {
+            fs.directory_store
+                .unlink_entry(&mut txn, *dir_id, &entry.name, entry.cookie);
+            if inode.as_ref().is_none_or(|inode| inode.parent().is_none()) {
+                fs.directory_store
+                    .remove_link_dir(&mut txn, id, *dir_id, &entry.name);
+            }
+        }
+
+        let mut stats_update = None;
+        if let Some(mut inode) = inode {
+            let nlink = match &mut inode {
+                Inode::File(file) => Some(&mut file.nlink),
+                Inode::Fifo(special)
+                | Inode::Socket(special)
+                | Inode::CharDevice(special)
+                | Inode::BlockDevice(special) => Some(&mut special.nlink),
+                Inode::Directory(_) | Inode::Symlink(_) => None,
+            };
+
+            match nlink {
+                Some(nlink) if *nlink > 1 => {
+                    *nlink -= 1;
+                    fs.inode_store.save(&mut txn, id, &inode)?;
+                }
+                _ => {
+                    let size = match &inode {
+                        Inode::File(file) => {
+                            fs.release_file_data(&mut txn, id, file).await?;
+                            Some(file.allocated_bytes())
+                        }
+                        Inode::Directory(_) => {
+                            fs.directory_store.delete_directory(&mut txn, id);
+                            fs.lazy_dirs.remove(&mut txn, id);
+                            None
+                        }
+                        _ => None,
+                    };
+                    fs.inode_store.delete(&mut txn, id);
+
+                    // The usage of a dataset destroyed meanwhile is no
+                    // longer tracked
+                    let dataset = match link {
+                        Some(_) => progress.account,
+                        None => progress.root_account,
+                    };
+                    let dataset = match fs.dataset_store.get_by_id(dataset).await {
+                        Some(_) => dataset,
+                        None => ROOT_DATASET_ID,
+                    };
+                    let account = InodeAccount::new(dataset, inode.ownership());
+                    let update = fs
+                        .global_stats
+                        .prepare_inode_remove(id, account, size)
+                        .await;
+                    fs.global_stats.add_to_transaction(&update, &mut txn)?;
+                    stats_update = Some(update);
+
+                    progress.reclaimed_inodes += 1;
+                    progress.reclaimed_bytes += size.unwrap_or(0);
+                }
+            }
+        }
+
+        if link.is_some() {
+            txn.put_bytes(&progress.key(), progress.encode()?);
+        } else {
+            txn.delete_bytes(&progress.key());
+        }
+
+        let mut seq_guard = fs.write_coordinator.allocate_sequence();
+        fs.commit_transaction(txn, &mut seq_guard).await?;
+        if let Some(update) = stats_update {
+            fs.global_stats.commit_update(&update);
+        }
+        fs.lazy_dirs.commit_remove(id);
+        if link.is_none() {
+            fs.detached_trees.commit_remove(id);
+        }
+        fs.stats.total_operations.fetch_add(1, Ordering::Relaxed);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,415 @@
+use crate::fs::ZeroFS;
+use crate::fs::dataset::DatasetId;
+use crate::fs::errors::FsError;
+use crate::fs::inode::{BackingLayer, FileInode, Inode, InodeAttrs, InodeId};
+use futures::{StreamExt, pin_mut};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+    Renamed,
+}
+
+/// One entry that differs between two trees
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Change {
+    pub kind: ChangeKind,
+    /// Path in the newer tree, or in the older one for a removed entry
+    pub path: String,
+    /// Path in the older tree of a renamed entry
+    pub old_path: Option<String>,
+    pub is_directory: bool,
+    /// File data or symlink target changed
+    pub content_changed: bool,
+    /// Mode, owner, device numbers or extended attributes changed, or the
+    /// modification time of a file whose content is the same
+    pub metadata_changed: bool,
+}
+
+/// A `Change` with the raw bytes of its paths, as used to replay it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct EntryChange {
+    pub kind: ChangeKind,
+    pub path: Vec<u8>,
+    pub old_path: Option<Vec<u8>>,
+    pub is_directory: bool,
+    pub content_changed: bool,
+    pub metadata_changed: bool,
+}
+
+impl From<EntryChange> for Change {
+    fn from(change: EntryChange) -> Self {
+        Change {
+            kind: change.kind,
+            path: display(&change.path),
+            old_path: change.old_path.as_deref().map(display),
+            is_directory: change.is_directory,
+            content_changed: change.content_changed,
+            metadata_changed: change.metadata_changed,
+        }
+    }
+}
+
+/// Entry present on one side only, before renames are matched
+struct Unpaired {
+    path: Vec<u8>,
+    id: InodeId,
+    inode: Inode,
+}
+
+/// What makes an entry the same across two trees: clones share directories
+/// they have not copied yet, and data layers.
+#[derive(PartialEq, Eq)]
+enum Identity {
+    Directory(InodeId),
+    Data(u64, Vec<BackingLayer>),
+}
+
+/// Changes from snapshot `from` to dataset or snapshot `to`; without `to`,
+/// to the live dataset `from` was taken of.
+pub async fn diff_snapshots(
+    fs: &ZeroFS,
+    from: DatasetId,
+    to: Option<DatasetId>,
+) -> Result<Vec<Change>, FsError> {
+    let from = fs
+        .dataset_store
+        .get_by_id(from)
+        .await
+        .filter(|d| d.is_snapshot)
+        .ok_or(FsError::NotFound)?;
+    let to_id = match to {
+        Some(id) => id,
+        None => from.parent_id.ok_or(FsError::InvalidArgument)?,
+    };
+    let to = fs
+        .dataset_store
+        .get_by_id(to_id)
+        .await
+        .ok_or(FsError::NotFound)?;
+
+    diff_trees(fs, from.root_inode, to.root_inode).await
+}
+
+/// Changes from the tree under directory `from_root` to that under
+/// `to_root`, sorted by path.
+///
+/// Both trees are walked together, by name. A subtree that both sides still
+/// read through the same lazy directory is unchanged and skipped, so after a
+/// snapshot the walk only visits the directories modified since. Entries
+/// present on one side only are paired as renames when they share a lazy
+/// directory or their data layers, and renamed directories are compared in
+/// turn; others are reported with every entry below them. File content is
+/// compared through data layers, so a file rewritten with the same data is
+/// reported as modified.
+pub async fn diff_trees(
+    fs: &ZeroFS,
+    from_root: InodeId,
+    to_root: InodeId,
+) -> Result<Vec<Change>, FsError> {
+    let changes = diff_entries(fs, from_root, to_root).await?;
+    Ok(changes.into_iter().map(Change::from).collect())
+}
+
+/// `diff_trees` with raw paths, sorted by their bytes
+pub(crate) async fn diff_entries(
+    fs: &ZeroFS,
+    from_root: InodeId,
+    to_root: InodeId,
+) -> Result<Vec<EntryChange>, FsError> {
+    // Lazy directories are read through their origins, which only keep the
+    // state of the clone while no modification is in flight: hold writes,
+    // copies and clones off until the walk is done
+    let _gate = fs.lazy_dirs.hold_exclusive().await;
+
+    let mut changes = Vec::new();
+    let mut removed: Vec<(Option<Unpaired>, Option<Identity>)> = Vec::new();
+    let mut added: Vec<(Unpaired, Option<Identity>)> = Vec::new();
+
+    // Directories to compare, with their paths on either side
+    let mut stack = vec![(from_root, to_root, Vec::new(), Vec::new())];
+    loop {
+        while let Some((from_dir, to_dir, from_path, to_path)) = stack.pop() {
+            let from_dir = fs.lazy_dirs.entries_dir(from_dir).await?;
+            let to_dir = fs.lazy_dirs.entries_dir(to_dir).await?;
+            if from_dir == to_dir {
+                continue;
+            }
+
+            let mut from_entries = list(fs, from_dir).await?;
+            let to_entries = list(fs, to_dir).await?;
+
+            for (name, (to_id, to_inode)) in to_entries {
+                let child_path = [to_path.as_slice(), b"/", &name].concat();
+                let Some((from_id, from_inode)) = from_entries.remove(&name) else {
+                    let entry = Unpaired {
+                        path: child_path,
+                        id: to_id,
+                        inode: to_inode,
+                    };
+                    let entry_identity = identity(fs, &entry).await?;
+                    added.push((entry, entry_identity));
+                    continue;
+                };
+                if from_id == to_id {
+                    continue;
+                }
+
+                let from_child_path = [from_path.as_slice(), b"/", &name].concat();
+                if !same_kind(&from_inode, &to_inode) {
+                    let old = Unpaired {
+                        path: from_child_path,
+                        id: from_id,
+                        inode: from_inode,
+                    };
+                    let old_identity = identity(fs, &old).await?;
+                    removed.push((Some(old), old_identity));
+                    let entry = Unpaired {
+                        path: child_path,
+                        id: to_id,
+                        inode: to_inode,
+                    };
+                    let entry_identity = identity(fs, &entry).await?;
+                    added.push((entry, entry_identity));
+                    continue;
+                }
+
+                let content_changed =
+                    !same_content(fs, from_id, &from_inode, to_id, &to_inode).await?;
+                let metadata_changed =
+                    !same_metadata(fs, from_id, &from_inode, to_id, &to_inode, content_changed)
+                        .await?;
+                if content_changed || metadata_changed {
+                    changes.push(EntryChange {
+                        kind: ChangeKind::Modified,
+                        path: child_path.clone(),
+                        old_path: None,
+                        is_directory: to_inode.is_directory(),
+                        content_changed,
+                        metadata_changed,
+                    });
+                }
+                if to_inode.is_directory() {
+                    stack.push((from_id, to_id, from_child_path, child_path));
+                }
+            }
+
+            for (name, (from_id, from_inode)) in from_entries {
+                let old = Unpaired {
+                    path: [from_path.as_slice(), b"/", &name].concat(),
+                    id: from_id,
+                    inode: from_inode,
+                };
+                let old_identity = identity(fs, &old).await?;
+                removed.push((Some(old), old_identity));
+            }
+        }
+
+        // Pair removed and added entries that are the same inode or clones.
+        // Entries left over are tried again once renamed directories have
+        // been compared, as an entry can move out of or into one.
+        let mut unmatched = Vec::with_capacity(added.len());
+        for (entry, entry_identity) in added {
+            let matched = removed.iter().position(|(old, old_identity)| {
+                old.as_ref().is_some_and(|old| {
+                    same_kind(&old.inode, &entry.inode)
+                        && (old.id == entry.id
+                            || (entry_identity.is_some() && *old_identity == entry_identity))
+                })
+            });
+            let Some(index) = matched else {
+                unmatched.push((entry, entry_identity));
+                continue;
+            };
+
+            let old = removed[index].0.take().unwrap();
+            let metadata_changed =
+                !same_metadata(fs, old.id, &old.inode, entry.id, &entry.inode, false).await?;
+            changes.push(EntryChange {
+                kind: ChangeKind::Renamed,
+                path: entry.path.clone(),
+                old_path: Some(old.path.clone()),
+                is_directory: entry.inode.is_directory(),
+                content_changed: false,
+                metadata_changed,
+            });
+            if entry.inode.is_directory() {
+                stack.push((old.id, entry.id, old.path, entry.path));
+            }
+        }
+        added = unmatched;
+
+        if stack.is_empty() {
+            break;
+        }
+    }
+
+    for entry in removed.into_iter().filter_map(|(entry, _)| entry) {
+        subtree(fs, entry, ChangeKind::Removed, &mut changes).await?;
+    }
+    for (entry, _) in added {
+        subtree(fs, entry, ChangeKind::Added, &mut changes).await?;
+    }
+
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(changes)
+}
+
+/// Entries of directory `dir_id` with their inodes, by name
+pub(crate) async fn list(
+    fs: &ZeroFS,
+    dir_id: InodeId,
+) -> Result<BTreeMap<Vec<u8>, (InodeId, Inode)>, FsError> {
+    let mut infos = Vec::new();
+    let stream = fs.directory_store.list(dir_id).await?;
+    pin_mut!(stream);
+    while let Some(entry) = stream.next().await {
+        infos.push(entry?);
+    }
+
+    let mut entries = BTreeMap::new();
+    for entry in infos {
+        let inode = match entry.inode {
+            Some(inode) => inode,
+            None => match fs.inode_store.get(entry.inode_id).await {
+                Ok(inode) => inode,
+                Err(FsError::NotFound) => {
+                    warn!(
+                        "Skipping entry '{}' of directory {}: inode {} not found",
+                        String::from_utf8_lossy(&entry.name),
+                        dir_id,
+                        entry.inode_id
+                    );
+                    continue;
+                }
+                Err(e) => return Err(e),
+            },
+        };
+        entries.insert(entry.name, (entry.inode_id, inode));
+    }
+    Ok(entries)
+}
+
+/// Report `entry` and, for a directory, every entry below it as `kind`
+async fn subtree(
+    fs: &ZeroFS,
+    entry: Unpaired,
+    kind: ChangeKind,
+    changes: &mut Vec<EntryChange>,
+) -> Result<(), FsError> {
+    let mut stack = vec![entry];
+    while let Some(entry) = stack.pop() {
+        if entry.inode.is_directory() {
+            for (name, (id, inode)) in list(fs, fs.lazy_dirs.entries_dir(entry.id).await?).await? {
+                stack.push(Unpaired {
+                    path: [entry.path.as_slice(), b"/", &name].concat(),
+                    id,
+                    inode,
+                });
+            }
+        }
+        changes.push(EntryChange {
+            kind,
+            is_directory: entry.inode.is_directory(),
+            path: entry.path,
+            old_path: None,
+            content_changed: false,
+            metadata_changed: false,
+        });
+    }
+    Ok(())
+}
+
+async fn identity(fs: &ZeroFS, entry: &Unpaired) -> Result<Option<Identity>, FsError> {
+    Ok(match &entry.inode {
+        Inode::Directory(_) => Some(Identity::Directory(
+            fs.lazy_dirs.entries_dir(entry.id).await?,
+        )),
+        Inode::File(file) => {
+            let layers = data_layers(fs, entry.id, file).await?;
+            (!layers.is_empty()).then_some(Identity::Data(file.size, layers))
+        }
+        _ => None,
+    })
+}
+
+/// The chunk maps file `id` reads its data from: its own map unless it is
+/// empty, then its backing layers.
+async fn data_layers(
+    fs: &ZeroFS,
+    id: InodeId,
+    file: &FileInode,
+) -> Result<Vec<BackingLayer>, FsError> {
+    let mut layers = Vec::with_capacity(file.backing.len() + 1);
+    let map_id = file.chunk_map_id(id);
+    if fs
+        .chunk_store
+        .has_chunks(map_id, file.size, file.chunk_size as usize)
+        .await?
+    {
+        layers.push(BackingLayer {
+            map_id,
+            visible_size: file.size,
+        });
+    }
+    layers.extend(file.backing.iter().filter(|layer| layer.visible_size > 0));
+    Ok(layers)
+}
+
+fn same_kind(a: &Inode, b: &Inode) -> bool {
+    std::mem::discriminant(a) == std::mem::discriminant(b)
+}
+
+async fn same_content(
+    fs: &ZeroFS,
+    a_id: InodeId,
+    a: &Inode,
+    b_id: InodeId,
+    b: &Inode,
+) -> Result<bool, FsError> {
+    Ok(match (a, b) {
+        (Inode::File(a), Inode::File(b)) => {
+            a.size == b.size && data_layers(fs, a_id, a).await? == data_layers(fs, b_id, b).await?
+        }
+        (Inode::Symlink(a), Inode::Symlink(b)) => a.target == b.target,
+        _ => true,
+    })
+}
+
+async fn same_metadata(
+    fs: &ZeroFS,
+    a_id: InodeId,
+    a: &Inode,
+    b_id: InodeId,
+    b: &Inode,
+    content_changed: bool,
+) -> Result<bool, FsError> {
+    if a.mode() != b.mode() || a.uid() != b.uid() || a.gid() != b.gid() {
+        return Ok(false);
+    }
+
+    // A directory's times change with its entries, and a file's with its
+    // content
+    let times_differ = (a.mtime(), a.mtime_nsec()) != (b.mtime(), b.mtime_nsec());
+    match (a, b) {
+        (Inode::Directory(_), Inode::Directory(_)) => {}
+        (
+            Inode::CharDevice(a) | Inode::BlockDevice(a),
+            Inode::CharDevice(b) | Inode::BlockDevice(b),
+        ) if a.rdev != b.rdev => return Ok(false),
+        _ if times_differ && !content_changed => return Ok(false),
+        _ => {}
+    }
+
+    Ok(fs.xattr_store.get(a_id).await? == fs.xattr_store.get(b_id).await?)
+}
+
+fn display(path: &[u8]) -> String {
+    String::from_utf8_lossy(path).into_owned()
+}
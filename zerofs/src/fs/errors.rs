@@ -35,6 +35,12 @@ pub enum FsError {
     InvalidData,
     #[error("Read-only file system")]
     ReadOnlyFilesystem,
+    #[error("No such attribute")]
+    NoData,
+    #[error("Disk quota exceeded")]
+    QuotaExceeded,
+    #[error("Cross-device link")]
+    CrossDevice,
 }
 
 impl From<bincode::Error> for FsError {
@@ -62,6 +68,9 @@ impl From<FsError> for nfsstat3 {
             FsError::StaleHandle => nfsstat3::NFS3ERR_STALE,
             FsError::InvalidData => nfsstat3::NFS3ERR_IO,
             FsError::ReadOnlyFilesystem => nfsstat3::NFS3ERR_ROFS,
+            FsError::NoData => nfsstat3::NFS3ERR_NOENT,
+            FsError::QuotaExceeded => nfsstat3::NFS3ERR_DQUOT,
+            FsError::CrossDevice => nfsstat3::NFS3ERR_XDEV,
         }
     }
 }
@@ -77,7 +86,9 @@ impl From<nfsstat3> for FsError {
             nfsstat3::NFS3ERR_ROFS => FsError::ReadOnlyFilesystem,
             nfsstat3::NFS3ERR_NOTEMPTY => FsError::NotEmpty,
             nfsstat3::NFS3ERR_MLINK => FsError::TooManyLinks,
-            nfsstat3::NFS3ERR_NOSPC | nfsstat3::NFS3ERR_DQUOT => FsError::NoSpace,
+            nfsstat3::NFS3ERR_NOSPC => FsError::NoSpace,
+            nfsstat3::NFS3ERR_DQUOT => FsError::QuotaExceeded,
+            nfsstat3::NFS3ERR_XDEV => FsError::CrossDevice,
             nfsstat3::NFS3ERR_ISDIR => FsError::IsDirectory,
             nfsstat3::NFS3ERR_NOTDIR => FsError::NotDirectory,
             nfsstat3::NFS3ERR_NAMETOOLONG => FsError::NameTooLong,
@@ -103,10 +114,13 @@ impl FsError {
             FsError::IsDirectory => libc::EISDIR as u32,
             FsError::NotDirectory => libc::ENOTDIR as u32,
             FsError::NameTooLong => libc::ENAMETOOLONG as u32,
-            FsError::NotSupported => libc::ENOSYS as u32,
+            FsError::NotSupported => libc::EOPNOTSUPP as u32,
             FsError::StaleHandle => libc::ESTALE as u32,
             FsError::InvalidData => libc::EIO as u32,
             FsError::ReadOnlyFilesystem => libc::EROFS as u32,
+            FsError::NoData => libc::ENODATA as u32,
+            FsError::QuotaExceeded => libc::EDQUOT as u32,
+            FsError::CrossDevice => libc::EXDEV as u32,
         }
     }
 }
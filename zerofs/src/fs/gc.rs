@@ -1,8 +1,9 @@
-use crate::encryption::EncryptedDb;
-use crate::fs::CHUNK_SIZE;
+use crate::encryption::{EncryptedDb, EncryptedTransaction};
 use crate::fs::errors::FsError;
+use crate::fs::key_codec::{KeyCodec, KeyPrefix};
 use crate::fs::metrics::FileSystemStats;
 use crate::fs::store::{ChunkStore, TombstoneStore};
+use crate::fs::write_coordinator::WriteCoordinator;
 use crate::task::{spawn_named, spawn_named_on};
 use bytes::Bytes;
 use slatedb::config::WriteOptions;
@@ -19,12 +20,14 @@ use fp::fail_point;
 
 const MAX_CHUNKS_PER_ROUND: usize = 10_000;
 const MAX_TOMBSTONES_PER_ROUND: usize = 10_000;
+const MAX_ORPHANS_PER_ROUND: usize = 10_000;
 
 pub struct GarbageCollector {
     db: Arc<EncryptedDb>,
     tombstone_store: TombstoneStore,
     chunk_store: ChunkStore,
     stats: Arc<FileSystemStats>,
+    write_coordinator: Arc<WriteCoordinator>,
 }
 
 impl GarbageCollector {
@@ -33,15 +36,42 @@ impl GarbageCollector {
         tombstone_store: TombstoneStore,
         chunk_store: ChunkStore,
         stats: Arc<FileSystemStats>,
+        write_coordinator: Arc<WriteCoordinator>,
     ) -> Self {
         Self {
             db,
             tombstone_store,
             chunk_store,
             stats,
+            write_coordinator,
         }
     }
 
+    /// Commit in sequence with filesystem writes, so chunk reference counts
+    /// are read after every earlier transaction has been applied.
+    async fn commit(&self, mut txn: EncryptedTransaction) -> Result<(), FsError> {
+        let mut seq_guard = self.write_coordinator.allocate_sequence();
+        if txn.has_cached_writes() {
+            seq_guard.wait_for_predecessors().await;
+            self.chunk_store.apply_cached_writes(&mut txn).await?;
+        }
+        let (prepared, _) = tokio::join!(txn.into_inner(), seq_guard.wait_for_predecessors());
+        let prepared = prepared.map_err(|_| FsError::IoError)?;
+
+        self.db
+            .write_prepared(
+                prepared,
+                &WriteOptions {
+                    await_durable: false,
+                },
+            )
+            .await
+            .map_err(|_| FsError::IoError)?;
+
+        seq_guard.mark_committed();
+        Ok(())
+    }
+
     pub fn start(
         self: Arc<Self>,
         shutdown: CancellationToken,
@@ -83,7 +113,7 @@ impl GarbageCollector {
         self.stats.gc_runs.fetch_add(1, Ordering::Relaxed);
 
         loop {
-            let mut tombstones_to_update: Vec<(Bytes, u64, usize, bool)> = Vec::new();
+            let mut tombstones_to_update: Vec<(Bytes, u64, u32, usize, bool)> = Vec::new();
             let mut chunks_deleted_this_round = 0;
             let mut tombstones_completed_this_round = 0;
             let mut tombstones_processed_this_round = 0;
@@ -108,7 +138,7 @@ impl GarbageCollector {
                 }
 
                 if entry.remaining_size == 0 {
-                    tombstones_to_update.push((entry.key, 0, 0, true));
+                    tombstones_to_update.push((entry.key, 0, entry.chunk_size, 0, true));
                     continue;
                 }
 
@@ -117,7 +147,7 @@ impl GarbageCollector {
                     break;
                 }
 
-                let total_chunks = entry.remaining_size.div_ceil(CHUNK_SIZE as u64) as usize;
+                let total_chunks = entry.remaining_size.div_ceil(entry.chunk_size as u64) as usize;
                 let chunks_to_delete = total_chunks.min(chunks_remaining_in_round);
                 let start_chunk = total_chunks.saturating_sub(chunks_to_delete);
 
@@ -128,28 +158,23 @@ impl GarbageCollector {
                 tombstones_to_update.push((
                     entry.key,
                     entry.remaining_size,
+                    entry.chunk_size,
                     start_chunk,
                     is_final_batch,
                 ));
 
                 let mut txn = self.db.new_transaction()?;
-                self.chunk_store.delete_range(
-                    &mut txn,
-                    entry.inode_id,
-                    start_chunk as u64,
-                    total_chunks as u64,
-                );
+                self.chunk_store
+                    .delete_range(
+                        &mut txn,
+                        entry.inode_id,
+                        start_chunk as u64,
+                        total_chunks as u64,
+                    )
+                    .await?;
 
                 if chunks_to_delete > 0 {
-                    self.db
-                        .write_with_options(
-                            txn,
-                            &WriteOptions {
-                                await_durable: false,
-                            },
-                        )
-                        .await
-                        .map_err(|_| FsError::IoError)?;
+                    self.commit(txn).await?;
 
                     #[cfg(feature = "failpoints")]
                     fail_point!(fp::GC_AFTER_CHUNK_DELETE);
@@ -170,27 +195,21 @@ impl GarbageCollector {
             if !tombstones_to_update.is_empty() {
                 let mut txn = self.db.new_transaction()?;
 
-                for (key, old_size, start_chunk, delete_tombstone) in tombstones_to_update {
+                for (key, old_size, chunk_size, start_chunk, delete_tombstone) in
+                    tombstones_to_update
+                {
                     if delete_tombstone {
                         self.tombstone_store.remove(&mut txn, &key);
                     } else {
                         let remaining_chunks = start_chunk;
-                        let remaining_size = (remaining_chunks as u64) * (CHUNK_SIZE as u64);
+                        let remaining_size = (remaining_chunks as u64) * (chunk_size as u64);
                         let actual_remaining = remaining_size.min(old_size);
                         self.tombstone_store
-                            .update(&mut txn, &key, actual_remaining);
+                            .update(&mut txn, &key, actual_remaining, chunk_size);
                     }
                 }
 
-                self.db
-                    .write_with_options(
-                        txn,
-                        &WriteOptions {
-                            await_durable: false,
-                        },
-                    )
-                    .await
-                    .map_err(|_| FsError::IoError)?;
+                self.commit(txn).await?;
 
                 #[cfg(feature = "failpoints")]
                 fail_point!(fp::GC_AFTER_TOMBSTONE_UPDATE);
@@ -219,6 +238,50 @@ impl GarbageCollector {
             tokio::task::yield_now().await;
         }
 
-        Ok(())
+        self.collect_orphan_chunks().await
+    }
+
+    /// Delete content-addressed chunk bodies whose last reference went away.
+    async fn collect_orphan_chunks(&self) -> Result<(), FsError> {
+        loop {
+            let (start, end) = KeyCodec::prefix_range(KeyPrefix::ChunkOrphan);
+            let iter = self
+                .db
+                .scan(start..end)
+                .await
+                .map_err(|_| FsError::IoError)?;
+            futures::pin_mut!(iter);
+
+            let mut txn = self.db.new_transaction()?;
+            let mut collected = 0usize;
+
+            while let Some(result) = futures::StreamExt::next(&mut iter).await {
+                let (key, _) = result.map_err(|_| FsError::IoError)?;
+                if let Some(hash) = KeyCodec::parse_chunk_orphan_key(&key) {
+                    txn.collect_orphan_chunk(hash);
+                    collected += 1;
+                }
+                if collected >= MAX_ORPHANS_PER_ROUND {
+                    break;
+                }
+            }
+
+            if collected == 0 {
+                return Ok(());
+            }
+
+            self.commit(txn).await?;
+
+            self.stats
+                .gc_orphan_chunks_collected
+                .fetch_add(collected as u64, Ordering::Relaxed);
+            tracing::debug!("GC: collected {} orphaned chunks", collected);
+
+            if collected < MAX_ORPHANS_PER_ROUND {
+                return Ok(());
+            }
+
+            tokio::task::yield_now().await;
+        }
     }
 }
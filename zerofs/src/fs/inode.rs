@@ -1,4 +1,6 @@
-use serde::{Deserialize, Serialize};
+use crate::fs::CHUNK_SIZE;
+use crate::fs::dataset::{DatasetId, ROOT_DATASET_ID};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 pub type InodeId = u64;
 
@@ -34,6 +36,303 @@ pub struct FileInode {
     /// File name in parent directory. None when file has multiple hardlinks.
     pub name: Option<Vec<u8>>,
     pub nlink: u32,
+    /// Chunk map this file writes to. None means the inode's own id; a file
+    /// that was cloned moves to a fresh map.
+    pub chunk_map: Option<InodeId>,
+    /// Read-only chunk maps shared with clones, nearest first. Chunks missing
+    /// from the file's own map are read from here.
+    pub backing: Vec<BackingLayer>,
+    /// Size in bytes of the chunks the file's data is split into. Fixed when
+    /// the file is created, and shared by its clones and backing layers.
+    pub chunk_size: u32,
+    /// Dataset whose own key encrypts the file's chunks. None for the
+    /// filesystem key. Fixed when the file is created.
+    pub encryption_dataset: Option<DatasetId>,
+    /// Dataset the file's usage is accounted to: that of the directory it
+    /// was created in. Files cannot be moved or linked across datasets.
+    pub dataset: DatasetId,
+    /// Number of chunks the file reads data from, in its own map or in the
+    /// backing layers it still exposes. Holes and chunks that were never
+    /// written, or only with zeros, take no storage and are not counted.
+    pub allocated_chunks: u64,
+}
+
+/// A frozen chunk map a file reads through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackingLayer {
+    pub map_id: InodeId,
+    /// Data at or beyond this offset is hidden, e.g. after the file was
+    /// truncated.
+    pub visible_size: u64,
+}
+
+impl FileInode {
+    /// Id of the chunk map this file writes to.
+    pub fn chunk_map_id(&self, id: InodeId) -> InodeId {
+        self.chunk_map.unwrap_or(id)
+    }
+
+    /// Hide backing data at or beyond `size`.
+    pub fn clip_backing(&mut self, size: u64) {
+        for layer in &mut self.backing {
+            layer.visible_size = layer.visible_size.min(size);
+        }
+    }
+
+    /// Number of chunks holding the file's data.
+    pub fn chunk_count(&self) -> u64 {
+        self.size.div_ceil(self.chunk_size as u64)
+    }
+
+    /// Bytes of storage the file's data takes: its allocated chunks, but no
+    /// more than its size.
+    pub fn allocated_bytes(&self) -> u64 {
+        self.allocated_chunks
+            .saturating_mul(self.chunk_size as u64)
+            .min(self.size)
+    }
+
+    /// Apply a change in allocated chunks reported by the chunk store.
+    pub fn adjust_allocated(&mut self, delta: i64) {
+        self.allocated_chunks = self.allocated_chunks.saturating_add_signed(delta);
+    }
+}
+
+/// File inode as stored before files could share chunk maps.
+#[derive(Deserialize)]
+struct FileInodeV1 {
+    size: u64,
+    mtime: u64,
+    mtime_nsec: u32,
+    ctime: u64,
+    ctime_nsec: u32,
+    atime: u64,
+    atime_nsec: u32,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    parent: Option<InodeId>,
+    name: Option<Vec<u8>>,
+    nlink: u32,
+}
+
+impl From<FileInodeV1> for FileInode {
+    fn from(v1: FileInodeV1) -> Self {
+        Self {
+            size: v1.size,
+            mtime: v1.mtime,
+            mtime_nsec: v1.mtime_nsec,
+            ctime: v1.ctime,
+            ctime_nsec: v1.ctime_nsec,
+            atime: v1.atime,
+            atime_nsec: v1.atime_nsec,
+            mode: v1.mode,
+            uid: v1.uid,
+            gid: v1.gid,
+            parent: v1.parent,
+            name: v1.name,
+            nlink: v1.nlink,
+            chunk_map: None,
+            backing: Vec::new(),
+            chunk_size: CHUNK_SIZE as u32,
+            encryption_dataset: None,
+            dataset: ROOT_DATASET_ID,
+            allocated_chunks: v1.size.div_ceil(CHUNK_SIZE as u64),
+        }
+    }
+}
+
+/// File inode as stored before the chunk size was recorded per file. All
+/// such files use the original fixed chunk size.
+#[derive(Deserialize)]
+struct FileInodeV2 {
+    size: u64,
+    mtime: u64,
+    mtime_nsec: u32,
+    ctime: u64,
+    ctime_nsec: u32,
+    atime: u64,
+    atime_nsec: u32,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    parent: Option<InodeId>,
+    name: Option<Vec<u8>>,
+    nlink: u32,
+    chunk_map: Option<InodeId>,
+    backing: Vec<BackingLayer>,
+}
+
+impl From<FileInodeV2> for FileInode {
+    fn from(v2: FileInodeV2) -> Self {
+        Self {
+            size: v2.size,
+            mtime: v2.mtime,
+            mtime_nsec: v2.mtime_nsec,
+            ctime: v2.ctime,
+            ctime_nsec: v2.ctime_nsec,
+            atime: v2.atime,
+            atime_nsec: v2.atime_nsec,
+            mode: v2.mode,
+            uid: v2.uid,
+            gid: v2.gid,
+            parent: v2.parent,
+            name: v2.name,
+            nlink: v2.nlink,
+            chunk_map: v2.chunk_map,
+            backing: v2.backing,
+            chunk_size: CHUNK_SIZE as u32,
+            encryption_dataset: None,
+            dataset: ROOT_DATASET_ID,
+            allocated_chunks: v2.size.div_ceil(CHUNK_SIZE as u64),
+        }
+    }
+}
+
+/// File inode as stored before datasets could have their own key.
+#[derive(Deserialize)]
+struct FileInodeV3 {
+    size: u64,
+    mtime: u64,
+    mtime_nsec: u32,
+    ctime: u64,
+    ctime_nsec: u32,
+    atime: u64,
+    atime_nsec: u32,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    parent: Option<InodeId>,
+    name: Option<Vec<u8>>,
+    nlink: u32,
+    chunk_map: Option<InodeId>,
+    backing: Vec<BackingLayer>,
+    chunk_size: u32,
+}
+
+impl From<FileInodeV3> for FileInode {
+    fn from(v3: FileInodeV3) -> Self {
+        Self {
+            size: v3.size,
+            mtime: v3.mtime,
+            mtime_nsec: v3.mtime_nsec,
+            ctime: v3.ctime,
+            ctime_nsec: v3.ctime_nsec,
+            atime: v3.atime,
+            atime_nsec: v3.atime_nsec,
+            mode: v3.mode,
+            uid: v3.uid,
+            gid: v3.gid,
+            parent: v3.parent,
+            name: v3.name,
+            nlink: v3.nlink,
+            chunk_map: v3.chunk_map,
+            backing: v3.backing,
+            chunk_size: v3.chunk_size,
+            encryption_dataset: None,
+            dataset: ROOT_DATASET_ID,
+            allocated_chunks: v3.size.div_ceil(v3.chunk_size as u64),
+        }
+    }
+}
+
+/// File inode as stored before datasets had quotas. Its usage is that of
+/// the root dataset.
+#[derive(Deserialize)]
+struct FileInodeV4 {
+    size: u64,
+    mtime: u64,
+    mtime_nsec: u32,
+    ctime: u64,
+    ctime_nsec: u32,
+    atime: u64,
+    atime_nsec: u32,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    parent: Option<InodeId>,
+    name: Option<Vec<u8>>,
+    nlink: u32,
+    chunk_map: Option<InodeId>,
+    backing: Vec<BackingLayer>,
+    chunk_size: u32,
+    encryption_dataset: Option<DatasetId>,
+}
+
+impl From<FileInodeV4> for FileInode {
+    fn from(v4: FileInodeV4) -> Self {
+        Self {
+            size: v4.size,
+            mtime: v4.mtime,
+            mtime_nsec: v4.mtime_nsec,
+            ctime: v4.ctime,
+            ctime_nsec: v4.ctime_nsec,
+            atime: v4.atime,
+            atime_nsec: v4.atime_nsec,
+            mode: v4.mode,
+            uid: v4.uid,
+            gid: v4.gid,
+            parent: v4.parent,
+            name: v4.name,
+            nlink: v4.nlink,
+            chunk_map: v4.chunk_map,
+            backing: v4.backing,
+            chunk_size: v4.chunk_size,
+            encryption_dataset: v4.encryption_dataset,
+            dataset: ROOT_DATASET_ID,
+            allocated_chunks: v4.size.div_ceil(v4.chunk_size as u64),
+        }
+    }
+}
+
+/// File inode as stored before allocated chunks were tracked. Such files are
+/// taken to be fully allocated, which is what was reported for them.
+#[derive(Deserialize)]
+struct FileInodeV5 {
+    size: u64,
+    mtime: u64,
+    mtime_nsec: u32,
+    ctime: u64,
+    ctime_nsec: u32,
+    atime: u64,
+    atime_nsec: u32,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    parent: Option<InodeId>,
+    name: Option<Vec<u8>>,
+    nlink: u32,
+    chunk_map: Option<InodeId>,
+    backing: Vec<BackingLayer>,
+    chunk_size: u32,
+    encryption_dataset: Option<DatasetId>,
+    dataset: DatasetId,
+}
+
+impl From<FileInodeV5> for FileInode {
+    fn from(v5: FileInodeV5) -> Self {
+        Self {
+            size: v5.size,
+            mtime: v5.mtime,
+            mtime_nsec: v5.mtime_nsec,
+            ctime: v5.ctime,
+            ctime_nsec: v5.ctime_nsec,
+            atime: v5.atime,
+            atime_nsec: v5.atime_nsec,
+            mode: v5.mode,
+            uid: v5.uid,
+            gid: v5.gid,
+            parent: v5.parent,
+            name: v5.name,
+            nlink: v5.nlink,
+            chunk_map: v5.chunk_map,
+            backing: v5.backing,
+            chunk_size: v5.chunk_size,
+            encryption_dataset: v5.encryption_dataset,
+            dataset: v5.dataset,
+            allocated_chunks: v5.size.div_ceil(v5.chunk_size as u64),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -238,7 +537,7 @@ impl InodeAttrs for SpecialInode {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone)]
 pub enum Inode {
     File(FileInode),
     Directory(DirectoryInode),
@@ -249,6 +548,68 @@ pub enum Inode {
     BlockDevice(SpecialInode),
 }
 
+/// Stored form of [`Inode`]. bincode encodes the variant index, so variants
+/// are only ever appended; a changed layout gets a new variant and the old
+/// one keeps decoding existing data.
+#[derive(Deserialize)]
+enum StoredInode {
+    File(FileInodeV1),
+    Directory(DirectoryInode),
+    Symlink(SymlinkInode),
+    Fifo(SpecialInode),
+    Socket(SpecialInode),
+    CharDevice(SpecialInode),
+    BlockDevice(SpecialInode),
+    FileV2(FileInodeV2),
+    FileV3(FileInodeV3),
+    FileV4(FileInodeV4),
+    FileV5(FileInodeV5),
+    FileV6(FileInode),
+}
+
+const STORED_INODE: &str = "StoredInode";
+
+impl Serialize for Inode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Inode::File(i) => serializer.serialize_newtype_variant(STORED_INODE, 11, "FileV6", i),
+            Inode::Directory(i) => {
+                serializer.serialize_newtype_variant(STORED_INODE, 1, "Directory", i)
+            }
+            Inode::Symlink(i) => {
+                serializer.serialize_newtype_variant(STORED_INODE, 2, "Symlink", i)
+            }
+            Inode::Fifo(i) => serializer.serialize_newtype_variant(STORED_INODE, 3, "Fifo", i),
+            Inode::Socket(i) => serializer.serialize_newtype_variant(STORED_INODE, 4, "Socket", i),
+            Inode::CharDevice(i) => {
+                serializer.serialize_newtype_variant(STORED_INODE, 5, "CharDevice", i)
+            }
+            Inode::BlockDevice(i) => {
+                serializer.serialize_newtype_variant(STORED_INODE, 6, "BlockDevice", i)
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Inode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match StoredInode::deserialize(deserializer)? {
+            StoredInode::File(i) => Inode::File(i.into()),
+            StoredInode::FileV2(i) => Inode::File(i.into()),
+            StoredInode::FileV3(i) => Inode::File(i.into()),
+            StoredInode::FileV4(i) => Inode::File(i.into()),
+            StoredInode::FileV5(i) => Inode::File(i.into()),
+            StoredInode::FileV6(i) => Inode::File(i),
+            StoredInode::Directory(i) => Inode::Directory(i),
+            StoredInode::Symlink(i) => Inode::Symlink(i),
+            StoredInode::Fifo(i) => Inode::Fifo(i),
+            StoredInode::Socket(i) => Inode::Socket(i),
+            StoredInode::CharDevice(i) => Inode::CharDevice(i),
+            StoredInode::BlockDevice(i) => Inode::BlockDevice(i),
+        })
+    }
+}
+
 impl InodeAttrs for Inode {
     fn uid(&self) -> u32 {
         match self {
@@ -413,6 +774,32 @@ impl Inode {
     pub fn ownership(&self) -> (u32, u32) {
         (self.uid(), self.gid())
     }
+
+    /// Set the status change time.
+    pub fn set_ctime(&mut self, sec: u64, nsec: u32) {
+        let (ctime, ctime_nsec) = match self {
+            Inode::File(f) => (&mut f.ctime, &mut f.ctime_nsec),
+            Inode::Directory(d) => (&mut d.ctime, &mut d.ctime_nsec),
+            Inode::Symlink(s) => (&mut s.ctime, &mut s.ctime_nsec),
+            Inode::Fifo(s) | Inode::Socket(s) | Inode::CharDevice(s) | Inode::BlockDevice(s) => {
+                (&mut s.ctime, &mut s.ctime_nsec)
+            }
+        };
+        *ctime = sec;
+        *ctime_nsec = nsec;
+    }
+
+    /// Set the mode bits.
+    pub fn set_mode(&mut self, mode: u32) {
+        match self {
+            Inode::File(f) => f.mode = mode,
+            Inode::Directory(d) => d.mode = mode,
+            Inode::Symlink(s) => s.mode = mode,
+            Inode::Fifo(s) | Inode::Socket(s) | Inode::CharDevice(s) | Inode::BlockDevice(s) => {
+                s.mode = mode
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -438,6 +825,12 @@ mod tests {
             parent: Some(0),
             name: Some(b"test.txt".to_vec()),
             nlink: 1,
+            chunk_map: None,
+            backing: Vec::new(),
+            chunk_size: CHUNK_SIZE as u32,
+            encryption_dataset: None,
+            dataset: 0,
+            allocated_chunks: 1,
         };
 
         let inode = Inode::File(file_inode);
@@ -541,6 +934,12 @@ mod tests {
             parent: Some(0),
             name: Some(b"test.txt".to_vec()),
             nlink: 1,
+            chunk_map: None,
+            backing: Vec::new(),
+            chunk_size: CHUNK_SIZE as u32,
+            encryption_dataset: None,
+            dataset: 0,
+            allocated_chunks: 1,
         };
 
         let inode = Inode::File(file_inode.clone());
@@ -560,4 +959,124 @@ mod tests {
             _ => panic!("Expected File inode"),
         }
     }
+
+    #[test]
+    fn test_legacy_file_inode_deserialization() {
+        #[derive(Serialize)]
+        enum LegacyInode {
+            File {
+                size: u64,
+                mtime: u64,
+                mtime_nsec: u32,
+                ctime: u64,
+                ctime_nsec: u32,
+                atime: u64,
+                atime_nsec: u32,
+                mode: u32,
+                uid: u32,
+                gid: u32,
+                parent: Option<InodeId>,
+                name: Option<Vec<u8>>,
+                nlink: u32,
+            },
+        }
+
+        let legacy = LegacyInode::File {
+            size: 4096,
+            mtime: 1,
+            mtime_nsec: 2,
+            ctime: 3,
+            ctime_nsec: 4,
+            atime: 5,
+            atime_nsec: 6,
+            mode: 0o600,
+            uid: 7,
+            gid: 8,
+            parent: Some(0),
+            name: Some(b"old.bin".to_vec()),
+            nlink: 1,
+        };
+
+        let serialized = bincode::serialize(&legacy).unwrap();
+        match bincode::deserialize::<Inode>(&serialized).unwrap() {
+            Inode::File(f) => {
+                assert_eq!(f.size, 4096);
+                assert_eq!(f.mode, 0o600);
+                assert_eq!(f.name.as_deref(), Some(&b"old.bin"[..]));
+                assert_eq!(f.chunk_map_id(42), 42);
+                assert!(f.backing.is_empty());
+                assert_eq!(f.chunk_size, CHUNK_SIZE as u32);
+                assert_eq!(f.dataset, 0);
+                assert_eq!(f.allocated_chunks, 1);
+            }
+            _ => panic!("Expected File inode"),
+        }
+    }
+
+    #[test]
+    fn test_file_inode_layers_roundtrip() {
+        let mut file_inode = FileInode {
+            size: 10,
+            mtime: 0,
+            mtime_nsec: 0,
+            ctime: 0,
+            ctime_nsec: 0,
+            atime: 0,
+            atime_nsec: 0,
+            mode: 0o644,
+            uid: 0,
+            gid: 0,
+            parent: None,
+            name: None,
+            nlink: 2,
+            chunk_map: Some(9),
+            backing: vec![BackingLayer {
+                map_id: 5,
+                visible_size: 100,
+            }],
+            chunk_size: 256 * 1024,
+            encryption_dataset: None,
+            dataset: 3,
+            allocated_chunks: 1,
+        };
+        file_inode.clip_backing(50);
+
+        let serialized = bincode::serialize(&Inode::File(file_inode)).unwrap();
+        match bincode::deserialize::<Inode>(&serialized).unwrap() {
+            Inode::File(f) => {
+                assert_eq!(f.chunk_map_id(1), 9);
+                assert_eq!(f.chunk_size, 256 * 1024);
+                assert_eq!(f.dataset, 3);
+                assert_eq!(
+                    f.backing,
+                    vec![BackingLayer {
+                        map_id: 5,
+                        visible_size: 50
+                    }]
+                );
+            }
+            _ => panic!("Expected File inode"),
+        }
+
+        let dir = Inode::Directory(DirectoryInode {
+            mtime: 0,
+            mtime_nsec: 0,
+            ctime: 0,
+            ctime_nsec: 0,
+            atime: 0,
+            atime_nsec: 0,
+            mode: 0o755,
+            uid: 0,
+            gid: 0,
+            entry_count: 3,
+            parent: 0,
+            name: None,
+            nlink: 2,
+        });
+        let serialized = bincode::serialize(&dir).unwrap();
+        assert!(matches!(
+            bincode::deserialize::<Inode>(&serialized).unwrap(),
+            Inode::Directory(d) if d.entry_count == 3
+        ));
+    }
 }
@@ -1,5 +1,9 @@
+use super::CHUNK_SIZE;
+use super::dataset::DatasetId;
 use super::errors::FsError;
 use super::inode::InodeId;
+use super::quota::Owner;
+use super::stats::SpaceUsage;
 use bytes::Bytes;
 
 // Key prefix design for LSM tree optimization with size-tiered compaction.
@@ -16,9 +20,25 @@ use bytes::Bytes;
 //   0x06-0x07: Cold metadata
 //     - SYSTEM: rarely accessed configuration
 //     - TOMBSTONE: only scanned during background GC
-//   0xFE: Bulk data
-//     - CHUNK: large data that dominates storage; isolated to prevent metadata
-//       scans from touching chunk-heavy SSTs
+//   0x08-0x0D: Dataset, chunk and clone bookkeeping
+//     - DATASET + DATASET_REGISTRY
+//     - CHUNK_REFCOUNT: live references per content-addressed chunk
+//     - CHUNK_ORPHAN: chunks whose last reference went away, scanned by GC
+//     - CHUNK_LAYER: reference count and size of chunk maps shared by clones
+//     - LAZY_DIR: cloned directories whose entries are still those of their origin
+//   0x0E: XATTR: extended attributes of an inode, all held in one value
+//   0x0F: DATASET_STATS: usage of each dataset, sharded like STATS
+//   0x10: OWNER_STATS: usage of each uid and gid, sharded like STATS
+//   0x11: CHUNK_FOOTPRINT: stored size of each content-addressed chunk body
+//   0x12: SPACE_STATS: stored size of the chunk bodies of each dataset
+//   0x13: DATASET_DESTROY: detached trees being reclaimed, by root inode
+//   0x14: LINK_DIR: directories holding the links of inodes with several links
+//   0x15: CASE_FOLD: entry names by their case-folded form, for names not in it
+//   0xFD-0xFE: Bulk data
+//     - CHUNK_CAS: chunk bodies keyed by content hash, shared between inodes
+//     - CHUNK: per-inode chunk map; values reference a CHUNK_CAS entry (older
+//       data may still hold the chunk body inline). Isolated to prevent
+//       metadata scans from touching chunk-heavy SSTs
 
 const PREFIX_INODE: u8 = 0x01;
 const PREFIX_DIR_ENTRY: u8 = 0x02;
@@ -29,17 +49,54 @@ const PREFIX_SYSTEM: u8 = 0x06;
 const PREFIX_TOMBSTONE: u8 = 0x07;
 const PREFIX_DATASET: u8 = 0x08;
 const PREFIX_DATASET_REGISTRY: u8 = 0x09;
+const PREFIX_CHUNK_REFCOUNT: u8 = 0x0A;
+const PREFIX_CHUNK_ORPHAN: u8 = 0x0B;
+const PREFIX_CHUNK_LAYER: u8 = 0x0C;
+const PREFIX_LAZY_DIR: u8 = 0x0D;
+const PREFIX_XATTR: u8 = 0x0E;
+const PREFIX_DATASET_STATS: u8 = 0x0F;
+const PREFIX_OWNER_STATS: u8 = 0x10;
+const PREFIX_CHUNK_FOOTPRINT: u8 = 0x11;
+const PREFIX_SPACE_STATS: u8 = 0x12;
+const PREFIX_DATASET_DESTROY: u8 = 0x13;
+const PREFIX_LINK_DIR: u8 = 0x14;
+const PREFIX_CASE_FOLD: u8 = 0x15;
+const PREFIX_CHUNK_CAS: u8 = 0xFD;
 const PREFIX_CHUNK: u8 = 0xFE;
-const PREFIX_CHUNK_CAS: u8 = 0xFD;  // Content-addressable chunks (by hash)
 
 const SYSTEM_COUNTER_SUBTYPE: u8 = 0x01;
+const SYSTEM_REENCRYPTION_SUBTYPE: u8 = 0x02;
+const SYSTEM_ACLS_SUBTYPE: u8 = 0x03;
+const SYSTEM_QUOTAS_SUBTYPE: u8 = 0x04;
+
+const OWNER_USER: u8 = 0x00;
+const OWNER_GROUP: u8 = 0x01;
 
 pub const SYSTEM_WRAPPED_ENCRYPTION_KEY: &[u8] = b"system:wrapped_encryption_key";
+pub const SYSTEM_KEY_SLOTS: &[u8] = b"system:key_slots";
 
 const U64_SIZE: usize = std::mem::size_of::<u64>();
+const U32_SIZE: usize = std::mem::size_of::<u32>();
 const KEY_INODE_SIZE: usize = 1 + U64_SIZE;
 const KEY_CHUNK_SIZE: usize = 17;
 const KEY_TOMBSTONE_SIZE: usize = 17;
+const KEY_DATASET_STATS_SIZE: usize = 17;
+const KEY_OWNER_STATS_SIZE: usize = 2 + U32_SIZE + U64_SIZE;
+const KEY_CHUNK_HASH_SIZE: usize = 1 + CHUNK_HASH_SIZE;
+
+pub const CHUNK_HASH_SIZE: usize = 32;
+
+/// Keyed content hash identifying a chunk body in the content-addressed store.
+pub type ChunkHash = [u8; CHUNK_HASH_SIZE];
+
+/// Marker byte of a chunk map value that references a content-addressed chunk.
+/// Inline chunk bodies are always a full chunk long, so a value of exactly
+/// `CHUNK_REF_SIZE` bytes starting with this marker is never inline data.
+const CHUNK_REF_MARKER: u8 = 0xCA;
+const CHUNK_REF_SIZE: usize = 1 + CHUNK_HASH_SIZE;
+
+/// Chunk map value hiding a chunk of a backing layer: the chunk reads as zeros.
+const CHUNK_HOLE: &[u8] = &[0x00];
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum KeyPrefix {
@@ -53,6 +110,19 @@ pub enum KeyPrefix {
     DirCookie,
     Dataset,
     DatasetRegistry,
+    ChunkRefCount,
+    ChunkOrphan,
+    ChunkLayer,
+    LazyDir,
+    Xattr,
+    DatasetStats,
+    OwnerStats,
+    ChunkFootprint,
+    SpaceStats,
+    DatasetDestroy,
+    LinkDir,
+    CaseFold,
+    ChunkCas,
 }
 
 impl TryFrom<u8> for KeyPrefix {
@@ -70,6 +140,19 @@ impl TryFrom<u8> for KeyPrefix {
             PREFIX_DIR_COOKIE => Ok(Self::DirCookie),
             PREFIX_DATASET => Ok(Self::Dataset),
             PREFIX_DATASET_REGISTRY => Ok(Self::DatasetRegistry),
+            PREFIX_CHUNK_REFCOUNT => Ok(Self::ChunkRefCount),
+            PREFIX_CHUNK_ORPHAN => Ok(Self::ChunkOrphan),
+            PREFIX_CHUNK_LAYER => Ok(Self::ChunkLayer),
+            PREFIX_LAZY_DIR => Ok(Self::LazyDir),
+            PREFIX_XATTR => Ok(Self::Xattr),
+            PREFIX_DATASET_STATS => Ok(Self::DatasetStats),
+            PREFIX_OWNER_STATS => Ok(Self::OwnerStats),
+            PREFIX_CHUNK_FOOTPRINT => Ok(Self::ChunkFootprint),
+            PREFIX_SPACE_STATS => Ok(Self::SpaceStats),
+            PREFIX_DATASET_DESTROY => Ok(Self::DatasetDestroy),
+            PREFIX_LINK_DIR => Ok(Self::LinkDir),
+            PREFIX_CASE_FOLD => Ok(Self::CaseFold),
+            PREFIX_CHUNK_CAS => Ok(Self::ChunkCas),
             _ => Err(()),
         }
     }
@@ -88,6 +171,19 @@ impl From<KeyPrefix> for u8 {
             KeyPrefix::DirCookie => PREFIX_DIR_COOKIE,
             KeyPrefix::Dataset => PREFIX_DATASET,
             KeyPrefix::DatasetRegistry => PREFIX_DATASET_REGISTRY,
+            KeyPrefix::ChunkRefCount => PREFIX_CHUNK_REFCOUNT,
+            KeyPrefix::ChunkOrphan => PREFIX_CHUNK_ORPHAN,
+            KeyPrefix::ChunkLayer => PREFIX_CHUNK_LAYER,
+            KeyPrefix::LazyDir => PREFIX_LAZY_DIR,
+            KeyPrefix::Xattr => PREFIX_XATTR,
+            KeyPrefix::DatasetStats => PREFIX_DATASET_STATS,
+            KeyPrefix::OwnerStats => PREFIX_OWNER_STATS,
+            KeyPrefix::ChunkFootprint => PREFIX_CHUNK_FOOTPRINT,
+            KeyPrefix::SpaceStats => PREFIX_SPACE_STATS,
+            KeyPrefix::DatasetDestroy => PREFIX_DATASET_DESTROY,
+            KeyPrefix::LinkDir => PREFIX_LINK_DIR,
+            KeyPrefix::CaseFold => PREFIX_CASE_FOLD,
+            KeyPrefix::ChunkCas => PREFIX_CHUNK_CAS,
         }
     }
 }
@@ -105,6 +201,19 @@ impl KeyPrefix {
             Self::DirCookie => "DIR_COOKIE",
             Self::Dataset => "DATASET",
             Self::DatasetRegistry => "DATASET_REGISTRY",
+            Self::ChunkRefCount => "CHUNK_REFCOUNT",
+            Self::ChunkOrphan => "CHUNK_ORPHAN",
+            Self::ChunkLayer => "CHUNK_LAYER",
+            Self::LazyDir => "LAZY_DIR",
+            Self::Xattr => "XATTR",
+            Self::DatasetStats => "DATASET_STATS",
+            Self::OwnerStats => "OWNER_STATS",
+            Self::ChunkFootprint => "CHUNK_FOOTPRINT",
+            Self::SpaceStats => "SPACE_STATS",
+            Self::DatasetDestroy => "DATASET_DESTROY",
+            Self::LinkDir => "LINK_DIR",
+            Self::CaseFold => "CASE_FOLD",
+            Self::ChunkCas => "CHUNK_CAS",
         }
     }
 }
@@ -142,6 +251,120 @@ impl KeyCodec {
         Some(u64::from_be_bytes(chunk_bytes))
     }
 
+    /// Key of a content-addressed chunk body
+    pub fn chunk_cas_key(hash: &ChunkHash) -> Bytes {
+        Self::chunk_hash_key(KeyPrefix::ChunkCas, hash)
+    }
+
+    /// Key holding the number of chunk map entries referencing a chunk body
+    pub fn chunk_refcount_key(hash: &ChunkHash) -> Bytes {
+        Self::chunk_hash_key(KeyPrefix::ChunkRefCount, hash)
+    }
+
+    /// Key marking a chunk body with no remaining references
+    pub fn chunk_orphan_key(hash: &ChunkHash) -> Bytes {
+        Self::chunk_hash_key(KeyPrefix::ChunkOrphan, hash)
+    }
+
+    /// Key holding the stored size of a chunk body and the dataset it is
+    /// accounted to
+    pub fn chunk_footprint_key(hash: &ChunkHash) -> Bytes {
+        Self::chunk_hash_key(KeyPrefix::ChunkFootprint, hash)
+    }
+
+    pub fn parse_chunk_orphan_key(key: &[u8]) -> Option<ChunkHash> {
+        if key.len() != KEY_CHUNK_HASH_SIZE || key[0] != PREFIX_CHUNK_ORPHAN {
+            return None;
+        }
+        key[1..].try_into().ok()
+    }
+
+    fn chunk_hash_key(prefix: KeyPrefix, hash: &ChunkHash) -> Bytes {
+        let mut key = Vec::with_capacity(KEY_CHUNK_HASH_SIZE);
+        key.push(u8::from(prefix));
+        key.extend_from_slice(hash);
+        Bytes::from(key)
+    }
+
+    /// Encode a chunk map value pointing at a content-addressed chunk
+    pub fn encode_chunk_ref(hash: &ChunkHash) -> Bytes {
+        let mut value = Vec::with_capacity(CHUNK_REF_SIZE);
+        value.push(CHUNK_REF_MARKER);
+        value.extend_from_slice(hash);
+        Bytes::from(value)
+    }
+
+    /// Decode a chunk map value. Returns `None` for chunk data stored inline.
+    pub fn decode_chunk_ref(data: &[u8]) -> Option<ChunkHash> {
+        if data.len() != CHUNK_REF_SIZE || data[0] != CHUNK_REF_MARKER {
+            return None;
+        }
+        data[1..].try_into().ok()
+    }
+
+    pub fn chunk_hole() -> Bytes {
+        Bytes::from_static(CHUNK_HOLE)
+    }
+
+    pub fn is_chunk_hole(data: &[u8]) -> bool {
+        data == CHUNK_HOLE
+    }
+
+    /// Key of the record tracking a chunk map shared as a backing layer
+    pub fn chunk_layer_key(map_id: InodeId) -> Bytes {
+        let mut key = Vec::with_capacity(KEY_INODE_SIZE);
+        key.push(u8::from(KeyPrefix::ChunkLayer));
+        key.extend_from_slice(&map_id.to_be_bytes());
+        Bytes::from(key)
+    }
+
+    /// Encode a backing layer record: reference count, and the size of the
+    /// data the layer holds and of its chunks.
+    pub fn encode_chunk_layer(refs: u64, size: u64, chunk_size: u32) -> Bytes {
+        let mut value = Vec::with_capacity(U64_SIZE * 2 + U32_SIZE);
+        value.extend_from_slice(&refs.to_le_bytes());
+        value.extend_from_slice(&size.to_le_bytes());
+        value.extend_from_slice(&chunk_size.to_le_bytes());
+        Bytes::from(value)
+    }
+
+    /// Decode a backing layer record. Records written before the chunk size
+    /// was stored use the original chunk size.
+    pub fn decode_chunk_layer(data: &[u8]) -> Result<(u64, u64, u32), FsError> {
+        if data.len() < U64_SIZE * 2 {
+            return Err(FsError::InvalidData);
+        }
+        let refs = Self::decode_counter(&data[..U64_SIZE])?;
+        let size = Self::decode_counter(&data[U64_SIZE..U64_SIZE * 2])?;
+        let chunk_size = Self::decode_chunk_size(&data[U64_SIZE * 2..])?;
+        Ok((refs, size, chunk_size))
+    }
+
+    /// Key marking a cloned directory whose entries have not been copied from
+    /// its origin yet. The value is the origin directory id.
+    pub fn lazy_dir_key(dir_id: InodeId) -> Bytes {
+        let mut key = Vec::with_capacity(KEY_INODE_SIZE);
+        key.push(u8::from(KeyPrefix::LazyDir));
+        key.extend_from_slice(&dir_id.to_be_bytes());
+        Bytes::from(key)
+    }
+
+    pub fn parse_lazy_dir_key(key: &[u8]) -> Option<InodeId> {
+        if key.len() != KEY_INODE_SIZE || key[0] != PREFIX_LAZY_DIR {
+            return None;
+        }
+        let id_bytes: [u8; U64_SIZE] = key[1..].try_into().ok()?;
+        Some(u64::from_be_bytes(id_bytes))
+    }
+
+    /// Key holding the extended attributes of an inode
+    pub fn xattr_key(inode_id: InodeId) -> Bytes {
+        let mut key = Vec::with_capacity(KEY_INODE_SIZE);
+        key.push(u8::from(KeyPrefix::Xattr));
+        key.extend_from_slice(&inode_id.to_be_bytes());
+        Bytes::from(key)
+    }
+
     pub fn dir_entry_key(dir_id: InodeId, name: &[u8]) -> Bytes {
         let mut key = Vec::with_capacity(KEY_INODE_SIZE + name.len());
         key.push(u8::from(KeyPrefix::DirEntry));
@@ -203,10 +426,92 @@ impl KeyCodec {
         Bytes::from(key)
     }
 
+    /// Key of one shard of the usage of a dataset
+    pub fn dataset_stats_key(dataset_id: DatasetId, shard_id: usize) -> Bytes {
+        let mut key = Vec::with_capacity(KEY_DATASET_STATS_SIZE);
+        key.push(u8::from(KeyPrefix::DatasetStats));
+        key.extend_from_slice(&dataset_id.to_be_bytes());
+        key.extend_from_slice(&(shard_id as u64).to_be_bytes());
+        Bytes::from(key)
+    }
+
+    pub fn parse_dataset_stats_key(key: &[u8]) -> Option<(DatasetId, usize)> {
+        if key.len() != KEY_DATASET_STATS_SIZE || key[0] != PREFIX_DATASET_STATS {
+            return None;
+        }
+        let dataset_bytes: [u8; U64_SIZE] = key[1..KEY_INODE_SIZE].try_into().ok()?;
+        let shard_bytes: [u8; U64_SIZE] = key[KEY_INODE_SIZE..].try_into().ok()?;
+        Some((
+            u64::from_be_bytes(dataset_bytes),
+            u64::from_be_bytes(shard_bytes) as usize,
+        ))
+    }
+
+    pub fn owner_stats_key(owner: Owner, shard_id: usize) -> Bytes {
+        let (kind, id) = match owner {
+            Owner::User(uid) => (OWNER_USER, uid),
+            Owner::Group(gid) => (OWNER_GROUP, gid),
+        };
+        let mut key = Vec::with_capacity(KEY_OWNER_STATS_SIZE);
+        key.push(u8::from(KeyPrefix::OwnerStats));
+        key.push(kind);
+        key.extend_from_slice(&id.to_be_bytes());
+        key.extend_from_slice(&(shard_id as u64).to_be_bytes());
+        Bytes::from(key)
+    }
+
+    pub fn parse_owner_stats_key(key: &[u8]) -> Option<(Owner, usize)> {
+        if key.len() != KEY_OWNER_STATS_SIZE || key[0] != PREFIX_OWNER_STATS {
+            return None;
+        }
+        let id = u32::from_be_bytes(key[2..2 + U32_SIZE].try_into().ok()?);
+        let shard_bytes: [u8; U64_SIZE] = key[2 + U32_SIZE..].try_into().ok()?;
+        let owner = match key[1] {
+            OWNER_USER => Owner::User(id),
+            OWNER_GROUP => Owner::Group(id),
+            _ => return None,
+        };
+        Some((owner, u64::from_be_bytes(shard_bytes) as usize))
+    }
+
+    /// Key of the stored size of the chunk bodies accounted to a dataset
+    pub fn space_stats_key(dataset_id: DatasetId) -> Bytes {
+        let mut key = Vec::with_capacity(KEY_INODE_SIZE);
+        key.push(u8::from(KeyPrefix::SpaceStats));
+        key.extend_from_slice(&dataset_id.to_be_bytes());
+        Bytes::from(key)
+    }
+
+    pub fn parse_space_stats_key(key: &[u8]) -> Option<DatasetId> {
+        if key.len() != KEY_INODE_SIZE || key[0] != PREFIX_SPACE_STATS {
+            return None;
+        }
+        let id_bytes: [u8; U64_SIZE] = key[1..].try_into().ok()?;
+        Some(u64::from_be_bytes(id_bytes))
+    }
+
     pub fn system_counter_key() -> Bytes {
         Bytes::from(vec![u8::from(KeyPrefix::System), SYSTEM_COUNTER_SUBTYPE])
     }
 
+    /// Progress of the background re-encryption of stored values
+    pub fn system_reencryption_key() -> Bytes {
+        Bytes::from(vec![
+            u8::from(KeyPrefix::System),
+            SYSTEM_REENCRYPTION_SUBTYPE,
+        ])
+    }
+
+    /// Present once any inode has had an ACL
+    pub fn system_acls_key() -> Bytes {
+        Bytes::from(vec![u8::from(KeyPrefix::System), SYSTEM_ACLS_SUBTYPE])
+    }
+
+    /// Limits on the usage of uids and gids
+    pub fn system_quotas_key() -> Bytes {
+        Bytes::from(vec![u8::from(KeyPrefix::System), SYSTEM_QUOTAS_SUBTYPE])
+    }
+
     pub fn parse_key(key: &[u8]) -> ParsedKey {
         let prefix = match key.first().and_then(|&b| KeyPrefix::try_from(b).ok()) {
             Some(p) => p,
@@ -270,16 +575,76 @@ impl KeyCodec {
         ))
     }
 
-    pub fn encode_tombstone_size(size: u64) -> Bytes {
-        Bytes::copy_from_slice(&size.to_le_bytes())
+    /// Encode a tombstone: the size of the data left to delete and the chunk
+    /// size it is stored with.
+    pub fn encode_tombstone(size: u64, chunk_size: u32) -> Bytes {
+        let mut value = Vec::with_capacity(U64_SIZE + U32_SIZE);
+        value.extend_from_slice(&size.to_le_bytes());
+        value.extend_from_slice(&chunk_size.to_le_bytes());
+        Bytes::from(value)
     }
 
-    pub fn decode_tombstone_size(data: &[u8]) -> Result<u64, FsError> {
-        if data.len() != U64_SIZE {
+    /// Decode a tombstone. Tombstones written before the chunk size was
+    /// stored use the original chunk size.
+    pub fn decode_tombstone(data: &[u8]) -> Result<(u64, u32), FsError> {
+        if data.len() < U64_SIZE {
             return Err(FsError::InvalidData);
         }
-        let bytes: [u8; U64_SIZE] = data.try_into().map_err(|_| FsError::InvalidData)?;
-        Ok(u64::from_le_bytes(bytes))
+        let bytes: [u8; U64_SIZE] = data[..U64_SIZE]
+            .try_into()
+            .map_err(|_| FsError::InvalidData)?;
+        let chunk_size = Self::decode_chunk_size(&data[U64_SIZE..])?;
+        Ok((u64::from_le_bytes(bytes), chunk_size))
+    }
+
+    /// Encode the sizes of stored chunk bodies: before compression, after
+    /// compression, and as written.
+    pub fn encode_space_usage(usage: &SpaceUsage) -> Bytes {
+        let mut value = Vec::with_capacity(U64_SIZE * 3);
+        value.extend_from_slice(&usage.logical.to_le_bytes());
+        value.extend_from_slice(&usage.compressed.to_le_bytes());
+        value.extend_from_slice(&usage.stored.to_le_bytes());
+        Bytes::from(value)
+    }
+
+    pub fn decode_space_usage(data: &[u8]) -> Result<SpaceUsage, FsError> {
+        if data.len() != U64_SIZE * 3 {
+            return Err(FsError::InvalidData);
+        }
+        Ok(SpaceUsage {
+            logical: Self::decode_counter(&data[..U64_SIZE])?,
+            compressed: Self::decode_counter(&data[U64_SIZE..U64_SIZE * 2])?,
+            stored: Self::decode_counter(&data[U64_SIZE * 2..])?,
+        })
+    }
+
+    /// Encode the footprint of a chunk body: the dataset it is accounted to,
+    /// followed by its sizes.
+    pub fn encode_chunk_footprint(dataset_id: DatasetId, usage: &SpaceUsage) -> Bytes {
+        let mut value = Vec::with_capacity(U64_SIZE * 4);
+        value.extend_from_slice(&dataset_id.to_le_bytes());
+        value.extend_from_slice(&Self::encode_space_usage(usage));
+        Bytes::from(value)
+    }
+
+    pub fn decode_chunk_footprint(data: &[u8]) -> Result<(DatasetId, SpaceUsage), FsError> {
+        if data.len() < U64_SIZE {
+            return Err(FsError::InvalidData);
+        }
+        let dataset_id = Self::decode_counter(&data[..U64_SIZE])?;
+        Ok((dataset_id, Self::decode_space_usage(&data[U64_SIZE..])?))
+    }
+
+    /// Decode an optional trailing chunk size
+    fn decode_chunk_size(data: &[u8]) -> Result<u32, FsError> {
+        match data.len() {
+            0 => Ok(CHUNK_SIZE as u32),
+            U32_SIZE => {
+                let bytes: [u8; U32_SIZE] = data.try_into().map_err(|_| FsError::InvalidData)?;
+                Ok(u32::from_le_bytes(bytes))
+            }
+            _ => Err(FsError::InvalidData),
+        }
     }
 
     pub fn prefix_range(prefix: KeyPrefix) -> (Bytes, Bytes) {
@@ -301,6 +666,95 @@ impl KeyCodec {
         key.extend_from_slice(&dataset_id.to_be_bytes());
         Bytes::from(key)
     }
+
+    /// Key holding the reclamation progress of the detached tree rooted at
+    /// `root_inode`
+    pub fn dataset_destroy_key(root_inode: InodeId) -> Bytes {
+        let mut key = Vec::with_capacity(KEY_INODE_SIZE);
+        key.push(u8::from(KeyPrefix::DatasetDestroy));
+        key.extend_from_slice(&root_inode.to_be_bytes());
+        Bytes::from(key)
+    }
+
+    pub fn parse_dataset_destroy_key(key: &[u8]) -> Option<InodeId> {
+        if key.len() != KEY_INODE_SIZE || key[0] != PREFIX_DATASET_DESTROY {
+            return None;
+        }
+        let id_bytes: [u8; U64_SIZE] = key[1..].try_into().ok()?;
+        Some(u64::from_be_bytes(id_bytes))
+    }
+
+    /// Key recording that directory `dir_id` holds link `name` to
+    /// `inode_id`, an inode with several links and so no parent of its own
+    pub fn link_dir_key(inode_id: InodeId, dir_id: InodeId, name: &[u8]) -> Bytes {
+        let mut key = Vec::with_capacity(KEY_CHUNK_SIZE + name.len());
+        key.push(u8::from(KeyPrefix::LinkDir));
+        key.extend_from_slice(&inode_id.to_be_bytes());
+        key.extend_from_slice(&dir_id.to_be_bytes());
+        key.extend_from_slice(name);
+        Bytes::from(key)
+    }
+
+    /// Range of the `link_dir_key`s of `inode_id`
+    pub fn link_dir_range(inode_id: InodeId) -> (Bytes, Bytes) {
+        let mut start = Vec::with_capacity(KEY_INODE_SIZE);
+        start.push(u8::from(KeyPrefix::LinkDir));
+        start.extend_from_slice(&inode_id.to_be_bytes());
+        let mut end = Vec::with_capacity(KEY_INODE_SIZE);
+        end.push(u8::from(KeyPrefix::LinkDir));
+        end.extend_from_slice(&(inode_id + 1).to_be_bytes());
+        (Bytes::from(start), Bytes::from(end))
+    }
+
+    /// Key recording that directory `dir_id` has an entry `name`, whose
+    /// case-folded form is `folded`. Names cannot hold a NUL byte, which
+    /// separates the two.
+    pub fn case_fold_key(dir_id: InodeId, folded: &[u8], name: &[u8]) -> Bytes {
+        let mut key = Self::case_fold_prefix(dir_id, folded);
+        key.extend_from_slice(name);
+        Bytes::from(key)
+    }
+
+    /// Range of the `case_fold_key`s of the names of directory `dir_id`
+    /// folding to `folded`
+    pub fn case_fold_range(dir_id: InodeId, folded: &[u8]) -> (Bytes, Bytes) {
+        let start = Self::case_fold_prefix(dir_id, folded);
+        let mut end = start.clone();
+        *end.last_mut().unwrap() = 1;
+        (Bytes::from(start), Bytes::from(end))
+    }
+
+    fn case_fold_prefix(dir_id: InodeId, folded: &[u8]) -> Vec<u8> {
+        let mut key = Vec::with_capacity(KEY_INODE_SIZE + folded.len() * 2 + 1);
+        key.push(u8::from(KeyPrefix::CaseFold));
+        key.extend_from_slice(&dir_id.to_be_bytes());
+        key.extend_from_slice(folded);
+        key.push(0);
+        key
+    }
+
+    /// Entry name of a `case_fold_key`
+    pub fn parse_case_fold_key(key: &[u8]) -> Option<&[u8]> {
+        if key.len() <= KEY_INODE_SIZE || key[0] != PREFIX_CASE_FOLD {
+            return None;
+        }
+        let separator = key[KEY_INODE_SIZE..].iter().position(|&b| b == 0)?;
+        Some(&key[KEY_INODE_SIZE + separator + 1..])
+    }
+
+    /// Inode, directory and name of a `link_dir_key`
+    pub fn parse_link_dir_key(key: &[u8]) -> Option<(InodeId, InodeId, &[u8])> {
+        if key.len() <= KEY_CHUNK_SIZE || key[0] != PREFIX_LINK_DIR {
+            return None;
+        }
+        let inode_bytes: [u8; U64_SIZE] = key[1..KEY_INODE_SIZE].try_into().ok()?;
+        let dir_bytes: [u8; U64_SIZE] = key[KEY_INODE_SIZE..KEY_CHUNK_SIZE].try_into().ok()?;
+        Some((
+            u64::from_be_bytes(inode_bytes),
+            u64::from_be_bytes(dir_bytes),
+            &key[KEY_CHUNK_SIZE..],
+        ))
+    }
 }
 
 #[cfg(test)]
@@ -355,11 +809,158 @@ mod tests {
         assert_eq!(decoded_id, inode_id);
         assert_eq!(decoded_cookie, cookie);
 
-        // Test tombstone size encoding
+        // Test tombstone encoding
         let size = 1024u64;
-        let encoded = KeyCodec::encode_tombstone_size(size);
-        let decoded = KeyCodec::decode_tombstone_size(&encoded).unwrap();
-        assert_eq!(decoded, size);
+        let encoded = KeyCodec::encode_tombstone(size, 256 * 1024);
+        let decoded = KeyCodec::decode_tombstone(&encoded).unwrap();
+        assert_eq!(decoded, (size, 256 * 1024));
+
+        // Tombstones without a chunk size use the original one
+        let decoded = KeyCodec::decode_tombstone(&encoded[..U64_SIZE]).unwrap();
+        assert_eq!(decoded, (size, CHUNK_SIZE as u32));
+    }
+
+    #[test]
+    fn test_chunk_ref_encoding() {
+        let hash = [7u8; CHUNK_HASH_SIZE];
+        let encoded = KeyCodec::encode_chunk_ref(&hash);
+        assert_eq!(KeyCodec::decode_chunk_ref(&encoded), Some(hash));
+
+        // Inline chunk data is never mistaken for a reference
+        assert_eq!(KeyCodec::decode_chunk_ref(&[0u8; 32 * 1024]), None);
+        let mut inline = vec![0u8; 32 * 1024];
+        inline[0] = CHUNK_REF_MARKER;
+        assert_eq!(KeyCodec::decode_chunk_ref(&inline), None);
+    }
+
+    #[test]
+    fn test_chunk_hash_keys() {
+        let hash = [3u8; CHUNK_HASH_SIZE];
+        let cas_key = KeyCodec::chunk_cas_key(&hash);
+        let refcount_key = KeyCodec::chunk_refcount_key(&hash);
+        let orphan_key = KeyCodec::chunk_orphan_key(&hash);
+
+        assert_eq!(KeyPrefix::try_from(cas_key[0]), Ok(KeyPrefix::ChunkCas));
+        assert_eq!(
+            KeyPrefix::try_from(refcount_key[0]),
+            Ok(KeyPrefix::ChunkRefCount)
+        );
+        assert_eq!(KeyCodec::parse_chunk_orphan_key(&orphan_key), Some(hash));
+        assert_eq!(KeyCodec::parse_chunk_orphan_key(&refcount_key), None);
+    }
+
+    #[test]
+    fn test_chunk_layer_encoding() {
+        let value = KeyCodec::encode_chunk_layer(2, 1 << 20, 64 * 1024);
+        assert_eq!(
+            KeyCodec::decode_chunk_layer(&value).unwrap(),
+            (2, 1 << 20, 64 * 1024)
+        );
+        assert_eq!(
+            KeyCodec::decode_chunk_layer(&value[..16]).unwrap(),
+            (2, 1 << 20, CHUNK_SIZE as u32)
+        );
+        assert!(KeyCodec::decode_chunk_layer(&value[..8]).is_err());
+        assert!(KeyCodec::decode_chunk_layer(&value[..18]).is_err());
+
+        let hole = KeyCodec::chunk_hole();
+        assert!(KeyCodec::is_chunk_hole(&hole));
+        assert_eq!(KeyCodec::decode_chunk_ref(&hole), None);
+        assert!(!KeyCodec::is_chunk_hole(&KeyCodec::encode_chunk_ref(
+            &[0u8; 32]
+        )));
+    }
+
+    #[test]
+    fn test_lazy_dir_key_parsing() {
+        let key = KeyCodec::lazy_dir_key(42);
+        assert_eq!(KeyCodec::parse_lazy_dir_key(&key), Some(42));
+        assert_eq!(KeyCodec::parse_lazy_dir_key(&KeyCodec::inode_key(42)), None);
+        assert_eq!(KeyCodec::parse_lazy_dir_key(&key[..5]), None);
+    }
+
+    #[test]
+    fn test_link_dir_key_parsing() {
+        let key = KeyCodec::link_dir_key(42, 7, b"a.txt");
+        assert_eq!(
+            KeyCodec::parse_link_dir_key(&key),
+            Some((42, 7, b"a.txt".as_slice()))
+        );
+        assert_eq!(KeyCodec::parse_link_dir_key(&key[..17]), None);
+        assert_eq!(
+            KeyCodec::parse_link_dir_key(&KeyCodec::dir_entry_key(42, b"a.txt")),
+            None
+        );
+
+        let (start, end) = KeyCodec::link_dir_range(42);
+        assert!(start < key && key < end);
+        assert!(KeyCodec::link_dir_key(43, 0, b"a") >= end);
+    }
+
+    #[test]
+    fn test_case_fold_key_parsing() {
+        let key = KeyCodec::case_fold_key(7, b"readme", b"README");
+        assert_eq!(
+            KeyCodec::parse_case_fold_key(&key),
+            Some(b"README".as_slice())
+        );
+        assert_eq!(KeyCodec::parse_case_fold_key(&key[..12]), None);
+
+        let (start, end) = KeyCodec::case_fold_range(7, b"readme");
+        assert!(start < key && key < end);
+        assert!(KeyCodec::case_fold_key(7, b"readme2", b"README2") >= end);
+        assert!(KeyCodec::case_fold_key(7, b"readm", b"READM") < start);
+    }
+
+    #[test]
+    fn test_dataset_stats_key_parsing() {
+        let key = KeyCodec::dataset_stats_key(3, 42);
+        assert_eq!(KeyCodec::parse_dataset_stats_key(&key), Some((3, 42)));
+        assert_eq!(
+            KeyCodec::parse_dataset_stats_key(&KeyCodec::stats_shard_key(42)),
+            None
+        );
+
+        // Shards of a dataset are adjacent, ahead of the next dataset's
+        assert!(KeyCodec::dataset_stats_key(3, 99) < KeyCodec::dataset_stats_key(4, 0));
+    }
+
+    #[test]
+    fn test_space_usage_encoding() {
+        let usage = SpaceUsage {
+            logical: 32 * 1024,
+            compressed: 1200,
+            stored: 1241,
+        };
+        let encoded = KeyCodec::encode_chunk_footprint(3, &usage);
+        assert_eq!(
+            KeyCodec::decode_chunk_footprint(&encoded).unwrap(),
+            (3, usage)
+        );
+        assert!(KeyCodec::decode_chunk_footprint(&encoded[..20]).is_err());
+
+        let key = KeyCodec::space_stats_key(3);
+        assert_eq!(KeyCodec::parse_space_stats_key(&key), Some(3));
+        assert_eq!(
+            KeyCodec::parse_space_stats_key(&KeyCodec::dataset_key(3)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_owner_stats_key_parsing() {
+        for owner in [Owner::User(1000), Owner::Group(1000), Owner::User(u32::MAX)] {
+            let key = KeyCodec::owner_stats_key(owner, 7);
+            assert_eq!(KeyCodec::parse_owner_stats_key(&key), Some((owner, 7)));
+        }
+        assert_ne!(
+            KeyCodec::owner_stats_key(Owner::User(5), 0),
+            KeyCodec::owner_stats_key(Owner::Group(5), 0)
+        );
+        assert_eq!(
+            KeyCodec::parse_owner_stats_key(&KeyCodec::dataset_stats_key(3, 42)),
+            None
+        );
     }
 
     #[test]
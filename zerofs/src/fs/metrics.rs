@@ -1,3 +1,4 @@
+use crate::fs::stats::SpaceUsage;
 use comfy_table::{Attribute, Cell, Color, ContentArrangement, Table};
 use num_format::{Locale, ToFormattedString};
 use std::sync::atomic::{AtomicU64, Ordering};
@@ -36,6 +37,7 @@ pub struct FileSystemStats {
     pub tombstones_created: AtomicU64,
     pub tombstones_processed: AtomicU64,
     pub gc_chunks_deleted: AtomicU64,
+    pub gc_orphan_chunks_collected: AtomicU64,
     pub gc_runs: AtomicU64,
 
     // Performance
@@ -70,6 +72,7 @@ impl FileSystemStats {
             tombstones_created: AtomicU64::new(0),
             tombstones_processed: AtomicU64::new(0),
             gc_chunks_deleted: AtomicU64::new(0),
+            gc_orphan_chunks_collected: AtomicU64::new(0),
             gc_runs: AtomicU64::new(0),
             total_operations: AtomicU64::new(0),
             last_snapshot: std::sync::Mutex::new(PreviousSnapshot {
@@ -83,7 +86,9 @@ impl FileSystemStats {
         }
     }
 
-    pub fn report(&self) -> String {
+    /// Render the counters as a table. `space` is the space taken by the
+    /// chunk bodies of the whole filesystem.
+    pub fn report(&self, space: SpaceUsage) -> String {
         // Load current values
         let files_created = self.files_created.load(Ordering::Relaxed);
         let files_deleted = self.files_deleted.load(Ordering::Relaxed);
@@ -103,6 +108,7 @@ impl FileSystemStats {
         let tombstones_created = self.tombstones_created.load(Ordering::Relaxed);
         let tombstones_processed = self.tombstones_processed.load(Ordering::Relaxed);
         let gc_chunks = self.gc_chunks_deleted.load(Ordering::Relaxed);
+        let gc_orphans = self.gc_orphan_chunks_collected.load(Ordering::Relaxed);
         let gc_runs = self.gc_runs.load(Ordering::Relaxed);
 
         let total_ops = self.total_operations.load(Ordering::Relaxed);
@@ -246,11 +252,49 @@ impl FileSystemStats {
                 gc_runs.to_formatted_string(&Locale::en)
             )),
         ]);
+        table.add_row(vec![
+            Cell::new("  Shared chunks freed"),
+            Cell::new(gc_orphans.to_formatted_string(&Locale::en)),
+        ]);
+
+        let ratio = if space.compressed > 0 {
+            space.logical as f64 / space.compressed as f64
+        } else {
+            1.0
+        };
+
+        table.add_row(vec![
+            Cell::new("Storage (total)")
+                .fg(Color::Yellow)
+                .add_attribute(Attribute::Bold),
+            Cell::new(""),
+        ]);
+        table.add_row(vec![
+            Cell::new("  Logical"),
+            Cell::new(format!(
+                "{} bytes",
+                space.logical.to_formatted_string(&Locale::en)
+            )),
+        ]);
+        table.add_row(vec![
+            Cell::new("  Compressed"),
+            Cell::new(format!(
+                "{} bytes ({ratio:.2}x)",
+                space.compressed.to_formatted_string(&Locale::en)
+            )),
+        ]);
+        table.add_row(vec![
+            Cell::new("  Stored"),
+            Cell::new(format!(
+                "{} bytes (after encryption)",
+                space.stored.to_formatted_string(&Locale::en)
+            )),
+        ]);
 
         table.to_string()
     }
 
-    pub fn output_report_debug(&self) {
-        tracing::debug!("\n{}", self.report());
+    pub fn output_report_debug(&self, space: SpaceUsage) {
+        tracing::debug!("\n{}", self.report(space));
     }
 }
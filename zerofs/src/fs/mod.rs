@@ -1,5 +1,9 @@
+pub mod acl;
 pub mod constants;
 pub mod dataset;
+pub mod dataset_ops;
+pub mod destroy;
+pub mod diff;
 pub mod errors;
 pub mod flush_coordinator;
 pub mod gc;
@@ -8,6 +12,12 @@ pub mod key_codec;
 pub mod lock_manager;
 pub mod metrics;
 pub mod permissions;
+pub mod properties;
+pub mod quota;
+pub mod reencrypt;
+pub mod restore;
+pub mod retention;
+pub mod send;
 pub mod clone;
 pub mod snapshot_vfs;
 pub mod stats;
@@ -16,17 +26,21 @@ pub mod tracing;
 pub mod types;
 pub mod write_coordinator;
 
+use self::destroy::DetachedTrees;
 use self::flush_coordinator::FlushCoordinator;
-use self::key_codec::KeyCodec;
+use self::key_codec::{KeyCodec, KeyPrefix};
 use self::lock_manager::LockManager;
 use self::metrics::FileSystemStats;
 use self::snapshot_vfs::SnapshotVfs;
-use self::stats::{FileSystemGlobalStats, StatsShardData};
-use self::store::{ChunkStore, DatasetStore, DirectoryStore, InodeStore, TombstoneStore};
+use self::stats::{FileSystemGlobalStats, InodeAccount, StatsShardData, UsageScope};
+use self::store::{
+    ChunkLayout, ChunkStore, DatasetStore, DirectoryStore, InodeStore, LazyDirStore, QuotaStore,
+    TombstoneStore, XattrStore,
+};
 use self::tracing::{AccessTracer, FileOperation};
 use self::write_coordinator::WriteCoordinator;
 use crate::config::CompressionConfig;
-use crate::encryption::{EncryptedDb, EncryptedTransaction, EncryptionManager};
+use crate::encryption::{DataKeys, EncryptedDb, EncryptedTransaction, EncryptionManager};
 use slatedb::config::{PutOptions, WriteOptions};
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -36,18 +50,25 @@ use crate::failpoints as fp;
 #[cfg(feature = "failpoints")]
 use fp::fail_point;
 
+pub use self::destroy::DatasetReclaimer;
 pub use self::gc::GarbageCollector;
+pub use self::reencrypt::Reencryptor;
+pub use self::retention::SnapshotScheduler;
 pub use self::write_coordinator::SequenceGuard;
 
+use self::acl::{ACL_ACCESS_XATTR, ACL_DEFAULT_XATTR, is_acl_xattr, set_acl, stored_acl};
 use self::errors::FsError;
 use self::inode::{
     DirectoryInode, FileInode, Inode, InodeAttrs, InodeId, SpecialInode, SymlinkInode,
 };
 use self::permissions::{
-    AccessMode, Credentials, can_set_times, check_access, check_ownership, check_sticky_bit_delete,
-    validate_mode,
+    AccessMode, Credentials, can_list_xattr, can_set_times, check_access, check_access_acl,
+    check_ownership, check_sticky_bit_delete, check_xattr_access, validate_mode,
 };
+use self::properties::{AtimeMode, CaseSensitivity};
 use self::store::inode::MAX_HARDLINKS_PER_INODE;
+use self::store::lazy_dir::Alias;
+use self::store::xattr::{XATTR_NAME_MAX, XATTR_SIZE_MAX, Xattrs};
 use self::types::{
     AuthContext, DirEntry, FileAttributes, FileType, InodeWithId, ReadDirResult, SetAttributes,
     SetGid, SetMode, SetSize, SetTime, SetUid,
@@ -75,10 +96,16 @@ pub fn get_current_time() -> (u64, u32) {
 }
 
 pub const CHUNK_SIZE: usize = 32 * 1024; // 32KB chunks - matches existing data
+/// Largest chunk size a file can be created with (`filesystem.chunk_size_kb`)
+pub const MAX_CHUNK_SIZE: usize = 1024 * 1024;
 pub const STATS_SHARDS: usize = 100;
 pub const SMALL_FILE_TOMBSTONE_THRESHOLD: usize = 10;
 pub const NAME_MAX: usize = 255;
 pub const ROOT_INODE_ID: InodeId = 0;
+/// `setxattr` flag: fail if the attribute already exists
+pub const XATTR_CREATE: u32 = 1;
+/// `setxattr` flag: fail if the attribute does not exist
+pub const XATTR_REPLACE: u32 = 2;
 
 pub fn validate_filename(filename: &[u8]) -> Result<(), FsError> {
     if filename.len() > NAME_MAX {
@@ -94,6 +121,10 @@ pub struct ZeroFS {
     pub directory_store: DirectoryStore,
     pub inode_store: InodeStore,
     pub tombstone_store: TombstoneStore,
+    pub lazy_dirs: LazyDirStore,
+    pub detached_trees: DetachedTrees,
+    pub xattr_store: XattrStore,
+    pub quota_store: QuotaStore,
     pub dataset_store: Arc<DatasetStore>,
     pub snapshot_vfs: Arc<SnapshotVfs>,
     pub lock_manager: Arc<LockManager>,
@@ -120,18 +151,18 @@ impl ZeroFS {
         max_bytes: u64,
         compression: CompressionConfig,
     ) -> anyhow::Result<Self> {
-        Self::new_with_slatedb_and_writeback(slatedb, encryption_key, max_bytes, compression, None, None).await
+        Self::new_with_slatedb_and_writeback(slatedb, DataKeys::new(encryption_key), max_bytes, compression, None, None).await
     }
 
     pub async fn new_with_slatedb_and_writeback(
         slatedb: crate::encryption::SlateDbHandle,
-        encryption_key: [u8; 32],
+        data_keys: DataKeys,
         max_bytes: u64,
         compression: CompressionConfig,
         writeback_config: Option<crate::writeback_cache::WritebackCacheConfig>,
         metadata_cache_config: Option<crate::config::MetadataCacheConfig>,
     ) -> anyhow::Result<Self> {
-        let encryptor = Arc::new(EncryptionManager::new(&encryption_key, compression));
+        let encryptor = Arc::new(EncryptionManager::with_keys(&data_keys, compression));
 
         let lock_manager = Arc::new(LockManager::new());
 
@@ -142,6 +173,10 @@ impl ZeroFS {
             }
         });
 
+        if reencrypt::ReencryptionState::load(&db).await?.unbound_cleared {
+            db.reject_unbound_values();
+        }
+
         let counter_key = KeyCodec::system_counter_key();
         let next_inode_id = match db.get_bytes(&counter_key).await? {
             Some(data) => KeyCodec::decode_counter(&data)?,
@@ -196,6 +231,46 @@ impl ZeroFS {
             }
         }
 
+        {
+            let (start, end) = KeyCodec::prefix_range(KeyPrefix::DatasetStats);
+            let iter = db.scan(start..end).await?;
+            pin_mut!(iter);
+            while let Some((key, value)) = iter.next().await.transpose()? {
+                if let Some((dataset_id, shard_id)) = KeyCodec::parse_dataset_stats_key(&key)
+                    && let Ok(shard_data) = bincode::deserialize::<StatsShardData>(&value)
+                {
+                    let scope = UsageScope::Dataset(dataset_id);
+                    global_stats.load_scope_shard(scope, shard_id, &shard_data);
+                }
+            }
+        }
+
+        {
+            let (start, end) = KeyCodec::prefix_range(KeyPrefix::OwnerStats);
+            let iter = db.scan(start..end).await?;
+            pin_mut!(iter);
+            while let Some((key, value)) = iter.next().await.transpose()? {
+                if let Some((owner, shard_id)) = KeyCodec::parse_owner_stats_key(&key)
+                    && let Ok(shard_data) = bincode::deserialize::<StatsShardData>(&value)
+                {
+                    global_stats.load_scope_shard(UsageScope::Owner(owner), shard_id, &shard_data);
+                }
+            }
+        }
+
+        {
+            let (start, end) = KeyCodec::prefix_range(KeyPrefix::SpaceStats);
+            let iter = db.scan(start..end).await?;
+            pin_mut!(iter);
+            while let Some((key, value)) = iter.next().await.transpose()? {
+                if let Some(dataset_id) = KeyCodec::parse_space_stats_key(&key)
+                    && let Ok(usage) = KeyCodec::decode_space_usage(&value)
+                {
+                    db.space_stats().set(dataset_id, usage);
+                }
+            }
+        }
+
         let flush_coordinator = FlushCoordinator::new(db.clone());
         let write_coordinator = Arc::new(WriteCoordinator::new());
         let stats = Arc::new(FileSystemStats::new());
@@ -203,7 +278,14 @@ impl ZeroFS {
         // Initialize writeback cache if configured
         let writeback_cache = if let Some(wb_config) = writeback_config {
             info!("Initializing writeback cache at {}", wb_config.cache_dir.display());
-            Some(crate::writeback_cache::WritebackCache::new(wb_config, db.clone()).await?)
+            Some(
+                crate::writeback_cache::WritebackCache::new(
+                    wb_config,
+                    db.clone(),
+                    write_coordinator.clone(),
+                )
+                .await?,
+            )
         } else {
             None
         };
@@ -248,12 +330,19 @@ impl ZeroFS {
             DirectoryStore::new(db.clone())
         };
         
+        let lazy_dirs = LazyDirStore::new(db.clone(), directory_store.clone()).await?;
         let inode_store = if let Some(ref cache) = metadata_cache {
             InodeStore::new_with_cache(db.clone(), next_inode_id, Arc::clone(cache))
         } else {
             InodeStore::new(db.clone(), next_inode_id)
-        };
+        }
+        .with_lazy_dirs(lazy_dirs.clone());
         let tombstone_store = TombstoneStore::new(db.clone());
+        let detached_trees = DetachedTrees::new(db.clone()).await?;
+        let xattr_store = XattrStore::new(db.clone())
+            .await?
+            .with_lazy_dirs(lazy_dirs.clone());
+        let quota_store = QuotaStore::new(db.clone()).await?;
         
         // Initialize dataset store
         let (created_sec, _) = get_current_time();
@@ -269,6 +358,10 @@ impl ZeroFS {
             directory_store,
             inode_store,
             tombstone_store,
+            lazy_dirs,
+            detached_trees,
+            xattr_store,
+            quota_store,
             dataset_store: dataset_store_arc,
             snapshot_vfs,
             lock_manager,
@@ -304,14 +397,19 @@ impl ZeroFS {
     ) -> Result<(), FsError> {
         self.inode_store.save_counter(&mut txn);
 
+        // Chunks go to the writeback cache before the batch is written, in
+        // sequence with other commits and with the cache's flushes.
+        if txn.has_cached_writes() {
+            seq_guard.wait_for_predecessors().await;
+            self.chunk_store.apply_cached_writes(&mut txn).await?;
+        }
+
         let (encrypt_result, _) = tokio::join!(txn.into_inner(), seq_guard.wait_for_predecessors());
         let prepared = encrypt_result.map_err(|_| FsError::IoError)?;
 
         self.db
-            .write_raw_batch(
-                prepared.batch,
-                prepared.pending_operations,
-                prepared.deleted_keys,
+            .write_prepared(
+                prepared,
                 &WriteOptions {
                     await_durable: false,
                 },
@@ -332,14 +430,19 @@ impl ZeroFS {
     ) -> Result<(), FsError> {
         self.inode_store.save_counter(&mut txn);
 
+        // Chunks go to the writeback cache before the batch is written, in
+        // sequence with other commits and with the cache's flushes.
+        if txn.has_cached_writes() {
+            seq_guard.wait_for_predecessors().await;
+            self.chunk_store.apply_cached_writes(&mut txn).await?;
+        }
+
         let (encrypt_result, _) = tokio::join!(txn.into_inner(), seq_guard.wait_for_predecessors());
         let prepared = encrypt_result.map_err(|_| FsError::IoError)?;
 
         self.db
-            .write_raw_batch(
-                prepared.batch,
-                prepared.pending_operations,
-                prepared.deleted_keys,
+            .write_prepared(
+                prepared,
                 &WriteOptions {
                     await_durable: false,
                 },
@@ -351,6 +454,37 @@ impl ZeroFS {
         Ok(())
     }
 
+    /// Release the data of file `id` once its last link is gone: the
+    /// references it holds on backing layers, and its chunks, left to the
+    /// garbage collector through a tombstone when there are many.
+    pub(crate) async fn release_file_data(
+        &self,
+        txn: &mut EncryptedTransaction,
+        id: InodeId,
+        file: &FileInode,
+    ) -> Result<(), FsError> {
+        let total_chunks = file.chunk_count();
+        let map_id = file.chunk_map_id(id);
+
+        for layer in &file.backing {
+            txn.remove_layer_ref(layer.map_id);
+        }
+
+        if total_chunks as usize <= SMALL_FILE_TOMBSTONE_THRESHOLD {
+            self.chunk_store
+                .delete_range(txn, map_id, 0, total_chunks)
+                .await?;
+        } else {
+            self.tombstone_store
+                .add(txn, map_id, file.size, file.chunk_size);
+            self.stats
+                .tombstones_created
+                .fetch_add(1, Ordering::Relaxed);
+        }
+
+        Ok(())
+    }
+
     /// Resolve inode ID to full path components by walking parent chain
     /// Returns Vec of path components (excluding root), in order from root to target
     pub async fn resolve_path_components(&self, id: InodeId) -> Vec<Vec<u8>> {
@@ -528,7 +662,8 @@ impl ZeroFS {
         while current_id != 0 {
             let parent_inode = self.inode_store.get(current_id).await?;
 
-            check_access(&parent_inode, creds, AccessMode::Execute)?;
+            self.check_inode_access(current_id, &parent_inode, creds, AccessMode::Execute)
+                .await?;
 
             current_id = match &parent_inode {
                 Inode::Directory(d) => d.parent,
@@ -539,6 +674,40 @@ impl ZeroFS {
         Ok(())
     }
 
+    /// Check `mode` access by `creds` to inode `id`, applying its access ACL.
+    /// The owner's permissions and root's are decided by the mode bits alone,
+    /// which the ACL is kept in sync with.
+    pub async fn check_inode_access(
+        &self,
+        id: InodeId,
+        inode: &Inode,
+        creds: &Credentials,
+        mode: AccessMode,
+    ) -> Result<(), FsError> {
+        if creds.uid == 0 || creds.uid == inode.uid() {
+            return check_access(inode, creds, mode);
+        }
+
+        let acl = self.xattr_store.get_acl(id, ACL_ACCESS_XATTR).await?;
+        check_access_acl(inode, acl.as_ref(), creds, mode)
+    }
+
+    /// Check that `bytes` more can be allocated to a file of `account`:
+    /// fails with `NoSpace` past the size of the filesystem, or with
+    /// `QuotaExceeded` past a quota
+    async fn check_space(&self, account: InodeAccount, bytes: u64) -> Result<(), FsError> {
+        let (used_bytes, _) = self.global_stats.get_totals();
+        if used_bytes.saturating_add(bytes) > self.max_bytes {
+            debug!(
+                "Allocation would exceed the filesystem size: used={}, increase={}, max={}",
+                used_bytes, bytes, self.max_bytes
+            );
+            return Err(FsError::NoSpace);
+        }
+
+        quota::check_quota(self, account, bytes, 0).await
+    }
+
     pub async fn write(
         &self,
         auth: &AuthContext,
@@ -558,44 +727,50 @@ impl ZeroFS {
 
         // Check parent permissions before lock (also validates inode exists)
         self.check_parent_execute_permissions(id, &creds).await?;
+        dataset_ops::inode_properties(self, id)
+            .await?
+            .check_writable()?;
 
+        let _cow = clone::prepare_write(self, &[id]).await?;
         let _guard = self.lock_manager.acquire_write(id).await;
+        destroy::check_attached(self, id).await?;
         let mut inode = self.inode_store.get(id).await?;
 
         // NFS RFC 1813 section 4.4: Allow owners to write to their files regardless of permission bits
         match &inode {
             Inode::File(file) if creds.uid != file.uid => {
-                check_access(&inode, &creds, AccessMode::Write)?;
+                self.check_inode_access(id, &inode, &creds, AccessMode::Write)
+                    .await?;
             }
             _ => {}
         }
 
         match &mut inode {
             Inode::File(file) => {
-                let old_size = file.size;
+                let account = InodeAccount::new(file.dataset, (file.uid, file.gid));
                 let end_offset = offset + data.len() as u64;
                 let new_size = std::cmp::max(file.size, end_offset);
 
-                if new_size > old_size {
-                    let size_increase = new_size - old_size;
-                    let (used_bytes, _) = self.global_stats.get_totals();
-
-                    if used_bytes.saturating_add(size_increase) > self.max_bytes {
-                        debug!(
-                            "Write would exceed quota: used={}, increase={}, max={}",
-                            used_bytes, size_increase, self.max_bytes
-                        );
-                        return Err(FsError::NoSpace);
-                    }
-                }
-
+                let old_allocated = file.allocated_bytes();
                 let mut txn = self.db.new_transaction()?;
-                self.chunk_store.write(&mut txn, id, offset, data).await?;
+                let allocated_delta = self
+                    .chunk_store
+                    .write(&mut txn, &ChunkLayout::for_file(id, file), offset, data)
+                    .await?;
 
                 #[cfg(feature = "failpoints")]
                 fail_point!(fp::WRITE_AFTER_CHUNK);
 
                 file.size = new_size;
+                file.adjust_allocated(allocated_delta);
+                let new_allocated = file.allocated_bytes();
+
+                // Limits apply to allocated bytes, which usage counts: filling
+                // a hole takes space, extending a file with one does not
+                if new_allocated > old_allocated {
+                    self.check_space(account, new_allocated - old_allocated)
+                        .await?;
+                }
                 let (now_sec, now_nsec) = get_current_time();
                 file.mtime = now_sec;
                 file.mtime_nsec = now_nsec;
@@ -622,7 +797,7 @@ impl ZeroFS {
 
                 let stats_update = if let Some(update) = self
                     .global_stats
-                    .prepare_size_change(id, old_size, new_size)
+                    .prepare_size_change(id, account, old_allocated, new_allocated)
                     .await
                 {
                     self.global_stats.add_to_transaction(&update, &mut txn)?;
@@ -686,18 +861,39 @@ impl ZeroFS {
             String::from_utf8_lossy(name)
         );
 
+        let dataset = dataset_ops::dataset_of(self, dirid).await?;
+        let props = self.dataset_store.effective_properties(dataset).await;
+        props.check_writable()?;
+
+        let _cow = clone::prepare_write(self, &[dirid]).await?;
         let _guard = self.lock_manager.acquire_write(dirid).await;
+        destroy::check_attached(self, dirid).await?;
         let mut dir_inode = self.inode_store.get(dirid).await?;
 
-        check_access(&dir_inode, creds, AccessMode::Write)?;
-        check_access(&dir_inode, creds, AccessMode::Execute)?;
+        self.check_inode_access(dirid, &dir_inode, creds, AccessMode::Write)
+            .await?;
+        self.check_inode_access(dirid, &dir_inode, creds, AccessMode::Execute)
+            .await?;
 
         match &mut dir_inode {
             Inode::Directory(dir) => {
-                if self.directory_store.exists(dirid, name).await? {
+                if properties::name_taken(self, &props, dirid, name).await? {
                     return Err(FsError::Exists);
                 }
 
+                let account = InodeAccount {
+                    dataset,
+                    uid: match &attr.uid {
+                        SetUid::Set(u) => *u,
+                        SetUid::NoChange => props.default_uid.unwrap_or(creds.uid),
+                    },
+                    gid: match &attr.gid {
+                        SetGid::Set(g) => *g,
+                        SetGid::NoChange => props.default_gid.unwrap_or(creds.gid),
+                    },
+                };
+                quota::check_quota(self, account, 0, 1).await?;
+
                 let file_id = self.inode_store.allocate();
                 debug!(
                     "Allocated inode {} for file {}",
@@ -706,11 +902,13 @@ impl ZeroFS {
                 );
 
                 let (now_sec, now_nsec) = get_current_time();
+                let encryption_dataset = self.dataset_store.encryption_dataset(dataset).await;
 
                 let final_mode = match &attr.mode {
                     SetMode::Set(m) => validate_mode(*m),
                     SetMode::NoChange => 0o666,
                 };
+                let (final_mode, acl_xattrs) = self.inherit_acls(dirid, final_mode, false).await?;
 
                 let file_inode = FileInode {
                     size: 0,
@@ -721,17 +919,17 @@ impl ZeroFS {
                     atime: now_sec,
                     atime_nsec: now_nsec,
                     mode: final_mode,
-                    uid: match &attr.uid {
-                        SetUid::Set(u) => *u,
-                        SetUid::NoChange => creds.uid,
-                    },
-                    gid: match &attr.gid {
-                        SetGid::Set(g) => *g,
-                        SetGid::NoChange => creds.gid,
-                    },
+                    uid: account.uid,
+                    gid: account.gid,
                     parent: Some(dirid),
                     name: Some(name.to_vec()),
                     nlink: 1,
+                    chunk_map: None,
+                    backing: Vec::new(),
+                    chunk_size: self.chunk_size as u32,
+                    encryption_dataset,
+                    dataset,
+                    allocated_chunks: 0,
                 };
 
                 let mut txn = self.db.new_transaction()?;
@@ -742,6 +940,9 @@ impl ZeroFS {
 
                 let file_inode_enum = Inode::File(file_inode.clone());
                 self.inode_store.save(&mut txn, file_id, &file_inode_enum)?;
+                if !acl_xattrs.is_empty() {
+                    self.xattr_store.save(&mut txn, file_id, &acl_xattrs)?;
+                }
 
                 #[cfg(feature = "failpoints")]
                 fail_point!(fp::CREATE_AFTER_INODE);
@@ -775,7 +976,10 @@ impl ZeroFS {
                         .ok();
                 }
 
-                let stats_update = self.global_stats.prepare_inode_create(file_id).await;
+                let stats_update = self
+                    .global_stats
+                    .prepare_inode_create(file_id, account)
+                    .await;
                 self.global_stats
                     .add_to_transaction(&stats_update, &mut txn)?;
 
@@ -847,7 +1051,8 @@ impl ZeroFS {
 
         self.check_parent_execute_permissions(id, &creds).await?;
 
-        check_access(&inode, &creds, AccessMode::Read)?;
+        self.check_inode_access(id, &inode, &creds, AccessMode::Read)
+            .await?;
 
         match &inode {
             Inode::File(file) => {
@@ -862,7 +1067,10 @@ impl ZeroFS {
                 }
 
                 let read_len = std::cmp::min(count as u64, file.size - offset);
-                let result_bytes = self.chunk_store.read(id, offset, read_len).await?;
+                let result_bytes = self
+                    .chunk_store
+                    .read(&ChunkLayout::for_file(id, file), offset, read_len)
+                    .await?;
                 let eof = offset + read_len >= file.size;
 
                 self.stats
@@ -881,12 +1089,68 @@ impl ZeroFS {
                     )
                     .await;
 
+                self.update_atime(id, file).await;
+
                 Ok((result_bytes, eof))
             }
             _ => Err(FsError::IsDirectory),
         }
     }
 
+    /// Record a read of file `id` in its access time, if the atime property
+    /// of its dataset asks for it. Failures are only logged: the read itself
+    /// succeeded.
+    async fn update_atime(&self, id: InodeId, file: &FileInode) {
+        if self.db.is_read_only() {
+            return;
+        }
+        let props = self.dataset_store.effective_properties(file.dataset).await;
+        if props.readonly {
+            return;
+        }
+
+        let (now_sec, now_nsec) = get_current_time();
+        let due = match props.atime {
+            AtimeMode::Off => false,
+            AtimeMode::On => true,
+            AtimeMode::Relatime => {
+                file.atime <= file.mtime
+                    || file.atime <= file.ctime
+                    || now_sec.saturating_sub(file.atime) >= properties::RELATIME_INTERVAL_SECS
+            }
+        };
+        if !due {
+            return;
+        }
+
+        if let Err(e) = self.set_atime(id, now_sec, now_nsec).await {
+            warn!("Failed to update access time of inode {}: {:?}", id, e);
+        }
+    }
+
+    async fn set_atime(&self, id: InodeId, atime: u64, atime_nsec: u32) -> Result<(), FsError> {
+        let _cow = clone::prepare_write(self, &[id]).await?;
+        let _guard = self.lock_manager.acquire_write(id).await;
+        let mut inode = self.inode_store.get(id).await?;
+        let Inode::File(file) = &mut inode else {
+            return Ok(());
+        };
+        file.atime = atime;
+        file.atime_nsec = atime_nsec;
+        let parent_name = file.parent.zip(file.name.clone());
+
+        let mut txn = self.db.new_transaction()?;
+        self.inode_store.save(&mut txn, id, &inode)?;
+        if let Some((parent_id, name)) = parent_name {
+            self.directory_store
+                .update_inode_in_entry(&mut txn, parent_id, &name, id, &inode)
+                .await?;
+        }
+
+        let mut seq_guard = self.write_coordinator.allocate_sequence();
+        self.commit_transaction(txn, &mut seq_guard).await
+    }
+
     pub async fn trim(
         &self,
         auth: &AuthContext,
@@ -899,30 +1163,69 @@ impl ZeroFS {
             id, offset, length
         );
 
+        dataset_ops::inode_properties(self, id)
+            .await?
+            .check_writable()?;
+        let _cow = clone::prepare_write(self, &[id]).await?;
         let _guard = self.lock_manager.acquire_write(id).await;
-        let inode = self.inode_store.get(id).await?;
+        destroy::check_attached(self, id).await?;
+        let mut inode = self.inode_store.get(id).await?;
 
         let creds = Credentials::from_auth_context(auth);
 
         match &inode {
             Inode::File(file) if creds.uid != file.uid => {
-                check_access(&inode, &creds, AccessMode::Write)?;
+                self.check_inode_access(id, &inode, &creds, AccessMode::Write)
+                    .await?;
             }
             Inode::File(_) => {}
             _ => return Err(FsError::IsDirectory),
         }
 
-        let file = match &inode {
+        let file = match &mut inode {
             Inode::File(f) => f,
             _ => return Err(FsError::IsDirectory),
         };
+        let account = InodeAccount::new(file.dataset, (file.uid, file.gid));
+        let old_allocated = file.allocated_bytes();
 
         let mut txn = self.db.new_transaction()?;
 
-        self.chunk_store
-            .zero_range(&mut txn, id, offset, length, file.size)
+        let allocated_delta = self
+            .chunk_store
+            .zero_range(
+                &mut txn,
+                &ChunkLayout::for_file(id, file),
+                offset,
+                length,
+                file.size,
+            )
             .await;
 
+        // Trimmed chunks no longer take storage
+        let mut stats_update = None;
+        if allocated_delta != 0 {
+            file.adjust_allocated(allocated_delta);
+            let new_allocated = file.allocated_bytes();
+            let parent_name_for_update = file.parent.zip(file.name.clone());
+
+            self.inode_store.save(&mut txn, id, &inode)?;
+            if let Some((parent_id, name)) = parent_name_for_update {
+                self.directory_store
+                    .update_inode_in_entry(&mut txn, parent_id, &name, id, &inode)
+                    .await?;
+            }
+
+            if let Some(update) = self
+                .global_stats
+                .prepare_size_change(id, account, old_allocated, new_allocated)
+                .await
+            {
+                self.global_stats.add_to_transaction(&update, &mut txn)?;
+                stats_update = Some(update);
+            }
+        }
+
         let mut seq_guard = self.write_coordinator.allocate_sequence();
         self.commit_transaction(txn, &mut seq_guard)
             .await
@@ -930,6 +1233,10 @@ impl ZeroFS {
                 error!("Failed to commit trim batch: {}", e);
             })?;
 
+        if let Some(update) = stats_update {
+            self.global_stats.commit_update(&update);
+        }
+
         debug!("Trim completed successfully for inode {}", id);
 
         self.tracer
@@ -958,9 +1265,27 @@ impl ZeroFS {
 
         match dir_inode {
             Inode::Directory(_) => {
-                check_access(&dir_inode, creds, AccessMode::Execute)?;
+                self.check_inode_access(dirid, &dir_inode, creds, AccessMode::Execute)
+                    .await?;
 
-                match self.directory_store.get(dirid, filename).await {
+                // A lazy directory is read through its origin, not copied.
+                // Its entries get IDs of their own, so that writes through
+                // them go to copies.
+                let entries_dir = self.lazy_dirs.entries_dir(dirid).await?;
+                let found = match self.directory_store.get(entries_dir, filename).await {
+                    Err(FsError::NotFound) => {
+                        properties::lookup_case_variant(self, dirid, filename).await
+                    }
+                    found => found.map(|id| (filename.to_vec(), id)),
+                };
+                let found = match found {
+                    Ok((name, _)) if entries_dir != dirid => {
+                        let dataset = dataset_ops::dataset_of(self, dirid).await?;
+                        clone::entry_alias(self, dirid, dataset, &name).await
+                    }
+                    found => found.map(|(_, id)| id),
+                };
+                match found {
                     Ok(inode_id) => {
                         debug!(
                             "lookup found: {} -> inode {}",
@@ -1008,15 +1333,23 @@ impl ZeroFS {
             String::from_utf8_lossy(name)
         );
 
+        let dataset = dataset_ops::dataset_of(self, dirid).await?;
+        let props = self.dataset_store.effective_properties(dataset).await;
+        props.check_writable()?;
+
+        let _cow = clone::prepare_write(self, &[dirid]).await?;
         let _guard = self.lock_manager.acquire_write(dirid).await;
+        destroy::check_attached(self, dirid).await?;
         let mut dir_inode = self.inode_store.get(dirid).await?;
 
-        check_access(&dir_inode, creds, AccessMode::Write)?;
-        check_access(&dir_inode, creds, AccessMode::Execute)?;
+        self.check_inode_access(dirid, &dir_inode, creds, AccessMode::Write)
+            .await?;
+        self.check_inode_access(dirid, &dir_inode, creds, AccessMode::Execute)
+            .await?;
 
         match &mut dir_inode {
             Inode::Directory(dir) => {
-                if self.directory_store.exists(dirid, name).await? {
+                if properties::name_taken(self, &props, dirid, name).await? {
                     return Err(FsError::Exists);
                 }
 
@@ -1033,10 +1366,11 @@ impl ZeroFS {
                 if parent_mode & 0o2000 != 0 {
                     new_mode |= 0o2000;
                 }
+                let (new_mode, acl_xattrs) = self.inherit_acls(dirid, new_mode, true).await?;
 
                 let new_uid = match &attr.uid {
                     SetUid::Set(u) => *u,
-                    SetUid::NoChange => creds.uid,
+                    SetUid::NoChange => props.default_uid.unwrap_or(creds.uid),
                 };
 
                 let new_gid = match &attr.gid {
@@ -1045,11 +1379,14 @@ impl ZeroFS {
                         if parent_mode & 0o2000 != 0 {
                             dir.gid
                         } else {
-                            creds.gid
+                            props.default_gid.unwrap_or(creds.gid)
                         }
                     }
                 };
 
+                let account = InodeAccount::new(dataset, (new_uid, new_gid));
+                quota::check_quota(self, account, 0, 1).await?;
+
                 let (atime_sec, atime_nsec) = match &attr.atime {
                     SetTime::SetToClientTime(ts) => (ts.seconds, ts.nanoseconds),
                     SetTime::SetToServerTime | SetTime::NoChange => (now_sec, now_nsec),
@@ -1085,6 +1422,9 @@ impl ZeroFS {
                 let new_dir_inode_enum = Inode::Directory(new_dir_inode.clone());
                 self.inode_store
                     .save(&mut txn, new_dir_id, &new_dir_inode_enum)?;
+                if !acl_xattrs.is_empty() {
+                    self.xattr_store.save(&mut txn, new_dir_id, &acl_xattrs)?;
+                }
 
                 #[cfg(feature = "failpoints")]
                 fail_point!(fp::MKDIR_AFTER_INODE);
@@ -1122,7 +1462,10 @@ impl ZeroFS {
                         .ok();
                 }
 
-                let stats_update = self.global_stats.prepare_inode_create(new_dir_id).await;
+                let stats_update = self
+                    .global_stats
+                    .prepare_inode_create(new_dir_id, account)
+                    .await;
                 self.global_stats
                     .add_to_transaction(&stats_update, &mut txn)?;
 
@@ -1170,7 +1513,8 @@ impl ZeroFS {
         let dir_inode = self.inode_store.get(dirid).await?;
 
         let creds = Credentials::from_auth_context(auth);
-        check_access(&dir_inode, &creds, AccessMode::Read)?;
+        self.check_inode_access(dirid, &dir_inode, &creds, AccessMode::Read)
+            .await?;
 
         use crate::fs::store::directory::{COOKIE_DOT, COOKIE_DOTDOT};
 
@@ -1216,11 +1560,16 @@ impl ZeroFS {
                     });
                 }
 
-                // Get regular entries, starting after the given cookie
+                // Get regular entries, starting after the given cookie. A lazy
+                // directory lists those of its origin, whose cookies its
+                // copies keep, under IDs of its own.
+                let entries_dir = self.lazy_dirs.entries_dir(dirid).await?;
                 let iter = if start_after < COOKIE_DOTDOT {
-                    self.directory_store.list(dirid).await?
+                    self.directory_store.list(entries_dir).await?
                 } else {
-                    self.directory_store.list_from(dirid, start_after).await?
+                    self.directory_store
+                        .list_from(entries_dir, start_after)
+                        .await?
                 };
                 pin_mut!(iter);
 
@@ -1286,8 +1635,26 @@ impl ZeroFS {
                     }
                 }
 
+                let dataset = if entries_dir != dirid {
+                    Some(dataset_ops::dataset_of(self, dirid).await?)
+                } else {
+                    None
+                };
                 for (inode_id, name, cookie, inode_opt) in dir_entries {
                     if let Some(inode) = inode_opt {
+                        let (inode_id, inode) = match dataset {
+                            Some(dataset) => {
+                                let alias = Alias {
+                                    dir: dirid,
+                                    name: name.clone(),
+                                    dataset,
+                                };
+                                let alias_id =
+                                    clone::entry_alias(self, dirid, dataset, &name).await?;
+                                (alias_id, alias.view(inode_id, inode))
+                            }
+                            None => (inode_id, inode),
+                        };
                     entries.push(DirEntry {
                         fileid: inode_id,
                         name,
@@ -1339,18 +1706,26 @@ impl ZeroFS {
             target
         );
 
+        let dataset = dataset_ops::dataset_of(self, dirid).await?;
+        let props = self.dataset_store.effective_properties(dataset).await;
+        props.check_writable()?;
+
+        let _cow = clone::prepare_write(self, &[dirid]).await?;
         let _guard = self.lock_manager.acquire_write(dirid).await;
+        destroy::check_attached(self, dirid).await?;
         let mut dir_inode = self.inode_store.get(dirid).await?;
 
-        check_access(&dir_inode, creds, AccessMode::Write)?;
-        check_access(&dir_inode, creds, AccessMode::Execute)?;
+        self.check_inode_access(dirid, &dir_inode, creds, AccessMode::Write)
+            .await?;
+        self.check_inode_access(dirid, &dir_inode, creds, AccessMode::Execute)
+            .await?;
 
         let dir = match &mut dir_inode {
             Inode::Directory(d) => d,
             _ => return Err(FsError::NotDirectory),
         };
 
-        if self.directory_store.exists(dirid, linkname).await? {
+        if properties::name_taken(self, &props, dirid, linkname).await? {
             return Err(FsError::Exists);
         }
 
@@ -1363,14 +1738,17 @@ impl ZeroFS {
 
         let uid = match &attr.uid {
             SetUid::Set(u) => *u,
-            SetUid::NoChange => creds.uid,
+            SetUid::NoChange => props.default_uid.unwrap_or(creds.uid),
         };
 
         let gid = match &attr.gid {
             SetGid::Set(g) => *g,
-            SetGid::NoChange => creds.gid,
+            SetGid::NoChange => props.default_gid.unwrap_or(creds.gid),
         };
 
+        let account = InodeAccount::new(dataset, (uid, gid));
+        quota::check_quota(self, account, 0, 1).await?;
+
         let (now_sec, now_nsec) = get_current_time();
         let symlink_inode = Inode::Symlink(SymlinkInode {
             target: target.to_vec(),
@@ -1428,7 +1806,10 @@ impl ZeroFS {
                 .ok();
         }
 
-        let stats_update = self.global_stats.prepare_inode_create(new_id).await;
+        let stats_update = self
+            .global_stats
+            .prepare_inode_create(new_id, account)
+            .await;
         self.global_stats
             .add_to_transaction(&stats_update, &mut txn)?;
 
@@ -1477,16 +1858,25 @@ impl ZeroFS {
             fileid, linkdirid, linkname_str
         );
 
+        let dataset = dataset_ops::dataset_of(self, linkdirid).await?;
+        let props = self.dataset_store.effective_properties(dataset).await;
+        props.check_writable()?;
+
+        let _cow = clone::prepare_write(self, &[fileid, linkdirid]).await?;
         let _guards = self
             .lock_manager
             .acquire_multiple_write(vec![fileid, linkdirid])
             .await;
+        destroy::check_attached(self, fileid).await?;
+        destroy::check_attached(self, linkdirid).await?;
 
         let link_dir_inode = self.inode_store.get(linkdirid).await?;
         let creds = Credentials::from_auth_context(auth);
 
-        check_access(&link_dir_inode, &creds, AccessMode::Write)?;
-        check_access(&link_dir_inode, &creds, AccessMode::Execute)?;
+        self.check_inode_access(linkdirid, &link_dir_inode, &creds, AccessMode::Write)
+            .await?;
+        self.check_inode_access(linkdirid, &link_dir_inode, &creds, AccessMode::Execute)
+            .await?;
 
         self.check_parent_execute_permissions(fileid, &creds)
             .await?;
@@ -1506,10 +1896,17 @@ impl ZeroFS {
             return Err(FsError::InvalidArgument);
         }
 
-        if self.directory_store.exists(linkdirid, linkname).await? {
+        if properties::name_taken(self, &props, linkdirid, linkname).await? {
             return Err(FsError::Exists);
         }
 
+        // The usage of a file is accounted to one dataset, so links stay in it
+        if let Inode::File(file) = &file_inode
+            && file.dataset != dataset
+        {
+            return Err(FsError::CrossDevice);
+        }
+
         let original_parent_name = file_inode
             .parent()
             .zip(file_inode.name().map(|n| n.to_vec()));
@@ -1526,11 +1923,17 @@ impl ZeroFS {
         #[cfg(feature = "failpoints")]
         fail_point!(fp::LINK_AFTER_DIR_ENTRY);
 
+        // The inode loses its parent: the directories of its links are
+        // recorded instead
         if let Some((orig_parent, orig_name)) = original_parent_name {
             self.directory_store
                 .convert_to_reference(&mut txn, orig_parent, &orig_name, fileid)
                 .await?;
+            self.directory_store
+                .add_link_dir(&mut txn, fileid, orig_parent, &orig_name);
         }
+        self.directory_store
+            .add_link_dir(&mut txn, fileid, linkdirid, linkname);
 
         let (now_sec, now_nsec) = get_current_time();
         match &mut file_inode {
@@ -1626,7 +2029,12 @@ impl ZeroFS {
         setattr: &SetAttributes,
     ) -> Result<FileAttributes, FsError> {
         debug!("setattr: id={}, setattr={:?}", id, setattr);
+        dataset_ops::inode_properties(self, id)
+            .await?
+            .check_writable()?;
+        let _cow = clone::prepare_write(self, &[id]).await?;
         let _guard = self.lock_manager.acquire_write(id).await;
+        destroy::check_attached(self, id).await?;
         let mut inode = self.inode_store.get(id).await?;
 
         self.check_parent_execute_permissions(id, creds).await?;
@@ -1677,27 +2085,22 @@ impl ZeroFS {
         }
 
         if matches!(setattr.size, SetSize::Set(_)) {
-            check_access(&inode, creds, AccessMode::Write)?;
+            self.check_inode_access(id, &inode, creds, AccessMode::Write)
+                .await?;
         }
 
+        let old_owner = inode.ownership();
+
         match &mut inode {
             Inode::File(file) => {
                 if let SetSize::Set(new_size) = setattr.size {
                     let old_size = file.size;
+                    let account = InodeAccount::new(file.dataset, (file.uid, file.gid));
                     if new_size != old_size {
-                        if new_size > old_size {
-                            let size_increase = new_size - old_size;
-                            let (used_bytes, _) = self.global_stats.get_totals();
-                            if used_bytes.saturating_add(size_increase) > self.max_bytes {
-                                debug!(
-                                    "Setattr size change would exceed quota: used={}, increase={}, max={}",
-                                    used_bytes, size_increase, self.max_bytes
-                                );
-                                return Err(FsError::NoSpace);
-                            }
-                        }
-
+                        let layout = ChunkLayout::for_file(id, file);
+                        let old_allocated = file.allocated_bytes();
                         file.size = new_size;
+                        file.clip_backing(new_size);
                         let (now_sec, now_nsec) = get_current_time();
                         file.mtime = now_sec;
                         file.mtime_nsec = now_nsec;
@@ -1706,9 +2109,16 @@ impl ZeroFS {
 
                         let mut txn = self.db.new_transaction()?;
 
-                        self.chunk_store
-                            .truncate(&mut txn, id, old_size, new_size)
+                        let allocated_delta = self
+                            .chunk_store
+                            .truncate(&mut txn, &layout, old_size, new_size)
                             .await?;
+                        file.adjust_allocated(allocated_delta);
+                        let new_allocated = file.allocated_bytes();
+                        if new_allocated > old_allocated {
+                            self.check_space(account, new_allocated - old_allocated)
+                                .await?;
+                        }
 
                         #[cfg(feature = "failpoints")]
                         fail_point!(fp::TRUNCATE_AFTER_CHUNKS);
@@ -1728,7 +2138,7 @@ impl ZeroFS {
 
                         let stats_update = if let Some(update) = self
                             .global_stats
-                            .prepare_size_change(id, old_size, new_size)
+                            .prepare_size_change(id, account, old_allocated, new_allocated)
                             .await
                         {
                             self.global_stats.add_to_transaction(&update, &mut txn)?;
@@ -2024,9 +2434,29 @@ impl ZeroFS {
             }
         }
 
+        // chown moves the inode's usage to its new owners
+        let new_owner = inode.ownership();
+        let size = match &inode {
+            Inode::File(file) => file.allocated_bytes(),
+            _ => 0,
+        };
+        if new_owner != old_owner {
+            quota::check_owner_change(self, old_owner, new_owner, size).await?;
+        }
+
         let mut txn = self.db.new_transaction()?;
         self.inode_store.save(&mut txn, id, &inode)?;
 
+        // chmod also applies to the access ACL
+        if matches!(setattr.mode, SetMode::Set(_))
+            && let Some(mut acl) = self.xattr_store.get_acl(id, ACL_ACCESS_XATTR).await?
+        {
+            acl.chmod(inode.mode());
+            let mut xattrs = self.xattr_store.get(id).await?;
+            xattrs.insert(ACL_ACCESS_XATTR.to_vec(), acl.encode());
+            self.xattr_store.save(&mut txn, id, &xattrs)?;
+        }
+
         if let Some(parent_id) = inode.parent()
             && let Some(name) = inode.name()
         {
@@ -2035,9 +2465,21 @@ impl ZeroFS {
                 .await?;
         }
 
+        let stats_update = self
+            .global_stats
+            .prepare_owner_change(id, old_owner, new_owner, size)
+            .await;
+        if let Some(update) = &stats_update {
+            self.global_stats.add_to_transaction(update, &mut txn)?;
+        }
+
         let mut seq_guard = self.write_coordinator.allocate_sequence();
         self.commit_transaction(txn, &mut seq_guard).await?;
 
+        if let Some(update) = &stats_update {
+            self.global_stats.commit_update(update);
+        }
+
         self.tracer
             .emit(
                 || self.resolve_path_lossy(id),
@@ -2053,69 +2495,234 @@ impl ZeroFS {
         Ok(InodeWithId { inode: &inode, id }.into())
     }
 
-    pub async fn mknod(
+    /// Value of extended attribute `name` of inode `id`
+    pub async fn getxattr(
         &self,
         creds: &Credentials,
-        dirid: InodeId,
+        id: InodeId,
         name: &[u8],
-        ftype: FileType,
-        attr: &SetAttributes,
-        rdev: Option<(u32, u32)>,
-    ) -> Result<(InodeId, FileAttributes), FsError> {
-        validate_filename(name)?;
+    ) -> Result<Vec<u8>, FsError> {
+        let inode = self.inode_store.get(id).await?;
+        let mut xattrs = self.xattr_store.get(id).await?;
+        let acl = stored_acl(&xattrs, ACL_ACCESS_XATTR)?;
+        check_xattr_access(&inode, acl.as_ref(), creds, name, AccessMode::Read)?;
 
-        debug!(
-            "mknod: dirid={}, filename={}, ftype={:?}",
-            dirid,
-            String::from_utf8_lossy(name),
-            ftype
-        );
+        xattrs.remove(name).ok_or(FsError::NoData)
+    }
 
-        let _guard = self.lock_manager.acquire_write(dirid).await;
-        let mut dir_inode = self.inode_store.get(dirid).await?;
+    /// Names of the extended attributes of inode `id` visible to `creds`
+    pub async fn listxattr(
+        &self,
+        creds: &Credentials,
+        id: InodeId,
+    ) -> Result<Vec<Vec<u8>>, FsError> {
+        self.inode_store.get(id).await?;
+
+        let xattrs = self.xattr_store.get(id).await?;
+        Ok(xattrs
+            .into_keys()
+            .filter(|name| can_list_xattr(creds, name))
+            .collect())
+    }
 
-        check_access(&dir_inode, creds, AccessMode::Write)?;
-        check_access(&dir_inode, creds, AccessMode::Execute)?;
+    /// Set extended attribute `name` of inode `id`. With `XATTR_CREATE` this
+    /// fails if the attribute exists, with `XATTR_REPLACE` if it does not.
+    /// ACLs ignore the flags, and the access ACL also sets the mode bits.
+    pub async fn setxattr(
+        &self,
+        creds: &Credentials,
+        id: InodeId,
+        name: &[u8],
+        value: &[u8],
+        flags: u32,
+    ) -> Result<(), FsError> {
+        if value.len() > XATTR_SIZE_MAX {
+            return Err(FsError::NoSpace);
+        }
 
-        match &mut dir_inode {
-            Inode::Directory(dir) => {
-                if self.directory_store.exists(dirid, name).await? {
-                    debug!("File already exists");
-                    return Err(FsError::Exists);
-                }
+        self.update_xattrs(creds, id, name, |inode, xattrs| {
+            if is_acl_xattr(name) {
+                return set_acl(inode, xattrs, creds, name, value);
+            }
 
-                let special_id = self.inode_store.allocate();
-                let (now_sec, now_nsec) = get_current_time();
+            let exists = xattrs.contains_key(name);
+            if flags & XATTR_CREATE != 0 && exists {
+                return Err(FsError::Exists);
+            }
+            if flags & XATTR_REPLACE != 0 && !exists {
+                return Err(FsError::NoData);
+            }
+            xattrs.insert(name.to_vec(), value.to_vec());
+            Ok(())
+        })
+        .await
+    }
 
-                let base_mode = match ftype {
-                    FileType::Fifo => 0o666,
-                    FileType::CharDevice | FileType::BlockDevice => 0o666,
-                    FileType::Socket => 0o666,
-                    _ => return Err(FsError::InvalidArgument),
-                };
+    pub async fn removexattr(
+        &self,
+        creds: &Credentials,
+        id: InodeId,
+        name: &[u8],
+    ) -> Result<(), FsError> {
+        self.update_xattrs(creds, id, name, |_, xattrs| {
+            xattrs.remove(name).map(|_| ()).ok_or(FsError::NoData)
+        })
+        .await
+    }
 
-                let final_mode = if let SetMode::Set(m) = attr.mode {
-                    validate_mode(m)
-                } else {
-                    base_mode
-                };
+    /// Apply `update` to the extended attributes of inode `id`, for a caller
+    /// allowed to write attribute `name`. Changes the inode's ctime.
+    async fn update_xattrs(
+        &self,
+        creds: &Credentials,
+        id: InodeId,
+        name: &[u8],
+        update: impl FnOnce(&mut Inode, &mut Xattrs) -> Result<(), FsError>,
+    ) -> Result<(), FsError> {
+        if name.is_empty() {
+            return Err(FsError::InvalidArgument);
+        }
+        if name.len() > XATTR_NAME_MAX {
+            return Err(FsError::NameTooLong);
+        }
 
-                let special_inode = SpecialInode {
-                    mtime: now_sec,
-                    mtime_nsec: now_nsec,
-                    ctime: now_sec,
-                    ctime_nsec: now_nsec,
-                    atime: now_sec,
-                    atime_nsec: now_nsec,
-                    mode: final_mode,
+        dataset_ops::inode_properties(self, id)
+            .await?
+            .check_writable()?;
+        let _cow = clone::prepare_write(self, &[id]).await?;
+        let _guard = self.lock_manager.acquire_write(id).await;
+        destroy::check_attached(self, id).await?;
+        let mut inode = self.inode_store.get(id).await?;
+
+        self.check_parent_execute_permissions(id, creds).await?;
+        let mut xattrs = self.xattr_store.get(id).await?;
+        let acl = stored_acl(&xattrs, ACL_ACCESS_XATTR)?;
+        check_xattr_access(&inode, acl.as_ref(), creds, name, AccessMode::Write)?;
+
+        update(&mut inode, &mut xattrs)?;
+
+        let (now_sec, now_nsec) = get_current_time();
+        inode.set_ctime(now_sec, now_nsec);
+
+        let mut txn = self.db.new_transaction()?;
+        self.xattr_store.save(&mut txn, id, &xattrs)?;
+        self.inode_store.save(&mut txn, id, &inode)?;
+
+        if let Some(parent_id) = inode.parent()
+            && let Some(entry_name) = inode.name()
+        {
+            self.directory_store
+                .update_inode_in_entry(&mut txn, parent_id, entry_name, id, &inode)
+                .await?;
+        }
+
+        let mut seq_guard = self.write_coordinator.allocate_sequence();
+        self.commit_transaction(txn, &mut seq_guard).await
+    }
+
+    /// ACLs a new inode created with `mode` in directory `dirid` inherits
+    /// from the directory's default ACL, and its mode with them applied.
+    /// Directories also inherit the default ACL itself.
+    async fn inherit_acls(
+        &self,
+        dirid: InodeId,
+        mode: u32,
+        is_directory: bool,
+    ) -> Result<(u32, Xattrs), FsError> {
+        let mut xattrs = Xattrs::new();
+        let Some(default_acl) = self.xattr_store.get_acl(dirid, ACL_DEFAULT_XATTR).await? else {
+            return Ok((mode, xattrs));
+        };
+
+        let (access_acl, perm_bits) = default_acl.inherit(mode & 0o777);
+        if !access_acl.is_minimal() {
+            xattrs.insert(ACL_ACCESS_XATTR.to_vec(), access_acl.encode());
+        }
+        if is_directory {
+            xattrs.insert(ACL_DEFAULT_XATTR.to_vec(), default_acl.encode());
+        }
+        Ok(((mode & !0o777) | perm_bits, xattrs))
+    }
+
+    pub async fn mknod(
+        &self,
+        creds: &Credentials,
+        dirid: InodeId,
+        name: &[u8],
+        ftype: FileType,
+        attr: &SetAttributes,
+        rdev: Option<(u32, u32)>,
+    ) -> Result<(InodeId, FileAttributes), FsError> {
+        validate_filename(name)?;
+
+        debug!(
+            "mknod: dirid={}, filename={}, ftype={:?}",
+            dirid,
+            String::from_utf8_lossy(name),
+            ftype
+        );
+
+        let dataset = dataset_ops::dataset_of(self, dirid).await?;
+        let props = self.dataset_store.effective_properties(dataset).await;
+        props.check_writable()?;
+
+        let _cow = clone::prepare_write(self, &[dirid]).await?;
+        let _guard = self.lock_manager.acquire_write(dirid).await;
+        destroy::check_attached(self, dirid).await?;
+        let mut dir_inode = self.inode_store.get(dirid).await?;
+
+        self.check_inode_access(dirid, &dir_inode, creds, AccessMode::Write)
+            .await?;
+        self.check_inode_access(dirid, &dir_inode, creds, AccessMode::Execute)
+            .await?;
+
+        match &mut dir_inode {
+            Inode::Directory(dir) => {
+                if properties::name_taken(self, &props, dirid, name).await? {
+                    debug!("File already exists");
+                    return Err(FsError::Exists);
+                }
+
+                let account = InodeAccount {
+                    dataset,
                     uid: match attr.uid {
                         SetUid::Set(u) => u,
-                        _ => creds.uid,
+                        _ => props.default_uid.unwrap_or(creds.uid),
                     },
                     gid: match attr.gid {
                         SetGid::Set(g) => g,
-                        _ => creds.gid,
+                        _ => props.default_gid.unwrap_or(creds.gid),
                     },
+                };
+                quota::check_quota(self, account, 0, 1).await?;
+
+                let special_id = self.inode_store.allocate();
+                let (now_sec, now_nsec) = get_current_time();
+
+                let base_mode = match ftype {
+                    FileType::Fifo => 0o666,
+                    FileType::CharDevice | FileType::BlockDevice => 0o666,
+                    FileType::Socket => 0o666,
+                    _ => return Err(FsError::InvalidArgument),
+                };
+
+                let final_mode = if let SetMode::Set(m) = attr.mode {
+                    validate_mode(m)
+                } else {
+                    base_mode
+                };
+                let (final_mode, acl_xattrs) = self.inherit_acls(dirid, final_mode, false).await?;
+
+                let special_inode = SpecialInode {
+                    mtime: now_sec,
+                    mtime_nsec: now_nsec,
+                    ctime: now_sec,
+                    ctime_nsec: now_nsec,
+                    atime: now_sec,
+                    atime_nsec: now_nsec,
+                    mode: final_mode,
+                    uid: account.uid,
+                    gid: account.gid,
                     parent: Some(dirid),
                     name: Some(name.to_vec()),
                     nlink: 1,
@@ -2137,6 +2744,9 @@ impl ZeroFS {
                     .await?;
 
                 self.inode_store.save(&mut txn, special_id, &inode)?;
+                if !acl_xattrs.is_empty() {
+                    self.xattr_store.save(&mut txn, special_id, &acl_xattrs)?;
+                }
 
                 #[cfg(feature = "failpoints")]
                 fail_point!(fp::MKNOD_AFTER_INODE);
@@ -2164,7 +2774,10 @@ impl ZeroFS {
                         .ok();
                 }
 
-                let stats_update = self.global_stats.prepare_inode_create(special_id).await;
+                let stats_update = self
+                    .global_stats
+                    .prepare_inode_create(special_id, account)
+                    .await;
                 self.global_stats
                     .add_to_transaction(&stats_update, &mut txn)?;
 
@@ -2206,19 +2819,28 @@ impl ZeroFS {
 
         let creds = Credentials::from_auth_context(auth);
 
+        dataset_ops::dir_properties(self, dirid)
+            .await?
+            .check_writable()?;
+
+        let _cow = clone::prepare_write(self, &[dirid]).await?;
         let (file_id, cookie) = self
             .directory_store
             .get_entry_with_cookie(dirid, name)
             .await?;
+        clone::materialize_paths(self, &[file_id]).await?;
 
         let _guards = self
             .lock_manager
             .acquire_multiple_write(vec![dirid, file_id])
             .await;
+        destroy::check_attached(self, dirid).await?;
 
         let mut dir_inode = self.inode_store.get(dirid).await?;
-        check_access(&dir_inode, &creds, AccessMode::Write)?;
-        check_access(&dir_inode, &creds, AccessMode::Execute)?;
+        self.check_inode_access(dirid, &dir_inode, &creds, AccessMode::Write)
+            .await?;
+        self.check_inode_access(dirid, &dir_inode, &creds, AccessMode::Execute)
+            .await?;
 
         let is_dir = matches!(dir_inode, Inode::Directory(_));
         if !is_dir {
@@ -2267,13 +2889,24 @@ impl ZeroFS {
 
                             self.inode_store.save(&mut txn, file_id, &file_inode)?;
                         } else {
-                            let total_chunks = file.size.div_ceil(CHUNK_SIZE as u64);
+                            let total_chunks = file.chunk_count();
+                            let map_id = file.chunk_map_id(file_id);
+
+                            for layer in &file.backing {
+                                txn.remove_layer_ref(layer.map_id);
+                            }
 
                             if total_chunks as usize <= SMALL_FILE_TOMBSTONE_THRESHOLD {
                                 self.chunk_store
-                                    .delete_range(&mut txn, file_id, 0, total_chunks);
+                                    .delete_range(&mut txn, map_id, 0, total_chunks)
+                                    .await?;
                             } else {
-                                self.tombstone_store.add(&mut txn, file_id, file.size);
+                                self.tombstone_store.add(
+                                    &mut txn,
+                                    map_id,
+                                    file.size,
+                                    file.chunk_size,
+                                );
 
                                 #[cfg(feature = "failpoints")]
                                 fail_point!(fp::REMOVE_AFTER_TOMBSTONE);
@@ -2331,6 +2964,10 @@ impl ZeroFS {
 
                 self.directory_store
                     .unlink_entry(&mut txn, dirid, name, cookie);
+                if file_inode.parent().is_none() {
+                    self.directory_store
+                        .remove_link_dir(&mut txn, file_id, dirid, name);
+                }
 
                 #[cfg(feature = "failpoints")]
                 fail_point!(fp::REMOVE_AFTER_DIR_UNLINK);
@@ -2355,15 +2992,20 @@ impl ZeroFS {
                 // For directories and symlinks: always remove from stats
                 // For files and special files: only remove if this is the last link
                 let (file_size, should_always_remove_stats) = match &file_inode {
-                    Inode::File(f) => (Some(f.size), false),
+                    Inode::File(f) => (Some(f.allocated_bytes()), false),
                     Inode::Directory(_) | Inode::Symlink(_) => (None, true),
                     _ => (None, false),
                 };
+                let dataset = match &file_inode {
+                    Inode::File(f) => f.dataset,
+                    _ => dataset_ops::dataset_of(self, dirid).await?,
+                };
+                let account = InodeAccount::new(dataset, file_inode.ownership());
 
                 let stats_update = if should_always_remove_stats || original_nlink <= 1 {
                     Some(
                         self.global_stats
-                            .prepare_inode_remove(file_id, file_size)
+                            .prepare_inode_remove(file_id, account, file_size)
                             .await,
                     )
                 } else {
@@ -2434,6 +3076,16 @@ impl ZeroFS {
 
         let creds = Credentials::from_auth_context(auth);
 
+        let to_props = dataset_ops::dir_properties(self, to_dirid).await?;
+        to_props.check_writable()?;
+        if from_dirid != to_dirid {
+            dataset_ops::dir_properties(self, from_dirid)
+                .await?
+                .check_writable()?;
+        }
+
+        let _cow = clone::prepare_write(self, &[from_dirid, to_dirid]).await?;
+
         // Look up all inode IDs without holding any locks
         let (source_inode_id, source_cookie) = self
             .directory_store
@@ -2453,7 +3105,24 @@ impl ZeroFS {
             Err(FsError::NotFound) => None,
             Err(e) => return Err(e),
         };
+
+        // Names differing only in case clash in case-insensitive datasets,
+        // unless the rename only changes the case of the name
+        if target_entry.is_none()
+            && to_props.case_sensitivity == CaseSensitivity::Insensitive
+            && self
+                .directory_store
+                .find_case_variant(to_dirid, to_name)
+                .await?
+                .is_some_and(|(_, id)| id != source_inode_id)
+        {
+            return Err(FsError::Exists);
+        }
+
         let target_inode_id = target_entry.map(|(id, _)| id);
+        let mut renamed = vec![source_inode_id];
+        renamed.extend(target_inode_id);
+        clone::materialize_paths(self, &renamed).await?;
 
         let mut all_inodes_to_lock = vec![from_dirid, source_inode_id];
         if from_dirid != to_dirid {
@@ -2467,6 +3136,8 @@ impl ZeroFS {
             .lock_manager
             .acquire_multiple_write(all_inodes_to_lock)
             .await;
+        destroy::check_attached(self, from_dirid).await?;
+        destroy::check_attached(self, to_dirid).await?;
 
         // Re-verify inside lock that entries still point to same inodes
         let (verified_source_id, verified_source_cookie) = self
@@ -2505,15 +3176,25 @@ impl ZeroFS {
             None
         };
 
-        check_access(&from_dir, &creds, AccessMode::Write)?;
-        check_access(&from_dir, &creds, AccessMode::Execute)?;
+        self.check_inode_access(from_dirid, &from_dir, &creds, AccessMode::Write)
+            .await?;
+        self.check_inode_access(from_dirid, &from_dir, &creds, AccessMode::Execute)
+            .await?;
         if let Some(ref to_dir) = to_dir {
-            check_access(to_dir, &creds, AccessMode::Write)?;
-            check_access(to_dir, &creds, AccessMode::Execute)?;
+            self.check_inode_access(to_dirid, to_dir, &creds, AccessMode::Write)
+                .await?;
+            self.check_inode_access(to_dirid, to_dir, &creds, AccessMode::Execute)
+                .await?;
         }
 
         check_sticky_bit_delete(&from_dir, &source_inode, &creds)?;
 
+        // Usage is accounted per dataset, so entries cannot leave theirs
+        let dataset = dataset_ops::dataset_of(self, to_dirid).await?;
+        if from_dirid != to_dirid && dataset_ops::dataset_of(self, from_dirid).await? != dataset {
+            return Err(FsError::CrossDevice);
+        }
+
         // Capture old path before rename for tracing (name will change after)
         let trace_old_path = if self.tracer.has_subscribers() {
             Some(self.resolve_path_lossy(source_inode_id).await)
@@ -2570,13 +3251,18 @@ impl ZeroFS {
 
             let (original_nlink, original_file_size, should_always_remove_stats) =
                 match &existing_inode {
-                    Inode::File(f) => (f.nlink, Some(f.size), false),
+                    Inode::File(f) => (f.nlink, Some(f.allocated_bytes()), false),
                     Inode::Directory(_) | Inode::Symlink(_) => (1, None, true),
                     Inode::Fifo(s)
                     | Inode::Socket(s)
                     | Inode::CharDevice(s)
                     | Inode::BlockDevice(s) => (s.nlink, None, false),
                 };
+            let target_account = match &existing_inode {
+                Inode::File(f) => InodeAccount::new(f.dataset, (f.uid, f.gid)),
+                inode => InodeAccount::new(dataset, inode.ownership()),
+            };
+            let target_linked = existing_inode.parent().is_none();
 
             macro_rules! handle_special_file {
                 ($special:expr, $inode_variant:ident) => {
@@ -2608,18 +3294,7 @@ impl ZeroFS {
                         self.inode_store
                             .save(&mut txn, target_id, &Inode::File(file))?;
                     } else {
-                        let total_chunks = file.size.div_ceil(CHUNK_SIZE as u64);
-
-                        if total_chunks as usize <= SMALL_FILE_TOMBSTONE_THRESHOLD {
-                            self.chunk_store
-                                .delete_range(&mut txn, target_id, 0, total_chunks);
-                        } else {
-                            self.tombstone_store.add(&mut txn, target_id, file.size);
-                            self.stats
-                                .tombstones_created
-                                .fetch_add(1, Ordering::Relaxed);
-                        }
-
+                        self.release_file_data(&mut txn, target_id, &file).await?;
                         self.inode_store.delete(&mut txn, target_id);
                     }
                 }
@@ -2652,13 +3327,17 @@ impl ZeroFS {
             if should_always_remove_stats || original_nlink <= 1 {
                 target_stats_update = Some(
                     self.global_stats
-                        .prepare_inode_remove(target_id, original_file_size)
+                        .prepare_inode_remove(target_id, target_account, original_file_size)
                         .await,
                 );
             }
 
             self.directory_store
                 .unlink_entry(&mut txn, to_dirid, to_name, target_cookie.unwrap());
+            if target_linked {
+                self.directory_store
+                    .remove_link_dir(&mut txn, target_id, to_dirid, to_name);
+            }
         } else if same_inode {
             self.directory_store
                 .unlink_entry(&mut txn, to_dirid, to_name, target_cookie.unwrap());
@@ -2671,6 +3350,7 @@ impl ZeroFS {
         fail_point!(fp::RENAME_AFTER_SOURCE_UNLINK);
 
         let dir_changed = from_dirid != to_dirid;
+        let source_linked = source_inode.parent().is_none();
         let (now_sec, now_nsec) = get_current_time();
         match &mut source_inode {
             Inode::Directory(d) => {
@@ -2733,6 +3413,21 @@ impl ZeroFS {
             embed_inode,
         );
 
+        // An inode without a parent is found through the directories of its
+        // links, which move with them
+        if source_linked {
+            self.directory_store
+                .remove_link_dir(&mut txn, source_inode_id, from_dirid, from_name);
+        }
+        if same_inode {
+            self.directory_store
+                .remove_link_dir(&mut txn, source_inode_id, to_dirid, to_name);
+        }
+        if source_inode.parent().is_none() {
+            self.directory_store
+                .add_link_dir(&mut txn, source_inode_id, to_dirid, to_name);
+        }
+
         #[cfg(feature = "failpoints")]
         fail_point!(fp::RENAME_AFTER_NEW_ENTRY);
 
@@ -2857,7 +3552,10 @@ impl ZeroFS {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fs::clone;
+    use crate::fs::destroy;
     use crate::fs::inode::FileInode;
+    use crate::fs::key_codec::KeyPrefix;
     use crate::test_helpers::test_helpers_mod::test_auth;
 
     fn test_creds() -> Credentials {
@@ -2915,6 +3613,12 @@ mod tests {
             parent: Some(0),
             name: Some(b"test.txt".to_vec()),
             nlink: 1,
+            chunk_map: None,
+            backing: Vec::new(),
+            chunk_size: CHUNK_SIZE as u32,
+            encryption_dataset: None,
+            dataset: 0,
+            allocated_chunks: 1,
         };
 
         let inode = Inode::File(file_inode.clone());
@@ -3037,6 +3741,12 @@ mod tests {
             parent: Some(0),
             name: Some(b"test.txt".to_vec()),
             nlink: 1,
+            chunk_map: None,
+            backing: Vec::new(),
+            chunk_size: CHUNK_SIZE as u32,
+            encryption_dataset: None,
+            dataset: 0,
+            allocated_chunks: 1,
         };
         let mut txn = fs_rw.db.new_transaction().unwrap();
         fs_rw
@@ -4389,4 +5099,2610 @@ mod tests {
         assert_eq!(original.attr.nlink, 2);
         assert_eq!(hardlink.attr.nlink, 2);
     }
+
+    async fn count_keys(fs: &ZeroFS, prefix: KeyPrefix) -> usize {
+        let (start, end) = KeyCodec::prefix_range(prefix);
+        let iter = fs.db.scan(start..end).await.unwrap();
+        futures::pin_mut!(iter);
+        let mut count = 0;
+        while let Some(result) = futures::StreamExt::next(&mut iter).await {
+            result.unwrap();
+            count += 1;
+        }
+        count
+    }
+
+    #[tokio::test]
+    async fn test_identical_chunks_stored_once() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let data = Bytes::from(vec![b'Z'; CHUNK_SIZE * 2]);
+
+        for name in [b"a.bin".as_slice(), b"b.bin".as_slice()] {
+            let (file_id, _) = fs
+                .create(&test_creds(), 0, name, &SetAttributes::default())
+                .await
+                .unwrap();
+            fs.write(&(&test_auth()).into(), file_id, 0, &data)
+                .await
+                .unwrap();
+        }
+
+        // Four chunk map entries, all pointing at one chunk body
+        assert_eq!(count_keys(&fs, KeyPrefix::Chunk).await, 4);
+        assert_eq!(count_keys(&fs, KeyPrefix::ChunkCas).await, 1);
+
+        let hash = fs.db.content_hash_in(None, &data[..CHUNK_SIZE]).unwrap();
+        let refcount = fs
+            .db
+            .get_bytes(&KeyCodec::chunk_refcount_key(&hash))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(KeyCodec::decode_counter(&refcount).unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_shared_chunk_freed_after_last_reference() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let data = Bytes::from(vec![b'Q'; 1024]);
+
+        let (a_id, _) = fs
+            .create(&test_creds(), 0, b"a.txt", &SetAttributes::default())
+            .await
+            .unwrap();
+        let (b_id, _) = fs
+            .create(&test_creds(), 0, b"b.txt", &SetAttributes::default())
+            .await
+            .unwrap();
+        fs.write(&(&test_auth()).into(), a_id, 0, &data)
+            .await
+            .unwrap();
+        fs.write(&(&test_auth()).into(), b_id, 0, &data)
+            .await
+            .unwrap();
+
+        let gc = GarbageCollector::new(
+            Arc::clone(&fs.db),
+            fs.tombstone_store.clone(),
+            fs.chunk_store.clone(),
+            Arc::clone(&fs.stats),
+            Arc::clone(&fs.write_coordinator),
+        );
+
+        fs.remove(&(&test_auth()).into(), 0, b"a.txt")
+            .await
+            .unwrap();
+        gc.run().await.unwrap();
+
+        assert_eq!(count_keys(&fs, KeyPrefix::ChunkCas).await, 1);
+        let (read_data, _) = fs
+            .read_file(&(&test_auth()).into(), b_id, 0, 1024)
+            .await
+            .unwrap();
+        assert_eq!(read_data, data);
+
+        fs.remove(&(&test_auth()).into(), 0, b"b.txt")
+            .await
+            .unwrap();
+        assert_eq!(count_keys(&fs, KeyPrefix::ChunkOrphan).await, 1);
+
+        gc.run().await.unwrap();
+
+        assert_eq!(count_keys(&fs, KeyPrefix::ChunkCas).await, 0);
+        assert_eq!(count_keys(&fs, KeyPrefix::ChunkRefCount).await, 0);
+        assert_eq!(count_keys(&fs, KeyPrefix::ChunkOrphan).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_space_usage_of_chunk_bodies() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let data = Bytes::from(vec![b'S'; 16_000]);
+
+        for name in [&b"a.txt"[..], b"b.txt"] {
+            let (id, _) = fs
+                .create(&test_creds(), 0, name, &SetAttributes::default())
+                .await
+                .unwrap();
+            fs.write(&(&test_auth()).into(), id, 0, &data)
+                .await
+                .unwrap();
+        }
+
+        // Both files share one chunk body, which counts once
+        let space = fs.db.space_stats().totals();
+        assert_eq!(space.logical, 16_000);
+        assert!(space.compressed < space.logical);
+        assert!(space.stored > space.compressed);
+        assert_eq!(fs.db.space_stats().get(dataset::ROOT_DATASET_ID), space);
+        assert_eq!(
+            count_keys(&fs, KeyPrefix::ChunkFootprint).await,
+            count_keys(&fs, KeyPrefix::ChunkCas).await
+        );
+
+        let gc = GarbageCollector::new(
+            Arc::clone(&fs.db),
+            fs.tombstone_store.clone(),
+            fs.chunk_store.clone(),
+            Arc::clone(&fs.stats),
+            Arc::clone(&fs.write_coordinator),
+        );
+        for name in [&b"a.txt"[..], b"b.txt"] {
+            fs.remove(&(&test_auth()).into(), 0, name).await.unwrap();
+        }
+
+        // Orphaned bodies are still stored until collected
+        assert_eq!(fs.db.space_stats().totals(), space);
+
+        gc.run().await.unwrap();
+
+        assert_eq!(fs.db.space_stats().totals(), stats::SpaceUsage::default());
+        assert_eq!(count_keys(&fs, KeyPrefix::ChunkFootprint).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_overwrite_releases_previous_chunk() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+
+        let (file_id, _) = fs
+            .create(&test_creds(), 0, b"test.txt", &SetAttributes::default())
+            .await
+            .unwrap();
+        fs.write(
+            &(&test_auth()).into(),
+            file_id,
+            0,
+            &Bytes::from(vec![1u8; 100]),
+        )
+        .await
+        .unwrap();
+        fs.write(
+            &(&test_auth()).into(),
+            file_id,
+            0,
+            &Bytes::from(vec![2u8; 100]),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(count_keys(&fs, KeyPrefix::ChunkRefCount).await, 1);
+        assert_eq!(count_keys(&fs, KeyPrefix::ChunkOrphan).await, 1);
+
+        let (read_data, _) = fs
+            .read_file(&(&test_auth()).into(), file_id, 0, 100)
+            .await
+            .unwrap();
+        assert_eq!(read_data.as_ref(), &[2u8; 100]);
+    }
+
+    async fn read_all(fs: &ZeroFS, id: InodeId, len: usize) -> Bytes {
+        let (data, _) = fs
+            .read_file(&(&test_auth()).into(), id, 0, len as u32)
+            .await
+            .unwrap();
+        data
+    }
+
+    #[tokio::test]
+    async fn test_clone_file_shares_chunks() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let data = Bytes::from(vec![b'R'; CHUNK_SIZE * 3]);
+
+        let (src_id, _) = fs
+            .create(&test_creds(), 0, b"src.bin", &SetAttributes::default())
+            .await
+            .unwrap();
+        fs.write(&(&test_auth()).into(), src_id, 0, &data)
+            .await
+            .unwrap();
+        let chunk_entries = count_keys(&fs, KeyPrefix::Chunk).await;
+
+        let (clone_id, _) = clone::clone_entry(&fs, src_id, 0, b"clone.bin")
+            .await
+            .unwrap();
+        assert_eq!(count_keys(&fs, KeyPrefix::Chunk).await, chunk_entries);
+        assert_eq!(read_all(&fs, clone_id, data.len()).await, data);
+
+        fs.write(
+            &(&test_auth()).into(),
+            clone_id,
+            10,
+            &Bytes::from_static(b"clone"),
+        )
+        .await
+        .unwrap();
+        fs.write(
+            &(&test_auth()).into(),
+            src_id,
+            CHUNK_SIZE as u64,
+            &Bytes::from_static(b"source"),
+        )
+        .await
+        .unwrap();
+
+        let mut expected_src = data.to_vec();
+        expected_src[CHUNK_SIZE..CHUNK_SIZE + 6].copy_from_slice(b"source");
+        let mut expected_clone = data.to_vec();
+        expected_clone[10..15].copy_from_slice(b"clone");
+
+        assert_eq!(read_all(&fs, src_id, data.len()).await, expected_src);
+        assert_eq!(read_all(&fs, clone_id, data.len()).await, expected_clone);
+        // Only the chunk each side wrote was copied
+        assert_eq!(count_keys(&fs, KeyPrefix::Chunk).await, chunk_entries + 2);
+    }
+
+    #[tokio::test]
+    async fn test_clone_file_truncate_and_zero_hide_backing_data() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let data = Bytes::from(vec![b'T'; CHUNK_SIZE * 2]);
+
+        let (src_id, _) = fs
+            .create(&test_creds(), 0, b"src.bin", &SetAttributes::default())
+            .await
+            .unwrap();
+        fs.write(&(&test_auth()).into(), src_id, 0, &data)
+            .await
+            .unwrap();
+        let (clone_id, _) = clone::clone_entry(&fs, src_id, 0, b"clone.bin")
+            .await
+            .unwrap();
+
+        // Shrinking and growing the clone must not expose the shared data again
+        for size in [100, data.len() as u64] {
+            let setattr = SetAttributes {
+                size: SetSize::Set(size),
+                ..Default::default()
+            };
+            fs.setattr(&test_creds(), clone_id, &setattr).await.unwrap();
+        }
+        let mut expected_clone = vec![0u8; data.len()];
+        expected_clone[..100].copy_from_slice(&data[..100]);
+        assert_eq!(read_all(&fs, clone_id, data.len()).await, expected_clone);
+
+        // Zeroing a shared chunk in the source leaves a hole, not the old data
+        fs.write(
+            &(&test_auth()).into(),
+            src_id,
+            0,
+            &Bytes::from(vec![0u8; CHUNK_SIZE]),
+        )
+        .await
+        .unwrap();
+        let mut expected_src = data.to_vec();
+        expected_src[..CHUNK_SIZE].fill(0);
+        assert_eq!(read_all(&fs, src_id, data.len()).await, expected_src);
+        assert_eq!(read_all(&fs, clone_id, data.len()).await, expected_clone);
+    }
+
+    #[tokio::test]
+    async fn test_clone_file_backing_layers_are_capped() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let size = CHUNK_SIZE * 20;
+
+        let (src_id, _) = fs
+            .create(&test_creds(), 0, b"src.bin", &SetAttributes::default())
+            .await
+            .unwrap();
+        fs.write(
+            &(&test_auth()).into(),
+            src_id,
+            0,
+            &Bytes::from(vec![b'A'; size]),
+        )
+        .await
+        .unwrap();
+
+        // Each clone freezes a layer holding one more chunk written since
+        let mut expected = vec![b'A'; size];
+        let mut clones = Vec::new();
+        for i in 0..20 {
+            let name = format!("clone{}.bin", i);
+            let (clone_id, _) = clone::clone_entry(&fs, src_id, 0, name.as_bytes())
+                .await
+                .unwrap();
+            clones.push((clone_id, expected.clone()));
+
+            let offset = i * CHUNK_SIZE;
+            fs.write(
+                &(&test_auth()).into(),
+                src_id,
+                offset as u64,
+                &Bytes::from(vec![b'a' + i as u8; CHUNK_SIZE]),
+            )
+            .await
+            .unwrap();
+            expected[offset..offset + CHUNK_SIZE].fill(b'a' + i as u8);
+
+            match fs.inode_store.get(src_id).await.unwrap() {
+                Inode::File(file) => assert!(file.backing.len() <= clone::MAX_BACKING_LAYERS),
+                _ => panic!("Expected file inode"),
+            }
+        }
+
+        assert_eq!(read_all(&fs, src_id, size).await, expected);
+        for (clone_id, expected) in &clones {
+            assert_eq!(&read_all(&fs, *clone_id, size).await, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cloned_chunks_freed_after_last_clone_removed() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let data = Bytes::from(vec![b'G'; CHUNK_SIZE]);
+
+        let (src_id, _) = fs
+            .create(&test_creds(), 0, b"src.bin", &SetAttributes::default())
+            .await
+            .unwrap();
+        fs.write(&(&test_auth()).into(), src_id, 0, &data)
+            .await
+            .unwrap();
+        let (clone_id, _) = clone::clone_entry(&fs, src_id, 0, b"clone.bin")
+            .await
+            .unwrap();
+        assert_eq!(count_keys(&fs, KeyPrefix::ChunkLayer).await, 1);
+
+        let gc = GarbageCollector::new(
+            Arc::clone(&fs.db),
+            fs.tombstone_store.clone(),
+            fs.chunk_store.clone(),
+            Arc::clone(&fs.stats),
+            Arc::clone(&fs.write_coordinator),
+        );
+
+        fs.remove(&(&test_auth()).into(), 0, b"src.bin")
+            .await
+            .unwrap();
+        gc.run().await.unwrap();
+        assert_eq!(read_all(&fs, clone_id, data.len()).await, data);
+
+        fs.remove(&(&test_auth()).into(), 0, b"clone.bin")
+            .await
+            .unwrap();
+        assert_eq!(count_keys(&fs, KeyPrefix::ChunkLayer).await, 0);
+
+        gc.run().await.unwrap();
+
+        assert_eq!(count_keys(&fs, KeyPrefix::Chunk).await, 0);
+        assert_eq!(count_keys(&fs, KeyPrefix::ChunkCas).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_clone_directory_copies_entries_lazily() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let auth = (&test_auth()).into();
+
+        let (src_id, _) = fs
+            .mkdir(&test_creds(), 0, b"src", &SetAttributes::default())
+            .await
+            .unwrap();
+        let (sub_id, _) = fs
+            .mkdir(&test_creds(), src_id, b"sub", &SetAttributes::default())
+            .await
+            .unwrap();
+        let (a_id, _) = fs
+            .create(&test_creds(), src_id, b"a", &SetAttributes::default())
+            .await
+            .unwrap();
+        let (b_id, _) = fs
+            .create(&test_creds(), sub_id, b"b", &SetAttributes::default())
+            .await
+            .unwrap();
+        fs.write(&auth, a_id, 0, &Bytes::from_static(b"old a"))
+            .await
+            .unwrap();
+        fs.write(&auth, b_id, 0, &Bytes::from_static(b"old b"))
+            .await
+            .unwrap();
+        let dir_entries = count_keys(&fs, KeyPrefix::DirEntry).await;
+
+        let (snap_id, _) = clone::clone_entry(&fs, src_id, 0, b"snap").await.unwrap();
+        assert_eq!(count_keys(&fs, KeyPrefix::LazyDir).await, 1);
+        assert_eq!(count_keys(&fs, KeyPrefix::DirEntry).await, dir_entries + 1);
+
+        // Writing below the source copies the clone's entries first
+        fs.write(&auth, b_id, 0, &Bytes::from_static(b"new b"))
+            .await
+            .unwrap();
+        fs.remove(&auth, src_id, b"a").await.unwrap();
+        assert_eq!(count_keys(&fs, KeyPrefix::LazyDir).await, 0);
+
+        let listing = fs.readdir(&auth, snap_id, 0, 10).await.unwrap();
+        let mut names: Vec<&[u8]> = listing.entries[2..]
+            .iter()
+            .map(|e| e.name.as_ref())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec![b"a".as_ref(), b"sub".as_ref()]);
+
+        let snap_a = fs.lookup(&test_creds(), snap_id, b"a").await.unwrap();
+        let snap_sub = fs.lookup(&test_creds(), snap_id, b"sub").await.unwrap();
+        let snap_b = fs.lookup(&test_creds(), snap_sub, b"b").await.unwrap();
+        assert_ne!(snap_b, b_id);
+        assert_eq!(read_all(&fs, snap_a, 5).await.as_ref(), b"old a");
+        assert_eq!(read_all(&fs, snap_b, 5).await.as_ref(), b"old b");
+
+        // And writing to the clone leaves the source alone
+        fs.write(&auth, snap_b, 0, &Bytes::from_static(b"cln b"))
+            .await
+            .unwrap();
+        assert_eq!(read_all(&fs, b_id, 5).await.as_ref(), b"new b");
+    }
+
+    #[tokio::test]
+    async fn test_clone_of_unused_clone() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let auth = (&test_auth()).into();
+
+        let (src_id, _) = fs
+            .mkdir(&test_creds(), 0, b"src", &SetAttributes::default())
+            .await
+            .unwrap();
+        let (file_id, _) = fs
+            .create(&test_creds(), src_id, b"f", &SetAttributes::default())
+            .await
+            .unwrap();
+        fs.write(&auth, file_id, 0, &Bytes::from_static(b"one"))
+            .await
+            .unwrap();
+
+        let (snap1_id, _) = clone::clone_entry(&fs, src_id, 0, b"snap1").await.unwrap();
+        let (snap2_id, _) = clone::clone_entry(&fs, snap1_id, 0, b"snap2")
+            .await
+            .unwrap();
+        fs.write(&auth, file_id, 0, &Bytes::from_static(b"two"))
+            .await
+            .unwrap();
+
+        for dir_id in [snap1_id, snap2_id] {
+            let id = fs.lookup(&test_creds(), dir_id, b"f").await.unwrap();
+            assert_eq!(read_all(&fs, id, 3).await.as_ref(), b"one");
+        }
+        assert_eq!(count_keys(&fs, KeyPrefix::LazyDir).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_reading_clone_leaves_it_lazy() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let auth = (&test_auth()).into();
+
+        let (src_id, _) = fs
+            .mkdir(&test_creds(), 0, b"src", &SetAttributes::default())
+            .await
+            .unwrap();
+        let (file_id, _) = fs
+            .create(&test_creds(), src_id, b"f", &SetAttributes::default())
+            .await
+            .unwrap();
+        fs.write(&auth, file_id, 0, &Bytes::from_static(b"one"))
+            .await
+            .unwrap();
+
+        let (snap_id, _) = clone::clone_entry(&fs, src_id, 0, b"snap").await.unwrap();
+        let listing = fs.readdir(&auth, snap_id, 0, 10).await.unwrap();
+        assert_eq!(listing.entries.len(), 3);
+        assert_eq!(&listing.entries[2].name[..], b"f");
+        let id = fs.lookup(&test_creds(), snap_id, b"f").await.unwrap();
+        assert_eq!(read_all(&fs, id, 3).await.as_ref(), b"one");
+        assert_eq!(fs.lazy_dirs.origin(snap_id), Some(src_id));
+        assert_eq!(count_keys(&fs, KeyPrefix::LazyDir).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_write_through_clone_lookup_goes_to_copy() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let auth = (&test_auth()).into();
+
+        let (src_id, _) = fs
+            .mkdir(&test_creds(), 0, b"src", &SetAttributes::default())
+            .await
+            .unwrap();
+        let (sub_id, _) = fs
+            .mkdir(&test_creds(), src_id, b"sub", &SetAttributes::default())
+            .await
+            .unwrap();
+        let (file_id, _) = fs
+            .create(&test_creds(), sub_id, b"f", &SetAttributes::default())
+            .await
+            .unwrap();
+        fs.write(&auth, file_id, 0, &Bytes::from_static(b"old"))
+            .await
+            .unwrap();
+
+        let snap_id = clone::snapshot_dir(&fs, src_id).await.unwrap();
+        let (copy_id, _) = clone::clone_entry(&fs, src_id, 0, b"copy").await.unwrap();
+
+        // Entries read through the clone have IDs of their own, which stay
+        // the same across lookups and listings
+        let copy_sub = fs.lookup(&test_creds(), copy_id, b"sub").await.unwrap();
+        assert_ne!(copy_sub, sub_id);
+        let listing = fs.readdir(&auth, copy_id, 0, 10).await.unwrap();
+        assert_eq!(listing.entries[2].fileid, copy_sub);
+        let copy_f = fs.lookup(&test_creds(), copy_sub, b"f").await.unwrap();
+        assert_ne!(copy_f, file_id);
+        assert_eq!(read_all(&fs, copy_f, 3).await.as_ref(), b"old");
+        assert_eq!(fs.lazy_dirs.origin(copy_id), Some(src_id));
+
+        fs.write(&auth, copy_f, 0, &Bytes::from_static(b"new"))
+            .await
+            .unwrap();
+        assert_eq!(read_all(&fs, copy_f, 3).await.as_ref(), b"new");
+        assert_eq!(
+            fs.lookup(&test_creds(), copy_sub, b"f").await.unwrap(),
+            copy_f
+        );
+        assert_eq!(read_all(&fs, file_id, 3).await.as_ref(), b"old");
+        let snap_sub = fs.lookup(&test_creds(), snap_id, b"sub").await.unwrap();
+        let snap_f = fs.lookup(&test_creds(), snap_sub, b"f").await.unwrap();
+        assert_ne!(snap_f, file_id);
+        assert_eq!(read_all(&fs, snap_f, 3).await.as_ref(), b"old");
+    }
+
+    #[tokio::test]
+    async fn test_clone_keeps_hard_linked_files() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let auth = (&test_auth()).into();
+
+        let (src_id, _) = fs
+            .mkdir(&test_creds(), 0, b"src", &SetAttributes::default())
+            .await
+            .unwrap();
+        let (sub_id, _) = fs
+            .mkdir(&test_creds(), src_id, b"sub", &SetAttributes::default())
+            .await
+            .unwrap();
+        let (file_id, _) = fs
+            .create(&test_creds(), src_id, b"f", &SetAttributes::default())
+            .await
+            .unwrap();
+        fs.write(&auth, file_id, 0, &Bytes::from_static(b"one"))
+            .await
+            .unwrap();
+        fs.link(&auth, file_id, sub_id, b"g").await.unwrap();
+
+        // The file has no parent, so the clone is found through its links
+        let (snap_id, _) = clone::clone_entry(&fs, src_id, 0, b"snap").await.unwrap();
+        fs.write(&auth, file_id, 0, &Bytes::from_static(b"two"))
+            .await
+            .unwrap();
+        let snap_f = fs.lookup(&test_creds(), snap_id, b"f").await.unwrap();
+        let snap_sub = fs.lookup(&test_creds(), snap_id, b"sub").await.unwrap();
+        let snap_g = fs.lookup(&test_creds(), snap_sub, b"g").await.unwrap();
+        assert_ne!(snap_f, file_id);
+        assert_ne!(snap_g, file_id);
+        assert_eq!(read_all(&fs, snap_f, 3).await.as_ref(), b"one");
+        assert_eq!(read_all(&fs, snap_g, 3).await.as_ref(), b"one");
+
+        // A link keeps being found once moved, and no longer once removed
+        let (other_id, _) = fs
+            .mkdir(&test_creds(), 0, b"other", &SetAttributes::default())
+            .await
+            .unwrap();
+        fs.rename(&auth, sub_id, b"g", other_id, b"g")
+            .await
+            .unwrap();
+        let (snap2_id, _) = clone::clone_entry(&fs, other_id, 0, b"snap2")
+            .await
+            .unwrap();
+        fs.write(&auth, file_id, 0, &Bytes::from_static(b"tri"))
+            .await
+            .unwrap();
+        let snap2_g = fs.lookup(&test_creds(), snap2_id, b"g").await.unwrap();
+        assert_eq!(read_all(&fs, snap2_g, 3).await.as_ref(), b"two");
+        assert_eq!(
+            fs.directory_store.link_dirs(file_id).await.unwrap(),
+            vec![src_id, other_id]
+        );
+        fs.remove(&auth, other_id, b"g").await.unwrap();
+        assert_eq!(
+            fs.directory_store.link_dirs(file_id).await.unwrap(),
+            vec![src_id]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_clone_directory_into_itself_rejected() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+
+        let (src_id, _) = fs
+            .mkdir(&test_creds(), 0, b"src", &SetAttributes::default())
+            .await
+            .unwrap();
+        let (sub_id, _) = fs
+            .mkdir(&test_creds(), src_id, b"sub", &SetAttributes::default())
+            .await
+            .unwrap();
+
+        let result = clone::clone_entry(&fs, src_id, sub_id, b"loop").await;
+        assert!(matches!(result, Err(FsError::InvalidArgument)));
+    }
+
+    #[tokio::test]
+    async fn test_files_keep_their_chunk_size() {
+        let mut fs = ZeroFS::new_in_memory().await.unwrap();
+        let auth = (&test_auth()).into();
+        let data = Bytes::from((0..CHUNK_SIZE * 4).map(|i| i as u8).collect::<Vec<_>>());
+
+        let (old_id, _) = fs
+            .create(&test_creds(), 0, b"old.bin", &SetAttributes::default())
+            .await
+            .unwrap();
+        fs.write(&auth, old_id, 0, &data).await.unwrap();
+        assert_eq!(count_keys(&fs, KeyPrefix::Chunk).await, 4);
+
+        fs.chunk_size = CHUNK_SIZE * 8;
+        let (new_id, _) = fs
+            .create(&test_creds(), 0, b"new.bin", &SetAttributes::default())
+            .await
+            .unwrap();
+        fs.write(&auth, new_id, 100, &data).await.unwrap();
+        assert_eq!(count_keys(&fs, KeyPrefix::Chunk).await, 5);
+
+        let Inode::File(new_file) = fs.inode_store.get(new_id).await.unwrap() else {
+            panic!("Expected File inode");
+        };
+        assert_eq!(new_file.chunk_size as usize, CHUNK_SIZE * 8);
+        let Inode::File(old_file) = fs.inode_store.get(old_id).await.unwrap() else {
+            panic!("Expected File inode");
+        };
+        assert_eq!(old_file.chunk_size as usize, CHUNK_SIZE);
+
+        assert_eq!(read_all(&fs, old_id, data.len()).await, data);
+        let read = read_all(&fs, new_id, data.len() + 100).await;
+        assert_eq!(&read[..100], &[0u8; 100]);
+        assert_eq!(&read[100..], &data[..]);
+
+        // A clone shares the chunk size along with the data
+        let (clone_id, _) = clone::clone_entry(&fs, new_id, 0, b"clone.bin")
+            .await
+            .unwrap();
+        let setattr = SetAttributes {
+            size: SetSize::Set(CHUNK_SIZE as u64),
+            ..Default::default()
+        };
+        fs.setattr(&test_creds(), clone_id, &setattr).await.unwrap();
+        let read = read_all(&fs, clone_id, CHUNK_SIZE).await;
+        assert_eq!(&read[100..], &data[..CHUNK_SIZE - 100]);
+        let read = read_all(&fs, new_id, data.len() + 100).await;
+        assert_eq!(&read[100..], &data[..]);
+
+        let gc = GarbageCollector::new(
+            Arc::clone(&fs.db),
+            fs.tombstone_store.clone(),
+            fs.chunk_store.clone(),
+            Arc::clone(&fs.stats),
+            Arc::clone(&fs.write_coordinator),
+        );
+        for name in [&b"old.bin"[..], b"new.bin", b"clone.bin"] {
+            fs.remove(&auth, 0, name).await.unwrap();
+        }
+        gc.run().await.unwrap();
+        assert_eq!(count_keys(&fs, KeyPrefix::Chunk).await, 0);
+        assert_eq!(count_keys(&fs, KeyPrefix::ChunkCas).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_dataset_key_shreds_data() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let auth = (&test_auth()).into();
+        let data = Bytes::from(vec![b'T'; CHUNK_SIZE * 2]);
+
+        let dataset = dataset_ops::create_dataset(&fs, "tenant", true)
+            .await
+            .unwrap();
+        assert!(dataset.wrapped_key.is_some());
+
+        let (plain_id, _) = fs
+            .create(&test_creds(), 0, b"plain.bin", &SetAttributes::default())
+            .await
+            .unwrap();
+        fs.write(&auth, plain_id, 0, &data).await.unwrap();
+        // The dataset root belongs to root
+        let root_creds = Credentials {
+            uid: 0,
+            gid: 0,
+            groups: [0; 16],
+            groups_count: 1,
+        };
+        let attr = SetAttributes {
+            uid: SetUid::Set(1000),
+            gid: SetGid::Set(1000),
+            ..Default::default()
+        };
+        let (file_id, _) = fs
+            .create(&root_creds, dataset.root_inode, b"secret.bin", &attr)
+            .await
+            .unwrap();
+        fs.write(&auth, file_id, 0, &data).await.unwrap();
+
+        // Same data, but not deduplicated across keys
+        assert_eq!(count_keys(&fs, KeyPrefix::ChunkCas).await, 2);
+        assert_eq!(read_all(&fs, file_id, data.len()).await, data);
+
+        dataset_ops::delete_dataset(&fs, dataset.id).await.unwrap();
+        assert!(
+            fs.read_file(&auth, file_id, 0, data.len() as u32)
+                .await
+                .is_err()
+        );
+        assert_eq!(read_all(&fs, plain_id, data.len()).await, data);
+    }
+
+    #[tokio::test]
+    async fn test_dataset_quota() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let auth = (&test_auth()).into();
+        let root = AuthContext {
+            uid: 0,
+            gid: 0,
+            gids: vec![0],
+        };
+        let root_creds = Credentials::from_auth_context(&root);
+
+        let dataset = dataset_ops::create_dataset(&fs, "tenant", false)
+            .await
+            .unwrap();
+        fs.dataset_store
+            .set_quota(
+                dataset.id,
+                dataset::DatasetQuota {
+                    bytes_hard: Some(1000),
+                    inodes_hard: Some(2),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let (file_id, _) = fs
+            .create(
+                &root_creds,
+                dataset.root_inode,
+                b"a.bin",
+                &SetAttributes::default(),
+            )
+            .await
+            .unwrap();
+        fs.write(&root, file_id, 0, &Bytes::from(vec![1; 600]))
+            .await
+            .unwrap();
+        assert!(matches!(
+            fs.write(&root, file_id, 600, &Bytes::from(vec![1; 600]))
+                .await,
+            Err(FsError::QuotaExceeded)
+        ));
+
+        let usage = dataset_ops::dataset_usage(&fs, dataset.id).await;
+        assert_eq!(usage.bytes, 600);
+        assert_eq!(usage.inodes, 1);
+
+        // The root dataset is not limited, and does not count the tenant's usage
+        let (plain_id, _) = fs
+            .create(&test_creds(), 0, b"plain.bin", &SetAttributes::default())
+            .await
+            .unwrap();
+        fs.write(&auth, plain_id, 0, &Bytes::from(vec![1; 2000]))
+            .await
+            .unwrap();
+        let root_usage = dataset_ops::dataset_usage(&fs, dataset::ROOT_DATASET_ID).await;
+        assert_eq!(root_usage.bytes, 2000);
+
+        fs.mkdir(
+            &root_creds,
+            dataset.root_inode,
+            b"dir",
+            &SetAttributes::default(),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            fs.create(
+                &root_creds,
+                dataset.root_inode,
+                b"b.bin",
+                &SetAttributes::default()
+            )
+            .await,
+            Err(FsError::QuotaExceeded)
+        ));
+
+        // Files cannot move or be linked across datasets
+        assert!(matches!(
+            fs.rename(&root, dataset.root_inode, b"a.bin", 0, b"a.bin")
+                .await,
+            Err(FsError::CrossDevice)
+        ));
+        assert!(matches!(
+            fs.link(&root, file_id, 0, b"a.bin").await,
+            Err(FsError::CrossDevice)
+        ));
+
+        fs.remove(&root, dataset.root_inode, b"a.bin")
+            .await
+            .unwrap();
+        let usage = dataset_ops::dataset_usage(&fs, dataset.id).await;
+        assert_eq!(usage.bytes, 0);
+        assert_eq!(usage.inodes, 1);
+    }
+
+    #[tokio::test]
+    async fn test_dataset_quota_counts_filled_holes() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let root = AuthContext {
+            uid: 0,
+            gid: 0,
+            gids: vec![0],
+        };
+        let root_creds = Credentials::from_auth_context(&root);
+        let chunk = CHUNK_SIZE as u64;
+
+        let dataset = dataset_ops::create_dataset(&fs, "tenant", false)
+            .await
+            .unwrap();
+        fs.dataset_store
+            .set_quota(
+                dataset.id,
+                dataset::DatasetQuota {
+                    bytes_hard: Some(2 * chunk),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        let (file_id, _) = fs
+            .create(
+                &root_creds,
+                dataset.root_inode,
+                b"sparse.bin",
+                &SetAttributes::default(),
+            )
+            .await
+            .unwrap();
+
+        // A sparse file takes no space, whatever its size
+        let setattr = SetAttributes {
+            size: SetSize::Set(8 * chunk),
+            ..Default::default()
+        };
+        fs.setattr(&root_creds, file_id, &setattr).await.unwrap();
+
+        // Filling its holes does, though the size does not change
+        let data = Bytes::from(vec![1; CHUNK_SIZE]);
+        fs.write(&root, file_id, 0, &data).await.unwrap();
+        fs.write(&root, file_id, 3 * chunk, &data).await.unwrap();
+        assert!(matches!(
+            fs.write(&root, file_id, 5 * chunk, &data).await,
+            Err(FsError::QuotaExceeded)
+        ));
+        let usage = dataset_ops::dataset_usage(&fs, dataset.id).await;
+        assert_eq!(usage.bytes, 2 * chunk);
+
+        // Overwriting allocated data takes nothing more
+        fs.write(&root, file_id, 0, &Bytes::from(vec![2; CHUNK_SIZE]))
+            .await
+            .unwrap();
+        let (read, _) = fs.read_file(&root, file_id, 0, 1).await.unwrap();
+        assert_eq!(read.as_ref(), &[2]);
+    }
+
+    #[tokio::test]
+    async fn test_dataset_destroy_reclaims_tree() {
+        let fs = Arc::new(ZeroFS::new_in_memory().await.unwrap());
+        let root = AuthContext {
+            uid: 0,
+            gid: 0,
+            gids: vec![0],
+        };
+        let root_creds = Credentials::from_auth_context(&root);
+        let data = Bytes::from(vec![b'D'; CHUNK_SIZE * (SMALL_FILE_TOMBSTONE_THRESHOLD + 2)]);
+        let (_, inodes_before) = fs.global_stats.get_totals();
+
+        let dataset = dataset_ops::create_dataset(&fs, "tenant", false)
+            .await
+            .unwrap();
+        let (sub_id, _) = fs
+            .mkdir(
+                &root_creds,
+                dataset.root_inode,
+                b"sub",
+                &SetAttributes::default(),
+            )
+            .await
+            .unwrap();
+        let (big_id, _) = fs
+            .create(&root_creds, sub_id, b"big.bin", &SetAttributes::default())
+            .await
+            .unwrap();
+        fs.write(&root, big_id, 0, &data).await.unwrap();
+        let (small_id, _) = fs
+            .create(
+                &root_creds,
+                dataset.root_inode,
+                b"small.bin",
+                &SetAttributes::default(),
+            )
+            .await
+            .unwrap();
+        fs.write(&root, small_id, 0, &data.slice(..100))
+            .await
+            .unwrap();
+
+        // Clones outside the dataset keep the data they share with it
+        let (clone_id, _) = clone::clone_entry(&fs, big_id, 0, b"clone.bin")
+            .await
+            .unwrap();
+        let (dir_clone_id, _) = clone::clone_entry(&fs, sub_id, 0, b"sub-clone")
+            .await
+            .unwrap();
+
+        let progress = dataset_ops::delete_dataset(&fs, dataset.id)
+            .await
+            .unwrap();
+        assert_eq!(progress.total_inodes, 3);
+        assert!(fs.dataset_store.get_by_name("tenant").await.is_none());
+        assert!(matches!(
+            fs.directory_store.get(0, b"tenant").await,
+            Err(FsError::NotFound)
+        ));
+        assert_eq!(destroy::pending(&fs).await.unwrap(), vec![progress]);
+
+        DatasetReclaimer::new(Arc::clone(&fs)).run().await.unwrap();
+        GarbageCollector::new(
+            Arc::clone(&fs.db),
+            fs.tombstone_store.clone(),
+            fs.chunk_store.clone(),
+            Arc::clone(&fs.stats),
+            Arc::clone(&fs.write_coordinator),
+        )
+        .run()
+        .await
+        .unwrap();
+
+        assert!(destroy::pending(&fs).await.unwrap().is_empty());
+        for id in [dataset.root_inode, sub_id, big_id, small_id] {
+            assert!(matches!(
+                fs.inode_store.get(id).await,
+                Err(FsError::NotFound)
+            ));
+        }
+
+        assert_eq!(read_all(&fs, clone_id, data.len()).await, data);
+        let cloned_big_id = fs
+            .directory_store
+            .get(dir_clone_id, b"big.bin")
+            .await
+            .unwrap();
+        assert_eq!(read_all(&fs, cloned_big_id, data.len()).await, data);
+
+        // Only the two clones and the file copied into the directory clone remain
+        let (_, inodes_after) = fs.global_stats.get_totals();
+        assert_eq!(inodes_after, inodes_before + 3);
+    }
+
+    #[tokio::test]
+    async fn test_dataset_rollback() {
+        let fs = Arc::new(ZeroFS::new_in_memory().await.unwrap());
+        let root = AuthContext {
+            uid: 0,
+            gid: 0,
+            gids: vec![0],
+        };
+        let root_creds = Credentials::from_auth_context(&root);
+
+        let dataset = dataset_ops::create_dataset(&fs, "tenant", false)
+            .await
+            .unwrap();
+        let (a_id, _) = fs
+            .create(
+                &root_creds,
+                dataset.root_inode,
+                b"a.txt",
+                &SetAttributes::default(),
+            )
+            .await
+            .unwrap();
+        fs.write(&root, a_id, 0, &Bytes::from_static(b"v1"))
+            .await
+            .unwrap();
+        let snap1 = dataset_ops::create_snapshot(&fs, dataset.id, "snap1", false)
+            .await
+            .unwrap();
+
+        fs.write(&root, a_id, 0, &Bytes::from_static(b"v2"))
+            .await
+            .unwrap();
+        fs.create(
+            &root_creds,
+            dataset.root_inode,
+            b"b.txt",
+            &SetAttributes::default(),
+        )
+        .await
+        .unwrap();
+        let snap2 = dataset_ops::create_snapshot(&fs, dataset.id, "snap2", false)
+            .await
+            .unwrap();
+
+        // A dataset with snapshots cannot be destroyed, nor rolled back past
+        // a newer snapshot unless it is destroyed
+        assert!(matches!(
+            dataset_ops::delete_dataset(&fs, dataset.id).await,
+            Err(FsError::NotEmpty)
+        ));
+        assert!(matches!(
+            dataset_ops::rollback_dataset(&fs, dataset.id, snap1.id, false).await,
+            Err(FsError::NotEmpty)
+        ));
+
+        let (rolled_back, destroyed, replaced) =
+            dataset_ops::rollback_dataset(&fs, dataset.id, snap1.id, true)
+                .await
+                .unwrap();
+        assert_eq!(destroyed.len(), 1);
+        assert_eq!(destroyed[0].dataset_id, snap2.id);
+        assert!(fs.dataset_store.get_by_name("snap2").await.is_none());
+        assert_eq!(replaced.root_inode, dataset.root_inode);
+        assert_eq!(
+            fs.directory_store.get(0, b"tenant").await.unwrap(),
+            rolled_back.root_inode
+        );
+        assert_eq!(
+            dataset_ops::dataset_of(&fs, rolled_back.root_inode)
+                .await
+                .unwrap(),
+            dataset.id
+        );
+
+        let new_a_id = fs
+            .lookup(&root_creds, rolled_back.root_inode, b"a.txt")
+            .await
+            .unwrap();
+        assert_eq!(read_all(&fs, new_a_id, 2).await, Bytes::from_static(b"v1"));
+        assert!(matches!(
+            fs.lookup(&root_creds, rolled_back.root_inode, b"b.txt")
+                .await,
+            Err(FsError::NotFound)
+        ));
+
+        // Handles into the replaced tree are stale
+        assert!(matches!(
+            destroy::check_attached(&fs, a_id).await,
+            Err(FsError::StaleHandle)
+        ));
+        assert!(matches!(
+            fs.write(&root, a_id, 0, &Bytes::from_static(b"v3")).await,
+            Err(FsError::StaleHandle)
+        ));
+        destroy::check_attached(&fs, new_a_id).await.unwrap();
+
+        DatasetReclaimer::new(Arc::clone(&fs)).run().await.unwrap();
+        assert!(destroy::pending(&fs).await.unwrap().is_empty());
+        assert!(matches!(
+            fs.inode_store.get(a_id).await,
+            Err(FsError::NotFound)
+        ));
+
+        // The snapshot is kept, and the dataset only counts what it holds now
+        let snap1_a_id = fs
+            .lookup(&root_creds, snap1.root_inode, b"a.txt")
+            .await
+            .unwrap();
+        assert_eq!(read_all(&fs, snap1_a_id, 2).await, Bytes::from_static(b"v1"));
+        assert_eq!(read_all(&fs, new_a_id, 2).await, Bytes::from_static(b"v1"));
+        assert_eq!(dataset_ops::dataset_usage(&fs, dataset.id).await.inodes, 1);
+    }
+
+    #[tokio::test]
+    async fn test_restore_from_snapshot() {
+        use super::restore::{self, OnConflict};
+
+        let fs = Arc::new(ZeroFS::new_in_memory().await.unwrap());
+        let root = AuthContext {
+            uid: 0,
+            gid: 0,
+            gids: vec![0],
+        };
+        let root_creds = Credentials::from_auth_context(&root);
+
+        let dataset = dataset_ops::create_dataset(&fs, "tenant", false)
+            .await
+            .unwrap();
+        let (docs_id, _) = fs
+            .mkdir(
+                &root_creds,
+                dataset.root_inode,
+                b"docs",
+                &SetAttributes::default(),
+            )
+            .await
+            .unwrap();
+        let (a_id, _) = fs
+            .create(&root_creds, docs_id, b"a.txt", &SetAttributes::default())
+            .await
+            .unwrap();
+        fs.write(&root, a_id, 0, &Bytes::from_static(b"v1"))
+            .await
+            .unwrap();
+        let (b_id, _) = fs
+            .create(
+                &root_creds,
+                dataset.root_inode,
+                b"b.txt",
+                &SetAttributes::default(),
+            )
+            .await
+            .unwrap();
+        fs.write(&root, b_id, 0, &Bytes::from_static(b"b1"))
+            .await
+            .unwrap();
+        let snap = dataset_ops::create_snapshot(&fs, dataset.id, "snap", false)
+            .await
+            .unwrap();
+
+        // A dry run does not copy the snapshot, which still reads through
+        // the dataset
+        let outcome = restore::restore(
+            &fs,
+            snap.id,
+            "/docs/a.txt",
+            "/tenant/docs/a.txt",
+            OnConflict::Fail,
+            true,
+        )
+        .await;
+        assert!(matches!(outcome, Err(FsError::Exists)));
+        let outcome = restore::restore(
+            &fs,
+            snap.id,
+            "/docs/a.txt",
+            "/tenant/docs/a.txt",
+            OnConflict::Rename,
+            true,
+        )
+        .await
+        .unwrap();
+        assert_eq!(outcome.destination_path, "/tenant/docs/a.txt.restored");
+        assert_eq!(
+            fs.lazy_dirs.origin(snap.root_inode),
+            Some(dataset.root_inode)
+        );
+
+        fs.write(&root, a_id, 0, &Bytes::from_static(b"v2"))
+            .await
+            .unwrap();
+        fs.write(&root, b_id, 0, &Bytes::from_static(b"b2"))
+            .await
+            .unwrap();
+
+        // A dry run reports the outcome without writing
+        let outcome = restore::restore(
+            &fs,
+            snap.id,
+            "/b.txt",
+            "/tenant/b.txt",
+            OnConflict::Overwrite,
+            true,
+        )
+        .await
+        .unwrap();
+        assert!(outcome.dry_run && outcome.replaced);
+        assert_eq!(outcome.inode_id, 0);
+        assert_eq!(outcome.size, 2);
+        assert_eq!(read_all(&fs, b_id, 2).await, Bytes::from_static(b"b2"));
+
+        assert!(matches!(
+            restore::restore(
+                &fs,
+                snap.id,
+                "/b.txt",
+                "/tenant/b.txt",
+                OnConflict::Fail,
+                false
+            )
+            .await,
+            Err(FsError::Exists)
+        ));
+
+        // Renaming keeps the current file
+        for expected in ["/tenant/b.txt.restored", "/tenant/b.txt.restored.1"] {
+            let outcome = restore::restore(
+                &fs,
+                snap.id,
+                "/b.txt",
+                "/tenant/b.txt",
+                OnConflict::Rename,
+                false,
+            )
+            .await
+            .unwrap();
+            assert_eq!(outcome.destination_path, expected);
+            assert!(!outcome.replaced);
+            assert_eq!(
+                read_all(&fs, outcome.inode_id, 2).await,
+                Bytes::from_static(b"b1")
+            );
+        }
+        assert_eq!(read_all(&fs, b_id, 2).await, Bytes::from_static(b"b2"));
+
+        // Overwriting a directory detaches the current tree
+        let outcome = restore::restore(
+            &fs,
+            snap.id,
+            "/docs",
+            "/tenant/docs",
+            OnConflict::Overwrite,
+            false,
+        )
+        .await
+        .unwrap();
+        assert!(outcome.is_directory && outcome.replaced);
+        assert_eq!(outcome.detached.unwrap().root_inode, docs_id);
+        assert_eq!(
+            fs.directory_store
+                .get(dataset.root_inode, b"docs")
+                .await
+                .unwrap(),
+            outcome.inode_id
+        );
+        let new_a_id = fs
+            .lookup(&root_creds, outcome.inode_id, b"a.txt")
+            .await
+            .unwrap();
+        assert_eq!(read_all(&fs, new_a_id, 2).await, Bytes::from_static(b"v1"));
+        assert!(matches!(
+            destroy::check_attached(&fs, a_id).await,
+            Err(FsError::StaleHandle)
+        ));
+
+        DatasetReclaimer::new(Arc::clone(&fs)).run().await.unwrap();
+        assert!(destroy::pending(&fs).await.unwrap().is_empty());
+        assert!(matches!(
+            fs.inode_store.get(a_id).await,
+            Err(FsError::NotFound)
+        ));
+        // docs, docs/a.txt, b.txt and its two restored copies
+        assert_eq!(dataset_ops::dataset_usage(&fs, dataset.id).await.inodes, 5);
+
+        // Dataset roots cannot be replaced
+        assert!(matches!(
+            restore::restore(&fs, snap.id, "", "/tenant", OnConflict::Overwrite, false).await,
+            Err(FsError::InvalidArgument)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_diff_snapshots() {
+        use super::diff::{self, ChangeKind};
+
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let root = AuthContext {
+            uid: 0,
+            gid: 0,
+            gids: vec![0],
+        };
+        let root_creds = Credentials::from_auth_context(&root);
+
+        let dataset = dataset_ops::create_dataset(&fs, "tenant", false)
+            .await
+            .unwrap();
+        let top = dataset.root_inode;
+        let mut files = Vec::new();
+        for name in [b"a.txt".as_slice(), b"b.txt"] {
+            let (id, _) = fs
+                .create(&root_creds, top, name, &SetAttributes::default())
+                .await
+                .unwrap();
+            fs.write(&root, id, 0, &Bytes::from_static(b"v1"))
+                .await
+                .unwrap();
+            files.push(id);
+        }
+        let mut dirs = Vec::new();
+        for name in [b"docs".as_slice(), b"keep"] {
+            let (dir_id, _) = fs
+                .mkdir(&root_creds, top, name, &SetAttributes::default())
+                .await
+                .unwrap();
+            let (id, _) = fs
+                .create(&root_creds, dir_id, b"c.txt", &SetAttributes::default())
+                .await
+                .unwrap();
+            fs.write(&root, id, 0, &Bytes::from_static(b"c"))
+                .await
+                .unwrap();
+            dirs.push(dir_id);
+        }
+
+        let snap1 = dataset_ops::create_snapshot(&fs, dataset.id, "snap1", false)
+            .await
+            .unwrap();
+        assert!(
+            diff::diff_snapshots(&fs, snap1.id, None)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        fs.write(&root, files[0], 0, &Bytes::from_static(b"v2"))
+            .await
+            .unwrap();
+        fs.rename(&root, dirs[0], b"c.txt", dirs[0], b"d.txt")
+            .await
+            .unwrap();
+        fs.remove(&root, dirs[1], b"c.txt").await.unwrap();
+        fs.create(&root_creds, top, b"new.txt", &SetAttributes::default())
+            .await
+            .unwrap();
+
+        let changes = diff::diff_snapshots(&fs, snap1.id, None).await.unwrap();
+        let summary: Vec<_> = changes
+            .iter()
+            .map(|c| (c.kind, c.path.as_str(), c.old_path.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (ChangeKind::Modified, "/a.txt", None),
+                (ChangeKind::Renamed, "/docs/d.txt", Some("/docs/c.txt")),
+                (ChangeKind::Removed, "/keep/c.txt", None),
+                (ChangeKind::Added, "/new.txt", None),
+            ]
+        );
+        assert!(changes[0].content_changed && !changes[0].metadata_changed);
+        assert!(!changes[1].content_changed);
+
+        // The same changes between two snapshots, and none since the last
+        let snap2 = dataset_ops::create_snapshot(&fs, dataset.id, "snap2", false)
+            .await
+            .unwrap();
+        assert_eq!(
+            diff::diff_snapshots(&fs, snap1.id, Some(snap2.id))
+                .await
+                .unwrap(),
+            changes
+        );
+        assert!(
+            diff::diff_snapshots(&fs, snap2.id, None)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_receive_snapshot() {
+        use super::send::{self, ReceiveOptions};
+
+        let source = ZeroFS::new_in_memory().await.unwrap();
+        let target = ZeroFS::new_in_memory().await.unwrap();
+        let root = AuthContext {
+            uid: 0,
+            gid: 0,
+            gids: vec![0],
+        };
+        let root_creds = Credentials::from_auth_context(&root);
+
+        let dataset = dataset_ops::create_dataset(&source, "tenant", false)
+            .await
+            .unwrap();
+        let top = dataset.root_inode;
+        let (file_id, _) = source
+            .create(&root_creds, top, b"a.txt", &SetAttributes::default())
+            .await
+            .unwrap();
+        source
+            .write(&root, file_id, 0, &Bytes::from_static(b"hello"))
+            .await
+            .unwrap();
+        source
+            .write(&root, file_id, 1 << 20, &Bytes::from_static(b"tail"))
+            .await
+            .unwrap();
+        let mode = SetAttributes {
+            mode: SetMode::Set(0o640),
+            ..Default::default()
+        };
+        source.setattr(&root_creds, file_id, &mode).await.unwrap();
+        source
+            .setxattr(&root_creds, file_id, b"user.tag", b"blue", 0)
+            .await
+            .unwrap();
+        let (docs, _) = source
+            .mkdir(&root_creds, top, b"docs", &SetAttributes::default())
+            .await
+            .unwrap();
+        let (c_id, _) = source
+            .create(&root_creds, docs, b"c.txt", &SetAttributes::default())
+            .await
+            .unwrap();
+        source
+            .write(&root, c_id, 0, &Bytes::from_static(b"c1"))
+            .await
+            .unwrap();
+        source
+            .symlink(&root_creds, top, b"link", b"a.txt", &SetAttributes::default())
+            .await
+            .unwrap();
+
+        let snap1 = dataset_ops::create_snapshot(&source, dataset.id, "snap1", false)
+            .await
+            .unwrap();
+        let mut full = Vec::new();
+        send::send(&source, snap1.id, None, &mut full).await.unwrap();
+
+        // A corrupt stream is refused, and the dataset it created removed
+        let mut corrupt = full.clone();
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 0xff;
+        assert!(matches!(
+            send::receive(&target, "broken", ReceiveOptions::default(), corrupt.as_slice()).await,
+            Err(FsError::InvalidData)
+        ));
+        assert!(target.dataset_store.get_by_name("broken").await.is_none());
+
+        let outcome = send::receive(&target, "copy", ReceiveOptions::default(), full.as_slice())
+            .await
+            .unwrap();
+        assert_eq!(outcome.snapshot.name, "snap1");
+        assert_eq!(outcome.snapshot.uuid, snap1.uuid);
+        assert_eq!(outcome.entries, 4);
+        let copy = outcome.dataset.root_inode;
+
+        let a = target.lookup(&root_creds, copy, b"a.txt").await.unwrap();
+        let Inode::File(file) = target.inode_store.get(a).await.unwrap() else {
+            panic!("a.txt is not a file");
+        };
+        assert_eq!(file.size, (1 << 20) + 4);
+        assert_eq!(file.mode & 0o777, 0o640);
+        let (head, _) = target.read_file(&root, a, 0, 5).await.unwrap();
+        assert_eq!(head.as_ref(), b"hello");
+        let (tail, _) = target.read_file(&root, a, 1 << 20, 4).await.unwrap();
+        assert_eq!(tail.as_ref(), b"tail");
+        assert_eq!(
+            target.getxattr(&root_creds, a, b"user.tag").await.unwrap(),
+            b"blue"
+        );
+        let link = target.lookup(&root_creds, copy, b"link").await.unwrap();
+        let Inode::Symlink(link) = target.inode_store.get(link).await.unwrap() else {
+            panic!("link is not a symlink");
+        };
+        assert_eq!(link.target, b"a.txt");
+
+        // Rename a directory and change a file in it, among other changes
+        source
+            .write(&root, file_id, 0, &Bytes::from_static(b"HELLO"))
+            .await
+            .unwrap();
+        source
+            .rename(&root, top, b"docs", top, b"papers")
+            .await
+            .unwrap();
+        let papers = source.lookup(&root_creds, top, b"papers").await.unwrap();
+        let c_id = source.lookup(&root_creds, papers, b"c.txt").await.unwrap();
+        source
+            .write(&root, c_id, 0, &Bytes::from_static(b"c2"))
+            .await
+            .unwrap();
+        source.remove(&root, top, b"link").await.unwrap();
+        source
+            .create(&root_creds, top, b"new.txt", &SetAttributes::default())
+            .await
+            .unwrap();
+
+        let snap2 = dataset_ops::create_snapshot(&source, dataset.id, "snap2", false)
+            .await
+            .unwrap();
+        let mut incremental = Vec::new();
+        send::send(&source, snap2.id, Some(snap1.id), &mut incremental)
+            .await
+            .unwrap();
+
+        // Refused while the dataset has changes of its own, unless forced
+        target
+            .create(&root_creds, copy, b"local.txt", &SetAttributes::default())
+            .await
+            .unwrap();
+        assert!(matches!(
+            send::receive(
+                &target,
+                "copy",
+                ReceiveOptions::default(),
+                incremental.as_slice()
+            )
+            .await,
+            Err(FsError::NotEmpty)
+        ));
+        let force = ReceiveOptions {
+            force: true,
+            ..Default::default()
+        };
+        let outcome = send::receive(&target, "copy", force, incremental.as_slice())
+            .await
+            .unwrap();
+        assert_eq!(outcome.snapshot.uuid, snap2.uuid);
+        let copy = outcome.dataset.root_inode;
+
+        let a = target.lookup(&root_creds, copy, b"a.txt").await.unwrap();
+        let (head, _) = target.read_file(&root, a, 0, 5).await.unwrap();
+        assert_eq!(head.as_ref(), b"HELLO");
+        let papers = target.lookup(&root_creds, copy, b"papers").await.unwrap();
+        let c_id = target.lookup(&root_creds, papers, b"c.txt").await.unwrap();
+        let (data, _) = target.read_file(&root, c_id, 0, 2).await.unwrap();
+        assert_eq!(data.as_ref(), b"c2");
+        for gone in [b"docs".as_slice(), b"link", b"local.txt"] {
+            assert!(matches!(
+                target.lookup(&root_creds, copy, gone).await,
+                Err(FsError::NotFound)
+            ));
+        }
+        target
+            .lookup(&root_creds, copy, b"new.txt")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_send_hard_links() {
+        use super::send::{self, ReceiveOptions};
+
+        let source = ZeroFS::new_in_memory().await.unwrap();
+        let target = ZeroFS::new_in_memory().await.unwrap();
+        let root = AuthContext {
+            uid: 0,
+            gid: 0,
+            gids: vec![0],
+        };
+        let root_creds = Credentials::from_auth_context(&root);
+
+        let dataset = dataset_ops::create_dataset(&source, "tenant", false)
+            .await
+            .unwrap();
+        let top = dataset.root_inode;
+        let (file_id, _) = source
+            .create(&root_creds, top, b"a.txt", &SetAttributes::default())
+            .await
+            .unwrap();
+        source
+            .write(&root, file_id, 0, &Bytes::from_static(b"shared"))
+            .await
+            .unwrap();
+        let (docs, _) = source
+            .mkdir(&root_creds, top, b"docs", &SetAttributes::default())
+            .await
+            .unwrap();
+        source.link(&root, file_id, docs, b"b.txt").await.unwrap();
+
+        let snap = dataset_ops::create_snapshot(&source, dataset.id, "snap", false)
+            .await
+            .unwrap();
+        let mut full = Vec::new();
+        send::send(&source, snap.id, None, &mut full).await.unwrap();
+        // Sent without copying the snapshot
+        assert!(source.lazy_dirs.origin(snap.root_inode).is_some());
+
+        let outcome = send::receive(&target, "copy", ReceiveOptions::default(), full.as_slice())
+            .await
+            .unwrap();
+        assert_eq!(outcome.entries, 3);
+        assert_eq!(outcome.data_bytes, 6);
+        let copy = outcome.dataset.root_inode;
+        let a = target.lookup(&root_creds, copy, b"a.txt").await.unwrap();
+        let copy_docs = target.lookup(&root_creds, copy, b"docs").await.unwrap();
+        let b = target
+            .lookup(&root_creds, copy_docs, b"b.txt")
+            .await
+            .unwrap();
+        assert_eq!(a, b);
+        assert_eq!(target.inode_store.get(a).await.unwrap().nlink(), 2);
+        assert_eq!(read_all(&target, a, 6).await, Bytes::from_static(b"shared"));
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_schedule_retention() {
+        use super::retention::{self, SnapshotScheduler};
+        use crate::config::SnapshotScheduleConfig;
+        use std::collections::BTreeMap;
+
+        let fs = Arc::new(ZeroFS::new_in_memory().await.unwrap());
+        let dataset = dataset_ops::create_dataset(&fs, "tenant", false)
+            .await
+            .unwrap();
+        dataset_ops::create_snapshot(&fs, dataset.id, "tenant-manual", false)
+            .await
+            .unwrap();
+
+        let schedule = SnapshotScheduleConfig {
+            hourly: 2,
+            daily: 1,
+            ..Default::default()
+        };
+        let schedules = BTreeMap::from([
+            ("tenant".to_string(), schedule),
+            ("missing".to_string(), schedule),
+        ]);
+        let scheduler = SnapshotScheduler::new(Arc::clone(&fs), schedules);
+
+        async fn scheduled(fs: &ZeroFS) -> Vec<String> {
+            let mut names: Vec<_> = fs
+                .dataset_store
+                .list_snapshots()
+                .await
+                .into_iter()
+                .map(|s| s.name)
+                .filter(|name| retention::auto_snapshot_time(name).is_some())
+                .collect();
+            names.sort();
+            names
+        }
+
+        // 2023-11-14 22:13:20 UTC
+        let start = 1_700_000_000;
+        scheduler.run(start).await;
+        scheduler.run(start + 60).await;
+        assert_eq!(scheduled(&fs).await, ["tenant@auto-20231114T221320Z"]);
+
+        scheduler.run(start + 3600).await;
+        scheduler.run(start + 7200).await;
+        assert_eq!(
+            scheduled(&fs).await,
+            [
+                "tenant@auto-20231114T231320Z",
+                "tenant@auto-20231115T001320Z"
+            ]
+        );
+
+        // Five hours later, as if the server had been down
+        scheduler.run(start + 25200).await;
+        assert_eq!(
+            scheduled(&fs).await,
+            [
+                "tenant@auto-20231115T001320Z",
+                "tenant@auto-20231115T051320Z"
+            ]
+        );
+        assert!(
+            fs.dataset_store
+                .get_by_name("tenant-manual")
+                .await
+                .is_some()
+        );
+
+        let status = scheduler.status();
+        let tenant = status.iter().find(|s| s.dataset == "tenant").unwrap();
+        assert_eq!(tenant.snapshots, 2);
+        assert_eq!(tenant.pruned, 2);
+        assert_eq!(tenant.missed_runs, 4);
+        assert_eq!(
+            tenant.last_snapshot.as_deref(),
+            Some("tenant@auto-20231115T051320Z")
+        );
+        assert_eq!(tenant.next_run, Some(start + 25200 + 2800));
+        assert!(tenant.last_error.is_none());
+        let missing = status.iter().find(|s| s.dataset == "missing").unwrap();
+        assert!(missing.last_error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_holds() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let dataset = dataset_ops::create_dataset(&fs, "tenant", false)
+            .await
+            .unwrap();
+        let snap1 = dataset_ops::create_snapshot(&fs, dataset.id, "snap1", true)
+            .await
+            .unwrap();
+        let snap2 = dataset_ops::create_snapshot(&fs, dataset.id, "snap2", true)
+            .await
+            .unwrap();
+
+        let held = fs
+            .dataset_store
+            .hold_snapshot(snap2.id, "send")
+            .await
+            .unwrap();
+        assert!(held.holds.contains("send"));
+        assert!(matches!(
+            fs.dataset_store.hold_snapshot(snap2.id, "send").await,
+            Err(FsError::Exists)
+        ));
+        assert!(matches!(
+            fs.dataset_store.hold_snapshot(dataset.id, "send").await,
+            Err(FsError::InvalidArgument)
+        ));
+        fs.dataset_store
+            .hold_snapshot(snap2.id, "restore")
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            dataset_ops::delete_dataset(&fs, snap2.id).await,
+            Err(FsError::OperationNotPermitted)
+        ));
+        assert!(matches!(
+            dataset_ops::rollback_dataset(&fs, dataset.id, snap1.id, true).await,
+            Err(FsError::OperationNotPermitted)
+        ));
+        assert!(fs.dataset_store.get_by_id(snap2.id).await.is_some());
+
+        fs.dataset_store
+            .release_snapshot(snap2.id, "send")
+            .await
+            .unwrap();
+        assert!(matches!(
+            fs.dataset_store.release_snapshot(snap2.id, "send").await,
+            Err(FsError::NotFound)
+        ));
+        assert!(matches!(
+            dataset_ops::delete_dataset(&fs, snap2.id).await,
+            Err(FsError::OperationNotPermitted)
+        ));
+
+        let released = fs
+            .dataset_store
+            .release_snapshot(snap2.id, "restore")
+            .await
+            .unwrap();
+        assert!(released.holds.is_empty());
+        dataset_ops::delete_dataset(&fs, snap2.id).await.unwrap();
+        assert!(fs.dataset_store.get_by_name("snap2").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_owner_quota() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let root = AuthContext {
+            uid: 0,
+            gid: 0,
+            gids: vec![0],
+        };
+        let root_creds = Credentials::from_auth_context(&root);
+        let user = quota::Owner::User(2000);
+
+        fs.quota_store
+            .set(
+                user,
+                quota::OwnerQuota {
+                    bytes: Some(1000),
+                    inodes: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        let owned_by_user = SetAttributes {
+            uid: SetUid::Set(2000),
+            ..Default::default()
+        };
+        let (file_id, _) = fs
+            .create(&root_creds, 0, b"owned.bin", &owned_by_user)
+            .await
+            .unwrap();
+        fs.write(&root, file_id, 0, &Bytes::from(vec![1; 600]))
+            .await
+            .unwrap();
+        assert!(matches!(
+            fs.write(&root, file_id, 600, &Bytes::from(vec![1; 600]))
+                .await,
+            Err(FsError::QuotaExceeded)
+        ));
+        assert_eq!(
+            fs.global_stats.get_scope_totals(UsageScope::Owner(user)),
+            (600, 1)
+        );
+
+        // chown moves usage, and is refused past the new owner's limit
+        let (other_id, _) = fs
+            .create(&root_creds, 0, b"other.bin", &SetAttributes::default())
+            .await
+            .unwrap();
+        fs.write(&root, other_id, 0, &Bytes::from(vec![1; 800]))
+            .await
+            .unwrap();
+        assert!(matches!(
+            fs.setattr(&root_creds, other_id, &owned_by_user).await,
+            Err(FsError::QuotaExceeded)
+        ));
+        let root_totals = fs
+            .global_stats
+            .get_scope_totals(UsageScope::Owner(quota::Owner::User(0)));
+        fs.setattr(
+            &root_creds,
+            other_id,
+            &SetAttributes {
+                uid: SetUid::Set(3000),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            fs.global_stats
+                .get_scope_totals(UsageScope::Owner(quota::Owner::User(3000))),
+            (800, 1)
+        );
+        assert_eq!(
+            fs.global_stats
+                .get_scope_totals(UsageScope::Owner(quota::Owner::User(0))),
+            (root_totals.0 - 800, root_totals.1 - 1)
+        );
+
+        let report = quota::report(&fs).await;
+        let user_report = report.iter().find(|r| r.owner == user).unwrap();
+        assert_eq!(user_report.bytes, 600);
+        assert_eq!(user_report.quota.bytes, Some(1000));
+        assert!(report.iter().any(|r| r.owner == quota::Owner::User(3000)));
+    }
+
+    #[tokio::test]
+    async fn test_xattrs() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let creds = test_creds();
+        let (file_id, _) = fs
+            .create(&creds, 0, b"file.txt", &SetAttributes::default())
+            .await
+            .unwrap();
+
+        fs.setxattr(&creds, file_id, b"user.a", b"1", 0)
+            .await
+            .unwrap();
+        assert_eq!(fs.getxattr(&creds, file_id, b"user.a").await.unwrap(), b"1");
+
+        assert_eq!(
+            fs.setxattr(&creds, file_id, b"user.a", b"2", XATTR_CREATE)
+                .await,
+            Err(FsError::Exists)
+        );
+        assert_eq!(
+            fs.setxattr(&creds, file_id, b"user.b", b"2", XATTR_REPLACE)
+                .await,
+            Err(FsError::NoData)
+        );
+        assert_eq!(
+            fs.setxattr(&creds, file_id, b"trusted.a", b"1", 0).await,
+            Err(FsError::OperationNotPermitted)
+        );
+        assert_eq!(
+            fs.setxattr(&creds, file_id, b"unknown.a", b"1", 0).await,
+            Err(FsError::NotSupported)
+        );
+        assert_eq!(
+            fs.listxattr(&creds, file_id).await.unwrap(),
+            vec![b"user.a".to_vec()]
+        );
+
+        fs.removexattr(&creds, file_id, b"user.a").await.unwrap();
+        assert_eq!(
+            fs.getxattr(&creds, file_id, b"user.a").await,
+            Err(FsError::NoData)
+        );
+        assert_eq!(
+            fs.removexattr(&creds, file_id, b"user.a").await,
+            Err(FsError::NoData)
+        );
+        assert_eq!(count_keys(&fs, KeyPrefix::Xattr).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_xattrs_copied_on_clone_and_removed_with_inode() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let creds = test_creds();
+        let (src_id, _) = fs
+            .create(&creds, 0, b"src.txt", &SetAttributes::default())
+            .await
+            .unwrap();
+        fs.setxattr(&creds, src_id, b"user.tag", b"blue", 0)
+            .await
+            .unwrap();
+
+        let (clone_id, _) = clone::clone_entry(&fs, src_id, 0, b"clone.txt")
+            .await
+            .unwrap();
+        assert_eq!(
+            fs.getxattr(&creds, clone_id, b"user.tag").await.unwrap(),
+            b"blue"
+        );
+        assert_eq!(count_keys(&fs, KeyPrefix::Xattr).await, 2);
+
+        fs.remove(&(&test_auth()).into(), 0, b"src.txt")
+            .await
+            .unwrap();
+        assert_eq!(count_keys(&fs, KeyPrefix::Xattr).await, 1);
+        fs.remove(&(&test_auth()).into(), 0, b"clone.txt")
+            .await
+            .unwrap();
+        assert_eq!(count_keys(&fs, KeyPrefix::Xattr).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_posix_acls() {
+        use crate::fs::acl::*;
+        const U: u32 = u32::MAX;
+
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let owner = test_creds();
+        let user = |uid, group| Credentials {
+            uid,
+            gid: uid,
+            groups: [group; 16],
+            groups_count: 1,
+        };
+        let (team_id, _) = fs
+            .mkdir(
+                &owner,
+                0,
+                b"team",
+                &SetAttributes {
+                    mode: SetMode::Set(0o770),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            fs.create(&user(1001, 1001), team_id, b"a", &SetAttributes::default())
+                .await,
+            Err(FsError::PermissionDenied)
+        );
+
+        // A named user entry grants access the mode bits do not
+        let access = acl_xattr(&[
+            (ACL_USER_OBJ, U, 7),
+            (ACL_USER, 1001, 7),
+            (ACL_GROUP_OBJ, U, 7),
+            (ACL_MASK, U, 7),
+            (ACL_OTHER, U, 0),
+        ]);
+        assert_eq!(
+            fs.setxattr(&user(1001, 1001), team_id, ACL_ACCESS_XATTR, &access, 0)
+                .await,
+            Err(FsError::OperationNotPermitted)
+        );
+        fs.setxattr(&owner, team_id, ACL_ACCESS_XATTR, &access, 0)
+            .await
+            .unwrap();
+        fs.create(&user(1001, 1001), team_id, b"a", &SetAttributes::default())
+            .await
+            .unwrap();
+
+        // chmod limits the named entries through the mask
+        fs.setattr(
+            &owner,
+            team_id,
+            &SetAttributes {
+                mode: SetMode::Set(0o750),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            fs.create(&user(1001, 1001), team_id, b"b", &SetAttributes::default())
+                .await,
+            Err(FsError::PermissionDenied)
+        );
+
+        // New files and directories inherit the default ACL
+        let default = acl_xattr(&[
+            (ACL_USER_OBJ, U, 7),
+            (ACL_GROUP_OBJ, U, 5),
+            (ACL_GROUP, 2000, 6),
+            (ACL_MASK, U, 7),
+            (ACL_OTHER, U, 0),
+        ]);
+        fs.setxattr(&owner, team_id, ACL_DEFAULT_XATTR, &default, 0)
+            .await
+            .unwrap();
+        let (file_id, attrs) = fs
+            .create(
+                &owner,
+                team_id,
+                b"c",
+                &SetAttributes {
+                    mode: SetMode::Set(0o644),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(attrs.mode & 0o777, 0o640);
+        assert_eq!(
+            fs.setxattr(&owner, file_id, ACL_DEFAULT_XATTR, &default, 0)
+                .await,
+            Err(FsError::PermissionDenied)
+        );
+
+        let file = fs.inode_store.get(file_id).await.unwrap();
+        let member = user(1002, 2000);
+        fs.check_inode_access(file_id, &file, &member, AccessMode::Read)
+            .await
+            .unwrap();
+        assert_eq!(
+            fs.check_inode_access(file_id, &file, &member, AccessMode::Write)
+                .await,
+            Err(FsError::PermissionDenied)
+        );
+
+        let (sub_id, _) = fs
+            .mkdir(&owner, team_id, b"sub", &SetAttributes::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            fs.getxattr(&owner, sub_id, ACL_DEFAULT_XATTR)
+                .await
+                .unwrap(),
+            default
+        );
+
+        // An access ACL the mode bits can express is not kept
+        let minimal = acl_xattr(&[
+            (ACL_USER_OBJ, U, 6),
+            (ACL_GROUP_OBJ, U, 4),
+            (ACL_OTHER, U, 4),
+        ]);
+        fs.setxattr(&owner, file_id, ACL_ACCESS_XATTR, &minimal, 0)
+            .await
+            .unwrap();
+        assert_eq!(
+            fs.getxattr(&owner, file_id, ACL_ACCESS_XATTR).await,
+            Err(FsError::NoData)
+        );
+        let file = fs.inode_store.get(file_id).await.unwrap();
+        assert_eq!(file.mode() & 0o777, 0o644);
+    }
+
+    #[tokio::test]
+    async fn test_dataset_properties() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let root = AuthContext {
+            uid: 0,
+            gid: 0,
+            gids: vec![0],
+        };
+        let root_creds = Credentials::from_auth_context(&root);
+
+        let dataset = dataset_ops::create_dataset(&fs, "tenant", false)
+            .await
+            .unwrap();
+        let store = &fs.dataset_store;
+        store
+            .set_property(
+                dataset::ROOT_DATASET_ID,
+                properties::Property::Compression,
+                Some("zstd-3"),
+            )
+            .await
+            .unwrap();
+        store
+            .set_property(dataset.id, properties::Property::Atime, Some("relatime"))
+            .await
+            .unwrap();
+        assert!(matches!(
+            store
+                .set_property(dataset.id, properties::Property::Compression, Some("gzip"))
+                .await,
+            Err(FsError::InvalidArgument)
+        ));
+
+        let values = store.property_values(dataset.id).await.unwrap();
+        let value = |name: &str| values.iter().find(|v| v.property == name).unwrap();
+        assert_eq!(value("compression").value, "zstd-3");
+        assert_eq!(
+            value("compression").source,
+            properties::PropertySource::Inherited
+        );
+        assert_eq!(value("compression").inherited_from.as_deref(), Some("root"));
+        assert_eq!(value("atime").value, "relatime");
+        assert_eq!(value("atime").source, properties::PropertySource::Local);
+        assert_eq!(
+            value("readonly").source,
+            properties::PropertySource::Default
+        );
+
+        // New files belong to the default owner instead of the caller
+        for (property, value) in [
+            (properties::Property::DefaultUid, "1234"),
+            (properties::Property::CaseSensitivity, "insensitive"),
+        ] {
+            dataset_ops::set_property(&fs, dataset.id, property, Some(value))
+                .await
+                .unwrap();
+        }
+        let (file_id, _) = fs
+            .create(
+                &root_creds,
+                dataset.root_inode,
+                b"Readme.txt",
+                &SetAttributes::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(fs.inode_store.get(file_id).await.unwrap().uid(), 1234);
+
+        // Names differing only in case refer to the same file
+        assert_eq!(
+            fs.lookup(&root_creds, dataset.root_inode, b"README.TXT")
+                .await
+                .unwrap(),
+            file_id
+        );
+        assert!(matches!(
+            fs.create(
+                &root_creds,
+                dataset.root_inode,
+                b"readme.txt",
+                &SetAttributes::default(),
+            )
+            .await,
+            Err(FsError::Exists)
+        ));
+
+        store
+            .set_property(dataset.id, properties::Property::Readonly, Some("on"))
+            .await
+            .unwrap();
+        assert!(matches!(
+            fs.write(&root, file_id, 0, &Bytes::from(vec![1; 10])).await,
+            Err(FsError::ReadOnlyFilesystem)
+        ));
+        assert!(matches!(
+            fs.create(
+                &root_creds,
+                dataset.root_inode,
+                b"other.txt",
+                &SetAttributes::default(),
+            )
+            .await,
+            Err(FsError::ReadOnlyFilesystem)
+        ));
+        assert!(matches!(
+            clone::clone_entry(&fs, file_id, dataset.root_inode, b"copy.txt").await,
+            Err(FsError::ReadOnlyFilesystem)
+        ));
+        // The root dataset is still writable
+        fs.create(&root_creds, 0, b"plain.txt", &SetAttributes::default())
+            .await
+            .unwrap();
+
+        store
+            .set_property(dataset.id, properties::Property::Readonly, None)
+            .await
+            .unwrap();
+        fs.write(&root, file_id, 0, &Bytes::from(vec![1; 10]))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_case_insensitive_refused_over_clashing_names() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let root = AuthContext {
+            uid: 0,
+            gid: 0,
+            gids: vec![0],
+        };
+        let root_creds = Credentials::from_auth_context(&root);
+
+        let dataset = dataset_ops::create_dataset(&fs, "tenant", false)
+            .await
+            .unwrap();
+        let (dir_id, _) = fs
+            .mkdir(
+                &root_creds,
+                dataset.root_inode,
+                b"docs",
+                &SetAttributes::default(),
+            )
+            .await
+            .unwrap();
+        for name in [b"a.txt", b"A.txt"] {
+            fs.create(&root_creds, dir_id, name, &SetAttributes::default())
+                .await
+                .unwrap();
+        }
+
+        assert!(matches!(
+            dataset_ops::set_property(
+                &fs,
+                dataset.id,
+                properties::Property::CaseSensitivity,
+                Some("insensitive"),
+            )
+            .await,
+            Err(FsError::Exists)
+        ));
+        assert_eq!(
+            fs.dataset_store
+                .effective_properties(dataset.id)
+                .await
+                .case_sensitivity,
+            CaseSensitivity::Sensitive
+        );
+
+        fs.remove(&root, dir_id, b"a.txt").await.unwrap();
+        dataset_ops::set_property(
+            &fs,
+            dataset.id,
+            properties::Property::CaseSensitivity,
+            Some("insensitive"),
+        )
+        .await
+        .unwrap();
+        let file_id = fs.directory_store.get(dir_id, b"A.txt").await.unwrap();
+        assert_eq!(
+            fs.lookup(&root_creds, dir_id, b"a.TXT").await.unwrap(),
+            file_id
+        );
+    }
+
+    #[tokio::test]
+    async fn test_nested_datasets() {
+        let fs = Arc::new(ZeroFS::new_in_memory().await.unwrap());
+        let root = AuthContext {
+            uid: 0,
+            gid: 0,
+            gids: vec![0],
+        };
+        let root_creds = Credentials::from_auth_context(&root);
+
+        let team = dataset_ops::create_dataset(&fs, "team", false)
+            .await
+            .unwrap();
+        let app = dataset_ops::create_dataset(&fs, "team/app", false)
+            .await
+            .unwrap();
+        let db = dataset_ops::create_dataset(&fs, "team/app/db", false)
+            .await
+            .unwrap();
+        assert_eq!(app.parent_id, Some(team.id));
+        assert_eq!(db.parent_id, Some(app.id));
+        assert!(matches!(
+            dataset_ops::create_dataset(&fs, "missing/db", false).await,
+            Err(FsError::NotFound)
+        ));
+        assert!(matches!(
+            dataset_ops::create_dataset(&fs, "team//db", false).await,
+            Err(FsError::InvalidArgument)
+        ));
+
+        // Children are mounted at their path within the parent
+        assert_eq!(
+            fs.lookup(&root_creds, team.root_inode, b"app")
+                .await
+                .unwrap(),
+            app.root_inode
+        );
+        assert_eq!(
+            fs.lookup(&root_creds, app.root_inode, b"db").await.unwrap(),
+            db.root_inode
+        );
+
+        let (file_id, _) = fs
+            .create(
+                &root_creds,
+                db.root_inode,
+                b"f.txt",
+                &SetAttributes::default(),
+            )
+            .await
+            .unwrap();
+        fs.write(&root, file_id, 0, &Bytes::from_static(b"v1"))
+            .await
+            .unwrap();
+        assert_eq!(
+            dataset_ops::dataset_of_inode(&fs, file_id).await.unwrap(),
+            db.id
+        );
+
+        // One snapshot per dataset, all of the same point in time
+        let snapshots = dataset_ops::create_recursive_snapshot(&fs, team.id, "nightly", true)
+            .await
+            .unwrap();
+        let names: Vec<_> = snapshots.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["nightly", "nightly/app", "nightly/app/db"]);
+        assert_eq!(snapshots[2].parent_id, Some(db.id));
+        fs.write(&root, file_id, 0, &Bytes::from_static(b"v2"))
+            .await
+            .unwrap();
+
+        let snap_file = fs
+            .lookup(&root_creds, snapshots[2].root_inode, b"f.txt")
+            .await
+            .unwrap();
+        assert_eq!(read_all(&fs, snap_file, 2).await, Bytes::from_static(b"v1"));
+        let mut dir = snapshots[0].root_inode;
+        for name in [&b"app"[..], b"db"] {
+            dir = fs.lookup(&root_creds, dir, name).await.unwrap();
+        }
+        let nested_file = fs.lookup(&root_creds, dir, b"f.txt").await.unwrap();
+        assert_eq!(
+            read_all(&fs, nested_file, 2).await,
+            Bytes::from_static(b"v1")
+        );
+
+        // A parent goes only with everything below it, and not while any of
+        // it is held
+        assert!(matches!(
+            dataset_ops::delete_dataset(&fs, team.id).await,
+            Err(FsError::NotEmpty)
+        ));
+        fs.dataset_store
+            .hold_snapshot(snapshots[1].id, "backup")
+            .await
+            .unwrap();
+        assert!(matches!(
+            dataset_ops::delete_dataset_recursive(&fs, team.id).await,
+            Err(FsError::OperationNotPermitted)
+        ));
+        assert_eq!(fs.dataset_store.list_datasets().await.len(), 7);
+        fs.dataset_store
+            .release_snapshot(snapshots[1].id, "backup")
+            .await
+            .unwrap();
+
+        let (_, destroyed) = dataset_ops::delete_dataset_recursive(&fs, snapshots[0].id)
+            .await
+            .unwrap();
+        assert_eq!(destroyed.len(), 2);
+        assert_eq!(fs.dataset_store.list_snapshots().await.len(), 0);
+
+        dataset_ops::create_snapshot(&fs, app.id, "app-snap", false)
+            .await
+            .unwrap();
+        let (progress, destroyed) = dataset_ops::delete_dataset_recursive(&fs, team.id)
+            .await
+            .unwrap();
+        assert_eq!(progress.dataset_id, team.id);
+        let destroyed: Vec<_> = destroyed.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(destroyed, ["app-snap", "team/app/db", "team/app"]);
+        assert_eq!(fs.dataset_store.list_datasets().await.len(), 1);
+        assert!(matches!(
+            fs.lookup(&root_creds, ROOT_INODE_ID, b"team").await,
+            Err(FsError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rollback_keeps_child_datasets() {
+        use super::restore::{self, OnConflict};
+
+        let fs = Arc::new(ZeroFS::new_in_memory().await.unwrap());
+        let root = AuthContext {
+            uid: 0,
+            gid: 0,
+            gids: vec![0],
+        };
+        let root_creds = Credentials::from_auth_context(&root);
+
+        let team = dataset_ops::create_dataset(&fs, "team", false)
+            .await
+            .unwrap();
+        let before = dataset_ops::create_snapshot(&fs, team.id, "before", false)
+            .await
+            .unwrap();
+        let app = dataset_ops::create_dataset(&fs, "team/app", false)
+            .await
+            .unwrap();
+        let (file_id, _) = fs
+            .create(
+                &root_creds,
+                app.root_inode,
+                b"f.txt",
+                &SetAttributes::default(),
+            )
+            .await
+            .unwrap();
+        fs.write(&root, file_id, 0, &Bytes::from_static(b"data"))
+            .await
+            .unwrap();
+
+        // Replacing the tree of team would take team/app along
+        assert!(matches!(
+            dataset_ops::rollback_dataset(&fs, team.id, before.id, false).await,
+            Err(FsError::CrossDevice)
+        ));
+        assert!(matches!(
+            restore::restore(&fs, before.id, "", "/team", OnConflict::Overwrite, false).await,
+            Err(FsError::InvalidArgument)
+        ));
+        assert!(destroy::pending(&fs).await.unwrap().is_empty());
+        assert_eq!(
+            fs.lookup(&root_creds, team.root_inode, b"app")
+                .await
+                .unwrap(),
+            app.root_inode
+        );
+        assert_eq!(read_all(&fs, file_id, 4).await, Bytes::from_static(b"data"));
+
+        // The reclaimer leaves registered dataset roots alone
+        let progress = destroy::DestroyProgress::new(
+            app.id,
+            app.name.clone(),
+            app.root_inode,
+            app.id,
+            dataset::ROOT_DATASET_ID,
+            (0, 0),
+        );
+        let mut txn = fs.db.new_transaction().unwrap();
+        txn.put_bytes(&progress.key(), progress.encode().unwrap());
+        let mut seq_guard = fs.write_coordinator.allocate_sequence();
+        fs.commit_transaction(txn, &mut seq_guard).await.unwrap();
+        DatasetReclaimer::new(Arc::clone(&fs)).run().await.unwrap();
+        assert!(destroy::pending(&fs).await.unwrap().is_empty());
+        assert_eq!(read_all(&fs, file_id, 4).await, Bytes::from_static(b"data"));
+
+        // Without the child, the rollback goes through
+        dataset_ops::delete_dataset(&fs, app.id).await.unwrap();
+        let (rolled_back, _, _) = dataset_ops::rollback_dataset(&fs, team.id, before.id, false)
+            .await
+            .unwrap();
+        assert!(matches!(
+            fs.lookup(&root_creds, rolled_back.root_inode, b"app").await,
+            Err(FsError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rename_dataset() {
+        let fs = Arc::new(ZeroFS::new_in_memory().await.unwrap());
+        let root = AuthContext {
+            uid: 0,
+            gid: 0,
+            gids: vec![0],
+        };
+        let root_creds = Credentials::from_auth_context(&root);
+
+        let team = dataset_ops::create_dataset(&fs, "team", false)
+            .await
+            .unwrap();
+        let app = dataset_ops::create_dataset(&fs, "team/app", false)
+            .await
+            .unwrap();
+        let other = dataset_ops::create_dataset(&fs, "other", false)
+            .await
+            .unwrap();
+        let (file_id, _) = fs
+            .create(
+                &root_creds,
+                app.root_inode,
+                b"f.txt",
+                &SetAttributes::default(),
+            )
+            .await
+            .unwrap();
+        fs.write(&root, file_id, 0, &Bytes::from_static(b"v1"))
+            .await
+            .unwrap();
+
+        // Moving a dataset moves its root directory
+        let moved = dataset_ops::rename_dataset(&fs, app.id, "other/app")
+            .await
+            .unwrap();
+        assert_eq!((moved.id, moved.uuid), (app.id, app.uuid));
+        assert_eq!(moved.parent_id, Some(other.id));
+        assert!(fs.dataset_store.get_by_name("team/app").await.is_none());
+        assert_eq!(
+            fs.lookup(&root_creds, other.root_inode, b"app")
+                .await
+                .unwrap(),
+            app.root_inode
+        );
+        assert!(matches!(
+            fs.lookup(&root_creds, team.root_inode, b"app").await,
+            Err(FsError::NotFound)
+        ));
+        let file = fs
+            .lookup(&root_creds, app.root_inode, b"f.txt")
+            .await
+            .unwrap();
+        assert_eq!(read_all(&fs, file, 2).await, Bytes::from_static(b"v1"));
+        assert_eq!(
+            dataset_ops::dataset_of_inode(&fs, file_id).await.unwrap(),
+            app.id
+        );
+
+        // Child datasets follow their parent
+        dataset_ops::rename_dataset(&fs, other.id, "team/other")
+            .await
+            .unwrap();
+        assert_eq!(
+            fs.dataset_store
+                .get_by_name("team/other/app")
+                .await
+                .unwrap()
+                .id,
+            app.id
+        );
+        assert!(matches!(
+            fs.lookup(&root_creds, ROOT_INODE_ID, b"other").await,
+            Err(FsError::NotFound)
+        ));
+        dataset_ops::rename_dataset(&fs, team.id, "squad")
+            .await
+            .unwrap();
+        assert_eq!(
+            fs.lookup(&root_creds, ROOT_INODE_ID, b"squad")
+                .await
+                .unwrap(),
+            team.root_inode
+        );
+        assert_eq!(
+            fs.dataset_store
+                .get_by_name("squad/other/app")
+                .await
+                .unwrap()
+                .id,
+            app.id
+        );
+
+        dataset_ops::create_dataset(&fs, "taken", false)
+            .await
+            .unwrap();
+        assert!(matches!(
+            dataset_ops::rename_dataset(&fs, team.id, "taken").await,
+            Err(FsError::Exists)
+        ));
+        assert!(matches!(
+            dataset_ops::rename_dataset(&fs, team.id, "squad/other/squad").await,
+            Err(FsError::InvalidArgument)
+        ));
+        assert!(matches!(
+            dataset_ops::rename_dataset(&fs, team.id, "missing/squad").await,
+            Err(FsError::NotFound)
+        ));
+        assert!(matches!(
+            dataset_ops::rename_dataset(&fs, dataset::ROOT_DATASET_ID, "top").await,
+            Err(FsError::InvalidArgument)
+        ));
+
+        // Data written in a dataset stays under the key it inherits
+        dataset_ops::create_dataset(&fs, "vault", true)
+            .await
+            .unwrap();
+        let data = dataset_ops::create_dataset(&fs, "vault/data", false)
+            .await
+            .unwrap();
+        assert!(matches!(
+            dataset_ops::rename_dataset(&fs, data.id, "data").await,
+            Err(FsError::InvalidArgument)
+        ));
+
+        // Snapshots are renamed in place, with those taken along
+        let snapshots = dataset_ops::create_recursive_snapshot(&fs, team.id, "nightly", true)
+            .await
+            .unwrap();
+        let renamed = dataset_ops::rename_dataset(&fs, snapshots[0].id, "weekly")
+            .await
+            .unwrap();
+        assert_eq!(renamed.uuid, snapshots[0].uuid);
+        assert_eq!(
+            fs.dataset_store
+                .get_by_name("weekly/other/app")
+                .await
+                .unwrap()
+                .id,
+            snapshots[2].id
+        );
+        assert_eq!(
+            fs.snapshot_vfs
+                .lookup_in_snapshots(b"weekly")
+                .await
+                .unwrap(),
+            SnapshotVfs::inode_for_snapshot(snapshots[0].id)
+        );
+        assert!(
+            fs.snapshot_vfs
+                .lookup_in_snapshots(b"nightly")
+                .await
+                .is_err()
+        );
+        assert!(matches!(
+            dataset_ops::rename_dataset(&fs, snapshots[1].id, "weekly-other").await,
+            Err(FsError::InvalidArgument)
+        ));
+
+        // The moved dataset is older than its new parent, yet goes first
+        dataset_ops::delete_dataset_recursive(&fs, team.id)
+            .await
+            .unwrap();
+        assert!(fs.dataset_store.get_by_id(app.id).await.is_none());
+    }
 }
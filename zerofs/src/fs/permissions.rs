@@ -1,3 +1,4 @@
+use super::acl::{ACL_EXECUTE, ACL_READ, ACL_WRITE, Acl, is_acl_xattr};
 use super::errors::FsError;
 use super::inode::{Inode, InodeAttrs};
 use super::types::AuthContext;
@@ -94,6 +95,30 @@ pub fn check_access(inode: &Inode, creds: &Credentials, mode: AccessMode) -> Res
     Err(FsError::PermissionDenied)
 }
 
+/// Like `check_access`, deciding by `acl` when the inode has an access ACL
+pub fn check_access_acl(
+    inode: &Inode,
+    acl: Option<&Acl>,
+    creds: &Credentials,
+    mode: AccessMode,
+) -> Result<(), FsError> {
+    match acl {
+        Some(acl) if creds.uid != 0 => {
+            let want = match mode {
+                AccessMode::Read => ACL_READ,
+                AccessMode::Write => ACL_WRITE,
+                AccessMode::Execute => ACL_EXECUTE,
+            };
+            if acl.permits(inode.uid(), inode.gid(), creds, want) {
+                Ok(())
+            } else {
+                Err(FsError::PermissionDenied)
+            }
+        }
+        _ => check_access(inode, creds, mode),
+    }
+}
+
 pub fn check_ownership(inode: &Inode, creds: &Credentials) -> Result<(), FsError> {
     if creds.uid == 0 || creds.uid == inode.uid() {
         Ok(())
@@ -137,3 +162,68 @@ pub fn can_set_times(
 
     Err(FsError::OperationNotPermitted)
 }
+
+/// Check access to extended attribute `name` of `inode`, following the rules
+/// of its namespace:
+/// - `user.`: the file's permissions, on regular files and directories
+/// - `trusted.`: root only, for reading as well
+/// - `security.`: readable by anyone, writable by root
+/// - `system.posix_acl_*`: readable by anyone, writable by the owner and root
+///
+/// `acl` is the inode's access ACL.
+pub fn check_xattr_access(
+    inode: &Inode,
+    acl: Option<&Acl>,
+    creds: &Credentials,
+    name: &[u8],
+    mode: AccessMode,
+) -> Result<(), FsError> {
+    let writing = matches!(mode, AccessMode::Write);
+
+    if name.starts_with(b"user.") {
+        if !inode.is_file() && !inode.is_directory() {
+            return Err(if writing {
+                FsError::OperationNotPermitted
+            } else {
+                FsError::NoData
+            });
+        }
+        if writing
+            && inode.is_directory()
+            && inode.mode() & S_ISVTX != 0
+            && creds.uid != 0
+            && creds.uid != inode.uid()
+        {
+            return Err(FsError::OperationNotPermitted);
+        }
+        check_access_acl(inode, acl, creds, mode)
+    } else if is_acl_xattr(name) {
+        if inode.is_symlink() {
+            Err(FsError::NotSupported)
+        } else if writing {
+            check_ownership(inode, creds)
+        } else {
+            Ok(())
+        }
+    } else if name.starts_with(b"trusted.") {
+        if creds.uid == 0 {
+            Ok(())
+        } else {
+            Err(FsError::OperationNotPermitted)
+        }
+    } else if name.starts_with(b"security.") {
+        if writing && creds.uid != 0 {
+            Err(FsError::OperationNotPermitted)
+        } else {
+            Ok(())
+        }
+    } else {
+        Err(FsError::NotSupported)
+    }
+}
+
+/// Whether extended attribute `name` is listed to `creds`: `trusted.`
+/// attributes are hidden from everyone but root.
+pub fn can_list_xattr(creds: &Credentials, name: &[u8]) -> bool {
+    creds.uid == 0 || !name.starts_with(b"trusted.")
+}
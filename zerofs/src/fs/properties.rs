@@ -0,0 +1,463 @@
+use crate::config::CompressionConfig;
+use crate::fs::ZeroFS;
+use crate::fs::dataset::{Dataset, DatasetId, DatasetQuota, DatasetRegistry};
+use crate::fs::dataset_ops;
+use crate::fs::errors::FsError;
+use crate::fs::inode::InodeId;
+use serde::de::IntoDeserializer;
+use serde::de::value::{Error as ValueError, StrDeserializer};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Age past which `relatime` updates an access time in any case
+pub const RELATIME_INTERVAL_SECS: u64 = 24 * 60 * 60;
+
+/// When the access time of a file is updated on read
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AtimeMode {
+    /// Never
+    #[default]
+    Off,
+    /// On every read
+    On,
+    /// When it is older than the modification or change time, or a day old
+    Relatime,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CaseSensitivity {
+    #[default]
+    Sensitive,
+    /// Names are looked up ignoring case, and cannot differ from an existing
+    /// name of the directory only in case
+    Insensitive,
+}
+
+/// Properties set on a dataset itself. Unset ones are inherited from the
+/// parent dataset, and take their default where no dataset up to the root
+/// one sets them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DatasetProperties {
+    /// Compression of the chunks written in the dataset
+    pub compression: Option<CompressionConfig>,
+    pub atime: Option<AtimeMode>,
+    /// Whether files of the dataset can be modified
+    pub readonly: Option<bool>,
+    pub case_sensitivity: Option<CaseSensitivity>,
+    /// Owner of new inodes created without one
+    pub default_uid: Option<u32>,
+    /// Group of new inodes created without one
+    pub default_gid: Option<u32>,
+}
+
+impl DatasetProperties {
+    /// These properties, with the unset ones taken from `parent`
+    fn inherit(self, parent: &DatasetProperties) -> Self {
+        Self {
+            compression: self.compression.or(parent.compression),
+            atime: self.atime.or(parent.atime),
+            readonly: self.readonly.or(parent.readonly),
+            case_sensitivity: self.case_sensitivity.or(parent.case_sensitivity),
+            default_uid: self.default_uid.or(parent.default_uid),
+            default_gid: self.default_gid.or(parent.default_gid),
+        }
+    }
+
+    /// Value of inherited `property`, if set
+    fn get(&self, property: Property) -> Option<String> {
+        match property {
+            Property::Compression => self.compression.map(|c| c.to_string()),
+            Property::Atime => self.atime.map(|a| atime_name(a).to_string()),
+            Property::Readonly => self.readonly.map(|r| switch_name(r).to_string()),
+            Property::CaseSensitivity => self
+                .case_sensitivity
+                .map(|c| case_sensitivity_name(c).to_string()),
+            Property::DefaultUid => self.default_uid.map(|uid| uid.to_string()),
+            Property::DefaultGid => self.default_gid.map(|gid| gid.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Set inherited `property` to `value`, or unset it for `None`
+    fn set(&mut self, property: Property, value: Option<&str>) -> Result<(), FsError> {
+        match property {
+            Property::Compression => self.compression = value.map(parse_compression).transpose()?,
+            Property::Atime => self.atime = value.map(parse_atime).transpose()?,
+            Property::Readonly => self.readonly = value.map(parse_switch).transpose()?,
+            Property::CaseSensitivity => {
+                self.case_sensitivity = value.map(parse_case_sensitivity).transpose()?
+            }
+            Property::DefaultUid => self.default_uid = value.map(parse_number).transpose()?,
+            Property::DefaultGid => self.default_gid = value.map(parse_number).transpose()?,
+            _ => return Err(FsError::InvalidArgument),
+        }
+        Ok(())
+    }
+}
+
+/// Properties in effect in a dataset, once inherited ones are resolved
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EffectiveProperties {
+    /// None where the filesystem compression applies
+    pub compression: Option<CompressionConfig>,
+    pub atime: AtimeMode,
+    pub readonly: bool,
+    pub case_sensitivity: CaseSensitivity,
+    pub default_uid: Option<u32>,
+    pub default_gid: Option<u32>,
+}
+
+impl EffectiveProperties {
+    pub fn check_writable(&self) -> Result<(), FsError> {
+        if self.readonly {
+            return Err(FsError::ReadOnlyFilesystem);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Property {
+    Compression,
+    Atime,
+    Readonly,
+    CaseSensitivity,
+    DefaultUid,
+    DefaultGid,
+    QuotaBytesHard,
+    QuotaBytesSoft,
+    QuotaInodesHard,
+    QuotaInodesSoft,
+}
+
+impl Property {
+    pub const ALL: [Property; 10] = [
+        Property::Compression,
+        Property::Atime,
+        Property::Readonly,
+        Property::CaseSensitivity,
+        Property::DefaultUid,
+        Property::DefaultGid,
+        Property::QuotaBytesHard,
+        Property::QuotaBytesSoft,
+        Property::QuotaInodesHard,
+        Property::QuotaInodesSoft,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Property::Compression => "compression",
+            Property::Atime => "atime",
+            Property::Readonly => "readonly",
+            Property::CaseSensitivity => "case_sensitivity",
+            Property::DefaultUid => "default_uid",
+            Property::DefaultGid => "default_gid",
+            Property::QuotaBytesHard => "quota.bytes_hard",
+            Property::QuotaBytesSoft => "quota.bytes_soft",
+            Property::QuotaInodesHard => "quota.inodes_hard",
+            Property::QuotaInodesSoft => "quota.inodes_soft",
+        }
+    }
+
+    /// Whether a dataset that does not set the property takes the value of
+    /// its parent. Quotas only apply to the dataset they are set on.
+    pub fn is_inherited(self) -> bool {
+        !matches!(
+            self,
+            Property::QuotaBytesHard
+                | Property::QuotaBytesSoft
+                | Property::QuotaInodesHard
+                | Property::QuotaInodesSoft
+        )
+    }
+
+    /// Limit of `quota` set by a quota property
+    fn quota_limit(self, quota: &mut DatasetQuota) -> Option<&mut Option<u64>> {
+        match self {
+            Property::QuotaBytesHard => Some(&mut quota.bytes_hard),
+            Property::QuotaBytesSoft => Some(&mut quota.bytes_soft),
+            Property::QuotaInodesHard => Some(&mut quota.inodes_hard),
+            Property::QuotaInodesSoft => Some(&mut quota.inodes_soft),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Property {
+    type Err = FsError;
+
+    fn from_str(name: &str) -> Result<Self, FsError> {
+        Property::ALL
+            .into_iter()
+            .find(|p| p.name() == name)
+            .ok_or(FsError::InvalidArgument)
+    }
+}
+
+impl fmt::Display for Property {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PropertySource {
+    /// Set on the dataset itself
+    Local,
+    /// Set on a dataset it inherits from
+    Inherited,
+    /// Not set anywhere
+    Default,
+}
+
+impl PropertySource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PropertySource::Local => "local",
+            PropertySource::Inherited => "inherited",
+            PropertySource::Default => "default",
+        }
+    }
+}
+
+impl FromStr for PropertySource {
+    type Err = FsError;
+
+    fn from_str(source: &str) -> Result<Self, FsError> {
+        match source {
+            "local" => Ok(PropertySource::Local),
+            "inherited" => Ok(PropertySource::Inherited),
+            "default" => Ok(PropertySource::Default),
+            _ => Err(FsError::InvalidArgument),
+        }
+    }
+}
+
+/// A property of a dataset as reported to users
+#[derive(Debug, Clone, Serialize)]
+pub struct PropertyValue {
+    pub property: String,
+    pub value: String,
+    pub source: PropertySource,
+    /// Dataset the value is inherited from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inherited_from: Option<String>,
+}
+
+/// Datasets dataset `id` inherits properties from, starting with itself
+fn lineage(registry: &DatasetRegistry, id: DatasetId) -> impl Iterator<Item = &Dataset> {
+    std::iter::successors(registry.get_by_id(id), |d| {
+        registry
+            .property_parent(d.id)
+            .and_then(|parent| registry.get_by_id(parent))
+    })
+}
+
+/// Properties in effect in dataset `id`
+pub fn effective_properties(registry: &DatasetRegistry, id: DatasetId) -> EffectiveProperties {
+    let properties = lineage(registry, id).fold(DatasetProperties::default(), |props, d| {
+        props.inherit(&d.properties)
+    });
+    let is_readonly = registry.get_by_id(id).is_some_and(|d| d.is_readonly);
+    EffectiveProperties {
+        compression: properties.compression,
+        atime: properties.atime.unwrap_or_default(),
+        readonly: is_readonly || properties.readonly.unwrap_or_default(),
+        case_sensitivity: properties.case_sensitivity.unwrap_or_default(),
+        default_uid: properties.default_uid,
+        default_gid: properties.default_gid,
+    }
+}
+
+/// Compression of the datasets whose effective compression is not the
+/// filesystem one
+pub fn compression_overrides(registry: &DatasetRegistry) -> HashMap<DatasetId, CompressionConfig> {
+    registry
+        .datasets
+        .keys()
+        .filter_map(|&id| Some((id, effective_properties(registry, id).compression?)))
+        .collect()
+}
+
+/// Every property of dataset `id` with where its value comes from.
+/// `compression` is the filesystem compression, used by default.
+pub fn property_values(
+    registry: &DatasetRegistry,
+    id: DatasetId,
+    compression: CompressionConfig,
+) -> Result<Vec<PropertyValue>, FsError> {
+    let dataset = registry.get_by_id(id).ok_or(FsError::NotFound)?;
+    let values = Property::ALL
+        .into_iter()
+        .map(|property| {
+            let reported = |value: String, source| PropertyValue {
+                property: property.name().to_string(),
+                value,
+                source,
+                inherited_from: None,
+            };
+
+            let mut quota = dataset.quota;
+            if let Some(limit) = property.quota_limit(&mut quota) {
+                return match *limit {
+                    Some(limit) => reported(limit.to_string(), PropertySource::Local),
+                    None => reported("none".to_string(), PropertySource::Default),
+                };
+            }
+            if property == Property::Readonly && dataset.is_readonly {
+                return reported(switch_name(true).to_string(), PropertySource::Local);
+            }
+
+            let inherited =
+                lineage(registry, id).find_map(|d| Some((d.properties.get(property)?, d)));
+            match inherited {
+                Some((value, d)) if d.id == id => reported(value, PropertySource::Local),
+                Some((value, d)) => PropertyValue {
+                    inherited_from: Some(d.name.clone()),
+                    ..reported(value, PropertySource::Inherited)
+                },
+                None => reported(
+                    default_value(property, compression),
+                    PropertySource::Default,
+                ),
+            }
+        })
+        .collect();
+    Ok(values)
+}
+
+/// Set `property` on `dataset` to `value`, or unset it for `None`. Setting a
+/// quota to `none` removes it too.
+pub fn set_property(
+    dataset: &mut Dataset,
+    property: Property,
+    value: Option<&str>,
+) -> Result<(), FsError> {
+    match property.quota_limit(&mut dataset.quota) {
+        Some(limit) => {
+            if dataset.is_snapshot {
+                return Err(FsError::InvalidArgument);
+            }
+            *limit = match value {
+                None | Some("none") => None,
+                Some(value) => Some(parse_number(value)?),
+            };
+            Ok(())
+        }
+        None => {
+            // Snapshots taken read-only stay so
+            if property == Property::Readonly && dataset.is_readonly {
+                return Err(FsError::InvalidArgument);
+            }
+            dataset.properties.set(property, value)
+        }
+    }
+}
+
+fn default_value(property: Property, compression: CompressionConfig) -> String {
+    let defaults = EffectiveProperties::default();
+    match property {
+        Property::Compression => compression.to_string(),
+        Property::Atime => atime_name(defaults.atime).to_string(),
+        Property::Readonly => switch_name(defaults.readonly).to_string(),
+        Property::CaseSensitivity => case_sensitivity_name(defaults.case_sensitivity).to_string(),
+        _ => "none".to_string(),
+    }
+}
+
+fn switch_name(on: bool) -> &'static str {
+    if on { "on" } else { "off" }
+}
+
+fn atime_name(atime: AtimeMode) -> &'static str {
+    match atime {
+        AtimeMode::Off => "off",
+        AtimeMode::On => "on",
+        AtimeMode::Relatime => "relatime",
+    }
+}
+
+fn case_sensitivity_name(case_sensitivity: CaseSensitivity) -> &'static str {
+    match case_sensitivity {
+        CaseSensitivity::Sensitive => "sensitive",
+        CaseSensitivity::Insensitive => "insensitive",
+    }
+}
+
+fn parse_compression(value: &str) -> Result<CompressionConfig, FsError> {
+    let deserializer: StrDeserializer<ValueError> = value.into_deserializer();
+    CompressionConfig::deserialize(deserializer).map_err(|_| FsError::InvalidArgument)
+}
+
+fn parse_switch(value: &str) -> Result<bool, FsError> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(FsError::InvalidArgument),
+    }
+}
+
+fn parse_atime(value: &str) -> Result<AtimeMode, FsError> {
+    match value {
+        "relatime" => Ok(AtimeMode::Relatime),
+        value => Ok(if parse_switch(value)? {
+            AtimeMode::On
+        } else {
+            AtimeMode::Off
+        }),
+    }
+}
+
+fn parse_case_sensitivity(value: &str) -> Result<CaseSensitivity, FsError> {
+    match value {
+        "sensitive" => Ok(CaseSensitivity::Sensitive),
+        "insensitive" => Ok(CaseSensitivity::Insensitive),
+        _ => Err(FsError::InvalidArgument),
+    }
+}
+
+fn parse_number<T: FromStr>(value: &str) -> Result<T, FsError> {
+    value.parse().map_err(|_| FsError::InvalidArgument)
+}
+
+/// Entry `name` resolves to in directory `dir_id` when no entry has that
+/// exact name: one differing only in case, if the dataset is
+/// case-insensitive. Returns the entry's name and inode.
+pub async fn lookup_case_variant(
+    fs: &ZeroFS,
+    dir_id: InodeId,
+    name: &[u8],
+) -> Result<(Vec<u8>, InodeId), FsError> {
+    let properties = dataset_ops::dir_properties(fs, dir_id).await?;
+    if properties.case_sensitivity == CaseSensitivity::Sensitive {
+        return Err(FsError::NotFound);
+    }
+    fs.directory_store
+        .find_case_variant(fs.lazy_dirs.entries_dir(dir_id).await?, name)
+        .await?
+        .ok_or(FsError::NotFound)
+}
+
+/// Whether an entry `name` cannot be created in directory `dir_id`, of a
+/// dataset with `properties`, because one by that name exists
+pub async fn name_taken(
+    fs: &ZeroFS,
+    properties: &EffectiveProperties,
+    dir_id: InodeId,
+    name: &[u8],
+) -> Result<bool, FsError> {
+    if fs.directory_store.exists(dir_id, name).await? {
+        return Ok(true);
+    }
+    match properties.case_sensitivity {
+        CaseSensitivity::Sensitive => Ok(false),
+        CaseSensitivity::Insensitive => Ok(fs
+            .directory_store
+            .find_case_variant(dir_id, name)
+            .await?
+            .is_some()),
+    }
+}
@@ -0,0 +1,186 @@
+use crate::fs::ZeroFS;
+use crate::fs::dataset_ops;
+use crate::fs::errors::FsError;
+use crate::fs::stats::{InodeAccount, UsageScope};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use tracing::{debug, warn};
+
+/// Owner of inodes, whose usage is tracked and can be limited
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Owner {
+    User(u32),
+    Group(u32),
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Owner::User(uid) => write!(f, "uid {}", uid),
+            Owner::Group(gid) => write!(f, "gid {}", gid),
+        }
+    }
+}
+
+/// Hard limits on the usage of an owner. None means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OwnerQuota {
+    pub bytes: Option<u64>,
+    pub inodes: Option<u64>,
+}
+
+impl OwnerQuota {
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_none() && self.inodes.is_none()
+    }
+}
+
+/// Usage and limits of an owner
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OwnerReport {
+    pub owner: Owner,
+    pub bytes: u64,
+    pub inodes: u64,
+    pub quota: OwnerQuota,
+}
+
+/// Usage and limits of `owner`
+pub async fn owner_report(fs: &ZeroFS, owner: Owner) -> OwnerReport {
+    let (bytes, inodes) = fs.global_stats.get_scope_totals(UsageScope::Owner(owner));
+    OwnerReport {
+        owner,
+        bytes,
+        inodes,
+        quota: fs.quota_store.get(owner).await,
+    }
+}
+
+/// Usage and limits of every owner that has either, ordered by owner
+pub async fn report(fs: &ZeroFS) -> Vec<OwnerReport> {
+    let quotas = fs.quota_store.list().await;
+
+    let mut reports: BTreeMap<Owner, OwnerReport> = BTreeMap::new();
+    for (owner, bytes, inodes) in fs.global_stats.owner_totals() {
+        if bytes == 0 && inodes == 0 {
+            continue;
+        }
+        reports.insert(
+            owner,
+            OwnerReport {
+                owner,
+                bytes,
+                inodes,
+                quota: quotas.get(&owner).copied().unwrap_or_default(),
+            },
+        );
+    }
+    for (owner, quota) in quotas {
+        reports.entry(owner).or_insert(OwnerReport {
+            owner,
+            bytes: 0,
+            inodes: 0,
+            quota,
+        });
+    }
+
+    reports.into_values().collect()
+}
+
+/// Check that the dataset and owners of `account` can take `bytes` and
+/// `inodes` more: fails with `QuotaExceeded` past a hard limit, and logs
+/// crossing a soft limit.
+pub async fn check_quota(
+    fs: &ZeroFS,
+    account: InodeAccount,
+    bytes: u64,
+    inodes: u64,
+) -> Result<(), FsError> {
+    let quota = fs.dataset_store.get_quota(account.dataset).await;
+    if !quota.is_empty() {
+        let scope = UsageScope::Dataset(account.dataset);
+        let usage = dataset_ops::dataset_usage(fs, account.dataset).await;
+        check_limit(
+            scope,
+            "bytes",
+            usage.bytes,
+            bytes,
+            quota.bytes_hard,
+            quota.bytes_soft,
+        )?;
+        check_limit(
+            scope,
+            "inodes",
+            usage.inodes,
+            inodes,
+            quota.inodes_hard,
+            quota.inodes_soft,
+        )?;
+    }
+
+    check_owner_quota(fs, Owner::User(account.uid), bytes, inodes).await?;
+    check_owner_quota(fs, Owner::Group(account.gid), bytes, inodes).await
+}
+
+/// Check that the owners an inode of `size` bytes is given to by chown,
+/// from `old` to `new` as (uid, gid), can take it
+pub async fn check_owner_change(
+    fs: &ZeroFS,
+    old: (u32, u32),
+    new: (u32, u32),
+    size: u64,
+) -> Result<(), FsError> {
+    if new.0 != old.0 {
+        check_owner_quota(fs, Owner::User(new.0), size, 1).await?;
+    }
+    if new.1 != old.1 {
+        check_owner_quota(fs, Owner::Group(new.1), size, 1).await?;
+    }
+    Ok(())
+}
+
+async fn check_owner_quota(
+    fs: &ZeroFS,
+    owner: Owner,
+    bytes: u64,
+    inodes: u64,
+) -> Result<(), FsError> {
+    let quota = fs.quota_store.get(owner).await;
+    if quota.is_empty() {
+        return Ok(());
+    }
+
+    let scope = UsageScope::Owner(owner);
+    let (used_bytes, used_inodes) = fs.global_stats.get_scope_totals(scope);
+    check_limit(scope, "bytes", used_bytes, bytes, quota.bytes, None)?;
+    check_limit(scope, "inodes", used_inodes, inodes, quota.inodes, None)
+}
+
+fn check_limit(
+    scope: UsageScope,
+    unit: &str,
+    used: u64,
+    added: u64,
+    hard: Option<u64>,
+    soft: Option<u64>,
+) -> Result<(), FsError> {
+    if added == 0 {
+        return Ok(());
+    }
+
+    let total = used.saturating_add(added);
+    if hard.is_some_and(|hard| total > hard) {
+        debug!(
+            "{} is out of {}: used={}, added={}, limit={:?}",
+            scope, unit, used, added, hard
+        );
+        return Err(FsError::QuotaExceeded);
+    }
+    if let Some(soft) = soft
+        && used <= soft
+        && total > soft
+    {
+        warn!("{} went over its soft limit of {} {}", scope, soft, unit);
+    }
+    Ok(())
+}
@@ -0,0 +1,224 @@
+use crate::encryption::{EncryptedDb, KeyId};
+use crate::fs::errors::FsError;
+use crate::fs::key_codec::KeyCodec;
+use crate::fs::store::dataset::DatasetStore;
+use crate::task::{spawn_named, spawn_named_on};
+use bytes::Bytes;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use slatedb::config::{PutOptions, WriteOptions};
+use std::ops::Bound;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+const MAX_KEYS_PER_BATCH: usize = 1_000;
+const RETRY_INTERVAL_SECS: u64 = 60;
+
+/// Progress of the re-encryption pass, persisted after every batch so an
+/// interrupted pass resumes where it stopped.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ReencryptionState {
+    /// Key generation values are re-encrypted with
+    pub key_id: KeyId,
+    /// Last key of the keyspace that has been checked
+    pub resume_after: Option<Vec<u8>>,
+    /// Every stored value is encrypted with `key_id`
+    pub complete: bool,
+    /// No value without associated data is left
+    pub unbound_cleared: bool,
+}
+
+impl ReencryptionState {
+    pub async fn load(db: &EncryptedDb) -> Result<Self, FsError> {
+        match db
+            .get_bytes(&KeyCodec::system_reencryption_key())
+            .await
+            .map_err(|_| FsError::IoError)?
+        {
+            Some(data) => bincode::deserialize(&data).map_err(|_| FsError::InvalidData),
+            None => Ok(Self::default()),
+        }
+    }
+
+    async fn save(&self, db: &EncryptedDb) -> Result<(), FsError> {
+        let data = bincode::serialize(self).map_err(|_| FsError::IoError)?;
+        db.put_with_options(
+            &KeyCodec::system_reencryption_key(),
+            &data,
+            &PutOptions::default(),
+            &WriteOptions {
+                await_durable: false,
+            },
+        )
+        .await
+        .map_err(|_| FsError::IoError)
+    }
+}
+
+/// Background pass re-encrypting stored values that are not in the current
+/// format and key generation: values written before ciphertexts were bound
+/// to their storage key, and values encrypted with a key that was rotated.
+///
+/// Walks the whole keyspace once per key generation, in batches, then
+/// rewraps the keys of encrypted datasets. When it completes, values without
+/// associated data are no longer accepted, here and on later starts, and
+/// older key generations can be retired.
+pub struct Reencryptor {
+    db: Arc<EncryptedDb>,
+    datasets: DatasetStore,
+}
+
+impl Reencryptor {
+    pub fn new(db: Arc<EncryptedDb>, datasets: DatasetStore) -> Self {
+        Self { db, datasets }
+    }
+
+    pub fn start(
+        self: Arc<Self>,
+        shutdown: CancellationToken,
+        runtime: Option<tokio::runtime::Handle>,
+    ) -> JoinHandle<()> {
+        let fut = async move {
+            loop {
+                tokio::select! {
+                    _ = shutdown.cancelled() => {
+                        info!("Re-encryption task shutting down");
+                        break;
+                    }
+                    result = self.run() => match result {
+                        Ok(()) => break,
+                        Err(e) => tracing::error!("Re-encryption failed: {:?}", e),
+                    }
+                }
+
+                tokio::select! {
+                    _ = shutdown.cancelled() => {
+                        info!("Re-encryption task shutting down");
+                        break;
+                    }
+                    _ = tokio::time::sleep(std::time::Duration::from_secs(RETRY_INTERVAL_SECS)) => {}
+                }
+            }
+        };
+
+        if let Some(rt) = runtime {
+            spawn_named_on("reencrypt", fut, &rt)
+        } else {
+            spawn_named("reencrypt", fut)
+        }
+    }
+
+    pub async fn run(&self) -> Result<(), FsError> {
+        let mut state = ReencryptionState::load(&self.db).await?;
+        let key_id = self.db.current_key_id();
+        if state.key_id != key_id {
+            state = ReencryptionState {
+                key_id,
+                resume_after: None,
+                complete: false,
+                unbound_cleared: state.unbound_cleared,
+            };
+        }
+        if state.complete {
+            return Ok(());
+        }
+
+        if state.resume_after.is_none() {
+            info!("Re-encrypting stored values with key generation {}", key_id);
+        }
+
+        let mut rewritten = 0;
+        loop {
+            let start = match &state.resume_after {
+                Some(key) => Bound::Excluded(Bytes::from(key.clone())),
+                None => Bound::Unbounded,
+            };
+
+            let mut last_key = None;
+            let mut stale = Vec::new();
+            {
+                let iter = self
+                    .db
+                    .scan_raw((start, Bound::Unbounded))
+                    .await
+                    .map_err(|_| FsError::IoError)?;
+                let mut iter = iter.take(MAX_KEYS_PER_BATCH);
+
+                while let Some(result) = iter.next().await {
+                    let (key, stored) = result.map_err(|_| FsError::IoError)?;
+                    if self.db.needs_reencryption(&key, &stored) {
+                        stale.push(key.clone());
+                    }
+                    last_key = Some(key);
+                }
+            }
+
+            let Some(last_key) = last_key else {
+                break;
+            };
+
+            rewritten += self
+                .db
+                .reencrypt(stale)
+                .await
+                .map_err(|_| FsError::IoError)?;
+
+            state.resume_after = Some(last_key.to_vec());
+            state.save(&self.db).await?;
+            tokio::task::yield_now().await;
+        }
+
+        let rewrapped = self.datasets.rewrap_keys().await?;
+        if rewrapped > 0 {
+            info!("Rewrapped {} dataset keys", rewrapped);
+        }
+
+        state.resume_after = None;
+        state.complete = true;
+        state.unbound_cleared = true;
+        state.save(&self.db).await?;
+        self.db.reject_unbound_values();
+
+        info!("Re-encryption complete: {} values rewritten", rewritten);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::ZeroFS;
+
+    #[tokio::test]
+    async fn test_reencrypts_unbound_values() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let reencryptor = Reencryptor::new(fs.db.clone(), fs.dataset_store.clone());
+
+        let keys: Vec<_> = (100..110).map(KeyCodec::inode_key).collect();
+        for key in &keys {
+            fs.db.put_unbound(key, key).await.unwrap();
+        }
+
+        reencryptor.run().await.unwrap();
+
+        let iter = fs.db.scan_raw(..).await.unwrap();
+        futures::pin_mut!(iter);
+        while let Some(result) = iter.next().await {
+            let (key, stored) = result.unwrap();
+            assert!(!fs.db.needs_reencryption(&key, &stored));
+        }
+        for key in &keys {
+            assert_eq!(fs.db.get_bytes(key).await.unwrap().as_ref(), Some(key));
+        }
+
+        let state = ReencryptionState::load(&fs.db).await.unwrap();
+        assert!(state.complete);
+        assert!(state.unbound_cleared);
+
+        // Values that are not bound are no longer accepted
+        fs.db.put_unbound(&keys[0], &keys[0]).await.unwrap();
+        fs.db.get_bytes(&keys[0]).await.unwrap_err();
+    }
+}
@@ -0,0 +1,200 @@
+use crate::fs::clone;
+use crate::fs::dataset::DatasetId;
+use crate::fs::destroy::DestroyProgress;
+use crate::fs::errors::FsError;
+use crate::fs::inode::{Inode, InodeId};
+use crate::fs::{ROOT_INODE_ID, ZeroFS, validate_filename};
+use serde::{Deserialize, Serialize};
+
+/// What to do when the destination of a restore already exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnConflict {
+    /// Fail with `Exists`
+    #[default]
+    Fail,
+    /// Replace the existing entry, see `clone::replace_entry`
+    Overwrite,
+    /// Restore next to it, as `<name>.restored` or `<name>.restored.<n>`
+    Rename,
+}
+
+/// A file or subtree restored from a snapshot. A dry run reports the
+/// attributes of the source.
+#[derive(Debug, Clone)]
+pub struct RestoreOutcome {
+    /// Inode of the restored copy; 0 for a dry run
+    pub inode_id: InodeId,
+    /// Path the copy was restored to, which differs from the requested one
+    /// after a rename
+    pub destination_path: String,
+    pub is_directory: bool,
+    /// File size, 0 for directories
+    pub size: u64,
+    pub nlink: u32,
+    /// Whether an existing entry was replaced
+    pub replaced: bool,
+    /// Tree detached by the replacement, left to the reclaimer
+    pub detached: Option<DestroyProgress>,
+    pub dry_run: bool,
+}
+
+/// Highest `<name>.restored.<n>` tried before giving up with `Exists`
+const MAX_RENAME_ATTEMPTS: u32 = 1000;
+
+/// Split an absolute path into its components, ignoring empty ones
+fn components(path: &str) -> Vec<&[u8]> {
+    path.split('/')
+        .filter(|part| !part.is_empty())
+        .map(str::as_bytes)
+        .collect()
+}
+
+/// Resolve `parts` from directory `from`, without writing: lazy directories
+/// on the way are read through their origins, so the entry found can be
+/// the origin's. Hold `fs.lazy_dirs.hold_exclusive()` while using it.
+pub(crate) async fn resolve(
+    fs: &ZeroFS,
+    from: InodeId,
+    parts: &[&[u8]],
+) -> Result<InodeId, FsError> {
+    let mut current = from;
+    for part in parts {
+        if !fs.inode_store.get(current).await?.is_directory() {
+            return Err(FsError::NotDirectory);
+        }
+        current = fs
+            .directory_store
+            .get(fs.lazy_dirs.entries_dir(current).await?, part)
+            .await?;
+    }
+    Ok(current)
+}
+
+/// Like `resolve`, but lazy directories on the way are materialized, so the
+/// entry found is the one below `from`, which can be modified. The caller
+/// holds the lazy directory gate, either side of it.
+pub(crate) async fn resolve_materialized(
+    fs: &ZeroFS,
+    from: InodeId,
+    parts: &[&[u8]],
+) -> Result<InodeId, FsError> {
+    let mut current = from;
+    for part in parts {
+        if !fs.inode_store.get(current).await?.is_directory() {
+            return Err(FsError::NotDirectory);
+        }
+        clone::materialize_dir(fs, current).await?;
+        current = fs.directory_store.get(current, part).await?;
+    }
+    Ok(current)
+}
+
+/// Restore `source_path` of snapshot `snapshot_id` to `destination_path`,
+/// counted from the filesystem root. The copy is a clone: files share their
+/// data with the snapshot and a directory is copied lazily, so the cost
+/// does not depend on the size of what is restored. An empty source path
+/// restores the whole snapshot.
+///
+/// With `dry_run`, nothing is written: the outcome is what the restore
+/// would do at this point.
+pub async fn restore(
+    fs: &ZeroFS,
+    snapshot_id: DatasetId,
+    source_path: &str,
+    destination_path: &str,
+    on_conflict: OnConflict,
+    dry_run: bool,
+) -> Result<RestoreOutcome, FsError> {
+    let snapshot = fs
+        .dataset_store
+        .get_by_id(snapshot_id)
+        .await
+        .filter(|d| d.is_snapshot)
+        .ok_or(FsError::NotFound)?;
+
+    let dest_parts = components(destination_path);
+    let Some((name, dest_dir_parts)) = dest_parts.split_last() else {
+        return Err(FsError::InvalidArgument);
+    };
+    validate_filename(name)?;
+
+    // Both trees stay as they are from the lookups to the clone. A dry run
+    // reads through lazy directories; a restore copies those on the way, as
+    // the clone needs the entries of the snapshot and of the destination
+    // themselves.
+    let _gate = fs.lazy_dirs.hold_exclusive().await;
+    let source_parts = components(source_path);
+    let (source_id, dest_dir_id) = if dry_run {
+        (
+            resolve(fs, snapshot.root_inode, &source_parts).await?,
+            resolve(fs, ROOT_INODE_ID, dest_dir_parts).await?,
+        )
+    } else {
+        (
+            resolve_materialized(fs, snapshot.root_inode, &source_parts).await?,
+            resolve_materialized(fs, ROOT_INODE_ID, dest_dir_parts).await?,
+        )
+    };
+    let source = fs.inode_store.get(source_id).await?;
+    if !fs.inode_store.get(dest_dir_id).await?.is_directory() {
+        return Err(FsError::NotDirectory);
+    }
+
+    let entries_dir = fs.lazy_dirs.entries_dir(dest_dir_id).await?;
+    let exists = fs.directory_store.exists(entries_dir, name).await?;
+    let name = match (exists, on_conflict) {
+        (true, OnConflict::Fail) => return Err(FsError::Exists),
+        (true, OnConflict::Rename) => free_name(fs, entries_dir, name).await?,
+        _ => name.to_vec(),
+    };
+    let replaced = exists && on_conflict == OnConflict::Overwrite;
+
+    let mut path: Vec<u8> = Vec::new();
+    for part in dest_dir_parts.iter().copied().chain([name.as_slice()]) {
+        path.push(b'/');
+        path.extend_from_slice(part);
+    }
+    let destination_path = String::from_utf8_lossy(&path).into_owned();
+
+    let (inode_id, inode, detached) = if dry_run {
+        (0, source, None)
+    } else {
+        clone::clone_into(fs, source_id, dest_dir_id, &name, replaced).await?
+    };
+
+    let (size, nlink) = match &inode {
+        Inode::File(file) => (file.size, file.nlink),
+        Inode::Directory(dir) => (0, dir.nlink),
+        Inode::Symlink(link) => (0, link.nlink),
+        Inode::Fifo(special)
+        | Inode::Socket(special)
+        | Inode::CharDevice(special)
+        | Inode::BlockDevice(special) => (0, special.nlink),
+    };
+
+    Ok(RestoreOutcome {
+        inode_id,
+        destination_path,
+        is_directory: inode.is_directory(),
+        size,
+        nlink,
+        replaced,
+        detached,
+        dry_run,
+    })
+}
+
+/// The first of `<name>.restored`, `<name>.restored.1`, ... not present in
+/// directory `dir_id`
+async fn free_name(fs: &ZeroFS, dir_id: InodeId, name: &[u8]) -> Result<Vec<u8>, FsError> {
+    let mut candidate = [name, b".restored".as_slice()].concat();
+    for n in 1..=MAX_RENAME_ATTEMPTS {
+        validate_filename(&candidate)?;
+        if !fs.directory_store.exists(dir_id, &candidate).await? {
+            return Ok(candidate);
+        }
+        candidate = [name, b".restored.".as_slice(), n.to_string().as_bytes()].concat();
+    }
+    Err(FsError::Exists)
+}
@@ -0,0 +1,319 @@
+use crate::config::SnapshotScheduleConfig;
+use crate::fs::ZeroFS;
+use crate::fs::dataset::{Dataset, DatasetId};
+use crate::fs::dataset_ops;
+use crate::fs::errors::FsError;
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+/// Separates the dataset name from the time in the name of a scheduled
+/// snapshot
+pub const AUTO_SNAPSHOT_MARKER: &str = "@auto-";
+/// UTC time a scheduled snapshot was taken at, in its name
+const AUTO_SNAPSHOT_TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl Period {
+    /// Shortest first
+    pub const ALL: [Period; 4] = [
+        Period::Hourly,
+        Period::Daily,
+        Period::Weekly,
+        Period::Monthly,
+    ];
+
+    /// Index of the period `time` falls in, in UTC. Weeks start on Monday.
+    fn index(self, time: u64) -> i64 {
+        let time = time as i64;
+        match self {
+            Period::Hourly => time.div_euclid(3600),
+            Period::Daily => time.div_euclid(86400),
+            // 1970-01-01 was a Thursday
+            Period::Weekly => (time.div_euclid(86400) + 3).div_euclid(7),
+            Period::Monthly => {
+                let date = DateTime::from_timestamp(time, 0).unwrap_or_default();
+                date.year() as i64 * 12 + date.month0() as i64
+            }
+        }
+    }
+
+    /// Start of the period after the one `time` falls in
+    fn next_start(self, time: u64) -> u64 {
+        let next = self.index(time) + 1;
+        let start = match self {
+            Period::Hourly => next * 3600,
+            Period::Daily => next * 86400,
+            Period::Weekly => (next * 7 - 3) * 86400,
+            Period::Monthly => NaiveDate::from_ymd_opt(
+                next.div_euclid(12) as i32,
+                next.rem_euclid(12) as u32 + 1,
+                1,
+            )
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .map(|date| date.and_utc().timestamp())
+            .unwrap_or(i64::MAX),
+        };
+        start.max(0) as u64
+    }
+
+    fn keep(self, schedule: &SnapshotScheduleConfig) -> u32 {
+        match self {
+            Period::Hourly => schedule.hourly,
+            Period::Daily => schedule.daily,
+            Period::Weekly => schedule.weekly,
+            Period::Monthly => schedule.monthly,
+        }
+    }
+}
+
+/// Period snapshots of `schedule` are taken at: the shortest one that keeps
+/// any
+fn snapshot_period(schedule: &SnapshotScheduleConfig) -> Option<Period> {
+    Period::ALL.into_iter().find(|p| p.keep(schedule) > 0)
+}
+
+/// Name of the snapshot of `dataset_name` scheduled at `time`
+pub fn auto_snapshot_name(dataset_name: &str, time: u64) -> String {
+    let time = DateTime::from_timestamp(time as i64, 0).unwrap_or_default();
+    format!(
+        "{}{}{}",
+        dataset_name,
+        AUTO_SNAPSHOT_MARKER,
+        time.format(AUTO_SNAPSHOT_TIME_FORMAT)
+    )
+}
+
+/// Time a scheduled snapshot was taken at, from its name; None for a
+/// snapshot taken otherwise
+pub fn auto_snapshot_time(snapshot_name: &str) -> Option<u64> {
+    let (_, time) = snapshot_name.rsplit_once(AUTO_SNAPSHOT_MARKER)?;
+    let time = NaiveDateTime::parse_from_str(time, AUTO_SNAPSHOT_TIME_FORMAT).ok()?;
+    u64::try_from(time.and_utc().timestamp()).ok()
+}
+
+/// Snapshots to keep out of `snapshots`, given with their time, newest
+/// first: for each period, the newest snapshot of each of the most recent
+/// periods that have one, up to the number `schedule` keeps
+fn retained(
+    schedule: &SnapshotScheduleConfig,
+    snapshots: &[(DatasetId, u64)],
+) -> BTreeSet<DatasetId> {
+    let mut keep = BTreeSet::new();
+    for period in Period::ALL {
+        let count = period.keep(schedule) as usize;
+        let mut last = None;
+        let mut kept = 0;
+        for &(id, time) in snapshots {
+            if kept == count {
+                break;
+            }
+            let index = period.index(time);
+            if last != Some(index) {
+                keep.insert(id);
+                last = Some(index);
+                kept += 1;
+            }
+        }
+    }
+    keep
+}
+
+/// State of the schedule of one dataset, as of its last run
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScheduleStatus {
+    pub dataset: String,
+    pub schedule: SnapshotScheduleConfig,
+    pub last_run: Option<u64>,
+    /// When the next snapshot is due
+    pub next_run: Option<u64>,
+    pub last_snapshot: Option<String>,
+    /// Scheduled snapshots kept
+    pub snapshots: u32,
+    /// Snapshots not taken while the server was down, before the one taken
+    /// on the last catch-up
+    pub missed_runs: u64,
+    /// Scheduled snapshots destroyed since the server started
+    pub pruned: u64,
+    pub last_error: Option<String>,
+}
+
+/// Takes the snapshots configured in `[snapshot_schedules]` and destroys
+/// those past their retention. Only snapshots named by the scheduler are
+/// pruned; others of the same dataset are left alone, and so are held ones
+/// until released.
+///
+/// A snapshot is due when none has been taken yet in the current period, so
+/// one missed while the server was down is taken on the next run; the
+/// periods missed are not made up for.
+pub struct SnapshotScheduler {
+    fs: Arc<ZeroFS>,
+    schedules: BTreeMap<String, SnapshotScheduleConfig>,
+    status: Mutex<BTreeMap<String, ScheduleStatus>>,
+}
+
+impl SnapshotScheduler {
+    pub fn new(fs: Arc<ZeroFS>, schedules: BTreeMap<String, SnapshotScheduleConfig>) -> Self {
+        let status = schedules
+            .iter()
+            .map(|(dataset, schedule)| {
+                let status = ScheduleStatus {
+                    dataset: dataset.clone(),
+                    schedule: *schedule,
+                    ..Default::default()
+                };
+                (dataset.clone(), status)
+            })
+            .collect();
+        Self {
+            fs,
+            schedules,
+            status: Mutex::new(status),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.schedules.is_empty()
+    }
+
+    pub fn status(&self) -> Vec<ScheduleStatus> {
+        self.status.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Earliest time a snapshot is due, after the last run
+    pub fn next_run(&self) -> Option<u64> {
+        self.status
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|s| s.next_run)
+            .min()
+    }
+
+    /// Take the snapshots due at `now` and prune expired ones
+    pub async fn run(&self, now: u64) {
+        for (name, schedule) in &self.schedules {
+            let result = self.run_schedule(name, schedule, now).await;
+
+            let mut status = self.status.lock().unwrap();
+            let status = status.entry(name.clone()).or_default();
+            status.last_run = Some(now);
+            status.next_run = snapshot_period(schedule).map(|p| p.next_start(now));
+            match result {
+                Ok(run) => {
+                    if run.taken.is_some() {
+                        status.last_snapshot = run.taken;
+                        status.missed_runs = run.missed;
+                    }
+                    status.snapshots = run.kept;
+                    status.pruned += run.pruned;
+                    status.last_error = None;
+                }
+                Err(e) => status.last_error = Some(e),
+            }
+        }
+    }
+
+    async fn run_schedule(
+        &self,
+        name: &str,
+        schedule: &SnapshotScheduleConfig,
+        now: u64,
+    ) -> Result<ScheduleRun, String> {
+        let fs = &self.fs;
+        let Some(period) = snapshot_period(schedule) else {
+            return Ok(ScheduleRun::default());
+        };
+        let dataset = fs
+            .dataset_store
+            .get_by_name(name)
+            .await
+            .filter(|d| !d.is_snapshot)
+            .ok_or_else(|| format!("Dataset '{}' not found", name))?;
+
+        let mut snapshots = scheduled_snapshots(fs, &dataset).await;
+        let mut run = ScheduleRun::default();
+
+        let latest = snapshots.first().map(|&(_, time)| time);
+        if latest.is_none_or(|time| period.index(time) < period.index(now)) {
+            let missed = latest
+                .map(|time| (period.index(now) - period.index(time) - 1).max(0) as u64)
+                .unwrap_or(0);
+            if missed > 0 {
+                warn!(
+                    "Missed {} {:?} snapshot(s) of dataset '{}', taking one now",
+                    missed, period, name
+                );
+            }
+
+            let snapshot_name = auto_snapshot_name(&dataset.name, now);
+            let snapshot = dataset_ops::create_snapshot(fs, dataset.id, &snapshot_name, true)
+                .await
+                .map_err(|e| format!("Failed to create snapshot '{}': {}", snapshot_name, e))?;
+            info!("Created scheduled snapshot '{}'", snapshot.name);
+            snapshots.insert(0, (snapshot.id, now));
+            run.taken = Some(snapshot.name);
+            run.missed = missed;
+        }
+
+        let keep = retained(schedule, &snapshots);
+        let mut failed = None;
+        for &(id, _) in &snapshots {
+            if keep.contains(&id) {
+                continue;
+            }
+            match dataset_ops::delete_dataset(fs, id).await {
+                Ok(progress) => {
+                    info!("Pruned scheduled snapshot '{}'", progress.name);
+                    run.pruned += 1;
+                }
+                // Pruned on a later run, once its holds are released
+                Err(FsError::OperationNotPermitted) => {
+                    info!("Not pruning held snapshot {} of '{}'", id, name);
+                }
+                Err(e) => {
+                    warn!("Failed to prune snapshot {} of '{}': {}", id, name, e);
+                    failed = Some(e);
+                }
+            }
+        }
+        run.kept = (snapshots.len() as u64 - run.pruned) as u32;
+
+        match failed {
+            Some(e) => Err(format!("Failed to prune snapshots: {}", e)),
+            None => Ok(run),
+        }
+    }
+}
+
+#[derive(Default)]
+struct ScheduleRun {
+    taken: Option<String>,
+    missed: u64,
+    pruned: u64,
+    kept: u32,
+}
+
+/// Snapshots of `dataset` taken by the scheduler, with their time, newest
+/// first
+async fn scheduled_snapshots(fs: &ZeroFS, dataset: &Dataset) -> Vec<(DatasetId, u64)> {
+    let mut snapshots: Vec<_> = fs
+        .dataset_store
+        .list_snapshots()
+        .await
+        .into_iter()
+        .filter(|s| s.parent_id == Some(dataset.id))
+        .filter_map(|s| Some((s.id, auto_snapshot_time(&s.name)?)))
+        .collect();
+    snapshots.sort_by(|a, b| b.1.cmp(&a.1).then(b.0.cmp(&a.0)));
+    snapshots
+}
@@ -0,0 +1,865 @@
+use crate::fs::ZeroFS;
+use crate::fs::dataset::{Dataset, DatasetId};
+use crate::fs::dataset_ops;
+use crate::fs::diff::{self, ChangeKind};
+use crate::fs::errors::FsError;
+use crate::fs::inode::{Inode, InodeAttrs, InodeId};
+use crate::fs::permissions::Credentials;
+use crate::fs::restore;
+use crate::fs::store::ChunkLayout;
+use crate::fs::store::xattr::Xattrs;
+use crate::fs::types::{
+    AuthContext, FileType, SetAttributes, SetGid, SetMode, SetSize, SetTime, SetUid, Timestamp,
+};
+use crate::fs::validate_filename;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// First bytes of a send stream, followed by `SEND_STREAM_VERSION`
+pub const SEND_STREAM_MAGIC: &[u8; 8] = b"ZEROFSTR";
+/// Version of the stream format written by `send`. `receive` refuses
+/// streams of any other version.
+pub const SEND_STREAM_VERSION: u32 = 2;
+
+/// File data is sent in records of at most this size
+const DATA_RECORD_SIZE: u64 = 256 * 1024;
+/// Largest record `receive` accepts, room for a data record or the
+/// extended attributes of an inode
+const MAX_RECORD_SIZE: u32 = 1024 * 1024;
+
+const ROOT_CREDS: Credentials = Credentials {
+    uid: 0,
+    gid: 0,
+    groups: [0; 16],
+    groups_count: 1,
+};
+
+/// What a stream brings the receiver to, and what it starts from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamHeader {
+    /// Snapshot created on the receiving side once the stream is applied
+    pub snapshot_name: String,
+    /// UUID of the sent snapshot, which the received one keeps so it can be
+    /// the base of the next incremental stream
+    pub snapshot_uuid: Uuid,
+    pub snapshot_created_at: u64,
+    /// Dataset the snapshot was taken of, on the sending side
+    pub dataset_name: String,
+    /// For an incremental stream, the snapshot the receiving dataset must
+    /// have; None for a full stream
+    pub base_uuid: Option<Uuid>,
+}
+
+/// Kind of a new entry, with what cannot be changed once it is created
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum EntryKind {
+    File,
+    Directory,
+    Symlink { target: Vec<u8> },
+    Fifo,
+    Socket,
+    CharDevice { rdev: Option<(u32, u32)> },
+    BlockDevice { rdev: Option<(u32, u32)> },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct EntryAttrs {
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    atime: (u64, u32),
+    mtime: (u64, u32),
+    xattrs: Xattrs,
+}
+
+/// One operation of a stream. Paths are counted from the dataset root, with
+/// components separated by `/`.
+#[derive(Debug, Serialize, Deserialize)]
+enum Record {
+    Begin(StreamHeader),
+    Create {
+        path: Vec<u8>,
+        kind: EntryKind,
+    },
+    /// Another link to the inode created at `target`
+    Link {
+        path: Vec<u8>,
+        target: Vec<u8>,
+    },
+    Write {
+        path: Vec<u8>,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    Truncate {
+        path: Vec<u8>,
+        size: u64,
+    },
+    SetAttrs {
+        path: Vec<u8>,
+        attrs: EntryAttrs,
+    },
+    Remove {
+        path: Vec<u8>,
+    },
+    Rename {
+        from: Vec<u8>,
+        to: Vec<u8>,
+    },
+    /// SHA-256 of every byte of the stream before this record
+    End {
+        checksum: Vec<u8>,
+    },
+}
+
+/// How `receive` applies a stream
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReceiveOptions {
+    /// Give a dataset created from a full stream a key of its own
+    pub encrypt: bool,
+    /// Roll the dataset back to the base of an incremental stream first,
+    /// discarding changes and snapshots made since
+    pub force: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReceiveOutcome {
+    pub dataset: Dataset,
+    pub snapshot: Dataset,
+    /// Entries created
+    pub entries: u64,
+    /// File data written, without the holes skipped by the sender
+    pub data_bytes: u64,
+}
+
+struct StreamWriter<W> {
+    writer: W,
+    hasher: Sha256,
+}
+
+impl<W: AsyncWrite + Unpin> StreamWriter<W> {
+    async fn start(writer: W) -> Result<Self, FsError> {
+        let mut stream = Self {
+            writer,
+            hasher: Sha256::new(),
+        };
+        stream.write(SEND_STREAM_MAGIC).await?;
+        stream.write(&SEND_STREAM_VERSION.to_le_bytes()).await?;
+        Ok(stream)
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), FsError> {
+        self.hasher.update(bytes);
+        self.writer
+            .write_all(bytes)
+            .await
+            .map_err(|_| FsError::IoError)
+    }
+
+    async fn record(&mut self, record: &Record) -> Result<(), FsError> {
+        let encoded = bincode::serialize(record).map_err(|_| FsError::IoError)?;
+        self.write(&(encoded.len() as u32).to_le_bytes()).await?;
+        self.write(&encoded).await
+    }
+
+    async fn finish(mut self) -> Result<(), FsError> {
+        let checksum = self.hasher.clone().finalize().to_vec();
+        self.record(&Record::End { checksum }).await?;
+        self.writer.shutdown().await.map_err(|_| FsError::IoError)
+    }
+}
+
+struct StreamReader<R> {
+    reader: R,
+    hasher: Sha256,
+}
+
+impl<R: AsyncRead + Unpin> StreamReader<R> {
+    async fn start(reader: R) -> Result<Self, FsError> {
+        let mut stream = Self {
+            reader,
+            hasher: Sha256::new(),
+        };
+        let mut magic = [0u8; 8];
+        stream.read(&mut magic).await?;
+        let mut version = [0u8; 4];
+        stream.read(&mut version).await?;
+        if &magic != SEND_STREAM_MAGIC || u32::from_le_bytes(version) != SEND_STREAM_VERSION {
+            return Err(FsError::InvalidData);
+        }
+        Ok(stream)
+    }
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<(), FsError> {
+        self.reader
+            .read_exact(buf)
+            .await
+            .map_err(|_| FsError::InvalidData)?;
+        self.hasher.update(&*buf);
+        Ok(())
+    }
+
+    /// The next record; the checksum of `End` is verified
+    async fn next(&mut self) -> Result<Record, FsError> {
+        let checksum = self.hasher.clone().finalize();
+
+        let mut len = [0u8; 4];
+        self.read(&mut len).await?;
+        let len = u32::from_le_bytes(len);
+        if len > MAX_RECORD_SIZE {
+            return Err(FsError::InvalidData);
+        }
+        let mut encoded = vec![0u8; len as usize];
+        self.read(&mut encoded).await?;
+
+        let record: Record = bincode::deserialize(&encoded).map_err(|_| FsError::InvalidData)?;
+        let corrupt = matches!(
+            &record,
+            Record::End { checksum: expected } if expected.as_slice() != checksum.as_slice()
+        );
+        if corrupt {
+            return Err(FsError::InvalidData);
+        }
+        Ok(record)
+    }
+}
+
+/// Write snapshot `snapshot_id` to `writer` as a stream `receive` can apply
+/// in another filesystem. With `base_id`, an earlier snapshot of the same
+/// dataset, the stream is incremental: it only carries what changed since,
+/// and applies to a dataset holding the received base.
+///
+/// Directories the snapshot still shares with the dataset are read through
+/// the dataset, without being copied. Each read holds writes off, so the
+/// stream is not affected by writes made while it is sent. Modified files
+/// are sent whole; runs of zeroes are left as holes.
+pub async fn send<W: AsyncWrite + Unpin>(
+    fs: &ZeroFS,
+    snapshot_id: DatasetId,
+    base_id: Option<DatasetId>,
+    writer: W,
+) -> Result<(), FsError> {
+    let snapshot = fs
+        .dataset_store
+        .get_by_id(snapshot_id)
+        .await
+        .filter(|d| d.is_snapshot)
+        .ok_or(FsError::NotFound)?;
+    let base = match base_id {
+        Some(id) => {
+            let base = fs
+                .dataset_store
+                .get_by_id(id)
+                .await
+                .filter(|d| d.is_snapshot)
+                .ok_or(FsError::NotFound)?;
+            if base.id == snapshot.id || base.parent_id != snapshot.parent_id {
+                return Err(FsError::InvalidArgument);
+            }
+            Some(base)
+        }
+        None => None,
+    };
+    let dataset_name = match snapshot.parent_id {
+        Some(id) => fs.dataset_store.get_by_id(id).await.map(|d| d.name),
+        None => None,
+    };
+
+    let mut sender = Sender {
+        fs,
+        root: snapshot.root_inode,
+        out: StreamWriter::start(writer).await?,
+    };
+    sender
+        .out
+        .record(&Record::Begin(StreamHeader {
+            snapshot_name: snapshot.name.clone(),
+            snapshot_uuid: snapshot.uuid,
+            snapshot_created_at: snapshot.created_at,
+            dataset_name: dataset_name.unwrap_or_default(),
+            base_uuid: base.as_ref().map(|b| b.uuid),
+        }))
+        .await?;
+
+    match &base {
+        Some(base) => sender.send_changes(base).await?,
+        None => sender.send_tree().await?,
+    }
+    sender.out.finish().await?;
+
+    info!(
+        "Sent snapshot '{}'{}",
+        snapshot.name,
+        base.map(|b| format!(" incrementally from '{}'", b.name))
+            .unwrap_or_default()
+    );
+    Ok(())
+}
+
+struct Sender<'a, W> {
+    fs: &'a ZeroFS,
+    /// Root directory of the sent snapshot
+    root: InodeId,
+    out: StreamWriter<W>,
+}
+
+impl<W: AsyncWrite + Unpin> Sender<'_, W> {
+    /// Every entry of the snapshot, then their attributes. An inode with
+    /// several links is created at the first of them and linked at the
+    /// others.
+    async fn send_tree(&mut self) -> Result<(), FsError> {
+        let mut sent = vec![Vec::new()];
+        let mut linked: HashMap<InodeId, Vec<u8>> = HashMap::new();
+        let mut stack = vec![Vec::new()];
+        while let Some(path) = stack.pop() {
+            let entries = {
+                let _gate = self.fs.lazy_dirs.hold_exclusive().await;
+                let dir_id = restore::resolve(self.fs, self.root, &components(&path)).await?;
+                let entries_dir = self.fs.lazy_dirs.entries_dir(dir_id).await?;
+                diff::list(self.fs, entries_dir).await?
+            };
+            for (name, (id, inode)) in entries {
+                let child_path = [path.as_slice(), b"/", &name].concat();
+                if !inode.is_directory() && inode.nlink() > 1 {
+                    if let Some(target) = linked.get(&id) {
+                        self.out
+                            .record(&Record::Link {
+                                path: child_path,
+                                target: target.clone(),
+                            })
+                            .await?;
+                        continue;
+                    }
+                    linked.insert(id, child_path.clone());
+                }
+                self.create(&child_path, &inode).await?;
+                if inode.is_directory() {
+                    stack.push(child_path.clone());
+                }
+                sent.push(child_path);
+            }
+        }
+
+        // Children first, as adding entries changes the times of their
+        // directory
+        for path in sent.iter().rev() {
+            self.attrs(path).await?;
+        }
+        Ok(())
+    }
+
+    /// The changes from `base`. Entries leaving their place in the base tree
+    /// are removed or moved aside first, deepest first, so the names they
+    /// free can be reused; entries of the new tree are then created, moved
+    /// in or updated, parents first.
+    async fn send_changes(&mut self, base: &Dataset) -> Result<(), FsError> {
+        let changes = diff::diff_entries(self.fs, base.root_inode, self.root).await?;
+        let aside = |index: usize| format!("/.zerofs-receive-{}", index).into_bytes();
+
+        let mut leaving: Vec<(&[u8], Option<usize>)> = changes
+            .iter()
+            .enumerate()
+            .filter_map(|(index, change)| match change.kind {
+                ChangeKind::Removed => Some((change.path.as_slice(), None)),
+                ChangeKind::Renamed => Some((change.old_path.as_deref()?, Some(index))),
+                _ => None,
+            })
+            .collect();
+        leaving.sort_by(|a, b| b.0.cmp(a.0));
+        for (path, renamed) in leaving {
+            let record = match renamed {
+                Some(index) => Record::Rename {
+                    from: path.to_vec(),
+                    to: aside(index),
+                },
+                None => Record::Remove {
+                    path: path.to_vec(),
+                },
+            };
+            self.out.record(&record).await?;
+        }
+
+        // Entries whose attributes are sent, with the directories that had
+        // entries added or removed
+        let mut touched = BTreeSet::from([Vec::new()]);
+        for (index, change) in changes.iter().enumerate() {
+            touched.insert(parent(&change.path).to_vec());
+            match change.kind {
+                ChangeKind::Removed => continue,
+                ChangeKind::Added => {
+                    let inode = self.entry(&change.path).await?;
+                    self.create(&change.path, &inode).await?;
+                }
+                ChangeKind::Renamed => {
+                    if let Some(old_path) = &change.old_path {
+                        touched.insert(parent(old_path).to_vec());
+                    }
+                    self.out
+                        .record(&Record::Rename {
+                            from: aside(index),
+                            to: change.path.clone(),
+                        })
+                        .await?;
+                }
+                ChangeKind::Modified => {
+                    let inode = self.entry(&change.path).await?;
+                    match &inode {
+                        Inode::File(file) if change.content_changed => {
+                            self.out
+                                .record(&Record::Truncate {
+                                    path: change.path.clone(),
+                                    size: 0,
+                                })
+                                .await?;
+                            self.data(&change.path, file.size).await?;
+                        }
+                        // The target and device numbers are only set on
+                        // creation
+                        Inode::Symlink(_) | Inode::CharDevice(_) | Inode::BlockDevice(_) => {
+                            self.out
+                                .record(&Record::Remove {
+                                    path: change.path.clone(),
+                                })
+                                .await?;
+                            self.create(&change.path, &inode).await?;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            touched.insert(change.path.clone());
+        }
+
+        for path in touched.iter().rev() {
+            match self.attrs(path).await {
+                // A directory removed with the entries it had
+                Ok(()) | Err(FsError::NotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Inode of the entry at `path` of the snapshot. Lazy directories are
+    /// read through their origins, which only keep the state of the
+    /// snapshot while writes are held off: ids found this way are not kept
+    /// past the gate.
+    async fn entry(&self, path: &[u8]) -> Result<Inode, FsError> {
+        let _gate = self.fs.lazy_dirs.hold_exclusive().await;
+        let id = restore::resolve(self.fs, self.root, &components(path)).await?;
+        self.fs.inode_store.get(id).await
+    }
+
+    async fn create(&mut self, path: &[u8], inode: &Inode) -> Result<(), FsError> {
+        let kind = match inode {
+            Inode::File(_) => EntryKind::File,
+            Inode::Directory(_) => EntryKind::Directory,
+            Inode::Symlink(link) => EntryKind::Symlink {
+                target: link.target.clone(),
+            },
+            Inode::Fifo(_) => EntryKind::Fifo,
+            Inode::Socket(_) => EntryKind::Socket,
+            Inode::CharDevice(special) => EntryKind::CharDevice { rdev: special.rdev },
+            Inode::BlockDevice(special) => EntryKind::BlockDevice { rdev: special.rdev },
+        };
+        self.out
+            .record(&Record::Create {
+                path: path.to_vec(),
+                kind,
+            })
+            .await?;
+
+        if let Inode::File(file) = inode {
+            self.data(path, file.size).await?;
+        }
+        Ok(())
+    }
+
+    /// Size and data of the file at `path`, without the pieces that are all
+    /// zeroes. The file is looked up again for each piece, which is read
+    /// with writes held off.
+    async fn data(&mut self, path: &[u8], size: u64) -> Result<(), FsError> {
+        self.out
+            .record(&Record::Truncate {
+                path: path.to_vec(),
+                size,
+            })
+            .await?;
+
+        let mut offset = 0;
+        while offset < size {
+            let len = DATA_RECORD_SIZE.min(size - offset);
+            let data = {
+                let _gate = self.fs.lazy_dirs.hold_exclusive().await;
+                let id = restore::resolve(self.fs, self.root, &components(path)).await?;
+                let Inode::File(file) = self.fs.inode_store.get(id).await? else {
+                    return Err(FsError::InvalidData);
+                };
+                let layout = ChunkLayout::for_file(id, &file);
+                self.fs.chunk_store.read(&layout, offset, len).await?
+            };
+            if data.iter().any(|&b| b != 0) {
+                self.out
+                    .record(&Record::Write {
+                        path: path.to_vec(),
+                        offset,
+                        data: data.to_vec(),
+                    })
+                    .await?;
+            }
+            offset += len;
+        }
+        Ok(())
+    }
+
+    async fn attrs(&mut self, path: &[u8]) -> Result<(), FsError> {
+        let attrs = {
+            let _gate = self.fs.lazy_dirs.hold_exclusive().await;
+            let id = restore::resolve(self.fs, self.root, &components(path)).await?;
+            let inode = self.fs.inode_store.get(id).await?;
+            EntryAttrs {
+                mode: inode.mode(),
+                uid: inode.uid(),
+                gid: inode.gid(),
+                atime: (inode.atime(), inode.atime_nsec()),
+                mtime: (inode.mtime(), inode.mtime_nsec()),
+                xattrs: self.fs.xattr_store.get(id).await?,
+            }
+        };
+        self.out
+            .record(&Record::SetAttrs {
+                path: path.to_vec(),
+                attrs,
+            })
+            .await
+    }
+}
+
+/// Apply a stream written by `send` to dataset `dataset_name`, then snapshot
+/// it under the name and UUID of the sent snapshot.
+///
+/// A full stream creates the dataset, which must not exist. An incremental
+/// one applies to a dataset holding its base snapshot and unchanged since,
+/// unless `force` rolls it back to the base. Entries are written through
+/// the filesystem, so data is encrypted with the keys of the receiving side
+/// and counted against its quotas. A stream that fails to apply, or whose
+/// checksum does not match, leaves the dataset as it was: a created one is
+/// destroyed and an existing one rolled back to the base.
+pub async fn receive<R: AsyncRead + Unpin>(
+    fs: &ZeroFS,
+    dataset_name: &str,
+    options: ReceiveOptions,
+    reader: R,
+) -> Result<ReceiveOutcome, FsError> {
+    let mut input = StreamReader::start(reader).await?;
+    let Record::Begin(header) = input.next().await? else {
+        return Err(FsError::InvalidData);
+    };
+    if fs
+        .dataset_store
+        .get_by_name(&header.snapshot_name)
+        .await
+        .is_some()
+    {
+        return Err(FsError::Exists);
+    }
+
+    let (dataset, base) = match header.base_uuid {
+        None => (
+            dataset_ops::create_dataset(fs, dataset_name, options.encrypt).await?,
+            None,
+        ),
+        Some(base_uuid) => {
+            let dataset = fs
+                .dataset_store
+                .get_by_name(dataset_name)
+                .await
+                .filter(|d| !d.is_snapshot)
+                .ok_or(FsError::NotFound)?;
+            let base = fs
+                .dataset_store
+                .list_snapshots()
+                .await
+                .into_iter()
+                .find(|s| s.parent_id == Some(dataset.id) && s.uuid == base_uuid)
+                .ok_or(FsError::NotFound)?;
+
+            let dataset = if options.force {
+                dataset_ops::rollback_dataset(fs, dataset.id, base.id, true)
+                    .await?
+                    .0
+            } else if !dataset_ops::newer_snapshots(fs, dataset.id, base.id)
+                .await
+                .is_empty()
+                || !diff::diff_entries(fs, base.root_inode, dataset.root_inode)
+                    .await?
+                    .is_empty()
+            {
+                return Err(FsError::NotEmpty);
+            } else {
+                dataset
+            };
+            (dataset, Some(base))
+        }
+    };
+
+    let mut receiver = Receiver {
+        fs,
+        root: dataset.root_inode,
+        entries: 0,
+        data_bytes: 0,
+    };
+    if let Err(e) = receiver.apply(&mut input).await {
+        warn!(
+            "Failed to receive snapshot '{}' into dataset '{}': {}",
+            header.snapshot_name, dataset.name, e
+        );
+        let undone = match &base {
+            Some(base) => dataset_ops::rollback_dataset(fs, dataset.id, base.id, false)
+                .await
+                .map(|_| ()),
+            None => dataset_ops::delete_dataset(fs, dataset.id)
+                .await
+                .map(|_| ()),
+        };
+        if let Err(undo_error) = undone {
+            warn!(
+                "Failed to undo the partial receive into dataset '{}': {}",
+                dataset.name, undo_error
+            );
+        }
+        return Err(e);
+    }
+
+    let snapshot =
+        dataset_ops::create_snapshot(fs, dataset.id, &header.snapshot_name, false).await?;
+    let snapshot = fs
+        .dataset_store
+        .set_uuid(snapshot.id, header.snapshot_uuid)
+        .await?;
+    let dataset = fs
+        .dataset_store
+        .get_by_id(dataset.id)
+        .await
+        .ok_or(FsError::NotFound)?;
+
+    info!(
+        "Received snapshot '{}' of dataset '{}' into '{}' ({} entries, {} bytes)",
+        snapshot.name, header.dataset_name, dataset.name, receiver.entries, receiver.data_bytes
+    );
+    Ok(ReceiveOutcome {
+        dataset,
+        snapshot,
+        entries: receiver.entries,
+        data_bytes: receiver.data_bytes,
+    })
+}
+
+struct Receiver<'a> {
+    fs: &'a ZeroFS,
+    /// Root directory of the receiving dataset
+    root: InodeId,
+    entries: u64,
+    data_bytes: u64,
+}
+
+impl Receiver<'_> {
+    /// Apply records up to and including `End`
+    async fn apply<R: AsyncRead + Unpin>(
+        &mut self,
+        input: &mut StreamReader<R>,
+    ) -> Result<(), FsError> {
+        let auth = AuthContext {
+            uid: 0,
+            gid: 0,
+            gids: vec![0],
+        };
+        loop {
+            match input.next().await? {
+                Record::Begin(_) => return Err(FsError::InvalidData),
+                Record::Create { path, kind } => {
+                    self.create(&path, kind).await?;
+                    self.entries += 1;
+                }
+                Record::Link { path, target } => {
+                    let target_id = self.resolve(&target).await?;
+                    let (dir_id, name) = self.parent(&path).await?;
+                    self.fs.link(&auth, target_id, dir_id, name).await?;
+                    self.entries += 1;
+                }
+                Record::Write { path, offset, data } => {
+                    let id = self.resolve(&path).await?;
+                    self.data_bytes += data.len() as u64;
+                    self.fs.write(&auth, id, offset, &Bytes::from(data)).await?;
+                }
+                Record::Truncate { path, size } => {
+                    let id = self.resolve(&path).await?;
+                    let attr = SetAttributes {
+                        size: SetSize::Set(size),
+                        ..Default::default()
+                    };
+                    self.fs.setattr(&ROOT_CREDS, id, &attr).await?;
+                }
+                Record::SetAttrs { path, attrs } => {
+                    let id = self.resolve(&path).await?;
+                    self.set_attrs(id, attrs).await?;
+                }
+                Record::Remove { path } => {
+                    let (dir_id, name) = self.parent(&path).await?;
+                    self.fs.remove(&auth, dir_id, name).await?;
+                }
+                Record::Rename { from, to } => {
+                    let (from_dir, from_name) = self.parent(&from).await?;
+                    let (to_dir, to_name) = self.parent(&to).await?;
+                    self.fs
+                        .rename(&auth, from_dir, from_name, to_dir, to_name)
+                        .await?;
+                }
+                Record::End { .. } => return Ok(()),
+            }
+        }
+    }
+
+    async fn create(&self, path: &[u8], kind: EntryKind) -> Result<(), FsError> {
+        let (dir_id, name) = self.parent(path).await?;
+        // Owner-only until the attributes of the entry are set
+        let attr = SetAttributes {
+            mode: SetMode::Set(0o700),
+            uid: SetUid::Set(0),
+            gid: SetGid::Set(0),
+            ..Default::default()
+        };
+        match kind {
+            EntryKind::File => {
+                self.fs.create(&ROOT_CREDS, dir_id, name, &attr).await?;
+            }
+            EntryKind::Directory => {
+                self.fs.mkdir(&ROOT_CREDS, dir_id, name, &attr).await?;
+            }
+            EntryKind::Symlink { target } => {
+                self.fs
+                    .symlink(&ROOT_CREDS, dir_id, name, &target, &attr)
+                    .await?;
+            }
+            EntryKind::Fifo => {
+                self.fs
+                    .mknod(&ROOT_CREDS, dir_id, name, FileType::Fifo, &attr, None)
+                    .await?;
+            }
+            EntryKind::Socket => {
+                self.fs
+                    .mknod(&ROOT_CREDS, dir_id, name, FileType::Socket, &attr, None)
+                    .await?;
+            }
+            EntryKind::CharDevice { rdev } => {
+                self.fs
+                    .mknod(&ROOT_CREDS, dir_id, name, FileType::CharDevice, &attr, rdev)
+                    .await?;
+            }
+            EntryKind::BlockDevice { rdev } => {
+                self.fs
+                    .mknod(
+                        &ROOT_CREDS,
+                        dir_id,
+                        name,
+                        FileType::BlockDevice,
+                        &attr,
+                        rdev,
+                    )
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Mode and owner first, as they rewrite an access ACL; then extended
+    /// attributes, and times last as the other changes touch them.
+    async fn set_attrs(&self, id: InodeId, attrs: EntryAttrs) -> Result<(), FsError> {
+        let owner = SetAttributes {
+            mode: SetMode::Set(attrs.mode),
+            uid: SetUid::Set(attrs.uid),
+            gid: SetGid::Set(attrs.gid),
+            ..Default::default()
+        };
+        self.fs.setattr(&ROOT_CREDS, id, &owner).await?;
+
+        let current = self.fs.xattr_store.get(id).await?;
+        for name in current
+            .keys()
+            .filter(|name| !attrs.xattrs.contains_key(*name))
+        {
+            self.fs.removexattr(&ROOT_CREDS, id, name).await?;
+        }
+        for (name, value) in &attrs.xattrs {
+            if current.get(name) != Some(value) {
+                self.fs.setxattr(&ROOT_CREDS, id, name, value, 0).await?;
+            }
+        }
+
+        let times = SetAttributes {
+            atime: SetTime::SetToClientTime(Timestamp {
+                seconds: attrs.atime.0,
+                nanoseconds: attrs.atime.1,
+            }),
+            mtime: SetTime::SetToClientTime(Timestamp {
+                seconds: attrs.mtime.0,
+                nanoseconds: attrs.mtime.1,
+            }),
+            ..Default::default()
+        };
+        self.fs.setattr(&ROOT_CREDS, id, &times).await?;
+        Ok(())
+    }
+
+    /// Entry at `path` of the dataset. A rolled back dataset reads through
+    /// its base snapshot, so the directories on the way are materialized
+    /// for the entry to be the dataset's own.
+    async fn resolve(&self, path: &[u8]) -> Result<InodeId, FsError> {
+        let parts = checked_components(path)?;
+        let _gate = self.fs.lazy_dirs.hold().await;
+        restore::resolve_materialized(self.fs, self.root, &parts).await
+    }
+
+    /// Directory holding `path`, with the name of the entry in it
+    async fn parent<'p>(&self, path: &'p [u8]) -> Result<(InodeId, &'p [u8]), FsError> {
+        let parts = checked_components(path)?;
+        let Some((name, dir_parts)) = parts.split_last() else {
+            return Err(FsError::InvalidData);
+        };
+        let _gate = self.fs.lazy_dirs.hold().await;
+        let dir_id = restore::resolve_materialized(self.fs, self.root, dir_parts).await?;
+        Ok((dir_id, *name))
+    }
+}
+
+fn components(path: &[u8]) -> Vec<&[u8]> {
+    path.split(|&b| b == b'/')
+        .filter(|part| !part.is_empty())
+        .collect()
+}
+
+/// Components of a path read from a stream, which must stay below the
+/// dataset root
+fn checked_components(path: &[u8]) -> Result<Vec<&[u8]>, FsError> {
+    let parts = components(path);
+    for part in &parts {
+        if *part == b"." || *part == b".." {
+            return Err(FsError::InvalidData);
+        }
+        validate_filename(part)?;
+    }
+    Ok(parts)
+}
+
+/// `path` without its last component
+fn parent(path: &[u8]) -> &[u8] {
+    match path.iter().rposition(|&b| b == b'/') {
+        Some(end) => &path[..end],
+        None => &[],
+    }
+}
@@ -42,7 +42,8 @@ impl SnapshotVfs {
         }
     }
 
-    /// Get virtual inode ID for a snapshot
+    /// Get virtual inode ID for a snapshot. It follows the snapshot id, so it
+    /// is kept when the snapshot is renamed.
     pub fn inode_for_snapshot(snapshot_id: u64) -> InodeId {
         SNAPSHOT_BASE_INODE + snapshot_id
     }
@@ -138,9 +139,14 @@ impl SnapshotVfs {
         Ok(snapshot.root_inode)
     }
 
-    /// List all snapshots for readdir on .snapshots
+    /// List all snapshots for readdir on .snapshots. Names containing '/'
+    /// cannot be looked up there and are left out; the snapshots a recursive
+    /// snapshot takes of child datasets, `<snapshot>/<path>`, have their
+    /// files at `<path>` in `<snapshot>`.
     pub async fn list_snapshots(&self) -> Vec<Dataset> {
-        self.dataset_store.list_snapshots().await
+        let mut snapshots = self.dataset_store.list_snapshots().await;
+        snapshots.retain(|s| !s.name.contains('/'));
+        snapshots
     }
 
     /// Check if an inode should be treated as read-only (snapshot content)
@@ -1,8 +1,13 @@
 use super::STATS_SHARDS;
+use super::dataset::{DatasetId, ROOT_DATASET_ID};
 use super::errors::FsError;
 use super::inode::InodeId;
 use super::key_codec::KeyCodec;
+use super::quota::Owner;
 use bytes::Bytes;
+use dashmap::DashMap;
+use std::fmt;
+use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::sync::RwLock;
 
@@ -18,17 +23,123 @@ pub struct StatsShard {
     pub lock: RwLock<()>,
 }
 
+/// Usage tracked besides the filesystem totals
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UsageScope {
+    Dataset(DatasetId),
+    Owner(Owner),
+}
+
+impl fmt::Display for UsageScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UsageScope::Dataset(id) => write!(f, "dataset {}", id),
+            UsageScope::Owner(owner) => owner.fmt(f),
+        }
+    }
+}
+
+impl UsageScope {
+    fn shard_key(self, shard_id: usize) -> Bytes {
+        match self {
+            UsageScope::Dataset(id) => KeyCodec::dataset_stats_key(id, shard_id),
+            UsageScope::Owner(owner) => KeyCodec::owner_stats_key(owner, shard_id),
+        }
+    }
+}
+
+/// Dataset and owners an inode's usage is accounted to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InodeAccount {
+    pub dataset: DatasetId,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl InodeAccount {
+    pub fn new(dataset: DatasetId, (uid, gid): (u32, u32)) -> Self {
+        Self { dataset, uid, gid }
+    }
+
+    /// Scopes tracking the inode. The root dataset is not tracked on its own.
+    fn scopes(self) -> impl Iterator<Item = UsageScope> {
+        (self.dataset != ROOT_DATASET_ID)
+            .then_some(UsageScope::Dataset(self.dataset))
+            .into_iter()
+            .chain(owner_scopes((self.uid, self.gid)))
+    }
+}
+
+fn owner_scopes((uid, gid): (u32, u32)) -> [UsageScope; 2] {
+    [
+        UsageScope::Owner(Owner::User(uid)),
+        UsageScope::Owner(Owner::Group(gid)),
+    ]
+}
+
+/// Usage counters of one shard of a scope. Updates to shard `i` of any
+/// scope are serialized by the lock of global shard `i`.
+#[derive(Default)]
+pub struct ScopeShard {
+    pub used_bytes: AtomicU64,
+    pub used_inodes: AtomicU64,
+}
+
+/// Change to usage counters
+#[derive(Debug, Clone, Copy, Default)]
+struct UsageDelta {
+    added_bytes: u64,
+    removed_bytes: u64,
+    added_inodes: u64,
+    removed_inodes: u64,
+}
+
+impl UsageDelta {
+    fn apply(self, data: &mut StatsShardData) {
+        data.used_bytes = data
+            .used_bytes
+            .saturating_add(self.added_bytes)
+            .saturating_sub(self.removed_bytes);
+        data.used_inodes = data
+            .used_inodes
+            .saturating_add(self.added_inodes)
+            .saturating_sub(self.removed_inodes);
+    }
+}
+
+/// Usage is tracked for the whole filesystem, for every dataset but the
+/// root one, whose usage is what the other datasets leave of the total, and
+/// for every uid and gid owning inodes.
 pub struct FileSystemGlobalStats {
     pub shards: Vec<StatsShard>,
+    scopes: DashMap<UsageScope, Arc<Vec<ScopeShard>>>,
+}
+
+/// Change to the usage of a scope the inode is accounted to
+pub struct ScopedStatsUpdate {
+    pub scope: UsageScope,
+    pub shard_key: Bytes,
+    pub shard_data: StatsShardData,
 }
 
 pub struct StatsUpdate<'a> {
     pub shard_id: usize,
     pub shard_key: Bytes,
     pub shard_data: StatsShardData,
+    pub scoped: Vec<ScopedStatsUpdate>,
     pub _guard: tokio::sync::RwLockWriteGuard<'a, ()>,
 }
 
+impl StatsUpdate<'_> {
+    /// Account `bytes` more, e.g. for the data of a cloned file
+    pub fn add_bytes(&mut self, bytes: u64) {
+        self.shard_data.used_bytes = self.shard_data.used_bytes.saturating_add(bytes);
+        for scoped in &mut self.scoped {
+            scoped.shard_data.used_bytes = scoped.shard_data.used_bytes.saturating_add(bytes);
+        }
+    }
+}
+
 impl Default for FileSystemGlobalStats {
     fn default() -> Self {
         Self::new()
@@ -44,7 +155,10 @@ impl FileSystemGlobalStats {
                 lock: RwLock::new(()),
             })
             .collect();
-        Self { shards }
+        Self {
+            shards,
+            scopes: DashMap::new(),
+        }
     }
 
     pub fn get_totals(&self) -> (u64, u64) {
@@ -57,8 +171,57 @@ impl FileSystemGlobalStats {
         (total_bytes, total_inodes)
     }
 
-    /// Prepare a statistics update for a new inode creation
-    pub async fn prepare_inode_create(&self, inode_id: InodeId) -> StatsUpdate<'_> {
+    /// Bytes and inodes used by `scope`. Not meaningful for the root
+    /// dataset, which is not tracked on its own.
+    pub fn get_scope_totals(&self, scope: UsageScope) -> (u64, u64) {
+        let Some(shards) = self.scopes.get(&scope).map(|s| s.clone()) else {
+            return (0, 0);
+        };
+
+        let mut total_bytes = 0u64;
+        let mut total_inodes = 0u64;
+        for shard in shards.iter() {
+            total_bytes += shard.used_bytes.load(Ordering::Relaxed);
+            total_inodes += shard.used_inodes.load(Ordering::Relaxed);
+        }
+        (total_bytes, total_inodes)
+    }
+
+    /// Bytes and inodes used by each uid and gid owning inodes
+    pub fn owner_totals(&self) -> Vec<(Owner, u64, u64)> {
+        let owners: Vec<Owner> = self
+            .scopes
+            .iter()
+            .filter_map(|entry| match *entry.key() {
+                UsageScope::Owner(owner) => Some(owner),
+                UsageScope::Dataset(_) => None,
+            })
+            .collect();
+
+        owners
+            .into_iter()
+            .map(|owner| {
+                let (bytes, inodes) = self.get_scope_totals(UsageScope::Owner(owner));
+                (owner, bytes, inodes)
+            })
+            .collect()
+    }
+
+    fn scope_shards(&self, scope: UsageScope) -> Arc<Vec<ScopeShard>> {
+        self.scopes
+            .entry(scope)
+            .or_insert_with(|| Arc::new((0..STATS_SHARDS).map(|_| ScopeShard::default()).collect()))
+            .clone()
+    }
+
+    /// Lock the shard of `inode_id` and compute its new usage, and that of
+    /// each scope in `scoped`, with the given changes applied.
+    async fn prepare(
+        &self,
+        inode_id: InodeId,
+        global: UsageDelta,
+        scoped: impl IntoIterator<Item = (UsageScope, UsageDelta)>,
+    ) -> StatsUpdate<'_> {
         let shard_id = inode_id as usize % STATS_SHARDS;
         let shard = &self.shards[shard_id];
 
@@ -68,49 +231,69 @@ impl FileSystemGlobalStats {
             used_bytes: shard.used_bytes.load(Ordering::Relaxed),
             used_inodes: shard.used_inodes.load(Ordering::Relaxed),
         };
-        shard_data.used_inodes = shard_data.used_inodes.saturating_add(1);
+        global.apply(&mut shard_data);
+
+        let scoped = scoped
+            .into_iter()
+            .map(|(scope, delta)| {
+                let shard = &self.scope_shards(scope)[shard_id];
+                let mut shard_data = StatsShardData {
+                    used_bytes: shard.used_bytes.load(Ordering::Relaxed),
+                    used_inodes: shard.used_inodes.load(Ordering::Relaxed),
+                };
+                delta.apply(&mut shard_data);
+                ScopedStatsUpdate {
+                    scope,
+                    shard_key: scope.shard_key(shard_id),
+                    shard_data,
+                }
+            })
+            .collect();
 
         StatsUpdate {
             shard_id,
             shard_key: KeyCodec::stats_shard_key(shard_id),
             shard_data,
+            scoped,
             _guard: guard,
         }
     }
 
+    /// Prepare a statistics update for a new inode creation
+    pub async fn prepare_inode_create(
+        &self,
+        inode_id: InodeId,
+        account: InodeAccount,
+    ) -> StatsUpdate<'_> {
+        let delta = UsageDelta {
+            added_inodes: 1,
+            ..Default::default()
+        };
+        self.prepare(inode_id, delta, account.scopes().map(|s| (s, delta)))
+            .await
+    }
+
     /// Prepare a statistics update for inode removal
     pub async fn prepare_inode_remove(
         &self,
         inode_id: InodeId,
+        account: InodeAccount,
         file_size: Option<u64>,
     ) -> StatsUpdate<'_> {
-        let shard_id = inode_id as usize % STATS_SHARDS;
-        let shard = &self.shards[shard_id];
-
-        let guard = shard.lock.write().await;
-
-        let mut shard_data = StatsShardData {
-            used_bytes: shard.used_bytes.load(Ordering::Relaxed),
-            used_inodes: shard.used_inodes.load(Ordering::Relaxed),
+        let delta = UsageDelta {
+            removed_bytes: file_size.unwrap_or(0),
+            removed_inodes: 1,
+            ..Default::default()
         };
-
-        shard_data.used_inodes = shard_data.used_inodes.saturating_sub(1);
-        if let Some(size) = file_size {
-            shard_data.used_bytes = shard_data.used_bytes.saturating_sub(size);
-        }
-
-        StatsUpdate {
-            shard_id,
-            shard_key: KeyCodec::stats_shard_key(shard_id),
-            shard_data,
-            _guard: guard,
-        }
+        self.prepare(inode_id, delta, account.scopes().map(|s| (s, delta)))
+            .await
     }
 
     /// Prepare a statistics update for file size change
     pub async fn prepare_size_change(
         &self,
         inode_id: InodeId,
+        account: InodeAccount,
         old_size: u64,
         new_size: u64,
     ) -> Option<StatsUpdate<'_>> {
@@ -118,43 +301,72 @@ impl FileSystemGlobalStats {
             return None;
         }
 
-        let shard_id = inode_id as usize % STATS_SHARDS;
-        let shard = &self.shards[shard_id];
-
-        let guard = shard.lock.write().await;
-
-        let mut shard_data = StatsShardData {
-            used_bytes: shard.used_bytes.load(Ordering::Relaxed),
-            used_inodes: shard.used_inodes.load(Ordering::Relaxed),
+        let delta = UsageDelta {
+            added_bytes: new_size.saturating_sub(old_size),
+            removed_bytes: old_size.saturating_sub(new_size),
+            ..Default::default()
         };
+        let update = self
+            .prepare(inode_id, delta, account.scopes().map(|s| (s, delta)))
+            .await;
+        Some(update)
+    }
 
-        if new_size > old_size {
-            shard_data.used_bytes = shard_data.used_bytes.saturating_add(new_size - old_size);
-        } else {
-            shard_data.used_bytes = shard_data.used_bytes.saturating_sub(old_size - new_size);
+    /// Prepare a statistics update moving the usage of an inode of `size`
+    /// bytes from owners `old` to owners `new`, as (uid, gid), on chown
+    pub async fn prepare_owner_change(
+        &self,
+        inode_id: InodeId,
+        old: (u32, u32),
+        new: (u32, u32),
+        size: u64,
+    ) -> Option<StatsUpdate<'_>> {
+        if old == new {
+            return None;
         }
 
-        Some(StatsUpdate {
-            shard_id,
-            shard_key: KeyCodec::stats_shard_key(shard_id),
-            shard_data,
-            _guard: guard,
-        })
+        let removed = UsageDelta {
+            removed_bytes: size,
+            removed_inodes: 1,
+            ..Default::default()
+        };
+        let added = UsageDelta {
+            added_bytes: size,
+            added_inodes: 1,
+            ..Default::default()
+        };
+        let (old, new) = (owner_scopes(old), owner_scopes(new));
+        let scoped = old
+            .iter()
+            .filter(|scope| !new.contains(scope))
+            .map(|&scope| (scope, removed))
+            .chain(
+                new.iter()
+                    .filter(|scope| !old.contains(scope))
+                    .map(|&scope| (scope, added)),
+            )
+            .collect::<Vec<_>>();
+        let update = self.prepare(inode_id, UsageDelta::default(), scoped).await;
+        Some(update)
     }
 
     pub fn add_to_transaction(
         &self,
-        update: &StatsUpdate,
+        update: &StatsUpdate<'_>,
         txn: &mut crate::encryption::EncryptedTransaction,
     ) -> Result<(), FsError> {
         let shard_bytes = bincode::serialize(&update.shard_data)?;
         txn.put_bytes(&update.shard_key, Bytes::from(shard_bytes));
 
+        for scoped in &update.scoped {
+            let shard_bytes = bincode::serialize(&scoped.shard_data)?;
+            txn.put_bytes(&scoped.shard_key, Bytes::from(shard_bytes));
+        }
+
         Ok(())
     }
 
-    /// Commit the statistics update to memory after successful database write
-    pub fn commit_update(&self, update: &StatsUpdate) {
+    pub fn commit_update(&self, update: &StatsUpdate<'_>) {
         let shard = &self.shards[update.shard_id];
         shard
             .used_bytes
@@ -162,6 +374,10 @@ impl FileSystemGlobalStats {
         shard
             .used_inodes
             .store(update.shard_data.used_inodes, Ordering::Relaxed);
+
+        for scoped in &update.scoped {
+            self.load_scope_shard(scoped.scope, update.shard_id, &scoped.shard_data);
+        }
     }
 
     /// Load statistics from persistent storage
@@ -175,6 +391,98 @@ impl FileSystemGlobalStats {
                 .store(data.used_inodes, Ordering::Relaxed);
         }
     }
+
+    /// Load the usage of a scope from persistent storage
+    pub fn load_scope_shard(&self, scope: UsageScope, shard_id: usize, data: &StatsShardData) {
+        if shard_id < STATS_SHARDS {
+            let shards = self.scope_shards(scope);
+            shards[shard_id]
+                .used_bytes
+                .store(data.used_bytes, Ordering::Relaxed);
+            shards[shard_id]
+                .used_inodes
+                .store(data.used_inodes, Ordering::Relaxed);
+        }
+    }
+
+    /// Stop tracking dataset `dataset_id`, e.g. once it is deleted. Its
+    /// inodes count towards the root dataset from then on.
+    pub fn forget_dataset(
+        &self,
+        dataset_id: DatasetId,
+        txn: &mut crate::encryption::EncryptedTransaction,
+    ) {
+        let scope = UsageScope::Dataset(dataset_id);
+        for shard_id in 0..STATS_SHARDS {
+            txn.delete_bytes(&scope.shard_key(shard_id));
+        }
+        self.scopes.remove(&scope);
+    }
+}
+
+/// Sizes of stored chunk bodies: before compression, after compression,
+/// and as written to the object store, i.e. including encryption overhead
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SpaceUsage {
+    pub logical: u64,
+    pub compressed: u64,
+    pub stored: u64,
+}
+
+impl SpaceUsage {
+    pub fn add(&mut self, other: &SpaceUsage) {
+        self.logical = self.logical.saturating_add(other.logical);
+        self.compressed = self.compressed.saturating_add(other.compressed);
+        self.stored = self.stored.saturating_add(other.stored);
+    }
+
+    pub fn sub(&mut self, other: &SpaceUsage) {
+        self.logical = self.logical.saturating_sub(other.logical);
+        self.compressed = self.compressed.saturating_sub(other.compressed);
+        self.stored = self.stored.saturating_sub(other.stored);
+    }
+
+    /// Bytes taken on the object store by data whose allocated size is
+    /// `allocated`. Data beyond the accounted chunk bodies, such as chunks
+    /// held by the writeback cache or shared by clones, counts at its
+    /// allocated size.
+    pub fn footprint(&self, allocated: u64) -> u64 {
+        allocated.saturating_sub(self.logical) + self.stored
+    }
+}
+
+/// Stored sizes of the chunk bodies of each dataset. Content-addressed
+/// bodies are accounted to the dataset that first stored them until they
+/// are collected.
+#[derive(Default)]
+pub struct SpaceStats {
+    datasets: DashMap<DatasetId, SpaceUsage>,
+}
+
+impl SpaceStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, dataset_id: DatasetId) -> SpaceUsage {
+        self.datasets
+            .get(&dataset_id)
+            .map(|usage| *usage)
+            .unwrap_or_default()
+    }
+
+    pub fn totals(&self) -> SpaceUsage {
+        let mut totals = SpaceUsage::default();
+        for usage in self.datasets.iter() {
+            totals.add(usage.value());
+        }
+        totals
+    }
+
+    /// Set the usage of a dataset, once loaded or committed
+    pub fn set(&self, dataset_id: DatasetId, usage: SpaceUsage) {
+        self.datasets.insert(dataset_id, usage);
+    }
 }
 
 #[cfg(test)]
@@ -241,7 +549,7 @@ mod tests {
             .unwrap();
 
         // Write 1000 bytes
-        let data = vec![0u8; 1000];
+        let data = vec![1u8; 1000];
         fs.write(&auth, file_id, 0, &Bytes::copy_from_slice(&data))
             .await
             .unwrap();
@@ -273,7 +581,7 @@ mod tests {
             .await
             .unwrap();
 
-        let data = vec![0u8; 1000];
+        let data = vec![1u8; 1000];
         fs.write(&auth, file_id, 0, &Bytes::copy_from_slice(&data))
             .await
             .unwrap();
@@ -308,9 +616,67 @@ mod tests {
             .await
             .unwrap();
 
+        // Only the chunk holding the byte is allocated
         let (bytes, inodes) = fs.global_stats.get_totals();
-        assert_eq!(bytes, 1_000_000_001); // Logical size
+        assert_eq!(bytes, crate::fs::CHUNK_SIZE as u64);
         assert_eq!(inodes, 1);
+
+        let inode = fs.inode_store.get(file_id).await.unwrap();
+        let attrs: crate::fs::types::FileAttributes = crate::fs::types::InodeWithId {
+            inode: &inode,
+            id: file_id,
+        }
+        .into();
+        assert_eq!(attrs.size, 1_000_000_001);
+        assert_eq!(attrs.used, crate::fs::CHUNK_SIZE as u64);
+    }
+
+    #[tokio::test]
+    async fn test_stats_zeros_and_trim() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let creds = test_creds();
+        let auth = test_auth();
+        let chunk_size = crate::fs::CHUNK_SIZE as u64;
+
+        let (file_id, _) = fs
+            .create(&creds, 0, b"disk.img", &SetAttributes::default())
+            .await
+            .unwrap();
+
+        // All-zero chunks are not stored
+        let data = vec![0u8; 2 * chunk_size as usize];
+        fs.write(&auth, file_id, 0, &Bytes::copy_from_slice(&data))
+            .await
+            .unwrap();
+        let (bytes, _) = fs.global_stats.get_totals();
+        assert_eq!(bytes, 0);
+
+        let data = vec![1u8; 3 * chunk_size as usize];
+        fs.write(&auth, file_id, 0, &Bytes::copy_from_slice(&data))
+            .await
+            .unwrap();
+        let (bytes, _) = fs.global_stats.get_totals();
+        assert_eq!(bytes, 3 * chunk_size);
+
+        fs.trim(&auth, file_id, chunk_size, chunk_size)
+            .await
+            .unwrap();
+        let (bytes, _) = fs.global_stats.get_totals();
+        assert_eq!(bytes, 2 * chunk_size);
+
+        // Truncating drops the allocation past the new size
+        let setattr = SetAttributes {
+            size: crate::fs::types::SetSize::Set(chunk_size / 2),
+            ..Default::default()
+        };
+        fs.setattr(&creds, file_id, &setattr).await.unwrap();
+        let (bytes, _) = fs.global_stats.get_totals();
+        assert_eq!(bytes, chunk_size / 2);
+
+        match fs.inode_store.get(file_id).await.unwrap() {
+            crate::fs::inode::Inode::File(file) => assert_eq!(file.allocated_chunks, 1),
+            _ => panic!("Expected File inode"),
+        }
     }
 
     #[tokio::test]
@@ -325,7 +691,7 @@ mod tests {
             .await
             .unwrap();
 
-        let data = vec![0u8; 5000];
+        let data = vec![1u8; 5000];
         fs.write(&auth, file_id, 0, &Bytes::copy_from_slice(&data))
             .await
             .unwrap();
@@ -393,7 +759,7 @@ mod tests {
             .await
             .unwrap();
 
-        let data = vec![0u8; 1000];
+        let data = vec![1u8; 1000];
         fs.write(&test_auth(), file_id, 0, &Bytes::copy_from_slice(&data))
             .await
             .unwrap();
@@ -432,7 +798,7 @@ mod tests {
             .await
             .unwrap();
 
-        let data = vec![0u8; 10000];
+        let data = vec![1u8; 10000];
         fs.write(&test_auth(), file_id, 0, &Bytes::copy_from_slice(&data))
             .await
             .unwrap();
@@ -483,7 +849,7 @@ mod tests {
                     .unwrap();
 
                 // Write different amounts of data
-                let data = vec![0u8; (i + 1) * 1000];
+                let data = vec![1u8; (i + 1) * 1000];
                 fs_clone
                     .write(&auth, file_id, 0, &Bytes::copy_from_slice(&data))
                     .await
@@ -515,8 +881,11 @@ mod tests {
             let shard_id = i % STATS_SHARDS;
             shard_counts[shard_id] += 1;
 
-            let update = stats.prepare_inode_create(i as u64).await;
+            let update = stats
+                .prepare_inode_create(i as u64, InodeAccount::new(0, (1000, 1000)))
+                .await;
             assert_eq!(update.shard_id, shard_id);
+            assert_eq!(update.scoped.len(), 2);
         }
 
         // Verify reasonable distribution (all shards should have some inodes)
@@ -539,7 +908,7 @@ mod tests {
                 .await
                 .unwrap();
 
-            let data = vec![0u8; 1_000_000]; // 1MB each
+            let data = vec![1u8; 1_000_000]; // 1MB each
             fs.write(&auth, file_id, 0, &Bytes::copy_from_slice(&data))
                 .await
                 .unwrap();
@@ -571,7 +940,7 @@ mod tests {
             .await
             .unwrap();
 
-        let data = vec![0u8; 1000];
+        let data = vec![1u8; 1000];
         fs.write(&auth, file_id, 0, &Bytes::copy_from_slice(&data))
             .await
             .unwrap();
@@ -599,7 +968,7 @@ mod tests {
             .create(&test_creds(), 0, b"source.txt", &SetAttributes::default())
             .await
             .unwrap();
-        let data1 = vec![0u8; 1000];
+        let data1 = vec![1u8; 1000];
         fs.write(&test_auth(), file1_id, 0, &Bytes::copy_from_slice(&data1))
             .await
             .unwrap();
@@ -609,7 +978,7 @@ mod tests {
             .create(&test_creds(), 0, b"target.txt", &SetAttributes::default())
             .await
             .unwrap();
-        let data2 = vec![0u8; 2000];
+        let data2 = vec![1u8; 2000];
         fs.write(&test_auth(), file2_id, 0, &Bytes::copy_from_slice(&data2))
             .await
             .unwrap();
@@ -637,7 +1006,7 @@ mod tests {
             .create(&test_creds(), 0, b"source.txt", &SetAttributes::default())
             .await
             .unwrap();
-        let data1 = vec![0u8; 500];
+        let data1 = vec![1u8; 500];
         fs.write(&test_auth(), source_id, 0, &Bytes::copy_from_slice(&data1))
             .await
             .unwrap();
@@ -647,7 +1016,7 @@ mod tests {
             .create(&test_creds(), 0, b"target.txt", &SetAttributes::default())
             .await
             .unwrap();
-        let data2 = vec![0u8; 1500];
+        let data2 = vec![1u8; 1500];
         fs.write(&test_auth(), target_id, 0, &Bytes::copy_from_slice(&data2))
             .await
             .unwrap();
@@ -721,7 +1090,7 @@ mod tests {
             .create(&creds, 0, b"file.txt", &SetAttributes::default())
             .await
             .unwrap();
-        let data = vec![0u8; 750];
+        let data = vec![1u8; 750];
         fs.write(&auth, file_id, 0, &Bytes::copy_from_slice(&data))
             .await
             .unwrap();
@@ -771,7 +1140,7 @@ mod tests {
             .create(&creds, dir1_id, b"file.txt", &SetAttributes::default())
             .await
             .unwrap();
-        let data = vec![0u8; 1234];
+        let data = vec![1u8; 1234];
         fs.write(&auth, file_id, 0, &Bytes::copy_from_slice(&data))
             .await
             .unwrap();
@@ -781,7 +1150,7 @@ mod tests {
             .create(&creds, dir2_id, b"target.txt", &SetAttributes::default())
             .await
             .unwrap();
-        let target_data = vec![0u8; 5678];
+        let target_data = vec![1u8; 5678];
         fs.write(&auth, target_id, 0, &Bytes::copy_from_slice(&target_data))
             .await
             .unwrap();
@@ -1,8 +1,10 @@
 use crate::encryption::{EncryptedDb, EncryptedTransaction};
-use crate::fs::inode::InodeId;
-use crate::fs::key_codec::KeyCodec;
-use crate::fs::{CHUNK_SIZE, FsError};
-use crate::writeback_cache::WritebackCache;
+use crate::fs::dataset::{DatasetId, ROOT_DATASET_ID};
+use crate::fs::inode::{BackingLayer, FileInode, InodeId};
+use crate::fs::key_codec::{ChunkHash, KeyCodec};
+use crate::fs::{FsError, MAX_CHUNK_SIZE};
+use crate::task::spawn_blocking_named;
+use crate::writeback_cache::{CachedWrite, WritebackCache};
 use bytes::{Bytes, BytesMut};
 use futures::stream::{self, StreamExt, TryStreamExt};
 use std::collections::HashMap;
@@ -10,8 +12,105 @@ use std::sync::Arc;
 use tracing::error;
 
 const PARALLEL_CHUNK_OPS: usize = 20;
-const ZERO_CHUNK: &[u8] = &[0u8; CHUNK_SIZE];
+static ZERO_CHUNK: [u8; MAX_CHUNK_SIZE] = [0u8; MAX_CHUNK_SIZE];
 
+/// An all-zero chunk of `chunk_size` bytes
+fn zero_chunk(chunk_size: usize) -> &'static [u8] {
+    &ZERO_CHUNK[..chunk_size]
+}
+
+/// Value of a chunk map entry.
+#[derive(Clone)]
+enum StoredChunk {
+    /// Reference to a chunk body in the content-addressed store
+    Shared(ChunkHash),
+    /// Chunk body stored under the chunk key itself. Data written before
+    /// content addressing, and chunks the writeback cache has not flushed
+    /// yet, use this form.
+    Inline(Bytes),
+    /// Zeroed chunk hiding data in a backing layer
+    Hole,
+}
+
+impl StoredChunk {
+    fn decode(value: Bytes) -> Self {
+        if KeyCodec::is_chunk_hole(&value) {
+            return Self::Hole;
+        }
+        match KeyCodec::decode_chunk_ref(&value) {
+            Some(hash) => Self::Shared(hash),
+            None => Self::Inline(value),
+        }
+    }
+
+    fn shared_hash(&self) -> Option<ChunkHash> {
+        match self {
+            Self::Shared(hash) => Some(*hash),
+            Self::Inline(_) | Self::Hole => None,
+        }
+    }
+}
+
+/// The chunk maps a file's data is read from.
+#[derive(Debug, Clone)]
+pub struct ChunkLayout {
+    /// Map written by the file
+    pub map_id: InodeId,
+    /// Frozen maps shared with clones, nearest first
+    pub backing: Vec<BackingLayer>,
+    /// Size of every chunk in these maps
+    pub chunk_size: usize,
+    /// Dataset whose key encrypts chunks written by the file
+    pub encryption_dataset: Option<DatasetId>,
+    /// Dataset the space of chunks written by the file is accounted to
+    pub dataset: DatasetId,
+}
+
+impl ChunkLayout {
+    pub fn new(map_id: InodeId, chunk_size: usize) -> Self {
+        Self {
+            map_id,
+            backing: Vec::new(),
+            chunk_size,
+            encryption_dataset: None,
+            dataset: ROOT_DATASET_ID,
+        }
+    }
+
+    pub fn for_file(id: InodeId, file: &FileInode) -> Self {
+        Self {
+            map_id: file.chunk_map_id(id),
+            backing: file.backing.clone(),
+            chunk_size: file.chunk_size as usize,
+            encryption_dataset: file.encryption_dataset,
+            dataset: file.dataset,
+        }
+    }
+
+    fn chunk_start(&self, chunk_idx: u64) -> u64 {
+        chunk_idx * self.chunk_size as u64
+    }
+
+    /// Whether a backing layer may supply this chunk, in which case clearing
+    /// it in the file's own map has to leave a hole.
+    fn is_shadowing(&self, chunk_idx: u64) -> bool {
+        let chunk_start = self.chunk_start(chunk_idx);
+        self.backing.iter().any(|l| chunk_start < l.visible_size)
+    }
+}
+
+/// Stores file data as fixed-size chunks. The chunk size is chosen per file
+/// when it is created (see [`ChunkLayout`]).
+///
+/// Each `(inode, chunk_idx)` key maps to a chunk body stored once under its
+/// content hash, with a reference count shared by every file, clone and
+/// snapshot using it. Reference counts are resolved when the transaction is
+/// written; bodies whose last reference goes away are deleted by the
+/// garbage collector.
+///
+/// A cloned file reads through its own chunk map first and then through the
+/// frozen maps it shares with its clones (see [`ChunkLayout`]), so cloning
+/// never copies chunk entries.
 #[derive(Clone)]
 pub struct ChunkStore {
     db: Arc<EncryptedDb>,
@@ -20,7 +119,7 @@ pub struct ChunkStore {
 
 impl ChunkStore {
     pub fn new(db: Arc<EncryptedDb>) -> Self {
-        Self { 
+        Self {
             db,
             writeback_cache: None,
         }
@@ -33,21 +132,45 @@ impl ChunkStore {
         }
     }
 
-    pub async fn get(&self, id: InodeId, chunk_idx: u64) -> Result<Option<Bytes>, FsError> {
+    pub async fn get(
+        &self,
+        id: InodeId,
+        chunk_idx: u64,
+        chunk_size: usize,
+    ) -> Result<Option<Bytes>, FsError> {
+        match self.get_stored(id, chunk_idx).await? {
+            Some(stored) => self.load(stored, chunk_size).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_stored(
+        &self,
+        id: InodeId,
+        chunk_idx: u64,
+    ) -> Result<Option<StoredChunk>, FsError> {
         // Try writeback cache first if enabled
         if let Some(ref cache) = self.writeback_cache {
             match cache.get(id, chunk_idx).await {
-                Ok(result) => return Ok(result),
+                Ok(result) => return Ok(result.map(StoredChunk::decode)),
                 Err(e) => {
                     error!("Writeback cache get error: {}, falling back to DB", e);
                 }
             }
         }
 
-        // Fall back to direct DB access
+        self.get_committed(id, chunk_idx).await
+    }
+
+    /// The entry the chunk map holds in the database, bypassing the cache.
+    async fn get_committed(
+        &self,
+        id: InodeId,
+        chunk_idx: u64,
+    ) -> Result<Option<StoredChunk>, FsError> {
         let key = KeyCodec::chunk_key(id, chunk_idx);
         match self.db.get_bytes(&key).await {
-            Ok(result) => Ok(result),
+            Ok(result) => Ok(result.map(StoredChunk::decode)),
             Err(e) => {
                 error!(
                     "Failed to read chunk (inode={}, chunk={}): {}",
@@ -58,84 +181,473 @@ impl ChunkStore {
         }
     }
 
-    fn save(&self, txn: &mut EncryptedTransaction, id: InodeId, chunk_idx: u64, data: Bytes) {
-        // If writeback cache is enabled, write to cache instead of transaction
-        if let Some(ref cache) = self.writeback_cache {
-            // Spawn async task to write to cache (non-blocking)
-            let cache_clone = Arc::clone(cache);
-            let data_clone = data.clone();
-            tokio::spawn(async move {
-                if let Err(e) = cache_clone.put(id, chunk_idx, data_clone).await {
-                    error!("Failed to write to writeback cache: {}", e);
-                }
-            });
-            // Don't add to transaction - writeback cache will handle persistence
-            return;
+    async fn load(&self, stored: StoredChunk, chunk_size: usize) -> Result<Bytes, FsError> {
+        let hash = match stored {
+            StoredChunk::Inline(data) => return Ok(data),
+            StoredChunk::Hole => return Ok(Bytes::from_static(zero_chunk(chunk_size))),
+            StoredChunk::Shared(hash) => hash,
+        };
+
+        match self.db.get_bytes(&KeyCodec::chunk_cas_key(&hash)).await {
+            Ok(Some(data)) => Ok(data),
+            Ok(None) => {
+                error!("Missing content-addressed chunk {}", hex(&hash));
+                Err(FsError::IoError)
+            }
+            Err(e) => {
+                error!(
+                    "Failed to read content-addressed chunk {}: {}",
+                    hex(&hash),
+                    e
+                );
+                Err(FsError::IoError)
+            }
         }
+    }
 
-        // No writeback cache - write directly to transaction
-        let key = KeyCodec::chunk_key(id, chunk_idx);
-        txn.put_bytes(&key, data);
+    /// Read a chunk the file's own map does not hold from its backing layers.
+    async fn get_backing(
+        &self,
+        layout: &ChunkLayout,
+        chunk_idx: u64,
+    ) -> Result<Option<Bytes>, FsError> {
+        let chunk_start = layout.chunk_start(chunk_idx);
+        for layer in &layout.backing {
+            if chunk_start >= layer.visible_size {
+                continue;
+            }
+            match self.get_stored(layer.map_id, chunk_idx).await? {
+                Some(StoredChunk::Hole) => return Ok(None),
+                Some(stored) => return self.load(stored, layout.chunk_size).await.map(Some),
+                None => {}
+            }
+        }
+        Ok(None)
     }
 
-    pub fn delete(&self, txn: &mut EncryptedTransaction, id: InodeId, chunk_idx: u64) {
-        // If writeback cache is enabled, delete from cache
-        if let Some(ref cache) = self.writeback_cache {
-            let cache_clone = Arc::clone(cache);
-            tokio::spawn(async move {
-                if let Err(e) = cache_clone.delete(id, chunk_idx).await {
-                    error!("Failed to delete from writeback cache: {}", e);
+    /// Current contents of a chunk, given the file's own entry for it.
+    async fn load_layered(
+        &self,
+        layout: &ChunkLayout,
+        chunk_idx: u64,
+        stored: Option<&StoredChunk>,
+    ) -> Result<Option<Bytes>, FsError> {
+        match stored {
+            Some(stored) => self.load(stored.clone(), layout.chunk_size).await.map(Some),
+            None => self.get_backing(layout, chunk_idx).await,
+        }
+    }
+
+    /// Whether the file reads data from a chunk, given its own entry for it.
+    /// Holes and chunks no layer holds read as zeros and take no storage.
+    async fn is_allocated(
+        &self,
+        layout: &ChunkLayout,
+        chunk_idx: u64,
+        stored: Option<&StoredChunk>,
+    ) -> Result<bool, FsError> {
+        match stored {
+            Some(StoredChunk::Hole) => return Ok(false),
+            Some(_) => return Ok(true),
+            None => {}
+        }
+
+        let chunk_start = layout.chunk_start(chunk_idx);
+        for layer in &layout.backing {
+            if chunk_start >= layer.visible_size {
+                continue;
+            }
+            match self.get_stored(layer.map_id, chunk_idx).await? {
+                Some(StoredChunk::Hole) => return Ok(false),
+                Some(_) => return Ok(true),
+                None => {}
+            }
+        }
+        Ok(false)
+    }
+
+    /// Whether a chunk map of `chunk_size` chunks holds any entry below `size`.
+    pub async fn has_chunks(
+        &self,
+        map_id: InodeId,
+        size: u64,
+        chunk_size: usize,
+    ) -> Result<bool, FsError> {
+        if let Some(ref cache) = self.writeback_cache
+            && cache.has_chunks(map_id)
+        {
+            return Ok(true);
+        }
+
+        let start_key = KeyCodec::chunk_key(map_id, 0);
+        let end_key = KeyCodec::chunk_key(map_id, size.div_ceil(chunk_size as u64));
+        let mut stream = self.db.scan(start_key..end_key).await.map_err(|e| {
+            error!("Failed to scan chunks (inode={}): {}", map_id, e);
+            FsError::IoError
+        })?;
+        match stream.next().await {
+            Some(Ok(_)) => Ok(true),
+            Some(Err(e)) => {
+                error!("Failed to read chunk during scan (inode={}): {}", map_id, e);
+                Err(FsError::IoError)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Copy what the file reads below `size`, from its own map and backing
+    /// layers together, into the empty map `map_id`, which can then replace
+    /// all of them. Holes are left out: nothing lies below the new map. Returns
+    /// false, copying nothing, while the writeback cache holds chunks of the
+    /// file's own map.
+    pub async fn flatten(
+        &self,
+        txn: &mut EncryptedTransaction,
+        layout: &ChunkLayout,
+        size: u64,
+        map_id: InodeId,
+    ) -> Result<bool, FsError> {
+        if let Some(ref cache) = self.writeback_cache
+            && cache.has_chunks(layout.map_id)
+        {
+            return Ok(false);
+        }
+
+        let end = size.div_ceil(layout.chunk_size as u64);
+        for (chunk_idx, stored) in self.scan_layered(layout, 0, end).await? {
+            let key = KeyCodec::chunk_key(map_id, chunk_idx);
+            match stored {
+                StoredChunk::Shared(hash) => {
+                    txn.add_chunk_ref(hash, None, layout.encryption_dataset, layout.dataset);
+                    txn.put_bytes(&key, KeyCodec::encode_chunk_ref(&hash));
                 }
-            });
+                StoredChunk::Inline(data) => txn.put_bytes(&key, data),
+                StoredChunk::Hole => {}
+            }
         }
+        Ok(true)
+    }
+
+    /// Writeback cache new chunks of this layout go to. Chunks encrypted with
+    /// a dataset key bypass it: the cache flushes them inline, under the
+    /// filesystem key.
+    fn writeback_for(&self, layout: &ChunkLayout) -> Option<&Arc<WritebackCache>> {
+        self.writeback_cache
+            .as_ref()
+            .filter(|_| layout.encryption_dataset.is_none())
+    }
 
-        // Always add to transaction for immediate consistency
+    /// Hash chunk bodies off the async runtime
+    async fn hash_chunks(
+        &self,
+        dataset: Option<DatasetId>,
+        chunks: Vec<Bytes>,
+    ) -> Result<Vec<ChunkHash>, FsError> {
+        let db = self.db.clone();
+        spawn_blocking_named("hash-chunks", move || {
+            chunks
+                .iter()
+                .map(|data| db.content_hash_in(dataset, data))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .await
+        .map_err(|e| {
+            error!("Chunk hashing task failed: {}", e);
+            FsError::IoError
+        })?
+        .map_err(|e| {
+            error!("Failed to hash chunks: {}", e);
+            FsError::IoError
+        })
+    }
+
+    /// Point a chunk at new data, replacing whatever `existing` held.
+    fn save(
+        &self,
+        txn: &mut EncryptedTransaction,
+        layout: &ChunkLayout,
+        chunk_idx: u64,
+        existing: Option<&StoredChunk>,
+        data: Bytes,
+        hash: Option<ChunkHash>,
+    ) -> Result<(), FsError> {
+        let id = layout.map_id;
         let key = KeyCodec::chunk_key(id, chunk_idx);
-        txn.delete_bytes(&key);
+
+        // The cache takes the chunk when the transaction commits. The chunk
+        // map keeps its entry, and the reference it holds, until the cache
+        // flushes the chunk in its place.
+        if self.writeback_for(layout).is_some() {
+            txn.cache_write(CachedWrite::Put {
+                id,
+                chunk_idx,
+                data,
+                dataset: layout.dataset,
+            });
+            return Ok(());
+        }
+        if self.writeback_cache.is_some() {
+            txn.cache_write(CachedWrite::Evict { id, chunk_idx });
+        }
+
+        let hash = match hash {
+            Some(hash) => hash,
+            None => self
+                .db
+                .content_hash_in(layout.encryption_dataset, &data)
+                .map_err(|e| {
+                    error!("Failed to hash chunk (inode={}): {}", id, e);
+                    FsError::IoError
+                })?,
+        };
+        let old = existing.and_then(StoredChunk::shared_hash);
+        if old == Some(hash) {
+            return Ok(());
+        }
+        if let Some(old) = old {
+            txn.remove_chunk_ref(old);
+        }
+
+        txn.add_chunk_ref(hash, Some(data), layout.encryption_dataset, layout.dataset);
+        txn.put_bytes(&key, KeyCodec::encode_chunk_ref(&hash));
+        Ok(())
     }
 
-    pub fn delete_range(&self, txn: &mut EncryptedTransaction, id: InodeId, start: u64, end: u64) {
-        for chunk_idx in start..end {
-            self.delete(txn, id, chunk_idx);
+    /// Remove a chunk, releasing its reference if it held one. With `hole`
+    /// set, a hole marker replaces it so backing layers stay hidden.
+    fn release(
+        &self,
+        txn: &mut EncryptedTransaction,
+        id: InodeId,
+        chunk_idx: u64,
+        existing: Option<&StoredChunk>,
+        hole: bool,
+    ) {
+        // `existing` may be the cache's copy of the chunk rather than the
+        // entry of the chunk map, and a flush may replace that entry before
+        // the transaction commits, so the entry is released at commit.
+        if self.writeback_cache.is_some() {
+            txn.cache_write(CachedWrite::Release {
+                id,
+                chunk_idx,
+                hole,
+            });
+            return;
         }
+        Self::release_entry(txn, id, chunk_idx, existing, hole);
     }
 
-    pub async fn read(&self, id: InodeId, offset: u64, length: u64) -> Result<Bytes, FsError> {
-        if length == 0 {
-            return Ok(Bytes::new());
+    fn release_entry(
+        txn: &mut EncryptedTransaction,
+        id: InodeId,
+        chunk_idx: u64,
+        existing: Option<&StoredChunk>,
+        hole: bool,
+    ) {
+        if let Some(hash) = existing.and_then(StoredChunk::shared_hash) {
+            txn.remove_chunk_ref(hash);
         }
 
-        let end = offset + length;
-        let start_chunk = offset / CHUNK_SIZE as u64;
-        let end_chunk = (end - 1) / CHUNK_SIZE as u64;
-        let start_offset = (offset % CHUNK_SIZE as u64) as usize;
+        let key = KeyCodec::chunk_key(id, chunk_idx);
+        if hole {
+            if !matches!(existing, Some(StoredChunk::Hole)) {
+                txn.put_bytes(&key, KeyCodec::chunk_hole());
+            }
+        } else {
+            txn.delete_bytes(&key);
+        }
+    }
+
+    /// Apply the writeback cache changes recorded on `txn`, releasing the
+    /// chunk map entries of removed chunks in the transaction. Called while
+    /// committing, once every earlier transaction has committed, so cache
+    /// flushes cannot change those entries before the transaction is written.
+    pub async fn apply_cached_writes(&self, txn: &mut EncryptedTransaction) -> Result<(), FsError> {
+        let writes = txn.take_cached_writes();
+        let Some(ref cache) = self.writeback_cache else {
+            return Ok(());
+        };
+
+        let mut released = Vec::new();
+        for write in writes {
+            let result = match write {
+                CachedWrite::Put {
+                    id,
+                    chunk_idx,
+                    data,
+                    dataset,
+                } => cache.put(id, chunk_idx, data, dataset).await,
+                CachedWrite::Release {
+                    id,
+                    chunk_idx,
+                    hole,
+                } => {
+                    released.push((id, chunk_idx, hole));
+                    cache.delete(id, chunk_idx).await
+                }
+                CachedWrite::Evict { id, chunk_idx } => cache.delete(id, chunk_idx).await,
+            };
+            result.map_err(|e| {
+                error!("Failed to update writeback cache: {}", e);
+                FsError::IoError
+            })?;
+        }
 
-        let start_key = KeyCodec::chunk_key(id, start_chunk);
-        let end_key = KeyCodec::chunk_key(id, end_chunk + 1);
+        let released: Vec<_> = stream::iter(released)
+            .map(|(id, chunk_idx, hole)| {
+                let store = self.clone();
+                async move {
+                    let stored = store.get_committed(id, chunk_idx).await?;
+                    Ok::<_, FsError>((id, chunk_idx, hole, stored))
+                }
+            })
+            .buffered(PARALLEL_CHUNK_OPS)
+            .try_collect()
+            .await?;
+        for (id, chunk_idx, hole, stored) in released {
+            Self::release_entry(txn, id, chunk_idx, stored.as_ref(), hole);
+        }
+        Ok(())
+    }
 
-        let mut chunk_map: HashMap<u64, Bytes> = HashMap::new();
+    /// Scan the entries of one chunk map in `start..end`.
+    async fn scan_stored(
+        &self,
+        map_id: InodeId,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<(u64, StoredChunk)>, FsError> {
+        let start_key = KeyCodec::chunk_key(map_id, start);
+        let end_key = KeyCodec::chunk_key(map_id, end);
+
+        let mut stored = Vec::new();
         let mut stream = self.db.scan(start_key..end_key).await.map_err(|e| {
-            error!("Failed to scan chunks (inode={}): {}", id, e);
+            error!("Failed to scan chunks (inode={}): {}", map_id, e);
             FsError::IoError
         })?;
 
         while let Some(result) = stream.next().await {
             let (key, value) = result.map_err(|e| {
-                error!("Failed to read chunk during scan (inode={}): {}", id, e);
+                error!("Failed to read chunk during scan (inode={}): {}", map_id, e);
                 FsError::IoError
             })?;
             if let Some(chunk_idx) = KeyCodec::parse_chunk_key(&key) {
-                chunk_map.insert(chunk_idx, value);
+                stored.push((chunk_idx, StoredChunk::decode(value)));
             }
         }
 
+        Ok(stored)
+    }
+
+    /// Entries chunks `start..end` of a file are read from: its own, then
+    /// those of the nearest backing layer that still exposes them.
+    async fn scan_layered(
+        &self,
+        layout: &ChunkLayout,
+        start: u64,
+        end: u64,
+    ) -> Result<HashMap<u64, StoredChunk>, FsError> {
+        let chunk_size = layout.chunk_size as u64;
+        let mut stored: HashMap<u64, StoredChunk> = self
+            .scan_stored(layout.map_id, start, end)
+            .await?
+            .into_iter()
+            .collect();
+
+        for layer in &layout.backing {
+            let visible_end = layer.visible_size.div_ceil(chunk_size).min(end);
+            if start >= visible_end || (start..visible_end).all(|idx| stored.contains_key(&idx)) {
+                continue;
+            }
+            for (chunk_idx, chunk) in self.scan_stored(layer.map_id, start, visible_end).await? {
+                stored.entry(chunk_idx).or_insert(chunk);
+            }
+        }
+
+        Ok(stored)
+    }
+
+    /// Number of chunks in `start..end` the file reads data from.
+    async fn count_allocated(
+        &self,
+        layout: &ChunkLayout,
+        start: u64,
+        end: u64,
+    ) -> Result<u64, FsError> {
+        if start >= end {
+            return Ok(0);
+        }
+        let stored = self.scan_layered(layout, start, end).await?;
+        Ok(stored
+            .values()
+            .filter(|chunk| !matches!(chunk, StoredChunk::Hole))
+            .count() as u64)
+    }
+
+    /// Delete chunks `start..end` of an inode, releasing the references they hold.
+    pub async fn delete_range(
+        &self,
+        txn: &mut EncryptedTransaction,
+        id: InodeId,
+        start: u64,
+        end: u64,
+    ) -> Result<(), FsError> {
+        if start >= end {
+            return Ok(());
+        }
+
+        let stored: HashMap<u64, StoredChunk> = self
+            .scan_stored(id, start, end)
+            .await?
+            .into_iter()
+            .collect();
+
+        for chunk_idx in start..end {
+            self.release(txn, id, chunk_idx, stored.get(&chunk_idx), false);
+        }
+
+        Ok(())
+    }
+
+    pub async fn read(
+        &self,
+        layout: &ChunkLayout,
+        offset: u64,
+        length: u64,
+    ) -> Result<Bytes, FsError> {
+        if length == 0 {
+            return Ok(Bytes::new());
+        }
+
+        let chunk_size = layout.chunk_size as u64;
+        let end = offset + length;
+        let start_chunk = offset / chunk_size;
+        let end_chunk = (end - 1) / chunk_size;
+        let start_offset = (offset % chunk_size) as usize;
+
+        let stored = self
+            .scan_layered(layout, start_chunk, end_chunk + 1)
+            .await?;
+
+        let chunk_map: HashMap<u64, Bytes> = stream::iter(stored)
+            .map(|(chunk_idx, stored)| {
+                let store = self.clone();
+                async move {
+                    let data = store.load(stored, layout.chunk_size).await?;
+                    Ok::<_, FsError>((chunk_idx, data))
+                }
+            })
+            .buffer_unordered(PARALLEL_CHUNK_OPS)
+            .try_collect()
+            .await?;
+
         let mut result = BytesMut::with_capacity(length as usize);
 
         for chunk_idx in start_chunk..=end_chunk {
             let chunk_data = chunk_map
                 .get(&chunk_idx)
                 .map(|b| b.as_ref())
-                .unwrap_or(ZERO_CHUNK);
+                .unwrap_or(zero_chunk(layout.chunk_size));
 
             let chunk_start = if chunk_idx == start_chunk {
                 start_offset
@@ -143,9 +655,9 @@ impl ChunkStore {
                 0
             };
             let chunk_end = if chunk_idx == end_chunk {
-                ((end - 1) % CHUNK_SIZE as u64 + 1) as usize
+                ((end - 1) % chunk_size + 1) as usize
             } else {
-                CHUNK_SIZE
+                layout.chunk_size
             };
             result.extend_from_slice(&chunk_data[chunk_start..chunk_end]);
         }
@@ -153,51 +665,68 @@ impl ChunkStore {
         Ok(result.freeze())
     }
 
+    /// Write `data` at `offset`. Returns the change in the number of chunks
+    /// the file reads data from.
     pub async fn write(
         &self,
         txn: &mut EncryptedTransaction,
-        id: InodeId,
+        layout: &ChunkLayout,
         offset: u64,
         data: &[u8],
-    ) -> Result<(), FsError> {
+    ) -> Result<i64, FsError> {
         if data.is_empty() {
-            return Ok(());
+            return Ok(0);
         }
 
+        let id = layout.map_id;
+        let chunk_size = layout.chunk_size;
         let end_offset = offset + data.len() as u64;
-        let start_chunk = offset / CHUNK_SIZE as u64;
-        let end_chunk = (end_offset - 1) / CHUNK_SIZE as u64;
+        let start_chunk = offset / chunk_size as u64;
+        let end_chunk = (end_offset - 1) / chunk_size as u64;
 
-        let existing_chunks: Result<HashMap<u64, Bytes>, FsError> =
+        // Fully overwritten chunks still need their current entry, to release
+        // the reference it holds, but not their data.
+        type Existing = (Option<StoredChunk>, Bytes, bool);
+        let existing_chunks: Result<HashMap<u64, Existing>, FsError> =
             stream::iter(start_chunk..=end_chunk)
                 .map(|chunk_idx| {
-                    let chunk_start = chunk_idx * CHUNK_SIZE as u64;
-                    let chunk_end = chunk_start + CHUNK_SIZE as u64;
+                    let chunk_start = layout.chunk_start(chunk_idx);
+                    let chunk_end = chunk_start + chunk_size as u64;
                     let will_overwrite_fully = offset <= chunk_start && end_offset >= chunk_end;
 
                     let store = self.clone();
                     async move {
+                        let stored = store.get_stored(id, chunk_idx).await?;
+                        let allocated = store
+                            .is_allocated(layout, chunk_idx, stored.as_ref())
+                            .await?;
                         let data = if will_overwrite_fully {
-                            Bytes::from_static(ZERO_CHUNK)
+                            None
                         } else {
                             store
-                                .get(id, chunk_idx)
+                                .load_layered(layout, chunk_idx, stored.as_ref())
                                 .await?
-                                .unwrap_or_else(|| Bytes::from_static(ZERO_CHUNK))
                         };
-                        Ok::<(u64, Bytes), FsError>((chunk_idx, data))
+                        let data =
+                            data.unwrap_or_else(|| Bytes::from_static(zero_chunk(chunk_size)));
+                        Ok::<_, FsError>((chunk_idx, (stored, data, allocated)))
                     }
                 })
                 .buffer_unordered(PARALLEL_CHUNK_OPS)
                 .try_collect()
                 .await;
 
-        let existing_chunks = existing_chunks?;
+        let mut existing_chunks = existing_chunks?;
+        let was_allocated = existing_chunks
+            .values()
+            .filter(|(_, _, allocated)| *allocated)
+            .count() as i64;
 
+        let mut updated_chunks: Vec<(u64, Bytes)> = Vec::new();
         let mut data_offset = 0usize;
         for chunk_idx in start_chunk..=end_chunk {
-            let chunk_start = chunk_idx * CHUNK_SIZE as u64;
-            let chunk_end = chunk_start + CHUNK_SIZE as u64;
+            let chunk_start = layout.chunk_start(chunk_idx);
+            let chunk_end = chunk_start + chunk_size as u64;
 
             let write_start = if offset > chunk_start {
                 (offset - chunk_start) as usize
@@ -207,89 +736,161 @@ impl ChunkStore {
             let write_end = if end_offset < chunk_end {
                 (end_offset - chunk_start) as usize
             } else {
-                CHUNK_SIZE
+                chunk_size
             };
 
             let write_len = write_end - write_start;
-            let mut chunk = BytesMut::from(existing_chunks[&chunk_idx].as_ref());
+            let mut chunk = BytesMut::from(existing_chunks[&chunk_idx].1.as_ref());
             chunk[write_start..write_end]
                 .copy_from_slice(&data[data_offset..data_offset + write_len]);
             data_offset += write_len;
 
-            if chunk.as_ref() == ZERO_CHUNK {
-                self.delete(txn, id, chunk_idx);
+            if chunk.as_ref() == zero_chunk(chunk_size) {
+                let stored = existing_chunks.remove(&chunk_idx).and_then(|(s, _, _)| s);
+                let hole = layout.is_shadowing(chunk_idx);
+                self.release(txn, id, chunk_idx, stored.as_ref(), hole);
             } else {
-                self.save(txn, id, chunk_idx, chunk.freeze());
+                updated_chunks.push((chunk_idx, chunk.freeze()));
             }
         }
 
-        Ok(())
+        let hashes = if self.writeback_for(layout).is_none() {
+            self.hash_chunks(
+                layout.encryption_dataset,
+                updated_chunks.iter().map(|(_, d)| d.clone()).collect(),
+            )
+            .await?
+            .into_iter()
+            .map(Some)
+            .collect()
+        } else {
+            vec![None; updated_chunks.len()]
+        };
+
+        let now_allocated = updated_chunks.len() as i64;
+        for ((chunk_idx, chunk), hash) in updated_chunks.into_iter().zip(hashes) {
+            let stored = existing_chunks.remove(&chunk_idx).and_then(|(s, _, _)| s);
+            self.save(txn, layout, chunk_idx, stored.as_ref(), chunk, hash)?;
+        }
+
+        Ok(now_allocated - was_allocated)
     }
 
+    /// Drop the chunks past `new_size` from the file's own map. Backing layers
+    /// are hidden by the caller with [`FileInode::clip_backing`], so `layout`
+    /// is the one from before. Returns the change in the number of chunks the
+    /// file reads data from.
     pub async fn truncate(
         &self,
         txn: &mut EncryptedTransaction,
-        id: InodeId,
+        layout: &ChunkLayout,
         old_size: u64,
         new_size: u64,
-    ) -> Result<(), FsError> {
+    ) -> Result<i64, FsError> {
         if new_size >= old_size {
-            return Ok(());
+            return Ok(0);
         }
 
-        let old_chunks = old_size.div_ceil(CHUNK_SIZE as u64);
-        let new_chunks = new_size.div_ceil(CHUNK_SIZE as u64);
+        let id = layout.map_id;
+        let chunk_size = layout.chunk_size;
+        let old_chunks = old_size.div_ceil(chunk_size as u64);
+        let new_chunks = new_size.div_ceil(chunk_size as u64);
 
-        self.delete_range(txn, id, new_chunks, old_chunks);
+        let mut delta = -(self.count_allocated(layout, new_chunks, old_chunks).await? as i64);
+        self.delete_range(txn, id, new_chunks, old_chunks).await?;
 
         if new_size > 0 {
             let last_chunk_idx = new_chunks - 1;
-            let clear_from = (new_size % CHUNK_SIZE as u64) as usize;
+            let clear_from = (new_size % chunk_size as u64) as usize;
 
             if clear_from > 0 {
-                let existing = self.get(id, last_chunk_idx).await?;
-                let mut chunk =
-                    BytesMut::from(existing.as_ref().map(|b| b.as_ref()).unwrap_or(ZERO_CHUNK));
+                let stored = self.get_stored(id, last_chunk_idx).await?;
+                if self
+                    .is_allocated(layout, last_chunk_idx, stored.as_ref())
+                    .await?
+                {
+                    delta -= 1;
+                }
+                let existing = self
+                    .load_layered(layout, last_chunk_idx, stored.as_ref())
+                    .await?;
+                let mut chunk = BytesMut::from(
+                    existing
+                        .as_ref()
+                        .map(|b| b.as_ref())
+                        .unwrap_or(zero_chunk(chunk_size)),
+                );
                 chunk[clear_from..].fill(0);
 
-                if chunk.as_ref() == ZERO_CHUNK {
-                    self.delete(txn, id, last_chunk_idx);
+                if chunk.as_ref() == zero_chunk(chunk_size) {
+                    let hole = layout.is_shadowing(last_chunk_idx);
+                    self.release(txn, id, last_chunk_idx, stored.as_ref(), hole);
                 } else {
-                    self.save(txn, id, last_chunk_idx, chunk.freeze());
+                    self.save(
+                        txn,
+                        layout,
+                        last_chunk_idx,
+                        stored.as_ref(),
+                        chunk.freeze(),
+                        None,
+                    )?;
+                    delta += 1;
                 }
             }
         }
 
-        Ok(())
+        Ok(delta)
     }
 
+    /// Zero `length` bytes at `offset`, dropping chunks that become all
+    /// zeros. Returns the change in the number of chunks the file reads data
+    /// from.
     pub async fn zero_range(
         &self,
         txn: &mut EncryptedTransaction,
-        id: InodeId,
+        layout: &ChunkLayout,
         offset: u64,
         length: u64,
         file_size: u64,
-    ) {
+    ) -> i64 {
         if length == 0 {
-            return;
+            return 0;
         }
 
+        let id = layout.map_id;
+        let chunk_size = layout.chunk_size;
         let end_offset = offset + length;
-        let start_chunk = offset / CHUNK_SIZE as u64;
-        let end_chunk = (end_offset - 1) / CHUNK_SIZE as u64;
+        let start_chunk = offset / chunk_size as u64;
+        let end_chunk = (end_offset - 1) / chunk_size as u64;
 
+        let mut delta = 0;
         for chunk_idx in start_chunk..=end_chunk {
-            let chunk_start = chunk_idx * CHUNK_SIZE as u64;
-            let chunk_end = chunk_start + CHUNK_SIZE as u64;
+            let chunk_start = layout.chunk_start(chunk_idx);
+            let chunk_end = chunk_start + chunk_size as u64;
 
             if chunk_start >= file_size {
                 continue;
             }
 
+            let stored = match self.get_stored(id, chunk_idx).await {
+                Ok(stored) => stored,
+                Err(_) => continue,
+            };
+            let hole = layout.is_shadowing(chunk_idx);
+            if stored.is_none() && !hole {
+                continue;
+            }
+            let Ok(was_allocated) = self.is_allocated(layout, chunk_idx, stored.as_ref()).await
+            else {
+                continue;
+            };
+
             if offset <= chunk_start && end_offset >= chunk_end {
-                self.delete(txn, id, chunk_idx);
-            } else if let Ok(Some(existing_data)) = self.get(id, chunk_idx).await {
+                self.release(txn, id, chunk_idx, stored.as_ref(), hole);
+                delta -= was_allocated as i64;
+            } else if let Ok(Some(existing_data)) =
+                self.load_layered(layout, chunk_idx, stored.as_ref()).await
+            {
                 let zero_start = if offset > chunk_start {
                     (offset - chunk_start) as usize
                 } else {
@@ -298,98 +899,34 @@ impl ChunkStore {
                 let zero_end = if end_offset < chunk_end {
                     (end_offset - chunk_start) as usize
                 } else {
-                    CHUNK_SIZE
+                    chunk_size
                 };
 
                 let mut chunk_data = BytesMut::from(existing_data.as_ref());
                 chunk_data[zero_start..zero_end].fill(0);
 
-                if chunk_data.as_ref() == ZERO_CHUNK {
-                    self.delete(txn, id, chunk_idx);
+                if chunk_data.as_ref() == zero_chunk(chunk_size) {
+                    self.release(txn, id, chunk_idx, stored.as_ref(), hole);
+                    delta -= was_allocated as i64;
+                } else if let Err(e) = self.save(
+                    txn,
+                    layout,
+                    chunk_idx,
+                    stored.as_ref(),
+                    chunk_data.freeze(),
+                    None,
+                ) {
+                    error!("Failed to zero chunk (inode={}): {:?}", id, e);
                 } else {
-                    self.save(txn, id, chunk_idx, chunk_data.freeze());
+                    delta += 1 - was_allocated as i64;
                 }
             }
         }
-    }
-
-    /// Copy all chunks from source inode to destination inode (for COW snapshots)
-    /// 
-    /// This creates a shallow copy of chunk metadata - the actual chunk data is shared
-    /// until one of the inodes modifies it. This enables instant snapshots without
-    /// duplicating data.
-    /// 
-    /// Note: This is a workaround for the current architecture where chunks are keyed
-    /// by (inode_id, chunk_index). A better long-term solution would be content-addressable
-    /// storage where chunks are keyed by hash.
-    pub async fn copy_chunks_for_cow(
-        &self,
-        source_id: InodeId,
-        dest_id: InodeId,
-        file_size: u64,
-    ) -> Result<(), FsError> {
-        if file_size == 0 {
-            return Ok(());
-        }
-
-        let num_chunks = file_size.div_ceil(CHUNK_SIZE as u64);
-        
-        tracing::debug!(
-            "Copying {} chunks from inode {} to inode {} (file_size={})",
-            num_chunks,
-            source_id,
-            dest_id,
-            file_size
-        );
-
-        // Scan all chunks for the source inode
-        let start_key = KeyCodec::chunk_key(source_id, 0);
-        let end_key = KeyCodec::chunk_key(source_id, num_chunks);
-
-        let mut stream = self.db.scan(start_key..end_key).await.map_err(|e| {
-            error!("Failed to scan chunks for inode {}: {}", source_id, e);
-            FsError::IoError
-        })?;
-
-        let mut chunks_copied = 0;
-        while let Some(result) = stream.next().await {
-            let (key, value) = result.map_err(|e| {
-                error!("Failed to read chunk during COW copy: {}", e);
-                FsError::IoError
-            })?;
-            
-            if let Some(chunk_idx) = KeyCodec::parse_chunk_key(&key) {
-                // Create new chunk key with destination inode ID
-                let dest_key = KeyCodec::chunk_key(dest_id, chunk_idx);
-                
-                // Copy the chunk data to the new key
-                self.db
-                    .put_with_options(
-                        &dest_key,
-                        &value,
-                        &slatedb::config::PutOptions::default(),
-                        &slatedb::config::WriteOptions {
-                            await_durable: false, // Batch for performance
-                        },
-                    )
-                    .await
-                    .map_err(|e| {
-                        error!("Failed to copy chunk {} from inode {} to {}: {}", 
-                            chunk_idx, source_id, dest_id, e);
-                        FsError::IoError
-                    })?;
-                
-                chunks_copied += 1;
-            }
-        }
-
-        tracing::info!(
-            "Copied {} chunks from inode {} to inode {} for COW",
-            chunks_copied,
-            source_id,
-            dest_id
-        );
 
-        Ok(())
+        delta
     }
 }
+
+fn hex(hash: &ChunkHash) -> String {
+    hash.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -1,9 +1,12 @@
-use crate::encryption::EncryptedDb;
-use crate::fs::dataset::{Dataset, DatasetId, DatasetRegistry};
+use crate::encryption::{EncryptedDb, EncryptedTransaction};
+use crate::fs::dataset::{Dataset, DatasetId, DatasetQuota, DatasetRegistry};
 use crate::fs::errors::FsError;
 use crate::fs::key_codec::KeyCodec;
+use crate::fs::properties::{self, EffectiveProperties, Property, PropertyValue};
+use bytes::Bytes;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{RwLock, RwLockWriteGuard};
+use uuid::Uuid;
 
 #[derive(Clone)]
 pub struct DatasetStore {
@@ -24,7 +27,7 @@ impl DatasetStore {
             .await
             .map_err(|_| FsError::IoError)?
         {
-            Some(data) => bincode::deserialize(&data).map_err(|e| {
+            Some(data) => DatasetRegistry::decode(&data).map_err(|e| {
                 tracing::warn!("Failed to deserialize dataset registry: {:?}", e);
                 FsError::InvalidData
             })?,
@@ -34,7 +37,7 @@ impl DatasetStore {
                     let registry = DatasetRegistry::new_with_root(root_inode, created_at);
 
                     // Persist the registry
-                    let serialized = bincode::serialize(&registry).map_err(|_| FsError::IoError)?;
+                    let serialized = registry.encode().map_err(|_| FsError::IoError)?;
                     db.put_with_options(
                         &registry_key,
                         &serialized,
@@ -53,6 +56,17 @@ impl DatasetStore {
             }
         };
 
+        for dataset in registry.datasets.values() {
+            if let Some(wrapped_key) = &dataset.wrapped_key {
+                db.unlock_dataset_key(dataset.id, wrapped_key)
+                    .map_err(|e| {
+                        tracing::error!("Failed to unlock dataset key {}: {:?}", dataset.id, e);
+                        FsError::InvalidData
+                    })?;
+            }
+        }
+        db.set_dataset_compression(properties::compression_overrides(&registry));
+
         Ok(Self {
             db,
             registry: Arc::new(RwLock::new(registry)),
@@ -64,13 +78,16 @@ impl DatasetStore {
         self.registry.read().await.clone()
     }
 
-    /// Create a new dataset
+    /// Create a new dataset, nested in `parent_id` if given. With `encrypt`,
+    /// the dataset gets a key of its own.
     pub async fn create_dataset(
         &self,
         name: String,
+        parent_id: Option<DatasetId>,
         root_inode: u64,
         created_at: u64,
         is_readonly: bool,
+        encrypt: bool,
     ) -> Result<Dataset, FsError> {
         if self.db.is_read_only() {
             return Err(FsError::ReadOnlyFilesystem);
@@ -79,7 +96,15 @@ impl DatasetStore {
         let mut registry = self.registry.write().await;
 
         let id = registry.allocate_id();
-        let dataset = Dataset::new(id, name, root_inode, created_at, is_readonly);
+        let mut dataset = Dataset::new(id, name, root_inode, created_at, is_readonly);
+        dataset.parent_id = parent_id;
+        if encrypt {
+            let wrapped_key = self.db.generate_dataset_key(id).map_err(|e| {
+                tracing::error!("Failed to generate key for dataset {}: {:?}", id, e);
+                FsError::IoError
+            })?;
+            dataset.wrapped_key = Some(wrapped_key);
+        }
 
         registry.add_dataset(dataset.clone()).map_err(|e| {
             tracing::warn!("Failed to add dataset to registry: {}", e);
@@ -101,50 +126,246 @@ impl DatasetStore {
         created_at: u64,
         is_readonly: bool,
     ) -> Result<Dataset, FsError> {
+        let mut snapshots = self
+            .create_snapshots(
+                vec![(source_id, snapshot_name, snapshot_root_inode)],
+                created_at,
+                is_readonly,
+            )
+            .await?;
+        Ok(snapshots.remove(0))
+    }
+
+    /// Create snapshots of several datasets at once, given as (source,
+    /// snapshot name, snapshot root inode). Either all of them are added or
+    /// none is.
+    pub async fn create_snapshots(
+        &self,
+        snapshots: Vec<(DatasetId, String, u64)>,
+        created_at: u64,
+        is_readonly: bool,
+    ) -> Result<Vec<Dataset>, FsError> {
         if self.db.is_read_only() {
             return Err(FsError::ReadOnlyFilesystem);
         }
 
         let mut registry = self.registry.write().await;
 
-        let source = registry
-            .get_by_id(source_id)
-            .ok_or(FsError::NotFound)?
-            .clone();
+        let mut updated = registry.clone();
+        let mut created = Vec::with_capacity(snapshots.len());
+        for (source_id, snapshot_name, snapshot_root_inode) in snapshots {
+            let source = updated
+                .get_by_id(source_id)
+                .ok_or(FsError::NotFound)?
+                .clone();
+
+            let id = updated.allocate_id();
+            let snapshot = Dataset::new_snapshot(
+                id,
+                snapshot_name,
+                &source,
+                snapshot_root_inode,
+                created_at,
+                is_readonly,
+            );
+
+            updated.add_dataset(snapshot.clone()).map_err(|e| {
+                tracing::warn!("Failed to add snapshot to registry: {}", e);
+                FsError::Exists
+            })?;
+            created.push(snapshot);
+        }
 
-        let id = registry.allocate_id();
-        let snapshot = Dataset::new_snapshot(
-            id,
-            snapshot_name,
-            &source,
-            snapshot_root_inode,
-            created_at,
-            is_readonly,
-        );
-
-        registry.add_dataset(snapshot.clone()).map_err(|e| {
-            tracing::warn!("Failed to add snapshot to registry: {}", e);
+        // Persist the registry
+        self.persist_registry(&updated).await?;
+        *registry = updated;
+
+        Ok(created)
+    }
+
+    /// Delete dataset or snapshot `id`. Held snapshots are refused. The
+    /// updated registry is written to `txn` like `prepare_root_swap`. The
+    /// key of a dataset that has its own is destroyed once the deletion is
+    /// committed, so its chunks can no longer be decrypted even before they
+    /// are reclaimed. Earlier versions of the registry remain in storage
+    /// until compaction drops them, and in checkpoints taken before.
+    pub async fn prepare_delete(
+        &self,
+        txn: &mut EncryptedTransaction,
+        id: DatasetId,
+    ) -> Result<RegistryUpdate<'_>, FsError> {
+        if self.db.is_read_only() {
+            return Err(FsError::ReadOnlyFilesystem);
+        }
+
+        let registry = self.registry.write().await;
+
+        // Snapshots and child datasets go with their dataset only when
+        // destroyed first, and children encrypted with this dataset's key
+        // would become unreadable
+        if registry.datasets.values().any(|d| {
+            d.id != id && (d.parent_id == Some(id) || registry.encryption_dataset(d.id) == Some(id))
+        }) {
+            return Err(FsError::NotEmpty);
+        }
+
+        if registry.get_by_id(id).is_some_and(|d| !d.holds.is_empty()) {
+            return Err(FsError::OperationNotPermitted);
+        }
+
+        let mut updated = registry.clone();
+        let dataset = updated.remove_dataset(id).map_err(|e| {
+            tracing::warn!("Failed to remove dataset: {}", e);
+            FsError::NotFound
+        })?;
+
+        let mut update = self.prepare_update(txn, registry, updated, dataset)?;
+        if update.dataset.wrapped_key.is_some() {
+            update.forget_key = true;
+        }
+        Ok(update)
+    }
+
+    /// Point dataset `id` at the new root directory `root_inode`. The
+    /// updated registry is written to `txn`; the returned `RegistryUpdate`
+    /// keeps other registry changes out until it is committed, once `txn`
+    /// has.
+    pub async fn prepare_root_swap(
+        &self,
+        txn: &mut EncryptedTransaction,
+        id: DatasetId,
+        root_inode: u64,
+    ) -> Result<RegistryUpdate<'_>, FsError> {
+        if self.db.is_read_only() {
+            return Err(FsError::ReadOnlyFilesystem);
+        }
+
+        let registry = self.registry.write().await;
+
+        let mut updated = registry.clone();
+        let dataset = updated.datasets.get_mut(&id).ok_or(FsError::NotFound)?;
+        dataset.root_inode = root_inode;
+        dataset.generation += 1;
+        let dataset = dataset.clone();
+
+        self.prepare_update(txn, registry, updated, dataset)
+    }
+
+    /// Rename dataset or snapshot `id` to `name` and nest it in `parent_id`,
+    /// renaming the datasets named after it along. Written to `txn` like
+    /// `prepare_root_swap`.
+    pub async fn prepare_rename(
+        &self,
+        txn: &mut EncryptedTransaction,
+        id: DatasetId,
+        name: String,
+        parent_id: Option<DatasetId>,
+    ) -> Result<RegistryUpdate<'_>, FsError> {
+        if self.db.is_read_only() {
+            return Err(FsError::ReadOnlyFilesystem);
+        }
+
+        let registry = self.registry.write().await;
+        if registry.get_by_id(id).is_none() {
+            return Err(FsError::NotFound);
+        }
+
+        let mut updated = registry.clone();
+        updated.rename_dataset(id, name, parent_id).map_err(|e| {
+            tracing::warn!("Failed to rename dataset: {}", e);
             FsError::Exists
         })?;
+        let dataset = updated.datasets[&id].clone();
+
+        self.prepare_update(txn, registry, updated, dataset)
+    }
+
+    fn prepare_update<'a>(
+        &'a self,
+        txn: &mut EncryptedTransaction,
+        registry: RwLockWriteGuard<'a, DatasetRegistry>,
+        updated: DatasetRegistry,
+        dataset: Dataset,
+    ) -> Result<RegistryUpdate<'a>, FsError> {
+        let serialized = updated.encode().map_err(|e| {
+            tracing::error!("Failed to serialize dataset registry: {:?}", e);
+            FsError::IoError
+        })?;
+        txn.put_bytes(&KeyCodec::dataset_registry_key(), Bytes::from(serialized));
+
+        Ok(RegistryUpdate {
+            db: &self.db,
+            registry,
+            updated,
+            dataset,
+            forget_key: false,
+        })
+    }
+
+    /// Get dataset by ID
+    pub async fn get_by_id(&self, id: DatasetId) -> Option<Dataset> {
+        let registry = self.registry.read().await;
+        registry.get_by_id(id).cloned()
+    }
+
+    /// Dataset rooted at `root_inode`, if any
+    pub async fn get_by_root_inode(&self, root_inode: u64) -> Option<Dataset> {
+        let registry = self.registry.read().await;
+        registry.get_by_root_inode(root_inode).cloned()
+    }
+
+    /// Datasets and snapshots below dataset `id`, deepest first
+    pub async fn descendants(&self, id: DatasetId) -> Vec<Dataset> {
+        let registry = self.registry.read().await;
+        registry.descendants(id).into_iter().cloned().collect()
+    }
+
+    /// Whether any dataset besides the root one holds files
+    pub async fn has_child_datasets(&self) -> bool {
+        self.registry.read().await.has_child_datasets()
+    }
+
+    /// Quota of dataset `id`; empty if the dataset does not exist
+    pub async fn get_quota(&self, id: DatasetId) -> DatasetQuota {
+        let registry = self.registry.read().await;
+        registry.get_by_id(id).map(|d| d.quota).unwrap_or_default()
+    }
+
+    /// Replace the quota of dataset `id`. Snapshots cannot have one.
+    pub async fn set_quota(&self, id: DatasetId, quota: DatasetQuota) -> Result<Dataset, FsError> {
+        if self.db.is_read_only() {
+            return Err(FsError::ReadOnlyFilesystem);
+        }
+
+        let mut registry = self.registry.write().await;
+
+        let dataset = registry.datasets.get_mut(&id).ok_or(FsError::NotFound)?;
+        if dataset.is_snapshot {
+            return Err(FsError::InvalidArgument);
+        }
+        dataset.quota = quota;
+        dataset.generation += 1;
+        let dataset = dataset.clone();
 
         // Persist the registry
         self.persist_registry(&registry).await?;
 
-        Ok(snapshot)
+        Ok(dataset)
     }
 
-    /// Delete a dataset or snapshot
-    pub async fn delete_dataset(&self, id: DatasetId) -> Result<Dataset, FsError> {
+    /// Give dataset `id` the UUID of the snapshot it was received from, so
+    /// later incremental streams can find their base
+    pub async fn set_uuid(&self, id: DatasetId, uuid: Uuid) -> Result<Dataset, FsError> {
         if self.db.is_read_only() {
             return Err(FsError::ReadOnlyFilesystem);
         }
 
         let mut registry = self.registry.write().await;
 
-        let dataset = registry.remove_dataset(id).map_err(|e| {
-            tracing::warn!("Failed to remove dataset: {}", e);
-            FsError::NotFound
-        })?;
+        let dataset = registry.datasets.get_mut(&id).ok_or(FsError::NotFound)?;
+        dataset.uuid = uuid;
+        dataset.generation += 1;
+        let dataset = dataset.clone();
 
         // Persist the registry
         self.persist_registry(&registry).await?;
@@ -152,10 +373,128 @@ impl DatasetStore {
         Ok(dataset)
     }
 
-    /// Get dataset by ID
-    pub async fn get_by_id(&self, id: DatasetId) -> Option<Dataset> {
+    /// Put hold `tag` on snapshot `id`
+    pub async fn hold_snapshot(&self, id: DatasetId, tag: &str) -> Result<Dataset, FsError> {
+        if self.db.is_read_only() {
+            return Err(FsError::ReadOnlyFilesystem);
+        }
+
+        let mut registry = self.registry.write().await;
+
+        let dataset = registry.datasets.get_mut(&id).ok_or(FsError::NotFound)?;
+        if !dataset.is_snapshot {
+            return Err(FsError::InvalidArgument);
+        }
+        if !dataset.holds.insert(tag.to_string()) {
+            return Err(FsError::Exists);
+        }
+        dataset.generation += 1;
+        let dataset = dataset.clone();
+
+        // Persist the registry
+        self.persist_registry(&registry).await?;
+
+        Ok(dataset)
+    }
+
+    /// Release hold `tag` on snapshot `id`
+    pub async fn release_snapshot(&self, id: DatasetId, tag: &str) -> Result<Dataset, FsError> {
+        if self.db.is_read_only() {
+            return Err(FsError::ReadOnlyFilesystem);
+        }
+
+        let mut registry = self.registry.write().await;
+
+        let dataset = registry.datasets.get_mut(&id).ok_or(FsError::NotFound)?;
+        if !dataset.holds.remove(tag) {
+            return Err(FsError::NotFound);
+        }
+        dataset.generation += 1;
+        let dataset = dataset.clone();
+
+        // Persist the registry
+        self.persist_registry(&registry).await?;
+
+        Ok(dataset)
+    }
+
+    /// Properties in effect in dataset `id`, set on it or inherited
+    pub async fn effective_properties(&self, id: DatasetId) -> EffectiveProperties {
+        properties::effective_properties(&*self.registry.read().await, id)
+    }
+
+    /// Every property of dataset `id`, with where its value comes from
+    pub async fn property_values(&self, id: DatasetId) -> Result<Vec<PropertyValue>, FsError> {
         let registry = self.registry.read().await;
-        registry.get_by_id(id).cloned()
+        properties::property_values(&registry, id, self.db.default_compression())
+    }
+
+    /// Set `property` on dataset `id`, or unset it for `None` so that it is
+    /// inherited again
+    pub async fn set_property(
+        &self,
+        id: DatasetId,
+        property: Property,
+        value: Option<&str>,
+    ) -> Result<Dataset, FsError> {
+        if self.db.is_read_only() {
+            return Err(FsError::ReadOnlyFilesystem);
+        }
+
+        let mut registry = self.registry.write().await;
+
+        let dataset = registry.datasets.get_mut(&id).ok_or(FsError::NotFound)?;
+        properties::set_property(dataset, property, value)?;
+        dataset.generation += 1;
+        let dataset = dataset.clone();
+
+        // Persist the registry
+        self.persist_registry(&registry).await?;
+
+        Ok(dataset)
+    }
+
+    /// Rewrap the keys of encrypted datasets with the current key generation,
+    /// so that older generations can be retired. Returns the number of keys
+    /// rewrapped.
+    pub async fn rewrap_keys(&self) -> Result<usize, FsError> {
+        if self.db.is_read_only() {
+            return Err(FsError::ReadOnlyFilesystem);
+        }
+
+        let mut registry = self.registry.write().await;
+        let mut rewrapped = 0;
+        for dataset in registry.datasets.values_mut() {
+            let Some(wrapped_key) = &dataset.wrapped_key else {
+                continue;
+            };
+            let new_key = self
+                .db
+                .rewrap_dataset_key(dataset.id, wrapped_key)
+                .map_err(|e| {
+                    tracing::error!("Failed to rewrap key of dataset {}: {:?}", dataset.id, e);
+                    FsError::InvalidData
+                })?;
+            if let Some(new_key) = new_key {
+                dataset.wrapped_key = Some(new_key);
+                rewrapped += 1;
+            }
+        }
+
+        if rewrapped > 0 {
+            self.persist_registry(&registry).await?;
+        }
+        Ok(rewrapped)
+    }
+
+    /// Whether any dataset has a key of its own
+    pub async fn has_dataset_keys(&self) -> bool {
+        self.registry.read().await.has_dataset_keys()
+    }
+
+    /// Dataset whose key encrypts data written in dataset `id`
+    pub async fn encryption_dataset(&self, id: DatasetId) -> Option<DatasetId> {
+        self.registry.read().await.encryption_dataset(id)
     }
 
     /// Get dataset by name
@@ -206,7 +545,7 @@ impl DatasetStore {
     /// Persist the registry to the database
     async fn persist_registry(&self, registry: &DatasetRegistry) -> Result<(), FsError> {
         let registry_key = KeyCodec::dataset_registry_key();
-        let serialized = bincode::serialize(registry).map_err(|e| {
+        let serialized = registry.encode().map_err(|e| {
             tracing::error!("Failed to serialize dataset registry: {:?}", e);
             FsError::IoError
         })?;
@@ -226,10 +565,41 @@ impl DatasetStore {
                 FsError::IoError
             })?;
 
+        // Datasets may have changed the compression they inherit
+        self.db
+            .set_dataset_compression(properties::compression_overrides(registry));
+
         Ok(())
     }
 }
 
+/// A registry change of `DatasetStore::prepare_root_swap`,
+/// `DatasetStore::prepare_rename` or `DatasetStore::prepare_delete`, written
+/// but not yet applied in memory
+pub struct RegistryUpdate<'a> {
+    db: &'a EncryptedDb,
+    registry: RwLockWriteGuard<'a, DatasetRegistry>,
+    updated: DatasetRegistry,
+    /// The dataset as it is after the change; the deleted one for a deletion
+    dataset: Dataset,
+    /// Destroy the key of the deleted dataset
+    forget_key: bool,
+}
+
+impl RegistryUpdate<'_> {
+    /// Apply the change once its transaction has committed
+    pub fn commit(mut self) -> Dataset {
+        *self.registry = self.updated;
+        // Datasets may have changed the compression they inherit
+        self.db
+            .set_dataset_compression(properties::compression_overrides(&self.registry));
+        if self.forget_key {
+            self.db.forget_dataset_key(self.dataset.id);
+        }
+        self.dataset
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -250,7 +620,7 @@ mod tests {
 
         // Create a new dataset
         let subvol = store
-            .create_dataset("data".to_string(), 100, 2000, false)
+            .create_dataset("data".to_string(), None, 100, 2000, false, false)
             .await
             .unwrap();
 
@@ -108,6 +108,14 @@ fn decode_dir_scan_value(data: &[u8]) -> Result<(Vec<u8>, DirScanValue), FsError
     Err(FsError::InvalidData)
 }
 
+/// Form names are compared in, in case-insensitive datasets
+pub fn fold_case(name: &[u8]) -> Vec<u8> {
+    match std::str::from_utf8(name) {
+        Ok(name) => name.to_lowercase().into_bytes(),
+        Err(_) => name.to_ascii_lowercase(),
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct DirEntryInfo {
     pub name: Vec<u8>,
@@ -366,6 +374,15 @@ impl DirectoryStore {
 
         let scan_key = KeyCodec::dir_scan_key(dir_id, cookie);
         txn.put_bytes(&scan_key, encode_dir_scan_value(name, &scan_value));
+
+        // Names already in folded form are found by their entry
+        let folded = fold_case(name);
+        if folded != name {
+            txn.put_bytes(
+                &KeyCodec::case_fold_key(dir_id, &folded, name),
+                Bytes::new(),
+            );
+        }
         
         // Invalidate cache for this entry (will be updated after transaction commits)
         self.invalidate_entry(dir_id, name);
@@ -383,6 +400,11 @@ impl DirectoryStore {
 
         let scan_key = KeyCodec::dir_scan_key(dir_id, cookie);
         txn.delete_bytes(&scan_key);
+
+        let folded = fold_case(name);
+        if folded != name {
+            txn.delete_bytes(&KeyCodec::case_fold_key(dir_id, &folded, name));
+        }
         
         // Invalidate cache for this entry
         self.invalidate_entry(dir_id, name);
@@ -451,6 +473,88 @@ impl DirectoryStore {
         Ok(())
     }
 
+    /// Entry of directory `dir_id` whose name differs from `name` at most in
+    /// case, with its name
+    pub async fn find_case_variant(
+        &self,
+        dir_id: InodeId,
+        name: &[u8],
+    ) -> Result<Option<(Vec<u8>, InodeId)>, FsError> {
+        let folded = fold_case(name);
+        match self.get(dir_id, &folded).await {
+            Ok(id) => return Ok(Some((folded, id))),
+            Err(FsError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+
+        let (start, end) = KeyCodec::case_fold_range(dir_id, &folded);
+        let iter = self
+            .db
+            .scan(start..end)
+            .await
+            .map_err(|_| FsError::IoError)?;
+        futures::pin_mut!(iter);
+        while let Some(result) = iter.next().await {
+            let (key, _) = result.map_err(|_| FsError::IoError)?;
+            let variant = KeyCodec::parse_case_fold_key(&key).ok_or(FsError::InvalidData)?;
+            match self.get(dir_id, variant).await {
+                Ok(id) => return Ok(Some((variant.to_vec(), id))),
+                Err(FsError::NotFound) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Record that directory `dir_id` holds link `name` to `inode_id`. An
+    /// inode with several links has no parent: this is how the directories
+    /// it is reached through are found.
+    pub fn add_link_dir(
+        &self,
+        txn: &mut EncryptedTransaction,
+        inode_id: InodeId,
+        dir_id: InodeId,
+        name: &[u8],
+    ) {
+        txn.put_bytes(
+            &KeyCodec::link_dir_key(inode_id, dir_id, name),
+            Bytes::new(),
+        );
+    }
+
+    /// Forget a link recorded by `add_link_dir`
+    pub fn remove_link_dir(
+        &self,
+        txn: &mut EncryptedTransaction,
+        inode_id: InodeId,
+        dir_id: InodeId,
+        name: &[u8],
+    ) {
+        txn.delete_bytes(&KeyCodec::link_dir_key(inode_id, dir_id, name));
+    }
+
+    /// Directories holding a link to `inode_id`, as recorded by
+    /// `add_link_dir`
+    pub async fn link_dirs(&self, inode_id: InodeId) -> Result<Vec<InodeId>, FsError> {
+        let (start, end) = KeyCodec::link_dir_range(inode_id);
+        let iter = self
+            .db
+            .scan(start..end)
+            .await
+            .map_err(|_| FsError::IoError)?;
+        futures::pin_mut!(iter);
+
+        let mut dirs = Vec::new();
+        while let Some(result) = iter.next().await {
+            let (key, _) = result.map_err(|_| FsError::IoError)?;
+            let (_, dir_id, _) = KeyCodec::parse_link_dir_key(&key).ok_or(FsError::InvalidData)?;
+            if !dirs.contains(&dir_id) {
+                dirs.push(dir_id);
+            }
+        }
+        Ok(dirs)
+    }
+
     /// Convert a directory scan entry to a Reference (for hardlinks).
     /// Used when nlink goes from 1 to 2+.
     pub async fn convert_to_reference(
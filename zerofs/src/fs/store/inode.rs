@@ -2,6 +2,7 @@ use crate::encryption::{EncryptedDb, EncryptedTransaction};
 use crate::fs::errors::FsError;
 use crate::fs::inode::{Inode, InodeId};
 use crate::fs::key_codec::KeyCodec;
+use crate::fs::store::LazyDirStore;
 use crate::metadata_cache::MetadataCache;
 use bytes::Bytes;
 use std::sync::Arc;
@@ -14,6 +15,7 @@ pub struct InodeStore {
     db: Arc<EncryptedDb>,
     next_id: Arc<AtomicU64>,
     metadata_cache: Option<Arc<MetadataCache>>,
+    lazy_dirs: Option<LazyDirStore>,
 }
 
 impl InodeStore {
@@ -22,6 +24,7 @@ impl InodeStore {
             db,
             next_id: Arc::new(AtomicU64::new(initial_next_id)),
             metadata_cache: None,
+            lazy_dirs: None,
         }
     }
 
@@ -34,9 +37,17 @@ impl InodeStore {
             db,
             next_id: Arc::new(AtomicU64::new(initial_next_id)),
             metadata_cache: Some(metadata_cache),
+            lazy_dirs: None,
         }
     }
 
+    /// Resolve the aliases `lazy_dirs` hands out for entries of lazy
+    /// directories
+    pub fn with_lazy_dirs(mut self, lazy_dirs: LazyDirStore) -> Self {
+        self.lazy_dirs = Some(lazy_dirs);
+        self
+    }
+
     pub fn allocate(&self) -> InodeId {
         self.next_id.fetch_add(1, Ordering::SeqCst)
     }
@@ -46,6 +57,13 @@ impl InodeStore {
     }
 
     pub async fn get(&self, id: InodeId) -> Result<Inode, FsError> {
+        if let Some(lazy_dirs) = &self.lazy_dirs
+            && let Some((alias, target)) = lazy_dirs.resolve_alias(id).await?
+        {
+            let inode = Box::pin(self.get(target)).await?;
+            return Ok(alias.view(target, inode));
+        }
+
         // Check metadata cache first
         if let Some(ref cache) = self.metadata_cache {
             if let Some(cached_inode) = cache.get_inode(id) {
@@ -123,9 +141,11 @@ impl InodeStore {
         Ok(())
     }
 
+    /// Delete an inode along with its extended attributes
     pub fn delete(&self, txn: &mut EncryptedTransaction, id: InodeId) {
         let key = KeyCodec::inode_key(id);
         txn.delete_bytes(&key);
+        txn.delete_bytes(&KeyCodec::xattr_key(id));
         
         // Invalidate cache
         if let Some(ref cache) = self.metadata_cache {
@@ -0,0 +1,322 @@
+use crate::encryption::{EncryptedDb, EncryptedTransaction};
+use crate::fs::dataset::DatasetId;
+use crate::fs::errors::FsError;
+use crate::fs::inode::{Inode, InodeId};
+use crate::fs::key_codec::{KeyCodec, KeyPrefix};
+use crate::fs::store::DirectoryStore;
+use futures::StreamExt;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use tokio::sync::{RwLockReadGuard, RwLockWriteGuard};
+
+#[derive(Default)]
+struct LazyDirs {
+    /// Lazy directory -> the directory it was cloned from
+    origins: HashMap<InodeId, InodeId>,
+    /// Origin directory -> lazy directories still reading through it
+    dependents: HashMap<InodeId, HashSet<InodeId>>,
+    /// Entries of lazy directories looked up before being copied
+    aliases: HashMap<InodeId, Alias>,
+    /// Directory -> IDs handed out for its entries, by name. Kept for the
+    /// entries copied since, until the directory is no longer lazy.
+    alias_ids: HashMap<InodeId, HashMap<Vec<u8>, InodeId>>,
+}
+
+/// Entry `name` of lazy directory `dir`, looked up through it before being
+/// copied. It stands for the entry of that name in the directory `dir`
+/// reads through, seen as a child of `dir`, and the copy takes its ID.
+#[derive(Debug, Clone)]
+pub struct Alias {
+    pub dir: InodeId,
+    pub name: Vec<u8>,
+    /// Dataset of `dir`, which the copy belongs to
+    pub dataset: DatasetId,
+}
+
+impl Alias {
+    /// Inode `target` that the alias stands for, as it would be copied
+    pub fn view(&self, target: InodeId, mut inode: Inode) -> Inode {
+        match &mut inode {
+            Inode::File(file) => {
+                file.chunk_map = Some(file.chunk_map_id(target));
+                file.parent = Some(self.dir);
+                file.name = Some(self.name.clone());
+                file.nlink = 1;
+                file.dataset = self.dataset;
+            }
+            Inode::Directory(dir) => {
+                dir.parent = self.dir;
+                dir.name = Some(self.name.clone());
+            }
+            Inode::Symlink(link) => {
+                link.parent = Some(self.dir);
+                link.name = Some(self.name.clone());
+                link.nlink = 1;
+            }
+            Inode::Fifo(special)
+            | Inode::Socket(special)
+            | Inode::CharDevice(special)
+            | Inode::BlockDevice(special) => {
+                special.parent = Some(self.dir);
+                special.name = Some(self.name.clone());
+                special.nlink = 1;
+            }
+        }
+        inode
+    }
+}
+
+/// Cloned directories whose entries have not been copied from their origin.
+///
+/// Cloning a directory only records the new directory here. Until its
+/// entries are copied, reads go through the origin (`entries_dir`),
+/// whose subtree must keep the state it had when the clone was taken: writers
+/// call `clone::prepare_write` before modifying either side, which copies
+/// the entries (`clone::materialize_dir`) one level at a time, subdirectories
+/// becoming lazy directories of their own.
+///
+/// Entries looked up in a lazy directory get IDs of their own, aliases,
+/// rather than those of the origin's entries: a write through one must go to
+/// a copy. The inode and attribute stores resolve an alias to the entry it
+/// stands for, and copying the entry gives the copy the alias's ID.
+///
+/// The set is kept in memory and loaded at startup. Changes are written to a
+/// transaction and applied in memory once it has committed. Aliases are
+/// only kept in memory: handles to entries never copied go stale on restart.
+#[derive(Clone)]
+pub struct LazyDirStore {
+    dirs: Arc<RwLock<LazyDirs>>,
+    gate: Arc<tokio::sync::RwLock<()>>,
+    directory_store: DirectoryStore,
+}
+
+impl LazyDirStore {
+    pub async fn new(
+        db: Arc<EncryptedDb>,
+        directory_store: DirectoryStore,
+    ) -> Result<Self, FsError> {
+        let (start, end) = KeyCodec::prefix_range(KeyPrefix::LazyDir);
+        let iter = db.scan(start..end).await.map_err(|_| FsError::IoError)?;
+        futures::pin_mut!(iter);
+
+        let mut dirs = LazyDirs::default();
+        while let Some(result) = iter.next().await {
+            let (key, value) = result.map_err(|_| FsError::IoError)?;
+            let dir_id = KeyCodec::parse_lazy_dir_key(&key).ok_or(FsError::InvalidData)?;
+            let origin = KeyCodec::decode_counter(&value)?;
+            dirs.insert(dir_id, origin);
+        }
+
+        Ok(Self {
+            dirs: Arc::new(RwLock::new(dirs)),
+            gate: Arc::new(tokio::sync::RwLock::new(())),
+            directory_store,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dirs.read().unwrap().origins.is_empty()
+    }
+
+    /// Some lazy directory, if there is any
+    pub fn any(&self) -> Option<InodeId> {
+        self.dirs.read().unwrap().origins.keys().next().copied()
+    }
+
+    /// The directory a lazy directory was cloned from
+    pub fn origin(&self, dir_id: InodeId) -> Option<InodeId> {
+        self.dirs.read().unwrap().origins.get(&dir_id).copied()
+    }
+
+    /// Lazy directories whose entries are still those of `origin`
+    pub fn dependents(&self, origin: InodeId) -> Vec<InodeId> {
+        self.dirs
+            .read()
+            .unwrap()
+            .dependents
+            .get(&origin)
+            .map(|dirs| dirs.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// The directory holding the entries of `dir_id`: that of its origin
+    /// while it is a lazy directory, or that of the directory it stands for
+    /// while it is an alias
+    pub async fn entries_dir(&self, mut dir_id: InodeId) -> Result<InodeId, FsError> {
+        loop {
+            if let Some(origin) = self.origin(dir_id) {
+                dir_id = origin;
+                continue;
+            }
+            match Box::pin(self.resolve_alias(dir_id)).await? {
+                Some((_, target)) => dir_id = target,
+                None => return Ok(dir_id),
+            }
+        }
+    }
+
+    /// The alias `id` is, with the inode it stands for, or `None` if `id`
+    /// is no alias
+    pub async fn resolve_alias(&self, id: InodeId) -> Result<Option<(Alias, InodeId)>, FsError> {
+        let alias = self.dirs.read().unwrap().aliases.get(&id).cloned();
+        let Some(alias) = alias else {
+            return Ok(None);
+        };
+        let entries_dir = self.entries_dir(alias.dir).await?;
+        let target = self.directory_store.get(entries_dir, &alias.name).await?;
+        Ok(Some((alias, target)))
+    }
+
+    /// ID for entry `name` of directory `dir_id`, of dataset `dataset`,
+    /// read through the directory's origin: the same for every lookup until
+    /// the directory is copied, taken from `allocate` the first time.
+    /// `None` once the directory is no longer lazy.
+    pub fn alias(
+        &self,
+        dir_id: InodeId,
+        name: &[u8],
+        dataset: DatasetId,
+        allocate: impl FnOnce() -> InodeId,
+    ) -> Option<InodeId> {
+        let mut dirs = self.dirs.write().unwrap();
+        if !dirs.origins.contains_key(&dir_id) && !dirs.aliases.contains_key(&dir_id) {
+            return None;
+        }
+        if let Some(&id) = dirs.alias_ids.get(&dir_id).and_then(|ids| ids.get(name)) {
+            return Some(id);
+        }
+
+        let id = allocate();
+        dirs.aliases.insert(
+            id,
+            Alias {
+                dir: dir_id,
+                name: name.to_vec(),
+                dataset,
+            },
+        );
+        dirs.alias_ids
+            .entry(dir_id)
+            .or_default()
+            .insert(name.to_vec(), id);
+        Some(id)
+    }
+
+    /// Apply the copy of the entry alias `id` stands for, saved as `id`,
+    /// once its transaction has committed
+    pub fn commit_copy(&self, id: InodeId) {
+        self.dirs.write().unwrap().aliases.remove(&id);
+    }
+
+    pub fn add(&self, txn: &mut EncryptedTransaction, dir_id: InodeId, origin: InodeId) {
+        txn.put_bytes(
+            &KeyCodec::lazy_dir_key(dir_id),
+            KeyCodec::encode_counter(origin),
+        );
+    }
+
+    pub fn remove(&self, txn: &mut EncryptedTransaction, dir_id: InodeId) {
+        txn.delete_bytes(&KeyCodec::lazy_dir_key(dir_id));
+    }
+
+    /// Apply an `add` once its transaction has committed
+    pub fn commit_add(&self, dir_id: InodeId, origin: InodeId) {
+        self.dirs.write().unwrap().insert(dir_id, origin);
+    }
+
+    /// Apply a `remove` once its transaction has committed
+    pub fn commit_remove(&self, dir_id: InodeId) {
+        self.dirs.write().unwrap().remove(dir_id);
+    }
+
+    /// Keep directories from being cloned while the caller modifies the tree.
+    /// Held from `clone::prepare_write` until the modification has committed.
+    pub async fn hold(&self) -> RwLockReadGuard<'_, ()> {
+        self.gate.read().await
+    }
+
+    /// Wait for in-flight modifications and keep new ones out while a
+    /// directory clone is taken, or while lazy directories are read through
+    /// their origins.
+    pub async fn hold_exclusive(&self) -> RwLockWriteGuard<'_, ()> {
+        self.gate.write().await
+    }
+}
+
+impl LazyDirs {
+    fn insert(&mut self, dir_id: InodeId, origin: InodeId) {
+        self.origins.insert(dir_id, origin);
+        self.dependents.entry(origin).or_default().insert(dir_id);
+    }
+
+    fn remove(&mut self, dir_id: InodeId) {
+        let Some(origin) = self.origins.remove(&dir_id) else {
+            return;
+        };
+        self.remove_aliases(dir_id);
+        if let Some(dirs) = self.dependents.get_mut(&origin) {
+            dirs.remove(&dir_id);
+            if dirs.is_empty() {
+                self.dependents.remove(&origin);
+            }
+        }
+    }
+
+    /// Drop the aliases of entries of `dir_id` that were not copied, and
+    /// those below them
+    fn remove_aliases(&mut self, dir_id: InodeId) {
+        let mut pending = vec![dir_id];
+        while let Some(dir_id) = pending.pop() {
+            for (_, id) in self.alias_ids.remove(&dir_id).unwrap_or_default() {
+                if self.aliases.remove(&id).is_some() {
+                    pending.push(id);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::ZeroFS;
+
+    #[tokio::test]
+    async fn test_lazy_dirs_persist() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let store = LazyDirStore::new(fs.db.clone(), fs.directory_store.clone())
+            .await
+            .unwrap();
+        assert!(store.is_empty());
+
+        let mut txn = fs.db.new_transaction().unwrap();
+        store.add(&mut txn, 10, 5);
+        store.add(&mut txn, 11, 5);
+        fs.db
+            .write_with_options(
+                txn,
+                &slatedb::config::WriteOptions {
+                    await_durable: false,
+                },
+            )
+            .await
+            .unwrap();
+        store.commit_add(10, 5);
+        store.commit_add(11, 5);
+
+        let mut dependents = store.dependents(5);
+        dependents.sort();
+        assert_eq!(dependents, vec![10, 11]);
+        assert_eq!(store.origin(10), Some(5));
+
+        let reloaded = LazyDirStore::new(fs.db.clone(), fs.directory_store.clone())
+            .await
+            .unwrap();
+        assert_eq!(reloaded.origin(11), Some(5));
+
+        store.commit_remove(10);
+        store.commit_remove(11);
+        assert!(store.is_empty());
+        assert!(store.dependents(5).is_empty());
+    }
+}
@@ -2,10 +2,16 @@ pub mod chunk;
 pub mod dataset;
 pub mod directory;
 pub mod inode;
+pub mod lazy_dir;
+pub mod quota;
 pub mod tombstone;
+pub mod xattr;
 
-pub use chunk::ChunkStore;
+pub use chunk::{ChunkLayout, ChunkStore};
 pub use dataset::DatasetStore;
 pub use directory::DirectoryStore;
 pub use inode::InodeStore;
+pub use lazy_dir::LazyDirStore;
+pub use quota::QuotaStore;
 pub use tombstone::TombstoneStore;
+pub use xattr::XattrStore;
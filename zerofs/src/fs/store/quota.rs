@@ -0,0 +1,126 @@
+use crate::encryption::EncryptedDb;
+use crate::fs::errors::FsError;
+use crate::fs::key_codec::KeyCodec;
+use crate::fs::quota::{Owner, OwnerQuota};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Limits on the usage of uids and gids, stored as a single value
+#[derive(Clone)]
+pub struct QuotaStore {
+    db: Arc<EncryptedDb>,
+    quotas: Arc<RwLock<BTreeMap<Owner, OwnerQuota>>>,
+}
+
+impl QuotaStore {
+    pub async fn new(db: Arc<EncryptedDb>) -> Result<Self, FsError> {
+        let quotas = match db
+            .get_bytes(&KeyCodec::system_quotas_key())
+            .await
+            .map_err(|_| FsError::IoError)?
+        {
+            Some(data) => bincode::deserialize(&data).map_err(|e| {
+                tracing::warn!("Failed to deserialize quotas: {:?}", e);
+                FsError::InvalidData
+            })?,
+            None => BTreeMap::new(),
+        };
+
+        Ok(Self {
+            db,
+            quotas: Arc::new(RwLock::new(quotas)),
+        })
+    }
+
+    /// Limits of `owner`, empty if it has none
+    pub async fn get(&self, owner: Owner) -> OwnerQuota {
+        self.quotas
+            .read()
+            .await
+            .get(&owner)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Limits of every owner that has any
+    pub async fn list(&self) -> BTreeMap<Owner, OwnerQuota> {
+        self.quotas.read().await.clone()
+    }
+
+    /// Set the limits of `owner`. An empty quota removes them.
+    pub async fn set(&self, owner: Owner, quota: OwnerQuota) -> Result<(), FsError> {
+        if self.db.is_read_only() {
+            return Err(FsError::ReadOnlyFilesystem);
+        }
+
+        let mut quotas = self.quotas.write().await;
+        let mut updated = quotas.clone();
+        if quota.is_empty() {
+            updated.remove(&owner);
+        } else {
+            updated.insert(owner, quota);
+        }
+
+        self.db
+            .put_with_options(
+                &KeyCodec::system_quotas_key(),
+                &bincode::serialize(&updated)?,
+                &slatedb::config::PutOptions::default(),
+                &slatedb::config::WriteOptions {
+                    await_durable: false,
+                },
+            )
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to persist quotas: {:?}", e);
+                FsError::IoError
+            })?;
+
+        *quotas = updated;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::ZeroFS;
+
+    #[tokio::test]
+    async fn test_quotas_persist() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let store = QuotaStore::new(fs.db.clone()).await.unwrap();
+        assert!(store.get(Owner::User(1000)).await.is_empty());
+
+        let quota = OwnerQuota {
+            bytes: Some(1 << 20),
+            inodes: None,
+        };
+        store.set(Owner::User(1000), quota).await.unwrap();
+        store
+            .set(
+                Owner::Group(1000),
+                OwnerQuota {
+                    bytes: None,
+                    inodes: Some(10),
+                },
+            )
+            .await
+            .unwrap();
+
+        let reloaded = QuotaStore::new(fs.db.clone()).await.unwrap();
+        assert_eq!(reloaded.get(Owner::User(1000)).await, quota);
+        assert_eq!(reloaded.list().await.len(), 2);
+
+        reloaded
+            .set(Owner::Group(1000), OwnerQuota::default())
+            .await
+            .unwrap();
+        let reloaded = QuotaStore::new(fs.db.clone()).await.unwrap();
+        assert_eq!(
+            reloaded.list().await.into_keys().collect::<Vec<_>>(),
+            vec![Owner::User(1000)]
+        );
+    }
+}
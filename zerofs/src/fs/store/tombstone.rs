@@ -13,6 +13,7 @@ pub struct TombstoneEntry {
     pub key: Bytes,
     pub inode_id: InodeId,
     pub remaining_size: u64,
+    pub chunk_size: u32,
 }
 
 #[derive(Clone)]
@@ -25,17 +26,29 @@ impl TombstoneStore {
         Self { db }
     }
 
-    pub fn add(&self, txn: &mut EncryptedTransaction, inode_id: InodeId, size: u64) {
+    pub fn add(
+        &self,
+        txn: &mut EncryptedTransaction,
+        inode_id: InodeId,
+        size: u64,
+        chunk_size: u32,
+    ) {
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .map(|d| d.as_secs())
             .unwrap_or(0);
         let key = KeyCodec::tombstone_key(timestamp, inode_id);
-        txn.put_bytes(&key, KeyCodec::encode_tombstone_size(size));
+        txn.put_bytes(&key, KeyCodec::encode_tombstone(size, chunk_size));
     }
 
-    pub fn update(&self, txn: &mut EncryptedTransaction, key: &Bytes, new_size: u64) {
-        txn.put_bytes(key, KeyCodec::encode_tombstone_size(new_size));
+    pub fn update(
+        &self,
+        txn: &mut EncryptedTransaction,
+        key: &Bytes,
+        new_size: u64,
+        chunk_size: u32,
+    ) {
+        txn.put_bytes(key, KeyCodec::encode_tombstone(new_size, chunk_size));
     }
 
     pub fn remove(&self, txn: &mut EncryptedTransaction, key: &Bytes) {
@@ -57,19 +70,18 @@ impl TombstoneStore {
         Ok(Box::pin(futures::stream::unfold(iter, |mut iter| async {
             match futures::StreamExt::next(&mut iter).await {
                 Some(Ok((key, value))) => match KeyCodec::parse_key(&key) {
-                    ParsedKey::Tombstone { inode_id } => {
-                        match KeyCodec::decode_tombstone_size(&value) {
-                            Ok(remaining_size) => Some((
-                                Ok(TombstoneEntry {
-                                    key,
-                                    inode_id,
-                                    remaining_size,
-                                }),
-                                iter,
-                            )),
-                            Err(e) => Some((Err(e), iter)),
-                        }
-                    }
+                    ParsedKey::Tombstone { inode_id } => match KeyCodec::decode_tombstone(&value) {
+                        Ok((remaining_size, chunk_size)) => Some((
+                            Ok(TombstoneEntry {
+                                key,
+                                inode_id,
+                                remaining_size,
+                                chunk_size,
+                            }),
+                            iter,
+                        )),
+                        Err(e) => Some((Err(e), iter)),
+                    },
                     _ => Some((Err(FsError::InvalidData), iter)),
                 },
                 Some(Err(_)) => Some((Err(FsError::IoError), iter)),
@@ -0,0 +1,181 @@
+use crate::encryption::{EncryptedDb, EncryptedTransaction};
+use crate::fs::acl::{Acl, is_acl_xattr, stored_acl};
+use crate::fs::errors::FsError;
+use crate::fs::inode::InodeId;
+use crate::fs::key_codec::KeyCodec;
+use crate::fs::store::LazyDirStore;
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Longest extended attribute name, as on Linux
+pub const XATTR_NAME_MAX: usize = 255;
+/// Largest extended attribute value, as on Linux
+pub const XATTR_SIZE_MAX: usize = 64 * 1024;
+/// Largest total size of the names and values of one inode's attributes.
+/// They are stored together and rewritten on every change.
+pub const XATTR_TOTAL_MAX: usize = 256 * 1024;
+
+/// Extended attributes of an inode: name -> value
+pub type Xattrs = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// Extended attributes, stored as a single value per inode. Inodes without
+/// attributes have no entry, so reading them costs one point lookup.
+///
+/// Permission checks look up ACLs only once any inode has had one, so
+/// filesystems that never use them pay nothing for it.
+#[derive(Clone)]
+pub struct XattrStore {
+    db: Arc<EncryptedDb>,
+    acls_in_use: Arc<AtomicBool>,
+    lazy_dirs: Option<LazyDirStore>,
+}
+
+impl XattrStore {
+    pub async fn new(db: Arc<EncryptedDb>) -> Result<Self, FsError> {
+        let acls_in_use = db
+            .get_bytes(&KeyCodec::system_acls_key())
+            .await
+            .map_err(|_| FsError::IoError)?
+            .is_some();
+
+        Ok(Self {
+            db,
+            acls_in_use: Arc::new(AtomicBool::new(acls_in_use)),
+            lazy_dirs: None,
+        })
+    }
+
+    /// Resolve the aliases `lazy_dirs` hands out for entries of lazy
+    /// directories
+    pub fn with_lazy_dirs(mut self, lazy_dirs: LazyDirStore) -> Self {
+        self.lazy_dirs = Some(lazy_dirs);
+        self
+    }
+
+    pub fn acls_in_use(&self) -> bool {
+        self.acls_in_use.load(Ordering::Relaxed)
+    }
+
+    pub async fn get(&self, mut id: InodeId) -> Result<Xattrs, FsError> {
+        if let Some(lazy_dirs) = &self.lazy_dirs
+            && let Some((_, target)) = lazy_dirs.resolve_alias(id).await?
+        {
+            id = target;
+        }
+
+        let data = self
+            .db
+            .get_bytes(&KeyCodec::xattr_key(id))
+            .await
+            .map_err(|_| FsError::IoError)?;
+
+        match data {
+            Some(data) => bincode::deserialize(&data).map_err(|e| {
+                tracing::warn!("Failed to deserialize xattrs of inode {}: {:?}", id, e);
+                FsError::InvalidData
+            }),
+            None => Ok(Xattrs::new()),
+        }
+    }
+
+    /// ACL stored as attribute `name` of inode `id`
+    pub async fn get_acl(&self, id: InodeId, name: &[u8]) -> Result<Option<Acl>, FsError> {
+        if !self.acls_in_use() {
+            return Ok(None);
+        }
+
+        stored_acl(&self.get(id).await?, name)
+    }
+
+    /// Replace the attributes of inode `id`. An empty set removes the entry.
+    pub fn save(
+        &self,
+        txn: &mut EncryptedTransaction,
+        id: InodeId,
+        xattrs: &Xattrs,
+    ) -> Result<(), FsError> {
+        let key = KeyCodec::xattr_key(id);
+        if xattrs.is_empty() {
+            txn.delete_bytes(&key);
+            return Ok(());
+        }
+
+        let total: usize = xattrs.iter().map(|(n, v)| n.len() + v.len()).sum();
+        if total > XATTR_TOTAL_MAX {
+            return Err(FsError::NoSpace);
+        }
+
+        if !self.acls_in_use() && xattrs.keys().any(|name| is_acl_xattr(name)) {
+            txn.put_bytes(&KeyCodec::system_acls_key(), Bytes::from_static(&[1]));
+            self.acls_in_use.store(true, Ordering::Relaxed);
+        }
+
+        txn.put_bytes(&key, Bytes::from(bincode::serialize(xattrs)?));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::ZeroFS;
+
+    #[tokio::test]
+    async fn test_xattrs_persist() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let store = XattrStore::new(fs.db.clone()).await.unwrap();
+        assert!(store.get(5).await.unwrap().is_empty());
+
+        let mut xattrs = Xattrs::new();
+        xattrs.insert(b"user.a".to_vec(), b"1".to_vec());
+        xattrs.insert(b"user.b".to_vec(), Vec::new());
+
+        let mut txn = fs.db.new_transaction().unwrap();
+        store.save(&mut txn, 5, &xattrs).unwrap();
+        fs.db
+            .write_with_options(
+                txn,
+                &slatedb::config::WriteOptions {
+                    await_durable: false,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(store.get(5).await.unwrap(), xattrs);
+
+        let mut txn = fs.db.new_transaction().unwrap();
+        store.save(&mut txn, 5, &Xattrs::new()).unwrap();
+        fs.db
+            .write_with_options(
+                txn,
+                &slatedb::config::WriteOptions {
+                    await_durable: false,
+                },
+            )
+            .await
+            .unwrap();
+        assert!(
+            fs.db
+                .get_bytes(&KeyCodec::xattr_key(5))
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_xattr_total_size_limit() {
+        let fs = ZeroFS::new_in_memory().await.unwrap();
+        let store = XattrStore::new(fs.db.clone()).await.unwrap();
+
+        let mut xattrs = Xattrs::new();
+        for i in 0..4 {
+            xattrs.insert(format!("user.{}", i).into_bytes(), vec![0; XATTR_SIZE_MAX]);
+        }
+
+        let mut txn = fs.db.new_transaction().unwrap();
+        assert_eq!(store.save(&mut txn, 5, &xattrs), Err(FsError::NoSpace));
+    }
+}
@@ -176,6 +176,11 @@ impl From<InodeWithId<'_>> for FileAttributes {
             Inode::CharDevice(s) => (FileType::CharDevice, 0, s.rdev),
             Inode::BlockDevice(s) => (FileType::BlockDevice, 0, s.rdev),
         };
+        // Sparse files take less than their size
+        let used = match inode {
+            Inode::File(f) => f.allocated_bytes(),
+            _ => size,
+        };
 
         FileAttributes {
             file_type,
@@ -184,7 +189,7 @@ impl From<InodeWithId<'_>> for FileAttributes {
             uid: inode.uid(),
             gid: inode.gid(),
             size,
-            used: size,
+            used,
             rdev,
             fsid: 0,
             fileid: inode_id,
@@ -1,11 +1,17 @@
 // REST API server for ZeroFS - wraps gRPC calls for Kubernetes CSI integration
 use crate::config::HttpConfig;
+use crate::fs::dataset::{Dataset, DatasetId, DatasetQuota, DatasetUsage, tree_children};
+use crate::fs::destroy::DestroyProgress;
+use crate::fs::diff::Change;
+use crate::fs::properties::PropertyValue;
+use crate::fs::restore::OnConflict;
+use crate::fs::retention::ScheduleStatus;
 use crate::rpc::client::RpcClient;
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     response::Json,
-    routing::{delete, get, post},
+    routing::{delete, get, post, put},
     Router,
 };
 use serde::{Deserialize, Serialize};
@@ -25,6 +31,9 @@ struct AppState {
 #[derive(Debug, Deserialize)]
 struct CreateDatasetRequest {
     name: String,
+    /// Encrypt the dataset's data with a key of its own
+    #[serde(default)]
+    encrypt: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -32,15 +41,80 @@ struct DatasetResponse {
     id: u64,
     name: String,
     uuid: String,
+    /// Dataset this one is nested in, or the source of a snapshot
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent_id: Option<u64>,
     created_at: u64,
     root_inode: u64,
     is_readonly: bool,
     is_snapshot: bool,
+    encrypted: bool,
+    quota: DatasetQuota,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usage: Option<DatasetUsage>,
+}
+
+impl From<Dataset> for DatasetResponse {
+    fn from(dataset: Dataset) -> Self {
+        Self {
+            id: dataset.id,
+            name: dataset.name,
+            uuid: dataset.uuid.to_string(),
+            parent_id: dataset.parent_id,
+            created_at: dataset.created_at,
+            root_inode: dataset.root_inode,
+            is_readonly: dataset.is_readonly,
+            is_snapshot: dataset.is_snapshot,
+            encrypted: dataset.wrapped_key.is_some(),
+            quota: dataset.quota,
+            usage: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ListDatasetsQuery {
+    /// List each dataset below its parent, and snapshots below their source
+    #[serde(default)]
+    tree: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct DatasetListEntry {
+    #[serde(flatten)]
+    dataset: DatasetResponse,
+    /// Datasets nested in this one and its snapshots, when listed as a tree
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    children: Vec<DatasetListEntry>,
 }
 
 #[derive(Debug, Serialize)]
 struct ListDatasetsResponse {
-    datasets: Vec<DatasetResponse>,
+    datasets: Vec<DatasetListEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecursiveQuery {
+    /// Also destroy what is below the dataset or snapshot
+    #[serde(default)]
+    recursive: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct DatasetPropertiesResponse {
+    properties: Vec<PropertyValue>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SetPropertyRequest {
+    /// New value; null to inherit it again
+    value: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RenameRequest {
+    /// New name. A dataset moves below the dataset the name is nested in.
+    name: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -52,6 +126,18 @@ struct CreateSnapshotRequest {
     /// Create read-only snapshot (default: false, read-write like btrfs)
     #[serde(default)]
     readonly: bool,
+    /// Also snapshot every child dataset, as `<name>/<path below source>`
+    #[serde(default)]
+    recursive: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateSnapshotResponse {
+    #[serde(flatten)]
+    snapshot: SnapshotResponse,
+    /// Snapshots of the child datasets, with `recursive`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    descendants: Vec<SnapshotResponse>,
 }
 
 #[derive(Debug, Serialize)]
@@ -62,6 +148,13 @@ struct SnapshotResponse {
     source: String,
     created_at: u64,
     readonly: bool,
+    /// Holds keeping the snapshot from being deleted
+    holds: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HoldRequest {
+    tag: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -69,17 +162,60 @@ struct ListSnapshotsResponse {
     snapshots: Vec<SnapshotResponse>,
 }
 
+#[derive(Debug, Deserialize)]
+struct RollbackRequest {
+    /// Snapshot of the dataset to roll back to
+    snapshot: String,
+    /// Destroy the snapshots taken after `snapshot` instead of failing
+    #[serde(default)]
+    destroy_newer: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct RollbackResponse {
+    dataset: DatasetResponse,
+    destroyed_snapshots: Vec<DestroyProgress>,
+    /// The tree the dataset had before, reclaimed in the background
+    replaced: DestroyProgress,
+}
+
+#[derive(Debug, Deserialize)]
+struct DiffQuery {
+    /// Snapshot or dataset to compare with; the live dataset if unset
+    to: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct DiffResponse {
+    from: String,
+    to: Option<String>,
+    changes: Vec<Change>,
+}
+
 #[derive(Debug, Deserialize)]
 struct RestoreRequest {
     snapshot: String,
+    /// Path within the snapshot; empty for the whole snapshot
+    #[serde(default)]
     source: String,
+    /// Path within the filesystem
     destination: String,
+    #[serde(default)]
+    on_conflict: OnConflict,
+    #[serde(default)]
+    dry_run: bool,
 }
 
 #[derive(Debug, Serialize)]
 struct RestoreResponse {
     inode_id: u64,
     file_size: u64,
+    destination: String,
+    is_directory: bool,
+    replaced: bool,
+    dry_run: bool,
+    /// The tree replaced by an overwrite, reclaimed in the background
+    detached: Option<DestroyProgress>,
     message: String,
 }
 
@@ -136,32 +272,25 @@ async fn create_dataset(
     Json(req): Json<CreateDatasetRequest>,
 ) -> Result<(StatusCode, Json<DatasetResponse>), (StatusCode, Json<ErrorResponse>)> {
     let client = get_rpc_client(&state).await?;
-    let dataset = client.create_dataset(&req.name).await.map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: "CREATE_DATASET_FAILED".to_string(),
-                message: e.to_string(),
-            }),
-        )
-    })?;
+    let dataset = client
+        .create_dataset(&req.name, req.encrypt)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "CREATE_DATASET_FAILED".to_string(),
+                    message: e.to_string(),
+                }),
+            )
+        })?;
 
-    Ok((
-        StatusCode::CREATED,
-        Json(DatasetResponse {
-            id: dataset.id,
-            name: dataset.name,
-            uuid: dataset.uuid.to_string(),
-            created_at: dataset.created_at,
-            root_inode: dataset.root_inode,
-            is_readonly: dataset.is_readonly,
-            is_snapshot: dataset.is_snapshot,
-        }),
-    ))
+    Ok((StatusCode::CREATED, Json(DatasetResponse::from(dataset))))
 }
 
 async fn list_datasets(
     State(state): State<AppState>,
+    Query(query): Query<ListDatasetsQuery>,
 ) -> Result<Json<ListDatasetsResponse>, (StatusCode, Json<ErrorResponse>)> {
     let client = get_rpc_client(&state).await?;
     let datasets = client.list_datasets().await.map_err(|e| {
@@ -174,22 +303,39 @@ async fn list_datasets(
         )
     })?;
 
+    if query.tree {
+        let mut children = tree_children(datasets);
+        return Ok(Json(ListDatasetsResponse {
+            datasets: dataset_tree(&mut children, None),
+        }));
+    }
+
     Ok(Json(ListDatasetsResponse {
         datasets: datasets
             .into_iter()
-            .map(|d| DatasetResponse {
-                id: d.id,
-                name: d.name,
-                uuid: d.uuid.to_string(),
-                created_at: d.created_at,
-                root_inode: d.root_inode,
-                is_readonly: d.is_readonly,
-                is_snapshot: d.is_snapshot,
+            .map(|dataset| DatasetListEntry {
+                dataset: dataset.into(),
+                children: Vec::new(),
             })
             .collect(),
     }))
 }
 
+// Entries for the datasets below `parent`, each with those below it
+fn dataset_tree(
+    children: &mut HashMap<Option<DatasetId>, Vec<Dataset>>,
+    parent: Option<DatasetId>,
+) -> Vec<DatasetListEntry> {
+    let below = children.remove(&parent).unwrap_or_default();
+    below
+        .into_iter()
+        .map(|dataset| DatasetListEntry {
+            children: dataset_tree(children, Some(dataset.id)),
+            dataset: dataset.into(),
+        })
+        .collect()
+}
+
 async fn get_dataset(
     State(state): State<AppState>,
     Path(name): Path<String>,
@@ -205,7 +351,7 @@ async fn get_dataset(
         )
     })?;
 
-    let dataset = dataset.ok_or_else(|| {
+    let (dataset, usage) = dataset.ok_or_else(|| {
         (
             StatusCode::NOT_FOUND,
             Json(ErrorResponse {
@@ -216,39 +362,210 @@ async fn get_dataset(
     })?;
 
     Ok(Json(DatasetResponse {
-        id: dataset.id,
-        name: dataset.name,
-        uuid: dataset.uuid.to_string(),
-        created_at: dataset.created_at,
-        root_inode: dataset.root_inode,
-        is_readonly: dataset.is_readonly,
-        is_snapshot: dataset.is_snapshot,
+        usage: Some(usage),
+        ..dataset.into()
+    }))
+}
+
+async fn set_dataset_quota(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(quota): Json<DatasetQuota>,
+) -> Result<Json<DatasetResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let client = get_rpc_client(&state).await?;
+    let (dataset, usage) = client.set_dataset_quota(&name, quota).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: "SET_DATASET_QUOTA_FAILED".to_string(),
+                message: e.to_string(),
+            }),
+        )
+    })?;
+
+    Ok(Json(DatasetResponse {
+        usage: Some(usage),
+        ..dataset.into()
     }))
 }
 
+async fn get_dataset_properties(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<DatasetPropertiesResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let client = get_rpc_client(&state).await?;
+    let properties = client
+        .get_dataset_properties(&name, None)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "GET_DATASET_PROPERTIES_FAILED".to_string(),
+                    message: e.to_string(),
+                }),
+            )
+        })?;
+
+    Ok(Json(DatasetPropertiesResponse { properties }))
+}
+
+async fn set_dataset_property(
+    State(state): State<AppState>,
+    Path((name, property)): Path<(String, String)>,
+    Json(req): Json<SetPropertyRequest>,
+) -> Result<Json<PropertyValue>, (StatusCode, Json<ErrorResponse>)> {
+    let client = get_rpc_client(&state).await?;
+    client
+        .set_dataset_property(&name, &property, req.value.as_deref())
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "SET_DATASET_PROPERTY_FAILED".to_string(),
+                    message: e.to_string(),
+                }),
+            )
+        })?;
+
+    let properties = client
+        .get_dataset_properties(&name, Some(&property))
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "GET_DATASET_PROPERTIES_FAILED".to_string(),
+                    message: e.to_string(),
+                }),
+            )
+        })?;
+    let value = properties.into_iter().next().ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "PROPERTY_NOT_FOUND".to_string(),
+                message: format!("Property '{}' not found", property),
+            }),
+        )
+    })?;
+
+    Ok(Json(value))
+}
+
 async fn delete_dataset(
     State(state): State<AppState>,
     Path(name): Path<String>,
+    Query(query): Query<RecursiveQuery>,
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
     let client = get_rpc_client(&state).await?;
-    client.delete_dataset(&name).await.map_err(|e| {
+    client
+        .delete_dataset(&name, query.recursive)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "DELETE_DATASET_FAILED".to_string(),
+                    message: e.to_string(),
+                }),
+            )
+        })?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Rename a dataset, keeping its UUID
+async fn rename_dataset(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(req): Json<RenameRequest>,
+) -> Result<Json<DatasetResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let client = get_rpc_client(&state).await?;
+    let dataset = client.rename_dataset(&name, &req.name).await.map_err(|e| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
-                error: "DELETE_DATASET_FAILED".to_string(),
+                error: "RENAME_DATASET_FAILED".to_string(),
                 message: e.to_string(),
             }),
         )
     })?;
 
-    Ok(StatusCode::NO_CONTENT)
+    Ok(Json(dataset.into()))
+}
+
+// Revert a dataset to one of its snapshots. Handles into its previous
+// contents become stale.
+async fn rollback_dataset(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(req): Json<RollbackRequest>,
+) -> Result<Json<RollbackResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let client = get_rpc_client(&state).await?;
+    let (dataset, destroyed, replaced) = client
+        .rollback_dataset(&name, &req.snapshot, req.destroy_newer)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "ROLLBACK_DATASET_FAILED".to_string(),
+                    message: e.to_string(),
+                }),
+            )
+        })?;
+
+    Ok(Json(RollbackResponse {
+        dataset: dataset.into(),
+        destroyed_snapshots: destroyed,
+        replaced,
+    }))
+}
+
+// Detached trees still being reclaimed
+async fn list_dataset_destroys(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<DestroyProgress>>, (StatusCode, Json<ErrorResponse>)> {
+    let client = get_rpc_client(&state).await?;
+    let destroys = client.list_dataset_destroys().await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: "LIST_DESTROYS_FAILED".to_string(),
+                message: e.to_string(),
+            }),
+        )
+    })?;
+
+    Ok(Json(destroys))
+}
+
+// Usage of the whole filesystem, including the space taken by chunk bodies
+// before and after compression
+async fn get_usage(
+    State(state): State<AppState>,
+) -> Result<Json<DatasetUsage>, (StatusCode, Json<ErrorResponse>)> {
+    let client = get_rpc_client(&state).await?;
+    let usage = client.usage().await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: "GET_USAGE_FAILED".to_string(),
+                message: e.to_string(),
+            }),
+        )
+    })?;
+
+    Ok(Json(usage))
 }
 
 // Snapshot endpoints
 async fn create_snapshot(
     State(state): State<AppState>,
     Json(req): Json<CreateSnapshotRequest>,
-) -> Result<(StatusCode, Json<SnapshotResponse>), (StatusCode, Json<ErrorResponse>)> {
+) -> Result<(StatusCode, Json<CreateSnapshotResponse>), (StatusCode, Json<ErrorResponse>)> {
     let client = get_rpc_client(&state).await?;
     // Validate that source is a dataset name, not a path
     if req.source.starts_with('/') {
@@ -264,9 +581,17 @@ async fn create_snapshot(
         ));
     }
 
-    let snapshot = client
-        .create_snapshot_with_options(&req.source, &req.name, req.readonly)
-        .await
+    let snapshots = if req.recursive {
+        client
+            .create_recursive_snapshot(&req.source, &req.name, req.readonly)
+            .await
+    } else {
+        client
+            .create_snapshot_with_options(&req.source, &req.name, req.readonly)
+            .await
+            .map(|snapshot| vec![snapshot])
+    };
+    let mut snapshots = snapshots
         .map_err(|e| {
             let error_msg = if e.to_string().contains("Not found") || e.to_string().contains("not found") {
                 format!(
@@ -283,17 +608,36 @@ async fn create_snapshot(
                     message: error_msg,
                 }),
             )
-        })?;
+        })?
+        .into_iter();
+
+    let snapshot = snapshots.next().ok_or_else(|| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: "CREATE_SNAPSHOT_FAILED".to_string(),
+                message: "No snapshot was taken".to_string(),
+            }),
+        )
+    })?;
+    let mut descendants = Vec::new();
+    for descendant in snapshots {
+        descendants.push(snapshot_response(&client, descendant).await);
+    }
 
     Ok((
         StatusCode::CREATED,
-        Json(SnapshotResponse {
-            id: snapshot.id,
-            name: snapshot.name,
-            uuid: snapshot.uuid.to_string(),
-            source: req.source,
-            created_at: snapshot.created_at,
-            readonly: snapshot.is_readonly,
+        Json(CreateSnapshotResponse {
+            snapshot: SnapshotResponse {
+                id: snapshot.id,
+                name: snapshot.name,
+                uuid: snapshot.uuid.to_string(),
+                source: req.source,
+                created_at: snapshot.created_at,
+                readonly: snapshot.is_readonly,
+                holds: snapshot.holds.into_iter().collect(),
+            },
+            descendants,
         }),
     ))
 }
@@ -332,6 +676,7 @@ async fn list_snapshots(
                     .unwrap_or_else(|| format!("ID:{}", s.parent_id.unwrap_or(0))),
                 created_at: s.created_at,
                 readonly: s.is_readonly,
+                holds: s.holds.into_iter().collect(),
             })
             .collect(),
     }))
@@ -352,7 +697,7 @@ async fn get_snapshot(
         )
     })?;
 
-    let snapshot = snapshot.ok_or_else(|| {
+    let (snapshot, _) = snapshot.ok_or_else(|| {
         (
             StatusCode::NOT_FOUND,
             Json(ErrorResponse {
@@ -389,106 +734,205 @@ async fn get_snapshot(
             .unwrap_or_else(|| format!("ID:{}", snapshot.parent_id.unwrap_or(0))),
         created_at: snapshot.created_at,
         readonly: snapshot.is_readonly,
+        holds: snapshot.holds.into_iter().collect(),
     }))
 }
 
 async fn delete_snapshot(
     State(state): State<AppState>,
     Path(name): Path<String>,
+    Query(query): Query<RecursiveQuery>,
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
     let client = get_rpc_client(&state).await?;
-    client.delete_snapshot(&name).await.map_err(|e| {
+    client
+        .delete_snapshot(&name, query.recursive)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "DELETE_SNAPSHOT_FAILED".to_string(),
+                    message: e.to_string(),
+                }),
+            )
+        })?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn hold_snapshot(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(req): Json<HoldRequest>,
+) -> Result<Json<SnapshotResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let client = get_rpc_client(&state).await?;
+    let snapshot = client.hold_snapshot(&name, &req.tag).await.map_err(|e| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
-                error: "DELETE_SNAPSHOT_FAILED".to_string(),
+                error: "HOLD_SNAPSHOT_FAILED".to_string(),
                 message: e.to_string(),
             }),
         )
     })?;
 
-    Ok(StatusCode::NO_CONTENT)
+    Ok(Json(snapshot_response(&client, snapshot).await))
 }
 
-async fn restore_from_snapshot(
+async fn rename_snapshot(
     State(state): State<AppState>,
-    Json(req): Json<RestoreRequest>,
-) -> Result<(StatusCode, Json<RestoreResponse>), (StatusCode, Json<ErrorResponse>)> {
+    Path(name): Path<String>,
+    Json(req): Json<RenameRequest>,
+) -> Result<Json<SnapshotResponse>, (StatusCode, Json<ErrorResponse>)> {
     let client = get_rpc_client(&state).await?;
+    let snapshot = client.rename_dataset(&name, &req.name).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: "RENAME_SNAPSHOT_FAILED".to_string(),
+                message: e.to_string(),
+            }),
+        )
+    })?;
 
-    // Check if destination is internal (for instant restore) or external (copy-based)
-    let is_internal = !req.destination.starts_with("/tmp/")
-        && !req.destination.starts_with("/home/")
-        && !req.destination.starts_with("/root/")
-        && req.destination.starts_with('/');
-
-    if is_internal {
-        // Instant restore (COW)
-        let (inode_id, file_size, _nlink) = client
-            .instant_restore_file(&req.snapshot, &req.source, &req.destination)
-            .await
-            .map_err(|e| {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ErrorResponse {
-                        error: "INSTANT_RESTORE_FAILED".to_string(),
-                        message: e.to_string(),
-                    }),
-                )
-            })?;
+    Ok(Json(snapshot_response(&client, snapshot).await))
+}
 
-        Ok((
-            StatusCode::OK,
-            Json(RestoreResponse {
-                inode_id,
-                file_size,
-                message: format!(
-                    "File restored instantly (COW) - no data copied. Inode: {}, Size: {} bytes",
-                    inode_id, file_size
-                ),
+async fn release_snapshot(
+    State(state): State<AppState>,
+    Path((name, tag)): Path<(String, String)>,
+) -> Result<Json<SnapshotResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let client = get_rpc_client(&state).await?;
+    let snapshot = client.release_snapshot(&name, &tag).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: "RELEASE_SNAPSHOT_FAILED".to_string(),
+                message: e.to_string(),
             }),
-        ))
-    } else {
-        // Copy-based restore
-        let file_data = client
-            .read_snapshot_file(&req.snapshot, &req.source)
-            .await
-            .map_err(|e| {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ErrorResponse {
-                        error: "READ_SNAPSHOT_FILE_FAILED".to_string(),
-                        message: e.to_string(),
-                    }),
-                )
-            })?;
+        )
+    })?;
 
-        tokio::fs::write(&req.destination, &file_data)
+    Ok(Json(snapshot_response(&client, snapshot).await))
+}
+
+// Snapshot as returned by the snapshot endpoints, named after its source
+async fn snapshot_response(client: &RpcClient, snapshot: Dataset) -> SnapshotResponse {
+    let source = match snapshot.parent_id {
+        Some(id) => client
+            .list_datasets()
             .await
-            .map_err(|e| {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ErrorResponse {
-                        error: "WRITE_DESTINATION_FAILED".to_string(),
-                        message: format!("Failed to write to {}: {}", req.destination, e),
-                    }),
-                )
-            })?;
+            .unwrap_or_default()
+            .into_iter()
+            .find(|d| d.id == id)
+            .map(|d| d.name),
+        None => None,
+    };
 
-        Ok((
-            StatusCode::OK,
-            Json(RestoreResponse {
-                inode_id: 0,
-                file_size: file_data.len() as u64,
-                message: format!(
-                    "File restored (copy-based). Size: {} bytes",
-                    file_data.len()
-                ),
-            }),
-        ))
+    SnapshotResponse {
+        id: snapshot.id,
+        source: source.unwrap_or_else(|| format!("ID:{}", snapshot.parent_id.unwrap_or(0))),
+        name: snapshot.name,
+        uuid: snapshot.uuid.to_string(),
+        created_at: snapshot.created_at,
+        readonly: snapshot.is_readonly,
+        holds: snapshot.holds.into_iter().collect(),
     }
 }
 
+async fn diff_snapshots(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<DiffQuery>,
+) -> Result<Json<DiffResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let client = get_rpc_client(&state).await?;
+    let changes = client
+        .diff_snapshots(&name, query.to.as_deref())
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "DIFF_SNAPSHOTS_FAILED".to_string(),
+                    message: e.to_string(),
+                }),
+            )
+        })?;
+
+    Ok(Json(DiffResponse {
+        from: name,
+        to: query.to,
+        changes,
+    }))
+}
+
+// Snapshot schedules from the server config and how each last ran
+async fn get_snapshot_schedules(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<ScheduleStatus>>, (StatusCode, Json<ErrorResponse>)> {
+    let client = get_rpc_client(&state).await?;
+    let schedules = client.get_snapshot_schedules().await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: "GET_SNAPSHOT_SCHEDULES_FAILED".to_string(),
+                message: e.to_string(),
+            }),
+        )
+    })?;
+
+    Ok(Json(schedules))
+}
+
+async fn restore_from_snapshot(
+    State(state): State<AppState>,
+    Json(req): Json<RestoreRequest>,
+) -> Result<(StatusCode, Json<RestoreResponse>), (StatusCode, Json<ErrorResponse>)> {
+    let client = get_rpc_client(&state).await?;
+
+    let outcome = client
+        .instant_restore_file(
+            &req.snapshot,
+            &req.source,
+            &req.destination,
+            req.on_conflict,
+            req.dry_run,
+        )
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: "INSTANT_RESTORE_FAILED".to_string(),
+                    message: e.to_string(),
+                }),
+            )
+        })?;
+
+    let message = if outcome.dry_run {
+        format!("Would restore to {}", outcome.destination_path)
+    } else {
+        format!(
+            "Restored instantly (COW) to {} - no data copied. Inode: {}, Size: {} bytes",
+            outcome.destination_path, outcome.inode_id, outcome.size
+        )
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(RestoreResponse {
+            inode_id: outcome.inode_id,
+            file_size: outcome.size,
+            destination: outcome.destination_path,
+            is_directory: outcome.is_directory,
+            replaced: outcome.replaced,
+            dry_run: outcome.dry_run,
+            detached: outcome.detached,
+            message,
+        }),
+    ))
+}
+
 #[derive(Debug, Deserialize)]
 struct CloneRequest {
     source: String,
@@ -547,11 +991,29 @@ pub fn create_router(rpc_config: crate::config::RpcConfig) -> Router {
         .route("/api/v1/datasets", get(list_datasets))
         .route("/api/v1/datasets/{name}", get(get_dataset))
         .route("/api/v1/datasets/{name}", delete(delete_dataset))
+        .route("/api/v1/datasets/{name}/quota", put(set_dataset_quota))
+        .route(
+            "/api/v1/datasets/{name}/properties",
+            get(get_dataset_properties),
+        )
+        .route(
+            "/api/v1/datasets/{name}/properties/{property}",
+            put(set_dataset_property),
+        )
+        .route("/api/v1/datasets/{name}/rename", post(rename_dataset))
+        .route("/api/v1/datasets/{name}/rollback", post(rollback_dataset))
+        .route("/api/v1/destroys", get(list_dataset_destroys))
+        .route("/api/v1/usage", get(get_usage))
         .route("/api/v1/snapshots", post(create_snapshot))
         .route("/api/v1/snapshots", get(list_snapshots))
         .route("/api/v1/snapshots/{name}", get(get_snapshot))
         .route("/api/v1/snapshots/{name}", delete(delete_snapshot))
+        .route("/api/v1/snapshots/{name}/diff", get(diff_snapshots))
+        .route("/api/v1/snapshots/{name}/rename", post(rename_snapshot))
+        .route("/api/v1/snapshots/{name}/holds", post(hold_snapshot))
+        .route("/api/v1/snapshots/{name}/holds/{tag}", delete(release_snapshot))
         .route("/api/v1/snapshots/restore", post(restore_from_snapshot))
+        .route("/api/v1/snapshot-schedules", get(get_snapshot_schedules))
         .route("/api/v1/clone", post(clone_path))
         .layer(CorsLayer::permissive())
         .layer(TraceLayer::new_for_http())
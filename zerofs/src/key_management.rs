@@ -1,47 +1,120 @@
-use crate::encryption::SlateDbHandle;
-use crate::fs::key_codec::SYSTEM_WRAPPED_ENCRYPTION_KEY;
+use crate::config::StorageConfig;
+use crate::encryption::{DataKeys, KeyId, SlateDbHandle};
+use crate::fs::key_codec::{SYSTEM_KEY_SLOTS, SYSTEM_WRAPPED_ENCRYPTION_KEY};
 use crate::task::spawn_blocking_named;
 use anyhow::Result;
 use argon2::{
     Algorithm, Argon2, Params, Version,
     password_hash::{PasswordHasher, SaltString},
 };
+use bytes::Bytes;
 use chacha20poly1305::{
     ChaCha20Poly1305, Key, Nonce,
     aead::{Aead, KeyInit},
 };
 use rand::{RngCore, thread_rng};
 use serde::{Deserialize, Serialize};
-use slatedb::config::{DurabilityLevel, ReadOptions};
+use slatedb::WriteBatch;
+use slatedb::config::{DurabilityLevel, ReadOptions, WriteOptions};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
 
 const ARGON2_MEM_COST: u32 = 65536;
 const ARGON2_TIME_COST: u32 = 3;
 const ARGON2_PARALLELISM: u32 = 4;
 
+/// `wrapped_dek` is a single 32-byte key
+const WRAPPED_KEY_VERSION_SINGLE: u32 = 1;
+/// `wrapped_dek` is a serialized `DataKeys`, holding every key generation
+const WRAPPED_KEY_VERSION_KEYS: u32 = 2;
+
+const KEY_SLOTS_VERSION: u32 = 1;
+
+/// Time a key command gets to print the secret, unless configured
+pub const DEFAULT_KEY_COMMAND_TIMEOUT_SECS: u64 = 30;
+/// Most bytes kept from each output stream of a key command
+const MAX_KEY_COMMAND_OUTPUT: usize = 64 * 1024;
+
+/// Label of the slot holding the password a filesystem was created with
+pub const DEFAULT_SLOT_LABEL: &str = "default";
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct WrappedDataKey {
     /// Salt for Argon2 password derivation
     pub salt: String,
     /// Nonce for XChaCha20-Poly1305 encryption of the DEK
     pub nonce: [u8; 12],
-    /// Encrypted data encryption key
+    /// Encrypted data encryption key(s)
     pub wrapped_dek: Vec<u8>,
     /// Version for future compatibility
     pub version: u32,
 }
 
+/// Argon2id cost parameters used to derive a key from a password
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Argon2Params {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for Argon2Params {
+    fn default() -> Self {
+        Self {
+            memory_kib: ARGON2_MEM_COST,
+            iterations: ARGON2_TIME_COST,
+            parallelism: ARGON2_PARALLELISM,
+        }
+    }
+}
+
+/// One password able to unlock the filesystem
+#[derive(Serialize, Deserialize, Debug)]
+pub struct KeySlot {
+    pub label: String,
+    pub params: Argon2Params,
+    /// The master key, wrapped with a key derived from the slot's password
+    pub wrapped_key: WrappedDataKey,
+}
+
+/// LUKS-style key slots. Every slot wraps the same master key with its own
+/// password, and the master key encrypts the data keys, so slots can be
+/// added and revoked, and the data keys rotated, independently.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct KeySlots {
+    pub slots: Vec<KeySlot>,
+    /// Nonce for the encryption of the data keys
+    pub nonce: [u8; 12],
+    /// `DataKeys`, encrypted with the master key
+    pub encrypted_keys: Vec<u8>,
+    /// Version for future compatibility
+    pub version: u32,
+}
+
 pub struct KeyManager {
     argon2: Argon2<'static>,
 }
 
 impl KeyManager {
     pub fn new() -> Self {
-        let params = Params::new(ARGON2_MEM_COST, ARGON2_TIME_COST, ARGON2_PARALLELISM, None)
-            .expect("Valid Argon2 parameters");
+        Self::with_params(&Argon2Params::default()).expect("Valid Argon2 parameters")
+    }
+
+    pub fn with_params(params: &Argon2Params) -> Result<Self> {
+        let params = Params::new(
+            params.memory_kib,
+            params.iterations,
+            params.parallelism,
+            None,
+        )
+        .map_err(|e| anyhow::anyhow!("Invalid Argon2 parameters: {}", e))?;
 
         let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
 
-        Self { argon2 }
+        Ok(Self { argon2 })
     }
 
     /// Derive a key encryption key (KEK) from a password
@@ -61,12 +134,19 @@ impl KeyManager {
         Ok(kek)
     }
 
-    /// Generate a new data encryption key and wrap it with a password
+    /// Generate a new random key and wrap it with a password
     pub fn generate_and_wrap_key(&self, password: &str) -> Result<(WrappedDataKey, [u8; 32])> {
-        // Generate random DEK
-        let mut dek = [0u8; 32];
-        thread_rng().fill_bytes(&mut dek);
+        // Generate random key
+        let mut key = [0u8; 32];
+        thread_rng().fill_bytes(&mut key);
+
+        let wrapped_key = self.wrap_key(password, &key)?;
+
+        Ok((wrapped_key, key))
+    }
 
+    /// Wrap a key with a password
+    pub fn wrap_key(&self, password: &str, key: &[u8; 32]) -> Result<WrappedDataKey> {
         // Generate random salt for password KDF
         let salt = SaltString::generate(&mut thread_rng());
 
@@ -78,31 +158,21 @@ impl KeyManager {
         thread_rng().fill_bytes(&mut nonce_bytes);
         let nonce = Nonce::from_slice(&nonce_bytes);
 
-        // Encrypt DEK with KEK
+        // Encrypt key with KEK
         let cipher = ChaCha20Poly1305::new(Key::from_slice(&kek));
         let wrapped_dek = cipher
-            .encrypt(nonce, dek.as_ref())
+            .encrypt(nonce, key.as_ref())
             .map_err(|e| anyhow::anyhow!("Failed to wrap DEK: {}", e))?;
 
-        let wrapped_key = WrappedDataKey {
+        Ok(WrappedDataKey {
             salt: salt.to_string(),
             nonce: nonce_bytes,
             wrapped_dek,
-            version: 1,
-        };
-
-        Ok((wrapped_key, dek))
+            version: WRAPPED_KEY_VERSION_SINGLE,
+        })
     }
 
-    /// Unwrap a data encryption key using a password
-    pub fn unwrap_key(&self, password: &str, wrapped_key: &WrappedDataKey) -> Result<[u8; 32]> {
-        if wrapped_key.version != 1 {
-            return Err(anyhow::anyhow!(
-                "Unsupported wrapped key version: {}",
-                wrapped_key.version
-            ));
-        }
-
+    fn unwrap(&self, password: &str, wrapped_key: &WrappedDataKey) -> Result<Vec<u8>> {
         // Parse salt
         let salt = SaltString::from_b64(&wrapped_key.salt)
             .map_err(|e| anyhow::anyhow!("Invalid salt: {}", e))?;
@@ -114,87 +184,480 @@ impl KeyManager {
         let cipher = ChaCha20Poly1305::new(Key::from_slice(&kek));
         let nonce = Nonce::from_slice(&wrapped_key.nonce);
 
-        let dek_vec = cipher
+        cipher
             .decrypt(nonce, wrapped_key.wrapped_dek.as_ref())
-            .map_err(|_| {
-                anyhow::anyhow!("Failed to unwrap DEK: Invalid password or corrupted key")
-            })?;
+            .map_err(|_| anyhow::anyhow!("Failed to unwrap DEK: Invalid password or corrupted key"))
+    }
+
+    /// Unwrap a key using a password
+    pub fn unwrap_key(&self, password: &str, wrapped_key: &WrappedDataKey) -> Result<[u8; 32]> {
+        if wrapped_key.version != WRAPPED_KEY_VERSION_SINGLE {
+            return Err(anyhow::anyhow!(
+                "Unsupported wrapped key version: {}",
+                wrapped_key.version
+            ));
+        }
 
-        let mut dek = [0u8; 32];
-        dek.copy_from_slice(&dek_vec);
-        Ok(dek)
+        self.unwrap(password, wrapped_key)?
+            .as_slice()
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Invalid wrapped DEK length"))
     }
 
-    /// Re-wrap a DEK with a new password (for password changes)
-    pub fn rewrap_key(
+    /// Unwrap the data keys stored before key slots were introduced
+    pub fn unwrap_data_keys(
         &self,
-        old_password: &str,
-        new_password: &str,
+        password: &str,
         wrapped_key: &WrappedDataKey,
-    ) -> Result<WrappedDataKey> {
-        // First unwrap with old password
-        let dek = self.unwrap_key(old_password, wrapped_key)?;
+    ) -> Result<DataKeys> {
+        match wrapped_key.version {
+            WRAPPED_KEY_VERSION_SINGLE => {
+                Ok(DataKeys::new(self.unwrap_key(password, wrapped_key)?))
+            }
+            WRAPPED_KEY_VERSION_KEYS => bincode::deserialize(&self.unwrap(password, wrapped_key)?)
+                .map_err(|e| anyhow::anyhow!("Failed to deserialize data keys: {}", e)),
+            version => Err(anyhow::anyhow!(
+                "Unsupported wrapped key version: {}",
+                version
+            )),
+        }
+    }
+}
 
-        // Generate new salt and wrap with new password
-        let salt = SaltString::generate(&mut thread_rng());
-        let kek = self.derive_kek(new_password, &salt)?;
+impl KeySlots {
+    /// Key slots with a single slot, returned with their new master key
+    pub fn new(
+        password: &str,
+        label: &str,
+        params: Argon2Params,
+        keys: &DataKeys,
+    ) -> Result<(Self, [u8; 32])> {
+        let (wrapped_key, master_key) =
+            KeyManager::with_params(&params)?.generate_and_wrap_key(password)?;
+
+        let mut slots = Self {
+            slots: vec![KeySlot {
+                label: label.to_string(),
+                params,
+                wrapped_key,
+            }],
+            nonce: [0u8; 12],
+            encrypted_keys: Vec::new(),
+            version: KEY_SLOTS_VERSION,
+        };
+        slots.set_data_keys(&master_key, keys)?;
 
-        let mut nonce_bytes = [0u8; 12];
-        thread_rng().fill_bytes(&mut nonce_bytes);
-        let nonce = Nonce::from_slice(&nonce_bytes);
+        Ok((slots, master_key))
+    }
 
-        let cipher = ChaCha20Poly1305::new(Key::from_slice(&kek));
-        let wrapped_dek = cipher
-            .encrypt(nonce, dek.as_ref())
-            .map_err(|e| anyhow::anyhow!("Failed to rewrap DEK: {}", e))?;
+    /// Find the slot `password` opens. Returns its index and the master key.
+    pub fn unlock(&self, password: &str) -> Result<(usize, [u8; 32])> {
+        if self.version != KEY_SLOTS_VERSION {
+            return Err(anyhow::anyhow!(
+                "Unsupported key slots version: {}",
+                self.version
+            ));
+        }
 
-        Ok(WrappedDataKey {
-            salt: salt.to_string(),
-            nonce: nonce_bytes,
-            wrapped_dek,
-            version: 1,
+        for (index, slot) in self.slots.iter().enumerate() {
+            let key_manager = KeyManager::with_params(&slot.params)?;
+            if let Ok(master_key) = key_manager.unwrap_key(password, &slot.wrapped_key) {
+                return Ok((index, master_key));
+            }
+        }
+
+        Err(anyhow::anyhow!(
+            "Failed to unwrap DEK: Invalid password or corrupted key"
+        ))
+    }
+
+    pub fn data_keys(&self, master_key: &[u8; 32]) -> Result<DataKeys> {
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(master_key));
+        let serialized = cipher
+            .decrypt(Nonce::from_slice(&self.nonce), self.encrypted_keys.as_ref())
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt data keys: corrupted key slots"))?;
+
+        bincode::deserialize(&serialized)
+            .map_err(|e| anyhow::anyhow!("Failed to deserialize data keys: {}", e))
+    }
+
+    pub fn set_data_keys(&mut self, master_key: &[u8; 32], keys: &DataKeys) -> Result<()> {
+        let serialized = bincode::serialize(keys)
+            .map_err(|e| anyhow::anyhow!("Failed to serialize data keys: {}", e))?;
+
+        thread_rng().fill_bytes(&mut self.nonce);
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(master_key));
+        self.encrypted_keys = cipher
+            .encrypt(Nonce::from_slice(&self.nonce), serialized.as_ref())
+            .map_err(|e| anyhow::anyhow!("Failed to encrypt data keys: {}", e))?;
+
+        Ok(())
+    }
+
+    pub fn add_slot(
+        &mut self,
+        master_key: &[u8; 32],
+        label: &str,
+        password: &str,
+        params: Argon2Params,
+    ) -> Result<()> {
+        if label.is_empty() {
+            return Err(anyhow::anyhow!("Key slot label cannot be empty"));
+        }
+        if self.slots.iter().any(|slot| slot.label == label) {
+            return Err(anyhow::anyhow!("Key slot '{}' already exists", label));
+        }
+
+        let wrapped_key = KeyManager::with_params(&params)?.wrap_key(password, master_key)?;
+        self.slots.push(KeySlot {
+            label: label.to_string(),
+            params,
+            wrapped_key,
+        });
+
+        Ok(())
+    }
+
+    pub fn remove_slot(&mut self, label: &str) -> Result<()> {
+        let index = self
+            .slots
+            .iter()
+            .position(|slot| slot.label == label)
+            .ok_or_else(|| anyhow::anyhow!("Key slot '{}' not found", label))?;
+        if self.slots.len() == 1 {
+            return Err(anyhow::anyhow!(
+                "Cannot revoke the last key slot: the filesystem would become unreadable"
+            ));
+        }
+
+        self.slots.remove(index);
+        Ok(())
+    }
+
+    /// Re-wrap the master key in slot `index` with a new password
+    pub fn set_password(
+        &mut self,
+        index: usize,
+        master_key: &[u8; 32],
+        password: &str,
+    ) -> Result<()> {
+        let slot = &mut self.slots[index];
+        slot.wrapped_key = KeyManager::with_params(&slot.params)?.wrap_key(password, master_key)?;
+        Ok(())
+    }
+}
+
+/// Where the secret unlocking the encryption key comes from. Every provider
+/// yields a string that is used like a password to open a key slot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyProvider {
+    /// Password written in the configuration
+    Password(String),
+    /// File holding the password. A trailing newline is ignored.
+    PasswordFile(PathBuf),
+    /// File holding 32 random bytes. They are not used as the key itself:
+    /// hex-encoded, they are the password of a slot, and go through its
+    /// Argon2id derivation like any other.
+    KeyFile(PathBuf),
+    /// Program printing the password on stdout, run without a shell and
+    /// killed once `timeout` passes
+    Command {
+        argv: Vec<String>,
+        timeout: Duration,
+    },
+}
+
+impl KeyProvider {
+    /// Pick the provider configured in `[storage]`. Exactly one of the
+    /// secret sources must be set.
+    pub fn from_config(storage: &StorageConfig) -> Result<Self> {
+        let mut providers = Vec::new();
+        if let Some(password) = &storage.encryption_password {
+            providers.push(Self::Password(password.clone()));
+        }
+        if let Some(path) = &storage.encryption_password_file {
+            providers.push(Self::PasswordFile(path.clone()));
+        }
+        if let Some(path) = &storage.encryption_key_file {
+            providers.push(Self::KeyFile(path.clone()));
+        }
+        if let Some(command) = &storage.encryption_key_command {
+            providers.push(Self::Command {
+                argv: command.clone(),
+                timeout: Duration::from_secs(
+                    storage
+                        .encryption_key_command_timeout_secs
+                        .unwrap_or(DEFAULT_KEY_COMMAND_TIMEOUT_SECS),
+                ),
+            });
+        }
+
+        if providers.len() > 1 {
+            return Err(anyhow::anyhow!(
+                "Only one of encryption_password, encryption_password_file, \
+                 encryption_key_file and encryption_key_command may be set in [storage]"
+            ));
+        }
+        providers.pop().ok_or_else(|| {
+            anyhow::anyhow!(
+                "No encryption secret configured: set one of encryption_password, \
+                 encryption_password_file, encryption_key_file or encryption_key_command \
+                 in [storage]"
+            )
         })
     }
+
+    pub async fn secret(&self) -> Result<String> {
+        match self {
+            Self::Password(password) => Ok(password.clone()),
+            Self::PasswordFile(path) => {
+                let contents = std::fs::read_to_string(path).map_err(|e| {
+                    anyhow::anyhow!("Failed to read password file {}: {}", path.display(), e)
+                })?;
+                Ok(trim_line_ending(&contents).to_string())
+            }
+            Self::KeyFile(path) => {
+                let key = std::fs::read(path).map_err(|e| {
+                    anyhow::anyhow!("Failed to read key file {}: {}", path.display(), e)
+                })?;
+                if key.len() != 32 {
+                    return Err(anyhow::anyhow!(
+                        "Key file {} must contain exactly 32 bytes, found {}",
+                        path.display(),
+                        key.len()
+                    ));
+                }
+                // Random bytes are all printable with negligible odds; text
+                // is a password, and far weaker than the key it stands for.
+                if key
+                    .iter()
+                    .all(|b| b.is_ascii_graphic() || b.is_ascii_whitespace())
+                {
+                    return Err(anyhow::anyhow!(
+                        "Key file {} holds text rather than random bytes; \
+                         use encryption_password_file for a password",
+                        path.display()
+                    ));
+                }
+                Ok(key.iter().map(|b| format!("{:02x}", b)).collect())
+            }
+            Self::Command { argv, timeout } => {
+                let (program, args) = argv
+                    .split_first()
+                    .ok_or_else(|| anyhow::anyhow!("encryption_key_command is empty"))?;
+                let mut child = Command::new(program)
+                    .args(args)
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .kill_on_drop(true)
+                    .spawn()
+                    .map_err(|e| anyhow::anyhow!("Failed to run key command {}: {}", program, e))?;
+                let stdout = child.stdout.take().expect("stdout is piped");
+                let stderr = child.stderr.take().expect("stderr is piped");
+
+                let run = async {
+                    let (stdout, stderr) = tokio::try_join!(
+                        read_limited(stdout, MAX_KEY_COMMAND_OUTPUT),
+                        read_limited(stderr, MAX_KEY_COMMAND_OUTPUT)
+                    )?;
+                    let status = child.wait().await?;
+                    Ok::<_, std::io::Error>((status, stdout, stderr))
+                };
+                let (status, stdout, stderr) = tokio::time::timeout(*timeout, run)
+                    .await
+                    .map_err(|_| {
+                        anyhow::anyhow!(
+                            "Key command {} timed out after {}s",
+                            program,
+                            timeout.as_secs_f64()
+                        )
+                    })?
+                    .map_err(|e| anyhow::anyhow!("Failed to run key command {}: {}", program, e))?;
+
+                if !status.success() {
+                    return Err(anyhow::anyhow!(
+                        "Key command {} failed ({}): {}",
+                        program,
+                        status,
+                        String::from_utf8_lossy(&stderr).trim()
+                    ));
+                }
+                if stdout.len() > MAX_KEY_COMMAND_OUTPUT {
+                    return Err(anyhow::anyhow!(
+                        "Key command {} printed more than {} bytes",
+                        program,
+                        MAX_KEY_COMMAND_OUTPUT
+                    ));
+                }
+                let stdout = String::from_utf8(stdout).map_err(|_| {
+                    anyhow::anyhow!("Key command {} printed invalid UTF-8", program)
+                })?;
+                let secret = trim_line_ending(&stdout);
+                if secret.is_empty() {
+                    return Err(anyhow::anyhow!("Key command {} printed nothing", program));
+                }
+                Ok(secret.to_string())
+            }
+        }
+    }
 }
 
-/// Load or initialize encryption key from database
-pub async fn load_or_init_encryption_key(
-    db_handle: &SlateDbHandle,
-    password: &str,
-) -> Result<[u8; 32]> {
-    let key_manager = KeyManager::new();
+fn trim_line_ending(s: &str) -> &str {
+    s.trim_end_matches(['\r', '\n'])
+}
 
-    // Check if wrapped key exists in database
+/// Read a stream to its end, keeping its first `limit` bytes and one more, so
+/// that longer output can be told apart
+async fn read_limited(
+    mut reader: impl AsyncRead + Unpin,
+    limit: usize,
+) -> std::io::Result<Vec<u8>> {
+    let mut kept = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(kept);
+        }
+        let room = (limit + 1).saturating_sub(kept.len());
+        kept.extend_from_slice(&buf[..n.min(room)]);
+    }
+}
+
+async fn read_raw(db_handle: &SlateDbHandle, key: &[u8]) -> Result<Option<Bytes>> {
     let read_options = ReadOptions {
         durability_filter: DurabilityLevel::Memory,
         ..Default::default()
     };
-    let existing_key = match db_handle {
-        SlateDbHandle::ReadWrite(db) => {
-            db.get_with_options(SYSTEM_WRAPPED_ENCRYPTION_KEY, &read_options)
-                .await?
-        }
+    Ok(match db_handle {
+        SlateDbHandle::ReadWrite(db) => db.get_with_options(key, &read_options).await?,
         SlateDbHandle::ReadOnly(reader_swap) => {
             let reader = reader_swap.load();
-            reader
-                .get_with_options(SYSTEM_WRAPPED_ENCRYPTION_KEY, &read_options)
-                .await?
+            reader.get_with_options(key, &read_options).await?
         }
-    };
+    })
+}
+
+/// The key slots, or the wrapped key of a filesystem created before key
+/// slots were introduced
+enum StoredKeys {
+    Slots(KeySlots),
+    Legacy(WrappedDataKey),
+}
 
-    match existing_key {
-        Some(data) => {
-            // Key exists, unwrap it
-            let wrapped_key: WrappedDataKey = bincode::deserialize(&data)
-                .map_err(|e| anyhow::anyhow!("Failed to deserialize wrapped key: {}", e))?;
+async fn read_stored_keys(db_handle: &SlateDbHandle) -> Result<Option<StoredKeys>> {
+    if let Some(data) = read_raw(db_handle, SYSTEM_KEY_SLOTS).await? {
+        let slots = bincode::deserialize(&data)
+            .map_err(|e| anyhow::anyhow!("Failed to deserialize key slots: {}", e))?;
+        return Ok(Some(StoredKeys::Slots(slots)));
+    }
 
-            let password = password.to_string();
-            spawn_blocking_named("argon2-unwrap", move || {
-                key_manager.unwrap_key(&password, &wrapped_key)
-            })
-            .await
-            .map_err(|e| anyhow::anyhow!("Task join error: {}", e))?
+    if let Some(data) = read_raw(db_handle, SYSTEM_WRAPPED_ENCRYPTION_KEY).await? {
+        let wrapped_key = bincode::deserialize(&data)
+            .map_err(|e| anyhow::anyhow!("Failed to deserialize wrapped key: {}", e))?;
+        return Ok(Some(StoredKeys::Legacy(wrapped_key)));
+    }
+
+    Ok(None)
+}
+
+/// Store the key slots, replacing the wrapped key of a filesystem created
+/// before key slots were introduced.
+async fn write_key_slots(db_handle: &SlateDbHandle, slots: &KeySlots) -> Result<()> {
+    let serialized = bincode::serialize(slots)
+        .map_err(|e| anyhow::anyhow!("Failed to serialize key slots: {}", e))?;
+
+    let mut batch = WriteBatch::new();
+    batch.put(SYSTEM_KEY_SLOTS, &serialized);
+    batch.delete(SYSTEM_WRAPPED_ENCRYPTION_KEY);
+
+    match db_handle {
+        SlateDbHandle::ReadWrite(db) => {
+            db.write_with_options(
+                batch,
+                &WriteOptions {
+                    await_durable: false,
+                },
+            )
+            .await?;
         }
+        SlateDbHandle::ReadOnly(_) => {
+            return Err(anyhow::anyhow!(
+                "Cannot write encryption keys in read-only mode"
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Unlock the key slots with `password`, let `update` change them, and store
+/// them. `update` gets the index of the slot the password opened and the
+/// master key.
+async fn update_key_slots<T: Send + 'static>(
+    db_handle: &SlateDbHandle,
+    password: &str,
+    update: impl FnOnce(&mut KeySlots, usize, &[u8; 32]) -> Result<T> + Send + 'static,
+) -> Result<T> {
+    if db_handle.is_read_only() {
+        return Err(anyhow::anyhow!(
+            "Cannot change encryption keys in read-only mode"
+        ));
+    }
+
+    let stored = read_stored_keys(db_handle)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("No encryption key found in database"))?;
+
+    let password = password.to_string();
+    let (slots, result) = spawn_blocking_named("argon2-unlock", move || {
+        let (mut slots, index, master_key) = match stored {
+            StoredKeys::Slots(slots) => {
+                let (index, master_key) = slots.unlock(&password)?;
+                (slots, index, master_key)
+            }
+            StoredKeys::Legacy(wrapped_key) => {
+                let keys = KeyManager::new().unwrap_data_keys(&password, &wrapped_key)?;
+                let (slots, master_key) = KeySlots::new(
+                    &password,
+                    DEFAULT_SLOT_LABEL,
+                    Argon2Params::default(),
+                    &keys,
+                )?;
+                (slots, 0, master_key)
+            }
+        };
+        let result = update(&mut slots, index, &master_key)?;
+        Ok::<_, anyhow::Error>((slots, result))
+    })
+    .await
+    .map_err(|e| anyhow::anyhow!("Task join error: {}", e))??;
+
+    write_key_slots(db_handle, &slots).await?;
+    Ok(result)
+}
+
+/// Load or initialize encryption key from database
+pub async fn load_or_init_encryption_key(
+    db_handle: &SlateDbHandle,
+    password: &str,
+) -> Result<DataKeys> {
+    let password = password.to_string();
+
+    // Check if the keys exist in database
+    match read_stored_keys(db_handle).await? {
+        Some(StoredKeys::Slots(slots)) => spawn_blocking_named("argon2-unwrap", move || {
+            let (_, master_key) = slots.unlock(&password)?;
+            slots.data_keys(&master_key)
+        })
+        .await
+        .map_err(|e| anyhow::anyhow!("Task join error: {}", e))?,
+        Some(StoredKeys::Legacy(wrapped_key)) => spawn_blocking_named("argon2-unwrap", move || {
+            KeyManager::new().unwrap_data_keys(&password, &wrapped_key)
+        })
+        .await
+        .map_err(|e| anyhow::anyhow!("Task join error: {}", e))?,
         None => {
             // First time setup - generate new key
             if db_handle.is_read_only() {
@@ -203,38 +666,31 @@ pub async fn load_or_init_encryption_key(
                 ));
             }
 
-            let password = password.to_string();
-            let (wrapped_key, dek) = spawn_blocking_named("argon2-generate", move || {
-                key_manager.generate_and_wrap_key(&password)
+            let mut dek = [0u8; 32];
+            thread_rng().fill_bytes(&mut dek);
+            let keys = DataKeys::new(dek);
+
+            let slot_keys = keys.clone();
+            let (slots, _) = spawn_blocking_named("argon2-generate", move || {
+                KeySlots::new(
+                    &password,
+                    DEFAULT_SLOT_LABEL,
+                    Argon2Params::default(),
+                    &slot_keys,
+                )
             })
             .await
             .map_err(|e| anyhow::anyhow!("Task join error: {}", e))??;
 
-            // Store wrapped key in database
-            let serialized = bincode::serialize(&wrapped_key)
-                .map_err(|e| anyhow::anyhow!("Failed to serialize wrapped key: {}", e))?;
-
-            match db_handle {
-                SlateDbHandle::ReadWrite(db) => {
-                    db.put_with_options(
-                        SYSTEM_WRAPPED_ENCRYPTION_KEY,
-                        &serialized,
-                        &slatedb::config::PutOptions::default(),
-                        &slatedb::config::WriteOptions {
-                            await_durable: false,
-                        },
-                    )
-                    .await?;
-                }
-                SlateDbHandle::ReadOnly(_) => unreachable!("Already checked read-only above"),
-            }
+            // Store key slots in database
+            write_key_slots(db_handle, &slots).await?;
 
-            Ok(dek)
+            Ok(keys)
         }
     }
 }
 
-/// Change the password used to encrypt the DEK
+/// Change the password of the key slot `old_password` opens
 pub async fn change_encryption_password(
     db_handle: &SlateDbHandle,
     old_password: &str,
@@ -244,62 +700,91 @@ pub async fn change_encryption_password(
         return Err(anyhow::anyhow!("Cannot change password in read-only mode"));
     }
 
-    let key_manager = KeyManager::new();
+    let new_password = new_password.to_string();
+    update_key_slots(db_handle, old_password, move |slots, index, master_key| {
+        slots.set_password(index, master_key, &new_password)
+    })
+    .await
+}
 
-    let read_options = ReadOptions {
-        durability_filter: DurabilityLevel::Memory,
-        ..Default::default()
-    };
-    let data = match db_handle {
-        SlateDbHandle::ReadWrite(db) => {
-            db.get_with_options(SYSTEM_WRAPPED_ENCRYPTION_KEY, &read_options)
-                .await?
-        }
-        SlateDbHandle::ReadOnly(reader_swap) => {
-            let reader = reader_swap.load();
-            reader
-                .get_with_options(SYSTEM_WRAPPED_ENCRYPTION_KEY, &read_options)
-                .await?
-        }
-    }
-    .ok_or_else(|| anyhow::anyhow!("No encryption key found in database"))?;
+/// Generate a new data encryption key and make it the one new values are
+/// encrypted with. Older generations stay readable; the server re-encrypts
+/// existing values in the background and retires them once it is done.
+pub async fn rotate_data_key(db_handle: &SlateDbHandle, password: &str) -> Result<KeyId> {
+    update_key_slots(db_handle, password, |slots, _, master_key| {
+        let mut keys = slots.data_keys(master_key)?;
+        let key_id = keys.rotate();
+        slots.set_data_keys(master_key, &keys)?;
+        Ok(key_id)
+    })
+    .await
+}
 
-    let wrapped_key: WrappedDataKey = bincode::deserialize(&data)
-        .map_err(|e| anyhow::anyhow!("Failed to deserialize wrapped key: {}", e))?;
+/// Drop the key generations no stored value is encrypted with anymore
+pub async fn retire_old_keys(db_handle: &SlateDbHandle, password: &str) -> Result<()> {
+    update_key_slots(db_handle, password, |slots, _, master_key| {
+        let mut keys = slots.data_keys(master_key)?;
+        keys.retire_old();
+        slots.set_data_keys(master_key, &keys)
+    })
+    .await
+}
 
-    let old_password = old_password.to_string();
+/// Add a key slot opened by `new_password`. `password` must open an
+/// existing slot.
+pub async fn add_key_slot(
+    db_handle: &SlateDbHandle,
+    password: &str,
+    label: &str,
+    new_password: &str,
+    params: Argon2Params,
+) -> Result<()> {
+    let label = label.to_string();
     let new_password = new_password.to_string();
-    let new_wrapped_key = spawn_blocking_named("argon2-rewrap", move || {
-        key_manager.rewrap_key(&old_password, &new_password, &wrapped_key)
+    update_key_slots(db_handle, password, move |slots, _, master_key| {
+        slots.add_slot(master_key, &label, &new_password, params)
     })
     .await
-    .map_err(|e| anyhow::anyhow!("Task join error: {}", e))??;
+}
 
-    let serialized = bincode::serialize(&new_wrapped_key)
-        .map_err(|e| anyhow::anyhow!("Failed to serialize wrapped key: {}", e))?;
+/// Revoke a key slot. `password` must open one of the slots.
+pub async fn revoke_key_slot(db_handle: &SlateDbHandle, password: &str, label: &str) -> Result<()> {
+    let label = label.to_string();
+    update_key_slots(db_handle, password, move |slots, _, _| {
+        slots.remove_slot(&label)
+    })
+    .await
+}
 
-    match db_handle {
-        SlateDbHandle::ReadWrite(db) => {
-            db.put_with_options(
-                SYSTEM_WRAPPED_ENCRYPTION_KEY,
-                &serialized,
-                &slatedb::config::PutOptions::default(),
-                &slatedb::config::WriteOptions {
-                    await_durable: false,
-                },
-            )
-            .await?;
-        }
-        SlateDbHandle::ReadOnly(_) => unreachable!("Already checked read-only above"),
+/// Labels and Argon2 parameters of the key slots
+pub async fn list_key_slots(db_handle: &SlateDbHandle) -> Result<Vec<(String, Argon2Params)>> {
+    match read_stored_keys(db_handle).await? {
+        Some(StoredKeys::Slots(slots)) => Ok(slots
+            .slots
+            .into_iter()
+            .map(|slot| (slot.label, slot.params))
+            .collect()),
+        Some(StoredKeys::Legacy(_)) => Ok(vec![(
+            DEFAULT_SLOT_LABEL.to_string(),
+            Argon2Params::default(),
+        )]),
+        None => Err(anyhow::anyhow!("No encryption key found in database")),
     }
-
-    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Cheap parameters, so tests don't spend their time in Argon2
+    fn test_params() -> Argon2Params {
+        Argon2Params {
+            memory_kib: 1024,
+            iterations: 1,
+            parallelism: 1,
+        }
+    }
+
     #[test]
     fn test_key_wrap_unwrap() {
         let key_manager = KeyManager::new();
@@ -338,31 +823,149 @@ mod tests {
 
     #[test]
     fn test_password_change() {
-        let key_manager = KeyManager::new();
         let old_password = "old_password";
         let new_password = "new_password";
 
-        let (wrapped_key, original_dek) = key_manager
-            .generate_and_wrap_key(old_password)
-            .expect("Failed to generate and wrap key");
+        let keys = DataKeys::new([3u8; 32]);
+        let (mut slots, master_key) =
+            KeySlots::new(old_password, DEFAULT_SLOT_LABEL, test_params(), &keys)
+                .expect("Failed to create key slots");
 
         // Change password
-        let new_wrapped_key = key_manager
-            .rewrap_key(old_password, new_password, &wrapped_key)
+        let (index, _) = slots.unlock(old_password).unwrap();
+        slots
+            .set_password(index, &master_key, new_password)
             .expect("Failed to rewrap key");
 
         // Old password should not work
+        assert!(slots.unlock(old_password).is_err());
+
+        // New password should work
+        let (_, unlocked) = slots
+            .unlock(new_password)
+            .expect("Failed to unwrap with new password");
+
+        assert!(slots.data_keys(&unlocked).unwrap() == keys);
+    }
+
+    #[test]
+    fn test_single_key_still_unwraps() {
+        let key_manager = KeyManager::new();
+        let password = "legacy_password";
+        let dek = [7u8; 32];
+
+        let wrapped_key = key_manager.wrap_key(password, &dek).unwrap();
+        let keys = key_manager
+            .unwrap_data_keys(password, &wrapped_key)
+            .unwrap();
+        assert!(keys == DataKeys::new(dek));
+    }
+
+    #[test]
+    fn test_key_slots() {
+        let keys = DataKeys::new([5u8; 32]);
+        let (mut slots, master_key) =
+            KeySlots::new("first_password", "ops", test_params(), &keys).unwrap();
+
+        slots
+            .add_slot(&master_key, "backup", "second_password", test_params())
+            .unwrap();
         assert!(
-            key_manager
-                .unwrap_key(old_password, &new_wrapped_key)
+            slots
+                .add_slot(&master_key, "backup", "third_password", test_params())
                 .is_err()
         );
 
-        // New password should work
-        let unwrapped_dek = key_manager
-            .unwrap_key(new_password, &new_wrapped_key)
-            .expect("Failed to unwrap with new password");
+        // Either password opens the same keys
+        let (index, unlocked) = slots.unlock("second_password").unwrap();
+        assert_eq!(index, 1);
+        assert!(slots.data_keys(&unlocked).unwrap() == keys);
+        assert!(slots.unlock("wrong_password").is_err());
+
+        // Rotating the data keys leaves the slots alone
+        let mut rotated = keys.clone();
+        rotated.rotate();
+        slots.set_data_keys(&master_key, &rotated).unwrap();
+        let (_, unlocked) = slots.unlock("first_password").unwrap();
+        assert!(slots.data_keys(&unlocked).unwrap() == rotated);
+
+        slots.remove_slot("ops").unwrap();
+        assert!(slots.unlock("first_password").is_err());
+        assert!(slots.unlock("second_password").is_ok());
+        assert!(slots.remove_slot("backup").is_err());
+    }
 
-        assert_eq!(original_dek, unwrapped_dek);
+    #[tokio::test]
+    async fn test_key_providers() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let password_file = dir.path().join("password");
+        std::fs::write(&password_file, "file_password\n").unwrap();
+        assert_eq!(
+            KeyProvider::PasswordFile(password_file)
+                .secret()
+                .await
+                .unwrap(),
+            "file_password"
+        );
+
+        let key_file = dir.path().join("key");
+        std::fs::write(&key_file, [0xabu8; 32]).unwrap();
+        assert_eq!(
+            KeyProvider::KeyFile(key_file.clone())
+                .secret()
+                .await
+                .unwrap(),
+            "ab".repeat(32)
+        );
+        std::fs::write(&key_file, [0xabu8; 31]).unwrap();
+        assert!(
+            KeyProvider::KeyFile(key_file.clone())
+                .secret()
+                .await
+                .is_err()
+        );
+        std::fs::write(&key_file, "a 32 character password here!!!\n").unwrap();
+        assert!(KeyProvider::KeyFile(key_file).secret().await.is_err());
+        assert!(
+            KeyProvider::PasswordFile(dir.path().join("missing"))
+                .secret()
+                .await
+                .is_err()
+        );
+
+        let command = |args: &[&str]| KeyProvider::Command {
+            argv: args.iter().map(|s| s.to_string()).collect(),
+            timeout: Duration::from_secs(5),
+        };
+        assert_eq!(
+            command(&["echo", "command_password"])
+                .secret()
+                .await
+                .unwrap(),
+            "command_password"
+        );
+        assert!(command(&["false"]).secret().await.is_err());
+        assert!(command(&["true"]).secret().await.is_err());
+        assert!(command(&[]).secret().await.is_err());
+
+        let error = command(&["sh", "-c", "echo denied >&2; exit 3"])
+            .secret()
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("denied"));
+        assert!(
+            command(&["sh", "-c", "head -c 100000 /dev/zero | tr '\\0' x"])
+                .secret()
+                .await
+                .is_err()
+        );
+
+        let slow = KeyProvider::Command {
+            argv: vec!["sleep".to_string(), "10".to_string()],
+            timeout: Duration::from_millis(100),
+        };
+        let error = slow.secret().await.unwrap_err();
+        assert!(error.to_string().contains("timed out"));
     }
 }
@@ -81,6 +81,52 @@ async fn main() -> Result<()> {
                 }
             }
         }
+        cli::Commands::RotateKey { config } => {
+            eprintln!("Rotating data encryption key...");
+            match cli::keys::rotate_key(&config).await {
+                Ok(key_id) => {
+                    println!("✓ Data encryption key rotated (key generation {})", key_id);
+                    println!(
+                        "ℹ The server uses the new key, and re-encrypts existing data in the \
+                         background, once it restarts"
+                    );
+                }
+                Err(e) => {
+                    eprintln!("✗ Error: {:#}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        cli::Commands::KeySlot { subcommand } => match subcommand {
+            cli::KeySlotCommands::Add {
+                config,
+                label,
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                eprintln!("Reading password for key slot '{}' from stdin...", label);
+                let mut new_password = String::new();
+                std::io::stdin()
+                    .lock()
+                    .read_line(&mut new_password)
+                    .context("Failed to read password from stdin")?;
+                let params = key_management::Argon2Params {
+                    memory_kib,
+                    iterations,
+                    parallelism,
+                };
+                cli::keys::add_key_slot(&config, &label, new_password.trim(), params).await?;
+                println!("✓ Key slot '{}' added", label);
+            }
+            cli::KeySlotCommands::List { config } => {
+                cli::keys::list_key_slots(&config).await?;
+            }
+            cli::KeySlotCommands::Revoke { config, label } => {
+                cli::keys::revoke_key_slot(&config, &label).await?;
+                println!("✓ Key slot '{}' revoked", label);
+            }
+        },
         cli::Commands::Run {
             config,
             read_only,
@@ -216,31 +262,132 @@ async fn main() -> Result<()> {
             }
         },
         cli::Commands::Dataset { subcommand } => match subcommand {
-            cli::DatasetCommands::Create { config, name } => {
-                cli::dataset::create_dataset(&config, &name).await?;
+            cli::DatasetCommands::Create {
+                config,
+                name,
+                encrypt,
+            } => {
+                cli::dataset::create_dataset(&config, &name, encrypt).await?;
             }
-            cli::DatasetCommands::List { config } => {
-                cli::dataset::list_datasets(&config).await?;
+            cli::DatasetCommands::List { config, tree } => {
+                cli::dataset::list_datasets(&config, tree).await?;
             }
-            cli::DatasetCommands::Delete { config, name } => {
-                cli::dataset::delete_dataset(&config, &name).await?;
+            cli::DatasetCommands::Delete {
+                config,
+                name,
+                recursive,
+            } => {
+                cli::dataset::delete_dataset(&config, &name, recursive).await?;
+            }
+            cli::DatasetCommands::Destroys { config } => {
+                cli::dataset::list_dataset_destroys(&config).await?;
             }
             cli::DatasetCommands::Info { config, name } => {
                 cli::dataset::get_dataset_info(&config, &name).await?;
             }
+            cli::DatasetCommands::Quota {
+                config,
+                name,
+                bytes,
+                bytes_soft,
+                inodes,
+                inodes_soft,
+            } => {
+                let quota = fs::dataset::DatasetQuota {
+                    bytes_hard: bytes,
+                    bytes_soft,
+                    inodes_hard: inodes,
+                    inodes_soft,
+                };
+                cli::dataset::set_dataset_quota(&config, &name, quota).await?;
+            }
+            cli::DatasetCommands::Get {
+                config,
+                name,
+                property,
+            } => {
+                cli::dataset::get_dataset_properties(&config, &name, property.as_deref()).await?;
+            }
+            cli::DatasetCommands::Set {
+                config,
+                name,
+                assignment,
+            } => {
+                cli::dataset::set_dataset_property(&config, &name, &assignment).await?;
+            }
+            cli::DatasetCommands::Rename {
+                config,
+                name,
+                new_name,
+            } => {
+                cli::dataset::rename_dataset(&config, &name, &new_name).await?;
+            }
             cli::DatasetCommands::Snapshot {
                 config,
                 source,
                 name,
                 readonly,
+                recursive,
             } => {
-                cli::dataset::create_snapshot(&config, &source, &name, readonly).await?;
+                cli::dataset::create_snapshot(&config, &source, &name, readonly, recursive).await?;
             }
             cli::DatasetCommands::ListSnapshots { config } => {
                 cli::dataset::list_snapshots(&config).await?;
             }
-            cli::DatasetCommands::DeleteSnapshot { config, name } => {
-                cli::dataset::delete_snapshot(&config, &name).await?;
+            cli::DatasetCommands::DeleteSnapshot {
+                config,
+                name,
+                recursive,
+            } => {
+                cli::dataset::delete_snapshot(&config, &name, recursive).await?;
+            }
+            cli::DatasetCommands::Hold {
+                config,
+                snapshot,
+                tag,
+            } => {
+                cli::dataset::hold_snapshot(&config, &snapshot, &tag).await?;
+            }
+            cli::DatasetCommands::Release {
+                config,
+                snapshot,
+                tag,
+            } => {
+                cli::dataset::release_snapshot(&config, &snapshot, &tag).await?;
+            }
+            cli::DatasetCommands::Rollback {
+                config,
+                dataset,
+                snapshot,
+                destroy_newer,
+            } => {
+                cli::dataset::rollback_dataset(&config, &dataset, &snapshot, destroy_newer).await?;
+            }
+            cli::DatasetCommands::Diff {
+                config,
+                snapshot,
+                to,
+            } => {
+                cli::dataset::diff_snapshots(&config, &snapshot, to.as_deref()).await?;
+            }
+            cli::DatasetCommands::Send {
+                config,
+                snapshot,
+                from,
+                output,
+            } => {
+                cli::dataset::send_snapshot(&config, &snapshot, from.as_deref(), output.as_deref())
+                    .await?;
+            }
+            cli::DatasetCommands::Receive {
+                config,
+                dataset,
+                input,
+                encrypt,
+                force,
+            } => {
+                cli::dataset::receive_snapshot(&config, &dataset, input.as_deref(), encrypt, force)
+                    .await?;
             }
             cli::DatasetCommands::SetDefault { config, name } => {
                 cli::dataset::set_default_dataset(&config, &name).await?;
@@ -253,9 +400,44 @@ async fn main() -> Result<()> {
                 snapshot,
                 source,
                 destination,
+                overwrite,
+                rename,
+                dry_run,
             } => {
-                cli::dataset::restore_from_snapshot(&config, &snapshot, &source, &destination)
-                    .await?;
+                let on_conflict = match (overwrite, rename) {
+                    (true, _) => fs::restore::OnConflict::Overwrite,
+                    (false, true) => fs::restore::OnConflict::Rename,
+                    (false, false) => fs::restore::OnConflict::Fail,
+                };
+                cli::dataset::restore_from_snapshot(
+                    &config,
+                    &snapshot,
+                    &source,
+                    &destination,
+                    on_conflict,
+                    dry_run,
+                )
+                .await?;
+            }
+        },
+        cli::Commands::Quota { subcommand } => match subcommand {
+            cli::QuotaCommands::Report { config } => {
+                cli::quota::quota_report(&config).await?;
+            }
+            cli::QuotaCommands::Set {
+                config,
+                user,
+                group,
+                bytes,
+                inodes,
+            } => {
+                let owner = match (user, group) {
+                    (Some(uid), _) => fs::quota::Owner::User(uid),
+                    (None, Some(gid)) => fs::quota::Owner::Group(gid),
+                    (None, None) => anyhow::bail!("Either --user or --group is required"),
+                };
+                let quota = fs::quota::OwnerQuota { bytes, inodes };
+                cli::quota::set_quota(&config, owner, quota).await?;
             }
         },
         cli::Commands::Clone {
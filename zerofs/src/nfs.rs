@@ -1,4 +1,6 @@
 use crate::fs::ZeroFS;
+use crate::fs::dataset_ops;
+use crate::fs::destroy;
 use crate::fs::inode::Inode;
 use crate::fs::permissions::Credentials;
 use crate::fs::types::{FileType, InodeWithId, SetAttributes};
@@ -22,6 +24,14 @@ impl NFSAdapter {
     pub fn new(fs: Arc<ZeroFS>) -> Self {
         Self { fs }
     }
+
+    /// Refuse handles into a tree detached by a dataset destroy or rollback
+    async fn check_handles(&self, ids: &[fileid3]) -> Result<(), nfsstat3> {
+        for &id in ids {
+            destroy::check_attached(&self.fs, id).await?;
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -46,6 +56,8 @@ impl NFSFileSystem for NFSAdapter {
             String::from_utf8_lossy(filename)
         );
 
+        self.check_handles(&[dirid]).await?;
+
         let auth_ctx: crate::fs::types::AuthContext = auth.into();
         let creds = Credentials::from_auth_context(&auth_ctx);
 
@@ -55,6 +67,7 @@ impl NFSFileSystem for NFSAdapter {
 
     async fn getattr(&self, _auth: &NfsAuthContext, id: fileid3) -> Result<fattr3, nfsstat3> {
         debug!("getattr called: id={}", id);
+        self.check_handles(&[id]).await?;
         let inode = self.fs.get_inode(id).await?;
         Ok(InodeWithId { inode: &inode, id }.into())
     }
@@ -67,6 +80,7 @@ impl NFSFileSystem for NFSAdapter {
         count: u32,
     ) -> Result<(Vec<u8>, bool), nfsstat3> {
         debug!("read called: id={}, offset={}, count={}", id, offset, count);
+        self.check_handles(&[id]).await?;
         let auth_ctx: crate::fs::types::AuthContext = auth.into();
         self.fs
             .read_file(&auth_ctx, id, offset, count)
@@ -89,6 +103,8 @@ impl NFSFileSystem for NFSAdapter {
             offset
         );
 
+        self.check_handles(&[id]).await?;
+
         let auth_ctx: crate::fs::types::AuthContext = auth.into();
         let data_bytes = bytes::Bytes::copy_from_slice(data);
         let file_attrs: crate::fs::types::FileAttributes =
@@ -109,6 +125,8 @@ impl NFSFileSystem for NFSAdapter {
             String::from_utf8_lossy(filename)
         );
 
+        self.check_handles(&[dirid]).await?;
+
         let auth_ctx: crate::fs::types::AuthContext = auth.into();
         let creds = Credentials::from_auth_context(&auth_ctx);
         let fs_attr = SetAttributes::from(attr);
@@ -130,6 +148,8 @@ impl NFSFileSystem for NFSAdapter {
             dirid, filename
         );
 
+        self.check_handles(&[dirid]).await?;
+
         let id = self
             .fs
             .create_exclusive(&auth.into(), dirid, filename)
@@ -151,6 +171,8 @@ impl NFSFileSystem for NFSAdapter {
             String::from_utf8_lossy(dirname)
         );
 
+        self.check_handles(&[dirid]).await?;
+
         let auth_ctx: crate::fs::types::AuthContext = auth.into();
         let creds = Credentials::from_auth_context(&auth_ctx);
         let fs_attr = SetAttributes::from(*attr);
@@ -167,6 +189,8 @@ impl NFSFileSystem for NFSAdapter {
     ) -> Result<(), nfsstat3> {
         debug!("remove called: dirid={}, filename={:?}", dirid, filename);
 
+        self.check_handles(&[dirid]).await?;
+
         let auth_ctx: crate::fs::types::AuthContext = auth.into();
         Ok(self.fs.remove(&auth_ctx, dirid, filename).await?)
     }
@@ -184,6 +208,8 @@ impl NFSFileSystem for NFSAdapter {
             from_dirid, to_dirid
         );
 
+        self.check_handles(&[from_dirid, to_dirid]).await?;
+
         self.fs
             .rename(
                 &auth.into(),
@@ -208,6 +234,8 @@ impl NFSFileSystem for NFSAdapter {
             dirid, start_after, max_entries
         );
 
+        self.check_handles(&[dirid]).await?;
+
         let result = self
             .fs
             .readdir(&auth.into(), dirid, start_after, max_entries)
@@ -243,6 +271,8 @@ impl NFSFileSystem for NFSAdapter {
     ) -> Result<fattr3, nfsstat3> {
         debug!("setattr called: id={}, setattr={:?}", id, setattr);
 
+        self.check_handles(&[id]).await?;
+
         let auth_ctx: crate::fs::types::AuthContext = auth.into();
         let creds = Credentials::from_auth_context(&auth_ctx);
         let fs_attr = SetAttributes::from(setattr);
@@ -263,6 +293,8 @@ impl NFSFileSystem for NFSAdapter {
             dirid, linkname, symlink
         );
 
+        self.check_handles(&[dirid]).await?;
+
         let auth_ctx: crate::fs::types::AuthContext = auth.into();
         let creds = Credentials::from_auth_context(&auth_ctx);
         let fs_attr = SetAttributes::from(*attr);
@@ -278,6 +310,8 @@ impl NFSFileSystem for NFSAdapter {
     async fn readlink(&self, _auth: &NfsAuthContext, id: fileid3) -> Result<nfspath3, nfsstat3> {
         debug!("readlink called: id={}", id);
 
+        self.check_handles(&[id]).await?;
+
         let inode = self.fs.get_inode(id).await?;
 
         match inode {
@@ -300,6 +334,8 @@ impl NFSFileSystem for NFSAdapter {
             dirid, filename, ftype
         );
 
+        self.check_handles(&[dirid]).await?;
+
         let rdev = match ftype {
             ftype3::NF3CHR | ftype3::NF3BLK => spec.map(|s| (s.specdata1, s.specdata2)),
             _ => None,
@@ -329,6 +365,8 @@ impl NFSFileSystem for NFSAdapter {
             fileid, linkdirid, linkname
         );
 
+        self.check_handles(&[fileid, linkdirid]).await?;
+
         Ok(self
             .fs
             .link(&auth.into(), fileid, linkdirid, &linkname.0)
@@ -373,16 +411,29 @@ impl NFSFileSystem for NFSAdapter {
             }
         };
 
-        let (used_bytes, used_inodes) = self.fs.global_stats.get_totals();
+        let (allocated_bytes, used_inodes) = self.fs.global_stats.get_totals();
+        let mut used_bytes = self.fs.db.space_stats().totals().footprint(allocated_bytes);
 
         let next_inode_id = self.fs.inode_store.next_id();
-        let available_inodes = u64::MAX.saturating_sub(next_inode_id);
-        let total_inodes = used_inodes + available_inodes;
+        let mut available_inodes = u64::MAX.saturating_sub(next_inode_id);
+        let mut total_inodes = used_inodes + available_inodes;
 
         // Use configured max_bytes from filesystem config, capped at 8 EiB
         // to avoid breaking NFS clients that can't handle larger values
         const MAX_NFS_BYTES: u64 = 8 * (1 << 60); // 8 EiB
-        let total_bytes = self.fs.max_bytes.min(MAX_NFS_BYTES);
+        let mut total_bytes = self.fs.max_bytes.min(MAX_NFS_BYTES);
+
+        // Inside a dataset with quotas, report the dataset's limits
+        if let Ok(Some((quota, usage))) = dataset_ops::dataset_limits(&self.fs, fileid).await {
+            if let Some(limit) = quota.bytes_hard {
+                total_bytes = total_bytes.min(limit);
+                used_bytes = usage.bytes;
+            }
+            if let Some(limit) = quota.inodes_hard {
+                total_inodes = limit;
+                available_inodes = available_inodes.min(limit.saturating_sub(usage.inodes));
+            }
+        }
         let free_bytes = total_bytes.saturating_sub(used_bytes);
 
         let res = fsstat3 {
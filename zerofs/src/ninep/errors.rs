@@ -13,7 +13,6 @@ pub enum P9Error {
     NotASymlink,
     InvalidDeviceType,
     LockConflict,
-    NotSupported,
     NotImplemented,
     Fs(FsError),
 }
@@ -33,7 +32,6 @@ impl P9Error {
             P9Error::NotADirectory => libc::ENOTDIR as u32,
             P9Error::IsADirectory => libc::EISDIR as u32,
             P9Error::LockConflict => libc::EAGAIN as u32,
-            P9Error::NotSupported => libc::ENOTSUP as u32,
             P9Error::NotImplemented => libc::ENOSYS as u32,
             P9Error::Fs(e) => e.to_errno(),
         }
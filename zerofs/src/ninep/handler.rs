@@ -3,13 +3,17 @@ use super::lock_manager::{FileLock, FileLockManager};
 use super::protocol::*;
 use super::protocol::{P9_MAX_GROUPS, P9_MAX_NAME_LEN, P9_NOBODY_UID, P9_READDIR_BATCH_SIZE};
 use crate::deku_bytes::DekuBytes;
-use crate::fs::ZeroFS;
+use crate::fs::dataset_ops;
+use crate::fs::destroy;
+use crate::fs::errors::FsError;
 use crate::fs::inode::{Inode, InodeAttrs, InodeId};
 use crate::fs::permissions::Credentials;
+use crate::fs::store::xattr::XATTR_SIZE_MAX;
 use crate::fs::types::{
     AuthContext, FileAttributes, FileType, SetAttributes, SetGid, SetMode, SetSize, SetTime,
     SetUid, Timestamp,
 };
+use crate::fs::{XATTR_REPLACE, ZeroFS};
 use bytes::Bytes;
 use dashmap::DashMap;
 use deku::DekuContainerWrite;
@@ -56,6 +60,21 @@ pub struct Fid {
     pub opened: bool,
     pub mode: Option<u32>,
     pub creds: Credentials, // Store credentials per fid/session
+    pub xattr: Option<XattrFid>,
+}
+
+/// Extended attribute transfer on a fid set up by Txattrwalk or Txattrcreate
+#[derive(Debug, Clone)]
+pub enum XattrFid {
+    /// Attribute value, or null-separated list of names, returned by Tread
+    Read(Bytes),
+    /// Value of `size` bytes sent by Twrite, set when the fid is clunked
+    Write {
+        name: Vec<u8>,
+        flags: u32,
+        size: usize,
+        value: Vec<u8>,
+    },
 }
 
 #[derive(Debug)]
@@ -154,6 +173,14 @@ impl NinePHandler {
             .map(|f| f.clone())
     }
 
+    /// `get_fid`, refusing fids into a tree detached by a dataset destroy or
+    /// rollback with `ESTALE`
+    async fn live_fid(&self, fid: u32) -> P9Result<Fid> {
+        let fid = self.get_fid(fid)?;
+        destroy::check_attached(&self.filesystem, fid.inode_id).await?;
+        Ok(fid)
+    }
+
     pub async fn handle_message(&self, tag: u16, msg: Message) -> P9Message {
         let result = match msg {
             Message::Tversion(tv) => self.version(tv).await,
@@ -163,7 +190,7 @@ impl NinePHandler {
             Message::Tlcreate(tc) => self.lcreate(tc).await,
             Message::Tread(tr) => self.read(tr).await,
             Message::Twrite(tw) => self.write(tw).await,
-            Message::Tclunk(tc) => Ok(self.clunk(tc).await),
+            Message::Tclunk(tc) => self.clunk(tc).await,
             Message::Treaddir(tr) => self.readdir(tr).await,
             Message::Tgetattr(tg) => self.getattr(tg).await,
             Message::Tsetattr(ts) => self.setattr(ts).await,
@@ -177,7 +204,8 @@ impl NinePHandler {
             Message::Tunlinkat(tu) => self.unlinkat(tu).await,
             Message::Tfsync(tf) => self.fsync(tf).await,
             Message::Tflush(_) => Ok(Message::Rflush(Rflush)),
-            Message::Txattrwalk(_) => Err(P9Error::NotSupported),
+            Message::Txattrwalk(tx) => self.xattrwalk(tx).await,
+            Message::Txattrcreate(tx) => self.xattrcreate(tx),
             Message::Tstatfs(ts) => self.statfs(ts).await,
             Message::Tlock(tl) => self.lock(tl).await,
             Message::Tgetlock(tg) => self.getlock(tg).await,
@@ -286,6 +314,7 @@ impl NinePHandler {
                 opened: false,
                 mode: None,
                 creds,
+                xattr: None,
             },
         );
 
@@ -293,7 +322,7 @@ impl NinePHandler {
     }
 
     async fn walk(&self, tw: Twalk) -> P9Result<Message> {
-        let src_fid = self.get_fid(tw.fid)?;
+        let src_fid = self.live_fid(tw.fid).await?;
 
         let mut current_path = src_fid.path.clone();
         let mut current_id = src_fid.inode_id;
@@ -355,6 +384,7 @@ impl NinePHandler {
                 opened: false,
                 mode: None,
                 creds: src_fid.creds, // Inherit credentials from source fid
+                xattr: None,
             };
             self.session.fids.insert(tw.newfid, new_fid);
         }
@@ -366,7 +396,7 @@ impl NinePHandler {
     }
 
     async fn lopen(&self, tl: Tlopen) -> P9Result<Message> {
-        let fid_entry = self.get_fid(tl.fid)?;
+        let fid_entry = self.live_fid(tl.fid).await?;
 
         if fid_entry.opened {
             return Err(P9Error::FidAlreadyOpen);
@@ -396,17 +426,105 @@ impl NinePHandler {
         }))
     }
 
-    async fn clunk(&self, tc: Tclunk) -> Message {
-        if let Some((_, fid_entry)) = self.session.fids.remove(&tc.fid) {
-            self.lock_manager
-                .unlock_range(fid_entry.inode_id, tc.fid, 0, 0, self.handler_id)
-                .await;
+    async fn clunk(&self, tc: Tclunk) -> P9Result<Message> {
+        let Some((_, fid_entry)) = self.session.fids.remove(&tc.fid) else {
+            return Ok(Message::Rclunk(Rclunk));
+        };
+
+        self.lock_manager
+            .unlock_range(fid_entry.inode_id, tc.fid, 0, 0, self.handler_id)
+            .await;
+
+        if let Some(XattrFid::Write {
+            name,
+            flags,
+            size,
+            value,
+        }) = fid_entry.xattr
+        {
+            if value.len() != size {
+                return Err(P9Error::InvalidArgument);
+            }
+            // Linux clients remove an attribute by setting an empty value
+            // with XATTR_REPLACE
+            if size == 0 && flags == XATTR_REPLACE {
+                self.filesystem
+                    .removexattr(&fid_entry.creds, fid_entry.inode_id, &name)
+                    .await?;
+            } else {
+                self.filesystem
+                    .setxattr(&fid_entry.creds, fid_entry.inode_id, &name, &value, flags)
+                    .await?;
+            }
         }
-        Message::Rclunk(Rclunk)
+
+        Ok(Message::Rclunk(Rclunk))
+    }
+
+    /// Fetch an extended attribute, or the list of names when `name` is
+    /// empty, for reading through `newfid`.
+    async fn xattrwalk(&self, tx: Txattrwalk) -> P9Result<Message> {
+        let fid_entry = self.live_fid(tx.fid).await?;
+
+        if tx.newfid != tx.fid && self.session.fids.contains_key(&tx.newfid) {
+            return Err(P9Error::FidInUse);
+        }
+
+        let data = if tx.name.data.is_empty() {
+            let names = self
+                .filesystem
+                .listxattr(&fid_entry.creds, fid_entry.inode_id)
+                .await?;
+            let mut list = Vec::new();
+            for name in names {
+                list.extend_from_slice(&name);
+                list.push(0);
+            }
+            list
+        } else {
+            self.filesystem
+                .getxattr(&fid_entry.creds, fid_entry.inode_id, &tx.name.data)
+                .await?
+        };
+
+        let size = data.len() as u64;
+        self.session.fids.insert(
+            tx.newfid,
+            Fid {
+                opened: false,
+                mode: None,
+                xattr: Some(XattrFid::Read(Bytes::from(data))),
+                ..fid_entry
+            },
+        );
+
+        Ok(Message::Rxattrwalk(Rxattrwalk { size }))
+    }
+
+    /// Turn `fid` into one receiving the value of an extended attribute
+    fn xattrcreate(&self, tx: Txattrcreate) -> P9Result<Message> {
+        if tx.attr_size > XATTR_SIZE_MAX as u64 {
+            return Err(FsError::NoSpace.into());
+        }
+
+        let mut fid_entry = self.session.fids.get_mut(&tx.fid).ok_or(P9Error::BadFid)?;
+        if fid_entry.opened {
+            return Err(P9Error::FidAlreadyOpen);
+        }
+
+        let size = tx.attr_size as usize;
+        fid_entry.xattr = Some(XattrFid::Write {
+            name: tx.name.data,
+            flags: tx.flags,
+            size,
+            value: Vec::with_capacity(size),
+        });
+
+        Ok(Message::Rxattrcreate(Rxattrcreate))
     }
 
     async fn readdir(&self, tr: Treaddir) -> P9Result<Message> {
-        let fid_entry = self.get_fid(tr.fid)?;
+        let fid_entry = self.live_fid(tr.fid).await?;
 
         if !fid_entry.opened {
             return Err(P9Error::FidNotOpen);
@@ -456,7 +574,7 @@ impl NinePHandler {
     }
 
     async fn lcreate(&self, tc: Tlcreate) -> P9Result<Message> {
-        let parent_fid = self.get_fid(tc.fid)?;
+        let parent_fid = self.live_fid(tc.fid).await?;
 
         if parent_fid.opened {
             return Err(P9Error::FidAlreadyOpen);
@@ -493,17 +611,26 @@ impl NinePHandler {
     }
 
     async fn read(&self, tr: Tread) -> P9Result<Message> {
-        let fid_entry = self.get_fid(tr.fid)?;
-
-        if !fid_entry.opened {
-            return Err(P9Error::FidNotOpen);
-        }
+        let fid_entry = self.live_fid(tr.fid).await?;
 
         // Clamp count to fit response within negotiated msize
         let msize = self.session.msize.load(AtomicOrdering::Relaxed);
         let max_count = msize.saturating_sub(P9_IOHDRSZ);
         let count = tr.count.min(max_count);
 
+        if let Some(XattrFid::Read(data)) = &fid_entry.xattr {
+            let start = tr.offset.min(data.len() as u64) as usize;
+            let end = start.saturating_add(count as usize).min(data.len());
+            return Ok(Message::Rread(Rread {
+                count: (end - start) as u32,
+                data: DekuBytes::from(data.slice(start..end)),
+            }));
+        }
+
+        if !fid_entry.opened {
+            return Err(P9Error::FidNotOpen);
+        }
+
         let auth = AuthContext::from(&fid_entry.creds);
 
         let (data, _eof) = self
@@ -518,7 +645,11 @@ impl NinePHandler {
     }
 
     async fn write(&self, tw: Twrite) -> P9Result<Message> {
-        let fid_entry = self.get_fid(tw.fid)?;
+        let fid_entry = self.live_fid(tw.fid).await?;
+
+        if fid_entry.xattr.is_some() {
+            return self.write_xattr(tw);
+        }
 
         if !fid_entry.opened {
             return Err(P9Error::FidNotOpen);
@@ -551,8 +682,26 @@ impl NinePHandler {
         }))
     }
 
+    /// Append to the value of an extended attribute being created. The value
+    /// must be sent in order and no longer than announced by Txattrcreate.
+    fn write_xattr(&self, tw: Twrite) -> P9Result<Message> {
+        let mut fid_entry = self.session.fids.get_mut(&tw.fid).ok_or(P9Error::BadFid)?;
+        let Some(XattrFid::Write { size, value, .. }) = &mut fid_entry.xattr else {
+            return Err(P9Error::BadFid);
+        };
+
+        if tw.offset != value.len() as u64 || value.len() + tw.data.len() > *size {
+            return Err(P9Error::InvalidArgument);
+        }
+        value.extend_from_slice(&tw.data);
+
+        Ok(Message::Rwrite(Rwrite {
+            count: tw.data.len() as u32,
+        }))
+    }
+
     async fn getattr(&self, tg: Tgetattr) -> P9Result<Message> {
-        let fid_entry = self.get_fid(tg.fid)?;
+        let fid_entry = self.live_fid(tg.fid).await?;
 
         let inode = self.filesystem.inode_store.get(fid_entry.inode_id).await?;
 
@@ -563,7 +712,7 @@ impl NinePHandler {
     }
 
     async fn setattr(&self, ts: Tsetattr) -> P9Result<Message> {
-        let fid_entry = self.get_fid(ts.fid)?;
+        let fid_entry = self.live_fid(ts.fid).await?;
         let attr = SetAttributes::from(&ts);
 
         self.filesystem
@@ -573,7 +722,7 @@ impl NinePHandler {
     }
 
     async fn mkdir(&self, tm: Tmkdir) -> P9Result<Message> {
-        let parent_fid = self.get_fid(tm.dfid)?;
+        let parent_fid = self.live_fid(tm.dfid).await?;
 
         debug!(
             "mkdir: parent_id={}, name={:?}, dfid={}, mode={:o}, gid={}, fid uid={}, fid gid={}",
@@ -606,7 +755,7 @@ impl NinePHandler {
     }
 
     async fn symlink(&self, ts: Tsymlink) -> P9Result<Message> {
-        let parent_fid = self.get_fid(ts.dfid)?;
+        let parent_fid = self.live_fid(ts.dfid).await?;
 
         let (new_id, post_attr) = self
             .filesystem
@@ -629,7 +778,7 @@ impl NinePHandler {
     }
 
     async fn mknod(&self, tm: Tmknod) -> P9Result<Message> {
-        let parent_fid = self.get_fid(tm.dfid)?;
+        let parent_fid = self.live_fid(tm.dfid).await?;
 
         let file_type = tm.mode & 0o170000; // S_IFMT
         let device_type = match file_type {
@@ -666,7 +815,7 @@ impl NinePHandler {
     }
 
     async fn readlink(&self, tr: Treadlink) -> P9Result<Message> {
-        let fid_entry = self.get_fid(tr.fid)?;
+        let fid_entry = self.live_fid(tr.fid).await?;
 
         let inode = self.filesystem.inode_store.get(fid_entry.inode_id).await?;
 
@@ -679,8 +828,8 @@ impl NinePHandler {
     }
 
     async fn link(&self, tl: Tlink) -> P9Result<Message> {
-        let dir_fid = self.get_fid(tl.dfid)?;
-        let file_fid = self.get_fid(tl.fid)?;
+        let dir_fid = self.live_fid(tl.dfid).await?;
+        let file_fid = self.live_fid(tl.fid).await?;
 
         let dir_id = dir_fid.inode_id;
         let file_id = file_fid.inode_id;
@@ -702,8 +851,8 @@ impl NinePHandler {
     }
 
     async fn rename(&self, tr: Trename) -> P9Result<Message> {
-        let source_fid = self.get_fid(tr.fid)?;
-        let dest_fid = self.get_fid(tr.dfid)?;
+        let source_fid = self.live_fid(tr.fid).await?;
+        let dest_fid = self.live_fid(tr.dfid).await?;
 
         if source_fid.path.is_empty() {
             return Err(P9Error::InvalidArgument);
@@ -740,8 +889,8 @@ impl NinePHandler {
     }
 
     async fn renameat(&self, tr: Trenameat) -> P9Result<Message> {
-        let old_dir_fid = self.get_fid(tr.olddirfid)?;
-        let new_dir_fid = self.get_fid(tr.newdirfid)?;
+        let old_dir_fid = self.live_fid(tr.olddirfid).await?;
+        let new_dir_fid = self.live_fid(tr.newdirfid).await?;
 
         let auth = AuthContext::from(&old_dir_fid.creds);
 
@@ -759,7 +908,7 @@ impl NinePHandler {
     }
 
     async fn unlinkat(&self, tu: Tunlinkat) -> P9Result<Message> {
-        let dir_fid = self.get_fid(tu.dirfid)?;
+        let dir_fid = self.live_fid(tu.dirfid).await?;
 
         let parent_id = dir_fid.inode_id;
         let creds = dir_fid.creds;
@@ -806,22 +955,43 @@ impl NinePHandler {
             return Err(P9Error::BadFid);
         }
 
-        let (used_bytes, used_inodes) = self.filesystem.global_stats.get_totals();
+        let (allocated_bytes, used_inodes) = self.filesystem.global_stats.get_totals();
+        let mut used_bytes = self
+            .filesystem
+            .db
+            .space_stats()
+            .totals()
+            .footprint(allocated_bytes);
 
         const TOTAL_INODES: u64 = 1 << 48; // ~281 trillion inodes
         const BLOCK_SIZE: u32 = 4096; // 4KB blocks
 
-        let total_bytes = self.filesystem.max_bytes;
-
-        let total_blocks = total_bytes.div_ceil(BLOCK_SIZE as u64);
-        let used_blocks = used_bytes.div_ceil(BLOCK_SIZE as u64);
-        let free_blocks = total_blocks.saturating_sub(used_blocks);
+        let mut total_bytes = self.filesystem.max_bytes;
 
         let next_inode_id = self.filesystem.inode_store.next_id();
 
-        let available_inodes = TOTAL_INODES.saturating_sub(next_inode_id);
+        let mut available_inodes = TOTAL_INODES.saturating_sub(next_inode_id);
 
-        let total_inodes = used_inodes + available_inodes;
+        let mut total_inodes = used_inodes + available_inodes;
+
+        // Inside a dataset with quotas, report the dataset's limits
+        let inode_id = self.live_fid(ts.fid).await?.inode_id;
+        if let Ok(Some((quota, usage))) =
+            dataset_ops::dataset_limits(&self.filesystem, inode_id).await
+        {
+            if let Some(limit) = quota.bytes_hard {
+                total_bytes = total_bytes.min(limit);
+                used_bytes = usage.bytes;
+            }
+            if let Some(limit) = quota.inodes_hard {
+                total_inodes = limit;
+                available_inodes = available_inodes.min(limit.saturating_sub(usage.inodes));
+            }
+        }
+
+        let total_blocks = total_bytes.div_ceil(BLOCK_SIZE as u64);
+        let used_blocks = used_bytes.div_ceil(BLOCK_SIZE as u64);
+        let free_blocks = total_blocks.saturating_sub(used_blocks);
 
         let statfs = Rstatfs {
             r#type: 0x5a45524f,
@@ -839,7 +1009,7 @@ impl NinePHandler {
     }
 
     async fn lock(&self, tl: Tlock) -> P9Result<Message> {
-        let fid = self.get_fid(tl.fid)?;
+        let fid = self.live_fid(tl.fid).await?;
 
         if matches!(tl.lock_type, LockType::Unlock) {
             self.lock_manager
@@ -883,7 +1053,7 @@ impl NinePHandler {
     }
 
     async fn getlock(&self, tg: Tgetlock) -> P9Result<Message> {
-        let fid = self.get_fid(tg.fid)?;
+        let fid = self.live_fid(tg.fid).await?;
 
         let test_lock = FileLock {
             lock_type: tg.lock_type,
@@ -979,6 +1149,10 @@ pub fn inode_to_stat(inode: &Inode, inode_id: u64) -> Stat {
         Inode::Fifo(_) => (S_IFIFO, 0, 0),
         Inode::Socket(_) => (S_IFSOCK, 0, 0),
     };
+    let used = match inode {
+        Inode::File(f) => f.allocated_bytes(),
+        _ => size,
+    };
 
     Stat {
         qid: inode_to_qid(inode, inode_id),
@@ -989,7 +1163,7 @@ pub fn inode_to_stat(inode: &Inode, inode_id: u64) -> Stat {
         rdev,
         size,
         blksize: DEFAULT_BLKSIZE,
-        blocks: size.div_ceil(BLOCK_SIZE),
+        blocks: used.div_ceil(BLOCK_SIZE),
         atime_sec: inode.atime(),
         atime_nsec: inode.atime_nsec() as u64,
         mtime_sec: inode.mtime(),
@@ -1575,4 +1749,138 @@ mod tests {
             _ => panic!("Expected Rreaddir"),
         };
     }
+
+    #[tokio::test]
+    async fn test_xattrs() {
+        let fs = Arc::new(ZeroFS::new_in_memory().await.unwrap());
+        let lock_manager = Arc::new(FileLockManager::new());
+        let handler = NinePHandler::new(fs.clone(), lock_manager);
+
+        let version_msg = Message::Tversion(Tversion {
+            msize: DEFAULT_MSIZE,
+            version: P9String::new(VERSION_9P2000L.to_vec()),
+        });
+        handler.handle_message(0, version_msg).await;
+
+        let attach_msg = Message::Tattach(Tattach {
+            fid: 1,
+            afid: u32::MAX,
+            uname: P9String::new(b"test".to_vec()),
+            aname: P9String::new(Vec::new()),
+            n_uname: 1000,
+        });
+        handler.handle_message(1, attach_msg).await;
+
+        let walk_msg = Message::Twalk(Twalk {
+            fid: 1,
+            newfid: 2,
+            nwname: 0,
+            wnames: vec![],
+        });
+        handler.handle_message(2, walk_msg).await;
+
+        let create_msg = Message::Tlcreate(Tlcreate {
+            fid: 2,
+            name: P9String::new(b"test.txt".to_vec()),
+            flags: 0x8002, // O_RDWR | O_CREAT
+            mode: 0o644,
+            gid: 1000,
+        });
+        handler.handle_message(3, create_msg).await;
+
+        let walk_to_file = |newfid| {
+            Message::Twalk(Twalk {
+                fid: 1,
+                newfid,
+                nwname: 1,
+                wnames: vec![P9String::new(b"test.txt".to_vec())],
+            })
+        };
+
+        // Set user.comment: Txattrcreate, Twrite, then Tclunk commits it
+        handler.handle_message(4, walk_to_file(3)).await;
+        let xattrcreate_msg = Message::Txattrcreate(Txattrcreate {
+            fid: 3,
+            name: P9String::new(b"user.comment".to_vec()),
+            attr_size: 5,
+            flags: 0,
+        });
+        let resp = handler.handle_message(5, xattrcreate_msg).await;
+        assert!(matches!(resp.body, Message::Rxattrcreate(_)));
+
+        let write_msg = Message::Twrite(Twrite {
+            fid: 3,
+            offset: 0,
+            count: 5,
+            data: DekuBytes::from(b"hello".to_vec()),
+        });
+        let resp = handler.handle_message(6, write_msg).await;
+        assert!(matches!(resp.body, Message::Rwrite(_)));
+
+        let resp = handler
+            .handle_message(7, Message::Tclunk(Tclunk { fid: 3 }))
+            .await;
+        assert!(matches!(resp.body, Message::Rclunk(_)));
+
+        // Read it back
+        handler.handle_message(8, walk_to_file(4)).await;
+        let xattrwalk_msg = Message::Txattrwalk(Txattrwalk {
+            fid: 4,
+            newfid: 5,
+            name: P9String::new(b"user.comment".to_vec()),
+        });
+        let resp = handler.handle_message(9, xattrwalk_msg).await;
+        match &resp.body {
+            Message::Rxattrwalk(rx) => assert_eq!(rx.size, 5),
+            _ => panic!("Expected Rxattrwalk, got {:?}", resp.body),
+        }
+
+        let read_msg = Message::Tread(Tread {
+            fid: 5,
+            offset: 0,
+            count: 4096,
+        });
+        let resp = handler.handle_message(10, read_msg).await;
+        match &resp.body {
+            Message::Rread(rread) => assert_eq!(&rread.data[..], b"hello"),
+            _ => panic!("Expected Rread, got {:?}", resp.body),
+        }
+
+        // An empty name lists the attributes
+        let xattrwalk_msg = Message::Txattrwalk(Txattrwalk {
+            fid: 4,
+            newfid: 6,
+            name: P9String::new(Vec::new()),
+        });
+        let resp = handler.handle_message(11, xattrwalk_msg).await;
+        match &resp.body {
+            Message::Rxattrwalk(rx) => assert_eq!(rx.size, b"user.comment\0".len() as u64),
+            _ => panic!("Expected Rxattrwalk, got {:?}", resp.body),
+        }
+
+        // An empty value with XATTR_REPLACE removes the attribute
+        handler.handle_message(12, walk_to_file(7)).await;
+        let xattrcreate_msg = Message::Txattrcreate(Txattrcreate {
+            fid: 7,
+            name: P9String::new(b"user.comment".to_vec()),
+            attr_size: 0,
+            flags: XATTR_REPLACE,
+        });
+        handler.handle_message(13, xattrcreate_msg).await;
+        let resp = handler
+            .handle_message(14, Message::Tclunk(Tclunk { fid: 7 }))
+            .await;
+        assert!(matches!(resp.body, Message::Rclunk(_)));
+
+        let xattrwalk_msg = Message::Txattrwalk(Txattrwalk {
+            fid: 4,
+            newfid: 8,
+            name: P9String::new(b"user.comment".to_vec()),
+        });
+        let resp = handler.handle_message(15, xattrwalk_msg).await;
+        match &resp.body {
+            Message::Rlerror(rerror) => assert_eq!(rerror.ecode, libc::ENODATA as u32),
+            _ => panic!("Expected Rlerror, got {:?}", resp.body),
+        }
+    }
 }
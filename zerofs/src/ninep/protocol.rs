@@ -381,6 +381,17 @@ pub struct Txattrwalk {
     pub name: P9String,
 }
 
+#[derive(Debug, Clone, DekuRead, DekuWrite)]
+pub struct Txattrcreate {
+    #[deku(endian = "little")]
+    pub fid: u32,
+    pub name: P9String,
+    #[deku(endian = "little")]
+    pub attr_size: u64,
+    #[deku(endian = "little")]
+    pub flags: u32,
+}
+
 #[derive(Debug, Clone, DekuRead, DekuWrite)]
 pub struct Tlock {
     #[deku(endian = "little")]
@@ -417,6 +428,9 @@ pub struct Rxattrwalk {
     pub size: u64,
 }
 
+#[derive(Debug, Clone, DekuRead, DekuWrite)]
+pub struct Rxattrcreate;
+
 #[derive(Debug, Clone, DekuRead, DekuWrite)]
 pub struct Rlock {
     pub status: LockStatus,
@@ -708,6 +722,10 @@ pub enum Message {
     Txattrwalk(Txattrwalk),
     #[deku(id = "31")]
     Rxattrwalk(Rxattrwalk),
+    #[deku(id = "32")]
+    Txattrcreate(Txattrcreate),
+    #[deku(id = "33")]
+    Rxattrcreate(Rxattrcreate),
     #[deku(id = "8")]
     Tstatfs(Tstatfs),
     #[deku(id = "9")]
@@ -788,6 +806,8 @@ impl P9Message {
             Message::Rflush(_) => 109,
             Message::Txattrwalk(_) => 30,
             Message::Rxattrwalk(_) => 31,
+            Message::Txattrcreate(_) => 32,
+            Message::Rxattrcreate(_) => 33,
             Message::Tstatfs(_) => 8,
             Message::Rstatfs(_) => 9,
         };
@@ -1,17 +1,31 @@
 use crate::checkpoint_manager::CheckpointInfo;
 use crate::config::RpcConfig;
-use crate::fs::dataset::Dataset;
+use crate::encryption::KeyId;
+use crate::fs::dataset::{Dataset, DatasetQuota, DatasetUsage};
+use crate::fs::destroy::DestroyProgress;
+use crate::fs::diff::Change;
+use crate::fs::properties::PropertyValue;
+use crate::fs::quota::{Owner, OwnerQuota, OwnerReport};
+use crate::fs::restore::{OnConflict, RestoreOutcome};
+use crate::fs::retention::ScheduleStatus;
+use crate::fs::send::{ReceiveOptions, ReceiveOutcome};
+use crate::key_management::Argon2Params;
 use crate::rpc::proto::{self, admin_service_client::AdminServiceClient};
 use anyhow::{Context, Result, anyhow};
+use futures::StreamExt;
 use hyper_util::rt::TokioIo;
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use tokio::io::{AsyncRead, AsyncReadExt};
 use tokio::net::UnixStream;
 use tonic::Code;
 use tonic::Streaming;
 use tonic::transport::{Channel, Endpoint, Uri};
 use tower::service_fn;
 
+/// Size of the pieces a stream is read and sent in by `receive_snapshot`
+const RECEIVE_CHUNK_SIZE: usize = 256 * 1024;
+
 pub struct RpcClient {
     client: AdminServiceClient<Channel>,
 }
@@ -167,9 +181,10 @@ impl RpcClient {
     }
 
     // Dataset operations
-    pub async fn create_dataset(&self, name: &str) -> Result<Dataset> {
+    pub async fn create_dataset(&self, name: &str, encrypt: bool) -> Result<Dataset> {
         let request = proto::CreateDatasetRequest {
             name: name.to_string(),
+            encrypt,
         };
 
         let response = self
@@ -205,21 +220,50 @@ impl RpcClient {
             .collect()
     }
 
-    pub async fn delete_dataset(&self, name: &str) -> Result<()> {
+    /// Destroy dataset `name`, with its child datasets and their snapshots
+    /// if `recursive`. Its tree is reclaimed in the background. Returns its
+    /// destroy and those of the datasets below it.
+    pub async fn delete_dataset(
+        &self,
+        name: &str,
+        recursive: bool,
+    ) -> Result<(DestroyProgress, Vec<DestroyProgress>)> {
         let request = proto::DeleteDatasetRequest {
             name: name.to_string(),
+            recursive,
         };
 
-        self.client
+        let response = self
+            .client
             .clone()
             .delete_dataset(request)
             .await
-            .map_err(|s| anyhow!("{}", s.message()))?;
+            .map_err(|s| anyhow!("{}", s.message()))?
+            .into_inner();
 
-        Ok(())
+        let progress = response
+            .destroy
+            .map(Into::into)
+            .ok_or_else(|| anyhow!("Empty response from server"))?;
+        let descendants = response.descendants.into_iter().map(Into::into).collect();
+        Ok((progress, descendants))
+    }
+
+    /// Detached trees still being reclaimed
+    pub async fn list_dataset_destroys(&self) -> Result<Vec<DestroyProgress>> {
+        let response = self
+            .client
+            .clone()
+            .list_dataset_destroys(proto::ListDatasetDestroysRequest {})
+            .await
+            .map_err(|s| anyhow!("{}", s.message()))?
+            .into_inner();
+
+        Ok(response.destroys.into_iter().map(Into::into).collect())
     }
 
-    pub async fn get_dataset_info(&self, name: &str) -> Result<Option<Dataset>> {
+    /// Dataset `name` and its current usage
+    pub async fn get_dataset_info(&self, name: &str) -> Result<Option<(Dataset, DatasetUsage)>> {
         let request = proto::GetDatasetInfoRequest {
             name: name.to_string(),
         };
@@ -232,10 +276,7 @@ impl RpcClient {
                     .into_inner()
                     .dataset
                     .ok_or_else(|| anyhow!("Empty response from server"))?;
-                Ok(Some(
-                    info.try_into()
-                        .map_err(|e| anyhow!("Invalid UUID: {}", e))?,
-                ))
+                Ok(Some(dataset_with_usage(info)?))
             }
             Err(status) if status.code() == Code::NotFound => Ok(None),
             Err(status) => Err(anyhow!("RPC call failed: {}", status.message())),
@@ -256,6 +297,103 @@ impl RpcClient {
         Ok(())
     }
 
+    /// Replace the quota of dataset `name`
+    pub async fn set_dataset_quota(
+        &self,
+        name: &str,
+        quota: DatasetQuota,
+    ) -> Result<(Dataset, DatasetUsage)> {
+        let request = proto::SetDatasetQuotaRequest {
+            name: name.to_string(),
+            quota: Some(quota.into()),
+        };
+
+        let response = self
+            .client
+            .clone()
+            .set_dataset_quota(request)
+            .await
+            .map_err(|s| anyhow!("{}", s.message()))?
+            .into_inner();
+
+        dataset_with_usage(
+            response
+                .dataset
+                .ok_or_else(|| anyhow!("Empty response from server"))?,
+        )
+    }
+
+    /// Properties of dataset `name`, or only `property` if given
+    pub async fn get_dataset_properties(
+        &self,
+        name: &str,
+        property: Option<&str>,
+    ) -> Result<Vec<PropertyValue>> {
+        let request = proto::GetDatasetPropertiesRequest {
+            name: name.to_string(),
+            property: property.map(str::to_string),
+        };
+
+        let response = self
+            .client
+            .clone()
+            .get_dataset_properties(request)
+            .await
+            .map_err(|s| anyhow!("{}", s.message()))?
+            .into_inner();
+
+        Ok(response.properties.into_iter().map(Into::into).collect())
+    }
+
+    /// Set `property` of dataset `name` to `value`, or inherit it for `None`
+    pub async fn set_dataset_property(
+        &self,
+        name: &str,
+        property: &str,
+        value: Option<&str>,
+    ) -> Result<Dataset> {
+        let request = proto::SetDatasetPropertyRequest {
+            name: name.to_string(),
+            property: property.to_string(),
+            value: value.map(str::to_string),
+        };
+
+        let response = self
+            .client
+            .clone()
+            .set_dataset_property(request)
+            .await
+            .map_err(|s| anyhow!("{}", s.message()))?
+            .into_inner();
+
+        response
+            .dataset
+            .ok_or_else(|| anyhow!("Empty response from server"))?
+            .try_into()
+            .map_err(|e| anyhow!("Invalid UUID: {}", e))
+    }
+
+    pub async fn rename_dataset(&self, name: &str, new_name: &str) -> Result<Dataset> {
+        let request = proto::RenameDatasetRequest {
+            name: name.to_string(),
+            new_name: new_name.to_string(),
+        };
+
+        let response = self
+            .client
+            .clone()
+            .rename_dataset(request)
+            .await
+            .map_err(|s| anyhow!("{}", s.message()))?
+            .into_inner();
+
+        response
+            .dataset
+            .ok_or_else(|| anyhow!("Empty response from server"))?
+            .try_into()
+            .map_err(|e| anyhow!("Invalid UUID: {}", e))
+    }
+
     pub async fn get_default_dataset(&self) -> Result<u64> {
         let request = proto::GetDefaultDatasetRequest {};
 
@@ -281,6 +419,7 @@ impl RpcClient {
             source_name: source_name.to_string(),
             snapshot_name: snapshot_name.to_string(),
             readonly: Some(readonly),
+            recursive: false,
         };
 
         let response = self
@@ -298,6 +437,38 @@ impl RpcClient {
             .map_err(|e| anyhow!("Invalid UUID: {}", e))
     }
 
+    /// Snapshot dataset `source_name` and every dataset below it at once.
+    /// Returns the snapshots, the source's first.
+    pub async fn create_recursive_snapshot(
+        &self,
+        source_name: &str,
+        snapshot_name: &str,
+        readonly: bool,
+    ) -> Result<Vec<Dataset>> {
+        let request = proto::CreateSnapshotRequest {
+            source_name: source_name.to_string(),
+            snapshot_name: snapshot_name.to_string(),
+            readonly: Some(readonly),
+            recursive: true,
+        };
+
+        let response = self
+            .client
+            .clone()
+            .create_snapshot(request)
+            .await
+            .map_err(|s| anyhow!("{}", s.message()))?
+            .into_inner();
+
+        let snapshot = response
+            .snapshot
+            .ok_or_else(|| anyhow!("Empty response from server"))?;
+        std::iter::once(snapshot)
+            .chain(response.descendants)
+            .map(|s| s.try_into().map_err(|e| anyhow!("Invalid UUID: {}", e)))
+            .collect()
+    }
+
     pub async fn list_snapshots(&self) -> Result<Vec<Dataset>> {
         let request = proto::ListSnapshotsRequest {};
 
@@ -316,61 +487,255 @@ impl RpcClient {
             .collect()
     }
 
-    pub async fn delete_snapshot(&self, name: &str) -> Result<()> {
+    /// Destroy snapshot `name`, with the snapshots taken with it by a
+    /// recursive snapshot if `recursive`. Returns its destroy and theirs.
+    pub async fn delete_snapshot(
+        &self,
+        name: &str,
+        recursive: bool,
+    ) -> Result<(DestroyProgress, Vec<DestroyProgress>)> {
         let request = proto::DeleteSnapshotRequest {
             name: name.to_string(),
+            recursive,
         };
 
-        self.client
+        let response = self
+            .client
             .clone()
             .delete_snapshot(request)
             .await
-            .map_err(|s| anyhow!("{}", s.message()))?;
+            .map_err(|s| anyhow!("{}", s.message()))?
+            .into_inner();
 
-        Ok(())
+        let progress = response
+            .destroy
+            .map(Into::into)
+            .ok_or_else(|| anyhow!("Empty response from server"))?;
+        let descendants = response.descendants.into_iter().map(Into::into).collect();
+        Ok((progress, descendants))
     }
 
-    pub async fn read_snapshot_file(
+    /// Put hold `tag` on snapshot `name`
+    pub async fn hold_snapshot(&self, name: &str, tag: &str) -> Result<Dataset> {
+        let request = proto::HoldSnapshotRequest {
+            name: name.to_string(),
+            tag: tag.to_string(),
+        };
+
+        let response = self
+            .client
+            .clone()
+            .hold_snapshot(request)
+            .await
+            .map_err(|s| anyhow!("{}", s.message()))?
+            .into_inner();
+
+        response
+            .snapshot
+            .ok_or_else(|| anyhow!("Empty response from server"))?
+            .try_into()
+            .map_err(|e| anyhow!("Invalid UUID: {}", e))
+    }
+
+    /// Release hold `tag` on snapshot `name`
+    pub async fn release_snapshot(&self, name: &str, tag: &str) -> Result<Dataset> {
+        let request = proto::ReleaseSnapshotRequest {
+            name: name.to_string(),
+            tag: tag.to_string(),
+        };
+
+        let response = self
+            .client
+            .clone()
+            .release_snapshot(request)
+            .await
+            .map_err(|s| anyhow!("{}", s.message()))?
+            .into_inner();
+
+        response
+            .snapshot
+            .ok_or_else(|| anyhow!("Empty response from server"))?
+            .try_into()
+            .map_err(|e| anyhow!("Invalid UUID: {}", e))
+    }
+
+    /// Roll dataset `name` back to its snapshot `snapshot_name`. Returns the
+    /// dataset, the snapshots destroyed on the way and the replaced tree.
+    pub async fn rollback_dataset(
         &self,
+        name: &str,
         snapshot_name: &str,
-        file_path: &str,
-    ) -> Result<Vec<u8>> {
-        use futures::StreamExt;
-
-        let request = proto::ReadSnapshotFileRequest {
+        destroy_newer: bool,
+    ) -> Result<(Dataset, Vec<DestroyProgress>, DestroyProgress)> {
+        let request = proto::RollbackDatasetRequest {
+            name: name.to_string(),
             snapshot_name: snapshot_name.to_string(),
-            file_path: file_path.to_string(),
+            destroy_newer,
         };
 
-        let mut stream = self
+        let response = self
             .client
             .clone()
-            .read_snapshot_file(request)
+            .rollback_dataset(request)
             .await
-            .map_err(|s| anyhow!("Failed to read snapshot file: {}", s.message()))?
+            .map_err(|s| anyhow!("{}", s.message()))?
             .into_inner();
 
-        let mut file_data = Vec::new();
+        let dataset: Dataset = response
+            .dataset
+            .ok_or_else(|| anyhow!("Empty response from server"))?
+            .try_into()
+            .map_err(|e| anyhow!("Invalid UUID: {}", e))?;
+        let replaced = response
+            .replaced
+            .map(Into::into)
+            .ok_or_else(|| anyhow!("Empty response from server"))?;
+        Ok((
+            dataset,
+            response
+                .destroyed_snapshots
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            replaced,
+        ))
+    }
 
-        while let Some(chunk) = stream.next().await {
-            let chunk = chunk.map_err(|s| anyhow!("Stream error: {}", s.message()))?;
-            file_data.extend_from_slice(&chunk.data);
-        }
+    /// Changes from snapshot `from_snapshot` to snapshot or dataset `to`, or
+    /// to its live dataset
+    pub async fn diff_snapshots(
+        &self,
+        from_snapshot: &str,
+        to: Option<&str>,
+    ) -> Result<Vec<Change>> {
+        let request = proto::DiffSnapshotsRequest {
+            from_snapshot: from_snapshot.to_string(),
+            to: to.map(str::to_string),
+        };
 
-        Ok(file_data)
+        let response = self
+            .client
+            .clone()
+            .diff_snapshots(request)
+            .await
+            .map_err(|s| anyhow!("Failed to diff snapshots: {}", s.message()))?
+            .into_inner();
+
+        Ok(response.changes.into_iter().map(Into::into).collect())
     }
 
-    /// Instant restore file from snapshot (COW - creates directory entry, no data copying)
+    /// Snapshot schedules configured on the server and how each last ran
+    pub async fn get_snapshot_schedules(&self) -> Result<Vec<ScheduleStatus>> {
+        let response = self
+            .client
+            .clone()
+            .get_snapshot_schedules(proto::GetSnapshotSchedulesRequest {})
+            .await
+            .map_err(|s| anyhow!("Failed to get snapshot schedules: {}", s.message()))?
+            .into_inner();
+
+        Ok(response.schedules.into_iter().map(Into::into).collect())
+    }
+
+    /// Replication stream of snapshot `snapshot_name`, incremental from
+    /// `base_snapshot_name` if given
+    pub async fn send_snapshot(
+        &self,
+        snapshot_name: &str,
+        base_snapshot_name: Option<&str>,
+    ) -> Result<Streaming<proto::SendStreamChunk>> {
+        let request = proto::SendSnapshotRequest {
+            snapshot_name: snapshot_name.to_string(),
+            base_snapshot_name: base_snapshot_name.map(str::to_string),
+        };
+
+        let response = self
+            .client
+            .clone()
+            .send_snapshot(request)
+            .await
+            .map_err(|s| anyhow!("Failed to send snapshot: {}", s.message()))?;
+
+        Ok(response.into_inner())
+    }
+
+    /// Apply the stream read from `input` to dataset `dataset_name`
+    pub async fn receive_snapshot<R: AsyncRead + Send + Unpin + 'static>(
+        &self,
+        dataset_name: &str,
+        options: ReceiveOptions,
+        input: R,
+    ) -> Result<ReceiveOutcome> {
+        // The first message names the dataset, even for an empty input
+        let first = proto::ReceiveSnapshotRequest {
+            dataset_name: dataset_name.to_string(),
+            encrypt: options.encrypt,
+            force: options.force,
+            data: Vec::new(),
+        };
+        let chunks = futures::stream::unfold(Some(input), |input| async move {
+            let mut input = input?;
+            let mut data = vec![0u8; RECEIVE_CHUNK_SIZE];
+            match input.read(&mut data).await {
+                Ok(0) => None,
+                Ok(n) => {
+                    data.truncate(n);
+                    let chunk = proto::ReceiveSnapshotRequest {
+                        data,
+                        ..Default::default()
+                    };
+                    Some((chunk, Some(input)))
+                }
+                Err(e) => {
+                    // Ending the input early fails the receive as truncated
+                    tracing::error!("Failed to read the send stream: {}", e);
+                    None
+                }
+            }
+        });
+        let request = futures::stream::iter([first]).chain(chunks);
+
+        let response = self
+            .client
+            .clone()
+            .receive_snapshot(request)
+            .await
+            .map_err(|s| anyhow!("Failed to receive snapshot: {}", s.message()))?
+            .into_inner();
+
+        let dataset = response
+            .dataset
+            .ok_or_else(|| anyhow!("Empty response from server"))?;
+        let snapshot = response
+            .snapshot
+            .ok_or_else(|| anyhow!("Empty response from server"))?;
+        Ok(ReceiveOutcome {
+            dataset: dataset
+                .try_into()
+                .map_err(|e| anyhow!("Invalid UUID: {}", e))?,
+            snapshot: snapshot
+                .try_into()
+                .map_err(|e| anyhow!("Invalid UUID: {}", e))?,
+            entries: response.entries,
+            data_bytes: response.data_bytes,
+        })
+    }
+
+    /// Restore a file or subtree from a snapshot as a COW clone, no data copying
     pub async fn instant_restore_file(
         &self,
         snapshot_name: &str,
         source_path: &str,
         destination_path: &str,
-    ) -> Result<(u64, u64, u32)> {
+        on_conflict: OnConflict,
+        dry_run: bool,
+    ) -> Result<RestoreOutcome> {
         let request = proto::InstantRestoreFileRequest {
             snapshot_name: snapshot_name.to_string(),
             source_path: source_path.to_string(),
             destination_path: destination_path.to_string(),
+            on_conflict: proto::RestoreConflict::from(on_conflict) as i32,
+            dry_run,
         };
 
         let response = self
@@ -381,7 +746,7 @@ impl RpcClient {
             .map_err(|s| anyhow!("Failed to instant restore file: {}", s.message()))?
             .into_inner();
 
-        Ok((response.inode_id, response.file_size, response.nlink))
+        Ok(response.into())
     }
 
     // Convenience method for creating read-write snapshots (default, like btrfs)
@@ -412,4 +777,116 @@ impl RpcClient {
 
         Ok((response.inode_id, response.size, response.is_directory))
     }
+
+    /// Set the limits of a uid or gid. Returns its usage and new limits.
+    pub async fn set_owner_quota(&self, owner: Owner, quota: OwnerQuota) -> Result<OwnerReport> {
+        let (kind, id) = owner.into();
+        let request = proto::SetOwnerQuotaRequest {
+            kind: kind as i32,
+            id,
+            quota: Some(quota.into()),
+        };
+
+        let response = self
+            .client
+            .clone()
+            .set_owner_quota(request)
+            .await
+            .map_err(|s| anyhow!("{}", s.message()))?
+            .into_inner();
+
+        response
+            .owner
+            .map(Into::into)
+            .ok_or_else(|| anyhow!("Empty response from server"))
+    }
+
+    /// Usage and limits of every uid and gid that has either
+    pub async fn quota_report(&self) -> Result<Vec<OwnerReport>> {
+        let response = self
+            .client
+            .clone()
+            .get_quota_report(proto::GetQuotaReportRequest {})
+            .await
+            .map_err(|s| anyhow!("{}", s.message()))?
+            .into_inner();
+
+        Ok(response.owners.into_iter().map(Into::into).collect())
+    }
+
+    /// Usage of the whole filesystem
+    pub async fn usage(&self) -> Result<DatasetUsage> {
+        let response = self
+            .client
+            .clone()
+            .get_usage(proto::GetUsageRequest {})
+            .await
+            .map_err(|s| anyhow!("{}", s.message()))?
+            .into_inner();
+
+        Ok(response.usage.map(Into::into).unwrap_or_default())
+    }
+
+    /// Generate a new data encryption key. `password` must open a key slot.
+    pub async fn rotate_data_key(&self, password: &str) -> Result<KeyId> {
+        let request = proto::RotateDataKeyRequest {
+            password: password.to_string(),
+        };
+
+        let response = self
+            .client
+            .clone()
+            .rotate_data_key(request)
+            .await
+            .map_err(|s| anyhow!("{}", s.message()))?
+            .into_inner();
+
+        Ok(response.key_id)
+    }
+
+    pub async fn add_key_slot(
+        &self,
+        password: &str,
+        label: &str,
+        new_password: &str,
+        params: Argon2Params,
+    ) -> Result<()> {
+        let request = proto::AddKeySlotRequest {
+            password: password.to_string(),
+            label: label.to_string(),
+            new_password: new_password.to_string(),
+            memory_kib: params.memory_kib,
+            iterations: params.iterations,
+            parallelism: params.parallelism,
+        };
+
+        self.client
+            .clone()
+            .add_key_slot(request)
+            .await
+            .map_err(|s| anyhow!("{}", s.message()))?;
+        Ok(())
+    }
+
+    pub async fn revoke_key_slot(&self, password: &str, label: &str) -> Result<()> {
+        let request = proto::RevokeKeySlotRequest {
+            password: password.to_string(),
+            label: label.to_string(),
+        };
+
+        self.client
+            .clone()
+            .revoke_key_slot(request)
+            .await
+            .map_err(|s| anyhow!("{}", s.message()))?;
+        Ok(())
+    }
+}
+
+fn dataset_with_usage(mut info: proto::DatasetInfo) -> Result<(Dataset, DatasetUsage)> {
+    let usage = info.usage.take().map(Into::into).unwrap_or_default();
+    let dataset = info
+        .try_into()
+        .map_err(|e| anyhow!("Invalid UUID: {}", e))?;
+    Ok((dataset, usage))
 }
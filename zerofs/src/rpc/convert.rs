@@ -1,5 +1,13 @@
 use crate::checkpoint_manager::CheckpointInfo;
-use crate::fs::dataset::Dataset;
+use crate::config::SnapshotScheduleConfig;
+use crate::fs::dataset::{Dataset, DatasetQuota, DatasetUsage};
+use crate::fs::destroy::DestroyProgress;
+use crate::fs::diff::{Change, ChangeKind};
+use crate::fs::properties::{PropertySource, PropertyValue};
+use crate::fs::quota::{Owner, OwnerQuota, OwnerReport};
+use crate::fs::restore::{OnConflict, RestoreOutcome};
+use crate::fs::retention::ScheduleStatus;
+use crate::fs::stats::SpaceUsage;
 use crate::fs::tracing::{FileAccessEvent, FileOperation};
 use crate::rpc::proto;
 use prost_types::Timestamp;
@@ -174,6 +182,10 @@ impl From<Dataset> for proto::DatasetInfo {
             is_snapshot: subvol.is_snapshot,
             generation: subvol.generation,
             flags: subvol.flags,
+            encrypted: subvol.wrapped_key.is_some(),
+            quota: Some(subvol.quota.into()),
+            usage: None,
+            holds: subvol.holds.into_iter().collect(),
         }
     }
 }
@@ -198,6 +210,342 @@ impl TryFrom<proto::DatasetInfo> for Dataset {
             is_snapshot: proto.is_snapshot,
             generation: proto.generation,
             flags: proto.flags,
+            // The wrapped key itself is never sent to clients
+            wrapped_key: proto.encrypted.then(Vec::new),
+            quota: proto.quota.map(Into::into).unwrap_or_default(),
+            holds: proto.holds.into_iter().collect(),
+            // Properties are reported by GetDatasetProperties
+            properties: Default::default(),
         })
     }
 }
+
+impl From<DatasetQuota> for proto::DatasetQuota {
+    fn from(quota: DatasetQuota) -> Self {
+        proto::DatasetQuota {
+            bytes_hard: quota.bytes_hard,
+            bytes_soft: quota.bytes_soft,
+            inodes_hard: quota.inodes_hard,
+            inodes_soft: quota.inodes_soft,
+        }
+    }
+}
+
+impl From<proto::DatasetQuota> for DatasetQuota {
+    fn from(proto: proto::DatasetQuota) -> Self {
+        DatasetQuota {
+            bytes_hard: proto.bytes_hard,
+            bytes_soft: proto.bytes_soft,
+            inodes_hard: proto.inodes_hard,
+            inodes_soft: proto.inodes_soft,
+        }
+    }
+}
+
+impl From<PropertyValue> for proto::DatasetProperty {
+    fn from(value: PropertyValue) -> Self {
+        proto::DatasetProperty {
+            property: value.property,
+            value: value.value,
+            source: value.source.as_str().to_string(),
+            inherited_from: value.inherited_from,
+        }
+    }
+}
+
+impl From<proto::DatasetProperty> for PropertyValue {
+    fn from(proto: proto::DatasetProperty) -> Self {
+        PropertyValue {
+            property: proto.property,
+            value: proto.value,
+            source: proto.source.parse().unwrap_or(PropertySource::Default),
+            inherited_from: proto.inherited_from,
+        }
+    }
+}
+
+impl From<DatasetUsage> for proto::DatasetUsage {
+    fn from(usage: DatasetUsage) -> Self {
+        proto::DatasetUsage {
+            bytes: usage.bytes,
+            inodes: usage.inodes,
+            space: Some(usage.space.into()),
+        }
+    }
+}
+
+impl From<proto::DatasetUsage> for DatasetUsage {
+    fn from(proto: proto::DatasetUsage) -> Self {
+        DatasetUsage {
+            bytes: proto.bytes,
+            inodes: proto.inodes,
+            space: proto.space.map(Into::into).unwrap_or_default(),
+        }
+    }
+}
+
+impl From<DestroyProgress> for proto::DestroyProgress {
+    fn from(progress: DestroyProgress) -> Self {
+        proto::DestroyProgress {
+            dataset_id: progress.dataset_id,
+            name: progress.name,
+            root_inode: progress.root_inode,
+            account: progress.account,
+            root_account: progress.root_account,
+            destroyed_at: progress.destroyed_at,
+            total_bytes: progress.total_bytes,
+            total_inodes: progress.total_inodes,
+            reclaimed_bytes: progress.reclaimed_bytes,
+            reclaimed_inodes: progress.reclaimed_inodes,
+        }
+    }
+}
+
+impl From<proto::DestroyProgress> for DestroyProgress {
+    fn from(proto: proto::DestroyProgress) -> Self {
+        DestroyProgress {
+            dataset_id: proto.dataset_id,
+            name: proto.name,
+            root_inode: proto.root_inode,
+            account: proto.account,
+            root_account: proto.root_account,
+            destroyed_at: proto.destroyed_at,
+            total_bytes: proto.total_bytes,
+            total_inodes: proto.total_inodes,
+            reclaimed_bytes: proto.reclaimed_bytes,
+            reclaimed_inodes: proto.reclaimed_inodes,
+        }
+    }
+}
+
+impl From<SpaceUsage> for proto::SpaceUsage {
+    fn from(usage: SpaceUsage) -> Self {
+        proto::SpaceUsage {
+            logical: usage.logical,
+            compressed: usage.compressed,
+            stored: usage.stored,
+        }
+    }
+}
+
+impl From<proto::SpaceUsage> for SpaceUsage {
+    fn from(proto: proto::SpaceUsage) -> Self {
+        SpaceUsage {
+            logical: proto.logical,
+            compressed: proto.compressed,
+            stored: proto.stored,
+        }
+    }
+}
+
+impl From<Owner> for (proto::OwnerKind, u32) {
+    fn from(owner: Owner) -> Self {
+        match owner {
+            Owner::User(uid) => (proto::OwnerKind::User, uid),
+            Owner::Group(gid) => (proto::OwnerKind::Group, gid),
+        }
+    }
+}
+
+impl From<(proto::OwnerKind, u32)> for Owner {
+    fn from((kind, id): (proto::OwnerKind, u32)) -> Self {
+        match kind {
+            proto::OwnerKind::User => Owner::User(id),
+            proto::OwnerKind::Group => Owner::Group(id),
+        }
+    }
+}
+
+impl From<OwnerQuota> for proto::OwnerQuota {
+    fn from(quota: OwnerQuota) -> Self {
+        proto::OwnerQuota {
+            bytes: quota.bytes,
+            inodes: quota.inodes,
+        }
+    }
+}
+
+impl From<proto::OwnerQuota> for OwnerQuota {
+    fn from(proto: proto::OwnerQuota) -> Self {
+        OwnerQuota {
+            bytes: proto.bytes,
+            inodes: proto.inodes,
+        }
+    }
+}
+
+impl From<OwnerReport> for proto::OwnerReport {
+    fn from(report: OwnerReport) -> Self {
+        let (kind, id) = report.owner.into();
+        proto::OwnerReport {
+            kind: kind as i32,
+            id,
+            used_bytes: report.bytes,
+            used_inodes: report.inodes,
+            quota: Some(report.quota.into()),
+        }
+    }
+}
+
+impl From<proto::OwnerReport> for OwnerReport {
+    fn from(proto: proto::OwnerReport) -> Self {
+        OwnerReport {
+            owner: (proto.kind(), proto.id).into(),
+            bytes: proto.used_bytes,
+            inodes: proto.used_inodes,
+            quota: proto.quota.map(Into::into).unwrap_or_default(),
+        }
+    }
+}
+
+impl From<OnConflict> for proto::RestoreConflict {
+    fn from(on_conflict: OnConflict) -> Self {
+        match on_conflict {
+            OnConflict::Fail => proto::RestoreConflict::Fail,
+            OnConflict::Overwrite => proto::RestoreConflict::Overwrite,
+            OnConflict::Rename => proto::RestoreConflict::Rename,
+        }
+    }
+}
+
+impl From<proto::RestoreConflict> for OnConflict {
+    fn from(on_conflict: proto::RestoreConflict) -> Self {
+        match on_conflict {
+            proto::RestoreConflict::Fail => OnConflict::Fail,
+            proto::RestoreConflict::Overwrite => OnConflict::Overwrite,
+            proto::RestoreConflict::Rename => OnConflict::Rename,
+        }
+    }
+}
+
+impl From<RestoreOutcome> for proto::InstantRestoreFileResponse {
+    fn from(outcome: RestoreOutcome) -> Self {
+        proto::InstantRestoreFileResponse {
+            inode_id: outcome.inode_id,
+            file_size: outcome.size,
+            nlink: outcome.nlink,
+            destination_path: outcome.destination_path,
+            is_directory: outcome.is_directory,
+            replaced: outcome.replaced,
+            dry_run: outcome.dry_run,
+            detached: outcome.detached.map(Into::into),
+        }
+    }
+}
+
+impl From<proto::InstantRestoreFileResponse> for RestoreOutcome {
+    fn from(proto: proto::InstantRestoreFileResponse) -> Self {
+        RestoreOutcome {
+            inode_id: proto.inode_id,
+            destination_path: proto.destination_path,
+            is_directory: proto.is_directory,
+            size: proto.file_size,
+            nlink: proto.nlink,
+            replaced: proto.replaced,
+            detached: proto.detached.map(Into::into),
+            dry_run: proto.dry_run,
+        }
+    }
+}
+
+impl From<ChangeKind> for proto::ChangeKind {
+    fn from(kind: ChangeKind) -> Self {
+        match kind {
+            ChangeKind::Added => proto::ChangeKind::Added,
+            ChangeKind::Removed => proto::ChangeKind::Removed,
+            ChangeKind::Modified => proto::ChangeKind::Modified,
+            ChangeKind::Renamed => proto::ChangeKind::Renamed,
+        }
+    }
+}
+
+impl From<proto::ChangeKind> for ChangeKind {
+    fn from(kind: proto::ChangeKind) -> Self {
+        match kind {
+            proto::ChangeKind::Added => ChangeKind::Added,
+            proto::ChangeKind::Removed => ChangeKind::Removed,
+            proto::ChangeKind::Modified => ChangeKind::Modified,
+            proto::ChangeKind::Renamed => ChangeKind::Renamed,
+        }
+    }
+}
+
+impl From<Change> for proto::SnapshotChange {
+    fn from(change: Change) -> Self {
+        proto::SnapshotChange {
+            kind: proto::ChangeKind::from(change.kind) as i32,
+            path: change.path,
+            old_path: change.old_path,
+            is_directory: change.is_directory,
+            content_changed: change.content_changed,
+            metadata_changed: change.metadata_changed,
+        }
+    }
+}
+
+impl From<proto::SnapshotChange> for Change {
+    fn from(proto: proto::SnapshotChange) -> Self {
+        Change {
+            kind: proto.kind().into(),
+            path: proto.path,
+            old_path: proto.old_path,
+            is_directory: proto.is_directory,
+            content_changed: proto.content_changed,
+            metadata_changed: proto.metadata_changed,
+        }
+    }
+}
+
+impl From<SnapshotScheduleConfig> for proto::SnapshotSchedule {
+    fn from(schedule: SnapshotScheduleConfig) -> Self {
+        proto::SnapshotSchedule {
+            hourly: schedule.hourly,
+            daily: schedule.daily,
+            weekly: schedule.weekly,
+            monthly: schedule.monthly,
+        }
+    }
+}
+
+impl From<proto::SnapshotSchedule> for SnapshotScheduleConfig {
+    fn from(proto: proto::SnapshotSchedule) -> Self {
+        SnapshotScheduleConfig {
+            hourly: proto.hourly,
+            daily: proto.daily,
+            weekly: proto.weekly,
+            monthly: proto.monthly,
+        }
+    }
+}
+
+impl From<ScheduleStatus> for proto::SnapshotScheduleStatus {
+    fn from(status: ScheduleStatus) -> Self {
+        proto::SnapshotScheduleStatus {
+            dataset: status.dataset,
+            schedule: Some(status.schedule.into()),
+            last_run: status.last_run,
+            next_run: status.next_run,
+            last_snapshot: status.last_snapshot,
+            snapshots: status.snapshots,
+            missed_runs: status.missed_runs,
+            pruned: status.pruned,
+            last_error: status.last_error,
+        }
+    }
+}
+
+impl From<proto::SnapshotScheduleStatus> for ScheduleStatus {
+    fn from(proto: proto::SnapshotScheduleStatus) -> Self {
+        ScheduleStatus {
+            dataset: proto.dataset,
+            schedule: proto.schedule.map(Into::into).unwrap_or_default(),
+            last_run: proto.last_run,
+            next_run: proto.next_run,
+            last_snapshot: proto.last_snapshot,
+            snapshots: proto.snapshots,
+            missed_runs: proto.missed_runs,
+            pruned: proto.pruned,
+            last_error: proto.last_error,
+        }
+    }
+}
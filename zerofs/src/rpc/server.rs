@@ -1,25 +1,42 @@
 use crate::checkpoint_manager::CheckpointManager;
-use crate::fs::ZeroFS;
+use crate::fs::{SnapshotScheduler, ZeroFS};
 use crate::fs::clone;
+use crate::fs::dataset::{Dataset, DatasetQuota};
+use crate::fs::dataset_ops;
+use crate::fs::destroy;
+use crate::fs::diff;
+use crate::fs::errors::FsError;
+use crate::fs::properties::Property;
+use crate::fs::quota::{self, Owner, OwnerQuota};
+use crate::fs::restore::{self, OnConflict};
+use crate::fs::send::{self, ReceiveOptions};
 use crate::fs::tracing::AccessTracer;
+use crate::key_management::{self, Argon2Params};
 use crate::rpc::proto::{self, admin_service_server::AdminService};
 use anyhow::{Context, Result};
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::UnixListener;
 use tokio_stream::StreamExt;
 use tokio_stream::wrappers::{BroadcastStream, UnixListenerStream};
 use tokio_util::sync::CancellationToken;
-use tonic::{Request, Response, Status};
+use tonic::{Request, Response, Status, Streaming};
 use tracing::info;
 
+/// Size of the pieces a send stream is forwarded in
+const SEND_BUFFER_SIZE: usize = 256 * 1024;
+
 #[derive(Clone)]
 pub struct AdminRpcServer {
     checkpoint_manager: Arc<CheckpointManager>,
     tracer: AccessTracer,
     fs: Arc<ZeroFS>,
+    snapshot_scheduler: Arc<SnapshotScheduler>,
+    /// Held while the key slots are read, changed and stored again
+    key_slots_lock: Arc<tokio::sync::Mutex<()>>,
 }
 
 impl AdminRpcServer {
@@ -27,36 +44,77 @@ impl AdminRpcServer {
         checkpoint_manager: Arc<CheckpointManager>,
         tracer: AccessTracer,
         fs: Arc<ZeroFS>,
+        snapshot_scheduler: Arc<SnapshotScheduler>,
     ) -> Self {
         Self {
             checkpoint_manager,
             tracer,
             fs,
+            snapshot_scheduler,
+            key_slots_lock: Arc::new(tokio::sync::Mutex::new(())),
         }
     }
 
-    /// Recursively clone directory contents
-    /// This performs true COW - all inodes and data chunks are shared until modified
-    async fn clone_directory_recursive(
-        &self,
-        source_dir_inode: u64,
-        dest_dir_inode: u64,
-    ) -> Result<(), Status> {
-        clone::clone_directory_deep(
-            self.fs.db.clone(),
-            &self.fs.inode_store,
-            &self.fs.directory_store,
-            &self.fs.chunk_store,
-            source_dir_inode,
-            dest_dir_inode,
-        )
-        .await
-        .map_err(|e| Status::internal(format!("Failed to clone directory: {}", e)))?;
+    /// Copy the entries of a lazily cloned directory an entry is added
+    /// below, so the directory store holds its own entries
+    async fn materialize_dir(&self, dir_id: u64) -> Result<(), Status> {
+        clone::prepare_write(&self.fs, &[dir_id])
+            .await
+            .map(drop)
+            .map_err(|e| Status::internal(format!("Failed to copy directory {}: {}", dir_id, e)))
+    }
 
-        Ok(())
+    async fn snapshot_by_name(&self, name: &str) -> Result<Dataset, Status> {
+        self.fs
+            .dataset_store
+            .get_by_name(name)
+            .await
+            .filter(|d| d.is_snapshot)
+            .ok_or_else(|| Status::not_found(format!("Snapshot '{}' not found", name)))
+    }
+
+    /// Dataset as sent to clients, with its current usage
+    async fn dataset_info(&self, dataset: Dataset) -> proto::DatasetInfo {
+        let usage = dataset_ops::dataset_usage(&self.fs, dataset.id).await;
+        proto::DatasetInfo {
+            usage: Some(usage.into()),
+            ..dataset.into()
+        }
     }
 }
 
+/// Status for a failed dataset operation
+fn dataset_status(e: FsError) -> Status {
+    match e {
+        FsError::NotFound => Status::not_found("Dataset not found"),
+        FsError::Exists => Status::already_exists("Dataset or directory already exists"),
+        FsError::NotEmpty => Status::failed_precondition(
+            "Dataset has snapshots or child datasets, or its key is used by other datasets",
+        ),
+        FsError::InvalidArgument | FsError::NameTooLong => {
+            Status::invalid_argument("Invalid dataset name")
+        }
+        FsError::ReadOnlyFilesystem => Status::failed_precondition("Filesystem is read-only"),
+        FsError::OperationNotPermitted => Status::failed_precondition("Snapshot is held"),
+        e => Status::internal(format!("Dataset operation failed: {}", e)),
+    }
+}
+
+/// Status for a failed change to the encryption keys
+fn key_status(e: anyhow::Error) -> Status {
+    Status::failed_precondition(format!("{:#}", e))
+}
+
+/// Status for destroying `snapshot` while it is held
+fn held_status(snapshot: &Dataset) -> Status {
+    let holds: Vec<_> = snapshot.holds.iter().map(String::as_str).collect();
+    Status::failed_precondition(format!(
+        "Snapshot '{}' is held by: {}",
+        snapshot.name,
+        holds.join(", ")
+    ))
+}
+
 #[tonic::async_trait]
 impl AdminService for AdminRpcServer {
     type WatchFileAccessStream =
@@ -146,65 +204,630 @@ impl AdminService for AdminRpcServer {
 
     async fn create_dataset(
         &self,
-        _request: Request<proto::CreateDatasetRequest>,
+        request: Request<proto::CreateDatasetRequest>,
     ) -> Result<Response<proto::CreateDatasetResponse>, Status> {
-        return Err(Status::unimplemented("Dataset management not implemented. Use clone command instead."));
+        let req = request.into_inner();
+
+        let dataset = dataset_ops::create_dataset(&self.fs, &req.name, req.encrypt)
+            .await
+            .map_err(dataset_status)?;
+
+        Ok(Response::new(proto::CreateDatasetResponse {
+            dataset: Some(dataset.into()),
+        }))
     }
 
     async fn list_datasets(
         &self,
         _request: Request<proto::ListDatasetsRequest>,
     ) -> Result<Response<proto::ListDatasetsResponse>, Status> {
-        return Err(Status::unimplemented("Dataset management not needed for clone-only"));
+        let mut datasets = Vec::new();
+        for dataset in self.fs.dataset_store.list_datasets().await {
+            datasets.push(self.dataset_info(dataset).await);
+        }
+
+        Ok(Response::new(proto::ListDatasetsResponse { datasets }))
     }
 
     async fn delete_dataset(
         &self,
-        _request: Request<proto::DeleteDatasetRequest>,
+        request: Request<proto::DeleteDatasetRequest>,
     ) -> Result<Response<proto::DeleteDatasetResponse>, Status> {
-        return Err(Status::unimplemented("Dataset management not needed for clone-only"));
+        let req = request.into_inner();
+        let name = req.name;
+
+        let dataset = self
+            .fs
+            .dataset_store
+            .get_by_name(&name)
+            .await
+            .ok_or_else(|| Status::not_found(format!("Dataset '{}' not found", name)))?;
+        let (progress, descendants) = if req.recursive {
+            dataset_ops::delete_dataset_recursive(&self.fs, dataset.id).await
+        } else {
+            dataset_ops::delete_dataset(&self.fs, dataset.id)
+                .await
+                .map(|progress| (progress, Vec::new()))
+        }
+        .map_err(dataset_status)?;
+
+        info!("Deleted dataset '{}' (id={})", name, dataset.id);
+        Ok(Response::new(proto::DeleteDatasetResponse {
+            destroy: Some(progress.into()),
+            descendants: descendants.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn list_dataset_destroys(
+        &self,
+        _request: Request<proto::ListDatasetDestroysRequest>,
+    ) -> Result<Response<proto::ListDatasetDestroysResponse>, Status> {
+        let destroys = destroy::pending(&self.fs)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to list destroyed datasets: {}", e)))?
+            .into_iter()
+            .map(Into::into)
+            .collect();
+
+        Ok(Response::new(proto::ListDatasetDestroysResponse { destroys }))
     }
 
     async fn get_dataset_info(
         &self,
-        _request: Request<proto::GetDatasetInfoRequest>,
+        request: Request<proto::GetDatasetInfoRequest>,
     ) -> Result<Response<proto::GetDatasetInfoResponse>, Status> {
-        return Err(Status::unimplemented("Dataset management not needed for clone-only"));
+        let name = request.into_inner().name;
+
+        let dataset = self
+            .fs
+            .dataset_store
+            .get_by_name(&name)
+            .await
+            .ok_or_else(|| Status::not_found(format!("Dataset '{}' not found", name)))?;
+
+        Ok(Response::new(proto::GetDatasetInfoResponse {
+            dataset: Some(self.dataset_info(dataset).await),
+        }))
     }
 
     async fn set_default_dataset(
         &self,
-        _request: Request<proto::SetDefaultDatasetRequest>,
+        request: Request<proto::SetDefaultDatasetRequest>,
     ) -> Result<Response<proto::SetDefaultDatasetResponse>, Status> {
-        return Err(Status::unimplemented("Dataset management not needed for clone-only"));
+        let name = request.into_inner().name;
+
+        let dataset = self
+            .fs
+            .dataset_store
+            .get_by_name(&name)
+            .await
+            .ok_or_else(|| Status::not_found(format!("Dataset '{}' not found", name)))?;
+        self.fs
+            .dataset_store
+            .set_default(dataset.id)
+            .await
+            .map_err(dataset_status)?;
+
+        Ok(Response::new(proto::SetDefaultDatasetResponse {}))
     }
 
     async fn get_default_dataset(
         &self,
         _request: Request<proto::GetDefaultDatasetRequest>,
     ) -> Result<Response<proto::GetDefaultDatasetResponse>, Status> {
-        return Err(Status::unimplemented("Dataset management not needed for clone-only"));
+        Ok(Response::new(proto::GetDefaultDatasetResponse {
+            dataset_id: self.fs.dataset_store.get_default().await,
+        }))
+    }
+
+    async fn set_dataset_quota(
+        &self,
+        request: Request<proto::SetDatasetQuotaRequest>,
+    ) -> Result<Response<proto::SetDatasetQuotaResponse>, Status> {
+        let req = request.into_inner();
+        let quota: DatasetQuota = req.quota.map(Into::into).unwrap_or_default();
+
+        let dataset = self
+            .fs
+            .dataset_store
+            .get_by_name(&req.name)
+            .await
+            .ok_or_else(|| Status::not_found(format!("Dataset '{}' not found", req.name)))?;
+        let dataset = self
+            .fs
+            .dataset_store
+            .set_quota(dataset.id, quota)
+            .await
+            .map_err(|e| match e {
+                FsError::InvalidArgument => {
+                    Status::invalid_argument("Snapshots cannot have a quota")
+                }
+                e => dataset_status(e),
+            })?;
+
+        info!("Set quota of dataset '{}' to {:?}", req.name, quota);
+        Ok(Response::new(proto::SetDatasetQuotaResponse {
+            dataset: Some(self.dataset_info(dataset).await),
+        }))
+    }
+
+    async fn get_dataset_properties(
+        &self,
+        request: Request<proto::GetDatasetPropertiesRequest>,
+    ) -> Result<Response<proto::GetDatasetPropertiesResponse>, Status> {
+        let req = request.into_inner();
+        let property = req
+            .property
+            .as_deref()
+            .map(str::parse::<Property>)
+            .transpose()
+            .map_err(|_| Status::invalid_argument("Unknown property"))?;
+
+        let dataset = self
+            .fs
+            .dataset_store
+            .get_by_name(&req.name)
+            .await
+            .ok_or_else(|| Status::not_found(format!("Dataset '{}' not found", req.name)))?;
+        let properties = self
+            .fs
+            .dataset_store
+            .property_values(dataset.id)
+            .await
+            .map_err(dataset_status)?
+            .into_iter()
+            .filter(|p| property.is_none_or(|property| p.property == property.name()))
+            .map(Into::into)
+            .collect();
+
+        Ok(Response::new(proto::GetDatasetPropertiesResponse {
+            properties,
+        }))
+    }
+
+    async fn set_dataset_property(
+        &self,
+        request: Request<proto::SetDatasetPropertyRequest>,
+    ) -> Result<Response<proto::SetDatasetPropertyResponse>, Status> {
+        let req = request.into_inner();
+        let property: Property = req.property.parse().map_err(|_| {
+            Status::invalid_argument(format!("Unknown property '{}'", req.property))
+        })?;
+
+        let dataset = self
+            .fs
+            .dataset_store
+            .get_by_name(&req.name)
+            .await
+            .ok_or_else(|| Status::not_found(format!("Dataset '{}' not found", req.name)))?;
+        let dataset =
+            dataset_ops::set_property(&self.fs, dataset.id, property, req.value.as_deref())
+                .await
+                .map_err(|e| match e {
+                    FsError::InvalidArgument => Status::invalid_argument(format!(
+                        "Invalid value for property '{}' of dataset '{}'",
+                        property, req.name
+                    )),
+                    FsError::Exists => Status::failed_precondition(format!(
+                        "Dataset '{}' has names differing only in case",
+                        req.name
+                    )),
+                    e => dataset_status(e),
+                })?;
+
+        match &req.value {
+            Some(value) => info!("Set {} of dataset '{}' to {}", property, req.name, value),
+            None => info!("Set {} of dataset '{}' to inherit", property, req.name),
+        }
+        Ok(Response::new(proto::SetDatasetPropertyResponse {
+            dataset: Some(self.dataset_info(dataset).await),
+        }))
+    }
+
+    async fn rename_dataset(
+        &self,
+        request: Request<proto::RenameDatasetRequest>,
+    ) -> Result<Response<proto::RenameDatasetResponse>, Status> {
+        let req = request.into_inner();
+
+        let dataset = self
+            .fs
+            .dataset_store
+            .get_by_name(&req.name)
+            .await
+            .ok_or_else(|| Status::not_found(format!("Dataset '{}' not found", req.name)))?;
+        let dataset = dataset_ops::rename_dataset(&self.fs, dataset.id, &req.new_name)
+            .await
+            .map_err(|e| match e {
+                FsError::NotFound => {
+                    Status::not_found(format!("Parent dataset of '{}' not found", req.new_name))
+                }
+                FsError::InvalidArgument => Status::invalid_argument(format!(
+                    "Cannot rename '{}' to '{}': invalid name, or a move not allowed",
+                    req.name, req.new_name
+                )),
+                e => dataset_status(e),
+            })?;
+
+        Ok(Response::new(proto::RenameDatasetResponse {
+            dataset: Some(self.dataset_info(dataset).await),
+        }))
     }
 
     async fn create_snapshot(
         &self,
-        _request: Request<proto::CreateSnapshotRequest>,
+        request: Request<proto::CreateSnapshotRequest>,
     ) -> Result<Response<proto::CreateSnapshotResponse>, Status> {
-        return Err(Status::unimplemented("Use clone command instead: zerofs dataset clone"));
+        let req = request.into_inner();
+
+        let source = self
+            .fs
+            .dataset_store
+            .get_by_name(&req.source_name)
+            .await
+            .ok_or_else(|| Status::not_found(format!("Dataset '{}' not found", req.source_name)))?;
+        let readonly = req.readonly.unwrap_or(false);
+        let mut snapshots = if req.recursive {
+            dataset_ops::create_recursive_snapshot(
+                &self.fs,
+                source.id,
+                &req.snapshot_name,
+                readonly,
+            )
+            .await
+        } else {
+            dataset_ops::create_snapshot(&self.fs, source.id, &req.snapshot_name, readonly)
+                .await
+                .map(|snapshot| vec![snapshot])
+        }
+        .map_err(dataset_status)?
+        .into_iter();
+
+        let snapshot = snapshots
+            .next()
+            .ok_or_else(|| Status::internal("No snapshot was taken"))?;
+        let mut descendants = Vec::new();
+        for descendant in snapshots {
+            descendants.push(self.dataset_info(descendant).await);
+        }
+
+        Ok(Response::new(proto::CreateSnapshotResponse {
+            snapshot: Some(self.dataset_info(snapshot).await),
+            descendants,
+        }))
     }
 
     async fn list_snapshots(
         &self,
         _request: Request<proto::ListSnapshotsRequest>,
     ) -> Result<Response<proto::ListSnapshotsResponse>, Status> {
-        return Err(Status::unimplemented("Clones are just directories - use ls /snapshots/"));
+        let mut snapshots = Vec::new();
+        for snapshot in self.fs.dataset_store.list_snapshots().await {
+            snapshots.push(self.dataset_info(snapshot).await);
+        }
+
+        Ok(Response::new(proto::ListSnapshotsResponse { snapshots }))
     }
 
     async fn delete_snapshot(
         &self,
-        _request: Request<proto::DeleteSnapshotRequest>,
+        request: Request<proto::DeleteSnapshotRequest>,
     ) -> Result<Response<proto::DeleteSnapshotResponse>, Status> {
-        return Err(Status::unimplemented("Clones are just directories - delete with rm -rf"));
+        let req = request.into_inner();
+        let name = req.name;
+
+        let snapshot = self
+            .fs
+            .dataset_store
+            .get_by_name(&name)
+            .await
+            .filter(|d| d.is_snapshot)
+            .ok_or_else(|| Status::not_found(format!("Snapshot '{}' not found", name)))?;
+        if !snapshot.holds.is_empty() {
+            return Err(held_status(&snapshot));
+        }
+        let (progress, descendants) = if req.recursive {
+            dataset_ops::delete_dataset_recursive(&self.fs, snapshot.id).await
+        } else {
+            dataset_ops::delete_dataset(&self.fs, snapshot.id)
+                .await
+                .map(|progress| (progress, Vec::new()))
+        }
+        .map_err(dataset_status)?;
+
+        info!("Deleted snapshot '{}' (id={})", name, snapshot.id);
+        Ok(Response::new(proto::DeleteSnapshotResponse {
+            destroy: Some(progress.into()),
+            descendants: descendants.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn hold_snapshot(
+        &self,
+        request: Request<proto::HoldSnapshotRequest>,
+    ) -> Result<Response<proto::HoldSnapshotResponse>, Status> {
+        let req = request.into_inner();
+        if req.tag.is_empty() {
+            return Err(Status::invalid_argument("Hold tag must not be empty"));
+        }
+
+        let snapshot = self.snapshot_by_name(&req.name).await?;
+        let snapshot = self
+            .fs
+            .dataset_store
+            .hold_snapshot(snapshot.id, &req.tag)
+            .await
+            .map_err(|e| match e {
+                FsError::Exists => Status::already_exists(format!(
+                    "Snapshot '{}' already has hold '{}'",
+                    req.name, req.tag
+                )),
+                e => dataset_status(e),
+            })?;
+
+        info!("Put hold '{}' on snapshot '{}'", req.tag, req.name);
+        Ok(Response::new(proto::HoldSnapshotResponse {
+            snapshot: Some(self.dataset_info(snapshot).await),
+        }))
+    }
+
+    async fn release_snapshot(
+        &self,
+        request: Request<proto::ReleaseSnapshotRequest>,
+    ) -> Result<Response<proto::ReleaseSnapshotResponse>, Status> {
+        let req = request.into_inner();
+
+        let snapshot = self.snapshot_by_name(&req.name).await?;
+        let snapshot = self
+            .fs
+            .dataset_store
+            .release_snapshot(snapshot.id, &req.tag)
+            .await
+            .map_err(|e| match e {
+                FsError::NotFound => Status::not_found(format!(
+                    "Snapshot '{}' has no hold '{}'",
+                    req.name, req.tag
+                )),
+                e => dataset_status(e),
+            })?;
+
+        info!("Released hold '{}' on snapshot '{}'", req.tag, req.name);
+        Ok(Response::new(proto::ReleaseSnapshotResponse {
+            snapshot: Some(self.dataset_info(snapshot).await),
+        }))
+    }
+
+    async fn rollback_dataset(
+        &self,
+        request: Request<proto::RollbackDatasetRequest>,
+    ) -> Result<Response<proto::RollbackDatasetResponse>, Status> {
+        let req = request.into_inner();
+
+        let dataset = self
+            .fs
+            .dataset_store
+            .get_by_name(&req.name)
+            .await
+            .filter(|d| !d.is_snapshot)
+            .ok_or_else(|| Status::not_found(format!("Dataset '{}' not found", req.name)))?;
+        let snapshot = self
+            .fs
+            .dataset_store
+            .get_by_name(&req.snapshot_name)
+            .await
+            .filter(|d| d.is_snapshot && d.parent_id == Some(dataset.id))
+            .ok_or_else(|| {
+                Status::not_found(format!(
+                    "Snapshot '{}' of dataset '{}' not found",
+                    req.snapshot_name, req.name
+                ))
+            })?;
+
+        let (dataset, destroyed, replaced) =
+            dataset_ops::rollback_dataset(&self.fs, dataset.id, snapshot.id, req.destroy_newer)
+                .await
+                .map_err(|e| match e {
+                    FsError::NotEmpty => Status::failed_precondition(format!(
+                        "Dataset '{}' has snapshots newer than '{}'; destroy them to roll back",
+                        req.name, req.snapshot_name
+                    )),
+                    FsError::OperationNotPermitted => Status::failed_precondition(format!(
+                        "A snapshot of dataset '{}' newer than '{}' is held",
+                        req.name, req.snapshot_name
+                    )),
+                    FsError::InvalidArgument => {
+                        Status::invalid_argument("The root dataset cannot be rolled back")
+                    }
+                    FsError::CrossDevice => Status::failed_precondition(format!(
+                        "Dataset '{}' has child datasets, which a rollback would detach",
+                        req.name
+                    )),
+                    e => dataset_status(e),
+                })?;
+
+        info!(
+            "Rolled back dataset '{}' to snapshot '{}'",
+            req.name, req.snapshot_name
+        );
+        Ok(Response::new(proto::RollbackDatasetResponse {
+            dataset: Some(self.dataset_info(dataset).await),
+            destroyed_snapshots: destroyed.into_iter().map(Into::into).collect(),
+            replaced: Some(replaced.into()),
+        }))
+    }
+
+    async fn diff_snapshots(
+        &self,
+        request: Request<proto::DiffSnapshotsRequest>,
+    ) -> Result<Response<proto::DiffSnapshotsResponse>, Status> {
+        let req = request.into_inner();
+
+        let from = self
+            .fs
+            .dataset_store
+            .get_by_name(&req.from_snapshot)
+            .await
+            .filter(|d| d.is_snapshot)
+            .ok_or_else(|| {
+                Status::not_found(format!("Snapshot '{}' not found", req.from_snapshot))
+            })?;
+        let to = match &req.to {
+            Some(name) => Some(
+                self.fs
+                    .dataset_store
+                    .get_by_name(name)
+                    .await
+                    .ok_or_else(|| Status::not_found(format!("Dataset '{}' not found", name)))?
+                    .id,
+            ),
+            None => None,
+        };
+
+        let changes = diff::diff_snapshots(&self.fs, from.id, to)
+            .await
+            .map_err(|e| match e {
+                FsError::NotFound => Status::not_found(format!(
+                    "The dataset snapshot '{}' was taken of no longer exists",
+                    req.from_snapshot
+                )),
+                e => Status::internal(format!("Failed to diff snapshots: {}", e)),
+            })?;
+
+        Ok(Response::new(proto::DiffSnapshotsResponse {
+            changes: changes.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    type SendSnapshotStream =
+        Pin<Box<dyn tokio_stream::Stream<Item = Result<proto::SendStreamChunk, Status>> + Send>>;
+
+    async fn send_snapshot(
+        &self,
+        request: Request<proto::SendSnapshotRequest>,
+    ) -> Result<Response<Self::SendSnapshotStream>, Status> {
+        let req = request.into_inner();
+
+        let snapshot = self.snapshot_by_name(&req.snapshot_name).await?;
+        let base = match &req.base_snapshot_name {
+            Some(name) => Some(self.snapshot_by_name(name).await?),
+            None => None,
+        };
+        if base
+            .as_ref()
+            .is_some_and(|b| b.id == snapshot.id || b.parent_id != snapshot.parent_id)
+        {
+            return Err(Status::invalid_argument(
+                "The base must be another snapshot of the same dataset",
+            ));
+        }
+
+        let (writer, reader) = tokio::io::duplex(SEND_BUFFER_SIZE);
+        let fs = self.fs.clone();
+        let task = tokio::spawn(async move {
+            send::send(&fs, snapshot.id, base.map(|b| b.id), writer).await
+        });
+
+        // The stream ends with the error of the send, if any
+        let stream = futures::stream::unfold(Some((reader, task)), |state| async move {
+            let (mut reader, task) = state?;
+            let mut buf = vec![0u8; SEND_BUFFER_SIZE];
+            match reader.read(&mut buf).await {
+                Ok(0) => match task.await {
+                    Ok(Ok(())) => None,
+                    Ok(Err(e)) => Some((
+                        Err(Status::internal(format!("Failed to send snapshot: {}", e))),
+                        None,
+                    )),
+                    Err(e) => Some((
+                        Err(Status::internal(format!("Send task failed: {}", e))),
+                        None,
+                    )),
+                },
+                Ok(n) => {
+                    buf.truncate(n);
+                    Some((Ok(proto::SendStreamChunk { data: buf }), Some((reader, task))))
+                }
+                Err(e) => {
+                    task.abort();
+                    Some((
+                        Err(Status::internal(format!("Failed to send snapshot: {}", e))),
+                        None,
+                    ))
+                }
+            }
+        });
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn receive_snapshot(
+        &self,
+        request: Request<Streaming<proto::ReceiveSnapshotRequest>>,
+    ) -> Result<Response<proto::ReceiveSnapshotResponse>, Status> {
+        let mut chunks = request.into_inner();
+        let first = chunks
+            .message()
+            .await?
+            .ok_or_else(|| Status::invalid_argument("Empty receive stream"))?;
+        let options = ReceiveOptions {
+            encrypt: first.encrypt,
+            force: first.force,
+        };
+
+        // Feed the request stream to the receiver; a client error ends the
+        // input early, which the receiver reports as a truncated stream
+        let (mut writer, reader) = tokio::io::duplex(SEND_BUFFER_SIZE);
+        let pump = tokio::spawn(async move {
+            writer.write_all(&first.data).await?;
+            while let Ok(Some(chunk)) = chunks.message().await {
+                writer.write_all(&chunk.data).await?;
+            }
+            writer.shutdown().await
+        });
+
+        let received = send::receive(&self.fs, &first.dataset_name, options, reader).await;
+        pump.abort();
+        let outcome = received.map_err(|e| match e {
+            FsError::NotFound => Status::not_found(format!(
+                "Dataset '{}' or the base snapshot of the stream not found",
+                first.dataset_name
+            )),
+            FsError::Exists => Status::already_exists(
+                "The dataset, or a snapshot with the name of the sent one, already exists",
+            ),
+            FsError::NotEmpty => Status::failed_precondition(
+                "Dataset changed since the base snapshot of the stream; receive with force to roll it back",
+            ),
+            FsError::InvalidData => {
+                Status::invalid_argument("Invalid, corrupt or truncated stream")
+            }
+            FsError::InvalidArgument | FsError::NameTooLong => {
+                Status::invalid_argument("Invalid dataset name")
+            }
+            FsError::ReadOnlyFilesystem => Status::failed_precondition("Filesystem is read-only"),
+            FsError::QuotaExceeded => Status::resource_exhausted("Quota exceeded"),
+            e => Status::internal(format!("Failed to receive snapshot: {}", e)),
+        })?;
+
+        Ok(Response::new(proto::ReceiveSnapshotResponse {
+            dataset: Some(self.dataset_info(outcome.dataset).await),
+            snapshot: Some(self.dataset_info(outcome.snapshot).await),
+            entries: outcome.entries,
+            data_bytes: outcome.data_bytes,
+        }))
+    }
+
+    async fn get_snapshot_schedules(
+        &self,
+        _request: Request<proto::GetSnapshotSchedulesRequest>,
+    ) -> Result<Response<proto::GetSnapshotSchedulesResponse>, Status> {
+        let schedules = self
+            .snapshot_scheduler
+            .status()
+            .into_iter()
+            .map(Into::into)
+            .collect();
+        Ok(Response::new(proto::GetSnapshotSchedulesResponse { schedules }))
     }
 
     type ReadSnapshotFileStream =
@@ -219,9 +842,58 @@ impl AdminService for AdminRpcServer {
 
     async fn instant_restore_file(
         &self,
-        _request: Request<proto::InstantRestoreFileRequest>,
+        request: Request<proto::InstantRestoreFileRequest>,
     ) -> Result<Response<proto::InstantRestoreFileResponse>, Status> {
-        return Err(Status::unimplemented("Use clone command instead: zerofs dataset clone"));
+        let req = request.into_inner();
+        let on_conflict: OnConflict = req.on_conflict().into();
+
+        let snapshot = self
+            .fs
+            .dataset_store
+            .get_by_name(&req.snapshot_name)
+            .await
+            .filter(|d| d.is_snapshot)
+            .ok_or_else(|| {
+                Status::not_found(format!("Snapshot '{}' not found", req.snapshot_name))
+            })?;
+
+        let outcome = restore::restore(
+            &self.fs,
+            snapshot.id,
+            &req.source_path,
+            &req.destination_path,
+            on_conflict,
+            req.dry_run,
+        )
+        .await
+        .map_err(|e| match e {
+            FsError::NotFound => Status::not_found(format!(
+                "'{}' not found in snapshot '{}', or destination directory missing",
+                req.source_path, req.snapshot_name
+            )),
+            FsError::Exists => Status::already_exists(format!(
+                "Destination '{}' already exists",
+                req.destination_path
+            )),
+            FsError::NotDirectory => {
+                Status::invalid_argument("A path component is not a directory")
+            }
+            FsError::InvalidArgument | FsError::NameTooLong => Status::invalid_argument(
+                "Invalid destination, or it is or holds a dataset root, or contains the source",
+            ),
+            FsError::ReadOnlyFilesystem => Status::failed_precondition("Filesystem is read-only"),
+            FsError::QuotaExceeded => Status::resource_exhausted("Quota exceeded"),
+            e => Status::internal(format!("Failed to restore: {}", e)),
+        })?;
+
+        info!(
+            "Restored '{}' of snapshot '{}' to '{}'{}",
+            req.source_path,
+            req.snapshot_name,
+            outcome.destination_path,
+            if outcome.dry_run { " (dry run)" } else { "" }
+        );
+        Ok(Response::new(outcome.into()))
     }
 
     async fn clone_path(
@@ -261,7 +933,7 @@ impl AdminService for AdminRpcServer {
 
         let fs_ref = &self.fs;
 
-        // Navigate to source
+        // Navigate to source, reading lazy directories through their origins
         let mut current_inode = 0u64; // root
         for part in &source_parts {
             let inode = fs_ref.inode_store.get(current_inode).await.map_err(|e| {
@@ -270,9 +942,20 @@ impl AdminService for AdminRpcServer {
 
             match inode {
                 Inode::Directory(_) => {
+                    let entries_dir =
+                        fs_ref
+                            .lazy_dirs
+                            .entries_dir(current_inode)
+                            .await
+                            .map_err(|e| {
+                                Status::internal(format!(
+                                    "Failed to read directory {}: {}",
+                                    current_inode, e
+                                ))
+                            })?;
                     current_inode = fs_ref
                         .directory_store
-                        .get(current_inode, part.as_bytes())
+                        .get(entries_dir, part.as_bytes())
                         .await
                         .map_err(|_| {
                             Status::not_found(format!("Source path component '{}' not found", part))
@@ -293,10 +976,6 @@ impl AdminService for AdminRpcServer {
         })?;
 
         let is_directory = matches!(source_inode, Inode::Directory(_));
-        let size = match &source_inode {
-            Inode::File(f) => f.size,
-            _ => 0,
-        };
 
         // Navigate to destination parent directory
         let dest_name = dest_parts.last().unwrap();
@@ -314,6 +993,7 @@ impl AdminService for AdminRpcServer {
 
             match inode {
                 Inode::Directory(_) => {
+                    self.materialize_dir(dest_dir_inode).await?;
                     dest_dir_inode = fs_ref
                         .directory_store
                         .get(dest_dir_inode, part.as_bytes())
@@ -335,6 +1015,7 @@ impl AdminService for AdminRpcServer {
         }
 
         // Check if destination already exists
+        self.materialize_dir(dest_dir_inode).await?;
         if fs_ref
             .directory_store
             .exists(dest_dir_inode, dest_name.as_bytes())
@@ -347,116 +1028,14 @@ impl AdminService for AdminRpcServer {
             )));
         }
 
-        // Allocate new inode for the clone
-        let new_inode_id = fs_ref.inode_store.allocate();
-
-        // Clone the inode (COW - shares data chunks)
-        let mut new_inode = source_inode.clone();
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-
-        // Update timestamps for the clone
-        match &mut new_inode {
-            Inode::File(f) => {
-                f.ctime = now;
-                f.ctime_nsec = 0;
-                f.mtime = now;
-                f.mtime_nsec = 0;
-                f.atime = now;
-                f.atime_nsec = 0;
-            }
-            Inode::Directory(d) => {
-                d.ctime = now;
-                d.ctime_nsec = 0;
-                d.mtime = now;
-                d.mtime_nsec = 0;
-                d.atime = now;
-                d.atime_nsec = 0;
-            }
-            _ => {}
-        }
-
-        // Create transaction
-        let mut txn = fs_ref
-            .db
-            .new_transaction()
-            .map_err(|e| Status::internal(format!("Failed to create transaction: {}", e)))?;
-
-        // Allocate cookie for directory entry
-        let cookie = fs_ref
-            .directory_store
-            .allocate_cookie(dest_dir_inode, &mut txn)
-            .await
-            .map_err(|e| Status::internal(format!("Failed to allocate cookie: {}", e)))?;
-
-        // Add directory entry
-        fs_ref.directory_store.add(
-            &mut txn,
-            dest_dir_inode,
-            dest_name.as_bytes(),
-            new_inode_id,
-            cookie,
-            Some(&new_inode),
-        );
-
-        // Save the new inode
-        fs_ref
-            .inode_store
-            .save(&mut txn, new_inode_id, &new_inode)
-            .map_err(|e| Status::internal(format!("Failed to save inode: {}", e)))?;
-
-        // Commit transaction
-        let mut seq_guard = fs_ref.write_coordinator.allocate_sequence();
-        fs_ref
-            .commit_transaction_internal(txn, &mut seq_guard, true)
-            .await
-            .map_err(|e| Status::internal(format!("Failed to commit: {}", e)))?;
-
-        // For files, copy chunk metadata (COW)
-        if !is_directory && size > 0 {
-            tracing::info!(
-                "Copying chunks from source inode {} to cloned inode {} (file_size={})",
-                current_inode,
-                new_inode_id,
-                size
-            );
-            fs_ref
-                .chunk_store
-                .copy_chunks_for_cow(current_inode, new_inode_id, size)
+        let (new_inode_id, new_inode) =
+            clone::clone_entry(fs_ref, current_inode, dest_dir_inode, dest_name.as_bytes())
                 .await
-                .map_err(|e| Status::internal(format!("Failed to copy chunks: {}", e)))?;
-            
-            // Flush to ensure chunks are persisted
-            if let Err(e) = fs_ref.db.flush().await {
-                tracing::warn!("Failed to flush after file clone: {}", e);
-            }
-        }
-
-        // If it's a directory, recursively clone its contents
-        if is_directory {
-            tracing::info!(
-                "Recursively cloning directory contents from source inode {} to new inode {}",
-                current_inode,
-                new_inode_id
-            );
-            
-            self.clone_directory_recursive(current_inode, new_inode_id)
-                .await?;
-            
-            // Flush to ensure all cloned directory entries are persisted
-            if let Err(e) = fs_ref.db.flush().await {
-                tracing::warn!("Failed to flush after directory clone: {}", e);
-                // Continue anyway - entries are written, just not guaranteed durable yet
-            }
-                
-            tracing::info!(
-                "Completed recursive clone of directory from {} to {}",
-                current_inode,
-                new_inode_id
-            );
-        }
+                .map_err(|e| Status::internal(format!("Failed to clone: {}", e)))?;
+        let size = match &new_inode {
+            Inode::File(f) => f.size,
+            _ => 0,
+        };
 
         tracing::info!(
             "COW clone complete: created new inode {} (type: {}) from source inode {}",
@@ -471,6 +1050,109 @@ impl AdminService for AdminRpcServer {
             is_directory,
         }))
     }
+
+    async fn set_owner_quota(
+        &self,
+        request: Request<proto::SetOwnerQuotaRequest>,
+    ) -> Result<Response<proto::SetOwnerQuotaResponse>, Status> {
+        let req = request.into_inner();
+        let owner: Owner = (req.kind(), req.id).into();
+        let quota: OwnerQuota = req.quota.map(Into::into).unwrap_or_default();
+
+        self.fs
+            .quota_store
+            .set(owner, quota)
+            .await
+            .map_err(|e| match e {
+                FsError::ReadOnlyFilesystem => {
+                    Status::failed_precondition("Filesystem is read-only")
+                }
+                e => Status::internal(format!("Failed to set quota: {}", e)),
+            })?;
+
+        info!("Set quota of {} to {:?}", owner, quota);
+        Ok(Response::new(proto::SetOwnerQuotaResponse {
+            owner: Some(quota::owner_report(&self.fs, owner).await.into()),
+        }))
+    }
+
+    async fn get_quota_report(
+        &self,
+        _request: Request<proto::GetQuotaReportRequest>,
+    ) -> Result<Response<proto::GetQuotaReportResponse>, Status> {
+        let owners = quota::report(&self.fs)
+            .await
+            .into_iter()
+            .map(Into::into)
+            .collect();
+
+        Ok(Response::new(proto::GetQuotaReportResponse { owners }))
+    }
+
+    async fn get_usage(
+        &self,
+        _request: Request<proto::GetUsageRequest>,
+    ) -> Result<Response<proto::GetUsageResponse>, Status> {
+        Ok(Response::new(proto::GetUsageResponse {
+            usage: Some(dataset_ops::filesystem_usage(&self.fs).into()),
+        }))
+    }
+
+    async fn rotate_data_key(
+        &self,
+        request: Request<proto::RotateDataKeyRequest>,
+    ) -> Result<Response<proto::RotateDataKeyResponse>, Status> {
+        let req = request.into_inner();
+        let _guard = self.key_slots_lock.lock().await;
+
+        let key_id = key_management::rotate_data_key(self.fs.db.handle(), &req.password)
+            .await
+            .map_err(key_status)?;
+
+        info!("Rotated data encryption key to generation {}", key_id);
+        Ok(Response::new(proto::RotateDataKeyResponse { key_id }))
+    }
+
+    async fn add_key_slot(
+        &self,
+        request: Request<proto::AddKeySlotRequest>,
+    ) -> Result<Response<proto::AddKeySlotResponse>, Status> {
+        let req = request.into_inner();
+        let params = Argon2Params {
+            memory_kib: req.memory_kib,
+            iterations: req.iterations,
+            parallelism: req.parallelism,
+        };
+        let _guard = self.key_slots_lock.lock().await;
+
+        key_management::add_key_slot(
+            self.fs.db.handle(),
+            &req.password,
+            &req.label,
+            &req.new_password,
+            params,
+        )
+        .await
+        .map_err(key_status)?;
+
+        info!("Added key slot '{}'", req.label);
+        Ok(Response::new(proto::AddKeySlotResponse {}))
+    }
+
+    async fn revoke_key_slot(
+        &self,
+        request: Request<proto::RevokeKeySlotRequest>,
+    ) -> Result<Response<proto::RevokeKeySlotResponse>, Status> {
+        let req = request.into_inner();
+        let _guard = self.key_slots_lock.lock().await;
+
+        key_management::revoke_key_slot(self.fs.db.handle(), &req.password, &req.label)
+            .await
+            .map_err(key_status)?;
+
+        info!("Revoked key slot '{}'", req.label);
+        Ok(Response::new(proto::RevokeKeySlotResponse {}))
+    }
 }
 
 /// Serve gRPC over TCP
@@ -1,5 +1,6 @@
 use bytes::Bytes;
 use dashmap::DashMap;
+use slatedb::config::WriteOptions;
 use std::collections::BTreeMap;
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
@@ -7,13 +8,15 @@ use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::fs::{self, File, OpenOptions};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::sync::{RwLock, Semaphore};
+use tokio::sync::{Notify, RwLock, Semaphore};
 use tokio::time::interval;
 use tracing::{debug, error, info, warn};
 
 use crate::encryption::EncryptedDb;
+use crate::fs::dataset::DatasetId;
 use crate::fs::inode::InodeId;
 use crate::fs::key_codec::KeyCodec;
+use crate::fs::write_coordinator::WriteCoordinator;
 
 /// Configuration for the writeback cache
 #[derive(Debug, Clone)]
@@ -120,6 +123,27 @@ impl WritebackCacheConfig {
     }
 }
 
+/// A change to a chunk through the writeback cache, recorded on a
+/// transaction and applied when it commits
+pub enum CachedWrite {
+    /// New contents of a chunk. The chunk map keeps its current entry until
+    /// the cache flushes the contents in its place.
+    Put {
+        id: InodeId,
+        chunk_idx: u64,
+        data: Bytes,
+        dataset: DatasetId,
+    },
+    /// Removal of a chunk, leaving a hole marker when `hole` is set
+    Release {
+        id: InodeId,
+        chunk_idx: u64,
+        hole: bool,
+    },
+    /// A chunk written to the database directly, whose cached copy is stale
+    Evict { id: InodeId, chunk_idx: u64 },
+}
+
 /// Metadata for a cached chunk
 #[derive(Debug, Clone)]
 struct CachedChunkMeta {
@@ -129,6 +153,8 @@ struct CachedChunkMeta {
     chunk_idx: u64,
     /// Size of the chunk data
     size: usize,
+    /// Dataset the space of a written chunk is accounted to once flushed
+    dataset: Option<DatasetId>,
     /// Whether the chunk is dirty (needs flushing)
     is_dirty: bool,
     /// When the chunk was last modified
@@ -179,6 +205,12 @@ pub struct WritebackCache {
     config: WritebackCacheConfig,
     db: Arc<EncryptedDb>,
     
+    /// Orders flushes with filesystem transactions
+    write_coordinator: Arc<WriteCoordinator>,
+    
+    /// Wakes the background flush when too many chunks are dirty
+    flush_wakeup: Notify,
+    
     /// Metadata for all cached chunks
     metadata: DashMap<ChunkKey, CachedChunkMeta>,
     
@@ -216,7 +248,11 @@ pub struct WritebackCacheStats {
 
 impl WritebackCache {
     /// Create a new writeback cache
-    pub async fn new(config: WritebackCacheConfig, db: Arc<EncryptedDb>) -> anyhow::Result<Arc<Self>> {
+    pub async fn new(
+        config: WritebackCacheConfig,
+        db: Arc<EncryptedDb>,
+        write_coordinator: Arc<WriteCoordinator>,
+    ) -> anyhow::Result<Arc<Self>> {
         // Create cache directory if it doesn't exist
         fs::create_dir_all(&config.cache_dir).await?;
         
@@ -230,6 +266,8 @@ impl WritebackCache {
             flush_semaphore: Arc::new(Semaphore::new(config.max_concurrent_flushes)),
             config,
             db,
+            write_coordinator,
+            flush_wakeup: Notify::new(),
             metadata: DashMap::new(),
             dirty_queue: Arc::new(RwLock::new(BTreeMap::new())),
             current_size: AtomicU64::new(0),
@@ -303,7 +341,7 @@ impl WritebackCache {
             };
             
             if should_cache {
-                let _ = self.cache_chunk(key, bytes.clone(), false).await;
+                let _ = self.cache_chunk(key, bytes.clone(), None).await;
             }
         }
         
@@ -322,8 +360,17 @@ impl WritebackCache {
         Ok(results)
     }
     
-    /// Write a chunk to the cache
-    pub async fn put(&self, inode_id: InodeId, chunk_idx: u64, data: Bytes) -> anyhow::Result<()> {
+    /// Write a chunk to the cache, accounting its space to `dataset` once
+    /// flushed. Writes come from committing transactions, which a flush waits
+    /// for, so reaching the dirty limit wakes the background flush instead of
+    /// flushing here.
+    pub async fn put(
+        &self,
+        inode_id: InodeId,
+        chunk_idx: u64,
+        data: Bytes,
+        dataset: DatasetId,
+    ) -> anyhow::Result<()> {
         let key = ChunkKey::new(inode_id, chunk_idx);
         self.stats.writes.fetch_add(1, Ordering::Relaxed);
         
@@ -331,38 +378,40 @@ impl WritebackCache {
         self.ensure_cache_space(data.len()).await?;
         
         // Write to cache and mark as dirty
-        self.cache_chunk(key, data, true).await?;
+        self.cache_chunk(key, data, Some(dataset)).await?;
         
         // Check if we need to force a flush
         let dirty_count = self.dirty_count.load(Ordering::Relaxed);
         if dirty_count >= self.config.max_dirty_chunks {
             debug!("Dirty chunk limit reached ({}), triggering flush", dirty_count);
-            // For database workloads, flush more aggressively (50% instead of 25%)
-            self.flush_some_dirty_chunks(dirty_count / 2).await?;
+            self.flush_wakeup.notify_one();
         }
         
         Ok(())
     }
     
     /// Batch write multiple chunks (optimized for database checkpoint-like operations)
-    pub async fn put_batch(&self, chunks: Vec<(InodeId, u64, Bytes)>) -> anyhow::Result<()> {
-        let total_size: usize = chunks.iter().map(|(_, _, data)| data.len()).sum();
+    pub async fn put_batch(
+        &self,
+        chunks: Vec<(InodeId, u64, Bytes, DatasetId)>,
+    ) -> anyhow::Result<()> {
+        let total_size: usize = chunks.iter().map(|(_, _, data, _)| data.len()).sum();
         
         // Ensure we have space for the entire batch
         self.ensure_cache_space(total_size).await?;
         
         // Write all chunks
-        for (inode_id, chunk_idx, data) in chunks {
+        for (inode_id, chunk_idx, data, dataset) in chunks {
             let key = ChunkKey::new(inode_id, chunk_idx);
             self.stats.writes.fetch_add(1, Ordering::Relaxed);
-            self.cache_chunk(key, data, true).await?;
+            self.cache_chunk(key, data, Some(dataset)).await?;
         }
         
         // Check if we need to flush after batch
         let dirty_count = self.dirty_count.load(Ordering::Relaxed);
         if dirty_count >= self.config.max_dirty_chunks {
             debug!("Dirty chunk limit reached after batch ({}), triggering flush", dirty_count);
-            self.flush_some_dirty_chunks(dirty_count / 2).await?;
+            self.flush_wakeup.notify_one();
         }
         
         Ok(())
@@ -397,6 +446,11 @@ impl WritebackCache {
         Ok(())
     }
     
+    /// Whether any chunk of an inode is cached
+    pub fn has_chunks(&self, inode_id: InodeId) -> bool {
+        self.metadata.iter().any(|entry| entry.key().inode_id == inode_id)
+    }
+
     /// Flush all dirty chunks to the underlying storage
     pub async fn flush_all(&self) -> anyhow::Result<()> {
         info!("Flushing all dirty chunks to storage");
@@ -440,9 +494,17 @@ impl WritebackCache {
     
     // Private helper methods
     
-    async fn cache_chunk(&self, key: ChunkKey, data: Bytes, is_dirty: bool) -> anyhow::Result<()> {
+    /// Store a chunk in the cache. Chunks written for `dataset` are dirty;
+    /// those read from the database carry none.
+    async fn cache_chunk(
+        &self,
+        key: ChunkKey,
+        data: Bytes,
+        dataset: Option<DatasetId>,
+    ) -> anyhow::Result<()> {
         let size = data.len();
         let now = Instant::now();
+        let is_dirty = dataset.is_some();
         
         // Write to cache file
         let cache_path = self.config.cache_dir.join(key.to_filename());
@@ -456,6 +518,7 @@ impl WritebackCache {
             let was_dirty = meta.is_dirty;
             
             meta.size = size;
+            meta.dataset = dataset;
             meta.is_dirty = is_dirty;
             meta.last_access = now;
             
@@ -471,6 +534,7 @@ impl WritebackCache {
                 inode_id: key.inode_id,
                 chunk_idx: key.chunk_idx,
                 size,
+                dataset,
                 is_dirty,
                 dirty_since,
                 last_access: now,
@@ -496,8 +560,14 @@ impl WritebackCache {
         Ok(())
     }
     
+    /// Flush a dirty chunk into the content-addressed store, in place of the
+    /// entry its chunk map held. Runs in sequence with filesystem
+    /// transactions, which apply their cache writes once their predecessors
+    /// have committed, so neither sees the other half done.
     async fn flush_chunk(&self, key: ChunkKey) -> anyhow::Result<()> {
         let _permit = self.flush_semaphore.acquire().await?;
+        let mut seq_guard = self.write_coordinator.allocate_sequence();
+        seq_guard.wait_for_predecessors().await;
         
         // Get metadata
         let meta = match self.metadata.get(&key) {
@@ -505,26 +575,38 @@ impl WritebackCache {
             None => return Ok(()), // Already evicted
         };
         
-        if !meta.is_dirty {
+        let Some(dataset) = meta.dataset.filter(|_| meta.is_dirty) else {
             return Ok(()); // Already clean
-        }
+        };
         
         // Read from cache file
         let cache_path = self.config.cache_dir.join(key.to_filename());
         let data = self.read_from_cache_file(&cache_path).await?;
         
-        // Write to underlying storage
+        // Write to underlying storage, releasing the body the entry referenced
         let db_key = KeyCodec::chunk_key(key.inode_id, key.chunk_idx);
-        self.db
-            .put_with_options(
-                &db_key,
-                &data,
-                &slatedb::config::PutOptions::default(),
-                &slatedb::config::WriteOptions {
-                    await_durable: false,
-                },
-            )
-            .await?;
+        let hash = self.db.content_hash_in(None, &data)?;
+        let old = self
+            .db
+            .get_bytes(&db_key)
+            .await?
+            .and_then(|value| KeyCodec::decode_chunk_ref(&value));
+        if old != Some(hash) {
+            let mut txn = self.db.new_transaction()?;
+            if let Some(old) = old {
+                txn.remove_chunk_ref(old);
+            }
+            txn.add_chunk_ref(hash, Some(data), None, dataset);
+            txn.put_bytes(&db_key, KeyCodec::encode_chunk_ref(&hash));
+            self.db
+                .write_with_options(
+                    txn,
+                    &WriteOptions {
+                        await_durable: false,
+                    },
+                )
+                .await?;
+        }
         
         // Mark as clean
         if let Some(mut meta) = self.metadata.get_mut(&key) {
@@ -534,6 +616,7 @@ impl WritebackCache {
                 self.dirty_count.fetch_sub(1, Ordering::Relaxed);
             }
         }
+        seq_guard.mark_committed();
         
         // Remove from dirty queue
         if let Some(dirty_since) = meta.dirty_since {
@@ -646,7 +729,10 @@ impl WritebackCache {
         let mut ticker = interval(Duration::from_secs(self.config.flush_interval_secs));
         
         loop {
-            ticker.tick().await;
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = self.flush_wakeup.notified() => {}
+            }
             
             if self.shutdown.load(Ordering::Relaxed) {
                 break;
@@ -659,8 +745,14 @@ impl WritebackCache {
             
             debug!("Background flush: {} dirty chunks", dirty_count);
             
-            // Flush chunks older than threshold
-            if let Err(e) = self.flush_some_dirty_chunks(dirty_count.min(100)).await {
+            // Flush chunks older than threshold. Past the dirty limit, flush
+            // more aggressively for database workloads (50% instead of 25%).
+            let count = if dirty_count >= self.config.max_dirty_chunks {
+                dirty_count / 2
+            } else {
+                dirty_count.min(100)
+            };
+            if let Err(e) = self.flush_some_dirty_chunks(count).await {
                 error!("Background flush error: {}", e);
             }
         }
@@ -899,6 +899,7 @@ async fn test_crash_gc_after_chunk_delete() {
         fs.tombstone_store.clone(),
         fs.chunk_store.clone(),
         Arc::clone(&fs.stats),
+        Arc::clone(&fs.write_coordinator),
     ));
     let handle = tokio::task::spawn(async move { gc.run().await });
     let _ = handle.await;
@@ -944,6 +945,7 @@ async fn test_crash_gc_after_tombstone_update() {
         fs.tombstone_store.clone(),
         fs.chunk_store.clone(),
         Arc::clone(&fs.stats),
+        Arc::clone(&fs.write_coordinator),
     ));
     let handle = tokio::task::spawn(async move { gc.run().await });
     let _ = handle.await;